@@ -0,0 +1,265 @@
+use crate::holepunch;
+use chrono::{Local, TimeZone};
+use libp2p::{
+    core::multiaddr::{Multiaddr, Protocol},
+    PeerId,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// How often a changed address book is written to disk.
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Addresses kept per peer, those with the lowest score go first beyond that.
+const MAX_ADDRS_PER_PEER: usize = 16;
+
+/// Known addresses dialed at once when reaching a peer directly.
+const MAX_DIAL_ADDRS: usize = 8;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KnownAddr {
+    pub addr: String,
+    /// Unix time in seconds the address was last learned or worked.
+    pub last_seen: u64,
+    #[serde(default)]
+    pub successes: u32,
+    #[serde(default)]
+    pub failures: u32,
+}
+
+impl KnownAddr {
+    pub fn score(&self) -> i64 {
+        self.successes as i64 - self.failures as i64
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Entry {
+    /// Unix time in seconds anything was last learned about the peer.
+    last_seen: u64,
+    addrs: Vec<KnownAddr>,
+}
+
+/// The addresses peers listened on or were reached at, so a restart doesn't have to go back
+/// through the relay to find them. Kept in the data dir with a last seen time and a
+/// success/failure count per address. Entries older than `max_age` are dropped at startup, and
+/// the least recently seen peers beyond `capacity`.
+pub struct AddressBook {
+    path: Option<PathBuf>,
+    peers: HashMap<PeerId, Entry>,
+    capacity: usize,
+    dirty: bool,
+    saved: Instant,
+}
+
+impl AddressBook {
+    pub fn load(
+        dir: Option<&Path>,
+        peer_id: &PeerId,
+        capacity: usize,
+        max_age: Duration,
+    ) -> Result<Self, Box<dyn Error>> {
+        let path = dir.map(|dir| dir.join(format!("addresses-{peer_id}.json")));
+        let stored: HashMap<String, Entry> = match &path {
+            Some(path) if path.exists() => {
+                let contents = fs::read(path)
+                    .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+                serde_json::from_slice(&contents).unwrap_or_else(|e| {
+                    warn!("Ignoring unreadable address book {}: {e}", path.display());
+                    HashMap::new()
+                })
+            }
+            _ => HashMap::new(),
+        };
+        let oldest = now_secs().saturating_sub(max_age.as_secs());
+        let mut peers = HashMap::new();
+        let mut pruned = 0;
+        for (peer_id, mut entry) in stored {
+            let peer_id = match peer_id.parse::<PeerId>() {
+                Ok(peer_id) => peer_id,
+                Err(_) => continue,
+            };
+            let known = entry.addrs.len();
+            entry.addrs.retain(|a| a.last_seen >= oldest);
+            pruned += known - entry.addrs.len();
+            if !entry.addrs.is_empty() {
+                peers.insert(peer_id, entry);
+            }
+        }
+        if pruned > 0 {
+            info!("Dropped {pruned} stale addresses from the address book");
+        }
+        let mut book = AddressBook {
+            path,
+            peers,
+            capacity,
+            dirty: pruned > 0,
+            saved: Instant::now(),
+        };
+        book.evict();
+        Ok(book)
+    }
+
+    /// Adds addresses `peer_id` listens on, as told by identify. Relayed ones are left out.
+    pub fn on_listen_addrs(&mut self, peer_id: PeerId, addrs: &[Multiaddr]) {
+        for addr in addrs.iter().filter(|a| !holepunch::is_relayed(a)) {
+            self.entry(peer_id, addr);
+        }
+        self.evict();
+    }
+
+    /// `addr` of `peer_id` worked, directly dialed or hole punched.
+    pub fn on_success(&mut self, peer_id: PeerId, addr: &Multiaddr) {
+        self.entry(peer_id, addr).successes += 1;
+        self.evict();
+    }
+
+    /// Dialing `addr` of `peer_id` failed. Unknown addresses aren't added.
+    pub fn on_failure(&mut self, peer_id: &PeerId, addr: &Multiaddr) {
+        let addr = key(addr);
+        if let Some(known) = self
+            .peers
+            .get_mut(peer_id)
+            .and_then(|e| e.addrs.iter_mut().find(|a| a.addr == addr))
+        {
+            known.failures += 1;
+            self.dirty = true;
+        }
+    }
+
+    /// What is known about `peer_id`, best scored addresses first.
+    pub fn addrs(&self, peer_id: &PeerId) -> Vec<&KnownAddr> {
+        let mut addrs = self
+            .peers
+            .get(peer_id)
+            .map(|e| e.addrs.iter().collect::<Vec<_>>())
+            .unwrap_or_default();
+        addrs.sort_by_key(|a| (-a.score(), std::cmp::Reverse(a.last_seen)));
+        addrs
+    }
+
+    /// The addresses of `peer_id` worth dialing, best scored first.
+    pub fn dial_addrs(&self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        self.addrs(peer_id)
+            .into_iter()
+            .filter_map(|a| a.addr.parse().ok())
+            .take(MAX_DIAL_ADDRS)
+            .collect()
+    }
+
+    /// One line per address of `peer_id`, for `/addrs`.
+    pub fn lines(&self, peer_id: &PeerId) -> Vec<String> {
+        self.addrs(peer_id)
+            .into_iter()
+            .map(|a| {
+                let seen = match Local.timestamp_opt(a.last_seen as i64, 0).single() {
+                    Some(at) => at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    None => "never".to_string(),
+                };
+                format!(
+                    "{} last seen {seen}, {} ok / {} failed",
+                    a.addr, a.successes, a.failures
+                )
+            })
+            .collect()
+    }
+
+    /// Writes the book to disk if it changed and the last save is long enough ago.
+    pub fn save_due(&mut self, now: Instant) {
+        if now.duration_since(self.saved) >= SAVE_INTERVAL {
+            self.save();
+            self.saved = now;
+        }
+    }
+
+    pub fn save(&mut self) {
+        let path = match &self.path {
+            Some(path) if self.dirty => path,
+            _ => return,
+        };
+        let peers = self
+            .peers
+            .iter()
+            .map(|(peer_id, entry)| (peer_id.to_string(), entry))
+            .collect::<HashMap<_, _>>();
+        let contents = serde_json::to_vec(&peers).expect("address book serializes to JSON");
+        if let Err(e) = fs::write(path, contents) {
+            warn!("Failed to save the address book to {}: {e}", path.display());
+        }
+        self.dirty = false;
+    }
+
+    /// The entry of `addr`, added if it's new, marked as seen now.
+    fn entry(&mut self, peer_id: PeerId, addr: &Multiaddr) -> &mut KnownAddr {
+        let now = now_secs();
+        let addr = key(addr);
+        let entry = self.peers.entry(peer_id).or_default();
+        entry.last_seen = now;
+        self.dirty = true;
+        let position = match entry.addrs.iter().position(|a| a.addr == addr) {
+            Some(position) => position,
+            None => {
+                if entry.addrs.len() >= MAX_ADDRS_PER_PEER {
+                    let worst = entry
+                        .addrs
+                        .iter()
+                        .enumerate()
+                        .min_by_key(|(_, a)| (a.score(), a.last_seen))
+                        .map(|(i, _)| i)
+                        .expect("a full entry has addresses");
+                    entry.addrs.swap_remove(worst);
+                }
+                entry.addrs.push(KnownAddr {
+                    addr,
+                    last_seen: now,
+                    successes: 0,
+                    failures: 0,
+                });
+                entry.addrs.len() - 1
+            }
+        };
+        let known = &mut entry.addrs[position];
+        known.last_seen = now;
+        known
+    }
+
+    /// Drops the least recently seen peers beyond the capacity.
+    fn evict(&mut self) {
+        if self.peers.len() <= self.capacity {
+            return;
+        }
+        let mut by_age = self
+            .peers
+            .iter()
+            .map(|(peer_id, entry)| (entry.last_seen, *peer_id))
+            .collect::<Vec<_>>();
+        by_age.sort();
+        let excess = self.peers.len() - self.capacity;
+        for (_, peer_id) in by_age.into_iter().take(excess) {
+            self.peers.remove(&peer_id);
+        }
+        self.dirty = true;
+    }
+}
+
+/// `addr` without the `/p2p/<peer-id>` the swarm appends when dialing, so dialed and listen
+/// addresses match.
+fn key(addr: &Multiaddr) -> String {
+    let mut addr = addr.clone();
+    if let Some(Protocol::P2p(_)) = addr.iter().last() {
+        addr.pop();
+    }
+    addr.to_string()
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
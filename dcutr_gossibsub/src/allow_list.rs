@@ -0,0 +1,112 @@
+use crate::pipeline::{Candidate, Check, Validator};
+use crate::validation::Rejection;
+use libp2p::PeerId;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Minimum time between two logged connection denials, the ones in between are only counted.
+const DENIAL_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Reads the peers of an `--allow-file`.
+pub fn load_file(path: &Path) -> Result<Vec<PeerId>, Box<dyn Error>> {
+    parse_peer_file(path, "allow file")
+}
+
+/// Reads one peer id per line, skipping blank lines and `#` comments. `what` names the file in
+/// errors.
+pub fn parse_peer_file(path: &Path, what: &str) -> Result<Vec<PeerId>, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {what} {}: {e}", path.display()))?;
+    let peers = contents
+        .lines()
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(n, line)| {
+            line.parse::<PeerId>().map_err(|e| {
+                format!(
+                    "{}:{}: invalid peer id {line:?}: {e}",
+                    path.display(),
+                    n + 1
+                )
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(peers)
+}
+
+/// The peers we may talk to when running with an allow list.
+#[derive(Clone)]
+pub struct AllowList {
+    peers: HashSet<PeerId>,
+}
+
+impl AllowList {
+    /// Fails on an empty list, which would keep the node from ever talking to anyone.
+    pub fn new(peers: impl IntoIterator<Item = PeerId>) -> Result<Self, String> {
+        let peers = peers.into_iter().collect::<HashSet<_>>();
+        if peers.is_empty() {
+            return Err("the allow list is empty, no peer could ever connect".to_string());
+        }
+        Ok(AllowList { peers })
+    }
+
+    /// Whether `peer_id` may talk to us.
+    pub fn contains(&self, peer_id: &PeerId) -> bool {
+        self.peers.contains(peer_id)
+    }
+
+    /// The allowed peers, in no particular order.
+    pub fn peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.peers.iter()
+    }
+}
+
+/// Ignores messages forwarded by peers not on the list.
+impl Validator for AllowList {
+    fn check(&mut self, candidate: &Candidate) -> Check {
+        match self.contains(&candidate.propagation_source) {
+            true => Check::Done(Ok(())),
+            false => Check::Done(Err(Rejection {
+                kind: "not-allowed",
+                reason: "peer is not on the allow list".to_string(),
+                penalize: false,
+            })),
+        }
+    }
+}
+
+/// Logs denied connections at most every `DENIAL_LOG_INTERVAL`, so a peer retrying in a loop
+/// doesn't flood the log.
+#[derive(Default)]
+pub struct DenialLog {
+    logged: Option<Instant>,
+    suppressed: u64,
+}
+
+impl DenialLog {
+    pub fn on_denied(&mut self, peer: &str, cause: &dyn std::fmt::Display) {
+        let now = Instant::now();
+        if self
+            .logged
+            .map_or(false, |at| now.duration_since(at) < DENIAL_LOG_INTERVAL)
+        {
+            self.suppressed += 1;
+            return;
+        }
+        if self.suppressed > 0 {
+            warn!(
+                "Denied connection with {peer}: {cause} ({} more denied since the last report)",
+                self.suppressed
+            );
+        } else {
+            warn!("Denied connection with {peer}: {cause}");
+        }
+        self.logged = Some(now);
+        self.suppressed = 0;
+    }
+}
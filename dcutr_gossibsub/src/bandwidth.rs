@@ -0,0 +1,213 @@
+use crate::paths::TransportPath;
+use futures::{AsyncRead, AsyncWrite};
+use libp2p::{
+    core::muxing::{StreamMuxer, StreamMuxerEvent},
+    PeerId,
+};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+/// Bytes moved in each direction, saturating at `u64::MAX` rather than wrapping.
+#[derive(Default)]
+struct Counters {
+    inbound: AtomicU64,
+    outbound: AtomicU64,
+}
+
+impl Counters {
+    fn add(counter: &AtomicU64, bytes: usize) {
+        let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+            Some(n.saturating_add(bytes as u64))
+        });
+    }
+}
+
+/// Bytes in and out per peer and path, counted on the substreams of every connection. Each
+/// connection is attributed to the path of its own address when it is established, so a
+/// relayed and a direct connection to the same peer are counted apart.
+#[derive(Clone, Default)]
+pub struct Bandwidth {
+    counters: Arc<Mutex<HashMap<(PeerId, TransportPath), Arc<Counters>>>>,
+}
+
+/// What a peer moved over one path, or everything that went over a path.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub inbound: u64,
+    pub outbound: u64,
+}
+
+impl Usage {
+    fn add(&mut self, other: Usage) {
+        self.inbound = self.inbound.saturating_add(other.inbound);
+        self.outbound = self.outbound.saturating_add(other.outbound);
+    }
+}
+
+impl Bandwidth {
+    /// Wraps the muxer of a new connection to `peer_id` so its traffic is counted.
+    pub fn count<M>(&self, muxer: M, peer_id: PeerId, path: TransportPath) -> CountingMuxer<M> {
+        let counters = self
+            .counters
+            .lock()
+            .unwrap()
+            .entry((peer_id, path))
+            .or_default()
+            .clone();
+        CountingMuxer {
+            inner: muxer,
+            counters,
+        }
+    }
+
+    /// The usage of every peer on every path it used, busiest first.
+    pub fn peers(&self) -> Vec<(PeerId, TransportPath, Usage)> {
+        let mut peers = self
+            .counters
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|((peer_id, path), counters)| {
+                let usage = Usage {
+                    inbound: counters.inbound.load(Ordering::Relaxed),
+                    outbound: counters.outbound.load(Ordering::Relaxed),
+                };
+                (*peer_id, *path, usage)
+            })
+            .collect::<Vec<_>>();
+        peers.sort_by_key(|(_, _, usage)| {
+            std::cmp::Reverse(usage.inbound.saturating_add(usage.outbound))
+        });
+        peers
+    }
+
+    /// The usage of all direct and all relayed connections.
+    pub fn totals(&self) -> (Usage, Usage) {
+        let mut direct = Usage::default();
+        let mut relayed = Usage::default();
+        for (_, path, usage) in self.peers() {
+            match path {
+                TransportPath::Direct => direct.add(usage),
+                TransportPath::Relayed(_) => relayed.add(usage),
+            }
+        }
+        (direct, relayed)
+    }
+
+    /// One line per peer and path for `/stats` and the summary on exit.
+    pub fn peer_lines(&self) -> Vec<String> {
+        self.peers()
+            .into_iter()
+            .map(|(peer_id, path, usage)| format!("{peer_id} {path}: {usage}"))
+            .collect()
+    }
+}
+
+/// Formats as `12.0 KiB in, 3.5 KiB out`.
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.1} KiB in, {:.1} KiB out",
+            self.inbound as f64 / 1024.0,
+            self.outbound as f64 / 1024.0
+        )
+    }
+}
+
+/// Formats as `bandwidth: direct 12.0 KiB in, 3.5 KiB out; relayed 1.0 KiB in, 0.5 KiB out`.
+impl fmt::Display for Bandwidth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (direct, relayed) = self.totals();
+        write!(f, "bandwidth: direct {direct}; relayed {relayed}")
+    }
+}
+
+/// A muxer whose substreams count the bytes read and written on them.
+pub struct CountingMuxer<M> {
+    inner: M,
+    counters: Arc<Counters>,
+}
+
+impl<M> StreamMuxer for CountingMuxer<M>
+where
+    M: StreamMuxer + Unpin,
+    M::Substream: Unpin,
+{
+    type Substream = CountingStream<M::Substream>;
+    type Error = M::Error;
+
+    fn poll_inbound(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Self::Substream, Self::Error>> {
+        let counters = self.counters.clone();
+        Pin::new(&mut self.inner)
+            .poll_inbound(cx)
+            .map_ok(|inner| CountingStream { inner, counters })
+    }
+
+    fn poll_outbound(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Self::Substream, Self::Error>> {
+        let counters = self.counters.clone();
+        Pin::new(&mut self.inner)
+            .poll_outbound(cx)
+            .map_ok(|inner| CountingStream { inner, counters })
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<StreamMuxerEvent, Self::Error>> {
+        Pin::new(&mut self.inner).poll(cx)
+    }
+}
+
+/// A substream of a [`CountingMuxer`].
+pub struct CountingStream<S> {
+    inner: S,
+    counters: Arc<Counters>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CountingStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let read = futures::ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        Counters::add(&self.counters.inbound, read);
+        Poll::Ready(Ok(read))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CountingStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let written = futures::ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        Counters::add(&self.counters.outbound, written);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
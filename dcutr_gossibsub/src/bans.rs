@@ -0,0 +1,99 @@
+use crate::console::say;
+use chrono::{Local, TimeZone};
+use libp2p::PeerId;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Peers banned at runtime, with the unix time they were banned at. Kept in a file with one
+/// `<peer-id> <unix-seconds>` line per peer when a path is given.
+pub struct BanList {
+    path: Option<PathBuf>,
+    entries: BTreeMap<PeerId, u64>,
+}
+
+impl BanList {
+    /// Reads the bans kept at `path`, if any. A missing file is an empty list, it is created on
+    /// the first ban.
+    pub fn load(path: Option<PathBuf>) -> Result<Self, Box<dyn Error>> {
+        let mut entries = BTreeMap::new();
+        if let Some(path) = path.as_ref().filter(|p| p.exists()) {
+            let contents = fs::read_to_string(path)
+                .map_err(|e| format!("failed to read ban file {}: {e}", path.display()))?;
+            for (n, line) in contents.lines().map(str::trim).enumerate() {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let (peer_id, banned_at) = line.split_once(' ').unwrap_or((line, "0"));
+                let peer_id = peer_id
+                    .parse::<PeerId>()
+                    .map_err(|e| format!("{}:{}: invalid peer id: {e}", path.display(), n + 1))?;
+                entries.insert(peer_id, banned_at.trim().parse().unwrap_or_default());
+            }
+        }
+        Ok(BanList { path, entries })
+    }
+
+    /// The banned peers, in peer id order.
+    pub fn peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.entries.keys()
+    }
+
+    /// Whether `peer_id` is banned.
+    pub fn contains(&self, peer_id: &PeerId) -> bool {
+        self.entries.contains_key(peer_id)
+    }
+
+    /// Adds `peer_id`, returning false if it was banned already.
+    pub fn ban(&mut self, peer_id: PeerId) -> bool {
+        if self.entries.contains_key(&peer_id) {
+            return false;
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        self.entries.insert(peer_id, now);
+        self.save();
+        true
+    }
+
+    /// Removes `peer_id`, returning false if it wasn't banned.
+    pub fn unban(&mut self, peer_id: &PeerId) -> bool {
+        if self.entries.remove(peer_id).is_none() {
+            return false;
+        }
+        self.save();
+        true
+    }
+
+    /// One line per banned peer with the local time it was banned at.
+    pub fn lines(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .map(
+                |(peer_id, banned_at)| match Local.timestamp_opt(*banned_at as i64, 0).single() {
+                    Some(at) => format!("{peer_id} (banned {})", at.format("%Y-%m-%d %H:%M:%S")),
+                    None => peer_id.to_string(),
+                },
+            )
+            .collect()
+    }
+
+    fn save(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let contents = self
+            .entries
+            .iter()
+            .map(|(peer_id, banned_at)| format!("{peer_id} {banned_at}\n"))
+            .collect::<String>();
+        if let Err(e) = fs::write(path, contents) {
+            say!("Failed to save ban list to {}: {e}", path.display());
+        }
+    }
+}
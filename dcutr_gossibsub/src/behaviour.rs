@@ -0,0 +1,142 @@
+use crate::dm::{self, DmCodec};
+use crate::fallback::{self, FallbackCodec};
+use crate::history::{self, HistoryCodec};
+use crate::idle::KeepAlive;
+use crate::latency::{self, EchoCodec};
+use crate::limits::{ConnectionGate, Limits};
+use crate::rtt::Ping;
+use crate::transfer::{self, FileCodec};
+use libp2p::{
+    allow_block_list::{self, AllowedPeers, BlockedPeers},
+    autonat, dcutr, gossipsub, identify, identity,
+    kad::{store::MemoryStore, Kademlia},
+    ping, relay, request_response,
+    swarm::{behaviour::toggle::Toggle, keep_alive, NetworkBehaviour},
+    PeerId,
+};
+use std::time::Duration;
+
+/// What the client identifies with unless told otherwise.
+pub const PROTOCOL_VERSION: &str =
+    concat!("/", env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+pub const AGENT_VERSION: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Everything the client speaks. The derive generates [`BehaviourEvent`], with a variant per
+/// field named after it.
+#[derive(NetworkBehaviour)]
+pub struct Behaviour {
+    pub relay_client: relay::client::Behaviour,
+    pub ping: Ping,
+    pub identify: identify::Behaviour,
+    pub dcutr: dcutr::Behaviour,
+    pub gossipsub: gossipsub::Behaviour,
+    /// Only with `--kademlia`.
+    pub kademlia: Toggle<Kademlia<MemoryStore>>,
+    pub autonat: autonat::Behaviour,
+    /// Peers banned with `/ban` or the ban file.
+    pub blocked: allow_block_list::Behaviour<BlockedPeers>,
+    /// Only with an allow list, other peers are turned away.
+    pub allowed: Toggle<allow_block_list::Behaviour<AllowedPeers>>,
+    pub dm: request_response::Behaviour<DmCodec>,
+    pub history: request_response::Behaviour<HistoryCodec>,
+    pub transfer: request_response::Behaviour<FileCodec>,
+    pub echo: request_response::Behaviour<EchoCodec>,
+    /// Without `--no-fallback-channel`, messages sent directly while gossipsub has no mesh.
+    pub fallback: Toggle<request_response::Behaviour<FallbackCodec>>,
+    /// Turns away connections beyond the configured limits.
+    pub limits: ConnectionGate,
+    /// Only with `--keep-alive always`, holds every connection open.
+    pub keep_alive: Toggle<keep_alive::Behaviour>,
+}
+
+/// How [`Behaviour::new`] sets up the protocols that aren't passed in ready made.
+pub struct BehaviourConfig {
+    /// Whether to run a Kademlia DHT.
+    pub kademlia: bool,
+    /// Whether to send messages directly while gossipsub has no mesh.
+    pub fallback_channel: bool,
+    /// Only these peers can connect if given, which has to include the relay.
+    pub allowed: Option<Vec<PeerId>>,
+    /// How long a direct message may go unacknowledged.
+    pub dm_timeout: Duration,
+    /// Closes a connection after its configured number of failed pings in a row.
+    pub ping: ping::Config,
+    pub identify: identify::Config,
+    /// With [`KeepAlive::Always`] connections stay open until something closes them explicitly.
+    pub keep_alive: KeepAlive,
+    pub limits: Limits,
+}
+
+impl BehaviourConfig {
+    /// The defaults of the command line, identifying with `local_key`.
+    pub fn new(local_key: &identity::Keypair) -> Self {
+        BehaviourConfig {
+            kademlia: false,
+            fallback_channel: true,
+            allowed: None,
+            dm_timeout: Duration::from_secs(10),
+            ping: ping::Config::new(),
+            identify: identify::Config::new(PROTOCOL_VERSION.to_string(), local_key.public()),
+            keep_alive: KeepAlive::Default,
+            limits: Limits::default(),
+        }
+    }
+}
+
+impl Behaviour {
+    /// `gossipsub` comes configured and subscribed, the rest is set up as `config` says.
+    pub fn new(
+        local_key: &identity::Keypair,
+        relay_client: relay::client::Behaviour,
+        gossipsub: gossipsub::Behaviour,
+        config: BehaviourConfig,
+    ) -> Self {
+        let BehaviourConfig {
+            kademlia,
+            fallback_channel,
+            allowed,
+            dm_timeout,
+            ping,
+            identify,
+            keep_alive,
+            limits,
+        } = config;
+        let local_peer_id = local_key.public().to_peer_id();
+        Behaviour {
+            relay_client,
+            ping: Ping::new(ping),
+            identify: identify::Behaviour::new(identify),
+            dcutr: dcutr::Behaviour::new(local_peer_id),
+            gossipsub,
+            kademlia: kademlia
+                .then(|| Kademlia::new(local_peer_id, MemoryStore::new(local_peer_id)))
+                .into(),
+            autonat: autonat::Behaviour::new(
+                local_peer_id,
+                autonat::Config {
+                    boot_delay: Duration::from_secs(1),
+                    ..Default::default()
+                },
+            ),
+            blocked: allow_block_list::Behaviour::default(),
+            allowed: allowed
+                .map(|peers| {
+                    let mut allowed = allow_block_list::Behaviour::<AllowedPeers>::default();
+                    for peer_id in peers {
+                        allowed.allow_peer(peer_id);
+                    }
+                    allowed
+                })
+                .into(),
+            dm: dm::behaviour(dm_timeout),
+            history: history::behaviour(),
+            transfer: transfer::behaviour(),
+            echo: latency::behaviour(),
+            fallback: fallback_channel.then(fallback::behaviour).into(),
+            limits: ConnectionGate::new(limits),
+            keep_alive: (keep_alive == KeepAlive::Always)
+                .then(keep_alive::Behaviour::default)
+                .into(),
+        }
+    }
+}
@@ -0,0 +1,407 @@
+use crate::console::say;
+use crate::envelope;
+use libp2p::{gossipsub::IdentTopic, PeerId};
+use rand::RngCore;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// The topic benchmark traffic goes over, apart from the chat.
+pub const TOPIC: &str = "dcutr-bench";
+
+pub const EXIT_BENCH_LOSS: u8 = 4;
+
+/// Frames published per round of the event loop, before the swarm gets to send them.
+pub const BATCH: usize = 64;
+
+/// Frames start with this, so they can't be mistaken for envelopes.
+const MAGIC: &[u8] = b"BENCH1";
+/// Magic, frame kind and two u32 fields.
+pub const HEADER_LEN: usize = MAGIC.len() + 1 + 8;
+
+/// How long the publisher waits after the receiver subscribed, for a hole punch to settle.
+const SETTLE: Duration = Duration::from_secs(3);
+/// How long the receiver waits for the end marker after the last frame.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the publisher waits for the receiver's report after the end marker.
+const REPORT_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long the receiver keeps running after publishing its report, so it gets sent.
+const LINGER: Duration = Duration::from_secs(2);
+
+/// What `--bench` does, `publish:<count>x<size>` in dial mode or `receive` in listen mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BenchSpec {
+    Publish { count: u32, size: usize },
+    Receive,
+}
+
+impl FromStr for BenchSpec {
+    type Err = String;
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        if spec == "receive" {
+            return Ok(BenchSpec::Receive);
+        }
+        let usage = || format!("Expected 'publish:<count>x<size>' or 'receive', got '{spec}'");
+        let (count, size) = spec
+            .strip_prefix("publish:")
+            .and_then(|rest| rest.split_once('x'))
+            .ok_or_else(usage)?;
+        let count = count.parse().map_err(|_| usage())?;
+        let size = size.parse().map_err(|_| usage())?;
+        if count == 0 || size < HEADER_LEN {
+            return Err(format!(
+                "The count must be positive and the size at least {HEADER_LEN} bytes"
+            ));
+        }
+        Ok(BenchSpec::Publish { count, size })
+    }
+}
+
+enum Frame {
+    Data { seq: u32 },
+    End { count: u32 },
+    Report { received: u32 },
+}
+
+impl Frame {
+    fn encode(&self, size: usize) -> Vec<u8> {
+        let (kind, value) = match self {
+            Frame::Data { seq } => (0, *seq),
+            Frame::End { count } => (1, *count),
+            Frame::Report { received } => (2, *received),
+        };
+        let mut data = Vec::with_capacity(size.max(HEADER_LEN));
+        data.extend_from_slice(MAGIC);
+        data.push(kind);
+        data.extend_from_slice(&value.to_be_bytes());
+        data.extend_from_slice(&0u32.to_be_bytes());
+        if let Frame::Data { .. } = self {
+            let mut padding = vec![0; size - HEADER_LEN];
+            rand::thread_rng().fill_bytes(&mut padding);
+            data.extend_from_slice(&padding);
+        }
+        data
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        let rest = data.strip_prefix(MAGIC)?;
+        let value = u32::from_be_bytes(rest.get(1..5)?.try_into().ok()?);
+        match rest.first()? {
+            0 => Some(Frame::Data { seq: value }),
+            1 => Some(Frame::End { count: value }),
+            2 => Some(Frame::Report { received: value }),
+            _ => None,
+        }
+    }
+}
+
+/// The numbers of a finished run, also what `--bench-results` gets as a JSON line.
+#[derive(Debug, Serialize)]
+pub struct BenchResult {
+    pub role: &'static str,
+    pub timestamp: u64,
+    pub messages: u32,
+    pub bytes: u64,
+    pub elapsed_ms: u64,
+    pub messages_per_sec: f64,
+    pub mb_per_sec: f64,
+    /// Messages that didn't arrive, unknown if the publisher got no report or the receiver no
+    /// end marker.
+    pub lost: Option<u32>,
+    pub loss_percent: Option<f64>,
+    pub direct: bool,
+}
+
+impl BenchResult {
+    fn new(
+        role: &'static str,
+        messages: u32,
+        bytes: u64,
+        elapsed: Duration,
+        lost: Option<(u32, u32)>,
+        direct: bool,
+    ) -> Self {
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        BenchResult {
+            role,
+            timestamp: envelope::unix_millis(),
+            messages,
+            bytes,
+            elapsed_ms: elapsed.as_millis() as u64,
+            messages_per_sec: messages as f64 / secs,
+            mb_per_sec: bytes as f64 / secs / 1_000_000.0,
+            lost: lost.map(|(lost, _)| lost),
+            loss_percent: lost.map(|(lost, expected)| match expected {
+                0 => 0.0,
+                expected => lost as f64 * 100.0 / expected as f64,
+            }),
+            direct,
+        }
+    }
+}
+
+/// Publishes `count` frames to the receiver, then the end marker, and waits for its report.
+struct Publisher {
+    remote: PeerId,
+    count: u32,
+    size: usize,
+    sent: u32,
+    start_at: Option<Instant>,
+    started: Option<Instant>,
+    /// When the end marker went out, and how long sending took.
+    ended: Option<(Instant, Duration)>,
+}
+
+/// Counts the frames that arrive until the end marker and reports back.
+#[derive(Default)]
+struct Receiver {
+    received: u32,
+    bytes: u64,
+    first: Option<Instant>,
+    last: Option<Instant>,
+    direct: bool,
+    /// The result, printed once the report had time to go out.
+    done: Option<(BenchResult, Instant)>,
+}
+
+enum Role {
+    Publisher(Publisher),
+    Receiver(Receiver),
+}
+
+/// A `--bench` run, which ends the process with its results.
+pub struct Bench {
+    role: Role,
+    max_loss: f64,
+    results: Option<PathBuf>,
+    exit_code: Option<u8>,
+}
+
+impl Bench {
+    /// `remote` is the peer to publish to, required for [`BenchSpec::Publish`].
+    pub fn new(
+        spec: BenchSpec,
+        remote: Option<PeerId>,
+        max_loss: f64,
+        results: Option<PathBuf>,
+    ) -> Result<Self, String> {
+        let role = match (spec, remote) {
+            (BenchSpec::Publish { count, size }, Some(remote)) => Role::Publisher(Publisher {
+                remote,
+                count,
+                size,
+                sent: 0,
+                start_at: None,
+                started: None,
+                ended: None,
+            }),
+            (BenchSpec::Publish { .. }, None) => {
+                return Err("--bench publish needs the remote peer id".to_string())
+            }
+            (BenchSpec::Receive, _) => Role::Receiver(Receiver::default()),
+        };
+        Ok(Bench {
+            role,
+            max_loss,
+            results,
+            exit_code: None,
+        })
+    }
+
+    /// The code to exit with once the run is over.
+    pub fn exit_code(&self) -> Option<u8> {
+        self.exit_code
+    }
+
+    pub fn topic() -> IdentTopic {
+        IdentTopic::new(TOPIC)
+    }
+
+    /// The receiver subscribing to the bench topic starts the countdown to the run.
+    pub fn on_subscribed(&mut self, peer_id: PeerId, now: Instant) {
+        if let Role::Publisher(publisher) = &mut self.role {
+            if peer_id == publisher.remote && publisher.start_at.is_none() {
+                publisher.start_at = Some(now + SETTLE);
+                say!("Benchmark receiver subscribed, publishing in {SETTLE:?}");
+            }
+        }
+    }
+
+    /// The next frame to publish, if the run is underway and there's one left. Call
+    /// [`Bench::on_sent`] once it was published.
+    pub fn next_frame(&mut self, now: Instant) -> Option<Vec<u8>> {
+        let publisher = match &mut self.role {
+            Role::Publisher(publisher) => publisher,
+            Role::Receiver(_) => return None,
+        };
+        if publisher.start_at.map_or(true, |at| at > now) || publisher.ended.is_some() {
+            return None;
+        }
+        if publisher.started.is_none() {
+            publisher.started = Some(now);
+            say!(
+                "Benchmark: publishing {} messages of {} bytes",
+                publisher.count,
+                publisher.size
+            );
+        }
+        Some(if publisher.sent < publisher.count {
+            Frame::Data {
+                seq: publisher.sent,
+            }
+            .encode(publisher.size)
+        } else {
+            Frame::End {
+                count: publisher.count,
+            }
+            .encode(0)
+        })
+    }
+
+    pub fn on_sent(&mut self, now: Instant) {
+        if let Role::Publisher(publisher) = &mut self.role {
+            if publisher.sent < publisher.count {
+                publisher.sent += 1;
+            } else if let Some(started) = publisher.started {
+                publisher.ended = Some((now, now - started));
+            }
+        }
+    }
+
+    /// Handles a frame from the bench topic that came in over a `direct` connection or not,
+    /// returning the frame to publish in reply.
+    pub fn on_message(&mut self, data: &[u8], direct: bool, now: Instant) -> Option<Vec<u8>> {
+        let frame = Frame::decode(data)?;
+        match (&mut self.role, frame) {
+            (Role::Receiver(receiver), Frame::Data { .. }) if receiver.done.is_none() => {
+                receiver.received += 1;
+                receiver.bytes += data.len() as u64;
+                receiver.first.get_or_insert(now);
+                receiver.last = Some(now);
+                receiver.direct = direct;
+                None
+            }
+            (Role::Receiver(receiver), Frame::End { count }) if receiver.done.is_none() => {
+                let elapsed = receiver.first.map_or(Duration::ZERO, |first| now - first);
+                let lost = count.saturating_sub(receiver.received);
+                let result = BenchResult::new(
+                    "receiver",
+                    receiver.received,
+                    receiver.bytes,
+                    elapsed,
+                    Some((lost, count)),
+                    direct,
+                );
+                receiver.done = Some((result, now + LINGER));
+                Some(
+                    Frame::Report {
+                        received: receiver.received,
+                    }
+                    .encode(0),
+                )
+            }
+            (Role::Publisher(publisher), Frame::Report { received }) => {
+                if let Some((_, elapsed)) = publisher.ended {
+                    let lost = publisher.count.saturating_sub(received);
+                    let result = publisher.result(elapsed, Some((lost, publisher.count)), direct);
+                    self.finish(result);
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Ends the run once the receiver is done or the other side went quiet for too long.
+    pub fn on_tick(&mut self, direct: bool, now: Instant) {
+        match &mut self.role {
+            Role::Receiver(receiver) => {
+                if let Some((_, at)) = &receiver.done {
+                    if *at <= now {
+                        let (result, _) = receiver.done.take().unwrap();
+                        self.finish(result);
+                    }
+                } else if let (Some(first), Some(last)) = (receiver.first, receiver.last) {
+                    if now - last > IDLE_TIMEOUT {
+                        say!("Benchmark: no end marker after {IDLE_TIMEOUT:?}, the run is over");
+                        let result = BenchResult::new(
+                            "receiver",
+                            receiver.received,
+                            receiver.bytes,
+                            last - first,
+                            None,
+                            receiver.direct,
+                        );
+                        self.finish(result);
+                    }
+                }
+            }
+            Role::Publisher(publisher) => {
+                if let Some((at, elapsed)) = publisher.ended {
+                    if now - at > REPORT_TIMEOUT {
+                        say!("Benchmark: no report from the receiver after {REPORT_TIMEOUT:?}");
+                        let result = publisher.result(elapsed, None, direct);
+                        self.finish(result);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Prints and stores the result and ends the run, to exit with [`EXIT_BENCH_LOSS`] if more
+    /// than `max_loss` percent of the messages were lost or the loss is unknown.
+    fn finish(&mut self, result: BenchResult) {
+        if self.exit_code.is_some() {
+            return;
+        }
+        let lost = match result.lost {
+            Some(lost) => lost.to_string(),
+            None => "unknown".to_string(),
+        };
+        say!(
+            "bench {}: {} messages, {:.1} msg/s, {:.2} MB/s, {} lost, {} path, {:.2}s",
+            result.role,
+            result.messages,
+            result.messages_per_sec,
+            result.mb_per_sec,
+            lost,
+            if result.direct { "direct" } else { "relayed" },
+            result.elapsed_ms as f64 / 1000.0
+        );
+        if let Some(path) = &self.results {
+            let written = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| {
+                    let line = serde_json::to_string(&result).expect("result serializes to JSON");
+                    writeln!(file, "{line}")
+                });
+            if let Err(e) = written {
+                say!("Failed to write the results to {}: {e}", path.display());
+            }
+        }
+        let loss = result.loss_percent.unwrap_or(f64::INFINITY);
+        self.exit_code = Some(if loss > self.max_loss {
+            EXIT_BENCH_LOSS
+        } else {
+            0
+        });
+    }
+}
+
+impl Publisher {
+    fn result(&self, elapsed: Duration, lost: Option<(u32, u32)>, direct: bool) -> BenchResult {
+        BenchResult::new(
+            "publisher",
+            self.count,
+            self.count as u64 * self.size as u64,
+            elapsed,
+            lost,
+            direct,
+        )
+    }
+}
@@ -0,0 +1,444 @@
+use crate::behaviour::BehaviourEvent;
+use libp2p::{
+    autonat::{self, NatStatus},
+    core::{
+        multiaddr::{Multiaddr, Protocol},
+        transport::ListenerId,
+    },
+    identify, relay,
+    swarm::SwarmEvent,
+    PeerId,
+};
+use std::collections::HashSet;
+use std::fmt;
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+pub const EXIT_LISTEN_TIMEOUT: u8 = 10;
+pub const EXIT_RELAY_IDENTIFY_TIMEOUT: u8 = 11;
+pub const EXIT_RESERVATION_TIMEOUT: u8 = 12;
+pub const EXIT_CIRCUIT_TIMEOUT: u8 = 13;
+
+/// The bootstrap phases `--bootstrap-timeout` covers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Binding the local listener.
+    Listen,
+    /// Exchanging identify with the relay to learn our observed address.
+    RelayIdentify,
+    /// Getting the relay reservation accepted, in listen mode.
+    Reservation,
+    /// Establishing the circuit to a remote peer, in dial mode.
+    CircuitDial,
+}
+
+impl Phase {
+    fn exit_code(self) -> u8 {
+        match self {
+            Phase::Listen => EXIT_LISTEN_TIMEOUT,
+            Phase::RelayIdentify => EXIT_RELAY_IDENTIFY_TIMEOUT,
+            Phase::Reservation => EXIT_RESERVATION_TIMEOUT,
+            Phase::CircuitDial => EXIT_CIRCUIT_TIMEOUT,
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Listen => "listen",
+            Phase::RelayIdentify => "relay identify",
+            Phase::Reservation => "relay reservation",
+            Phase::CircuitDial => "circuit dial",
+        })
+    }
+}
+
+/// A bootstrap phase that ran out of time, with what it was still waiting for.
+#[derive(Debug)]
+pub struct TimedOut {
+    phase: Phase,
+    timeout: Duration,
+    missing: String,
+}
+
+impl TimedOut {
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    /// The code the process exits with, so scripts can tell where a start got stuck.
+    pub fn exit_code(&self) -> u8 {
+        self.phase.exit_code()
+    }
+}
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Bootstrap timed out after {}s in the {} phase: {}",
+            self.timeout.as_secs(),
+            self.phase,
+            self.missing
+        )
+    }
+}
+
+/// The deadline of the bootstrap phase in progress.
+struct Deadline {
+    timeout: Duration,
+    current: Option<(Phase, Instant)>,
+    overdue: bool,
+}
+
+impl Deadline {
+    fn new(timeout: Duration) -> Self {
+        Deadline {
+            timeout,
+            current: None,
+            overdue: false,
+        }
+    }
+
+    /// Starts the deadline of `phase`, or stops it for `None`.
+    fn start(&mut self, phase: Option<Phase>) {
+        self.current = phase.map(|phase| (phase, Instant::now() + self.timeout));
+        self.overdue = false;
+    }
+
+    /// Whether the phase ran out of time. It is only reported on the second poll past the
+    /// deadline, so a completion already queued in the swarm when the deadline passed still gets
+    /// handled first.
+    fn expired(&mut self, now: Instant) -> bool {
+        match self.current {
+            Some((_, at)) if now >= at => std::mem::replace(&mut self.overdue, true),
+            _ => false,
+        }
+    }
+
+    /// The phase in progress as failed, with what it was still waiting for.
+    fn fail(&self, missing: String) -> TimedOut {
+        let (phase, _) = self.current.expect("a bootstrap phase is in progress");
+        TimedOut {
+            phase,
+            timeout: self.timeout,
+            missing,
+        }
+    }
+}
+
+/// What bootstrapping waits for, taken from the swarm events.
+#[derive(Debug)]
+pub enum Progress {
+    NewListenAddr(ListenerId),
+    ListenerFailed(ListenerId, String),
+    ConnectionEstablished(PeerId),
+    DialFailed(Option<PeerId>, String),
+    IdentifySent(PeerId),
+    IdentifyReceived(PeerId),
+    NatStatus(NatStatus),
+    ReservationAccepted,
+    CircuitEstablished,
+}
+
+impl Progress {
+    /// The progress `event` makes, if it's one bootstrapping waits for.
+    pub fn of<E: fmt::Display>(event: &SwarmEvent<BehaviourEvent, E>) -> Option<Self> {
+        match event {
+            SwarmEvent::NewListenAddr { listener_id, .. } => {
+                Some(Progress::NewListenAddr(*listener_id))
+            }
+            SwarmEvent::ListenerError { listener_id, error } => {
+                Some(Progress::ListenerFailed(*listener_id, error.to_string()))
+            }
+            SwarmEvent::ListenerClosed {
+                listener_id,
+                reason,
+                ..
+            } => Some(Progress::ListenerFailed(
+                *listener_id,
+                match reason {
+                    Ok(()) => "the listener closed before it was bound".to_string(),
+                    Err(e) => e.to_string(),
+                },
+            )),
+            SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+                Some(Progress::ConnectionEstablished(*peer_id))
+            }
+            SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
+                Some(Progress::DialFailed(*peer_id, error.to_string()))
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Sent { peer_id })) => {
+                Some(Progress::IdentifySent(*peer_id))
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Received {
+                peer_id,
+                ..
+            })) => Some(Progress::IdentifyReceived(*peer_id)),
+            SwarmEvent::Behaviour(BehaviourEvent::Autonat(autonat::Event::StatusChanged {
+                new,
+                ..
+            })) => Some(Progress::NatStatus(new.clone())),
+            SwarmEvent::Behaviour(BehaviourEvent::RelayClient(
+                relay::client::Event::ReservationReqAccepted { .. },
+            )) => Some(Progress::ReservationAccepted),
+            SwarmEvent::Behaviour(BehaviourEvent::RelayClient(
+                relay::client::Event::OutboundCircuitEstablished { .. },
+            )) => Some(Progress::CircuitEstablished),
+            _ => None,
+        }
+    }
+}
+
+/// Where the client is in getting online.
+#[derive(Debug)]
+pub enum State {
+    /// Waiting for each of these listeners to report an address.
+    WaitingForListeners(HashSet<ListenerId>),
+    /// Exchanging identify with the relay to learn our observed address.
+    ConnectingRelay {
+        connected: bool,
+        learned_observed_addr: bool,
+        told_relay_observed_addr: bool,
+    },
+    /// Waiting for the relay to accept our reservation, in listen mode.
+    Reserving,
+    /// Waiting for a circuit to, or any connection with, one of the remote peers in dial mode.
+    DialingCircuit(Vec<PeerId>),
+    Running,
+}
+
+impl State {
+    fn phase(&self) -> Option<Phase> {
+        match self {
+            State::WaitingForListeners(_) => Some(Phase::Listen),
+            State::ConnectingRelay { .. } => Some(Phase::RelayIdentify),
+            State::Reserving => Some(Phase::Reservation),
+            State::DialingCircuit(_) => Some(Phase::CircuitDial),
+            State::Running => None,
+        }
+    }
+
+    /// What the state is still waiting for, to tell when it timed out.
+    fn missing(&self) -> String {
+        match self {
+            State::WaitingForListeners(_) => "no listen address came up".to_string(),
+            State::ConnectingRelay {
+                connected: false, ..
+            } => "never connected to the relay".to_string(),
+            State::ConnectingRelay {
+                learned_observed_addr: false,
+                ..
+            } => "never received identify from the relay".to_string(),
+            State::ConnectingRelay { .. } => "never sent identify to the relay".to_string(),
+            State::Reserving => "the relay never accepted the reservation".to_string(),
+            State::DialingCircuit(remotes) => format!(
+                "no circuit to {} was established",
+                remotes
+                    .iter()
+                    .map(|r| r.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" or ")
+            ),
+            State::Running => "nothing".to_string(),
+        }
+    }
+}
+
+/// What the event loop has to do once a bootstrap phase completed.
+#[derive(Debug)]
+pub enum Step {
+    /// The listeners are bound, dial the relay from their port.
+    DialRelay,
+    /// Our addresses are known, reserve a slot at the relay or dial the remote peer depending on
+    /// the NAT status.
+    Connect(NatStatus),
+    /// AutoNAT found us publicly reachable at the address after we went on without a verdict.
+    Reachable(Multiaddr),
+    /// Bootstrapping is done, the input held back so far can be handled.
+    Running,
+}
+
+/// Drives the client through the bootstrap states. There is a single event loop for all of them:
+/// it feeds the [`Progress`] it sees to [`Bootstrap::on_progress`] and acts on the [`Step`]s it
+/// gets back, handling every event as usual otherwise.
+pub struct Bootstrap {
+    state: State,
+    relay: Option<PeerId>,
+    remotes: Vec<PeerId>,
+    autonat_wait: Option<Duration>,
+    /// The latest AutoNAT verdict, whenever it came in.
+    nat_status: NatStatus,
+    /// Until when a verdict arriving after we went on without one is still acted on.
+    probe_until: Option<Instant>,
+    deadline: Deadline,
+}
+
+impl Bootstrap {
+    /// `remotes` are the peers to dial in dial mode, `relay` the peer id of the relay if its address
+    /// has one. `autonat_wait` is how long an AutoNAT verdict is acted on once we went on without
+    /// one, `None` ignores the probe. Nothing waits for it.
+    pub fn new(
+        listeners: HashSet<ListenerId>,
+        relay: Option<PeerId>,
+        remotes: Vec<PeerId>,
+        autonat_wait: Option<Duration>,
+        timeout: Duration,
+    ) -> Self {
+        let mut bootstrap = Bootstrap {
+            state: State::Running,
+            relay,
+            remotes,
+            autonat_wait,
+            nat_status: NatStatus::Unknown,
+            probe_until: None,
+            deadline: Deadline::new(timeout),
+        };
+        bootstrap.enter(State::WaitingForListeners(listeners));
+        bootstrap
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(self.state, State::Running)
+    }
+
+    fn enter(&mut self, state: State) {
+        debug!(?state, "Bootstrap state changed");
+        self.deadline.start(state.phase());
+        self.state = state;
+    }
+
+    /// Advances the state, returning what to do next if a phase completed. Errors end the
+    /// bootstrap for good.
+    pub fn on_progress(&mut self, progress: Progress) -> Result<Option<Step>, String> {
+        // Without a `/p2p` suffix on the relay address, whoever talks to us first is the relay.
+        let relay = self.relay;
+        let is_relay = |peer_id: &PeerId| relay.map_or(true, |relay| relay == *peer_id);
+        if let Progress::NatStatus(status) = &progress {
+            self.nat_status = status.clone();
+            if let NatStatus::Public(addr) = status {
+                if self.probe_until.take().is_some() {
+                    return Ok(Some(Step::Reachable(addr.clone())));
+                }
+            }
+            return Ok(None);
+        }
+        let next = match (&mut self.state, progress) {
+            (State::WaitingForListeners(pending), Progress::NewListenAddr(listener_id)) => {
+                pending.remove(&listener_id);
+                pending.is_empty().then_some(State::ConnectingRelay {
+                    connected: false,
+                    learned_observed_addr: false,
+                    told_relay_observed_addr: false,
+                })
+            }
+            (State::WaitingForListeners(pending), Progress::ListenerFailed(listener_id, error))
+                if pending.contains(&listener_id) =>
+            {
+                return Err(format!("failed to listen: {error}"));
+            }
+            (
+                State::ConnectingRelay { connected, .. },
+                Progress::ConnectionEstablished(peer_id),
+            ) if is_relay(&peer_id) => {
+                *connected = true;
+                None
+            }
+            (State::ConnectingRelay { .. }, Progress::DialFailed(peer_id, error))
+                if peer_id.map_or(true, |peer_id| is_relay(&peer_id)) =>
+            {
+                return Err(format!("failed to connect to the relay: {error}"));
+            }
+            (
+                State::ConnectingRelay {
+                    told_relay_observed_addr,
+                    ..
+                },
+                Progress::IdentifySent(peer_id),
+            ) if is_relay(&peer_id) => {
+                info!("Told relay its public address.");
+                *told_relay_observed_addr = true;
+                None
+            }
+            (
+                State::ConnectingRelay {
+                    learned_observed_addr,
+                    ..
+                },
+                Progress::IdentifyReceived(peer_id),
+            ) if is_relay(&peer_id) => {
+                *learned_observed_addr = true;
+                None
+            }
+            (State::Reserving, Progress::ReservationAccepted)
+            | (State::DialingCircuit(_), Progress::CircuitEstablished) => Some(State::Running),
+            // Reached directly or through the DHT, no circuit needed.
+            (State::DialingCircuit(remotes), Progress::ConnectionEstablished(peer_id))
+                if remotes.contains(&peer_id) =>
+            {
+                Some(State::Running)
+            }
+            _ => None,
+        };
+        if let Some(state) = next {
+            let step = match state {
+                State::ConnectingRelay { .. } => Step::DialRelay,
+                _ => Step::Running,
+            };
+            self.enter(state);
+            return Ok(Some(step));
+        }
+        if let State::ConnectingRelay {
+            learned_observed_addr: true,
+            told_relay_observed_addr: true,
+            ..
+        } = self.state
+        {
+            // Going on right away, the relay works either way. A verdict that comes in while the
+            // probe still runs is acted on when it does.
+            let nat_status = match (self.autonat_wait, &self.nat_status) {
+                (None, _) => NatStatus::Unknown,
+                (Some(_), NatStatus::Public(_)) => self.nat_status.clone(),
+                (Some(wait), _) => {
+                    self.probe_until = Some(Instant::now() + wait);
+                    NatStatus::Unknown
+                }
+            };
+            return Ok(Some(self.connect(nat_status)));
+        }
+        Ok(None)
+    }
+
+    /// Checks the timers, from the event loop tick. The AutoNAT probe just ends without a
+    /// verdict, other phases running out of time fail the bootstrap.
+    pub fn poll(&mut self, now: Instant) -> Result<(), TimedOut> {
+        if self.probe_until.map_or(false, |until| now >= until) {
+            info!("No AutoNAT verdict, staying behind the relay.");
+            self.probe_until = None;
+        }
+        if self.deadline.expired(now) {
+            return Err(self.deadline.fail(self.state.missing()));
+        }
+        Ok(())
+    }
+
+    /// Moves on to reaching the remote peer or reserving a slot at the relay, unless we're
+    /// publicly reachable in listen mode and there's nothing left to wait for.
+    fn connect(&mut self, nat_status: NatStatus) -> Step {
+        match (self.remotes.is_empty(), &nat_status) {
+            (false, _) => self.enter(State::DialingCircuit(self.remotes.clone())),
+            (true, NatStatus::Public(_)) => self.enter(State::Running),
+            (true, _) => self.enter(State::Reserving),
+        }
+        Step::Connect(nat_status)
+    }
+}
+
+/// The address of `peer_id` through a circuit of the relay at `relay_address`.
+pub fn relayed_addr(relay_address: &Multiaddr, peer_id: PeerId) -> Multiaddr {
+    relay_address
+        .clone()
+        .with(Protocol::P2pCircuit)
+        .with(Protocol::P2p(peer_id.into()))
+}
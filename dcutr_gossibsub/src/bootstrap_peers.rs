@@ -0,0 +1,137 @@
+use crate::console::say;
+use libp2p::{
+    core::multiaddr::{Multiaddr, Protocol},
+    PeerId,
+};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// Returns the peer id from the trailing `/p2p/<peer-id>` component of `addr`, if any.
+pub fn peer_id_of(addr: &Multiaddr) -> Option<PeerId> {
+    addr.iter().find_map(|p| match p {
+        Protocol::P2p(hash) => PeerId::from_multihash(hash).ok(),
+        _ => None,
+    })
+}
+
+/// Reads one multiaddr per line, skipping blank lines and `#` comments.
+pub fn load_file(path: &Path) -> Result<Vec<Multiaddr>, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("failed to read bootstrap file {}: {e}", path.display()))?;
+    let addrs = contents
+        .lines()
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(n, line)| {
+            line.parse::<Multiaddr>().map_err(|e| {
+                format!(
+                    "{}:{}: invalid multiaddr {line:?}: {e}",
+                    path.display(),
+                    n + 1
+                )
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if addrs.is_empty() {
+        return Err(format!("bootstrap file {} contains no addresses", path.display()).into());
+    }
+    Ok(addrs)
+}
+
+struct BootstrapPeer {
+    peer_id: PeerId,
+    addr: Multiaddr,
+    connected: bool,
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+/// Bootstrap nodes dialed at startup. Unreachable nodes are redialed in the background with
+/// exponential backoff so they never hold up the relay bootstrap phases.
+pub struct BootstrapPeers {
+    peers: Vec<BootstrapPeer>,
+}
+
+impl BootstrapPeers {
+    pub fn new(addrs: Vec<Multiaddr>) -> Result<Self, Box<dyn Error>> {
+        let peers = addrs
+            .into_iter()
+            .map(|addr| match peer_id_of(&addr) {
+                Some(peer_id) => Ok(BootstrapPeer {
+                    peer_id,
+                    addr,
+                    connected: false,
+                    failures: 0,
+                    retry_at: Some(Instant::now()),
+                }),
+                None => Err(format!(
+                    "bootstrap address {addr} has no /p2p/<peer-id> component"
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(BootstrapPeers { peers })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    pub fn addresses(&self) -> impl Iterator<Item = (PeerId, &Multiaddr)> {
+        self.peers.iter().map(|p| (p.peer_id, &p.addr))
+    }
+
+    /// Addresses whose (re)dial is due. They won't be returned again until a failure is reported.
+    pub fn due(&mut self, now: Instant) -> Vec<Multiaddr> {
+        self.peers
+            .iter_mut()
+            .filter(|p| !p.connected && p.retry_at.map_or(false, |at| at <= now))
+            .map(|p| {
+                p.retry_at = None;
+                p.addr.clone()
+            })
+            .collect()
+    }
+
+    pub fn on_connected(&mut self, peer_id: &PeerId) {
+        for peer in self.peers.iter_mut().filter(|p| &p.peer_id == peer_id) {
+            if !peer.connected {
+                say!("Connected to bootstrap peer {}", peer.addr);
+            }
+            peer.connected = true;
+            peer.failures = 0;
+            peer.retry_at = None;
+        }
+    }
+
+    pub fn on_disconnected(&mut self, peer_id: &PeerId) {
+        for peer in self.peers.iter_mut().filter(|p| &p.peer_id == peer_id) {
+            info!("Lost connection to bootstrap peer {}, redialing", peer.addr);
+            peer.connected = false;
+            peer.retry_at = Some(Instant::now());
+        }
+    }
+
+    pub fn on_dial_failure(&mut self, peer_id: &PeerId) {
+        for peer in self
+            .peers
+            .iter_mut()
+            .filter(|p| &p.peer_id == peer_id && !p.connected)
+        {
+            peer.failures += 1;
+            let delay = Duration::from_secs(1 << peer.failures.min(9)).min(MAX_RETRY_DELAY);
+            say!(
+                "Failed to reach bootstrap peer {} ({} failures), retrying in {:?}",
+                peer.addr,
+                peer.failures,
+                delay
+            );
+            peer.retry_at = Some(Instant::now() + delay);
+        }
+    }
+}
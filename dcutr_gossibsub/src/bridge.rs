@@ -0,0 +1,427 @@
+use futures::channel::mpsc::UnboundedSender;
+use libp2p::PeerId;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tracing::{info, warn};
+
+/// How many messages wait to be written to a client before further ones are dropped for it.
+const CLIENT_QUEUE: usize = 256;
+/// Largest length prefixed message accepted from a client.
+const MAX_FRAME: usize = 1024 * 1024;
+/// How many UDP senders are answered, the ones heard from last.
+const MAX_UDP_PEERS: usize = 64;
+
+/// How messages are delimited on a TCP bridge.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Framing {
+    /// One message per line.
+    Lines,
+    /// Each message preceded by its length in bytes, as a big endian u32.
+    LengthPrefixed,
+}
+
+impl FromStr for Framing {
+    type Err = String;
+    fn from_str(framing: &str) -> Result<Self, Self::Err> {
+        match framing {
+            "lines" => Ok(Framing::Lines),
+            "length" => Ok(Framing::LengthPrefixed),
+            _ => Err("Expected either 'lines' or 'length'".to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+    TcpListen,
+    /// Every datagram is a message, answered to the addresses that sent the latest ones.
+    UdpListen,
+}
+
+/// A `--bridge` between a topic and a local socket, e.g.
+/// `tcp-listen:127.0.0.1:9000:topic=test-net:framing=length`. The framing defaults to lines.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BridgeSpec {
+    pub transport: Transport,
+    pub addr: SocketAddr,
+    pub topic: String,
+    pub framing: Framing,
+}
+
+impl BridgeSpec {
+    /// The bridge without its options, as shown in logs.
+    pub fn name(&self) -> String {
+        match self.transport {
+            Transport::TcpListen => format!("tcp-listen:{}", self.addr),
+            Transport::UdpListen => format!("udp-listen:{}", self.addr),
+        }
+    }
+}
+
+impl FromStr for BridgeSpec {
+    type Err = String;
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let usage = || {
+            format!(
+                "invalid bridge '{spec}', expected e.g. tcp-listen:127.0.0.1:9000:topic=test-net"
+            )
+        };
+        let (transport, rest) = spec.split_once(':').ok_or_else(usage)?;
+        let transport = match transport {
+            "tcp-listen" => Transport::TcpListen,
+            "udp-listen" => Transport::UdpListen,
+            _ => {
+                return Err(format!(
+                    "{}, the kinds are tcp-listen and udp-listen",
+                    usage()
+                ))
+            }
+        };
+        // Options are the parts with a `=`, the address is everything before them.
+        let parts = rest.split(':').collect::<Vec<_>>();
+        let split = parts
+            .iter()
+            .position(|part| part.contains('='))
+            .unwrap_or(parts.len());
+        let addr = parts[..split]
+            .join(":")
+            .parse::<SocketAddr>()
+            .map_err(|e| format!("{}: {e}", usage()))?;
+        let mut topic = None;
+        let mut framing = Framing::Lines;
+        for option in &parts[split..] {
+            match option.split_once('=') {
+                Some(("topic", name)) if !name.is_empty() => topic = Some(name.to_string()),
+                Some(("framing", value)) => framing = value.parse()?,
+                _ => return Err(format!("{}, unknown option '{option}'", usage())),
+            }
+        }
+        Ok(BridgeSpec {
+            transport,
+            addr,
+            topic: topic.ok_or_else(|| format!("{}, the topic is missing", usage()))?,
+            framing,
+        })
+    }
+}
+
+/// A message a bridge received from its socket, to publish to its topic.
+pub struct BridgeLine {
+    pub bridge: usize,
+    pub text: String,
+}
+
+#[derive(Default)]
+struct Counters {
+    received: AtomicU64,
+    sent: AtomicU64,
+    dropped: AtomicU64,
+}
+
+struct Client {
+    addr: SocketAddr,
+    frames: SyncSender<Vec<u8>>,
+}
+
+/// Where a bridge writes the topic's messages to.
+enum Peers {
+    Tcp(Arc<Mutex<Vec<Client>>>),
+    Udp(UdpSocket, Arc<Mutex<Vec<SocketAddr>>>),
+}
+
+struct Bridge {
+    spec: BridgeSpec,
+    /// Put into the envelopes of the messages entering through the bridge.
+    marker: String,
+    peers: Peers,
+    counters: Arc<Counters>,
+}
+
+/// The `--bridge`s, each serving its socket on threads of its own. What clients send comes out
+/// of the channel given to [`Bridges::start`], the topic's messages go in through
+/// [`Bridges::on_message`]. Clients reading too slowly lose messages instead of holding up the
+/// swarm, counted as dropped.
+#[derive(Default)]
+pub struct Bridges {
+    bridges: Vec<Bridge>,
+}
+
+impl Bridges {
+    /// Binds every bridge's socket, failing if any can't be bound.
+    pub fn start(
+        specs: &[BridgeSpec],
+        local_peer_id: &PeerId,
+        lines: UnboundedSender<BridgeLine>,
+    ) -> Result<Self, String> {
+        let mut bridges = Vec::new();
+        for (index, spec) in specs.iter().enumerate() {
+            let counters = Arc::new(Counters::default());
+            let bind_error = |e: io::Error| format!("failed to bind --bridge {}: {e}", spec.name());
+            let peers = match spec.transport {
+                Transport::TcpListen => {
+                    let listener = TcpListener::bind(spec.addr).map_err(bind_error)?;
+                    let clients = Arc::new(Mutex::new(Vec::new()));
+                    let accepting = Accepting {
+                        bridge: index,
+                        framing: spec.framing,
+                        clients: clients.clone(),
+                        counters: counters.clone(),
+                        lines: lines.clone(),
+                    };
+                    thread::spawn(move || accepting.run(listener));
+                    Peers::Tcp(clients)
+                }
+                Transport::UdpListen => {
+                    let socket = UdpSocket::bind(spec.addr).map_err(bind_error)?;
+                    let receiving = socket.try_clone().map_err(bind_error)?;
+                    let senders = Arc::new(Mutex::new(Vec::new()));
+                    let (known, counters, lines) =
+                        (senders.clone(), counters.clone(), lines.clone());
+                    thread::spawn(move || receive_udp(index, receiving, &known, &counters, &lines));
+                    Peers::Udp(socket, senders)
+                }
+            };
+            info!("Bridging topic '{}' to {}", spec.topic, spec.name());
+            bridges.push(Bridge {
+                spec: spec.clone(),
+                marker: format!("{local_peer_id}/{}", spec.name()),
+                peers,
+                counters,
+            });
+        }
+        Ok(Bridges { bridges })
+    }
+
+    pub fn topic(&self, bridge: usize) -> &str {
+        &self.bridges[bridge].spec.topic
+    }
+
+    pub fn marker(&self, bridge: usize) -> &str {
+        &self.bridges[bridge].marker
+    }
+
+    pub fn wants(&self, topic: &str) -> bool {
+        self.bridges.iter().any(|bridge| bridge.spec.topic == topic)
+    }
+
+    /// Writes a message of `topic` to the clients of its bridges, except to the bridge `marker`
+    /// says it entered through.
+    pub fn on_message(&self, topic: &str, body: &str, marker: Option<&str>) {
+        for bridge in &self.bridges {
+            if bridge.spec.topic != topic || marker == Some(bridge.marker.as_str()) {
+                continue;
+            }
+            let counters = &bridge.counters;
+            match &bridge.peers {
+                Peers::Tcp(clients) => {
+                    let frame = frame(bridge.spec.framing, body);
+                    let mut clients = clients.lock().expect("bridge clients lock");
+                    clients.retain(|client| match client.frames.try_send(frame.clone()) {
+                        Ok(()) => {
+                            counters.sent.fetch_add(1, Ordering::Relaxed);
+                            true
+                        }
+                        Err(TrySendError::Full(_)) => {
+                            counters.dropped.fetch_add(1, Ordering::Relaxed);
+                            true
+                        }
+                        // Its writer is gone along with the connection.
+                        Err(TrySendError::Disconnected(_)) => false,
+                    });
+                }
+                Peers::Udp(socket, senders) => {
+                    for addr in senders.lock().expect("bridge senders lock").iter() {
+                        let counter = match socket.send_to(body.as_bytes(), addr) {
+                            Ok(_) => &counters.sent,
+                            Err(_) => &counters.dropped,
+                        };
+                        counter.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+    }
+
+    /// One line per bridge for `/stats`.
+    pub fn lines(&self) -> Vec<String> {
+        self.bridges
+            .iter()
+            .map(|bridge| {
+                let connected = match &bridge.peers {
+                    Peers::Tcp(clients) => clients.lock().expect("bridge clients lock").len(),
+                    Peers::Udp(_, senders) => senders.lock().expect("bridge senders lock").len(),
+                };
+                let counters = &bridge.counters;
+                format!(
+                    "bridge {} <-> '{}': {connected} clients, {} in, {} out, {} dropped",
+                    bridge.spec.name(),
+                    bridge.spec.topic,
+                    counters.received.load(Ordering::Relaxed),
+                    counters.sent.load(Ordering::Relaxed),
+                    counters.dropped.load(Ordering::Relaxed),
+                )
+            })
+            .collect()
+    }
+}
+
+fn frame(framing: Framing, body: &str) -> Vec<u8> {
+    match framing {
+        Framing::Lines => format!("{}\n", body.replace('\n', " ")).into_bytes(),
+        Framing::LengthPrefixed => {
+            let mut frame = (body.len() as u32).to_be_bytes().to_vec();
+            frame.extend_from_slice(body.as_bytes());
+            frame
+        }
+    }
+}
+
+/// Accepts the clients of a TCP bridge, each read and written on threads of its own.
+struct Accepting {
+    bridge: usize,
+    framing: Framing,
+    clients: Arc<Mutex<Vec<Client>>>,
+    counters: Arc<Counters>,
+    lines: UnboundedSender<BridgeLine>,
+}
+
+impl Accepting {
+    fn run(self, listener: TcpListener) {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Failed to accept a bridge client: {e}");
+                    continue;
+                }
+            };
+            let (addr, writer) = match (stream.peer_addr(), stream.try_clone()) {
+                (Ok(addr), Ok(writer)) => (addr, writer),
+                (Err(e), _) | (_, Err(e)) => {
+                    warn!("Failed to set up a bridge client: {e}");
+                    continue;
+                }
+            };
+            info!("Bridge client {addr} connected");
+            let (frames, queued) = mpsc::sync_channel::<Vec<u8>>(CLIENT_QUEUE);
+            thread::spawn(move || {
+                let mut writer = writer;
+                for frame in queued {
+                    if writer.write_all(&frame).is_err() {
+                        break;
+                    }
+                }
+            });
+            self.clients
+                .lock()
+                .expect("bridge clients lock")
+                .push(Client { addr, frames });
+            let (bridge, framing) = (self.bridge, self.framing);
+            let (clients, counters, lines) = (
+                self.clients.clone(),
+                self.counters.clone(),
+                self.lines.clone(),
+            );
+            thread::spawn(move || {
+                if let Err(e) = read_client(bridge, framing, stream, &counters, &lines) {
+                    warn!("Bridge client {addr} failed: {e}");
+                }
+                info!("Bridge client {addr} disconnected");
+                // Dropping its queue ends the writer.
+                clients
+                    .lock()
+                    .expect("bridge clients lock")
+                    .retain(|client| client.addr != addr);
+            });
+        }
+    }
+}
+
+/// Reads a client's messages until it disconnects.
+fn read_client(
+    bridge: usize,
+    framing: Framing,
+    stream: TcpStream,
+    counters: &Counters,
+    lines: &UnboundedSender<BridgeLine>,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    loop {
+        let text = match framing {
+            Framing::Lines => {
+                let mut line = String::new();
+                if reader.read_line(&mut line)? == 0 {
+                    return Ok(());
+                }
+                line.trim_end_matches(['\r', '\n']).to_string()
+            }
+            Framing::LengthPrefixed => {
+                let mut length = [0; 4];
+                match reader.read_exact(&mut length) {
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                    result => result?,
+                }
+                let length = u32::from_be_bytes(length) as usize;
+                if length > MAX_FRAME {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("message of {length} bytes is longer than {MAX_FRAME}"),
+                    ));
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body)?;
+                String::from_utf8(body)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+            }
+        };
+        if text.is_empty() {
+            continue;
+        }
+        counters.received.fetch_add(1, Ordering::Relaxed);
+        if lines.unbounded_send(BridgeLine { bridge, text }).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+fn receive_udp(
+    bridge: usize,
+    socket: UdpSocket,
+    senders: &Mutex<Vec<SocketAddr>>,
+    counters: &Counters,
+    lines: &UnboundedSender<BridgeLine>,
+) {
+    let mut buffer = vec![0; 65536];
+    loop {
+        let (length, addr) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("Bridge failed to receive a datagram: {e}");
+                continue;
+            }
+        };
+        {
+            let mut senders = senders.lock().expect("bridge senders lock");
+            senders.retain(|known| *known != addr);
+            if senders.len() >= MAX_UDP_PEERS {
+                senders.remove(0);
+            }
+            senders.push(addr);
+        }
+        let text = String::from_utf8_lossy(&buffer[..length])
+            .trim_end_matches(['\r', '\n'])
+            .to_string();
+        if text.is_empty() {
+            continue;
+        }
+        counters.received.fetch_add(1, Ordering::Relaxed);
+        if lines.unbounded_send(BridgeLine { bridge, text }).is_err() {
+            return;
+        }
+    }
+}
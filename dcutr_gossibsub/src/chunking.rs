@@ -0,0 +1,208 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Room left in a gossipsub frame for the signature, public key, sequence number and protobuf
+/// framing around the payload and topic.
+const PUBLISH_OVERHEAD: usize = 256;
+
+/// Room taken by the chunk's JSON fields around the base64 data.
+const CHUNK_OVERHEAD: usize = 128;
+
+/// Upper bound on the chunks of a single message, so a peer can't make us allocate at will.
+const MAX_CHUNKS: u32 = 4096;
+
+/// One piece of a payload that was too large to publish in one message.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Chunk {
+    pub msg_id: String,
+    pub chunk_index: u32,
+    pub chunk_count: u32,
+    /// Base64 of this chunk's bytes.
+    pub data: String,
+}
+
+impl Chunk {
+    pub fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("chunk serializes to JSON")
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Chunk> {
+        serde_json::from_slice(data).ok()
+    }
+}
+
+/// Largest payload that fits into a gossipsub message of `max_message_size` on `topic`.
+pub fn max_payload(max_message_size: usize, topic: &str) -> usize {
+    max_message_size.saturating_sub(PUBLISH_OVERHEAD + topic.len())
+}
+
+/// Checks that a gossipsub message of `max_message_size` has room for a chunk of at least one
+/// byte on each of `topics`, which also keeps it above gossipsub's own minimum.
+pub fn validate_max_message_size<'a>(
+    max_message_size: usize,
+    topics: impl IntoIterator<Item = &'a str>,
+) -> Result<(), String> {
+    for topic in topics {
+        let min = PUBLISH_OVERHEAD + topic.len() + CHUNK_OVERHEAD + 4;
+        if max_message_size < min {
+            return Err(format!(
+                "max message size must be at least {min} bytes to fit a chunk on topic {topic}, got {max_message_size}"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Returns `payload` unchanged if it fits into `max_payload` bytes, otherwise encoded chunks
+/// that each fit. Fails if `max_payload` leaves no room for chunk data.
+pub fn split(payload: Vec<u8>, max_payload: usize) -> Result<Vec<Vec<u8>>, String> {
+    if payload.len() <= max_payload {
+        return Ok(vec![payload]);
+    }
+    let chunk_size = max_payload.saturating_sub(CHUNK_OVERHEAD) / 4 * 3;
+    if chunk_size == 0 {
+        return Err(format!(
+            "max message size leaves no room for chunks of a {} byte message",
+            payload.len()
+        ));
+    }
+    let chunk_count = (payload.len() + chunk_size - 1) / chunk_size;
+    if chunk_count > MAX_CHUNKS as usize {
+        return Err(format!(
+            "message of {} bytes would need {chunk_count} chunks, at most {MAX_CHUNKS} are allowed",
+            payload.len()
+        ));
+    }
+    let msg_id = STANDARD.encode(&Sha256::digest(&payload)[..16]);
+    Ok(payload
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(i, data)| {
+            Chunk {
+                msg_id: msg_id.clone(),
+                chunk_index: i as u32,
+                chunk_count: chunk_count as u32,
+                data: STANDARD.encode(data),
+            }
+            .encode()
+        })
+        .collect())
+}
+
+struct Partial {
+    chunks: Vec<Option<Vec<u8>>>,
+    received: usize,
+    bytes: usize,
+    started: Instant,
+}
+
+/// Reassembles chunked payloads, in whatever order their chunks arrive.
+///
+/// What's held back is bounded: each source can have `max_per_source` messages in progress, and
+/// chunks beyond `max_bytes` buffered for all of them together are dropped until messages
+/// complete or expire.
+pub struct Reassembly {
+    timeout: Duration,
+    max_per_source: usize,
+    max_bytes: usize,
+    bytes: usize,
+    partials: HashMap<(Option<PeerId>, String), Partial>,
+}
+
+impl Reassembly {
+    pub fn new(timeout: Duration, max_per_source: usize, max_bytes: usize) -> Self {
+        Reassembly {
+            timeout,
+            max_per_source,
+            max_bytes,
+            bytes: 0,
+            partials: HashMap::new(),
+        }
+    }
+
+    /// Bytes of the messages in progress.
+    pub fn buffered(&self) -> usize {
+        self.bytes
+    }
+
+    /// Adds a chunk from `source`, returning the whole payload once its last chunk arrived.
+    pub fn add(&mut self, source: Option<PeerId>, chunk: Chunk) -> Result<Option<Vec<u8>>, String> {
+        if chunk.chunk_count == 0
+            || chunk.chunk_count > MAX_CHUNKS
+            || chunk.chunk_index >= chunk.chunk_count
+        {
+            return Err(format!(
+                "invalid chunk {}/{} of message {}",
+                chunk.chunk_index, chunk.chunk_count, chunk.msg_id
+            ));
+        }
+        let data = STANDARD
+            .decode(&chunk.data)
+            .map_err(|e| format!("invalid chunk data of message {}: {e}", chunk.msg_id))?;
+
+        let key = (source, chunk.msg_id);
+        if !self.partials.contains_key(&key) {
+            let in_progress = self.partials.keys().filter(|(s, _)| *s == source).count();
+            if in_progress >= self.max_per_source {
+                return Err(format!(
+                    "{in_progress} messages of the source are already being reassembled, dropping message {}",
+                    key.1
+                ));
+            }
+        }
+        if self.bytes + data.len() > self.max_bytes {
+            return Err(format!(
+                "{} bytes of chunks are already buffered, dropping a chunk of message {}",
+                self.bytes, key.1
+            ));
+        }
+        let partial = self.partials.entry(key.clone()).or_insert_with(|| Partial {
+            chunks: vec![None; chunk.chunk_count as usize],
+            received: 0,
+            bytes: 0,
+            started: Instant::now(),
+        });
+        if partial.chunks.len() != chunk.chunk_count as usize {
+            let partial = self.partials.remove(&key).expect("present");
+            self.bytes -= partial.bytes;
+            return Err(format!("chunk count of message {} changed", key.1));
+        }
+        let slot = &mut partial.chunks[chunk.chunk_index as usize];
+        if slot.is_none() {
+            partial.bytes += data.len();
+            self.bytes += data.len();
+            *slot = Some(data);
+            partial.received += 1;
+        }
+        if partial.received < partial.chunks.len() {
+            return Ok(None);
+        }
+        let partial = self.partials.remove(&key).expect("present");
+        self.bytes -= partial.bytes;
+        Ok(Some(
+            partial.chunks.into_iter().flatten().flatten().collect(),
+        ))
+    }
+
+    /// Discards messages whose chunks didn't all arrive in time, returning their ids and how
+    /// many chunks were missing.
+    pub fn expire(&mut self, now: Instant) -> Vec<(String, usize)> {
+        let timeout = self.timeout;
+        let mut expired = Vec::new();
+        let mut freed = 0;
+        self.partials.retain(|(_, msg_id), partial| {
+            if now.duration_since(partial.started) < timeout {
+                return true;
+            }
+            expired.push((msg_id.clone(), partial.chunks.len() - partial.received));
+            freed += partial.bytes;
+            false
+        });
+        self.bytes -= freed;
+        expired
+    }
+}
@@ -0,0 +1,4345 @@
+//! The command line client: the options of the `dcutr` binary and the event loop that drives a
+//! [`Node`] with them, connecting the console, the control socket, the HTTP API and the other
+//! front ends to the swarm.
+
+use crate::address_book::AddressBook;
+use crate::allow_list::{self, AllowList, DenialLog};
+use crate::bandwidth::Bandwidth;
+use crate::bans::BanList;
+use crate::behaviour::{self, Behaviour, BehaviourConfig, BehaviourEvent};
+use crate::bench::{self, Bench, BenchSpec};
+use crate::bootstrap::{Bootstrap, Progress, Step, TimedOut};
+use crate::bootstrap_peers::{self, BootstrapPeers};
+use crate::bridge::{BridgeSpec, Bridges};
+use crate::chunking::{self, Reassembly};
+use crate::commands::{self, Command};
+use crate::config::Config;
+use crate::console::{self, say};
+use crate::control::{ControlRequest, ControlSocket, Response};
+use crate::dm::{DirectMessage, DmAck, DmFailure, PendingDms, SeenDms};
+use crate::e2e::{E2eKey, ReplayGuard};
+use crate::envelope::{self, Envelope, Kind, KindTtl, Sequencer, WireFormat};
+use crate::events::{EventLog, NodeEvent};
+use crate::explicit::ExplicitPeers;
+use crate::external::{self, ExternalAddresses};
+use crate::fallback::{self, FallbackChannel, FallbackMessage};
+use crate::feed::Feed;
+use crate::forward::{ForwardSpec, Forwarder};
+use crate::health::{self, Check, Health};
+use crate::history::{History, HistoryRequest, HistoryResponse};
+use crate::holepunch::{self, CircuitLimit, HolePunchStats, HolePunchTracker, RelayedConnections};
+use crate::hook::{Hook, HookMessage};
+use crate::http_api;
+use crate::identity;
+use crate::idle::{IdleConnections, KeepAlive};
+use crate::input::{self, Backlog};
+use crate::journal::Journal;
+use crate::latency::{Echo, EchoCodec, LatencyProbe};
+use crate::limits::Limits;
+use crate::logging::{self, LogFormat, Phase};
+use crate::lookup::{self, LookupStep, RemoteLookup};
+use crate::mesh::{self, MeshParams, PublishParams};
+use crate::message_id::MessageIdScheme;
+use crate::message_log::{LogRecord, MessageLog};
+use crate::metrics::{self, Metrics};
+use crate::mqtt::{Mapping, Mqtt};
+use crate::node::{self, Node};
+use crate::once::{OneShot, Outcome};
+use crate::outbox::{Outbox, Sent};
+use crate::output::{JsonMessage, Output};
+use crate::paths::{ConnectionPaths, TransportPath};
+use crate::peer_info::PeerInfos;
+use crate::pipeline::{Candidate, Pipeline, Validated};
+use crate::presence::{self, Roster};
+use crate::psk;
+use crate::qr;
+use crate::relay_addr;
+use crate::relay_session::RelaySession;
+use crate::remotes::{self, Remotes};
+use crate::reputation::{self, Reputation, ReputationConfig};
+use crate::resend::{Queued, ResendBuffer};
+use crate::resolver::{self, DnsProtocol, DnsSettings};
+use crate::rtt::{PathComparisons, PingEvent, RttStats};
+use crate::scoring::{ScoreConfig, ScoreWatch};
+use crate::seen::SeenMessages;
+use crate::sequences::Sequences;
+use crate::shutdown;
+use crate::signing;
+use crate::soak::Soak;
+use crate::subscribers::Subscribers;
+use crate::throttle::TokenBucket;
+use crate::ticket::Ticket;
+use crate::topic_keys::TopicKeys;
+use crate::topics::{TopicHashing, Topics};
+use crate::traffic::Traffic;
+use crate::transfer::{FileRequest, FileResponse, TransferOutput, TransferWorker, Transfers};
+use crate::transport;
+use crate::tui::Tui;
+use crate::upnp::{UpnpEvent, UpnpHandle};
+use crate::validation::{BodySize, Freshness, PeerRateLimiter, Rate, Reassembled, ValidationStats};
+use clap::Parser;
+use futures::{
+    channel::{mpsc, oneshot},
+    future::FutureExt,
+    stream::StreamExt,
+};
+use libp2p::{
+    autonat::{self, NatStatus},
+    core::{
+        multiaddr::{Multiaddr, Protocol},
+        ConnectedPoint,
+    },
+    gossipsub::{self, MessageId, TopicHash},
+    identify,
+    identity::Keypair,
+    kad, ping,
+    pnet::PreSharedKey,
+    relay, request_response,
+    swarm::{dial_opts::DialOpts, AddressScore, ConnectionId, DialError, ListenError, SwarmEvent},
+    PeerId,
+};
+use prometheus_client::registry::Registry;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::io::{self, IsTerminal};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::num::NonZeroU32;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// Chats over gossipsub with peers behind NATs, through a relay circuit that DCUtR upgrades to a
+/// direct connection.
+#[derive(Debug, Parser)]
+#[clap(name = "libp2p DCUtR client")]
+pub struct Opts {
+    /// TOML file with settings named like the flags, e.g. `relay_address = "/ip4/..."` or
+    /// `topics = ["a", "b"]`. Flags given on the command line take precedence over it.
+    #[clap(long)]
+    config: Option<PathBuf>,
+
+    /// Print the effective configuration, the command line merged over the config file, as TOML
+    /// and exit.
+    #[clap(long)]
+    print_config: bool,
+
+    /// The mode (client-listen, client-dial).
+    #[clap(long)]
+    mode: Mode,
+
+    /// Fixed value to generate deterministic peer id.
+    #[clap(long)]
+    secret_key_seed: u8,
+
+    /// The relay's address, e.g. `/ip4/198.51.100.1/tcp/4001/p2p/<peer-id>`. Without the `/p2p`
+    /// part the relay is dialed once to learn its peer id.
+    #[clap(long, required_unless_present = "ticket")]
+    relay_address: Option<Multiaddr>,
+
+    /// Ticket a listening client printed, in place of --relay-address, --remote-peer-id and
+    /// --topic. Dial mode only.
+    #[clap(long, conflicts_with_all = ["relay_address", "remote_peer_id"])]
+    ticket: Option<Ticket>,
+
+    /// Show the ticket of listen mode as a QR code as well, when stdout is a terminal and the
+    /// output is text.
+    #[clap(long)]
+    qr: bool,
+
+    /// Peer ID of a remote peer to hole punch to. Can be repeated.
+    #[clap(long)]
+    remote_peer_id: Vec<PeerId>,
+
+    /// Direct address of a remote peer, dialed before going through the relay. Ends with
+    /// `/p2p/<peer-id>` of a --remote-peer-id unless there's only one. Can be repeated.
+    #[clap(long)]
+    remote_address: Vec<Multiaddr>,
+
+    /// File with one remote peer ID per line, dialed along with those of --remote-peer-id.
+    #[clap(long)]
+    peers_file: Option<PathBuf>,
+
+    /// Find the remote peer through a Kademlia DHT lookup instead of assuming it shares our relay.
+    /// The relay server is a DHT node to start from, --bootstrap adds more.
+    #[clap(long)]
+    kademlia: bool,
+
+    /// How many times a failed DHT lookup for the remote peer is retried.
+    #[clap(long, default_value = "3")]
+    kad_lookup_retries: u32,
+
+    /// Bootstrap peer to dial at startup, must contain a `/p2p/<peer-id>` component. Can be repeated.
+    #[clap(long)]
+    bootstrap: Vec<Multiaddr>,
+
+    /// File with one bootstrap peer multiaddr per line.
+    #[clap(long)]
+    bootstrap_file: Option<PathBuf>,
+
+    /// Dial remote peers through the relay right away instead of first trying the addresses the
+    /// address book has for them.
+    #[clap(long)]
+    relay_first: bool,
+
+    /// Seconds the known direct addresses of a remote peer get to answer before it is dialed
+    /// through the relay.
+    #[clap(long, default_value = "5")]
+    direct_dial_timeout: u64,
+
+    /// Seconds between pings on each connection.
+    #[clap(long, default_value = "15")]
+    ping_interval: u64,
+
+    /// Seconds a ping may take before it counts as failed.
+    #[clap(long, default_value = "20")]
+    ping_timeout: u64,
+
+    /// Failed pings in a row after which the connection is closed and redialed. For the remote
+    /// peer that means another hole punch, for the relay another reservation.
+    #[clap(long, default_value = "3")]
+    ping_max_failures: NonZeroU32,
+
+    /// Keep connections whose pings fail open, for debugging lossy links.
+    #[clap(long)]
+    no_ping_disconnect: bool,
+
+    /// The protocol version sent with identify, `/<package>/<version>` by default. Peers with a
+    /// different one ignore our identify info.
+    #[clap(long)]
+    identify_protocol_version: Option<String>,
+
+    /// The agent version sent with identify, `<package>/<version>` by default.
+    #[clap(long)]
+    agent_version: Option<String>,
+
+    /// Appended to the agent version after a `/`, e.g. the name of the deployment.
+    #[clap(long)]
+    agent_suffix: Option<String>,
+
+    /// Seconds between identify requests on each connection. Changes of our listen addresses are
+    /// pushed to the connected peers right away either way.
+    #[clap(long, default_value = "300")]
+    identify_interval: u64,
+
+    /// Whether idle connections are held open (default, always). With `always` every connection
+    /// stays open, combine it with --idle-timeout to only hold explicit peers and the relay.
+    #[clap(long, default_value = "default")]
+    keep_alive: KeepAlive,
+
+    /// Seconds after which connections without messages or requests are closed, except those to
+    /// explicit peers and the relay. Pings don't count as activity: they keep NAT mappings
+    /// alive, not the connection.
+    #[clap(long)]
+    idle_timeout: Option<u64>,
+
+    /// Always go through the relay, even when AutoNAT reports that we are publicly reachable.
+    #[clap(long)]
+    force_relay: bool,
+
+    /// Seconds an AutoNAT verdict still counts once bootstrap went on through the relay without
+    /// one. Found publicly reachable by then, we advertise the address to peers.
+    #[clap(long, default_value = "10")]
+    autonat_wait: u64,
+
+    /// Seconds each bootstrap phase may take: listening, the identify exchange with the relay,
+    /// the reservation in listen mode and the circuit in dial mode. A phase running out of time
+    /// exits with a code of its own, 10 to 13 in that order.
+    #[clap(long, default_value = "30")]
+    bootstrap_timeout: u64,
+
+    /// Ask the router to forward our TCP port via UPnP so peers can reach us without hole punching.
+    #[clap(long)]
+    enable_upnp: bool,
+
+    /// Publicly reachable address to advertise to peers, e.g. a static port forward. Can be repeated.
+    #[clap(long)]
+    external_address: Vec<Multiaddr>,
+
+    /// How many times a failed hole punch is retried by redialing the relayed address.
+    #[clap(long, default_value = "3")]
+    holepunch_retries: u32,
+
+    /// Dial mode only: attempt a single hole punch, print the result and exit with 0 on success,
+    /// 2 if hole punching failed and 3 if the relay could not be used.
+    #[clap(long)]
+    once: bool,
+
+    /// Show a terminal UI with panes for messages, connected peers and logs instead of plain
+    /// output.
+    #[clap(long)]
+    tui: bool,
+
+    /// Read the input from this file instead of stdin, slash commands included. A regular file is
+    /// published line by line and the client exits after the last one, a named pipe is kept open
+    /// and read whenever something writes to it.
+    #[clap(long, conflicts_with = "tui")]
+    input: Option<PathBuf>,
+
+    /// Milliseconds to wait between the lines of an `--input` file.
+    #[clap(long, default_value = "0", requires = "input")]
+    input_delay_ms: u64,
+
+    /// Keep running after the last line of an `--input` file instead of exiting.
+    #[clap(long, requires = "input")]
+    stay: bool,
+
+    /// Run this shell command for each received message, with the message's body on its stdin
+    /// and its topic, author, id and path in `P2P_TOPIC`, `P2P_FROM`, `P2P_MSG_ID` and `P2P_PATH`.
+    #[clap(long)]
+    on_message: Option<String>,
+
+    /// Only run `--on-message` for messages on this topic, may be given more than once.
+    #[clap(long, requires = "on_message")]
+    hook_filter: Vec<String>,
+
+    /// How many `--on-message` commands may run at once.
+    #[clap(long, default_value = "4")]
+    hook_concurrency: usize,
+
+    /// Seconds after which an `--on-message` command is killed.
+    #[clap(long, default_value = "10")]
+    hook_timeout: u64,
+
+    /// Append the node's lifecycle events, e.g. connections, relay reservations and hole punches,
+    /// to this file as JSON lines. `-` writes them to stdout.
+    #[clap(long)]
+    event_file: Option<PathBuf>,
+
+    /// Append connection, relay reservation, hole punch, identify and publish error events to
+    /// this file in a compact line format, for debugging after the fact.
+    #[clap(long)]
+    event_log: Option<PathBuf>,
+
+    /// Rotate the `--event-log` once it grows past this many bytes.
+    #[clap(long, default_value = "10485760")]
+    event_log_max_size: u64,
+
+    /// How many rotated `--event-log` files to keep.
+    #[clap(long, default_value = "5")]
+    event_log_keep: usize,
+
+    /// Listen on this Unix socket for newline delimited JSON requests of local processes, e.g.
+    /// `{"cmd":"publish","topic":"chat","body":"hi"}`, `{"cmd":"peers"}` or
+    /// `{"cmd":"dial","addr":"/ip4/..."}`.
+    #[clap(long)]
+    control_socket: Option<PathBuf>,
+
+    /// Serve an HTTP API on this address: `POST /publish`, `POST /dial`, `GET /peers`,
+    /// `GET /status` and `GET /messages?since=<id>&wait=<seconds>`.
+    #[clap(long)]
+    http_api: Option<SocketAddr>,
+
+    /// Allow `--http-api` on an address other than loopback.
+    #[clap(long)]
+    http_api_public: bool,
+
+    /// Bearer token HTTP API requests have to carry in their `Authorization` header.
+    #[clap(long)]
+    http_token: Option<String>,
+
+    /// Serve Prometheus metrics of the swarm, its protocols and the chat on `/metrics` at this
+    /// address.
+    #[clap(long)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Serve the `/healthz` and `/readyz` probes at this address. They are also part of the
+    /// `--http-api`.
+    #[clap(long)]
+    health_addr: Option<SocketAddr>,
+
+    /// Seconds without an event loop tick after which `/healthz` reports the node as stuck.
+    #[clap(long, default_value = "10")]
+    health_stale_after: u64,
+
+    /// Measure gossipsub throughput: `publish:<count>x<size>` in dial mode publishes `count`
+    /// messages of `size` random bytes to the remote once the connection settled, `receive` in
+    /// listen mode counts them. Both sides print the results and exit.
+    #[clap(long)]
+    bench: Option<BenchSpec>,
+
+    /// Percentage of lost `--bench` messages above which the run exits with a failure.
+    #[clap(long, default_value = "0")]
+    bench_max_loss: f64,
+
+    /// Append the `--bench` results to this file as a JSON line, for comparing runs.
+    #[clap(long)]
+    bench_results: Option<PathBuf>,
+
+    /// Measure round trips to the remote peer with this many echo requests, once over the first
+    /// connection and again after a hole punch, and print their percentiles and loss.
+    #[clap(long)]
+    latency_probe: Option<u32>,
+
+    /// Publish a numbered heartbeat every this many seconds and track those of the other peers,
+    /// reporting loss, gaps and reconnects every 10 minutes and on exit. The reports are also
+    /// appended to `soak-<peer id>.jsonl` in the data dir.
+    #[clap(long)]
+    soak: Option<u64>,
+
+    /// Heartbeats a `--soak` peer may miss before it is reconnected to.
+    #[clap(long, default_value = "3")]
+    soak_miss_threshold: u32,
+
+    /// Seconds after which a `--once` run gives up.
+    #[clap(long, requires = "once")]
+    timeout: Option<u64>,
+
+    /// Keep the relayed connection open after a direct connection has been established.
+    #[clap(long)]
+    keep_relayed: bool,
+
+    /// Gossipsub topic to subscribe to. Can be repeated, input goes to the first one unless a
+    /// line starts with `@<topic>`.
+    #[clap(long = "topic", default_value = "test-net")]
+    topics: Vec<String>,
+
+    /// Bridge a topic to a local socket, e.g. `tcp-listen:127.0.0.1:9000:topic=test-net`. What
+    /// clients send is published to the topic and the topic's messages are written to every
+    /// client, one per line or with `:framing=length` after a big endian u32 length.
+    /// `udp-listen:<addr>:topic=<topic>` takes a message per datagram and answers its senders.
+    /// The topic is subscribed to. Can be repeated.
+    #[clap(long)]
+    bridge: Vec<BridgeSpec>,
+
+    /// Republish the messages of one topic on another, `<from>=<to>`, or both ways with
+    /// `<a>~<b>`. Messages keep their author and signature, and aren't forwarded to a topic they
+    /// already passed through. Can be repeated.
+    #[clap(long)]
+    forward: Vec<ForwardSpec>,
+
+    /// MQTT broker to forward messages to and from, `host[:port]`. Needs the `mqtt` feature.
+    #[clap(long)]
+    mqtt_broker: Option<String>,
+
+    /// Forward the messages of an MQTT topic filter to a gossipsub topic,
+    /// `<mqtt-topic>=<gossip-topic>`. The filter may use `+` and `#`. Can be repeated.
+    #[clap(long, requires = "mqtt_broker")]
+    mqtt_sub: Vec<Mapping>,
+
+    /// Forward the messages of a gossipsub topic to an MQTT topic, `<gossip-topic>=<mqtt-topic>`.
+    /// Can be repeated.
+    #[clap(long, requires = "mqtt_broker")]
+    mqtt_pub: Vec<Mapping>,
+
+    /// QoS of the MQTT subscriptions and publishes, 0, 1 or 2.
+    #[clap(long, default_value = "1")]
+    mqtt_qos: u8,
+
+    /// Name shown to other peers next to our messages instead of our peer id.
+    #[clap(long)]
+    nick: Option<String>,
+
+    /// How our messages are encoded (json, cbor). Peers decode both, so this can differ
+    /// between them.
+    #[clap(long, default_value = "json")]
+    wire_format: WireFormat,
+
+    /// How received messages are printed (text, json). With json every message is a JSON object
+    /// on a line of its own on stdout and all other output goes to stderr.
+    #[clap(long, default_value = "text")]
+    output: Output,
+
+    /// How diagnostics are written to stderr (text, json). `RUST_LOG` filters them as usual.
+    #[clap(long, default_value = "text")]
+    log_format: LogFormat,
+
+    /// Only show errors among the diagnostics. Chat output is printed all the same.
+    #[clap(long)]
+    quiet: bool,
+
+    /// Bodies longer than this many bytes are zstd compressed before publishing.
+    #[clap(long, default_value = "1024")]
+    compress_threshold: usize,
+
+    /// Never compress what we publish. Compressed messages of others are still understood.
+    #[clap(long)]
+    no_compress: bool,
+
+    /// Messages whose body decompresses to more than this many bytes are rejected.
+    #[clap(long, default_value = "1048576")]
+    max_decompressed_size: usize,
+
+    /// Seconds after which a message is considered stale and rejected instead of propagated.
+    #[clap(long, default_value = "300")]
+    max_message_age: u64,
+
+    /// Seconds the clocks of sender and receiver may differ by, in either direction.
+    #[clap(long, default_value = "30")]
+    clock_skew: u64,
+
+    /// Seconds a check may take to validate a received message, the message is ignored after
+    /// that.
+    #[clap(long, default_value = "5")]
+    validation_timeout: u64,
+
+    /// Let the messages of a kind expire, `<kind>=<seconds>` with the kinds chat, presence and
+    /// control. Receivers drop them after that, and they are neither served as history nor
+    /// replayed. Can be repeated.
+    #[clap(long)]
+    ttl: Vec<KindTtl>,
+
+    /// Most established incoming connections, the relay and explicit peers don't count. Circuits
+    /// beyond it are turned away before the upgrade.
+    #[clap(long)]
+    max_incoming: Option<usize>,
+
+    /// Most established outgoing connections, the relay and explicit peers don't count.
+    #[clap(long)]
+    max_outgoing: Option<usize>,
+
+    /// Most incoming connections still being upgraded. A direct one could be from the relay or
+    /// an explicit peer, which only the handshake tells, so there's a slot more for each of them.
+    #[clap(long)]
+    max_pending_incoming: Option<usize>,
+
+    /// Most incoming connections through a relay circuit. Direct connections, the relay and
+    /// explicit peers don't count.
+    #[clap(long, default_value = "8")]
+    max_relayed_inbound: usize,
+
+    /// Most connections with a single peer. Hole punching needs two, the relayed and the direct
+    /// one.
+    #[clap(long)]
+    max_connections_per_peer: Option<usize>,
+
+    /// Messages a single peer may forward to us, e.g. `10/s`. Beyond it messages are rejected
+    /// and not propagated. Unlimited if not set.
+    #[clap(long)]
+    max_msgs_per_peer: Option<Rate>,
+
+    /// How many messages a peer may send in a burst before `--max-msgs-per-peer` applies.
+    #[clap(long, default_value = "20")]
+    max_msgs_burst: u32,
+
+    /// File the runtime ban list (`/ban`, `/unban`) is kept in and loaded from at startup.
+    #[clap(long)]
+    ban_file: Option<PathBuf>,
+
+    /// Reputation at or below which a peer is banned for a while. Each rejected message costs its
+    /// sender points, see `/rep <peer-id>`.
+    #[clap(long, default_value = "-100.0", allow_hyphen_values = true)]
+    reputation_threshold: f64,
+
+    /// Reputation points a peer earns back per minute, up to zero.
+    #[clap(long, default_value = "1.0")]
+    reputation_recovery: f64,
+
+    /// Seconds the first reputation ban of a peer lasts, each further one twice as long.
+    #[clap(long, default_value = "60")]
+    reputation_ban: u64,
+
+    /// Most seconds a reputation ban lasts.
+    #[clap(long, default_value = "86400")]
+    reputation_max_ban: u64,
+
+    /// Keep peer reputations in the data dir across restarts instead of starting from scratch.
+    #[clap(long)]
+    persist_reputation: bool,
+
+    /// Only ever talk to this peer, plus the relay. Can be repeated.
+    #[clap(long)]
+    allow_peer: Vec<PeerId>,
+
+    /// File with one peer id per line to only ever talk to, plus the relay.
+    #[clap(long)]
+    allow_file: Option<PathBuf>,
+
+    /// Pre-shared key (`swarm.key`) of a private network. Peers and the relay without the same
+    /// key can't complete the transport handshake with us.
+    #[clap(long)]
+    psk_file: Option<PathBuf>,
+
+    /// DNS server to resolve host names with instead of the system's. Can be repeated.
+    #[clap(long)]
+    dns_server: Vec<IpAddr>,
+
+    /// How the --dns-server are queried: udp (default), tls or https.
+    #[clap(long, default_value = "udp")]
+    dns_protocol: DnsProtocol,
+
+    /// The name the certificates of the --dns-server are issued for, e.g. `cloudflare-dns.com`,
+    /// required with tls and https.
+    #[clap(long)]
+    dns_tls_name: Option<String>,
+
+    /// DNS server to fall back to when the system's DNS configuration can't be read, 1.1.1.1 and
+    /// 8.8.8.8 if none is given. Can be repeated.
+    #[clap(long)]
+    dns_fallback_server: Vec<IpAddr>,
+
+    /// Seconds a DNS query may take.
+    #[clap(long, default_value = "5")]
+    dns_timeout: u64,
+
+    /// Write a new random pre-shared key to `--psk-file` and use it. Copy the file to the other
+    /// peers and the relay of the private network.
+    #[clap(long, requires = "psk_file")]
+    generate_psk: bool,
+
+    /// Seconds a direct message may go unacknowledged before it is sent again.
+    #[clap(long, default_value = "10")]
+    dm_timeout: u64,
+
+    /// How many times an unacknowledged direct message is sent again.
+    #[clap(long, default_value = "3")]
+    dm_retries: u32,
+
+    /// How many recent messages per topic are kept to answer history requests of peers that
+    /// join later. 0 disables history.
+    #[clap(long, default_value = "100")]
+    history_size: usize,
+
+    /// Directory the message log is kept in.
+    #[clap(long, default_value = "chat-data")]
+    data_dir: PathBuf,
+
+    /// Don't keep a message log, messages are gone once the process exits.
+    #[clap(long)]
+    no_persist: bool,
+
+    /// Rotate the message log once it grows past this many bytes. The rotated file is replayed
+    /// too, older ones are deleted.
+    #[clap(long, default_value = "10485760")]
+    message_log_max_size: u64,
+
+    /// How many logged messages are shown again at startup.
+    #[clap(long, default_value = "20")]
+    replay: usize,
+
+    /// How many peers the address book in the data dir remembers addresses of, the least
+    /// recently seen are forgotten first.
+    #[clap(long, default_value = "1000")]
+    address_book_size: usize,
+
+    /// Hours after which addresses not seen again are dropped from the address book.
+    #[clap(long, default_value = "168")]
+    address_max_age: u64,
+
+    /// How many ids of received messages the data dir remembers, so messages still gossiped
+    /// after a restart aren't shown again.
+    #[clap(long, default_value = "10000")]
+    seen_cache_size: usize,
+
+    /// Seconds the ids of received messages are remembered across restarts.
+    #[clap(long, default_value = "600")]
+    seen_cache_age: u64,
+
+    /// Seconds a sender has to be silent before a lower sequence number from it is taken as a
+    /// restart rather than a late message.
+    #[clap(long, default_value = "300")]
+    seq_reset_after: u64,
+
+    /// Ask the sender for messages found missing in its sequence numbers via the history
+    /// protocol.
+    #[clap(long)]
+    fetch_missed: bool,
+
+    /// How many messages are kept per peer to send again once a dropped connection to it is
+    /// back, the oldest are dropped beyond that.
+    #[clap(long, default_value = "100")]
+    resend_buffer: usize,
+
+    /// Seconds after which messages waiting for a peer to come back are dropped.
+    #[clap(long, default_value = "600")]
+    resend_max_age: u64,
+
+    /// Seconds between our presence announcements. Peers missing three of them drop out of
+    /// `/who`.
+    #[clap(long, default_value = "30")]
+    presence_interval: u64,
+
+    /// Don't send messages directly to connected peers while gossipsub has no mesh on a topic.
+    #[clap(long)]
+    no_fallback_channel: bool,
+
+    /// Seconds connected to a peer without a gossipsub mesh on a topic before messages go to it
+    /// over a direct channel as well.
+    #[clap(long, default_value = "30")]
+    fallback_after: u64,
+
+    /// Mesh peers a topic needs for the direct channel to close, and to not open.
+    #[clap(long, default_value = "1")]
+    fallback_min_mesh: usize,
+
+    /// File of `<topic> <base64 key>` lines with 32 byte keys to encrypt topics with. A topic may
+    /// have several keys for rotation: all of them decrypt, the last one encrypts.
+    #[clap(long)]
+    topic_key_file: Option<PathBuf>,
+
+    /// Passphrase to derive topic keys from, for every topic. Repeat it to rotate: all of them
+    /// decrypt, the last one encrypts and takes precedence over the key file.
+    #[clap(long)]
+    topic_passphrase: Vec<String>,
+
+    /// Directory received files are written to.
+    #[clap(long, default_value = "downloads")]
+    download_dir: PathBuf,
+
+    /// Replace existing files in the download directory instead of refusing the transfer.
+    #[clap(long)]
+    overwrite: bool,
+
+    /// Largest file in bytes a peer may send us.
+    #[clap(long, default_value = "1073741824")]
+    max_file_size: u64,
+
+    /// How many files peers may send us at once, further offers are refused.
+    #[clap(long, default_value = "4")]
+    max_incoming_transfers: usize,
+
+    /// Seconds a file transfer may go without progress before it is given up. Partial
+    /// downloads are kept for a day to resume.
+    #[clap(long, default_value = "120")]
+    transfer_timeout: u64,
+
+    /// How topic names are hashed (identity, sha256). Peers using a different hashing for the
+    /// same name never see each other's messages.
+    #[clap(long, default_value = "identity")]
+    topic_hashing: TopicHashing,
+
+    /// Target number of peers in the gossipsub mesh (gossipsub default 6, 2 suits small networks).
+    #[clap(long)]
+    mesh_n: Option<usize>,
+
+    /// Mesh size below which more peers are grafted (gossipsub default 5, 1 suits small networks).
+    #[clap(long)]
+    mesh_n_low: Option<usize>,
+
+    /// Mesh size above which peers are pruned (gossipsub default 12, 4 suits small networks).
+    #[clap(long)]
+    mesh_n_high: Option<usize>,
+
+    /// Minimum number of outbound peers kept in the mesh (gossipsub default 2, 0 suits small
+    /// networks). Must not exceed mesh_n_low or half of mesh_n.
+    #[clap(long)]
+    mesh_outbound_min: Option<usize>,
+
+    /// Number of peers outside the mesh that gossip is emitted to (gossipsub default 6).
+    #[clap(long)]
+    gossip_lazy: Option<usize>,
+
+    /// How gossipsub message ids are derived (sha256, legacy). `legacy` is the 64 bit content hash
+    /// of older versions, use it when talking to peers that still run them.
+    #[clap(long, default_value = "sha256")]
+    message_id: MessageIdScheme,
+
+    /// Publish only to the mesh instead of every peer subscribed to the topic. Flooding, the
+    /// default, keeps the first messages of a small session from waiting for the mesh to be
+    /// grafted.
+    #[clap(long)]
+    no_flood_publish: bool,
+
+    /// Milliseconds before the first gossipsub heartbeat, at most the 10s heartbeat interval.
+    #[clap(long)]
+    heartbeat_initial_delay_ms: Option<u64>,
+
+    /// Accept messages signed by our own peer id, e.g. from a second instance using the same seed.
+    #[clap(long)]
+    allow_self_origin: bool,
+
+    /// How many messages are held back while no peer is subscribed to their topic yet, or for
+    /// `--publish-rate`. The oldest ones are dropped when it is full.
+    #[clap(long, default_value = "100")]
+    outbox_size: usize,
+
+    /// Most messages we publish, e.g. `5/s`, beyond which they wait in the outbox. Presence
+    /// announcements get a quarter of it on top. Unlimited if not set.
+    #[clap(long)]
+    publish_rate: Option<Rate>,
+
+    /// How many messages we may publish in a burst before `--publish-rate` applies.
+    #[clap(long, default_value = "10")]
+    publish_burst: u32,
+
+    /// Hold the input back after bootstrapping until this many peers subscribed to the topic
+    /// input goes to.
+    #[clap(long, default_value = "0")]
+    wait_for_subscribers: usize,
+
+    /// Largest gossipsub message in bytes. Longer input lines are split into chunks that the
+    /// receiving peers put back together.
+    #[clap(long, default_value = "65536")]
+    max_message_size: usize,
+
+    /// Seconds to wait for the missing chunks of a message before discarding it.
+    #[clap(long, default_value = "30")]
+    chunk_timeout: u64,
+
+    /// How many chunked messages of a single peer are reassembled at the same time.
+    #[clap(long, default_value = "8")]
+    max_partial_messages: usize,
+
+    /// Bytes of chunks buffered for reassembly, over all peers. Chunks beyond it are dropped.
+    #[clap(long, default_value = "16777216")]
+    max_reassembly_bytes: usize,
+
+    /// Score peers and stop talking to those that misbehave, e.g. by sending invalid messages.
+    #[clap(long)]
+    peer_scoring: bool,
+
+    /// Weight of each subscribed topic in a peer's score.
+    #[clap(long, default_value = "1.0")]
+    score_topic_weight: f64,
+
+    /// Score penalty weight per message that fails validation, must be negative.
+    #[clap(long, default_value = "-10.0", allow_hyphen_values = true)]
+    score_invalid_message_penalty: f64,
+
+    /// Score below which a peer is graylisted. Peers below half of it are no longer published
+    /// to and peers below an eighth of it no longer receive gossip.
+    #[clap(long, default_value = "-80.0", allow_hyphen_values = true)]
+    score_graylist_threshold: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Parser)]
+enum Mode {
+    Dial,
+    Listen,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "dial" => Ok(Mode::Dial),
+            "listen" => Ok(Mode::Listen),
+            _ => Err("Expected either 'dial' or 'listen'".to_string()),
+        }
+    }
+}
+
+/// Runs the client with the options of the command line, the environment and the config file
+/// until it is interrupted, `--once` or `--bench` finish or the bootstrap times out. The exit
+/// code tells which.
+pub async fn run(config: Config<Opts>) -> Result<ExitCode, Box<dyn Error>> {
+    if config.opts.print_config {
+        say!("{}", config.effective());
+        return Ok(ExitCode::SUCCESS);
+    }
+    let mut opts = config.opts;
+    if opts.tui && opts.output == Output::Json {
+        return Err("--tui can't be combined with --output json".into());
+    }
+    if opts.output == Output::Json {
+        // Keeps stdout for the messages.
+        console::set_sink(|line| {
+            eprintln!("{line}");
+            Ok(())
+        });
+    }
+
+    // Opened before anything else starts, so a wrong path fails right away.
+    let file_input = opts
+        .input
+        .as_deref()
+        .map(|path| input::from_path(path, Duration::from_millis(opts.input_delay_ms), opts.stay))
+        .transpose()?;
+    let (ui, ui_input) = match opts.tui {
+        true => {
+            let (ui, input) = Tui::start()?;
+            (Some(ui), Some(input))
+        }
+        false => (None, None),
+    };
+    let event_log = opts.event_file.as_deref().map(EventLog::open).transpose()?;
+    let journal = opts
+        .event_log
+        .as_deref()
+        .map(|path| Journal::open(path, opts.event_log_max_size, opts.event_log_keep))
+        .transpose()?;
+    logging::init(
+        opts.log_format,
+        opts.quiet,
+        ui.as_ref().map(|ui| ui.log_writer()),
+    )?;
+    if let Some(path) = &opts.config {
+        info!("Loaded configuration from {}", path.display());
+    }
+    for warning in &config.warnings {
+        warn!("{warning}");
+    }
+    if !config.from_env.is_empty() {
+        info!(
+            "Settings from the environment: {}",
+            config.from_env.join(", ")
+        );
+    }
+
+    let mut remote_peer_ids = opts.remote_peer_id.clone();
+    let mut relay_address = match (&opts.ticket, &opts.relay_address) {
+        (Some(_), _) if opts.mode == Mode::Listen => {
+            return Err("--ticket is only supported in dial mode".into())
+        }
+        (Some(ticket), _) => {
+            if ticket.relays.len() > 1 {
+                info!(
+                    "The ticket names {} relays, using the first",
+                    ticket.relays.len()
+                );
+            }
+            remote_peer_ids.push(ticket.peer_id);
+            if let Some(topic) = &ticket.topic {
+                opts.topics.retain(|t| t != topic);
+                opts.topics.insert(0, topic.clone());
+            }
+            ticket.relays[0].clone()
+        }
+        (None, Some(relay_address)) => relay_address.clone(),
+        (None, None) => return Err("--relay-address or --ticket is required".into()),
+    };
+    if let Some(path) = &opts.peers_file {
+        remote_peer_ids.extend(remotes::load_file(path)?);
+    }
+    let remote_addrs = match opts.mode {
+        Mode::Dial => remotes::pair_addresses(&opts.remote_address, &remote_peer_ids)?,
+        Mode::Listen if opts.remote_address.is_empty() => Vec::new(),
+        Mode::Listen => return Err("--remote-address is only supported in dial mode".into()),
+    };
+    let remotes = Remotes::new(match opts.mode {
+        Mode::Dial => &remote_peer_ids[..],
+        Mode::Listen => &[],
+    });
+    // Measuring a single connection, the once check and the latency probe take exactly one remote.
+    let single_remote = |what: &str| match remote_peer_ids.as_slice() {
+        [remote_peer_id] => Ok(*remote_peer_id),
+        [] => Err(format!("{what} requires --remote-peer-id")),
+        _ => Err(format!("{what} takes a single remote peer")),
+    };
+
+    let mut one_shot = match (opts.once, &opts.mode) {
+        (false, _) => None,
+        (true, Mode::Listen) => return Err("--once is only supported in dial mode".into()),
+        (true, Mode::Dial) => Some(OneShot::new(single_remote("--once")?)),
+    };
+    if let (Some(one_shot), Some(timeout)) = (one_shot.as_mut(), opts.timeout) {
+        one_shot.set_timeout(Duration::from_secs(timeout));
+    }
+    let bench = match (opts.bench, &opts.mode) {
+        (None, _) => None,
+        (Some(spec @ BenchSpec::Publish { size, .. }), Mode::Dial) => {
+            if size > opts.max_message_size {
+                return Err(format!(
+                    "--bench messages can't be larger than --max-message-size {}",
+                    opts.max_message_size
+                )
+                .into());
+            }
+            Some(Bench::new(
+                spec,
+                remote_peer_ids.first().copied(),
+                opts.bench_max_loss,
+                opts.bench_results.clone(),
+            )?)
+        }
+        (Some(BenchSpec::Receive), Mode::Listen) => Some(Bench::new(
+            BenchSpec::Receive,
+            None,
+            opts.bench_max_loss,
+            opts.bench_results.clone(),
+        )?),
+        (Some(_), _) => {
+            return Err(
+                "--bench publish is for dial mode and --bench receive for listen mode".into(),
+            )
+        }
+    };
+    let latency_probe = match (opts.latency_probe, &opts.mode) {
+        (None, _) => None,
+        (Some(count), Mode::Dial) => {
+            Some(LatencyProbe::new(single_remote("--latency-probe")?, count))
+        }
+        (Some(_), Mode::Listen) => {
+            return Err("--latency-probe is only supported in dial mode".into())
+        }
+    };
+
+    let mut bootstrap_addrs = opts.bootstrap.clone();
+    if let Some(path) = &opts.bootstrap_file {
+        bootstrap_addrs.extend(bootstrap_peers::load_file(path)?);
+    }
+    let mut bootstrap_peers = BootstrapPeers::new(bootstrap_addrs)?;
+    for addr in &opts.external_address {
+        external::validate(addr)?;
+    }
+    let external_addrs = ExternalAddresses::new(opts.external_address.clone());
+    let allow_list = match &opts.allow_file {
+        Some(path) => Some(AllowList::new(
+            opts.allow_peer
+                .iter()
+                .copied()
+                .chain(allow_list::load_file(path)?),
+        )?),
+        None if !opts.allow_peer.is_empty() => Some(AllowList::new(opts.allow_peer.clone())?),
+        None => None,
+    };
+    let mesh_params = MeshParams::new(
+        opts.mesh_n,
+        opts.mesh_n_low,
+        opts.mesh_n_high,
+        opts.mesh_outbound_min,
+        opts.gossip_lazy,
+    );
+    mesh_params.validate()?;
+    chunking::validate_max_message_size(
+        opts.max_message_size,
+        opts.topics.iter().map(String::as_str),
+    )?;
+    let score_config = ScoreConfig {
+        topic_weight: opts.score_topic_weight,
+        invalid_message_penalty: opts.score_invalid_message_penalty,
+        graylist_threshold: opts.score_graylist_threshold,
+    };
+    score_config.validate()?;
+    let reputation_config = ReputationConfig {
+        threshold: opts.reputation_threshold,
+        recovery_per_min: opts.reputation_recovery,
+        ban: Duration::from_secs(opts.reputation_ban),
+        max_ban: Duration::from_secs(opts.reputation_max_ban),
+    };
+    reputation_config.validate()?;
+    let publish_params = PublishParams {
+        flood_publish: opts.no_flood_publish.then_some(false),
+        heartbeat_initial_delay: opts.heartbeat_initial_delay_ms.map(Duration::from_millis),
+        allow_self_origin: opts.allow_self_origin,
+    };
+    publish_params.validate(HEARTBEAT_INTERVAL)?;
+
+    let local_key = identity::generate_ed25519(opts.secret_key_seed);
+    let local_peer_id = PeerId::from(local_key.public());
+    let e2e_key =
+        E2eKey::from_ed25519_seed(&identity::ed25519_seed(opts.secret_key_seed), local_peer_id);
+    info!("Local peer id: {:?}", local_peer_id);
+    let configured_relay = relay_addr::validate(&relay_address)?;
+    relay_addr::check_peer_ids(&local_peer_id, configured_relay, &remote_peer_ids)?;
+
+    let (relay_transport, client) = relay::client::new(local_peer_id);
+
+    let psk = match &opts.psk_file {
+        Some(path) if opts.generate_psk => {
+            let psk = psk::generate(path)?;
+            say!("Wrote a new pre-shared key to {}", path.display());
+            Some(psk)
+        }
+        Some(path) => Some(psk::load(path)?),
+        None => None,
+    };
+    if let Some(psk) = psk {
+        info!(
+            "Private network with pre-shared key fingerprint {}",
+            psk.fingerprint()
+        );
+    }
+
+    let bandwidth = Bandwidth::default();
+    let dns = DnsSettings {
+        servers: opts.dns_server.clone(),
+        fallback: opts.dns_fallback_server.clone(),
+        protocol: opts.dns_protocol,
+        tls_name: opts.dns_tls_name.clone(),
+        timeout: Duration::from_secs(opts.dns_timeout),
+    };
+    let transport = transport::build(&local_key, relay_transport, psk, &dns, &bandwidth).await?;
+
+    // Set a custom gossipsub configuration
+    let mut gossipsub_config = gossipsub::ConfigBuilder::default();
+    mesh_params.apply(&mut gossipsub_config);
+    if let Some(idle_timeout) = opts.idle_timeout {
+        gossipsub_config.idle_timeout(Duration::from_secs(idle_timeout));
+    }
+    let gossipsub_config = publish_params
+        .apply(&mut gossipsub_config)
+        .max_transmit_size(opts.max_message_size)
+        .validate_messages() // Messages are only forwarded once we reported them as valid.
+        .heartbeat_interval(HEARTBEAT_INTERVAL) // This is set to aid debugging by not cluttering the log space
+        .validation_mode(gossipsub::ValidationMode::Strict) // This sets the kind of message validation. The default is Strict (enforce message signing)
+        .message_id_fn(opts.message_id.id_fn()) // Duplicates, i.e. messages with the same id, are not propagated.
+        .build()
+        .map_err(|e| format!("invalid gossipsub config: {e}"))?;
+    mesh::log_publish_config(&gossipsub_config);
+
+    // build a gossipsub network behaviour
+    let mut gossipsub = gossipsub::Behaviour::new(
+        gossipsub::MessageAuthenticity::Signed(local_key.clone()),
+        gossipsub_config,
+    )
+    .expect("Correct configuration");
+    let forwarder = Forwarder::new(&opts.forward);
+    let bridged_topics = opts
+        .bridge
+        .iter()
+        .map(|bridge| bridge.topic.clone())
+        .chain(opts.mqtt_sub.iter().map(|mapping| mapping.to.clone()))
+        .chain(opts.mqtt_pub.iter().map(|mapping| mapping.from.clone()))
+        .chain(forwarder.topics().map(str::to_string))
+        .collect::<Vec<_>>();
+    for topic in bridged_topics {
+        if !opts.topics.contains(&topic) {
+            opts.topics.push(topic);
+        }
+    }
+    // Create the Gossipsub topics and subscribe to them
+    let mut topics = Topics::new(&opts.topics, opts.topic_hashing);
+    let topic_keys = TopicKeys::load(
+        opts.topic_key_file.as_deref(),
+        opts.topic_passphrase.clone(),
+        &opts.topics,
+    )?;
+    let publish_limit = opts
+        .publish_rate
+        .map(|rate| TokenBucket::new(rate, opts.publish_burst, Instant::now()));
+    let presence_budget = publish_limit
+        .as_ref()
+        .map(|limit| limit.quarter(Instant::now()));
+    let outbox = Outbox::new(opts.outbox_size).with_limit(publish_limit);
+    let sequencer = Sequencer::new(local_peer_id, opts.nick.clone()).with_ttls(&opts.ttl);
+    let compress_threshold = if opts.no_compress {
+        usize::MAX
+    } else {
+        opts.compress_threshold
+    };
+    let reassembly = Reassembly::new(
+        Duration::from_secs(opts.chunk_timeout),
+        opts.max_partial_messages,
+        opts.max_reassembly_bytes,
+    );
+    let freshness = Freshness {
+        max_age: Duration::from_secs(opts.max_message_age),
+        skew: Duration::from_secs(opts.clock_skew),
+    };
+    let rate_limiter = opts
+        .max_msgs_per_peer
+        .map(|rate| PeerRateLimiter::new(rate, opts.max_msgs_burst));
+    let validation_stats = ValidationStats::default();
+    let denials = DenialLog::default();
+    let pending_dms = PendingDms::new(opts.dm_retries);
+    let seen_dms = SeenDms::default();
+    let replay_guard = ReplayGuard::default();
+    let resend = ResendBuffer::new(
+        opts.resend_buffer,
+        Duration::from_secs(opts.resend_max_age),
+        remote_peer_ids.iter().copied(),
+    );
+    let mut history =
+        History::new(opts.history_size).with_clock_skew(Duration::from_secs(opts.clock_skew));
+    let roster = Roster::new(Duration::from_secs(opts.presence_interval));
+    let fallback = (!opts.no_fallback_channel).then(|| {
+        FallbackChannel::new(
+            Duration::from_secs(opts.fallback_after),
+            opts.fallback_min_mesh,
+        )
+    });
+    let transfers = TransferWorker::start(Transfers::new(
+        opts.download_dir.clone(),
+        opts.overwrite,
+        opts.max_file_size,
+        opts.max_incoming_transfers,
+        Duration::from_secs(opts.transfer_timeout),
+    ))?;
+    let mut message_log = None;
+    if !opts.no_persist {
+        let now = envelope::unix_millis();
+        let mut live = VecDeque::new();
+        let log = MessageLog::open(
+            &opts.data_dir,
+            &local_peer_id,
+            opts.message_log_max_size,
+            |record| {
+                history.record(&record.topic_hash(), &record.payload());
+                if opts.replay > 0 && !record.is_expired(now) {
+                    if live.len() == opts.replay {
+                        live.pop_front();
+                    }
+                    live.push_back(record);
+                }
+            },
+        )?;
+        for record in &live {
+            let keys = topic_keys.of(&record.topic_name);
+            let text = envelope::render(
+                &record.payload(),
+                None,
+                opts.max_decompressed_size,
+                &record.topic_hash(),
+                keys,
+            )
+            .unwrap_or_else(|_| record.body.clone());
+            say!("[log] [{}] {text}", record.topic_name);
+        }
+        message_log = Some(log);
+    }
+    let input_history = (!opts.no_persist).then(|| {
+        opts.data_dir
+            .join(format!("input-history-{local_peer_id}.txt"))
+    });
+    // Requests of the control socket and the HTTP API, answered by the event loop.
+    let (control_sender, control_requests) = futures::channel::mpsc::unbounded();
+    let mut control_requests = control_requests.fuse();
+    let _control_socket = opts
+        .control_socket
+        .as_deref()
+        .map(|path| ControlSocket::bind(path, control_sender.clone()))
+        .transpose()?;
+    let mut registry = Registry::default();
+    let metrics = Metrics::new(&mut registry);
+    if let Some(addr) = opts.metrics_addr {
+        metrics::serve(addr, registry)?;
+    }
+    let health = Health::new(Duration::from_secs(opts.health_stale_after));
+    if let Some(addr) = opts.health_addr {
+        health::serve(addr, &health)?;
+    }
+    let mut feed = None;
+    if let Some(addr) = opts.http_api {
+        if !addr.ip().is_loopback() && !opts.http_api_public {
+            return Err(format!("--http-api {addr} is not a loopback address, pass --http-api-public to serve it anyway").into());
+        }
+        http_api::start(
+            addr,
+            opts.http_token.clone(),
+            control_sender.clone(),
+            &health,
+        )?;
+        feed = Some(Feed::default());
+    }
+    let hook = opts.on_message.clone().map(|command| {
+        Hook::start(
+            command,
+            opts.hook_filter.clone(),
+            opts.hook_concurrency,
+            Duration::from_secs(opts.hook_timeout),
+        )
+    });
+    let (bridge_sender, mut bridge_lines) = futures::channel::mpsc::unbounded();
+    let bridges = Bridges::start(&opts.bridge, &local_peer_id, bridge_sender)?;
+    let (mqtt_sender, mut mqtt_messages) = futures::channel::mpsc::unbounded();
+    let mqtt = opts
+        .mqtt_broker
+        .as_deref()
+        .map(|broker| {
+            Mqtt::start(
+                broker,
+                format!("dcutr-{local_peer_id}"),
+                opts.mqtt_sub.clone(),
+                opts.mqtt_pub.clone(),
+                opts.mqtt_qos,
+                mqtt_sender,
+            )
+        })
+        .transpose()?;
+    let subscribers = Subscribers::default();
+    // Lines typed while bootstrapping are held back until the session is up.
+    let mut stdin = Backlog::new(
+        match ui_input.or(file_input) {
+            Some(input) => input,
+            None => input::lines(input_history, opts.output == Output::Text),
+        }
+        .fuse(),
+        opts.outbox_size,
+    );
+    let sequences = Sequences::load(
+        (!opts.no_persist).then_some(opts.data_dir.as_path()),
+        &local_peer_id,
+        Duration::from_secs(opts.seq_reset_after),
+    )?;
+    let address_book = AddressBook::load(
+        (!opts.no_persist).then_some(opts.data_dir.as_path()),
+        &local_peer_id,
+        opts.address_book_size,
+        Duration::from_secs(opts.address_max_age * 3600),
+    )?;
+    let seen = SeenMessages::load(
+        (!opts.no_persist).then_some(opts.data_dir.as_path()),
+        &local_peer_id,
+        opts.seen_cache_size,
+        Duration::from_secs(opts.seen_cache_age),
+    )?;
+    topics.subscribe_all(&mut gossipsub)?;
+    if bench.is_some() {
+        gossipsub
+            .subscribe(&Bench::topic())
+            .map_err(|e| format!("failed to subscribe to the bench topic: {e:?}"))?;
+    }
+    let soak = match opts.soak {
+        Some(0) => return Err("--soak needs an interval of at least one second".into()),
+        Some(interval) => {
+            gossipsub
+                .subscribe(&Soak::topic())
+                .map_err(|e| format!("failed to subscribe to the soak topic: {e:?}"))?;
+            Some(Soak::new(
+                Duration::from_secs(interval),
+                opts.soak_miss_threshold.max(1),
+                (!opts.no_persist).then_some(opts.data_dir.as_path()),
+                &local_peer_id,
+            )?)
+        }
+        None => None,
+    };
+    let mut score_watch = None;
+    if opts.peer_scoring {
+        gossipsub.with_peer_score(score_config.params(), score_config.thresholds())?;
+        topics.set_score_params(score_config.topic_params(), &mut gossipsub)?;
+        info!("Gossipsub peer scoring enabled: {score_config:?}");
+        score_watch = Some(ScoreWatch::new(&score_config));
+    }
+
+    // The relay is always allowed, the circuits to our peers go through it.
+    if allow_list.is_some() && configured_relay.is_none() {
+        return Err(
+            "with --allow-file the relay address needs its /p2p/<peer-id> to let the relay in"
+                .into(),
+        );
+    }
+    let allowed = allow_list.as_ref().map(|allow_list| {
+        allow_list
+            .peers()
+            .chain(configured_relay.as_ref())
+            .copied()
+            .collect()
+    });
+    // What a received message has to pass before it's shown and propagated, in this order.
+    let mut pipeline = Pipeline::new(Duration::from_secs(opts.validation_timeout));
+    let direct_allow_list = allow_list.clone();
+    if let Some(allow_list) = allow_list {
+        pipeline.push(allow_list);
+    }
+    if let Some(rate_limiter) = rate_limiter {
+        pipeline.push(rate_limiter);
+    }
+    let body_size = BodySize {
+        max_decompressed: opts.max_decompressed_size,
+    };
+    pipeline.push(freshness);
+    pipeline.push(body_size);
+    let reassembled = Reassembled {
+        freshness,
+        body_size,
+    };
+    if opts.max_connections_per_peer.map_or(false, |max| max < 2) {
+        return Err("--max-connections-per-peer must be at least 2 for hole punching".into());
+    }
+    let limits = Limits {
+        max_established_incoming: opts.max_incoming,
+        max_established_outgoing: opts.max_outgoing,
+        max_pending_incoming: opts.max_pending_incoming,
+        max_established_per_peer: opts.max_connections_per_peer,
+        max_relayed_incoming: Some(opts.max_relayed_inbound),
+    };
+    let agent_version = {
+        let agent_version = opts
+            .agent_version
+            .clone()
+            .unwrap_or_else(|| behaviour::AGENT_VERSION.to_string());
+        match &opts.agent_suffix {
+            Some(suffix) => format!("{agent_version}/{suffix}"),
+            None => agent_version,
+        }
+    };
+    info!(%agent_version, "Identifying as");
+    let config = BehaviourConfig {
+        kademlia: opts.kademlia,
+        fallback_channel: !opts.no_fallback_channel,
+        allowed,
+        dm_timeout: Duration::from_secs(opts.dm_timeout),
+        ping: ping::Config::new()
+            .with_interval(Duration::from_secs(opts.ping_interval))
+            .with_timeout(Duration::from_secs(opts.ping_timeout))
+            .with_max_failures(match opts.no_ping_disconnect {
+                true => NonZeroU32::MAX,
+                false => opts.ping_max_failures,
+            }),
+        identify: identify::Config::new(
+            opts.identify_protocol_version
+                .clone()
+                .unwrap_or_else(|| behaviour::PROTOCOL_VERSION.to_string()),
+            local_key.public(),
+        )
+        .with_agent_version(agent_version)
+        .with_interval(Duration::from_secs(opts.identify_interval))
+        .with_push_listen_addr_updates(true),
+        keep_alive: opts.keep_alive,
+        limits,
+    };
+    let mut behaviour = Behaviour::new(&local_key, client, gossipsub, config);
+    // The core session is never starved by strangers.
+    if let Some(relay) = configured_relay {
+        behaviour.limits.protect(relay);
+    }
+    let mut swarm = Node::new(transport, behaviour, local_peer_id);
+    if configured_relay.is_none() {
+        relay_address = relay_addr::complete(
+            &mut swarm,
+            &relay_address,
+            Duration::from_secs(opts.bootstrap_timeout),
+        )
+        .await?;
+        let relay = bootstrap_peers::peer_id_of(&relay_address);
+        relay_addr::check_peer_ids(&local_peer_id, relay, &remote_peer_ids)?;
+        if let Some(relay) = relay {
+            swarm.behaviour_mut().limits.protect(relay);
+        }
+    }
+
+    let explicit = ExplicitPeers::load(
+        (!opts.no_persist).then_some(opts.data_dir.as_path()),
+        &local_peer_id,
+        &remote_peer_ids,
+    )?;
+    for peer_id in explicit.peers() {
+        swarm.behaviour_mut().gossipsub.add_explicit_peer(peer_id);
+        swarm.behaviour_mut().limits.protect(*peer_id);
+    }
+    let bans = BanList::load(opts.ban_file.clone())?;
+    for peer_id in bans.peers() {
+        swarm.behaviour_mut().gossipsub.blacklist_peer(peer_id);
+        swarm.behaviour_mut().blocked.block_peer(*peer_id);
+    }
+    let session_peers = remote_peer_ids
+        .iter()
+        .copied()
+        .chain(bootstrap_peers::peer_id_of(&relay_address))
+        .collect::<Vec<_>>();
+    let mut reputation = Reputation::load(
+        opts.persist_reputation.then_some(opts.data_dir.as_path()),
+        &local_peer_id,
+        reputation_config,
+    )?;
+    for peer_id in session_peers.iter().chain(explicit.peers()) {
+        reputation.protect(*peer_id);
+    }
+    for peer_id in reputation.banned(reputation::now_secs()) {
+        swarm.behaviour_mut().gossipsub.blacklist_peer(&peer_id);
+        swarm.behaviour_mut().blocked.block_peer(peer_id);
+    }
+
+    for addr in external_addrs.iter() {
+        swarm.add_external_address(addr.clone(), AddressScore::Infinite);
+    }
+    if !external_addrs.is_empty() {
+        say!("{external_addrs}");
+    }
+
+    let listener = swarm
+        .listen_on(
+            Multiaddr::empty()
+                .with("0.0.0.0".parse::<Ipv4Addr>().unwrap().into())
+                .with(Protocol::Tcp(0)),
+        )
+        .map_err(|e| format!("failed to listen: {e}"))?;
+    // Bootstrap peers are dialed right away, alongside the relay bootstrap phases rather than
+    // after them. Failures are retried from the tick.
+    if !bootstrap_peers.is_empty() {
+        say!("Dialing bootstrap peers");
+    }
+    for addr in bootstrap_peers.due(Instant::now()) {
+        if let Err(e) = swarm.dial(addr.clone()) {
+            say!("Failed to dial bootstrap peer {addr}: {e}");
+            if let Some(peer_id) = bootstrap_peers::peer_id_of(&addr) {
+                bootstrap_peers.on_dial_failure(&peer_id);
+            }
+        }
+    }
+    let relay = RelaySession::new(relay_address);
+    let bootstrap = Bootstrap::new(
+        HashSet::from([listener]),
+        relay.peer_id(),
+        remotes.peer_ids().collect(),
+        (!opts.force_relay).then(|| Duration::from_secs(opts.autonat_wait)),
+        Duration::from_secs(opts.bootstrap_timeout),
+    );
+
+    // The listen port is mapped on the router once it's bound. Failures are only logged, the
+    // relay/DCUtR flow works regardless.
+    let (upnp_tx, mut upnp_events) = futures::channel::mpsc::unbounded();
+
+    if let Some(kademlia) = swarm.behaviour_mut().kademlia.as_mut() {
+        for (peer_id, addr) in bootstrap_peers.addresses() {
+            kademlia.add_address(&peer_id, addr.clone());
+        }
+    }
+
+    let holepunch = HolePunchTracker::new(
+        opts.holepunch_retries,
+        opts.mode == Mode::Dial,
+        relay.address().clone(),
+    );
+    let relayed_connections =
+        (!opts.keep_relayed).then(|| RelayedConnections::new(RELAYED_CLOSE_GRACE));
+    let connection_paths = ConnectionPaths::default();
+    let holepunch_stats = HolePunchStats::default();
+    let traffic = Traffic::default();
+    let rtt_stats = RttStats::default();
+    let path_comparisons = PathComparisons::default();
+    let peer_infos = PeerInfos::default();
+    let idle = opts
+        .idle_timeout
+        .map(|secs| IdleConnections::new(Duration::from_secs(secs)));
+
+    let mut session = Session {
+        opts,
+        ui,
+        event_log,
+        journal,
+        remote_peer_ids,
+        remote_addrs,
+        remotes,
+        one_shot,
+        bench,
+        latency_probe,
+        bootstrap_peers,
+        external_addrs,
+        local_key,
+        local_peer_id,
+        e2e_key,
+        psk,
+        bandwidth,
+        forwarder,
+        topics,
+        topic_keys,
+        presence_budget,
+        outbox,
+        sequencer,
+        compress_threshold,
+        reassembly,
+        reassembled,
+        validation_stats,
+        denials,
+        pending_dms,
+        seen_dms,
+        replay_guard,
+        resend,
+        history,
+        roster,
+        fallback,
+        transfers,
+        message_log,
+        metrics,
+        health,
+        feed,
+        hook,
+        bridges,
+        mqtt,
+        subscribers,
+        waiting_for_subscribers: false,
+        sequences,
+        address_book,
+        seen,
+        soak,
+        score_watch,
+        pipeline,
+        direct_allow_list,
+        explicit,
+        bans,
+        session_peers,
+        reputation,
+        relay,
+        bootstrap,
+        upnp_tx,
+        _upnp: None,
+        holepunch,
+        relayed_connections,
+        connection_paths,
+        holepunch_stats,
+        traffic,
+        rtt_stats,
+        path_comparisons,
+        peer_infos,
+        dht_lookups: Vec::new(),
+        idle,
+        ping_failures: HashMap::new(),
+        next_stats_report: Instant::now() + STATS_INTERVAL,
+        direct_candidates: HashSet::new(),
+    };
+    say!("Enter messages via STDIN and they will be sent to connected peers using Gossipsub, /help lists the commands");
+
+    let mut signals = shutdown::signals()?;
+    let mut tick = futures_timer::Delay::new(TICK_INTERVAL).fuse();
+    let mut bench_pacer = futures_timer::Delay::new(TICK_INTERVAL).fuse();
+    let mut publish_pacer = futures_timer::Delay::new(TICK_INTERVAL).fuse();
+    // Set once a `--once` or `--bench` run is over, or bootstrapping timed out.
+    let mut exit_code = None;
+    loop {
+        if let Some(code) = session.exit_code() {
+            exit_code = Some(code);
+            break;
+        }
+        // A line typed or sent through the control socket, handled once the select is done.
+        let mut input = None;
+        // The bridge a published line came from.
+        let mut bridged = None;
+        futures::select!(
+            line = stdin.next() => match line {
+                Some(line) => input = Some((line, None)),
+                None => {
+                    say!("Input closed, shutting down");
+                    break;
+                }
+            },
+            _ = signals.select_next_some() => break,
+            done = session.pipeline.select_next_some() => session.on_validated(&mut swarm, done),
+            line = bridge_lines.select_next_some() => {
+                // Routed like `@topic` input, so it's published like a typed line.
+                input = Some((format!("@{} {}", session.bridges.topic(line.bridge), line.text), None));
+                bridged = Some(session.bridges.marker(line.bridge).to_string());
+            }
+            message = mqtt_messages.select_next_some() => {
+                input = Some((format!("@{} {}", message.topic, message.text), None));
+                bridged = session.mqtt.as_ref().map(|mqtt| mqtt.marker().to_string());
+            }
+            request = control_requests.select_next_some() => {
+                input = session.on_control_request(&mut swarm, request);
+            }
+            _ = publish_pacer => {
+                let wait = session.on_publish_pace(&mut swarm);
+                publish_pacer = futures_timer::Delay::new(wait).fuse();
+            }
+            _ = bench_pacer => {
+                bench_pacer = futures_timer::Delay::new(BENCH_PACE).fuse();
+                session.on_bench_pace(&mut swarm);
+            }
+            _ = tick => {
+                tick = futures_timer::Delay::new(TICK_INTERVAL).fuse();
+                if let Err(timed_out) = session.on_tick(&mut swarm) {
+                    say!("{timed_out}");
+                    exit_code = Some(timed_out.exit_code());
+                    break;
+                }
+            },
+            output = session.transfers.select_next_some() => session.on_transfer_output(&mut swarm, output),
+            upnp_event = upnp_events.select_next_some() => match upnp_event {
+                UpnpEvent::Mapped(addr) => {
+                    say!("Router forwards {addr} to us via UPnP");
+                    swarm.add_external_address(addr, AddressScore::Infinite);
+                }
+                UpnpEvent::Failed(e) => info!("UPnP port mapping unavailable: {e}"),
+            },
+            event = swarm.select_next_some() => session.on_swarm_event(&mut swarm, event)?,
+        );
+        if session.bootstrap.is_running() && !stdin.is_released() && session.ready_for_input() {
+            stdin.release();
+        }
+        let (line, reply) = match input {
+            Some(input) => input,
+            None => continue,
+        };
+        let command = match Command::parse(&line) {
+            Ok(command) => command,
+            Err(e) => {
+                say!("{e}");
+                continue;
+            }
+        };
+        if session
+            .on_command(&mut swarm, command, reply, bridged)
+            .is_break()
+        {
+            break;
+        }
+    }
+    shutdown::begin();
+    session.leave(&mut swarm);
+    if !shutdown::close_connections(&mut *swarm, &mut signals).await {
+        return Ok(ExitCode::from(shutdown::EXIT_INTERRUPTED));
+    }
+    session.finish();
+    Ok(ExitCode::from(exit_code.unwrap_or(0)))
+}
+
+/// What the event loop keeps between events besides the swarm and the input streams. Each kind of
+/// event has a method of its own, handed the swarm to act on.
+struct Session {
+    opts: Opts,
+    ui: Option<Tui>,
+    event_log: Option<EventLog>,
+    journal: Option<Journal>,
+    remote_peer_ids: Vec<PeerId>,
+    /// The addresses of the remotes given on the command line.
+    remote_addrs: Vec<(PeerId, Multiaddr)>,
+    remotes: Remotes,
+    one_shot: Option<OneShot>,
+    bench: Option<Bench>,
+    latency_probe: Option<LatencyProbe>,
+    bootstrap_peers: BootstrapPeers,
+    external_addrs: ExternalAddresses,
+    local_key: Keypair,
+    local_peer_id: PeerId,
+    e2e_key: E2eKey,
+    psk: Option<PreSharedKey>,
+    bandwidth: Bandwidth,
+    forwarder: Forwarder,
+    topics: Topics,
+    topic_keys: TopicKeys,
+    /// Presence has a budget of its own, so a flood of messages can't hold it back.
+    presence_budget: Option<TokenBucket>,
+    outbox: Outbox,
+    sequencer: Sequencer,
+    compress_threshold: usize,
+    reassembly: Reassembly,
+    reassembled: Reassembled,
+    validation_stats: ValidationStats,
+    denials: DenialLog,
+    pending_dms: PendingDms,
+    seen_dms: SeenDms,
+    replay_guard: ReplayGuard,
+    resend: ResendBuffer,
+    history: History,
+    roster: Roster,
+    fallback: Option<FallbackChannel>,
+    transfers: TransferWorker,
+    message_log: Option<MessageLog>,
+    metrics: Metrics,
+    health: Health,
+    feed: Option<Feed>,
+    hook: Option<Hook>,
+    bridges: Bridges,
+    mqtt: Option<Mqtt>,
+    /// The peers subscribed to each topic, `--wait-for-subscribers` counts them.
+    subscribers: Subscribers,
+    waiting_for_subscribers: bool,
+    sequences: Sequences,
+    address_book: AddressBook,
+    seen: SeenMessages,
+    soak: Option<Soak>,
+    score_watch: Option<ScoreWatch>,
+    pipeline: Pipeline,
+    /// Direct channel messages are turned away before they're acked.
+    direct_allow_list: Option<AllowList>,
+    explicit: ExplicitPeers,
+    bans: BanList,
+    /// Banning these breaks the session, so it has to be forced.
+    session_peers: Vec<PeerId>,
+    reputation: Reputation,
+    relay: RelaySession,
+    /// Listening, reaching the relay, probing the NAT and getting the reservation or circuit all
+    /// happen in the event loop, driven by this.
+    bootstrap: Bootstrap,
+    upnp_tx: mpsc::UnboundedSender<UpnpEvent>,
+    /// Held until shutdown, dropping it removes the mapping.
+    _upnp: Option<UpnpHandle>,
+    holepunch: HolePunchTracker,
+    relayed_connections: Option<RelayedConnections>,
+    connection_paths: ConnectionPaths,
+    holepunch_stats: HolePunchStats,
+    traffic: Traffic,
+    /// The recent ping round trip times of each connection.
+    rtt_stats: RttStats,
+    /// How much faster the direct connections are than the relayed ones.
+    path_comparisons: PathComparisons,
+    /// What each connected peer identified itself with.
+    peer_infos: PeerInfos,
+    dht_lookups: Vec<RemoteLookup>,
+    idle: Option<IdleConnections>,
+    /// Failed pings in a row per connection, ping closes the connection once there are too many.
+    ping_failures: HashMap<ConnectionId, u32>,
+    next_stats_report: Instant,
+    /// The ids of the candidates that came over the direct channel, which gossipsub doesn't know.
+    direct_candidates: HashSet<MessageId>,
+}
+
+impl Session {
+    /// Set once a `--once` or `--bench` run is over.
+    fn exit_code(&self) -> Option<u8> {
+        self.one_shot
+            .as_ref()
+            .and_then(OneShot::exit_code)
+            .or_else(|| self.bench.as_ref().and_then(Bench::exit_code))
+    }
+
+    /// Whether the input held back while bootstrapping can be let through, enough peers having
+    /// subscribed to the selected topic.
+    fn ready_for_input(&mut self) -> bool {
+        let topic = self.topics.selected().cloned();
+        let subscribed = topic
+            .as_ref()
+            .map_or(0, |topic| self.subscribers.count(topic));
+        let name = topic
+            .as_ref()
+            .map(|topic| self.topics.name(topic))
+            .unwrap_or_default();
+        if subscribed >= self.opts.wait_for_subscribers {
+            if self.opts.wait_for_subscribers > 0 {
+                say!("{subscribed} peers subscribed to '{name}', ready");
+            }
+            return true;
+        }
+        if !self.waiting_for_subscribers {
+            say!(
+                "Waiting for {} peers to subscribe to '{name}', {subscribed} so far",
+                self.opts.wait_for_subscribers
+            );
+            self.waiting_for_subscribers = true;
+        }
+        false
+    }
+
+    /// Answers a request of the control socket or the HTTP API. A line to publish is returned
+    /// with where to send the reply, it's handled like typed input.
+    fn on_control_request(
+        &mut self,
+        swarm: &mut Node,
+        request: ControlRequest,
+    ) -> Option<(String, Option<oneshot::Sender<Response>>)> {
+        match request {
+            ControlRequest::Publish { line, reply } => return Some((line, Some(reply))),
+            ControlRequest::Peers { reply } => {
+                let peers = swarm
+                .connected_peers()
+                .map(|peer_id| {
+                    let info = self.peer_infos.get(peer_id);
+                    serde_json::json!({
+                        "peer_id": peer_id.to_string(),
+                        "explicit": self.explicit.contains(peer_id),
+                        "path": self.connection_paths.path(peer_id).map(|path| path.to_string()),
+                        "rtt": self.rtt_stats.of_peer(peer_id).into_iter().map(|(connection, rtt)| serde_json::json!({
+                            "path": self.connection_paths.connections(peer_id).into_iter()
+                                .find(|(id, _)| *id == connection)
+                                .map(|(_, path)| path.to_string()),
+                            "current_ms": rtt.current.as_millis() as u64,
+                            "min_ms": rtt.min.as_millis() as u64,
+                            "avg_ms": rtt.avg.as_millis() as u64,
+                            "p95_ms": rtt.p95.as_millis() as u64,
+                        })).collect::<Vec<_>>(),
+                        "agent_version": info.map(|info| &info.agent_version),
+                        "protocol_version": info.map(|info| &info.protocol_version),
+                        "protocols": info.map(|info| &info.protocols),
+                    })
+                })
+                .collect();
+                let _ = reply.send(Response::ok(serde_json::Value::Array(peers)));
+            }
+            ControlRequest::Status { reply } => {
+                let _ = reply.send(Response::ok(serde_json::json!({
+                "peer_id": self.local_peer_id.to_string(),
+                "relay": self.relay.status(),
+                "listen_addrs": swarm.listeners().map(|a| a.to_string()).collect::<Vec<_>>(),
+                "external_addrs": swarm.external_addresses().map(|a| a.addr.to_string()).collect::<Vec<_>>(),
+                "hole_punch": self.holepunch_stats.to_string(),
+                "hole_punch_peers": self.holepunch_stats.peer_lines(),
+                "topics": self.topics.hashes().map(|t| self.topics.name(t)).collect::<Vec<_>>(),
+            })));
+            }
+            ControlRequest::Messages {
+                since,
+                topic,
+                wait,
+                reply,
+            } => match self.feed.as_mut() {
+                Some(feed) => feed.request(since, topic, wait, reply),
+                None => {
+                    let _ = reply.send(Response::error("messages are only kept with --http-api"));
+                }
+            },
+            ControlRequest::Dial { addr, reply } => {
+                let response = match swarm.dial(addr.clone()) {
+                    Ok(()) => Response::ok(serde_json::json!({ "addr": addr.to_string() })),
+                    Err(e) => Response::error(format!("failed to dial {addr}: {e}")),
+                };
+                let _ = reply.send(response);
+            }
+        }
+        None
+    }
+
+    /// Publishes what the outbox can send now, returning how long to wait for the next round.
+    fn on_publish_pace(&mut self, swarm: &mut Node) -> Duration {
+        flush_outbox(
+            &mut self.outbox,
+            &mut swarm.behaviour_mut().gossipsub,
+            &self.topics,
+            self.opts.max_decompressed_size,
+        );
+        self.outbox
+            .ready_in(Instant::now())
+            .unwrap_or(TICK_INTERVAL)
+            .max(PUBLISH_PACE)
+    }
+
+    /// Hands the swarm the next batch of `--bench` messages.
+    fn on_bench_pace(&mut self, swarm: &mut Node) {
+        if let Some(bench) = self.bench.as_mut() {
+            for _ in 0..bench::BATCH {
+                let frame = match bench.next_frame(Instant::now()) {
+                    Some(frame) => frame,
+                    None => break,
+                };
+                match swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .publish(Bench::topic(), frame)
+                {
+                    Ok(_) => bench.on_sent(Instant::now()),
+                    // Tried again on the next round.
+                    Err(e) => {
+                        debug!(?e, "Benchmark publish failed");
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The work due every [`TICK_INTERVAL`]. Fails once bootstrapping took too long.
+    fn on_tick(&mut self, swarm: &mut Node) -> Result<(), TimedOut> {
+        self.transfers.expire(Instant::now());
+        self.bootstrap.poll(Instant::now())?;
+        self.poll_measurements(swarm);
+        self.connections_due(swarm);
+        self.messages_due(swarm);
+        self.bookkeeping_due(swarm);
+        Ok(())
+    }
+
+    /// Drives `--once`, the latency probe, `--bench` and `--soak`.
+    fn poll_measurements(&mut self, swarm: &mut Node) {
+        if let Some(one_shot) = self.one_shot.as_mut() {
+            one_shot.poll(Instant::now());
+        }
+        if let Some(probe) = self.latency_probe.as_mut() {
+            let echo = probe.poll(
+                self.connection_paths.has_relayed(&probe.remote()),
+                Instant::now(),
+            );
+            send_echo(&mut swarm.behaviour_mut().echo, probe, echo);
+        }
+        if let Some(bench) = self.bench.as_mut() {
+            let direct = self
+                .remote_peer_ids
+                .first()
+                .and_then(|p| self.connection_paths.path(p))
+                == Some(TransportPath::Direct);
+            bench.on_tick(direct, Instant::now());
+        }
+        if let Some(soak) = self.soak.as_mut() {
+            if let Some(heartbeat) = soak.heartbeat(Instant::now()) {
+                if let Err(e) = swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .publish(Soak::topic(), heartbeat)
+                {
+                    debug!(?e, "Failed to publish the soak heartbeat");
+                }
+            }
+            for peer_id in soak.missed(Instant::now()) {
+                if !self
+                    .relay
+                    .peer_id()
+                    .map_or(false, |relay| swarm.is_connected(&relay))
+                {
+                    if let Err(e) = swarm.dial(self.relay.address().clone()) {
+                        warn!(error = %e, "Failed to redial the relay");
+                    }
+                }
+                if !swarm.is_connected(&peer_id) {
+                    if let Err(e) = swarm.dial(self.relay.relayed_addr(peer_id)) {
+                        warn!(%peer_id, error = %e, "Failed to redial");
+                    }
+                }
+            }
+            soak.report_due(
+                Instant::now(),
+                &self.connection_paths,
+                &self.holepunch_stats,
+            );
+        }
+    }
+
+    /// Retries lookups and dials that are due and closes idle and superseded relayed connections.
+    fn connections_due(&mut self, swarm: &mut Node) {
+        for remote_lookup in self
+            .dht_lookups
+            .iter_mut()
+            .filter(|l| l.retry_due(Instant::now()))
+        {
+            if let Some(kademlia) = swarm.behaviour_mut().kademlia.as_mut() {
+                remote_lookup.start(kademlia);
+            }
+        }
+        // The relay connection backs our reservation or circuits, it's never idle.
+        let protected =
+            |peer_id: &PeerId| self.relay.is_relay(peer_id) || self.explicit.contains(peer_id);
+        for peer_id in self
+            .idle
+            .as_mut()
+            .map(|idle| idle.due(Instant::now(), protected))
+            .unwrap_or_default()
+        {
+            info!(%peer_id, "Closing idle connection");
+            let _ = swarm.disconnect_peer_id(peer_id);
+        }
+        for peer_id in self.remotes.direct_timed_out(Instant::now()) {
+            say!(
+                "No known address of {peer_id} answered within {}s, dialing through the relay",
+                self.opts.direct_dial_timeout
+            );
+            if let Err(e) = swarm.dial(self.relay.relayed_addr(peer_id)) {
+                self.remotes.on_dial_failure(&peer_id, &e.to_string());
+            }
+        }
+        for peer_id in self.remotes.due(Instant::now()) {
+            info!("Redialing {peer_id} through the relay");
+            if let Err(e) = swarm.dial(self.relay.relayed_addr(peer_id)) {
+                self.remotes.on_dial_failure(&peer_id, &e.to_string());
+            }
+        }
+        for addr in self.bootstrap_peers.due(Instant::now()) {
+            if let Err(e) = swarm.dial(addr.clone()) {
+                say!("Failed to dial bootstrap peer {addr}: {e}");
+                if let Some(peer_id) = bootstrap_peers::peer_id_of(&addr) {
+                    self.bootstrap_peers.on_dial_failure(&peer_id);
+                }
+            }
+        }
+        for (peer_id, relayed, direct_addr) in self
+            .relayed_connections
+            .as_mut()
+            .map(|r| r.due(Instant::now()))
+            .unwrap_or_default()
+        {
+            for connection_id in relayed {
+                swarm.close_connection(connection_id);
+            }
+            say!("Closed relayed connection to {peer_id}, traffic now flows over {direct_addr}");
+        }
+        if self.relay.redial_due(Instant::now()) {
+            info!("Redialing the relay to renew the reservation");
+            if let Err(e) = swarm.dial(self.relay.address().clone()) {
+                warn!(error = %e, "Failed to redial the relay");
+                self.relay.on_redial_failed(Instant::now());
+            }
+        }
+        for peer_id in self.holepunch.due(Instant::now()) {
+            let _span = logging::span(Phase::HolePunch, Some(peer_id)).entered();
+            info!("Redialing through the relay for another hole punch attempt");
+            if let Err(e) = swarm.dial(self.relay.relayed_addr(peer_id)) {
+                warn!(error = %e, "Failed to redial");
+                if self.holepunch.on_failed(peer_id) {
+                    if let Some(one_shot) = self.one_shot.as_mut() {
+                        one_shot.fail(Outcome::HolePunchFailed(e.to_string()));
+                    }
+                    if !swarm.is_connected(&peer_id) {
+                        for text in self.pending_dms.drop_peer(&peer_id) {
+                            say!("delivery failed: DM '{text}' to {peer_id}: the peer can't be redialed");
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends DMs again, announces our presence and expires what waited too long.
+    fn messages_due(&mut self, swarm: &mut Node) {
+        for (peer_id, message, text) in self.pending_dms.due(Instant::now()) {
+            if !swarm.is_connected(&peer_id) {
+                swarm
+                    .behaviour_mut()
+                    .dm
+                    .add_address(&peer_id, self.relay.relayed_addr(peer_id));
+            }
+            let id = swarm
+                .behaviour_mut()
+                .dm
+                .send_request(&peer_id, message.clone());
+            self.pending_dms.on_sent(id, peer_id, message, text);
+        }
+        for (peer_id, dropped) in self.resend.expire(Instant::now()) {
+            say!(
+                "Gave up waiting for {peer_id} to come back, dropped {}",
+                dropped.describe(|t| self.topics.name(t))
+            );
+        }
+        if self.roster.announce_due(Instant::now()) {
+            let presence = self
+                .sequencer
+                .wrap(Kind::Presence, presence::HERE)
+                .signed(&self.local_key)
+                .encode(self.opts.wire_format);
+            let hashes = self.topics.hashes().cloned().collect::<Vec<_>>();
+            for topic in hashes {
+                if !self
+                    .presence_budget
+                    .as_mut()
+                    .map_or(true, |budget| budget.try_take(Instant::now()))
+                {
+                    debug!("Skipping the presence announcement on {topic}, over the publish rate");
+                    continue;
+                }
+                // Failing for lack of peers is fine, there's nobody to tell.
+                let _ = swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .publish(topic, presence.clone());
+            }
+        }
+        if let Some(fallback) = self.fallback.as_mut() {
+            let gossipsub = &swarm.behaviour().gossipsub;
+            let changes = fallback.poll(
+                Instant::now(),
+                self.topics.hashes(),
+                |topic| gossipsub.mesh_peers(topic).count(),
+                |peer_id, topic| {
+                    gossipsub
+                        .all_peers()
+                        .any(|(p, t)| p == peer_id && t.contains(&topic))
+                },
+            );
+            for change in changes {
+                match change {
+                    fallback::Change::Opened { peer_id, topic } => say!(
+                        "No gossipsub mesh on '{}' after {}s connected to {peer_id}, sending messages to it directly as well",
+                        self.topics.name(&topic),
+                        self.opts.fallback_after
+                    ),
+                    fallback::Change::Closed { peer_id, topic } => say!(
+                        "Closing the direct channel to {peer_id} on '{}', the gossipsub mesh formed or it left the topic",
+                        self.topics.name(&topic)
+                    ),
+                }
+            }
+        }
+        for nick in self.roster.expire(Instant::now()) {
+            say!("{nick} is gone");
+        }
+        if let Some(score_watch) = &mut self.score_watch {
+            score_watch.poll(&swarm.behaviour().gossipsub);
+        }
+        for (msg_id, missing) in self.reassembly.expire(Instant::now()) {
+            say!("Discarded incomplete message {msg_id}, {missing} chunks did not arrive in time");
+        }
+    }
+
+    /// Saves what is kept across runs and updates the UI, health, metrics and stats log.
+    fn bookkeeping_due(&mut self, swarm: &mut Node) {
+        if let Some(log) = self.message_log.as_mut() {
+            log.sync_due(Instant::now());
+        }
+        if let Some(ui) = &self.ui {
+            let peers = peer_lines(
+                swarm.connected_peers(),
+                &self.connection_paths,
+                &self.rtt_stats,
+                &self.explicit,
+                &self.peer_infos,
+            );
+            ui.update(
+                peers,
+                format!(
+                    "relay: {} | {} | {}",
+                    self.relay.status(),
+                    self.holepunch_stats,
+                    self.rtt_stats
+                ),
+            );
+        }
+        self.sequences.save_due(Instant::now());
+        self.address_book.save_due(Instant::now());
+        self.seen.save_due(Instant::now());
+        self.reputation.save_due(Instant::now());
+        for peer_id in self.reputation.expired(reputation::now_secs()) {
+            // A peer banned by hand as well stays banned.
+            if !self.bans.contains(&peer_id) {
+                info!("The reputation ban of {peer_id} ended");
+                swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .remove_blacklisted_peer(&peer_id);
+                swarm.behaviour_mut().blocked.unblock_peer(peer_id);
+            }
+        }
+        self.health.on_tick(readiness_checks(
+            &self.opts.mode,
+            self.relay.status(),
+            self.relay
+                .peer_id()
+                .map_or(false, |relay| swarm.is_connected(&relay)),
+            self.relay.has_circuit()
+                && self
+                    .remotes
+                    .peer_ids()
+                    .any(|remote| swarm.is_connected(&remote)),
+            swarm.behaviour().gossipsub.all_mesh_peers().count(),
+        ));
+        self.metrics.on_bandwidth(&self.bandwidth);
+        self.metrics.on_rtt(&self.rtt_stats, &self.connection_paths);
+        if self.next_stats_report <= Instant::now() {
+            self.next_stats_report = Instant::now() + STATS_INTERVAL;
+            if self.holepunch_stats.take_changed() {
+                info!("{}", self.holepunch_stats);
+                for line in self.holepunch_stats.peer_lines() {
+                    info!("{line}");
+                }
+            }
+            if self.validation_stats.take_changed() {
+                info!("{}", self.validation_stats);
+            }
+        }
+    }
+
+    fn on_transfer_output(&mut self, swarm: &mut Node, output: TransferOutput) {
+        match output {
+            TransferOutput::Request(peer_id, request) => {
+                let id = swarm
+                    .behaviour_mut()
+                    .transfer
+                    .send_request(&peer_id, request.clone());
+                self.transfers.on_sent(id, &request);
+            }
+            TransferOutput::Response(peer_id, channel, response) => {
+                if swarm
+                    .behaviour_mut()
+                    .transfer
+                    .send_response(channel, response)
+                    .is_err()
+                {
+                    warn!(
+                        "Failed to answer file transfer request of {peer_id}, the stream is gone"
+                    );
+                }
+            }
+        }
+    }
+
+    /// Handles an event of the swarm, going on with bootstrapping if it completed a phase.
+    fn on_swarm_event(
+        &mut self,
+        swarm: &mut Node,
+        event: node::Event,
+    ) -> Result<(), Box<dyn Error>> {
+        let (phase, peer_id) = match &event {
+            SwarmEvent::Behaviour(BehaviourEvent::RelayClient(event)) => {
+                (Phase::Reservation, Some(logging::relay_peer(event)))
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Dcutr(event)) => {
+                (Phase::HolePunch, Some(logging::dcutr_peer(event)))
+            }
+            SwarmEvent::ConnectionEstablished { peer_id, .. }
+            | SwarmEvent::ConnectionClosed { peer_id, .. } => (Phase::SteadyState, Some(*peer_id)),
+            SwarmEvent::OutgoingConnectionError { peer_id, .. } => (Phase::SteadyState, *peer_id),
+            SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Received {
+                peer_id,
+                ..
+            })) => (Phase::SteadyState, Some(*peer_id)),
+            _ => (Phase::SteadyState, None),
+        };
+        let phase = match phase {
+            Phase::SteadyState if !self.bootstrap.is_running() => Phase::Bootstrap,
+            phase => phase,
+        };
+        let _span = logging::span(phase, peer_id).entered();
+        self.metrics.on_event(&event);
+        if let Some(idle) = self.idle.as_mut() {
+            idle.record(&event, Instant::now());
+        }
+        if self.event_log.is_some() || self.journal.is_some() {
+            let node_event = match &event {
+                SwarmEvent::NewListenAddr { address, .. } => Some(NodeEvent::listen_addr(address)),
+                SwarmEvent::ConnectionEstablished {
+                    peer_id, endpoint, ..
+                } => Some(NodeEvent::connection_established(peer_id, endpoint)),
+                SwarmEvent::ConnectionClosed {
+                    peer_id,
+                    endpoint,
+                    cause,
+                    ..
+                } => Some(NodeEvent::connection_closed(
+                    peer_id,
+                    endpoint,
+                    cause.as_ref().map(|e| format!("{e:?}")),
+                )),
+                SwarmEvent::Behaviour(BehaviourEvent::RelayClient(event)) => {
+                    NodeEvent::from_relay(event)
+                }
+                SwarmEvent::Behaviour(BehaviourEvent::Dcutr(event)) => NodeEvent::from_dcutr(event),
+                SwarmEvent::Behaviour(BehaviourEvent::Identify(event)) => {
+                    NodeEvent::from_identify(event)
+                }
+                _ => None,
+            };
+            if let Some(node_event) = node_event {
+                if let Some(event_log) = self.event_log.as_mut() {
+                    event_log.write(&node_event);
+                }
+                if let Some(journal) = self.journal.as_mut() {
+                    journal.write(&node_event);
+                }
+            }
+        }
+        let mut step = None;
+        if !self.bootstrap.is_running() {
+            let progress = Progress::of(&event);
+            match progress
+                .map(|progress| self.bootstrap.on_progress(progress))
+                .transpose()
+            {
+                Ok(next) => step = next.flatten(),
+                Err(error) => match self.one_shot.as_mut() {
+                    Some(one_shot) => one_shot.fail(Outcome::RelayFailed(error)),
+                    None => return Err(error.into()),
+                },
+            }
+        }
+        match event {
+            SwarmEvent::NewListenAddr { address, .. } => {
+                info!(%address, "Listening");
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::RelayClient(event)) => {
+                self.on_relay_event(swarm, event)
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Dcutr(event)) => self.on_dcutr_event(event),
+            SwarmEvent::Behaviour(BehaviourEvent::Identify(event)) => {
+                self.on_identify_event(swarm, event)
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Kademlia(event)) => {
+                self.on_kademlia_event(swarm, event)
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Autonat(event)) => self.on_autonat_event(event),
+            SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(event)) => {
+                self.on_gossipsub_event(swarm, event)
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::History(event)) => {
+                self.on_history_event(swarm, event)
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Dm(event)) => self.on_dm_event(swarm, event),
+            SwarmEvent::Behaviour(BehaviourEvent::Transfer(event)) => self.on_transfer_event(event),
+            SwarmEvent::Behaviour(BehaviourEvent::Echo(event)) => self.on_echo_event(swarm, event),
+            SwarmEvent::Behaviour(BehaviourEvent::Fallback(event)) => {
+                self.on_fallback_event(swarm, event)
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Ping(event)) => self.on_ping_event(event),
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                connection_id,
+                endpoint,
+                ..
+            } => self.on_connection_established(swarm, peer_id, connection_id, endpoint),
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                connection_id,
+                num_established,
+                ..
+            } => self.on_connection_closed(peer_id, connection_id, num_established),
+            SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
+                self.on_dial_error(swarm, peer_id, error)
+            }
+            SwarmEvent::IncomingConnectionError {
+                send_back_addr,
+                error,
+                ..
+            } => self.on_listen_error(send_back_addr, error),
+            _ => {}
+        }
+        if let Some(step) = step {
+            self.on_step(swarm, step)?;
+        }
+        Ok(())
+    }
+
+    fn on_relay_event(&mut self, swarm: &mut Node, event: relay::client::Event) {
+        match event {
+            relay::client::Event::ReservationReqAccepted { renewal, .. } => {
+                assert!(self.opts.mode == Mode::Listen);
+                info!("Relay accepted our reservation request");
+                self.relay.on_reservation_accepted();
+                if !renewal {
+                    let ticket = Ticket {
+                        peer_id: self.local_peer_id,
+                        relays: vec![self.relay.address().clone()],
+                        topic: self.opts.topics.first().cloned(),
+                    };
+                    say!("share this ticket: {ticket}");
+                    if self.opts.qr
+                        && self.opts.output == Output::Text
+                        && self.ui.is_none()
+                        && io::stdout().is_terminal()
+                    {
+                        match qr::render(&ticket.to_string(), qr::unicode_supported()) {
+                            Ok(code) => say!("{code}"),
+                            Err(e) => warn!("{e}"),
+                        }
+                    }
+                }
+                if self.opts.kademlia {
+                    // Advertise the relayed address so peers resolving us through the DHT
+                    // learn how to reach us.
+                    let relayed_addr = self
+                        .relay
+                        .circuit_addr()
+                        .with(Protocol::P2p(self.local_peer_id.into()));
+                    swarm.add_external_address(relayed_addr, AddressScore::Infinite);
+                }
+            }
+            relay::client::Event::InboundCircuitEstablished { src_peer_id, limit } => {
+                info!(?limit, "Inbound circuit established");
+                if let Some(limit) = limit {
+                    self.holepunch.on_circuit_limit(
+                        src_peer_id,
+                        CircuitLimit {
+                            duration: limit.duration(),
+                            data_in_bytes: limit.data_in_bytes(),
+                        },
+                    );
+                }
+            }
+            relay::client::Event::OutboundCircuitEstablished { limit, .. } => {
+                info!(?limit, "Outbound circuit established");
+                self.relay.on_circuit_established();
+                // The event doesn't say which circuit it is, so the limit is only known
+                // for certain with a single remote.
+                if let (Some(limit), [remote_peer_id]) = (limit, self.remote_peer_ids.as_slice()) {
+                    self.holepunch.on_circuit_limit(
+                        *remote_peer_id,
+                        CircuitLimit {
+                            duration: limit.duration(),
+                            data_in_bytes: limit.data_in_bytes(),
+                        },
+                    );
+                }
+            }
+            event => debug!(?event),
+        }
+    }
+
+    fn on_dcutr_event(&mut self, event: libp2p::dcutr::Event) {
+        debug!(?event, "Hole punch event");
+        match event {
+            libp2p::dcutr::Event::InitiatedDirectConnectionUpgrade { remote_peer_id, .. }
+            | libp2p::dcutr::Event::RemoteInitiatedDirectConnectionUpgrade {
+                remote_peer_id, ..
+            } => {
+                self.holepunch_stats.on_attempt(remote_peer_id);
+            }
+            libp2p::dcutr::Event::DirectConnectionUpgradeSucceeded { remote_peer_id } => {
+                let time_to_direct = self.holepunch_stats.on_success(remote_peer_id);
+                self.metrics.on_hole_punch_succeeded(time_to_direct);
+                self.holepunch.on_succeeded(remote_peer_id);
+                if let Some(probe) = self.latency_probe.as_mut() {
+                    probe.on_upgraded(remote_peer_id, Instant::now());
+                }
+                if let Some(relayed_connections) = self.relayed_connections.as_mut() {
+                    relayed_connections.on_upgraded(remote_peer_id);
+                }
+                if let Some(one_shot) = self.one_shot.as_mut() {
+                    one_shot.on_upgrade_succeeded(remote_peer_id);
+                }
+            }
+            libp2p::dcutr::Event::DirectConnectionUpgradeFailed {
+                remote_peer_id,
+                error,
+            } => {
+                let kind = self
+                    .holepunch_stats
+                    .on_failure(remote_peer_id, &format!("{error:?}"));
+                self.metrics.on_hole_punch_failed(&kind);
+                if self.holepunch.on_failed(remote_peer_id) {
+                    say!("{}", self.holepunch_stats);
+                    if let Some(one_shot) = self.one_shot.as_mut() {
+                        one_shot.fail(Outcome::HolePunchFailed(format!("{error:?}")));
+                    }
+                }
+            }
+        }
+    }
+
+    fn on_identify_event(&mut self, swarm: &mut Node, event: identify::Event) {
+        match event {
+            identify::Event::Received { peer_id, info } => {
+                info!(
+                    agent_version = %info.agent_version,
+                    observed_addr = %info.observed_addr,
+                    "Received identify info"
+                );
+                debug!(?info);
+                // Only peers speaking the protocol get a direct channel, which leaves out
+                // the relay and bootstrap peers.
+                if let Some(fallback) = self
+                    .fallback
+                    .as_mut()
+                    .filter(|_| info.protocols.iter().any(|p| p == fallback::PROTOCOL))
+                {
+                    fallback.on_connected(peer_id, Instant::now());
+                }
+                self.peer_infos.on_received(peer_id, info.clone());
+                if self.external_addrs.confirm(&info.observed_addr) {
+                    say!("{}", self.external_addrs);
+                }
+                self.address_book
+                    .on_listen_addrs(peer_id, &info.listen_addrs);
+                if let Some(kademlia) = swarm.behaviour_mut().kademlia.as_mut() {
+                    if info.protocols.iter().any(|p| p == lookup::KAD_PROTOCOL) {
+                        for addr in info.listen_addrs {
+                            kademlia.add_address(&peer_id, addr);
+                        }
+                    }
+                }
+            }
+            event => debug!(?event),
+        }
+    }
+
+    fn on_kademlia_event(&mut self, swarm: &mut Node, event: kad::KademliaEvent) {
+        match event {
+            kad::KademliaEvent::OutboundQueryProgressed {
+                id,
+                result: kad::QueryResult::GetClosestPeers(result),
+                ..
+            } => {
+                for remote_lookup in self.dht_lookups.iter_mut() {
+                    match remote_lookup.on_closest_peers(id, result.clone()) {
+                        LookupStep::Pending => {}
+                        LookupStep::Dial => {
+                            let target = remote_lookup.target();
+                            if !swarm.is_connected(&target) {
+                                if let Err(e) = swarm.dial(target) {
+                                    warn!("Failed to dial {target}: {e}");
+                                }
+                            }
+                        }
+                        LookupStep::GiveUp => {
+                            let target = remote_lookup.target();
+                            say!("Falling back to dialing {target} through our relay");
+                            if let Err(e) = swarm.dial(self.relay.relayed_addr(target)) {
+                                self.remotes.on_dial_failure(&target, &e.to_string());
+                            }
+                        }
+                    }
+                }
+            }
+            event => debug!(?event),
+        }
+    }
+
+    fn on_autonat_event(&mut self, event: autonat::Event) {
+        match event {
+            autonat::Event::StatusChanged { old, new } => {
+                say!("NAT status changed from {old:?} to {new:?}");
+                if let NatStatus::Public(addr) = &new {
+                    if self.external_addrs.confirm(addr) {
+                        say!("{}", self.external_addrs);
+                    }
+                }
+            }
+            event => debug!(?event),
+        }
+    }
+
+    fn on_gossipsub_event(&mut self, swarm: &mut Node, event: gossipsub::Event) {
+        match event {
+            gossipsub::Event::Message {
+                propagation_source,
+                message_id: id,
+                message,
+            } => self.validate(
+                swarm,
+                Candidate {
+                    id,
+                    propagation_source,
+                    message,
+                },
+            ),
+            gossipsub::Event::Unsubscribed { peer_id, topic } => {
+                info!(
+                    "{peer_id} unsubscribed from topic {}",
+                    self.topics.name(&topic)
+                );
+                if self.subscribers.on_unsubscribed(&peer_id, &topic)
+                    && self.topics.contains(&topic)
+                {
+                    say!("peer {peer_id} left topic {}", self.topics.name(&topic));
+                }
+            }
+            gossipsub::Event::Subscribed { peer_id, topic } => {
+                info!("{peer_id} subscribed to topic {}", self.topics.name(&topic));
+                if self.subscribers.on_subscribed(peer_id, topic.clone())
+                    && self.topics.contains(&topic)
+                {
+                    say!("peer {peer_id} joined topic {}", self.topics.name(&topic));
+                }
+                if let Some(bench) = self
+                    .bench
+                    .as_mut()
+                    .filter(|_| topic == Bench::topic().hash())
+                {
+                    bench.on_subscribed(peer_id, Instant::now());
+                }
+                flush_outbox(
+                    &mut self.outbox,
+                    &mut swarm.behaviour_mut().gossipsub,
+                    &self.topics,
+                    self.opts.max_decompressed_size,
+                );
+                let now = envelope::unix_millis();
+                let replays = self
+                    .resend
+                    .on_subscribed(&peer_id, &topic)
+                    .into_iter()
+                    .filter(|(envelope, _)| !envelope.is_expired(now, Duration::ZERO))
+                    .collect::<Vec<_>>();
+                if !replays.is_empty() {
+                    say!(
+                        "{peer_id} is back, sending it {} missed messages again",
+                        replays.len()
+                    );
+                }
+                for (mut envelope, _) in replays {
+                    envelope.replayed = true;
+                    let data = envelope
+                        .signed(&self.local_key)
+                        .encode(self.opts.wire_format);
+                    self.traffic.on_sent(data.len());
+                    if let Err(error) = publish_chunked(
+                        &mut self.outbox,
+                        &mut swarm.behaviour_mut().gossipsub,
+                        &self.topics,
+                        topic.clone(),
+                        data,
+                        self.opts.max_message_size,
+                        self.opts.max_decompressed_size,
+                    ) {
+                        self.metrics.on_publish_error(&self.topics.name(&topic));
+                        if let Some(journal) = self.journal.as_mut() {
+                            journal.write(&NodeEvent::PublishError {
+                                topic: self.topics.name(&topic),
+                                error,
+                            });
+                        }
+                    }
+                }
+                if self.topics.contains(&topic)
+                    && self.opts.history_size > 0
+                    && self.history.should_request(&topic)
+                {
+                    let request = self.history.request(&topic);
+                    let request_id = swarm
+                        .behaviour_mut()
+                        .history
+                        .send_request(&peer_id, request);
+                    self.history.on_request_sent(request_id, topic);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn on_history_event(
+        &mut self,
+        swarm: &mut Node,
+        event: request_response::Event<HistoryRequest, HistoryResponse>,
+    ) {
+        match event {
+            request_response::Event::Message { peer, message } => match message {
+                request_response::Message::Request {
+                    request, channel, ..
+                } => {
+                    let response = self.history.respond(&request);
+                    info!(
+                        "Sending {} history entries to {peer}",
+                        response.entries.len()
+                    );
+                    if swarm
+                        .behaviour_mut()
+                        .history
+                        .send_response(channel, response)
+                        .is_err()
+                    {
+                        warn!("Failed to send history to {peer}, the stream is gone");
+                    }
+                }
+                request_response::Message::Response {
+                    request_id,
+                    response,
+                } => {
+                    if let Some((topic, entries)) = self.history.merge(&request_id, response) {
+                        for data in entries {
+                            let keys = self.topic_keys.of(&self.topics.name(&topic));
+                            match envelope::render(
+                                &data,
+                                None,
+                                self.opts.max_decompressed_size,
+                                &topic,
+                                keys,
+                            ) {
+                                Ok(text) => {
+                                    say!("[history] [{}] {text}", self.topics.name(&topic));
+                                    if let Some(log) = self.message_log.as_mut() {
+                                        log.append(&LogRecord::new(
+                                            &topic,
+                                            self.topics.name(&topic),
+                                            String::new(),
+                                            None,
+                                            &data,
+                                            self.opts.max_decompressed_size,
+                                        ));
+                                    }
+                                }
+                                Err(e) => warn!("Dropping history entry from {peer}: {e}"),
+                            }
+                        }
+                    }
+                }
+            },
+            request_response::Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                self.history.on_request_failed(&request_id);
+                info!("History request to {peer} failed: {error}");
+            }
+            _ => {}
+        }
+    }
+
+    fn on_dm_event(
+        &mut self,
+        swarm: &mut Node,
+        event: request_response::Event<DirectMessage, DmAck>,
+    ) {
+        match event {
+            request_response::Event::Message { peer, message } => match message {
+                request_response::Message::Request {
+                    request, channel, ..
+                } => {
+                    let sender = request
+                        .from_nick
+                        .clone()
+                        .unwrap_or_else(|| peer.to_string());
+                    let path = self
+                        .connection_paths
+                        .path(&peer)
+                        .map(|path| format!(" {path}"))
+                        .unwrap_or_default();
+                    // DMs are always sealed, a plaintext one would pass for an encrypted one.
+                    let text = match request.open(&self.e2e_key, peer, self.local_peer_id) {
+                        Ok(body) => format!("[e2e] {body}"),
+                        Err(e) => {
+                            // Not acked, the sender learns of it through its retries failing.
+                            say!("Dropped a DM from {sender} that could not be opened: {e}");
+                            return;
+                        }
+                    };
+                    if self.seen_dms.first_time(peer, &request.id) {
+                        if let Some(sealed) = &request.sealed {
+                            if let Err(e) = self.replay_guard.check(
+                                peer,
+                                sealed,
+                                request.sent_at,
+                                envelope::unix_millis(),
+                            ) {
+                                say!("Rejected a replayed DM from {sender}: {e}");
+                                return;
+                            }
+                        }
+                        let replayed = if request.replayed { "[replayed] " } else { "" };
+                        say!("[DM from {sender}] {replayed}{text}{path}");
+                    }
+                    // Retransmissions are acked again, the previous ack may have been lost.
+                    let ack = DmAck {
+                        received_at: envelope::unix_millis(),
+                    };
+                    if swarm
+                        .behaviour_mut()
+                        .dm
+                        .send_response(channel, ack)
+                        .is_err()
+                    {
+                        warn!("Failed to acknowledge DM from {peer}, the stream is gone");
+                    }
+                }
+                request_response::Message::Response { request_id, .. } => {
+                    if let Some((peer_id, text)) = self.pending_dms.on_ack(&request_id) {
+                        say!("DM to {peer_id} delivered: '{text}'");
+                    }
+                }
+            },
+            request_response::Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                let reason = match error {
+                    request_response::OutboundFailure::DialFailure => {
+                        "could not reach it directly or through the relay"
+                    }
+                    request_response::OutboundFailure::Timeout => "no ack in time",
+                    request_response::OutboundFailure::ConnectionClosed => {
+                        "the peer closed the connection"
+                    }
+                    request_response::OutboundFailure::UnsupportedProtocols => {
+                        "peer does not support encrypted DMs"
+                    }
+                };
+                match self.pending_dms.on_failure(&request_id) {
+                    Some(DmFailure::Retrying { attempt }) => {
+                        info!(%peer, attempt, reason, "DM failed, sending it again");
+                    }
+                    Some(DmFailure::GaveUp { peer_id, text }) if !swarm.is_connected(&peer_id) => {
+                        say!("DM '{text}' to {peer_id} failed ({reason}), sending it again once it connects");
+                        if let Some(dropped) = self.resend.push(peer_id, Queued::Dm { text }) {
+                            say!(
+                                "Resend buffer for {peer_id} is full, dropped {}",
+                                dropped.describe(|t| self.topics.name(t))
+                            );
+                        }
+                    }
+                    Some(DmFailure::GaveUp { peer_id, text }) => {
+                        say!("delivery failed: DM '{text}' to {peer_id}: {reason}")
+                    }
+                    None => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn on_transfer_event(&mut self, event: request_response::Event<FileRequest, FileResponse>) {
+        match event {
+            request_response::Event::Message { peer, message } => match message {
+                request_response::Message::Request {
+                    request, channel, ..
+                } => {
+                    self.transfers.on_request(peer, request, channel);
+                }
+                request_response::Message::Response {
+                    request_id,
+                    response,
+                } => {
+                    self.transfers.on_response(request_id, response);
+                }
+            },
+            request_response::Event::OutboundFailure {
+                request_id, error, ..
+            } => {
+                self.transfers.on_failure(request_id, &error);
+            }
+            request_response::Event::InboundFailure { peer, error, .. } => {
+                info!("File transfer request of {peer} failed: {error}");
+            }
+            _ => {}
+        }
+    }
+
+    fn on_echo_event(&mut self, swarm: &mut Node, event: request_response::Event<Echo, Echo>) {
+        match event {
+            request_response::Event::Message { peer, message } => match message {
+                request_response::Message::Request {
+                    request, channel, ..
+                } => {
+                    if swarm
+                        .behaviour_mut()
+                        .echo
+                        .send_response(channel, request)
+                        .is_err()
+                    {
+                        debug!(%peer, "Failed to answer an echo, the stream is gone");
+                    }
+                }
+                request_response::Message::Response { request_id, .. } => {
+                    if let Some(probe) = self.latency_probe.as_mut() {
+                        let echo = probe.on_response(&request_id, Instant::now());
+                        send_echo(&mut swarm.behaviour_mut().echo, probe, echo);
+                    }
+                }
+            },
+            request_response::Event::OutboundFailure {
+                request_id, error, ..
+            } => {
+                debug!(%error, "Echo failed");
+                if let Some(probe) = self.latency_probe.as_mut() {
+                    let echo = probe.on_failure(&request_id);
+                    send_echo(&mut swarm.behaviour_mut().echo, probe, echo);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn on_fallback_event(
+        &mut self,
+        swarm: &mut Node,
+        event: request_response::Event<FallbackMessage, ()>,
+    ) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Request {
+                        request, channel, ..
+                    },
+            } => {
+                // Gossipsub drops banned peers and the pipeline those not on the allow list,
+                // the direct channel turns both away before acking.
+                if self.bans.contains(&peer)
+                    || !self
+                        .direct_allow_list
+                        .as_ref()
+                        .map_or(true, |a| a.contains(&peer))
+                {
+                    debug!(%peer, "Ignoring a direct channel message from a banned peer or one not on the allow list");
+                    return;
+                }
+                if let Some(fallback) = swarm.behaviour_mut().fallback.as_mut() {
+                    let _ = fallback.send_response(channel, ());
+                }
+                let topic = self.topics.hash(&request.topic);
+                if !self.topics.contains(&topic) {
+                    debug!(%peer, topic = %request.topic, "Ignoring a direct channel message for a topic we're not in");
+                    return;
+                }
+                let data = match request.data() {
+                    Ok(data) => data,
+                    Err(e) => {
+                        warn!("Dropped a direct channel message from {peer}: {e}");
+                        on_offence(
+                            swarm.behaviour_mut(),
+                            &mut self.reputation,
+                            peer,
+                            "body",
+                            &e,
+                        );
+                        return;
+                    }
+                };
+                // Only our peer's own messages are sent directly, it's their source.
+                let message = gossipsub::Message {
+                    source: Some(peer),
+                    data,
+                    sequence_number: None,
+                    topic,
+                };
+                let id = self.opts.message_id.id_fn()(&message);
+                self.direct_candidates.insert(id.clone());
+                let candidate = Candidate {
+                    id,
+                    propagation_source: peer,
+                    message,
+                };
+                self.validate(swarm, candidate);
+            }
+            request_response::Event::OutboundFailure { peer, error, .. } => {
+                if let request_response::OutboundFailure::UnsupportedProtocols = error {
+                    for topic in self
+                        .fallback
+                        .as_mut()
+                        .map(|f| f.on_unsupported(peer))
+                        .unwrap_or_default()
+                    {
+                        info!(
+                            "{peer} has no direct channel, closing it on '{}'",
+                            self.topics.name(&topic)
+                        );
+                    }
+                } else {
+                    debug!(%peer, %error, "Direct channel message failed");
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn on_ping_event(&mut self, PingEvent { connection, event }: PingEvent) {
+        match &event.result {
+            Ok(ping::Success::Ping { rtt }) => {
+                self.rtt_stats.record(event.peer, connection, *rtt);
+                let path = self
+                    .connection_paths
+                    .connections(&event.peer)
+                    .into_iter()
+                    .find(|(id, _)| *id == connection);
+                if let Some((_, path)) = path {
+                    if let Some(comparison) =
+                        self.path_comparisons
+                            .on_ping(event.peer, connection, path, &self.rtt_stats)
+                    {
+                        say!("{comparison}");
+                    }
+                }
+                self.ping_failures.remove(&connection);
+            }
+            Ok(ping::Success::Pong) => {}
+            Err(ping::Failure::Unsupported) => {
+                debug!(peer = %event.peer, "Peer doesn't support ping");
+            }
+            Err(failure) => {
+                let failures = self.ping_failures.entry(connection).or_default();
+                *failures += 1;
+                warn!(peer = %event.peer, ?connection, failures = *failures, "Ping failed: {failure}");
+                if !self.opts.no_ping_disconnect && *failures >= self.opts.ping_max_failures.get() {
+                    // The ping handler closes it, `ConnectionClosed` follows.
+                    info!(peer = %event.peer, ?connection, "Ping gave up on the connection after {failures} failed pings");
+                }
+            }
+        }
+        debug!(?event)
+    }
+
+    fn on_connection_established(
+        &mut self,
+        swarm: &mut Node,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        endpoint: ConnectedPoint,
+    ) {
+        info!(
+            address = %endpoint.get_remote_address(),
+            relayed = endpoint.is_relayed(),
+            "Connection established"
+        );
+        self.connection_paths
+            .on_established(peer_id, connection_id, &endpoint);
+        if let Some(relayed_connections) = self.relayed_connections.as_mut() {
+            relayed_connections.on_established(peer_id, connection_id, &endpoint);
+        }
+        if let Some(probe) = self.latency_probe.as_mut() {
+            let direct = !endpoint.is_relayed();
+            let echo = probe.on_connected(peer_id, direct);
+            send_echo(&mut swarm.behaviour_mut().echo, probe, echo);
+        }
+        if let Some(soak) = self.soak.as_mut() {
+            soak.on_connected(&peer_id);
+        }
+        for text in self.resend.on_connected(&peer_id) {
+            match DirectMessage::seal(
+                &self.e2e_key,
+                self.local_peer_id,
+                peer_id,
+                self.opts.nick.clone(),
+                &text,
+                true,
+            ) {
+                Ok(request) => {
+                    let id = swarm
+                        .behaviour_mut()
+                        .dm
+                        .send_request(&peer_id, request.clone());
+                    self.pending_dms.on_sent(id, peer_id, request, text);
+                }
+                Err(e) => say!("Can't encrypt a DM to {peer_id}: {e}"),
+            }
+        }
+        // Inbound circuits only show the relay in the local address.
+        if !endpoint.is_relayed() {
+            // Only dialed addresses are worth keeping, those of inbound
+            // connections are ephemeral ports.
+            if endpoint.is_dialer() {
+                self.address_book
+                    .on_success(peer_id, endpoint.get_remote_address());
+            }
+            self.holepunch.on_direct_connection(peer_id);
+            if let Some(one_shot) = self.one_shot.as_mut() {
+                one_shot.on_direct_connection(peer_id, endpoint.get_remote_address());
+            }
+        }
+        if let Some(remote_lookup) = self.dht_lookups.iter_mut().find(|l| l.target() == peer_id) {
+            remote_lookup.on_connected();
+        }
+        if self.remotes.is_dialing_direct(&peer_id) && !endpoint.is_relayed() {
+            say!(
+                "Reached {peer_id} directly at {}, no relay needed",
+                endpoint.get_remote_address()
+            );
+        }
+        self.remotes.on_connected(&peer_id);
+        if self.relay.on_connected(&peer_id) {
+            info!("Reconnected to the relay, renewing the reservation");
+            if let Err(e) = swarm.listen_on(self.relay.circuit_addr()) {
+                warn!(error = %e, "Failed to renew the reservation");
+            }
+        }
+        if self
+            .bootstrap_peers
+            .addresses()
+            .any(|(id, _)| id == peer_id)
+        {
+            self.bootstrap_peers.on_connected(&peer_id);
+            if let Some(kademlia) = swarm.behaviour_mut().kademlia.as_mut() {
+                if let Err(e) = kademlia.bootstrap() {
+                    warn!("Failed to bootstrap Kademlia: {e:?}");
+                }
+            }
+        }
+    }
+
+    fn on_connection_closed(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        num_established: u32,
+    ) {
+        self.metrics
+            .on_connection_closed(&peer_id, connection_id, &self.connection_paths);
+        self.connection_paths.on_closed(peer_id, connection_id);
+        self.rtt_stats.on_closed(connection_id);
+        self.ping_failures.remove(&connection_id);
+        if let Some(relayed_connections) = self.relayed_connections.as_mut() {
+            relayed_connections.on_closed(peer_id, connection_id);
+        }
+        if num_established == 0 {
+            if self.relay.on_disconnected(&peer_id, Instant::now()) {
+                warn!("Lost the connection to the relay, the reservation is gone");
+            }
+            self.resend.on_disconnected(peer_id);
+            self.peer_infos.on_disconnected(&peer_id);
+            for topic in self.subscribers.on_disconnected(&peer_id) {
+                if self.topics.contains(&topic) {
+                    say!(
+                        "peer {peer_id} left topic {} (disconnected)",
+                        self.topics.name(&topic)
+                    );
+                }
+            }
+            self.bootstrap_peers.on_disconnected(&peer_id);
+            self.remotes.on_disconnected(&peer_id);
+            self.holepunch.on_disconnected(peer_id);
+            // Nothing redials other peers, they are gone for good.
+            let redialed = self.relay.is_relay(&peer_id)
+                || self.remotes.contains(&peer_id)
+                || self.bootstrap_peers.addresses().any(|(p, _)| p == peer_id);
+            if !redialed {
+                for text in self.pending_dms.drop_peer(&peer_id) {
+                    say!("delivery failed: DM '{text}' to {peer_id}: the peer disconnected");
+                }
+            }
+            if let Some(soak) = self.soak.as_mut() {
+                soak.on_disconnected(&peer_id);
+            }
+            self.pipeline.on_disconnected(&peer_id);
+            for topic in self
+                .fallback
+                .as_mut()
+                .map(|f| f.on_disconnected(&peer_id))
+                .unwrap_or_default()
+            {
+                info!(
+                    "Closed the direct channel to {peer_id} on '{}', it disconnected",
+                    self.topics.name(&topic)
+                );
+            }
+        }
+    }
+
+    fn on_dial_error(&mut self, swarm: &mut Node, peer_id: Option<PeerId>, error: DialError) {
+        match error {
+            DialError::Denied { cause } => {
+                let peer = peer_id.map(|p| p.to_string()).unwrap_or_default();
+                self.denials.on_denied(&peer, &cause);
+                if let Some(peer_id) = peer_id {
+                    self.bootstrap_peers.on_dial_failure(&peer_id);
+                    self.remotes
+                        .on_dial_failure(&peer_id, &format!("denied: {cause}"));
+                }
+            }
+            error if self.psk.is_some() && psk::dial_refused(&error) => {
+                say!("Connection to {peer_id:?} failed: protected network: handshake refused");
+                if let Some(peer_id) = peer_id {
+                    self.bootstrap_peers.on_dial_failure(&peer_id);
+                    self.remotes.on_dial_failure(&peer_id, "handshake refused");
+                }
+            }
+            error => {
+                warn!(?error, "Outgoing connection error");
+                if let DialError::LocalPeerId { endpoint } = &error {
+                    say!(
+                        "{} leads back to ourselves, the other side probably uses our --secret-key-seed",
+                        endpoint.get_remote_address()
+                    );
+                }
+                if let (Some(peer_id), DialError::WrongPeerId { obtained, endpoint }) =
+                    (peer_id, &error)
+                {
+                    say!(
+                        "{} is {obtained}, not {peer_id}, check the address",
+                        endpoint.get_remote_address()
+                    );
+                }
+                if peer_id.map_or(false, |p| self.relay.is_relay(&p))
+                    && resolver::is_resolve_failure(&error)
+                {
+                    if let Some(host) = resolver::host(self.relay.address()) {
+                        say!("Failed to resolve the relay's host {host}, check the name and the DNS servers");
+                    }
+                }
+                if let Some(peer_id) = peer_id {
+                    self.relay.on_dial_failure(&peer_id, Instant::now());
+                    self.bootstrap_peers.on_dial_failure(&peer_id);
+                    if let DialError::Transport(addrs) = &error {
+                        for (addr, _) in addrs {
+                            self.address_book.on_failure(&peer_id, addr);
+                        }
+                    }
+                }
+                let relayed_dial = matches!(
+                    &error,
+                    DialError::Transport(addrs) if addrs.iter().any(|(addr, _)| holepunch::is_relayed(addr))
+                );
+                // The known direct addresses of a remote didn't work, on to the relay.
+                let direct_failed =
+                    peer_id.filter(|p| !relayed_dial && self.remotes.on_direct_failed(p));
+                if let Some(peer_id) = direct_failed {
+                    say!("No known address of {peer_id} answered ({error}), dialing through the relay");
+                    if let Err(e) = swarm.dial(self.relay.relayed_addr(peer_id)) {
+                        self.remotes.on_dial_failure(&peer_id, &e.to_string());
+                    }
+                } else if let Some(peer_id) = peer_id {
+                    self.remotes.on_dial_failure(&peer_id, &error.to_string());
+                }
+                if let Some(one_shot) = self.one_shot.as_mut() {
+                    // Without any connection to the remote the circuit itself could not
+                    // be established, failed hole punch dials leave the relayed one open.
+                    if direct_failed.is_none()
+                        && peer_id.map_or(false, |p| {
+                            self.remotes.contains(&p) && !swarm.is_connected(&p)
+                        })
+                    {
+                        one_shot.fail(Outcome::RelayFailed(format!(
+                            "failed to dial the remote through the relay: {error}"
+                        )));
+                    }
+                }
+                if let Some(remote_lookup) = self
+                    .dht_lookups
+                    .iter_mut()
+                    .find(|l| Some(l.target()) == peer_id)
+                {
+                    if remote_lookup.on_dial_failure() == LookupStep::GiveUp {
+                        let target = remote_lookup.target();
+                        say!("Falling back to dialing {target} through our relay");
+                        if let Err(e) = swarm.dial(self.relay.relayed_addr(target)) {
+                            self.remotes.on_dial_failure(&target, &e.to_string());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn on_listen_error(&mut self, send_back_addr: Multiaddr, error: ListenError) {
+        match error {
+            ListenError::Denied { cause } => {
+                self.denials.on_denied(&send_back_addr.to_string(), &cause);
+            }
+            error if self.psk.is_some() && psk::listen_refused(&error) => {
+                say!(
+                    "Connection from {send_back_addr} failed: protected network: handshake refused"
+                );
+            }
+            _ => {}
+        }
+    }
+
+    /// Runs a received message through the seen cache and the pipeline, handling it right away
+    /// unless a check has to wait for its verdict.
+    fn validate(&mut self, swarm: &mut Node, candidate: Candidate) {
+        // Kept along with the message log, so checked before the pipeline.
+        let validated = match self.seen.check(&candidate.id) {
+            Err(rejection) => Some((candidate, Err(rejection))),
+            Ok(()) => self.pipeline.validate(candidate),
+        };
+        if let Some(validated) = validated {
+            self.on_validated(swarm, validated);
+        }
+    }
+
+    /// Reports the verdict on a received message to gossipsub and shows the message if it passed.
+    fn on_validated(&mut self, swarm: &mut Node, (candidate, verdict): Validated) {
+        let Candidate {
+            id,
+            propagation_source: peer_id,
+            message,
+        } = candidate;
+        let acceptance = match &verdict {
+            Ok(()) => gossipsub::MessageAcceptance::Accept,
+            Err(rejection) => rejection.acceptance(),
+        };
+        // Gossipsub doesn't know those that came over the direct channel.
+        let direct = self.direct_candidates.remove(&id);
+        if !direct {
+            if let Err(e) = swarm
+                .behaviour_mut()
+                .gossipsub
+                .report_message_validation_result(&id, &peer_id, acceptance)
+            {
+                warn!("Failed to report validation result of {id}: {e:?}");
+            }
+        }
+        if let Err(rejection) = verdict {
+            self.validation_stats.on_rejected(&rejection);
+            info!("Rejected message {id} from {peer_id}: {rejection}");
+            for notice in self.pipeline.notices() {
+                say!("{notice}");
+            }
+            if rejection.penalize {
+                on_offence(
+                    swarm.behaviour_mut(),
+                    &mut self.reputation,
+                    peer_id,
+                    rejection.kind,
+                    &rejection.reason,
+                );
+            }
+            return;
+        }
+        self.validation_stats.on_accepted();
+        self.seen.insert(&id);
+        if self.bench.is_some() && message.topic == Bench::topic().hash() {
+            let direct = self.connection_paths.path(&peer_id) == Some(TransportPath::Direct);
+            let reply = self
+                .bench
+                .as_mut()
+                .and_then(|bench| bench.on_message(&message.data, direct, Instant::now()));
+            if let Some(reply) = reply {
+                if let Err(e) = swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .publish(Bench::topic(), reply)
+                {
+                    warn!(?e, "Failed to publish the benchmark report");
+                }
+            }
+            return;
+        }
+        if let Some(soak) = self
+            .soak
+            .as_mut()
+            .filter(|_| message.topic == Soak::topic().hash())
+        {
+            soak.on_heartbeat(
+                message.source.unwrap_or(peer_id),
+                &message.data,
+                Instant::now(),
+            );
+            return;
+        }
+        self.traffic.on_received(message.data.len());
+        self.metrics.on_received(&self.topics.name(&message.topic));
+        let path = self
+            .connection_paths
+            .path(&peer_id)
+            .map(|path| format!(" {path}"))
+            .unwrap_or_default();
+        let via = if direct {
+            " via the direct channel"
+        } else if self.holepunch.is_relayed_fallback(&peer_id) {
+            " (hole punch failed)"
+        } else {
+            ""
+        };
+        let data = match chunking::Chunk::decode(&message.data) {
+            None => Some(message.data),
+            Some(chunk) => match self.reassembly.add(message.source, chunk) {
+                Ok(Some(data)) => match self.reassembled.check(&data, envelope::unix_millis()) {
+                    Ok(()) => Some(data),
+                    Err(rejection) => {
+                        self.validation_stats.on_rejected(&rejection);
+                        info!("Rejected reassembled message {id} from {peer_id}: {rejection}");
+                        if rejection.penalize {
+                            // Gossipsub checked that the source signed every chunk.
+                            on_offence(
+                                swarm.behaviour_mut(),
+                                &mut self.reputation,
+                                message.source.unwrap_or(peer_id),
+                                rejection.kind,
+                                &rejection.reason,
+                            );
+                        }
+                        None
+                    }
+                },
+                Ok(None) => None,
+                Err(e) => {
+                    warn!("Dropping chunk from {peer_id}: {e}");
+                    None
+                }
+            },
+        };
+        let presence = data
+            .as_ref()
+            .and_then(|data| Envelope::decode(data))
+            .filter(|e| e.kind == Kind::Presence);
+        if let Some(presence) = presence {
+            // Presence names its origin in the roster, so only the origin may announce it:
+            // an unsigned one counts if gossipsub's verified source is that origin.
+            let vouched = match signing::verify(&presence, message.source) {
+                signing::Verification::Verified(_) => true,
+                // Republished by someone else, who can't announce the origin.
+                signing::Verification::Forwarded { .. } => false,
+                signing::Verification::Unsigned => message
+                    .source
+                    .map_or(false, |source| source.to_string() == presence.origin),
+                signing::Verification::Invalid(_) => false,
+            };
+            if !vouched {
+                warn!("Ignoring presence not vouched for by its origin from {peer_id}");
+            } else if let Some(notice) = self.roster.on_presence(&presence, Instant::now()) {
+                say!("{notice}");
+            }
+        } else if let Some(data) = data {
+            if !self
+                .fallback
+                .as_mut()
+                .map_or(true, |f| f.on_delivered(&data))
+            {
+                debug!(%id, %peer_id, "Dropping a message already delivered over the other channel");
+                return;
+            }
+            // Gossipsub checked that the source signed the message, so it's to blame for
+            // what's in there, not the peer that passed it on.
+            let author = message.source.unwrap_or(peer_id);
+            let verification = Envelope::decode(&data).map(|e| signing::verify(&e, message.source));
+            if let Some(signing::Verification::Invalid(reason)) = verification {
+                on_offence(
+                    swarm.behaviour_mut(),
+                    &mut self.reputation,
+                    author,
+                    "signature",
+                    &reason,
+                );
+            }
+            let keys = self.topic_keys.of(&self.topics.name(&message.topic));
+            match envelope::render(
+                &data,
+                message.source,
+                self.opts.max_decompressed_size,
+                &message.topic,
+                keys,
+            ) {
+                Ok(text) => {
+                    let fresh = self.history.record(&message.topic, &data);
+                    let replayed = Envelope::decode(&data).map_or(false, |e| e.replayed);
+                    if replayed && !fresh && self.opts.history_size > 0 {
+                        info!("Ignoring replay of a message already seen: {id}");
+                        return;
+                    }
+                    if let Some(log) = self.message_log.as_mut() {
+                        let name = self.topics.name(&message.topic);
+                        log.append(&LogRecord::new(
+                            &message.topic,
+                            name,
+                            id.to_string(),
+                            message.source,
+                            &data,
+                            self.opts.max_decompressed_size,
+                        ));
+                    }
+                    // A forwarded message is numbered on the topic it came from. Only numbers
+                    // of the origin gossipsub verified as the source count.
+                    let numbered = Envelope::decode(&data)
+                        .filter(|e| e.forwarded_from.is_empty())
+                        .and_then(|e| {
+                            let source = message.source.filter(|s| s.to_string() == e.origin)?;
+                            Some((source, e.topic_seq?, e))
+                        });
+                    let gap = numbered.and_then(|(source, topic_seq, envelope)| {
+                        let gap = self.sequences.observe(
+                            &source,
+                            &message.topic,
+                            topic_seq,
+                            envelope.sent_at,
+                            envelope::unix_millis(),
+                        )?;
+                        Some((gap, envelope))
+                    });
+                    if let Some((gap, envelope)) = gap {
+                        let plural = if gap.missed == 1 { "" } else { "s" };
+                        say!(
+                            "\u{26a0} missed {} message{plural} from {}",
+                            gap.missed,
+                            envelope.sender()
+                        );
+                        if self.opts.fetch_missed && self.opts.history_size > 0 {
+                            let request =
+                                self.history
+                                    .request_since(&message.topic, gap.since, gap.missed);
+                            let request_id = swarm
+                                .behaviour_mut()
+                                .history
+                                .send_request(&peer_id, request);
+                            self.history
+                                .on_request_sent(request_id, message.topic.clone());
+                        }
+                    }
+                    for (to, envelope) in self
+                        .forwarder
+                        .on_message(&self.topics.name(&message.topic), &data)
+                    {
+                        let forwarded = envelope.encode(self.opts.wire_format);
+                        self.traffic.on_sent(forwarded.len());
+                        // Split up like our own messages if it's too large for the destination.
+                        if let Err(e) = publish_chunked(
+                            &mut self.outbox,
+                            &mut swarm.behaviour_mut().gossipsub,
+                            &self.topics,
+                            self.topics.hash(&to),
+                            forwarded,
+                            self.opts.max_message_size,
+                            self.opts.max_decompressed_size,
+                        ) {
+                            warn!("Failed to forward message {id} to topic '{to}': {e}");
+                        }
+                    }
+                    let hooked = self
+                        .hook
+                        .as_ref()
+                        .filter(|hook| hook.wants(&self.topics.name(&message.topic)));
+                    let to_bridges = self.bridges.wants(&self.topics.name(&message.topic))
+                        || self
+                            .mqtt
+                            .as_ref()
+                            .map_or(false, |mqtt| mqtt.wants(&self.topics.name(&message.topic)));
+                    if self.opts.output == Output::Json
+                        || self.feed.is_some()
+                        || hooked.is_some()
+                        || to_bridges
+                    {
+                        let json = JsonMessage::new(
+                            self.topics.name(&message.topic),
+                            id.to_string(),
+                            message.source,
+                            self.connection_paths.path(&peer_id),
+                        )
+                        .with_payload(
+                            &data,
+                            self.opts.max_decompressed_size,
+                            &message.topic,
+                            keys,
+                        );
+                        match json {
+                            Ok(json) => {
+                                if to_bridges {
+                                    let marker = Envelope::decode(&data).and_then(|e| e.bridge);
+                                    self.bridges.on_message(
+                                        &json.topic,
+                                        &json.body,
+                                        marker.as_deref(),
+                                    );
+                                    if let Some(mqtt) = &self.mqtt {
+                                        mqtt.on_message(&json.topic, &json.body, marker.as_deref());
+                                    }
+                                }
+                                if let Some(hook) = hooked {
+                                    hook.on_message(HookMessage {
+                                        topic: json.topic.clone(),
+                                        message_id: json.message_id.clone(),
+                                        source: message.source,
+                                        path: self.connection_paths.path(&peer_id),
+                                        body: json.body.clone(),
+                                        body_encoding: json.body_encoding,
+                                    });
+                                }
+                                if self.opts.output == Output::Json {
+                                    println!(
+                                        "{}",
+                                        serde_json::to_string(&json)
+                                            .expect("message serializes to JSON")
+                                    );
+                                }
+                                if let Some(feed) = self.feed.as_mut() {
+                                    feed.push(json);
+                                }
+                            }
+                            Err(e) => warn!("Can't turn message {id} into JSON: {e}"),
+                        }
+                    }
+                    if self.opts.output == Output::Text {
+                        say!(
+                            "[{}] {text} (id: {id}, from peer: {peer_id}{path}{via})",
+                            self.topics.name(&message.topic),
+                        )
+                    }
+                }
+                Err(e) => {
+                    warn!("Rejected message {id} from {peer_id}: {e}");
+                    on_offence(
+                        swarm.behaviour_mut(),
+                        &mut self.reputation,
+                        author,
+                        "body",
+                        &e,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Goes on with bootstrapping after a phase completed.
+    fn on_step(&mut self, swarm: &mut Node, step: Step) -> Result<(), Box<dyn Error>> {
+        match step {
+            Step::DialRelay => {
+                let tcp_port = swarm.listeners().find_map(|addr| {
+                    addr.iter().find_map(|p| match p {
+                        Protocol::Tcp(port) => Some(port),
+                        _ => None,
+                    })
+                });
+                if let Some(port) = tcp_port.filter(|_| self.opts.enable_upnp) {
+                    self._upnp = Some(UpnpHandle::spawn(port, self.upnp_tx.clone()));
+                }
+                // Connect to the relay server. Not for the reservation or relayed connection,
+                // but to (a) learn our local public address and (b) enable a freshly started
+                // relay to learn its public address.
+                if let Err(e) = swarm.dial(self.relay.address().clone()) {
+                    return Err(
+                        format!("failed to dial the relay {}: {e}", self.relay.address()).into(),
+                    );
+                }
+            }
+            Step::Connect(nat_status) => {
+                match self.opts.mode {
+                    Mode::Dial => {
+                        // Each remote is dialed on its own, one that can't be reached is retried
+                        // from the tick.
+                        for remote_peer_id in self.remotes.peer_ids().collect::<Vec<_>>() {
+                            // The addresses given on the command line come first, whatever
+                            // --relay-first says.
+                            let mut known = self
+                                .remote_addrs
+                                .iter()
+                                .filter(|(peer_id, _)| *peer_id == remote_peer_id)
+                                .map(|(_, addr)| addr.clone())
+                                .collect::<Vec<_>>();
+                            if !self.opts.relay_first {
+                                for addr in self.address_book.dial_addrs(&remote_peer_id) {
+                                    if !known.contains(&addr) {
+                                        known.push(addr);
+                                    }
+                                }
+                            }
+                            // We are reachable ourselves, so the addresses other behaviours learned
+                            // for the remote are worth a try before paying for the relay.
+                            let public = matches!(nat_status, NatStatus::Public(_))
+                                && !self.opts.relay_first
+                                && swarm.behaviour().kademlia.is_none();
+                            if !known.is_empty() || public {
+                                match known.len() {
+                                    0 => say!("Publicly reachable, trying the addresses learned for {remote_peer_id} before the relay"),
+                                    n => say!("Dialing {remote_peer_id} at {n} known direct addresses, the relay is only used if none answers"),
+                                }
+                                self.remotes.on_direct_dial(
+                                    &remote_peer_id,
+                                    Instant::now()
+                                        + Duration::from_secs(self.opts.direct_dial_timeout),
+                                );
+                                // A dial that fails later on is taken to the relay by the
+                                // connection error handling.
+                                let dial = DialOpts::peer_id(remote_peer_id)
+                                    .addresses(known)
+                                    .extend_addresses_through_behaviour()
+                                    .build();
+                                if let Err(e) = swarm.dial(dial) {
+                                    say!("No known address of {remote_peer_id} could be dialed ({e}), dialing through the relay");
+                                    self.remotes.on_direct_failed(&remote_peer_id);
+                                    if let Err(e) =
+                                        swarm.dial(self.relay.relayed_addr(remote_peer_id))
+                                    {
+                                        self.remotes
+                                            .on_dial_failure(&remote_peer_id, &e.to_string());
+                                    }
+                                }
+                                continue;
+                            }
+                            let dialed = match swarm.behaviour_mut().kademlia.as_mut() {
+                                Some(kademlia) => {
+                                    let mut remote_lookup = RemoteLookup::new(
+                                        remote_peer_id,
+                                        self.opts.kad_lookup_retries,
+                                    );
+                                    remote_lookup.start(kademlia);
+                                    self.dht_lookups.push(remote_lookup);
+                                    Ok(())
+                                }
+                                None => {
+                                    match self.opts.relay_first {
+                                        true => say!("Dialing {remote_peer_id} through the relay (--relay-first)"),
+                                        false => say!("No direct address of {remote_peer_id} is known, dialing through the relay"),
+                                    }
+                                    swarm.dial(self.relay.relayed_addr(remote_peer_id))
+                                }
+                            };
+                            if let Err(e) = dialed {
+                                say!("Failed to dial {remote_peer_id}: {e}");
+                                self.remotes
+                                    .on_dial_failure(&remote_peer_id, &e.to_string());
+                            }
+                        }
+                        if let Some(one_shot) = self.one_shot.as_mut() {
+                            one_shot.on_circuit_dialed();
+                        }
+                    }
+                    Mode::Listen => match &nat_status {
+                        NatStatus::Public(addr) => {
+                            say!("Publicly reachable at {addr}, skipping the relay reservation");
+                            swarm.add_external_address(addr.clone(), AddressScore::Infinite);
+                        }
+                        _ => {
+                            self.relay.on_reservation_requested();
+                            let circuit = self.relay.circuit_addr();
+                            if let Err(e) = swarm.listen_on(circuit.clone()) {
+                                return Err(format!("failed to listen on {circuit}: {e}").into());
+                            }
+                        }
+                    },
+                }
+                // From here on explicit peers are kept dialed like the remote peers, the
+                // configured ones among them were just dialed above. In listen mode those dial us.
+                for peer_id in self
+                    .explicit
+                    .peers()
+                    .filter(|p| self.opts.mode == Mode::Dial || !self.remote_peer_ids.contains(p))
+                {
+                    self.remotes.add(*peer_id, swarm.is_connected(peer_id));
+                }
+            }
+            Step::Reachable(addr) => {
+                say!("Publicly reachable at {addr}, peers can dial us directly as well as through the relay");
+                swarm.add_external_address(addr, AddressScore::Infinite);
+            }
+            Step::Running => {}
+        }
+        Ok(())
+    }
+
+    /// Handles a command typed or sent through the control socket, breaking once it's `/quit`.
+    fn on_command(
+        &mut self,
+        swarm: &mut Node,
+        command: Command,
+        reply: Option<oneshot::Sender<Response>>,
+        bridged: Option<String>,
+    ) -> ControlFlow<()> {
+        match command {
+            Command::Join(name) => {
+                self.topic_keys.derive(name);
+                self.topics.join(name, &mut swarm.behaviour_mut().gossipsub);
+                let hash = self.topics.hash(name);
+                if self.topics.contains(&hash) {
+                    let presence = self
+                        .sequencer
+                        .wrap(Kind::Presence, presence::JOINED)
+                        .signed(&self.local_key)
+                        .encode(self.opts.wire_format);
+                    // Nobody may be subscribed yet, the periodic announcement follows, which also
+                    // makes up for one over the publish rate.
+                    if self
+                        .presence_budget
+                        .as_mut()
+                        .map_or(true, |budget| budget.try_take(Instant::now()))
+                    {
+                        let _ = swarm.behaviour_mut().gossipsub.publish(hash, presence);
+                    }
+                }
+            }
+            Command::Leave(name) => {
+                if let Some(fallback) = self.fallback.as_mut() {
+                    fallback.on_left(&self.topics.hash(name));
+                }
+                self.topics
+                    .leave(name, &mut swarm.behaviour_mut().gossipsub)
+            }
+            Command::Topics => {
+                let gossipsub = &swarm.behaviour().gossipsub;
+                for hash in self.topics.hashes() {
+                    let mesh = gossipsub.mesh_peers(hash).count();
+                    let subscribed = self.subscribers.count(hash);
+                    let current = if self.topics.selected() == Some(hash) {
+                        " (current)"
+                    } else {
+                        ""
+                    };
+                    say!(
+                        "  {}{current}: {mesh} mesh peers, {subscribed} subscribers",
+                        self.topics.name(hash)
+                    );
+                }
+            }
+            Command::Peers => {
+                let mut lines = peer_lines(
+                    swarm.connected_peers(),
+                    &self.connection_paths,
+                    &self.rtt_stats,
+                    &self.explicit,
+                    &self.peer_infos,
+                );
+                if lines.is_empty() {
+                    say!("Not connected to any peers");
+                }
+                lines.extend(self.remotes.lines(Instant::now()));
+                for line in lines {
+                    say!("  {line}");
+                }
+            }
+            Command::PeerAdd(peer_id) => {
+                if !self.explicit.add(peer_id) {
+                    say!("{peer_id} is an explicit peer already");
+                    return ControlFlow::Continue(());
+                }
+                swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
+                swarm.behaviour_mut().limits.protect(peer_id);
+                self.reputation.protect(peer_id);
+                self.remotes.add(peer_id, swarm.is_connected(&peer_id));
+                say!("Added explicit peer {peer_id}");
+            }
+            Command::PeerRm(peer_id) => {
+                if !self.explicit.remove(&peer_id) {
+                    say!("{peer_id} is not an explicit peer");
+                    return ControlFlow::Continue(());
+                }
+                swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .remove_explicit_peer(&peer_id);
+                swarm.behaviour_mut().limits.unprotect(&peer_id);
+                if !self.session_peers.contains(&peer_id) {
+                    self.reputation.unprotect(&peer_id);
+                }
+                self.remotes.remove(&peer_id);
+                say!("Removed explicit peer {peer_id}");
+            }
+            Command::Addrs(peer_id) => {
+                let lines = self.address_book.lines(&peer_id);
+                if lines.is_empty() {
+                    say!("No addresses known for {peer_id}");
+                }
+                for line in lines {
+                    say!("  {line}");
+                }
+            }
+            Command::Info(peer_id) => {
+                let lines = self.peer_infos.lines(&peer_id);
+                if lines.is_empty() {
+                    say!("{peer_id} hasn't identified itself, or isn't connected");
+                }
+                for line in lines {
+                    say!("  {line}");
+                }
+            }
+            Command::Stats => {
+                say!("{}", self.traffic);
+                say!("{}", self.outbox);
+                say!("{}", swarm.behaviour().limits);
+                say!("{}", self.bandwidth);
+                for line in self.bandwidth.peer_lines() {
+                    say!("  {line}");
+                }
+                say!("{}", self.holepunch_stats);
+                for line in self.holepunch_stats.peer_lines() {
+                    say!("  {line}");
+                }
+                for line in self.path_comparisons.lines() {
+                    say!("  {line}");
+                }
+                say!("{}", self.validation_stats);
+                for line in self.bridges.lines() {
+                    say!("{line}");
+                }
+                for line in self.forwarder.lines() {
+                    say!("{line}");
+                }
+                if let Some(mqtt) = &self.mqtt {
+                    say!("{mqtt}");
+                }
+            }
+            Command::Ban { peer_id, force } => {
+                if self.session_peers.contains(&peer_id) && !force {
+                    say!("{peer_id} is the relay or remote peer of this session, use '/ban {peer_id} --force' to ban it anyway");
+                    return ControlFlow::Continue(());
+                }
+                swarm.behaviour_mut().gossipsub.blacklist_peer(&peer_id);
+                // Also closes the connections we have to it.
+                swarm.behaviour_mut().blocked.block_peer(peer_id);
+                self.pending_dms.drop_peer(&peer_id);
+                if self.bans.ban(peer_id) {
+                    say!("Banned {peer_id}");
+                } else {
+                    say!("{peer_id} is already banned");
+                }
+            }
+            Command::Unban(peer_id) => {
+                swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .remove_blacklisted_peer(&peer_id);
+                swarm.behaviour_mut().blocked.unblock_peer(peer_id);
+                if self.bans.unban(&peer_id) {
+                    say!("Unbanned {peer_id}");
+                } else {
+                    say!("{peer_id} is not banned");
+                }
+            }
+            Command::Dm { peer_id, text } => {
+                if !swarm.is_connected(&peer_id) {
+                    say!("Not connected to {peer_id}, dialing it before sending the DM");
+                    // Addresses known from elsewhere, e.g. the DHT, are tried as
+                    // well, the circuit through our relay is the fallback.
+                    swarm
+                        .behaviour_mut()
+                        .dm
+                        .add_address(&peer_id, self.relay.relayed_addr(peer_id));
+                }
+                match DirectMessage::seal(
+                    &self.e2e_key,
+                    self.local_peer_id,
+                    peer_id,
+                    self.opts.nick.clone(),
+                    text,
+                    false,
+                ) {
+                    Ok(request) => {
+                        let id = swarm
+                            .behaviour_mut()
+                            .dm
+                            .send_request(&peer_id, request.clone());
+                        self.pending_dms
+                            .on_sent(id, peer_id, request, text.to_string());
+                    }
+                    Err(e) => say!("Can't encrypt a DM to {peer_id}: {e}"),
+                }
+            }
+            Command::Queue => {
+                let lines = self.resend.lines(Instant::now(), |t| self.topics.name(t));
+                if lines.is_empty() {
+                    say!("No messages are waiting for a peer to come back");
+                }
+                for line in lines {
+                    say!("  {line}");
+                }
+            }
+            Command::Pending => {
+                let lines = self.pending_dms.lines();
+                if lines.is_empty() {
+                    say!("No direct messages are awaiting an ack");
+                }
+                for line in lines {
+                    say!("  {line}");
+                }
+            }
+            Command::Send { peer_id, path } => {
+                if !swarm.is_connected(&peer_id) {
+                    say!("Not connected to {peer_id}, dialing it before sending the file");
+                    swarm
+                        .behaviour_mut()
+                        .transfer
+                        .add_address(&peer_id, self.relay.relayed_addr(peer_id));
+                }
+                if let Some(TransportPath::Relayed(_)) = self.connection_paths.path(&peer_id) {
+                    say!(
+                        "Warning: {peer_id} is only reachable through the relay, the transfer \
+                         may hit the relay's byte limit and fail"
+                    );
+                }
+                // Hashing the file takes a while, the offer goes out once it's done.
+                self.transfers.offer(peer_id, PathBuf::from(path));
+            }
+            Command::Who => {
+                let lines = self.roster.lines(&self.connection_paths, Instant::now());
+                if lines.is_empty() {
+                    say!("Nobody else announced themselves yet");
+                }
+                for line in lines {
+                    say!("  {line}");
+                }
+            }
+            Command::Rep(peer_id) => {
+                let lines = self.reputation.lines(&peer_id, reputation::now_secs());
+                if lines.is_empty() {
+                    say!("{peer_id} has a clean record");
+                }
+                for line in lines {
+                    say!("  {line}");
+                }
+            }
+            Command::Bans => {
+                let lines = self.bans.lines();
+                if lines.is_empty() {
+                    say!("No peers are banned");
+                }
+                for line in lines {
+                    say!("  {line}");
+                }
+            }
+            Command::Help => say!("{}", commands::HELP),
+            Command::Quit => return ControlFlow::Break(()),
+            Command::Publish(text) => match self.topics.route(text) {
+                Ok(Some((topic, message))) => {
+                    let mut envelope = self
+                        .sequencer
+                        .wrap(Kind::Chat, message)
+                        .numbered(self.sequences.next(&topic))
+                        .compress(self.compress_threshold);
+                    envelope.bridge = bridged;
+                    if let Some(key) = self.topic_keys.of(&self.topics.name(&topic)).last() {
+                        envelope = envelope.encrypt(&topic, key);
+                    }
+                    let away = self.resend.disconnected().copied().collect::<Vec<_>>();
+                    for peer_id in away {
+                        let queued = Queued::Topic {
+                            topic: topic.clone(),
+                            envelope: envelope.clone(),
+                            text: message.to_string(),
+                        };
+                        if let Some(dropped) = self.resend.push(peer_id, queued) {
+                            say!(
+                                "Resend buffer for {peer_id} is full, dropped {}",
+                                dropped.describe(|t| self.topics.name(t))
+                            );
+                        }
+                    }
+                    let data = envelope
+                        .signed(&self.local_key)
+                        .encode(self.opts.wire_format);
+                    self.history.record(&topic, &data);
+                    if let Some(log) = self.message_log.as_mut() {
+                        log.append(&LogRecord::new(
+                            &topic,
+                            self.topics.name(&topic),
+                            String::new(),
+                            Some(self.local_peer_id),
+                            &data,
+                            self.opts.max_decompressed_size,
+                        ));
+                    }
+                    self.traffic.on_sent(data.len());
+                    if let Some(reply) = reply {
+                        let _ = reply.send(Response::ok(serde_json::json!({
+                            "topic": self.topics.name(&topic),
+                            "bytes": data.len(),
+                        })));
+                    }
+                    let name = self.topics.name(&topic);
+                    for peer_id in self
+                        .fallback
+                        .as_ref()
+                        .map(|f| f.peers(&topic))
+                        .unwrap_or_default()
+                    {
+                        if let Some(channel) = swarm.behaviour_mut().fallback.as_mut() {
+                            channel
+                                .send_request(&peer_id, FallbackMessage::new(name.clone(), &data));
+                        }
+                    }
+                    let result = publish_chunked(
+                        &mut self.outbox,
+                        &mut swarm.behaviour_mut().gossipsub,
+                        &self.topics,
+                        topic,
+                        data,
+                        self.opts.max_message_size,
+                        self.opts.max_decompressed_size,
+                    );
+                    match result {
+                        Ok(()) => self.metrics.on_published(&name),
+                        Err(error) => {
+                            self.metrics.on_publish_error(&name);
+                            if let Some(journal) = self.journal.as_mut() {
+                                journal.write(&NodeEvent::PublishError { topic: name, error });
+                            }
+                        }
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => match reply {
+                    Some(reply) => {
+                        let _ = reply.send(Response::error(e));
+                    }
+                    None => say!("{e}"),
+                },
+            },
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Says goodbye on every topic and leaves them.
+    fn leave(&mut self, swarm: &mut Node) {
+        let leaving = self
+            .sequencer
+            .wrap(Kind::Presence, presence::LEAVING)
+            .signed(&self.local_key)
+            .encode(self.opts.wire_format);
+        for topic in self.topics.hashes().cloned().collect::<Vec<_>>() {
+            // Failing for lack of peers is fine, there's nobody to tell.
+            let _ = swarm
+                .behaviour_mut()
+                .gossipsub
+                .publish(topic, leaving.clone());
+        }
+        self.topics
+            .unsubscribe_all(&mut swarm.behaviour_mut().gossipsub);
+    }
+
+    /// Saves what is kept across runs and reports the totals.
+    fn finish(&mut self) {
+        self.sequences.save();
+        self.address_book.save();
+        self.seen.save();
+        self.reputation.save();
+        say!("{}", self.traffic);
+        say!("{}", self.bandwidth);
+        for line in self.bandwidth.peer_lines() {
+            say!("  {line}");
+        }
+        say!("{}", self.holepunch_stats);
+        for line in self.holepunch_stats.peer_lines() {
+            say!("{line}");
+        }
+        if let Some(soak) = &self.soak {
+            soak.report(true, &self.connection_paths, &self.holepunch_stats);
+        }
+        if let Some(log) = self.message_log.as_mut() {
+            log.sync();
+        }
+    }
+}
+
+/// Interval between gossipsub heartbeats.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often the event loop wakes up to drive retries and other timers.
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Shortest wait between draining the outbox under `--publish-rate`.
+const PUBLISH_PACE: Duration = Duration::from_millis(10);
+
+/// How often the `--bench` publisher hands the swarm another batch.
+const BENCH_PACE: Duration = Duration::from_millis(1);
+
+/// How often the hole punch statistics are logged, if anything changed.
+const STATS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long a relayed connection is kept after a hole punch so in-flight streams can finish.
+const RELAYED_CLOSE_GRACE: Duration = Duration::from_secs(5);
+
+/// The checks of `/readyz`: connected to the relay, holding a reservation in listen mode or a
+/// circuit to the remote in dial mode, and at least one gossipsub mesh peer. The relay checks pass
+/// when we are publicly reachable and don't use the relay.
+fn readiness_checks(
+    mode: &Mode,
+    relay_status: &str,
+    relay_connected: bool,
+    remote_connected: bool,
+    mesh_peers: usize,
+) -> Vec<(&'static str, Check)> {
+    let relay_used = !(*mode == Mode::Listen && relay_status == "not used");
+    let mut checks = vec![(
+        "relay_connected",
+        match (relay_used, relay_connected) {
+            (false, _) => Check::new(true, "relay not used, publicly reachable"),
+            (true, connected) => Check::new(
+                connected,
+                if connected {
+                    "connected"
+                } else {
+                    "not connected"
+                },
+            ),
+        },
+    )];
+    checks.push(match mode {
+        Mode::Listen => (
+            "reservation",
+            Check::new(
+                !relay_used || relay_status == "reservation accepted",
+                relay_status,
+            ),
+        ),
+        Mode::Dial => (
+            "circuit",
+            Check::new(
+                remote_connected,
+                if remote_connected {
+                    "connected to the remote peer"
+                } else {
+                    "no connection to the remote peer"
+                },
+            ),
+        ),
+    });
+    checks.push((
+        "mesh_peers",
+        Check::new(
+            mesh_peers > 0,
+            format!("{mesh_peers} peers in the gossipsub mesh"),
+        ),
+    ));
+    checks
+}
+
+/// Counts an offence against the reputation of `peer_id`, banning it if that was one too many.
+fn on_offence(
+    behaviour: &mut Behaviour,
+    reputation: &mut Reputation,
+    peer_id: PeerId,
+    kind: &str,
+    reason: &str,
+) {
+    if let Some(ban) = reputation.on_offence(peer_id, kind, reason, reputation::now_secs()) {
+        warn!(
+            "Banning {} for {}s, its reputation fell to the threshold after {}",
+            ban.peer_id,
+            ban.duration.as_secs(),
+            ban.reasons
+        );
+        behaviour.gossipsub.blacklist_peer(&ban.peer_id);
+        // Also closes the connections we have to it.
+        behaviour.blocked.block_peer(ban.peer_id);
+    }
+}
+
+/// Sends the next echo of the latency probe, if there is one.
+fn send_echo(
+    behaviour: &mut request_response::Behaviour<EchoCodec>,
+    probe: &mut LatencyProbe,
+    echo: Option<Echo>,
+) {
+    if let Some(echo) = echo {
+        let id = behaviour.send_request(&probe.remote(), echo);
+        probe.on_sent(id, Instant::now());
+    }
+}
+
+/// One line per connection of each connected peer with the path it takes and its ping round
+/// trip times, so a peer with a relayed and a direct connection has two. Peers that identified
+/// themselves show their agent version and whether they lack protocols we need.
+fn peer_lines<'a>(
+    peers: impl Iterator<Item = &'a PeerId>,
+    connection_paths: &ConnectionPaths,
+    rtt_stats: &RttStats,
+    explicit: &ExplicitPeers,
+    peer_infos: &PeerInfos,
+) -> Vec<String> {
+    peers
+        .flat_map(|peer_id| {
+            let explicit = match explicit.contains(peer_id) {
+                true => " [explicit]",
+                false => "",
+            };
+            let agent = peer_infos
+                .summary(peer_id)
+                .map(|summary| format!(" ({summary})"))
+                .unwrap_or_default();
+            connection_paths
+                .connections(peer_id)
+                .into_iter()
+                .map(move |(connection, path)| {
+                    let rtt = rtt_stats
+                        .summary(connection)
+                        .map(|rtt| rtt.to_string())
+                        .unwrap_or_else(|| "rtt unknown".to_string());
+                    format!("{peer_id}{agent}{explicit} {path} {rtt}")
+                })
+        })
+        .collect()
+}
+
+/// Publishes `data`, in chunks if it's too large for a single message. Fails if any of it could
+/// neither be published nor queued. Queued messages are shown decompressed up to
+/// `max_decompressed` bytes.
+fn publish_chunked(
+    outbox: &mut Outbox,
+    gossipsub: &mut gossipsub::Behaviour,
+    topics: &Topics,
+    topic: TopicHash,
+    data: Vec<u8>,
+    max_message_size: usize,
+    max_decompressed: usize,
+) -> Result<(), String> {
+    let max_payload = chunking::max_payload(max_message_size, topic.as_str());
+    match chunking::split(data, max_payload) {
+        Ok(pieces) => {
+            if pieces.len() > 1 {
+                say!("Message too large, sending it in {} chunks", pieces.len());
+            }
+            let mut result = Ok(());
+            for piece in pieces {
+                if let Err(e) = publish(
+                    outbox,
+                    gossipsub,
+                    topics,
+                    topic.clone(),
+                    piece,
+                    max_decompressed,
+                ) {
+                    result = Err(e);
+                }
+            }
+            result
+        }
+        Err(e) => {
+            say!("Message not sent: {e}");
+            Err(e)
+        }
+    }
+}
+
+/// Publishes `data` through the outbox and tells the user if it had to be queued.
+fn publish(
+    outbox: &mut Outbox,
+    gossipsub: &mut gossipsub::Behaviour,
+    topics: &Topics,
+    topic: TopicHash,
+    data: Vec<u8>,
+    max_decompressed: usize,
+) -> Result<(), String> {
+    match outbox.publish(gossipsub, topic.clone(), data, Instant::now()) {
+        Ok(Sent::Published) => {
+            flush_outbox(outbox, gossipsub, topics, max_decompressed);
+            Ok(())
+        }
+        Ok(Sent::Queued(dropped)) => {
+            say!(
+                "No peers on topic '{}' yet, message queued until one subscribes",
+                topics.name(&topic)
+            );
+            say_dropped(topics, dropped, max_decompressed);
+            Ok(())
+        }
+        Ok(Sent::Throttled { started, dropped }) => {
+            if let Some(rate) = outbox.rate().filter(|_| started) {
+                say!(
+                    "Publishing faster than {:.1} messages/s, holding messages back",
+                    rate.per_second
+                );
+            }
+            say_dropped(topics, dropped, max_decompressed);
+            Ok(())
+        }
+        Err(e) => {
+            say!("Publish error: {e:?}");
+            Err(format!("{e:?}"))
+        }
+    }
+}
+
+fn say_dropped(topics: &Topics, dropped: Option<(TopicHash, Vec<u8>)>, max_decompressed: usize) {
+    if let Some((topic, data)) = dropped {
+        say!(
+            "Outbox full, dropped queued message '{}' for topic '{}'",
+            envelope::text_of(&data, max_decompressed),
+            topics.name(&topic)
+        );
+    }
+}
+
+/// Publishes whatever the outbox can send now and tells the user about it.
+fn flush_outbox(
+    outbox: &mut Outbox,
+    gossipsub: &mut gossipsub::Behaviour,
+    topics: &Topics,
+    max_decompressed: usize,
+) {
+    if outbox.is_empty() {
+        return;
+    }
+    let (sent, failed) = outbox.flush(gossipsub, Instant::now());
+    for (topic, data) in sent {
+        say!(
+            "Sent queued message '{}' to topic '{}'",
+            envelope::text_of(&data, max_decompressed),
+            topics.name(&topic)
+        );
+    }
+    for (topic, e) in failed {
+        say!(
+            "Dropped queued message for topic '{}': {e:?}",
+            topics.name(&topic)
+        );
+    }
+}
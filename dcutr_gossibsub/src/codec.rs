@@ -0,0 +1,23 @@
+use futures::{AsyncRead, AsyncWrite};
+use libp2p::core::upgrade::{read_length_prefixed, write_length_prefixed};
+use serde::{de::DeserializeOwned, Serialize};
+use std::io;
+
+/// Reads one length prefixed JSON message of at most `max_size` bytes.
+pub async fn read_json<T, M>(io: &mut T, max_size: usize) -> io::Result<M>
+where
+    T: AsyncRead + Unpin + Send,
+    M: DeserializeOwned,
+{
+    let data = read_length_prefixed(io, max_size).await?;
+    serde_json::from_slice(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub async fn write_json<T, M>(io: &mut T, message: &M) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+    M: Serialize,
+{
+    let data = serde_json::to_vec(message)?;
+    write_length_prefixed(io, data).await
+}
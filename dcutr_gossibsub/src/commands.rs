@@ -0,0 +1,152 @@
+use libp2p::PeerId;
+use std::str::FromStr;
+
+pub const HELP: &str = "\
+Commands:
+  /join <topic>             subscribe to a topic
+  /leave <topic>            unsubscribe from a topic
+  /topics                   list subscriptions with their mesh sizes
+  /peers                    list connected peers with path and round trip time
+  /addrs <peer-id>          show the addresses known for a peer
+  /info <peer-id>           show what a connected peer identified itself with
+  /peer add|rm <peer-id>    always forward to a peer and keep it dialed, or stop doing so
+  /who                      list peers that announced themselves
+  /stats                    show message and hole punch counters
+  /dm <peer-id> <text>      send an end-to-end encrypted direct message
+  /pending                  list direct messages awaiting an ack
+  /queue                    list messages waiting for a peer to come back
+  /send <peer-id> <path>    send a file
+  /ban <peer-id> [--force]  ban a peer
+  /unban <peer-id>          lift a ban
+  /bans                     list banned peers
+  /rep <peer-id>            show a peer's reputation and what it lost points for
+  /quit                     exit
+  /help                     show this help
+Anything else is published, to another topic than the current one with '@<topic> <text>'.
+Start a line with '//' to publish it starting with a single '/'.";
+
+/// A line read from stdin.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command<'a> {
+    Join(&'a str),
+    Leave(&'a str),
+    Topics,
+    Peers,
+    Addrs(PeerId),
+    Info(PeerId),
+    PeerAdd(PeerId),
+    PeerRm(PeerId),
+    Who,
+    Stats,
+    Dm {
+        peer_id: PeerId,
+        text: &'a str,
+    },
+    Pending,
+    Queue,
+    Send {
+        peer_id: PeerId,
+        path: &'a str,
+    },
+    Ban {
+        peer_id: PeerId,
+        force: bool,
+    },
+    Unban(PeerId),
+    Bans,
+    Rep(PeerId),
+    Quit,
+    Help,
+    /// Text to publish, with the escaping slash of a `//` line removed.
+    Publish(&'a str),
+}
+
+impl<'a> Command<'a> {
+    /// Parses a line, failing with the usage of the command if its arguments are wrong and with
+    /// the help for unknown commands, which are never published.
+    pub fn parse(line: &'a str) -> Result<Self, String> {
+        if line.starts_with("//") {
+            return Ok(Command::Publish(&line[1..]));
+        }
+        let rest = match line.strip_prefix('/') {
+            Some(rest) => rest,
+            None => return Ok(Command::Publish(line)),
+        };
+        let (name, args) = rest.split_once(' ').unwrap_or((rest, ""));
+        let args = args.trim();
+        let usage = |usage: &str| Err(format!("Usage: {usage}"));
+        match name {
+            "join" if !args.is_empty() => Ok(Command::Join(args)),
+            "join" => usage("/join <topic>"),
+            "leave" if !args.is_empty() => Ok(Command::Leave(args)),
+            "leave" => usage("/leave <topic>"),
+            "topics" => Ok(Command::Topics),
+            "peers" => Ok(Command::Peers),
+            "peer" => match args
+                .split_once(' ')
+                .map(|(op, p)| (op, PeerId::from_str(p.trim())))
+            {
+                Some(("add", Ok(peer_id))) => Ok(Command::PeerAdd(peer_id)),
+                Some(("rm", Ok(peer_id))) => Ok(Command::PeerRm(peer_id)),
+                _ => usage("/peer add|rm <peer-id>"),
+            },
+            "addrs" => match PeerId::from_str(args) {
+                Ok(peer_id) => Ok(Command::Addrs(peer_id)),
+                Err(_) => usage("/addrs <peer-id>"),
+            },
+            "info" => match PeerId::from_str(args) {
+                Ok(peer_id) => Ok(Command::Info(peer_id)),
+                Err(_) => usage("/info <peer-id>"),
+            },
+            "who" => Ok(Command::Who),
+            "stats" => Ok(Command::Stats),
+            "dm" => match peer_and_rest(args) {
+                Some((peer_id, text)) => Ok(Command::Dm { peer_id, text }),
+                None => usage("/dm <peer-id> <text>"),
+            },
+            "pending" => Ok(Command::Pending),
+            "queue" => Ok(Command::Queue),
+            "send" => match peer_and_rest(args) {
+                Some((peer_id, path)) => Ok(Command::Send { peer_id, path }),
+                None => usage("/send <peer-id> <path>"),
+            },
+            "ban" => {
+                let mut args = args.split_whitespace();
+                let peer_id = args.next().map(PeerId::from_str);
+                let force = match args.next() {
+                    None => false,
+                    Some("--force") => true,
+                    Some(_) => return usage("/ban <peer-id> [--force]"),
+                };
+                match peer_id {
+                    Some(Ok(peer_id)) => Ok(Command::Ban { peer_id, force }),
+                    _ => usage("/ban <peer-id> [--force]"),
+                }
+            }
+            "unban" => match PeerId::from_str(args) {
+                Ok(peer_id) => Ok(Command::Unban(peer_id)),
+                Err(_) => usage("/unban <peer-id>"),
+            },
+            "bans" => Ok(Command::Bans),
+            "rep" => match PeerId::from_str(args) {
+                Ok(peer_id) => Ok(Command::Rep(peer_id)),
+                Err(_) => usage("/rep <peer-id>"),
+            },
+            "quit" | "exit" => Ok(Command::Quit),
+            "help" | "?" => Ok(Command::Help),
+            _ => Err(format!(
+                "Unknown command '/{name}', nothing was sent.\n{HELP}"
+            )),
+        }
+    }
+}
+
+/// Splits `<peer-id> <rest>`, with a non-empty rest.
+fn peer_and_rest(args: &str) -> Option<(PeerId, &str)> {
+    let (peer_id, rest) = args.split_once(' ')?;
+    let rest = rest.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    Some((PeerId::from_str(peer_id).ok()?, rest))
+}
@@ -0,0 +1,288 @@
+use clap::{parser::ValueSource, Arg, ArgAction, ArgMatches, Command, CommandFactory, Parser};
+use std::any::TypeId;
+use std::collections::HashSet;
+use std::env::{self, VarError};
+use std::error::Error;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// Flags about the config file itself, which can't be set in it.
+const OWN_FLAGS: [&str; 2] = ["config", "print_config"];
+
+/// Settings whose values are left out of the log and of `--print-config`.
+const SECRETS: [&str; 3] = ["secret_key_seed", "http_token", "topic_passphrase"];
+
+/// Environment variables are named after the flags with this in front, e.g. `P2P_RELAY_ADDRESS`.
+const ENV_PREFIX: &str = "P2P_";
+
+/// The options of the command line merged over the environment and the `--config` file: flags
+/// given on the command line win over the environment, the environment over the file and the file
+/// over the defaults.
+pub struct Config<O> {
+    pub opts: O,
+    /// Problems with the file that didn't stop it from loading, to log once logging is set up.
+    pub warnings: Vec<String>,
+    /// The variables settings were taken from, `NAME=value` with the values of secrets redacted.
+    pub from_env: Vec<String>,
+    matches: ArgMatches,
+}
+
+/// Parses the command line, filling in what it leaves out from the environment and then from the
+/// TOML file its `config` flag points to. The keys of the file are the names of the flags, in
+/// snake or kebab case. The variables are the long flags in upper case with [`ENV_PREFIX`] in
+/// front; settings that can be repeated take comma separated values there.
+pub fn load<O: Parser>() -> Result<Config<O>, Box<dyn Error>> {
+    load_from(env::args_os(), |name| env::var(name))
+}
+
+/// [`load`] with the command line `args`, program name first, and the environment `var` looks
+/// variables up in.
+pub fn load_from<O: Parser>(
+    args: impl IntoIterator<Item = impl Into<OsString>>,
+    var: impl Fn(&str) -> Result<String, VarError>,
+) -> Result<Config<O>, Box<dyn Error>> {
+    let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+    let command = O::command();
+    // Required flags may come from the file, so only `--help` and the like are errors here.
+    let cli = command
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(&args)
+        .unwrap_or_else(|e| e.exit());
+    let env = from_env(&command, &cli, var)?;
+    let mut warnings = Vec::new();
+    let mut file_args = Vec::new();
+    if let Ok(Some(path)) = cli.try_get_one::<PathBuf>("config") {
+        file_args = from_file(path, &command, &cli, &env.ids, &mut warnings)?;
+    }
+    let mut merged = args.into_iter();
+    let program = merged.next().unwrap_or_default();
+    let matches = command
+        .try_get_matches_from(
+            std::iter::once(program)
+                .chain(file_args)
+                .chain(env.args)
+                .chain(merged),
+        )
+        .unwrap_or_else(|e| e.exit());
+    let opts = O::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    Ok(Config {
+        opts,
+        warnings,
+        from_env: env.names,
+        matches,
+    })
+}
+
+/// The settings taken from the environment.
+struct FromEnv {
+    args: Vec<OsString>,
+    /// The ids of the flags set.
+    ids: HashSet<String>,
+    /// `NAME=value` of each variable used, for the log.
+    names: Vec<String>,
+}
+
+/// Turns the variables set into flags, leaving out those given on the command line.
+fn from_env(
+    command: &Command,
+    cli: &ArgMatches,
+    var: impl Fn(&str) -> Result<String, VarError>,
+) -> Result<FromEnv, Box<dyn Error>> {
+    let mut env = FromEnv {
+        args: Vec::new(),
+        ids: HashSet::new(),
+        names: Vec::new(),
+    };
+    for arg in command.get_arguments() {
+        let id = arg.get_id().as_str();
+        let long = match arg.get_long() {
+            Some(long) if !OWN_FLAGS.contains(&id) => long,
+            _ => continue,
+        };
+        let name = format!("{ENV_PREFIX}{}", long.to_uppercase().replace('-', "_"));
+        let value = match var(&name) {
+            Ok(value) => value,
+            Err(VarError::NotPresent) => continue,
+            Err(VarError::NotUnicode(_)) => return Err(format!("{name} is not valid UTF-8").into()),
+        };
+        if cli.value_source(id) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        if let ArgAction::SetTrue = arg.get_action() {
+            match value.trim() {
+                "true" | "1" | "yes" => env.args.push(format!("--{long}").into()),
+                "false" | "0" | "no" | "" => {}
+                _ => return Err(format!("{name} must be true or false").into()),
+            }
+        } else {
+            let values = match arg.get_action() {
+                ArgAction::Append => value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|value| !value.is_empty())
+                    .collect(),
+                _ => vec![value.as_str()],
+            };
+            for value in values {
+                check(command, arg, value).map_err(|e| format!("{name} {e}"))?;
+                env.args.push(format!("--{long}={value}").into());
+            }
+        }
+        env.ids.insert(id.to_string());
+        env.names.push(match SECRETS.contains(&id) {
+            true => format!("{name}=<redacted>"),
+            false => format!("{name}={value}"),
+        });
+    }
+    Ok(env)
+}
+
+/// Turns the settings of the file into flags, leaving out those given on the command line or set
+/// from the environment, the `from_env` ids.
+fn from_file(
+    path: &Path,
+    command: &Command,
+    cli: &ArgMatches,
+    from_env: &HashSet<String>,
+    warnings: &mut Vec<String>,
+) -> Result<Vec<OsString>, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("failed to read config file {}: {e}", path.display()))?;
+    let table = contents
+        .parse::<Table>()
+        .map_err(|e| format!("invalid config file {}: {e}", path.display()))?;
+    let mut args = Vec::new();
+    for (key, value) in table {
+        let name = key.replace('-', "_");
+        let arg = command.get_arguments().find(|arg| {
+            arg.get_id() == name.as_str() || arg.get_long() == Some(key.replace('_', "-").as_str())
+        });
+        let arg = match arg {
+            Some(arg) if !OWN_FLAGS.contains(&arg.get_id().as_str()) => arg,
+            Some(_) => {
+                warnings.push(format!(
+                    "`{key}` can't be set in config file {}, ignored",
+                    path.display()
+                ));
+                continue;
+            }
+            None => {
+                warnings.push(format!(
+                    "Unknown key `{key}` in config file {}, ignored",
+                    path.display()
+                ));
+                continue;
+            }
+        };
+        let id = arg.get_id().as_str();
+        if cli.value_source(id) == Some(ValueSource::CommandLine) || from_env.contains(id) {
+            continue;
+        }
+        let long = arg.get_long().unwrap_or(id);
+        let invalid = |e: String| format!("config file {}: `{key}` {e}", path.display());
+        if let ArgAction::SetTrue = arg.get_action() {
+            match value {
+                Value::Boolean(true) => args.push(format!("--{long}").into()),
+                Value::Boolean(false) => {}
+                _ => return Err(invalid("must be true or false".to_string()).into()),
+            }
+            continue;
+        }
+        let values = match value {
+            Value::Array(values) if matches!(arg.get_action(), ArgAction::Append) => values,
+            Value::Array(_) => return Err(invalid("takes a single value".to_string()).into()),
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                Value::String(value) => value,
+                Value::Integer(value) => value.to_string(),
+                Value::Float(value) => value.to_string(),
+                Value::Boolean(value) => value.to_string(),
+                _ => return Err(invalid("must be a string, number or boolean".to_string()).into()),
+            };
+            check(command, arg, &value).map_err(invalid)?;
+            args.push(format!("--{long}={value}").into());
+        }
+    }
+    Ok(args)
+}
+
+/// Whether the flag takes `value`, so a bad one is reported with the key it's under.
+fn check(command: &Command, arg: &Arg, value: &str) -> Result<(), String> {
+    arg.get_value_parser()
+        .parse_ref(command, Some(arg), OsStr::new(value))
+        .map(|_| ())
+        .map_err(|e| {
+            let e = e.to_string();
+            let e = e.trim().trim_start_matches("error: ");
+            format!("has an invalid value: {}", e.lines().next().unwrap_or(e))
+        })
+}
+
+impl<O: CommandFactory> Config<O> {
+    /// The effective settings as a config file, for `--print-config`. Settings without a value
+    /// are left out, the values of secrets are redacted.
+    pub fn effective(&self) -> String {
+        let mut table = Table::new();
+        for arg in O::command().get_arguments() {
+            let id = arg.get_id().as_str();
+            if OWN_FLAGS.contains(&id) {
+                continue;
+            }
+            if let ArgAction::SetTrue = arg.get_action() {
+                table.insert(id.to_string(), Value::Boolean(self.matches.get_flag(id)));
+                continue;
+            }
+            let values = match self.matches.get_raw(id) {
+                Some(values) if SECRETS.contains(&id) => values
+                    .map(|_| Value::String("<redacted>".to_string()))
+                    .collect::<Vec<_>>(),
+                Some(values) => values
+                    .map(|value| typed(arg, &value.to_string_lossy()))
+                    .collect::<Vec<_>>(),
+                None => continue,
+            };
+            let value = match arg.get_action() {
+                ArgAction::Append => Value::Array(values),
+                _ => match values.into_iter().next() {
+                    Some(value) => value,
+                    None => continue,
+                },
+            };
+            table.insert(id.to_string(), value);
+        }
+        toml::to_string(&table).expect("a TOML table serializes")
+    }
+}
+
+/// A value of the command line as the TOML type of the flag it's for, going by the type its
+/// value parser produces. Everything else stays a string as it was given, e.g. a topic `007`.
+fn typed(arg: &Arg, value: &str) -> Value {
+    let type_id = arg.get_value_parser().type_id();
+    let integers = [
+        TypeId::of::<u8>(),
+        TypeId::of::<u16>(),
+        TypeId::of::<u32>(),
+        TypeId::of::<u64>(),
+        TypeId::of::<usize>(),
+        TypeId::of::<i32>(),
+        TypeId::of::<i64>(),
+    ];
+    let floats = [TypeId::of::<f32>(), TypeId::of::<f64>()];
+    if integers.iter().any(|id| type_id == *id) {
+        if let Ok(value) = value.parse() {
+            return Value::Integer(value);
+        }
+    } else if floats.iter().any(|id| type_id == *id) {
+        // `nan` and `inf` stay as they were given.
+        match value.parse::<f64>() {
+            Ok(float) if float.is_finite() => return Value::Float(float),
+            _ => {}
+        }
+    }
+    Value::String(value.to_string())
+}
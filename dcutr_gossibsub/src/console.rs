@@ -0,0 +1,36 @@
+use std::sync::{Mutex, OnceLock};
+
+type Sink = Box<dyn FnMut(String) -> Result<(), String> + Send>;
+
+/// Where output goes instead of stdout, set while a readline prompt or the terminal UI is active
+/// so output doesn't clobber the line being typed.
+static SINK: OnceLock<Mutex<Sink>> = OnceLock::new();
+
+pub fn set_sink(sink: impl FnMut(String) -> Result<(), String> + Send + 'static) {
+    let _ = SINK.set(Mutex::new(Box::new(sink)));
+}
+
+/// Prints a line of user facing output, use [`say!`] rather than calling this directly.
+pub fn print(line: String) {
+    match SINK.get() {
+        Some(sink) => {
+            let mut sink = sink.lock().unwrap_or_else(|e| e.into_inner());
+            if let Err(e) = sink(line.clone()) {
+                // The prompt is gone, e.g. the terminal closed, so print plainly instead.
+                tracing::debug!("Failed to print above the prompt: {e}");
+                println!("{line}");
+            }
+        }
+        None => println!("{line}"),
+    }
+}
+
+/// `println!` for output meant for the user, kept clear of the input prompt.
+#[macro_export]
+macro_rules! say {
+    ($($arg:tt)*) => {
+        $crate::console::print(format!($($arg)*))
+    };
+}
+
+pub use crate::say;
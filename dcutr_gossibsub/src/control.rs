@@ -0,0 +1,230 @@
+use futures::channel::{mpsc, oneshot};
+use libp2p::core::multiaddr::Multiaddr;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// A request line of a control client.
+#[derive(Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+enum Request {
+    Publish { topic: String, body: String },
+    Peers,
+    Dial { addr: String },
+}
+
+/// The answer to a request line, `{"ok":true,"data":...}` or `{"ok":false,"error":"..."}`.
+#[derive(Serialize)]
+pub struct Response {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Response {
+    pub fn ok(data: Value) -> Self {
+        Response {
+            ok: true,
+            data: Some(data),
+            error: None,
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.ok
+    }
+
+    pub fn error(error: impl Into<String>) -> Self {
+        Response {
+            ok: false,
+            data: None,
+            error: Some(error.into()),
+        }
+    }
+}
+
+/// A request for the event loop, to be answered through `reply`.
+pub enum ControlRequest {
+    /// A line to handle as if it was typed, `@<topic> <body>`.
+    Publish {
+        line: String,
+        reply: oneshot::Sender<Response>,
+    },
+    Peers {
+        reply: oneshot::Sender<Response>,
+    },
+    Dial {
+        addr: Multiaddr,
+        reply: oneshot::Sender<Response>,
+    },
+    Status {
+        reply: oneshot::Sender<Response>,
+    },
+    /// The received messages after id `since`, see `feed::Feed::request`.
+    Messages {
+        since: u64,
+        topic: Option<String>,
+        wait: bool,
+        reply: oneshot::Sender<Response>,
+    },
+}
+
+/// The `--control-socket`, a Unix socket local processes send newline delimited JSON requests
+/// to. Each client is served by a task of its own that hands requests to the event loop and
+/// waits for the answer, so any number of clients can be connected. The socket file is removed
+/// when this is dropped.
+pub struct ControlSocket {
+    path: PathBuf,
+}
+
+impl ControlSocket {
+    pub fn bind(
+        path: &Path,
+        requests: mpsc::UnboundedSender<ControlRequest>,
+    ) -> Result<Self, Box<dyn Error>> {
+        if path.exists() {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(format!("control socket {} is in use", path.display()).into());
+            }
+            // Left behind by a process that didn't shut down cleanly.
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)
+            .and_then(|listener| accept(listener, requests))
+            .map_err(|e| format!("failed to bind control socket {}: {e}", path.display()))?;
+        info!("Control socket listening on {}", path.display());
+        Ok(ControlSocket {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Serves each client connecting to `listener` on a task of its own.
+#[cfg(feature = "tokio")]
+fn accept(
+    listener: UnixListener,
+    requests: mpsc::UnboundedSender<ControlRequest>,
+) -> io::Result<()> {
+    listener.set_nonblocking(true)?;
+    let listener = tokio::net::UnixListener::from_std(listener)?;
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(serve(stream, requests.clone()));
+                }
+                Err(e) => warn!("Failed to accept a control connection: {e}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(feature = "tokio")]
+async fn serve(stream: tokio::net::UnixStream, requests: mpsc::UnboundedSender<ControlRequest>) {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if let Some(data) = answer(&line, &requests).await {
+            if writer.write_all(&data).await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// Serves each client connecting to `listener` on a task of its own.
+#[cfg(not(feature = "tokio"))]
+fn accept(
+    listener: UnixListener,
+    requests: mpsc::UnboundedSender<ControlRequest>,
+) -> io::Result<()> {
+    use futures::StreamExt;
+    let listener = async_std::os::unix::net::UnixListener::from(listener);
+    async_std::task::spawn(async move {
+        let mut incoming = listener.incoming();
+        while let Some(stream) = incoming.next().await {
+            match stream {
+                Ok(stream) => {
+                    async_std::task::spawn(serve(stream, requests.clone()));
+                }
+                Err(e) => warn!("Failed to accept a control connection: {e}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(feature = "tokio"))]
+async fn serve(
+    stream: async_std::os::unix::net::UnixStream,
+    requests: mpsc::UnboundedSender<ControlRequest>,
+) {
+    use futures::{AsyncBufReadExt, AsyncWriteExt, StreamExt};
+    let mut lines = async_std::io::BufReader::new(stream.clone()).lines();
+    let mut writer = stream;
+    while let Some(Ok(line)) = lines.next().await {
+        if let Some(data) = answer(&line, &requests).await {
+            if writer.write_all(&data).await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// The response line to a request line, nothing for a blank one.
+async fn answer(line: &str, requests: &mpsc::UnboundedSender<ControlRequest>) -> Option<Vec<u8>> {
+    if line.trim().is_empty() {
+        return None;
+    }
+    let response = match serde_json::from_str(line) {
+        Ok(request) => handle(request, requests).await,
+        Err(e) => Response::error(format!("malformed request: {e}")),
+    };
+    let mut data = serde_json::to_vec(&response).expect("response serializes to JSON");
+    data.push(b'\n');
+    Some(data)
+}
+
+async fn handle(request: Request, requests: &mpsc::UnboundedSender<ControlRequest>) -> Response {
+    let (reply, response) = oneshot::channel();
+    let request = match request {
+        Request::Publish { topic, body } => {
+            if topic.is_empty() || topic.contains(char::is_whitespace) {
+                return Response::error("invalid topic name");
+            }
+            if body.trim().is_empty() {
+                return Response::error("empty body");
+            }
+            ControlRequest::Publish {
+                line: format!("@{topic} {body}"),
+                reply,
+            }
+        }
+        Request::Peers => ControlRequest::Peers { reply },
+        Request::Dial { addr } => match addr.parse() {
+            Ok(addr) => ControlRequest::Dial { addr, reply },
+            Err(e) => return Response::error(format!("invalid address: {e}")),
+        },
+    };
+    if requests.unbounded_send(request).is_err() {
+        return Response::error("the node is shutting down");
+    }
+    response
+        .await
+        .unwrap_or_else(|_| Response::error("the request was not handled"))
+}
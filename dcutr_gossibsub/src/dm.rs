@@ -0,0 +1,332 @@
+use crate::codec::{read_json, write_json};
+use crate::e2e::{E2eKey, Sealed};
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncWrite};
+use libp2p::{
+    core::upgrade::ProtocolName,
+    request_response::{self, RequestId},
+    PeerId,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::time::{Duration, Instant};
+
+/// Largest direct message or ack we read.
+const MAX_DM_SIZE: usize = 1024 * 1024;
+
+/// How many received DM ids are remembered to recognise retransmissions.
+const SEEN_DMS: usize = 1024;
+
+/// A message sent to a single peer instead of a topic.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DirectMessage {
+    /// Chosen by the sender and kept across retransmissions, so the receiver can drop repeats.
+    #[serde(default)]
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_nick: Option<String>,
+    /// Unix time in milliseconds.
+    pub sent_at: u64,
+    /// Empty when the body is `sealed`.
+    #[serde(default)]
+    pub body: String,
+    /// Sent again after the receiver was unreachable.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub replayed: bool,
+    /// The body encrypted end-to-end for the receiver.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sealed: Option<Sealed>,
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+impl DirectMessage {
+    /// A DM to `to` whose body only `to` can read.
+    pub fn seal(
+        key: &E2eKey,
+        from: PeerId,
+        to: PeerId,
+        from_nick: Option<String>,
+        body: &str,
+        replayed: bool,
+    ) -> Result<Self, String> {
+        let mut message = DirectMessage {
+            id: PendingDms::new_id(),
+            from_nick,
+            sent_at: crate::envelope::unix_millis(),
+            body: String::new(),
+            replayed,
+            sealed: None,
+        };
+        message.sealed = Some(key.seal(&to, &message.aad(from, to), body.as_bytes())?);
+        Ok(message)
+    }
+
+    /// The body of a sealed DM `from` sent us, `to`.
+    pub fn open(&self, key: &E2eKey, from: PeerId, to: PeerId) -> Result<String, String> {
+        let sealed = self
+            .sealed
+            .as_ref()
+            .ok_or_else(|| "not encrypted".to_string())?;
+        let body = key.open(&from, &self.aad(from, to), sealed)?;
+        String::from_utf8(body).map_err(|_| "body is not UTF-8".to_string())
+    }
+
+    /// Everything but the body, authenticated along with it so none of it can be swapped.
+    fn aad(&self, from: PeerId, to: PeerId) -> Vec<u8> {
+        let mut aad = Vec::new();
+        for field in [
+            from.to_bytes(),
+            to.to_bytes(),
+            self.id.as_bytes().to_vec(),
+            self.from_nick
+                .as_deref()
+                .unwrap_or_default()
+                .as_bytes()
+                .to_vec(),
+            self.sent_at.to_be_bytes().to_vec(),
+            vec![self.replayed as u8],
+        ] {
+            aad.extend_from_slice(&(field.len() as u64).to_be_bytes());
+            aad.extend_from_slice(&field);
+        }
+        aad
+    }
+}
+
+/// The receiver's confirmation of a `DirectMessage`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DmAck {
+    /// Unix time in milliseconds.
+    pub received_at: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct DmProtocol;
+
+impl ProtocolName for DmProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        // Version 2 DMs are end-to-end encrypted, peers without support fail to negotiate.
+        b"/dcutr-chat/dm/2.0.0"
+    }
+}
+
+/// Length prefixed JSON.
+#[derive(Clone, Default)]
+pub struct DmCodec;
+
+#[async_trait]
+impl request_response::Codec for DmCodec {
+    type Protocol = DmProtocol;
+    type Request = DirectMessage;
+    type Response = DmAck;
+
+    async fn read_request<T>(&mut self, _: &DmProtocol, io: &mut T) -> io::Result<DirectMessage>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_json(io, MAX_DM_SIZE).await
+    }
+
+    async fn read_response<T>(&mut self, _: &DmProtocol, io: &mut T) -> io::Result<DmAck>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_json(io, MAX_DM_SIZE).await
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &DmProtocol,
+        io: &mut T,
+        request: DirectMessage,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_json(io, &request).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &DmProtocol,
+        io: &mut T,
+        response: DmAck,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_json(io, &response).await
+    }
+}
+
+/// `timeout` is how long a DM may go without its ack before the attempt counts as failed.
+pub fn behaviour(timeout: Duration) -> request_response::Behaviour<DmCodec> {
+    let mut config = request_response::Config::default();
+    config.set_request_timeout(timeout);
+    request_response::Behaviour::new(
+        DmCodec,
+        [(DmProtocol, request_response::ProtocolSupport::Full)],
+        config,
+    )
+}
+
+struct PendingDm {
+    peer_id: PeerId,
+    message: DirectMessage,
+    /// The plain body of the sealed message.
+    text: String,
+    attempts: u32,
+    in_flight: Option<RequestId>,
+    retry_at: Option<Instant>,
+}
+
+/// What happened to a DM whose attempt failed.
+pub enum DmFailure {
+    /// Another attempt is scheduled.
+    Retrying { attempt: u32 },
+    /// All attempts failed, the DM is dropped.
+    GaveUp { peer_id: PeerId, text: String },
+}
+
+/// Direct messages waiting for their ack. Failed attempts are retried with a growing delay
+/// until `max_attempts` is reached, giving at-least-once delivery.
+pub struct PendingDms {
+    max_attempts: u32,
+    pending: HashMap<String, PendingDm>,
+    requests: HashMap<RequestId, String>,
+}
+
+impl PendingDms {
+    pub fn new(retries: u32) -> Self {
+        PendingDms {
+            max_attempts: retries + 1,
+            pending: HashMap::new(),
+            requests: HashMap::new(),
+        }
+    }
+
+    pub fn new_id() -> String {
+        format!("{:016x}", rand::thread_rng().gen::<u64>())
+    }
+
+    /// Records that (another attempt of) `message` went out as `request_id`.
+    pub fn on_sent(
+        &mut self,
+        request_id: RequestId,
+        peer_id: PeerId,
+        message: DirectMessage,
+        text: String,
+    ) {
+        self.requests.insert(request_id, message.id.clone());
+        let pending = self.pending.entry(message.id.clone()).or_insert(PendingDm {
+            peer_id,
+            message,
+            text,
+            attempts: 0,
+            in_flight: None,
+            retry_at: None,
+        });
+        pending.attempts += 1;
+        pending.in_flight = Some(request_id);
+        pending.retry_at = None;
+    }
+
+    pub fn on_ack(&mut self, request_id: &RequestId) -> Option<(PeerId, String)> {
+        let id = self.requests.remove(request_id)?;
+        let pending = self.pending.remove(&id)?;
+        Some((pending.peer_id, pending.text))
+    }
+
+    pub fn on_failure(&mut self, request_id: &RequestId) -> Option<DmFailure> {
+        let id = self.requests.remove(request_id)?;
+        let pending = self.pending.get_mut(&id)?;
+        pending.in_flight = None;
+        if pending.attempts >= self.max_attempts {
+            let pending = self.pending.remove(&id).expect("present");
+            return Some(DmFailure::GaveUp {
+                peer_id: pending.peer_id,
+                text: pending.text,
+            });
+        }
+        pending.retry_at = Some(Instant::now() + Duration::from_secs(u64::from(pending.attempts)));
+        Some(DmFailure::Retrying {
+            attempt: pending.attempts + 1,
+        })
+    }
+
+    /// Gives up on all DMs to `peer_id`, e.g. because it was banned or is gone for good,
+    /// returning their plain bodies.
+    pub fn drop_peer(&mut self, peer_id: &PeerId) -> Vec<String> {
+        let mut dropped = Vec::new();
+        self.pending.retain(|_, p| {
+            let keep = &p.peer_id != peer_id;
+            if !keep {
+                dropped.push(p.text.clone());
+            }
+            keep
+        });
+        let pending = &self.pending;
+        self.requests.retain(|_, id| pending.contains_key(id));
+        dropped
+    }
+
+    /// DMs whose next attempt is due, with their plain body.
+    pub fn due(&mut self, now: Instant) -> Vec<(PeerId, DirectMessage, String)> {
+        self.pending
+            .values_mut()
+            .filter(|p| p.retry_at.map_or(false, |at| at <= now))
+            .map(|p| {
+                p.retry_at = None;
+                (p.peer_id, p.message.clone(), p.text.clone())
+            })
+            .collect()
+    }
+
+    /// One line per pending DM, for `/pending`.
+    pub fn lines(&self) -> Vec<String> {
+        self.pending
+            .values()
+            .map(|p| {
+                let state = if p.in_flight.is_some() {
+                    "awaiting ack"
+                } else {
+                    "retry scheduled"
+                };
+                format!(
+                    "{} to {}: '{}' (attempt {}/{}, {state})",
+                    p.message.id, p.peer_id, p.text, p.attempts, self.max_attempts
+                )
+            })
+            .collect()
+    }
+}
+
+/// Ids of recently received DMs, so retransmissions of a DM whose ack got lost aren't shown
+/// twice.
+#[derive(Default)]
+pub struct SeenDms {
+    order: VecDeque<(PeerId, String)>,
+    seen: HashSet<(PeerId, String)>,
+}
+
+impl SeenDms {
+    /// Whether this is the first time DM `id` from `peer_id` arrives.
+    pub fn first_time(&mut self, peer_id: PeerId, id: &str) -> bool {
+        let key = (peer_id, id.to_string());
+        if id.is_empty() || !self.seen.insert(key.clone()) {
+            return id.is_empty();
+        }
+        self.order.push_back(key);
+        if self.order.len() > SEEN_DMS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,192 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use hkdf::Hkdf;
+use libp2p::PeerId;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::{HashSet, VecDeque};
+use std::time::Duration;
+
+/// HKDF info, so the derived key is never the same as one derived from the shared secret for
+/// anything else.
+const INFO: &[u8] = b"dcutr-chat/dm/e2e/1";
+
+/// Protobuf prefix of an ed25519 public key inlined into a peer id: key type 1, 32 bytes of data.
+const ED25519_PREFIX: [u8; 4] = [0x08, 0x01, 0x12, 0x20];
+
+/// How old a sealed message may be. Older ones are rejected as replays, younger ones by their
+/// nonce.
+pub const REPLAY_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// A ciphertext and the nonce it was sealed with.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Sealed {
+    /// Base64 of the 12 byte ChaCha20-Poly1305 nonce.
+    pub nonce: String,
+    /// Base64 of the ciphertext including the tag.
+    pub ciphertext: String,
+}
+
+/// Our X25519 key, the birational image of the ed25519 identity key. A peer's X25519 public key
+/// is derived from its peer id the same way, so no extra exchange is needed and a DM can only be
+/// opened by the owner of the peer id it was sent to.
+pub struct E2eKey {
+    peer_id: PeerId,
+    /// The first half of SHA-512 of the ed25519 seed, the same scalar ed25519 signs with.
+    scalar: [u8; 32],
+}
+
+impl E2eKey {
+    pub fn from_ed25519_seed(seed: &[u8; 32], peer_id: PeerId) -> Self {
+        let mut scalar = [0; 32];
+        scalar.copy_from_slice(&Sha512::digest(seed)[..32]);
+        E2eKey { peer_id, scalar }
+    }
+
+    /// The ChaCha20-Poly1305 key shared with `peer_id`: HKDF-SHA256 over the X25519 shared secret,
+    /// salted with both peer ids in a fixed order so both sides derive the same key.
+    fn shared_key(&self, peer_id: &PeerId) -> Result<[u8; 32], String> {
+        let public = x25519_public_of(peer_id)?;
+        let shared = public.mul_clamped(self.scalar).to_bytes();
+        if shared == [0; 32] {
+            return Err(format!("{peer_id} has a low order key"));
+        }
+        let (a, b) = if self.peer_id.to_bytes() <= peer_id.to_bytes() {
+            (self.peer_id, *peer_id)
+        } else {
+            (*peer_id, self.peer_id)
+        };
+        let salt = Sha256::new()
+            .chain_update(a.to_bytes())
+            .chain_update(b.to_bytes())
+            .finalize();
+        let mut key = [0; 32];
+        Hkdf::<Sha256>::new(Some(&salt), &shared)
+            .expand(INFO, &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        Ok(key)
+    }
+
+    /// Encrypts `plaintext` for `peer_id` under a fresh random nonce. `aad` is authenticated but
+    /// not encrypted.
+    pub fn seal(&self, peer_id: &PeerId, aad: &[u8], plaintext: &[u8]) -> Result<Sealed, String> {
+        let key = self.shared_key(peer_id)?;
+        let nonce = rand::thread_rng().gen::<[u8; 12]>();
+        let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&key))
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .map_err(|_| "encryption failed".to_string())?;
+        Ok(Sealed {
+            nonce: STANDARD.encode(nonce),
+            ciphertext: STANDARD.encode(ciphertext),
+        })
+    }
+
+    /// Decrypts a message `peer_id` sealed for us with the same `aad`.
+    pub fn open(&self, peer_id: &PeerId, aad: &[u8], sealed: &Sealed) -> Result<Vec<u8>, String> {
+        let key = self.shared_key(peer_id)?;
+        let nonce = decode_nonce(sealed)?;
+        let ciphertext = STANDARD
+            .decode(&sealed.ciphertext)
+            .map_err(|e| format!("invalid ciphertext encoding: {e}"))?;
+        ChaCha20Poly1305::new(Key::from_slice(&key))
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| format!("not sealed by {peer_id} for us or tampered with"))
+    }
+}
+
+fn decode_nonce(sealed: &Sealed) -> Result<[u8; 12], String> {
+    STANDARD
+        .decode(&sealed.nonce)
+        .ok()
+        .and_then(|nonce| nonce.try_into().ok())
+        .ok_or_else(|| "invalid nonce".to_string())
+}
+
+/// The X25519 public key of an ed25519 peer id.
+fn x25519_public_of(
+    peer_id: &PeerId,
+) -> Result<curve25519_dalek::montgomery::MontgomeryPoint, String> {
+    let multihash = peer_id.as_ref();
+    let encoded = multihash.digest();
+    if multihash.code() != 0 || encoded.len() != 36 || encoded[..4] != ED25519_PREFIX {
+        return Err(format!(
+            "{peer_id} has no inlined ed25519 key to encrypt to"
+        ));
+    }
+    let mut bytes = [0; 32];
+    bytes.copy_from_slice(&encoded[4..]);
+    CompressedEdwardsY(bytes)
+        .decompress()
+        .map(|point| point.to_montgomery())
+        .ok_or_else(|| format!("{peer_id} has an invalid ed25519 key"))
+}
+
+/// Nonces of the sealed messages received within the replay window. A message whose nonce was
+/// seen before, or that is older than the window, is a replay.
+#[derive(Default)]
+pub struct ReplayGuard {
+    order: VecDeque<(u64, PeerId, [u8; 12])>,
+    seen: HashSet<(PeerId, [u8; 12])>,
+}
+
+impl ReplayGuard {
+    /// Accepts a message `peer_id` sealed at `sent_at` (unix millis), unless it's a replay.
+    pub fn check(
+        &mut self,
+        peer_id: PeerId,
+        sealed: &Sealed,
+        sent_at: u64,
+        now_millis: u64,
+    ) -> Result<(), String> {
+        let window = REPLAY_WINDOW.as_millis() as u64;
+        while let Some((at, peer, nonce)) = self.order.front() {
+            if at.saturating_add(window) >= now_millis {
+                break;
+            }
+            self.seen.remove(&(*peer, *nonce));
+            self.order.pop_front();
+        }
+        // `sent_at` is the peer's to pick, so overflowing it is a rejection too.
+        let expired = sent_at
+            .checked_add(window)
+            .map_or(true, |until| until < now_millis);
+        if expired || sent_at > now_millis.saturating_add(window) {
+            return Err("sent outside the replay window".to_string());
+        }
+        let nonce = decode_nonce(sealed)?;
+        if !self.seen.insert((peer_id, nonce)) {
+            return Err("nonce seen before".to_string());
+        }
+        // Kept until the message would fail the window check anyway, no later than the skew
+        // allows so a peer can't move its entries to the back.
+        let at = sent_at.clamp(now_millis, now_millis.saturating_add(window));
+        self.order.push_back((at, peer_id, nonce));
+        Ok(())
+    }
+
+    /// How many nonces are remembered.
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+}
@@ -0,0 +1,438 @@
+use crate::chunking::Chunk;
+use crate::signing::{self, Verification};
+use crate::topic_keys::TopicKey;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{Local, TimeZone};
+use libp2p::{gossipsub::TopicHash, identity::Keypair, PeerId};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// First byte of a CBOR encoded envelope. JSON envelopes start with `{` and bare lines of old
+/// peers with printable text, so receivers can tell the formats apart.
+const CBOR_TAG: u8 = 0x01;
+
+/// How envelopes are encoded on the wire. Receivers understand both, whatever they publish.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WireFormat {
+    Json,
+    /// Tagged CBOR, more compact for high-rate small messages.
+    Cbor,
+}
+
+impl FromStr for WireFormat {
+    type Err = String;
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "json" => Ok(WireFormat::Json),
+            "cbor" => Ok(WireFormat::Cbor),
+            _ => Err("Expected either 'json' or 'cbor'".to_string()),
+        }
+    }
+}
+
+/// What a message is for. Only chat messages are shown as conversation lines.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    #[default]
+    Chat,
+    Presence,
+    Control,
+}
+
+impl FromStr for Kind {
+    type Err = String;
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind {
+            "chat" => Ok(Kind::Chat),
+            "presence" => Ok(Kind::Presence),
+            "control" => Ok(Kind::Control),
+            _ => Err("Expected 'chat', 'presence' or 'control'".to_string()),
+        }
+    }
+}
+
+/// How long messages of a kind are worth anything, a `--ttl` like `presence=10` in seconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KindTtl {
+    pub kind: Kind,
+    pub ttl: Duration,
+}
+
+impl FromStr for KindTtl {
+    type Err = String;
+    fn from_str(ttl: &str) -> Result<Self, Self::Err> {
+        let (kind, seconds) = ttl
+            .split_once('=')
+            .ok_or_else(|| format!("Expected <kind>=<seconds>, got '{ttl}'"))?;
+        let seconds = seconds
+            .parse::<u64>()
+            .map_err(|_| format!("Expected <kind>=<seconds>, got '{ttl}'"))?;
+        Ok(KindTtl {
+            kind: kind.parse()?,
+            ttl: Duration::from_secs(seconds),
+        })
+    }
+}
+
+/// What we publish instead of the bare input line. The origin and a per-process sequence number
+/// make every publish unique, so typing the same line twice sends it twice, while a rebroadcast
+/// of the same publish still carries identical bytes and is deduplicated by gossipsub.
+///
+/// Fields added after the first version default when missing, so envelopes of older peers
+/// still parse.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Envelope {
+    pub origin: String,
+    pub seq: u64,
+    /// Consecutive number of the origin's chat messages on the topic, to notice missing ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic_seq: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_nick: Option<String>,
+    /// Unix time in milliseconds.
+    #[serde(default)]
+    pub sent_at: u64,
+    #[serde(default)]
+    pub kind: Kind,
+    /// The body is base64 of its zstd compressed text.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub compressed: bool,
+    /// The body is encrypted with the topic key of this id, after compression.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+    /// Published again for a peer that was disconnected when it was first sent.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub replayed: bool,
+    /// The `--bridge` the message entered through, so it isn't written back to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bridge: Option<String>,
+    /// The topics a `--forward` republished the message from, oldest first. Added by the
+    /// forwarding client, so it isn't signed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub forwarded_from: Vec<String>,
+    /// Unix time in milliseconds after which the message is dropped instead of shown, kept in
+    /// history or replayed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    pub body: String,
+    /// Base64 of the origin's signature over `signing::signing_bytes`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+impl Envelope {
+    pub fn encode(&self, format: WireFormat) -> Vec<u8> {
+        match format {
+            WireFormat::Json => serde_json::to_vec(self).expect("envelope serializes to JSON"),
+            WireFormat::Cbor => {
+                let mut data = vec![CBOR_TAG];
+                ciborium::ser::into_writer(self, &mut data).expect("envelope serializes to CBOR");
+                data
+            }
+        }
+    }
+
+    /// Parses an envelope in either wire format, `None` for payloads of peers that publish bare
+    /// lines and for anything undecodable.
+    pub fn decode(data: &[u8]) -> Option<Envelope> {
+        match *data.first()? {
+            CBOR_TAG => ciborium::de::from_reader(&data[1..]).ok(),
+            b'{' => serde_json::from_slice(data).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn numbered(mut self, topic_seq: u64) -> Envelope {
+        self.topic_seq = Some(topic_seq);
+        self
+    }
+
+    /// Compresses the body with zstd if it is longer than `threshold` bytes and compressing
+    /// actually makes it smaller.
+    pub fn compress(mut self, threshold: usize) -> Envelope {
+        if self.compressed || self.body.len() <= threshold {
+            return self;
+        }
+        let compressed = zstd::encode_all(self.body.as_bytes(), 0).expect("compressing in memory");
+        let encoded = STANDARD.encode(compressed);
+        if encoded.len() < self.body.len() {
+            self.body = encoded;
+            self.compressed = true;
+        }
+        self
+    }
+
+    /// Restores a compressed body, refusing to inflate it beyond `max_size` bytes.
+    pub fn decompress(mut self, max_size: usize) -> Result<Envelope, String> {
+        if !self.compressed {
+            return Ok(self);
+        }
+        let compressed = STANDARD
+            .decode(&self.body)
+            .map_err(|e| format!("invalid compressed body: {e}"))?;
+        let decoder = zstd::stream::read::Decoder::new(compressed.as_slice())
+            .map_err(|e| format!("invalid compressed body: {e}"))?;
+        let mut body = Vec::new();
+        decoder
+            .take(max_size as u64 + 1)
+            .read_to_end(&mut body)
+            .map_err(|e| format!("invalid compressed body: {e}"))?;
+        if body.len() > max_size {
+            return Err(format!("body decompresses to more than {max_size} bytes"));
+        }
+        self.body = String::from_utf8(body).map_err(|e| format!("invalid compressed body: {e}"))?;
+        self.compressed = false;
+        Ok(self)
+    }
+
+    /// Encrypts the body with `key`, bound to `topic` and this envelope. Comes after compressing,
+    /// ciphertext doesn't compress.
+    pub fn encrypt(mut self, topic: &TopicHash, key: &TopicKey) -> Envelope {
+        if self.key_id.is_some() {
+            return self;
+        }
+        self.body = key.seal(&self.aad(topic), self.body.as_bytes());
+        self.key_id = Some(key.id.clone());
+        self
+    }
+
+    /// Decrypts the body with whichever of `keys` it was encrypted with.
+    pub fn decrypt(mut self, topic: &TopicHash, keys: &[TopicKey]) -> Result<Envelope, String> {
+        let key_id = match &self.key_id {
+            Some(key_id) => key_id,
+            None => return Ok(self),
+        };
+        let key = keys
+            .iter()
+            .find(|k| &k.id == key_id)
+            .ok_or_else(|| format!("no key {key_id}"))?;
+        let body = key.open(&self.aad(topic), &self.body)?;
+        self.body = String::from_utf8(body).map_err(|e| format!("invalid encrypted body: {e}"))?;
+        self.key_id = None;
+        Ok(self)
+    }
+
+    /// Authenticated along with an encrypted body, so it can't be moved to another topic or
+    /// message.
+    fn aad(&self, topic: &TopicHash) -> Vec<u8> {
+        let mut aad = Vec::new();
+        for field in [
+            topic.as_str().as_bytes(),
+            self.origin.as_bytes(),
+            &self.seq.to_be_bytes(),
+        ] {
+            aad.extend_from_slice(&(field.len() as u64).to_be_bytes());
+            aad.extend_from_slice(field);
+        }
+        aad
+    }
+
+    /// Signs the envelope as it will be sent, i.e. after compression and encryption.
+    pub fn signed(mut self, key: &Keypair) -> Envelope {
+        signing::sign(&mut self, key);
+        self
+    }
+
+    /// Whether the envelope expired by `now_millis`, allowing for `skew` between the clocks of
+    /// sender and receiver.
+    pub fn is_expired(&self, now_millis: u64, skew: Duration) -> bool {
+        self.expires_at.map_or(false, |expires_at| {
+            now_millis > expires_at.saturating_add(skew.as_millis() as u64)
+        })
+    }
+
+    /// Identifies the envelope across peers, whatever gossipsub message id scheme they use.
+    pub fn key(&self) -> String {
+        format!("{}/{}", self.origin, self.seq)
+    }
+
+    /// The nick if the sender set one, otherwise the tail of its peer id. An origin that isn't a
+    /// peer id is shown whole.
+    pub fn sender(&self) -> &str {
+        match &self.from_nick {
+            Some(nick) => nick,
+            // Base58 is ASCII, so the cut can't land inside a char.
+            None if PeerId::from_str(&self.origin).is_ok() => {
+                &self.origin[self.origin.len().saturating_sub(8)..]
+            }
+            None => &self.origin,
+        }
+    }
+}
+
+impl Envelope {
+    /// Formats as `12:31 <alice mark> hello`, in local time, or without the space and mark if
+    /// it is empty.
+    fn line(&self, mark: &str) -> String {
+        let time = match Local.timestamp_millis_opt(self.sent_at as i64).single() {
+            Some(sent_at) if self.sent_at > 0 => sent_at.format("%H:%M").to_string(),
+            _ => "--:--".to_string(),
+        };
+        let sender = match mark {
+            "" => self.sender().to_string(),
+            mark => format!("{} {mark}", self.sender()),
+        };
+        format!("{time} <{sender}> {}", self.body)
+    }
+}
+
+/// Formats as `12:31 <alice> hello`, in local time.
+impl fmt::Display for Envelope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.line(""))
+    }
+}
+
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Wraps outgoing lines in envelopes with increasing sequence numbers. Numbering starts at the
+/// current time so that a restarted process doesn't repeat the ids of its previous run.
+pub struct Sequencer {
+    origin: String,
+    nick: Option<String>,
+    next: u64,
+    /// How long envelopes of each kind live, forever if missing.
+    ttls: HashMap<Kind, Duration>,
+}
+
+impl Sequencer {
+    pub fn new(origin: PeerId, nick: Option<String>) -> Self {
+        Sequencer {
+            origin: origin.to_base58(),
+            nick,
+            next: unix_millis() * 1000,
+            ttls: HashMap::new(),
+        }
+    }
+
+    /// Lets the envelopes of the given kinds expire.
+    pub fn with_ttls(mut self, ttls: &[KindTtl]) -> Self {
+        self.ttls = ttls.iter().map(|ttl| (ttl.kind, ttl.ttl)).collect();
+        self
+    }
+
+    pub fn wrap(&mut self, kind: Kind, body: &str) -> Envelope {
+        let seq = self.next;
+        self.next += 1;
+        let sent_at = unix_millis();
+        Envelope {
+            origin: self.origin.clone(),
+            seq,
+            topic_seq: None,
+            from_nick: self.nick.clone(),
+            sent_at,
+            kind,
+            compressed: false,
+            key_id: None,
+            replayed: false,
+            bridge: None,
+            forwarded_from: Vec::new(),
+            expires_at: self
+                .ttls
+                .get(&kind)
+                .map(|ttl| sent_at + ttl.as_millis() as u64),
+            body: body.to_string(),
+            signature: None,
+        }
+    }
+}
+
+/// The envelope in `data` with its body decompressed, up to `max_decompressed` bytes, unless it
+/// is encrypted. `None` for payloads that aren't envelopes.
+pub fn open(data: &[u8], max_decompressed: usize) -> Option<Result<Envelope, String>> {
+    let envelope = Envelope::decode(data)?;
+    Some(match envelope.key_id {
+        Some(_) => Ok(envelope),
+        None => envelope.decompress(max_decompressed),
+    })
+}
+
+/// The text of a payload we published, enveloped or not, with bodies decompressed up to
+/// `max_decompressed` bytes.
+pub fn text_of(data: &[u8], max_decompressed: usize) -> String {
+    if let Some(envelope) = open(data, max_decompressed) {
+        return match envelope {
+            Ok(envelope) if envelope.key_id.is_some() => "<encrypted>".to_string(),
+            Ok(envelope) => envelope.body,
+            Err(e) => format!("<{e}>"),
+        };
+    }
+    if let Some(chunk) = Chunk::decode(data) {
+        return format!(
+            "<chunk {}/{} of message {}>",
+            chunk.chunk_index + 1,
+            chunk.chunk_count,
+            chunk.msg_id
+        );
+    }
+    String::from_utf8_lossy(data).into_owned()
+}
+
+/// A received payload as shown on the console, with the envelope's signature checked against
+/// `source`, the message's gossipsub origin. Payloads of peers on the old format, which publish
+/// bare lines, are shown as they are and marked `[raw]`, anything else that can't be decoded as
+/// hex. Encrypted bodies are decrypted with `keys`, the keys of `topic`, and shown as
+/// `[encrypted, no key]` if none fits. Fails for compressed bodies inflating beyond
+/// `max_decompressed` bytes.
+pub fn render(
+    data: &[u8],
+    source: Option<PeerId>,
+    max_decompressed: usize,
+    topic: &TopicHash,
+    keys: &[TopicKey],
+) -> Result<String, String> {
+    if let Some(envelope) = Envelope::decode(data) {
+        let verification = signing::verify(&envelope, source);
+        let replayed = if envelope.replayed { "[replayed] " } else { "" };
+        let line = match envelope.clone().decrypt(topic, keys) {
+            Ok(envelope) => envelope
+                .decompress(max_decompressed)?
+                .line(&verification.to_string()),
+            Err(e) => {
+                info!("Can't decrypt message {}: {e}", envelope.key());
+                Envelope {
+                    body: "[encrypted, no key]".to_string(),
+                    ..envelope
+                }
+                .line(&verification.to_string())
+            }
+        };
+        let line = format!("{replayed}{line}");
+        if let Verification::Invalid(reason) = &verification {
+            warn!("Message with a bad signature: {reason}");
+            return Ok(format!(
+                "!!! WARNING: SIGNATURE CHECK FAILED ({reason}) !!! {line}"
+            ));
+        }
+        return Ok(line);
+    }
+    match std::str::from_utf8(data) {
+        Ok(text) if !text.starts_with(CBOR_TAG as char) => Ok(format!("[raw] {text}")),
+        _ => {
+            warn!(
+                "Received a payload of {} bytes that could not be decoded",
+                data.len()
+            );
+            Ok(format!("[hex] {}", hex(data)))
+        }
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
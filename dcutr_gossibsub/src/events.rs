@@ -0,0 +1,278 @@
+use crate::envelope;
+use libp2p::{
+    core::{multiaddr::Multiaddr, ConnectedPoint},
+    dcutr, identify, relay, PeerId,
+};
+use serde::Serialize;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+
+/// A lifecycle event of the node as written to `--event-file`, one JSON object per line with a
+/// `timestamp` in unix milliseconds and the kind of event in `event`, e.g.
+///
+/// ```text
+/// {"timestamp":1700000000000,"event":"hole_punch_succeeded","peer_id":"12D3KooW..."}
+/// ```
+///
+/// The matches below list every variant of the libp2p events they map, so new upstream variants
+/// fail to compile here rather than silently going missing from the output. Field names are
+/// stable, new events and fields may be added.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum NodeEvent {
+    ListenAddr {
+        address: String,
+    },
+    ConnectionEstablished {
+        peer_id: String,
+        endpoint: Endpoint,
+    },
+    ConnectionClosed {
+        peer_id: String,
+        endpoint: Endpoint,
+        cause: Option<String>,
+    },
+    ReservationAccepted {
+        relay_peer_id: String,
+        renewal: bool,
+    },
+    ReservationFailed {
+        relay_peer_id: String,
+        renewal: bool,
+        error: String,
+    },
+    CircuitEstablished {
+        direction: Direction,
+        peer_id: String,
+    },
+    CircuitFailed {
+        direction: Direction,
+        peer_id: String,
+        error: String,
+    },
+    HolePunchStarted {
+        peer_id: String,
+        /// Who initiated the upgrade, `local` or `remote`.
+        initiator: &'static str,
+    },
+    HolePunchSucceeded {
+        peer_id: String,
+    },
+    HolePunchFailed {
+        peer_id: String,
+        error: String,
+    },
+    IdentifyReceived {
+        peer_id: String,
+        agent_version: String,
+        protocol_version: String,
+        listen_addrs: Vec<String>,
+        observed_addr: String,
+        protocols: Vec<String>,
+    },
+    PublishError {
+        topic: String,
+        error: String,
+    },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// The remote end of a connection.
+#[derive(Debug, Serialize)]
+pub struct Endpoint {
+    /// `dialer` if we dialed, `listener` if the peer did.
+    pub role: &'static str,
+    pub address: String,
+    pub relayed: bool,
+}
+
+impl From<&ConnectedPoint> for Endpoint {
+    fn from(endpoint: &ConnectedPoint) -> Self {
+        let address = endpoint.get_remote_address();
+        Endpoint {
+            role: match endpoint {
+                ConnectedPoint::Dialer { .. } => "dialer",
+                ConnectedPoint::Listener { .. } => "listener",
+            },
+            address: address.to_string(),
+            // An inbound circuit's remote address is just the dialer's `/p2p`.
+            relayed: endpoint.is_relayed(),
+        }
+    }
+}
+
+impl NodeEvent {
+    pub fn listen_addr(address: &Multiaddr) -> Self {
+        NodeEvent::ListenAddr {
+            address: address.to_string(),
+        }
+    }
+
+    pub fn connection_established(peer_id: &PeerId, endpoint: &ConnectedPoint) -> Self {
+        NodeEvent::ConnectionEstablished {
+            peer_id: peer_id.to_string(),
+            endpoint: endpoint.into(),
+        }
+    }
+
+    pub fn connection_closed(
+        peer_id: &PeerId,
+        endpoint: &ConnectedPoint,
+        cause: Option<String>,
+    ) -> Self {
+        NodeEvent::ConnectionClosed {
+            peer_id: peer_id.to_string(),
+            endpoint: endpoint.into(),
+            cause,
+        }
+    }
+
+    pub fn from_relay(event: &relay::client::Event) -> Option<Self> {
+        use relay::client::Event;
+        Some(match event {
+            Event::ReservationReqAccepted {
+                relay_peer_id,
+                renewal,
+                ..
+            } => NodeEvent::ReservationAccepted {
+                relay_peer_id: relay_peer_id.to_string(),
+                renewal: *renewal,
+            },
+            Event::ReservationReqFailed {
+                relay_peer_id,
+                renewal,
+                error,
+            } => NodeEvent::ReservationFailed {
+                relay_peer_id: relay_peer_id.to_string(),
+                renewal: *renewal,
+                error: format!("{error:?}"),
+            },
+            Event::OutboundCircuitEstablished { relay_peer_id, .. } => {
+                NodeEvent::CircuitEstablished {
+                    direction: Direction::Outbound,
+                    peer_id: relay_peer_id.to_string(),
+                }
+            }
+            Event::OutboundCircuitReqFailed {
+                relay_peer_id,
+                error,
+            } => NodeEvent::CircuitFailed {
+                direction: Direction::Outbound,
+                peer_id: relay_peer_id.to_string(),
+                error: format!("{error:?}"),
+            },
+            Event::InboundCircuitEstablished { src_peer_id, .. } => NodeEvent::CircuitEstablished {
+                direction: Direction::Inbound,
+                peer_id: src_peer_id.to_string(),
+            },
+            Event::InboundCircuitReqFailed {
+                relay_peer_id,
+                error,
+            } => NodeEvent::CircuitFailed {
+                direction: Direction::Inbound,
+                peer_id: relay_peer_id.to_string(),
+                error: format!("{error:?}"),
+            },
+            Event::InboundCircuitReqDenied { src_peer_id } => NodeEvent::CircuitFailed {
+                direction: Direction::Inbound,
+                peer_id: src_peer_id.to_string(),
+                error: "denied".to_string(),
+            },
+            Event::InboundCircuitReqDenyFailed { .. } => return None,
+        })
+    }
+
+    pub fn from_dcutr(event: &dcutr::Event) -> Option<Self> {
+        use dcutr::Event;
+        Some(match event {
+            Event::InitiatedDirectConnectionUpgrade { remote_peer_id, .. } => {
+                NodeEvent::HolePunchStarted {
+                    peer_id: remote_peer_id.to_string(),
+                    initiator: "local",
+                }
+            }
+            Event::RemoteInitiatedDirectConnectionUpgrade { remote_peer_id, .. } => {
+                NodeEvent::HolePunchStarted {
+                    peer_id: remote_peer_id.to_string(),
+                    initiator: "remote",
+                }
+            }
+            Event::DirectConnectionUpgradeSucceeded { remote_peer_id } => {
+                NodeEvent::HolePunchSucceeded {
+                    peer_id: remote_peer_id.to_string(),
+                }
+            }
+            Event::DirectConnectionUpgradeFailed {
+                remote_peer_id,
+                error,
+            } => NodeEvent::HolePunchFailed {
+                peer_id: remote_peer_id.to_string(),
+                error: format!("{error:?}"),
+            },
+        })
+    }
+
+    pub fn from_identify(event: &identify::Event) -> Option<Self> {
+        use identify::Event;
+        match event {
+            Event::Received { peer_id, info } => Some(NodeEvent::IdentifyReceived {
+                peer_id: peer_id.to_string(),
+                agent_version: info.agent_version.clone(),
+                protocol_version: info.protocol_version.clone(),
+                listen_addrs: info.listen_addrs.iter().map(|a| a.to_string()).collect(),
+                observed_addr: info.observed_addr.to_string(),
+                protocols: info.protocols.clone(),
+            }),
+            Event::Sent { .. } | Event::Pushed { .. } | Event::Error { .. } => None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    timestamp: u64,
+    #[serde(flatten)]
+    event: &'a NodeEvent,
+}
+
+/// Where `--event-file` events are written, `-` for stdout.
+pub struct EventLog {
+    out: Box<dyn Write>,
+}
+
+impl EventLog {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let out: Box<dyn Write> = if path == Path::new("-") {
+            Box::new(io::stdout())
+        } else {
+            let file: File = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("failed to open event file {}: {e}", path.display()))?;
+            // Each event is flushed as a whole, for tools following the file.
+            Box::new(LineWriter::new(file))
+        };
+        Ok(EventLog { out })
+    }
+
+    pub fn write(&mut self, event: &NodeEvent) {
+        let line = Line {
+            timestamp: envelope::unix_millis(),
+            event,
+        };
+        let mut data = serde_json::to_vec(&line).expect("event serializes to JSON");
+        data.push(b'\n');
+        if let Err(e) = self.out.write_all(&data).and_then(|()| self.out.flush()) {
+            tracing::warn!("Failed to write to the event file: {e}");
+        }
+    }
+}
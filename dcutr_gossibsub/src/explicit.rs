@@ -0,0 +1,87 @@
+use crate::console::say;
+use libp2p::PeerId;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The peers gossipsub always forwards to and never prunes, whatever the mesh looks like: the
+/// configured remote peers plus those added with `/peer add`. The added ones are kept in the
+/// data dir.
+pub struct ExplicitPeers {
+    path: Option<PathBuf>,
+    configured: BTreeSet<PeerId>,
+    added: BTreeSet<PeerId>,
+}
+
+impl ExplicitPeers {
+    pub fn load(
+        dir: Option<&Path>,
+        peer_id: &PeerId,
+        configured: &[PeerId],
+    ) -> Result<Self, Box<dyn Error>> {
+        let path = dir.map(|dir| dir.join(format!("explicit-peers-{peer_id}.json")));
+        let added = match &path {
+            Some(path) if path.exists() => {
+                let contents = fs::read(path)
+                    .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+                serde_json::from_slice::<Vec<String>>(&contents)
+                    .map_err(|e| format!("invalid explicit peers file {}: {e}", path.display()))?
+                    .iter()
+                    .map(|peer_id| {
+                        peer_id.parse::<PeerId>().map_err(|e| {
+                            format!("{}: invalid peer id {peer_id:?}: {e}", path.display())
+                        })
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+            _ => BTreeSet::new(),
+        };
+        Ok(ExplicitPeers {
+            path,
+            configured: configured.iter().copied().collect(),
+            added,
+        })
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.configured.union(&self.added)
+    }
+
+    pub fn contains(&self, peer_id: &PeerId) -> bool {
+        self.configured.contains(peer_id) || self.added.contains(peer_id)
+    }
+
+    /// Adds `peer_id`, returning false if it was explicit already.
+    pub fn add(&mut self, peer_id: PeerId) -> bool {
+        if self.contains(&peer_id) {
+            return false;
+        }
+        self.added.insert(peer_id);
+        self.save();
+        true
+    }
+
+    /// Removes `peer_id` for good, or until the next start if it's a configured remote peer.
+    /// Returns false if it wasn't explicit.
+    pub fn remove(&mut self, peer_id: &PeerId) -> bool {
+        let configured = self.configured.remove(peer_id);
+        if !self.added.remove(peer_id) {
+            return configured;
+        }
+        self.save();
+        true
+    }
+
+    fn save(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let peers = self.added.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        let contents = serde_json::to_vec(&peers).expect("peer ids serialize to JSON");
+        if let Err(e) = fs::write(path, contents) {
+            say!("Failed to save explicit peers to {}: {e}", path.display());
+        }
+    }
+}
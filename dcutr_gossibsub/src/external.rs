@@ -0,0 +1,85 @@
+use crate::holepunch::is_relayed;
+use libp2p::core::multiaddr::{Multiaddr, Protocol};
+use std::fmt;
+
+/// Checks that a configured external address can be dialed through our TCP transport.
+pub fn validate(addr: &Multiaddr) -> Result<(), String> {
+    let mut protocols = addr.iter();
+    match protocols.next() {
+        Some(
+            Protocol::Ip4(_)
+            | Protocol::Ip6(_)
+            | Protocol::Dns(_)
+            | Protocol::Dns4(_)
+            | Protocol::Dns6(_),
+        ) => {}
+        _ => {
+            return Err(format!(
+                "external address {addr} must start with /ip4, /ip6 or /dns"
+            ))
+        }
+    }
+    match protocols.next() {
+        Some(Protocol::Tcp(_)) => {}
+        _ => {
+            return Err(format!(
+                "external address {addr} must use /tcp, the only enabled transport"
+            ))
+        }
+    }
+    if is_relayed(addr) {
+        return Err(format!(
+            "external address {addr} must not be a relayed address"
+        ));
+    }
+    Ok(())
+}
+
+/// Statically configured external addresses and whether a peer has confirmed them, either by
+/// reporting them as our observed address or through AutoNAT.
+pub struct ExternalAddresses {
+    entries: Vec<(Multiaddr, bool)>,
+}
+
+impl ExternalAddresses {
+    pub fn new(configured: Vec<Multiaddr>) -> Self {
+        ExternalAddresses {
+            entries: configured.into_iter().map(|addr| (addr, false)).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Multiaddr> {
+        self.entries.iter().map(|(addr, _)| addr)
+    }
+
+    /// Marks `addr` as confirmed, returning whether that changed anything.
+    pub fn confirm(&mut self, addr: &Multiaddr) -> bool {
+        let mut changed = false;
+        for (configured, confirmed) in self.entries.iter_mut() {
+            if configured == addr && !*confirmed {
+                *confirmed = true;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+impl fmt::Display for ExternalAddresses {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "External addresses:")?;
+        for (addr, confirmed) in &self.entries {
+            let state = if *confirmed {
+                "confirmed"
+            } else {
+                "configured"
+            };
+            write!(f, "\n  {addr} ({state})")?;
+        }
+        Ok(())
+    }
+}
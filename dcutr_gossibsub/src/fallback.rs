@@ -0,0 +1,248 @@
+use crate::codec::{read_json, write_json};
+use crate::envelope::Envelope;
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::{AsyncRead, AsyncWrite};
+use libp2p::{core::upgrade::ProtocolName, gossipsub::TopicHash, request_response, PeerId};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::time::{Duration, Instant};
+
+pub const PROTOCOL: &str = "/chat-fallback/1";
+
+/// Largest message taken over the fallback channel, which doesn't chunk.
+const MAX_FALLBACK_SIZE: usize = 1024 * 1024;
+
+/// How many delivered messages are remembered to drop the copy that arrives second.
+const DELIVERED_SIZE: usize = 1024;
+
+/// A message published while the fallback channel is open, sent to the peer as well. The topic
+/// goes by name, so peers disagreeing on how topics are hashed still understand each other.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FallbackMessage {
+    pub topic: String,
+    /// The encoded envelope as published on gossipsub, base64.
+    data: String,
+}
+
+impl FallbackMessage {
+    pub fn new(topic: String, data: &[u8]) -> Self {
+        FallbackMessage {
+            topic,
+            data: STANDARD.encode(data),
+        }
+    }
+
+    pub fn data(&self) -> Result<Vec<u8>, String> {
+        STANDARD
+            .decode(&self.data)
+            .map_err(|e| format!("invalid fallback message: {e}"))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FallbackProtocol;
+
+impl ProtocolName for FallbackProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        PROTOCOL.as_bytes()
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct FallbackCodec;
+
+#[async_trait]
+impl request_response::Codec for FallbackCodec {
+    type Protocol = FallbackProtocol;
+    type Request = FallbackMessage;
+    /// Only acknowledges the message.
+    type Response = ();
+
+    async fn read_request<T>(
+        &mut self,
+        _: &FallbackProtocol,
+        io: &mut T,
+    ) -> io::Result<FallbackMessage>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_json(io, MAX_FALLBACK_SIZE).await
+    }
+
+    async fn read_response<T>(&mut self, _: &FallbackProtocol, io: &mut T) -> io::Result<()>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_json(io, 16).await
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &FallbackProtocol,
+        io: &mut T,
+        message: FallbackMessage,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_json(io, &message).await
+    }
+
+    async fn write_response<T>(&mut self, _: &FallbackProtocol, io: &mut T, _: ()) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_json(io, &()).await
+    }
+}
+
+pub fn behaviour() -> request_response::Behaviour<FallbackCodec> {
+    request_response::Behaviour::new(
+        FallbackCodec,
+        [(FallbackProtocol, request_response::ProtocolSupport::Full)],
+        request_response::Config::default(),
+    )
+}
+
+/// The fallback channel to a peer on a topic opening or closing.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Change {
+    Opened { peer_id: PeerId, topic: TopicHash },
+    Closed { peer_id: PeerId, topic: TopicHash },
+}
+
+/// Notices peers we're connected to while gossipsub has no mesh on a topic, which leaves a
+/// session connected but silent. Once a peer was connected for `after` with fewer than
+/// `min_mesh` mesh peers on a topic, what we publish there is sent to the peer directly as
+/// well, until the mesh forms.
+pub struct FallbackChannel {
+    after: Duration,
+    min_mesh: usize,
+    /// The peers we're connected to that speak the protocol, with the time they were first seen.
+    connected: HashMap<PeerId, Instant>,
+    open: HashSet<(PeerId, TopicHash)>,
+    /// Peers that don't speak the fallback protocol, never tried again.
+    unsupported: HashSet<PeerId>,
+    /// The envelope keys of the last messages delivered over either channel, oldest first.
+    delivered: VecDeque<String>,
+}
+
+impl FallbackChannel {
+    pub fn new(after: Duration, min_mesh: usize) -> Self {
+        FallbackChannel {
+            after,
+            min_mesh: min_mesh.max(1),
+            connected: HashMap::new(),
+            open: HashSet::new(),
+            unsupported: HashSet::new(),
+            delivered: VecDeque::new(),
+        }
+    }
+
+    /// Notes a message delivered over gossipsub or directly, returning false if it was already
+    /// delivered over the other channel. Bare payloads can't be told apart and are always new.
+    pub fn on_delivered(&mut self, data: &[u8]) -> bool {
+        let key = match Envelope::decode(data) {
+            Some(envelope) => envelope.key(),
+            None => return true,
+        };
+        if self.delivered.contains(&key) {
+            return false;
+        }
+        if self.delivered.len() >= DELIVERED_SIZE {
+            self.delivered.pop_front();
+        }
+        self.delivered.push_back(key);
+        true
+    }
+
+    /// Notes a connected peer, the first time counting when it's seen more than once.
+    pub fn on_connected(&mut self, peer_id: PeerId, now: Instant) {
+        self.connected.entry(peer_id).or_insert(now);
+    }
+
+    /// Forgets a peer whose last connection closed, returning the channels that closed with it.
+    pub fn on_disconnected(&mut self, peer_id: &PeerId) -> Vec<TopicHash> {
+        self.connected.remove(peer_id);
+        self.close_all(peer_id)
+    }
+
+    /// Closes the channels to a peer that turned out not to speak the protocol.
+    pub fn on_unsupported(&mut self, peer_id: PeerId) -> Vec<TopicHash> {
+        self.unsupported.insert(peer_id);
+        self.close_all(&peer_id)
+    }
+
+    /// Opens the channels of peers connected for long enough to `topics` whose mesh has fewer
+    /// than the minimum peers, `mesh_size` telling how many it has, and closes those of topics
+    /// whose mesh formed. Only peers `subscribed` to a topic, as gossipsub knows them, get a
+    /// channel on it, and lose it once they leave.
+    pub fn poll<'a>(
+        &mut self,
+        now: Instant,
+        topics: impl IntoIterator<Item = &'a TopicHash>,
+        mesh_size: impl Fn(&TopicHash) -> usize,
+        subscribed: impl Fn(&PeerId, &TopicHash) -> bool,
+    ) -> Vec<Change> {
+        let mut changes = Vec::new();
+        for topic in topics {
+            let formed = mesh_size(topic) >= self.min_mesh;
+            for (peer_id, since) in &self.connected {
+                let key = (*peer_id, topic.clone());
+                let is_open = self.open.contains(&key);
+                let due = now.saturating_duration_since(*since) >= self.after;
+                let subscribed = subscribed(peer_id, topic);
+                if is_open && (formed || !subscribed) {
+                    self.open.remove(&key);
+                    changes.push(Change::Closed {
+                        peer_id: *peer_id,
+                        topic: topic.clone(),
+                    });
+                } else if !formed
+                    && !is_open
+                    && due
+                    && subscribed
+                    && !self.unsupported.contains(peer_id)
+                {
+                    self.open.insert(key);
+                    changes.push(Change::Opened {
+                        peer_id: *peer_id,
+                        topic: topic.clone(),
+                    });
+                }
+            }
+        }
+        changes
+    }
+
+    /// The peers to send what we publish on `topic` to directly.
+    pub fn peers(&self, topic: &TopicHash) -> Vec<PeerId> {
+        self.open
+            .iter()
+            .filter(|(_, t)| t == topic)
+            .map(|(peer_id, _)| *peer_id)
+            .collect()
+    }
+
+    pub fn is_open(&self, peer_id: &PeerId, topic: &TopicHash) -> bool {
+        self.open.contains(&(*peer_id, topic.clone()))
+    }
+
+    /// Closes the channels of a topic we left.
+    pub fn on_left(&mut self, topic: &TopicHash) {
+        self.open.retain(|(_, t)| t != topic);
+    }
+
+    fn close_all(&mut self, peer_id: &PeerId) -> Vec<TopicHash> {
+        let closed = self
+            .open
+            .iter()
+            .filter(|(p, _)| p == peer_id)
+            .map(|(_, topic)| topic.clone())
+            .collect::<Vec<_>>();
+        self.open.retain(|(p, _)| p != peer_id);
+        closed
+    }
+}
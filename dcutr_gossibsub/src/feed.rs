@@ -0,0 +1,76 @@
+use crate::control::Response;
+use crate::output::JsonMessage;
+use futures::channel::oneshot;
+use serde_json::Value;
+use std::collections::VecDeque;
+
+/// How many received messages `GET /messages` can page through.
+const CAPACITY: usize = 1000;
+
+struct Waiter {
+    since: u64,
+    topic: Option<String>,
+    reply: oneshot::Sender<Response>,
+}
+
+/// The latest received messages numbered in arrival order, for `GET /messages`. Requests for
+/// messages after the newest wait until one arrives, for long polling.
+#[derive(Default)]
+pub struct Feed {
+    last_id: u64,
+    messages: VecDeque<(u64, JsonMessage)>,
+    waiters: Vec<Waiter>,
+}
+
+impl Feed {
+    pub fn push(&mut self, message: JsonMessage) {
+        self.last_id += 1;
+        self.messages.push_back((self.last_id, message));
+        if self.messages.len() > CAPACITY {
+            self.messages.pop_front();
+        }
+        for waiter in std::mem::take(&mut self.waiters) {
+            let page = self.page(waiter.since, waiter.topic.as_deref());
+            if page.is_empty() {
+                self.waiters.push(waiter);
+            } else {
+                // The client may have given up waiting already.
+                let _ = waiter.reply.send(Response::ok(Value::Array(page)));
+            }
+        }
+        self.waiters.retain(|waiter| !waiter.reply.is_canceled());
+    }
+
+    /// Answers with the messages after id `since`, on `topic` if given. If there are none and
+    /// `wait` is set the answer is held back until there are.
+    pub fn request(
+        &mut self,
+        since: u64,
+        topic: Option<String>,
+        wait: bool,
+        reply: oneshot::Sender<Response>,
+    ) {
+        let page = self.page(since, topic.as_deref());
+        if page.is_empty() && wait {
+            self.waiters.push(Waiter {
+                since,
+                topic,
+                reply,
+            });
+        } else {
+            let _ = reply.send(Response::ok(Value::Array(page)));
+        }
+    }
+
+    fn page(&self, since: u64, topic: Option<&str>) -> Vec<Value> {
+        self.messages
+            .iter()
+            .filter(|(id, message)| *id > since && topic.map_or(true, |t| t == message.topic))
+            .map(|(id, message)| {
+                let mut value = serde_json::to_value(message).expect("message serializes to JSON");
+                value["id"] = Value::from(*id);
+                value
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,111 @@
+use crate::envelope::Envelope;
+use std::str::FromStr;
+
+/// A `--forward` argument, `<from>=<to>` one way or `<a>~<b>` both ways.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ForwardSpec {
+    pub routes: Vec<(String, String)>,
+}
+
+impl FromStr for ForwardSpec {
+    type Err = String;
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (a, b, both) = match (spec.split_once('='), spec.split_once('~')) {
+            (Some((from, to)), None) => (from, to, false),
+            (None, Some((a, b))) => (a, b, true),
+            _ => {
+                return Err(format!(
+                    "invalid forward '{spec}', expected <from>=<to> or <a>~<b>"
+                ))
+            }
+        };
+        if a.is_empty() || b.is_empty() || a == b {
+            return Err(format!(
+                "invalid forward '{spec}', expected two different topics"
+            ));
+        }
+        let mut routes = vec![(a.to_string(), b.to_string())];
+        if both {
+            routes.push((b.to_string(), a.to_string()));
+        }
+        Ok(ForwardSpec { routes })
+    }
+}
+
+struct Route {
+    from: String,
+    to: String,
+    forwarded: u64,
+    /// Already forwarded to `to` before, by us or another forwarding client.
+    looped: u64,
+    /// Raw or encrypted payloads, which can't carry the marker.
+    skipped: u64,
+}
+
+/// Republishes the messages of one topic on another. The envelope is passed on unchanged, its
+/// signature still the author's, with the source topic added to `forwarded_from`. An envelope
+/// that passed through the destination before isn't forwarded there again, which ends loops of
+/// any length and between any number of forwarding clients.
+#[derive(Default)]
+pub struct Forwarder {
+    routes: Vec<Route>,
+}
+
+impl Forwarder {
+    pub fn new(specs: &[ForwardSpec]) -> Self {
+        let routes = specs
+            .iter()
+            .flat_map(|spec| spec.routes.iter())
+            .map(|(from, to)| Route {
+                from: from.clone(),
+                to: to.clone(),
+                forwarded: 0,
+                looped: 0,
+                skipped: 0,
+            })
+            .collect();
+        Forwarder { routes }
+    }
+
+    /// The topics messages are forwarded from or to, which have to be subscribed.
+    pub fn topics(&self) -> impl Iterator<Item = &str> {
+        self.routes
+            .iter()
+            .flat_map(|route| [route.from.as_str(), route.to.as_str()])
+    }
+
+    /// The topics to republish a message of `topic` on, with the envelope to publish there.
+    pub fn on_message(&mut self, topic: &str, data: &[u8]) -> Vec<(String, Envelope)> {
+        let mut forwards = Vec::new();
+        for route in self.routes.iter_mut().filter(|route| route.from == topic) {
+            let mut envelope = match Envelope::decode(data) {
+                Some(envelope) if envelope.key_id.is_none() => envelope,
+                _ => {
+                    route.skipped += 1;
+                    continue;
+                }
+            };
+            if envelope.forwarded_from.contains(&route.to) {
+                route.looped += 1;
+                continue;
+            }
+            envelope.forwarded_from.push(route.from.clone());
+            route.forwarded += 1;
+            forwards.push((route.to.clone(), envelope));
+        }
+        forwards
+    }
+
+    /// One line per route for `/stats`.
+    pub fn lines(&self) -> Vec<String> {
+        self.routes
+            .iter()
+            .map(|route| {
+                format!(
+                    "forward '{}' -> '{}': {} forwarded, {} looped, {} skipped",
+                    route.from, route.to, route.forwarded, route.looped, route.skipped
+                )
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,115 @@
+use crate::http;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The outcome of one readiness check, with what was found for the probe output.
+#[derive(Clone, Debug, Serialize)]
+pub struct Check {
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl Check {
+    pub fn new(ok: bool, detail: impl Into<String>) -> Self {
+        Check {
+            ok,
+            detail: detail.into(),
+        }
+    }
+}
+
+struct State {
+    last_tick: Instant,
+    checks: Vec<(&'static str, Check)>,
+}
+
+/// What `/healthz` and `/readyz` answer. The event loop reports in on every tick with the
+/// readiness checks it evaluated, the HTTP handlers only read what was reported last.
+#[derive(Clone)]
+pub struct Health {
+    state: Arc<Mutex<State>>,
+    stale_after: Duration,
+}
+
+impl Health {
+    /// The event loop counts as stuck once it hasn't ticked for `stale_after`.
+    pub fn new(stale_after: Duration) -> Self {
+        Health {
+            state: Arc::new(Mutex::new(State {
+                last_tick: Instant::now(),
+                checks: Vec::new(),
+            })),
+            stale_after,
+        }
+    }
+
+    pub fn on_tick(&self, checks: Vec<(&'static str, Check)>) {
+        let mut state = self.state.lock().unwrap();
+        state.last_tick = Instant::now();
+        state.checks = checks;
+    }
+
+    fn event_loop(&self, state: &State) -> Check {
+        let since = state.last_tick.elapsed();
+        Check::new(
+            since <= self.stale_after,
+            format!("last tick {:.1}s ago", since.as_secs_f64()),
+        )
+    }
+
+    /// Alive as long as the event loop ticks.
+    fn liveness(&self) -> (bool, Value) {
+        let state = self.state.lock().unwrap();
+        report(vec![("event_loop", self.event_loop(&state))])
+    }
+
+    /// Ready once the event loop ticks and every check it reported passed.
+    fn readiness(&self) -> (bool, Value) {
+        let state = self.state.lock().unwrap();
+        let mut checks = vec![("event_loop", self.event_loop(&state))];
+        if state.checks.is_empty() {
+            checks.push(("started", Check::new(false, "no checks reported yet")));
+        }
+        checks.extend(state.checks.iter().cloned());
+        report(checks)
+    }
+}
+
+/// `{"status":"ok","checks":{"<name>":{"ok":true,"detail":"..."}}}`, `"fail"` if any failed.
+fn report(checks: Vec<(&'static str, Check)>) -> (bool, Value) {
+    let ok = checks.iter().all(|(_, check)| check.ok);
+    let checks = checks
+        .into_iter()
+        .map(|(name, check)| (name.to_string(), json!(check)))
+        .collect::<Map<_, _>>();
+    let status = if ok { "ok" } else { "fail" };
+    (ok, json!({ "status": status, "checks": checks }))
+}
+
+impl Health {
+    /// Answers `GET /healthz` and `GET /readyz`, `None` for any other request. Probes don't need
+    /// the API token.
+    pub fn respond(&self, request: &http::Request) -> Option<http::Response> {
+        let (ok, body) = match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/healthz") => self.liveness(),
+            ("GET", "/readyz") => self.readiness(),
+            _ => return None,
+        };
+        Some(http::Response::json(if ok { 200 } else { 503 }, &body))
+    }
+}
+
+/// Serves the probes on a listener of their own.
+pub fn serve(addr: SocketAddr, health: &Health) -> Result<SocketAddr, Box<dyn Error>> {
+    let health = health.clone();
+    http::serve(addr, "health endpoint", move |request| {
+        let response = health
+            .respond(&request)
+            .unwrap_or_else(http::Response::not_found);
+        async move { response }
+    })
+}
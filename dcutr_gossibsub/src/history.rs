@@ -0,0 +1,266 @@
+use crate::codec::{read_json, write_json};
+use crate::envelope::{unix_millis, Envelope};
+use crate::signing::{self, Verification};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::{AsyncRead, AsyncWrite};
+use libp2p::{
+    core::upgrade::ProtocolName,
+    gossipsub::TopicHash,
+    request_response::{self, RequestId},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::time::Duration;
+
+/// Largest history response we send or accept, in bytes of encoded envelopes.
+const MAX_RESPONSE_SIZE: usize = 512 * 1024;
+
+/// Asks a peer for the latest messages it has seen on a topic.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HistoryRequest {
+    /// The topic hash.
+    pub topic: String,
+    pub limit: u32,
+    /// Only messages sent after this unix time in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HistoryResponse {
+    /// Base64 of the envelopes as they were published, oldest first.
+    pub entries: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct HistoryProtocol;
+
+impl ProtocolName for HistoryProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        b"/chat-history/1"
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct HistoryCodec;
+
+#[async_trait]
+impl request_response::Codec for HistoryCodec {
+    type Protocol = HistoryProtocol;
+    type Request = HistoryRequest;
+    type Response = HistoryResponse;
+
+    async fn read_request<T>(
+        &mut self,
+        _: &HistoryProtocol,
+        io: &mut T,
+    ) -> io::Result<HistoryRequest>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_json(io, 1024).await
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _: &HistoryProtocol,
+        io: &mut T,
+    ) -> io::Result<HistoryResponse>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        // Base64 and the JSON around it take up to half again of the envelope bytes.
+        read_json(io, MAX_RESPONSE_SIZE * 3 / 2).await
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &HistoryProtocol,
+        io: &mut T,
+        request: HistoryRequest,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_json(io, &request).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &HistoryProtocol,
+        io: &mut T,
+        response: HistoryResponse,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_json(io, &response).await
+    }
+}
+
+pub fn behaviour() -> request_response::Behaviour<HistoryCodec> {
+    request_response::Behaviour::new(
+        HistoryCodec,
+        [(HistoryProtocol, request_response::ProtocolSupport::Full)],
+        request_response::Config::default(),
+    )
+}
+
+struct Entry {
+    key: String,
+    sent_at: u64,
+    expires_at: Option<u64>,
+    data: Vec<u8>,
+}
+
+/// The latest `size` enveloped messages of each topic, sent or received, as they were published.
+pub struct History {
+    size: usize,
+    /// Allowed for when judging whether an envelope expired.
+    skew: Duration,
+    topics: HashMap<TopicHash, VecDeque<Entry>>,
+    keys: HashSet<String>,
+    requested: HashSet<TopicHash>,
+    in_flight: HashMap<RequestId, TopicHash>,
+}
+
+impl History {
+    pub fn new(size: usize) -> Self {
+        History {
+            size,
+            skew: Duration::ZERO,
+            topics: HashMap::new(),
+            keys: HashSet::new(),
+            requested: HashSet::new(),
+            in_flight: HashMap::new(),
+        }
+    }
+
+    /// Allows for `skew` between the clocks of sender and receiver when judging whether an
+    /// envelope expired.
+    pub fn with_clock_skew(mut self, skew: Duration) -> Self {
+        self.skew = skew;
+        self
+    }
+
+    /// Remembers an enveloped payload, returning false for duplicates, bare payloads and
+    /// expired envelopes.
+    pub fn record(&mut self, topic: &TopicHash, data: &[u8]) -> bool {
+        let envelope = match Envelope::decode(data) {
+            Some(envelope) if !envelope.is_expired(unix_millis(), self.skew) => envelope,
+            _ => return false,
+        };
+        let key = envelope.key();
+        if self.size == 0 || !self.keys.insert(key.clone()) {
+            return false;
+        }
+        let entries = self.topics.entry(topic.clone()).or_default();
+        entries.push_back(Entry {
+            key,
+            sent_at: envelope.sent_at,
+            expires_at: envelope.expires_at,
+            data: data.to_vec(),
+        });
+        if entries.len() > self.size {
+            if let Some(oldest) = entries.pop_front() {
+                self.keys.remove(&oldest.key);
+            }
+        }
+        true
+    }
+
+    /// Whether history for `topic` should still be requested, true only the first time.
+    pub fn should_request(&mut self, topic: &TopicHash) -> bool {
+        self.requested.insert(topic.clone())
+    }
+
+    pub fn request(&self, topic: &TopicHash) -> HistoryRequest {
+        HistoryRequest {
+            topic: topic.to_string(),
+            limit: self.size as u32,
+            since: None,
+        }
+    }
+
+    /// Asks for up to `limit` messages on `topic` sent after `since`, to fill a gap.
+    pub fn request_since(&self, topic: &TopicHash, since: u64, limit: u64) -> HistoryRequest {
+        HistoryRequest {
+            topic: topic.to_string(),
+            limit: limit.min(self.size as u64) as u32,
+            since: Some(since),
+        }
+    }
+
+    pub fn on_request_sent(&mut self, request_id: RequestId, topic: TopicHash) {
+        self.in_flight.insert(request_id, topic);
+    }
+
+    /// Allows asking the next peer that subscribes to the topic.
+    pub fn on_request_failed(&mut self, request_id: &RequestId) {
+        if let Some(topic) = self.in_flight.remove(request_id) {
+            self.requested.remove(&topic);
+        }
+    }
+
+    /// The newest entries matching `request` that haven't expired, as many as fit into the
+    /// response size cap.
+    pub fn respond(&self, request: &HistoryRequest) -> HistoryResponse {
+        let now = unix_millis();
+        let entries = match self.topics.get(&TopicHash::from_raw(request.topic.clone())) {
+            Some(entries) => entries,
+            None => return HistoryResponse::default(),
+        };
+        let mut size = 0;
+        let mut selected = entries
+            .iter()
+            .rev()
+            .filter(|e| request.since.map_or(true, |since| e.sent_at > since))
+            .filter(|e| e.expires_at.map_or(true, |expires_at| expires_at >= now))
+            .take(request.limit as usize)
+            .take_while(|e| {
+                size += e.data.len();
+                size <= MAX_RESPONSE_SIZE
+            })
+            .map(|e| STANDARD.encode(&e.data))
+            .collect::<Vec<_>>();
+        selected.reverse();
+        HistoryResponse { entries: selected }
+    }
+
+    /// Adds the entries of the response to `request_id` we haven't seen yet and returns them
+    /// in timestamp order, with the topic they were requested for. Envelopes that aren't signed
+    /// by their origin are dropped, the responder could have made them up.
+    pub fn merge(
+        &mut self,
+        request_id: &RequestId,
+        response: HistoryResponse,
+    ) -> Option<(TopicHash, Vec<Vec<u8>>)> {
+        let topic = self.in_flight.remove(request_id)?;
+        let mut merged = Vec::new();
+        let mut size = 0;
+        for entry in response.entries {
+            let data = match STANDARD.decode(entry) {
+                Ok(data) => data,
+                Err(_) => continue,
+            };
+            size += data.len();
+            if size > MAX_RESPONSE_SIZE {
+                break;
+            }
+            let envelope = match Envelope::decode(&data) {
+                Some(envelope) => envelope,
+                None => continue,
+            };
+            if !matches!(signing::verify(&envelope, None), Verification::Verified(_)) {
+                continue;
+            }
+            if self.record(&topic, &data) {
+                merged.push((envelope.sent_at, data));
+            }
+        }
+        merged.sort_by_key(|(sent_at, _)| *sent_at);
+        Some((topic, merged.into_iter().map(|(_, data)| data).collect()))
+    }
+}
@@ -0,0 +1,356 @@
+use crate::console::say;
+use libp2p::{
+    core::{
+        multiaddr::{Multiaddr, Protocol},
+        ConnectedPoint,
+    },
+    swarm::ConnectionId,
+    PeerId,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Whether `addr` goes through a relay circuit. The remote address of an inbound circuit is just
+/// the dialer's `/p2p`, so for a connection ask its `ConnectedPoint::is_relayed` instead.
+pub fn is_relayed(addr: &Multiaddr) -> bool {
+    addr.iter().any(|p| p == Protocol::P2pCircuit)
+}
+
+/// Limits a relay puts on a circuit, after which it closes the relayed connection.
+#[derive(Clone, Copy, Debug)]
+pub struct CircuitLimit {
+    pub duration: Option<Duration>,
+    pub data_in_bytes: Option<u64>,
+}
+
+impl fmt::Display for CircuitLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.duration, self.data_in_bytes) {
+            (Some(duration), Some(bytes)) => write!(f, "{}s / {bytes} bytes", duration.as_secs()),
+            (Some(duration), None) => write!(f, "{}s", duration.as_secs()),
+            (None, Some(bytes)) => write!(f, "{bytes} bytes"),
+            (None, None) => write!(f, "no limit"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct PeerState {
+    failures: u32,
+    retry_at: Option<Instant>,
+    direct: bool,
+    relayed_fallback: bool,
+    circuit_limit: Option<CircuitLimit>,
+}
+
+/// Tracks hole punch outcomes per remote peer and schedules new attempts after a failure.
+///
+/// A new attempt is triggered by dialing the `/p2p-circuit` address again: the remote accepts the
+/// new relayed connection and starts another DCUtR upgrade. Only the dialing side can do that, the
+/// listening side just records the outcomes.
+pub struct HolePunchTracker {
+    max_retries: u32,
+    redial: bool,
+    relay: Multiaddr,
+    peers: HashMap<PeerId, PeerState>,
+}
+
+impl HolePunchTracker {
+    pub fn new(max_retries: u32, redial: bool, relay: Multiaddr) -> Self {
+        HolePunchTracker {
+            max_retries,
+            redial,
+            relay,
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Whether hole punching to the peer was given up on and traffic stays on the relay.
+    pub fn is_relayed_fallback(&self, peer_id: &PeerId) -> bool {
+        self.peers
+            .get(peer_id)
+            .map_or(false, |s| s.relayed_fallback)
+    }
+
+    /// Remembers the limit the relay announced for the circuit to `peer_id`.
+    pub fn on_circuit_limit(&mut self, peer_id: PeerId, limit: CircuitLimit) {
+        self.peers.entry(peer_id).or_default().circuit_limit = Some(limit);
+    }
+
+    /// Records a failed attempt, returning `true` once all retries are exhausted.
+    pub fn on_failed(&mut self, peer_id: PeerId) -> bool {
+        let state = self.peers.entry(peer_id).or_default();
+        if state.direct {
+            return false;
+        }
+        state.failures += 1;
+
+        if state.failures > self.max_retries {
+            state.retry_at = None;
+            state.relayed_fallback = true;
+            say!(
+                "hole punch to {peer_id} failed after {} attempts; staying on relayed connection via {}",
+                state.failures, self.relay
+            );
+            if let Some(limit) = state.circuit_limit {
+                say!("relay limits this circuit to {limit}, the session may be cut off");
+            }
+            return true;
+        }
+        if self.redial {
+            let delay = Duration::from_secs(1 << state.failures.min(6));
+            info!(
+                %peer_id,
+                attempt = state.failures,
+                max_attempts = self.max_retries + 1,
+                retry_in = ?delay,
+                "Hole punch failed, retrying"
+            );
+            state.retry_at = Some(Instant::now() + delay);
+        }
+        false
+    }
+
+    pub fn on_succeeded(&mut self, peer_id: PeerId) {
+        self.on_direct_connection(peer_id);
+    }
+
+    /// A non-relayed connection to the peer exists, no more attempts are needed.
+    pub fn on_direct_connection(&mut self, peer_id: PeerId) {
+        let state = self.peers.entry(peer_id).or_default();
+        if state.relayed_fallback {
+            say!("Direct connection to {peer_id} established, no longer relayed");
+        }
+        state.direct = true;
+        state.relayed_fallback = false;
+        state.retry_at = None;
+    }
+
+    /// All connections to the peer closed, the next connection starts with a clean slate.
+    pub fn on_disconnected(&mut self, peer_id: PeerId) {
+        self.peers.remove(&peer_id);
+    }
+
+    /// Peers whose next hole punch attempt is due.
+    pub fn due(&mut self, now: Instant) -> Vec<PeerId> {
+        self.peers
+            .iter_mut()
+            .filter(|(_, state)| state.retry_at.map_or(false, |at| at <= now))
+            .map(|(peer_id, state)| {
+                state.retry_at = None;
+                *peer_id
+            })
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct Connections {
+    relayed: Vec<ConnectionId>,
+    direct: Vec<(ConnectionId, Multiaddr)>,
+    upgraded: bool,
+    close_at: Option<Instant>,
+}
+
+/// Relayed connections that became redundant after a successful hole punch. They are closed after
+/// a grace period so streams in flight can move over to the direct connection, and to keep the
+/// relay's bandwidth limits for peers that need them.
+pub struct RelayedConnections {
+    grace: Duration,
+    peers: HashMap<PeerId, Connections>,
+}
+
+impl RelayedConnections {
+    pub fn new(grace: Duration) -> Self {
+        RelayedConnections {
+            grace,
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Notes a connection to `peer_id`. Inbound circuits only show the relay in the local
+    /// address, so it's told by the whole `endpoint`.
+    pub fn on_established(&mut self, peer_id: PeerId, id: ConnectionId, endpoint: &ConnectedPoint) {
+        let connections = self.peers.entry(peer_id).or_default();
+        if endpoint.is_relayed() {
+            connections.relayed.push(id);
+        } else {
+            connections
+                .direct
+                .push((id, endpoint.get_remote_address().clone()));
+        }
+        self.schedule(peer_id);
+    }
+
+    pub fn on_upgraded(&mut self, peer_id: PeerId) {
+        self.peers.entry(peer_id).or_default().upgraded = true;
+        self.schedule(peer_id);
+    }
+
+    pub fn on_closed(&mut self, peer_id: PeerId, id: ConnectionId) {
+        if let Some(connections) = self.peers.get_mut(&peer_id) {
+            connections.relayed.retain(|c| *c != id);
+            connections.direct.retain(|(c, _)| *c != id);
+            if connections.relayed.is_empty() && connections.direct.is_empty() {
+                self.peers.remove(&peer_id);
+            }
+        }
+    }
+
+    /// Relayed connections whose grace period is over, along with the direct address that keeps
+    /// carrying the traffic to that peer.
+    pub fn due(&mut self, now: Instant) -> Vec<(PeerId, Vec<ConnectionId>, Multiaddr)> {
+        let mut due = Vec::new();
+        for (peer_id, connections) in self.peers.iter_mut() {
+            if !connections.close_at.map_or(false, |at| at <= now) {
+                continue;
+            }
+            connections.close_at = None;
+            if let Some((_, direct_addr)) = connections.direct.first() {
+                due.push((
+                    *peer_id,
+                    std::mem::take(&mut connections.relayed),
+                    direct_addr.clone(),
+                ));
+            }
+        }
+        due
+    }
+
+    fn schedule(&mut self, peer_id: PeerId) {
+        if let Some(connections) = self.peers.get_mut(&peer_id) {
+            if connections.upgraded
+                && !connections.direct.is_empty()
+                && !connections.relayed.is_empty()
+                && connections.close_at.is_none()
+            {
+                connections.close_at = Some(Instant::now() + self.grace);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct PeerCounts {
+    attempts: u32,
+    successes: u32,
+    failures: u32,
+}
+
+/// Counters for DCUtR attempts, overall and per remote peer.
+///
+/// An attempt is open from the first upgrade event until its outcome, so both sides reporting
+/// the start of the same attempt or a reconnect in between count it only once.
+#[derive(Default)]
+pub struct HolePunchStats {
+    attempts: u32,
+    successes: u32,
+    failures: BTreeMap<String, u32>,
+    time_to_direct: Vec<Duration>,
+    open: HashMap<PeerId, Instant>,
+    peers: HashMap<PeerId, PeerCounts>,
+    changed: bool,
+}
+
+impl HolePunchStats {
+    pub fn on_attempt(&mut self, peer_id: PeerId) {
+        if self.open.contains_key(&peer_id) {
+            return;
+        }
+        self.open.insert(peer_id, Instant::now());
+        self.attempts += 1;
+        self.peers.entry(peer_id).or_default().attempts += 1;
+        self.changed = true;
+    }
+
+    /// Returns how long it took from the start of the attempt to the direct connection.
+    pub fn on_success(&mut self, peer_id: PeerId) -> Duration {
+        let started = self.close_attempt(peer_id);
+        self.successes += 1;
+        self.peers.entry(peer_id).or_default().successes += 1;
+        let time_to_direct = started.elapsed();
+        self.time_to_direct.push(time_to_direct);
+        time_to_direct
+    }
+
+    /// `error` is the debug representation of the DCUtR error, its variant name is the kind,
+    /// which is returned.
+    pub fn on_failure(&mut self, peer_id: PeerId, error: &str) -> String {
+        self.close_attempt(peer_id);
+        let kind = error
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .next()
+            .filter(|k| !k.is_empty())
+            .unwrap_or("Unknown");
+        *self.failures.entry(kind.to_string()).or_default() += 1;
+        self.peers.entry(peer_id).or_default().failures += 1;
+        kind.to_string()
+    }
+
+    /// Whether anything happened since the last call.
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    pub fn median_time_to_direct(&self) -> Option<Duration> {
+        let mut durations = self.time_to_direct.clone();
+        durations.sort();
+        durations.get(durations.len() / 2).copied()
+    }
+
+    /// Hole punch attempts with `peer_id` so far.
+    pub fn attempts(&self, peer_id: &PeerId) -> u32 {
+        self.peers.get(peer_id).map_or(0, |c| c.attempts)
+    }
+
+    /// Per-peer breakdown, one line per peer.
+    pub fn peer_lines(&self) -> Vec<String> {
+        self.peers
+            .iter()
+            .map(|(peer_id, c)| {
+                format!(
+                    "  {peer_id}: {} attempts, {} success, {} failed",
+                    c.attempts, c.successes, c.failures
+                )
+            })
+            .collect()
+    }
+
+    /// Counts an outcome without a start event as an attempt of its own.
+    fn close_attempt(&mut self, peer_id: PeerId) -> Instant {
+        self.changed = true;
+        match self.open.remove(&peer_id) {
+            Some(started) => started,
+            None => {
+                self.attempts += 1;
+                self.peers.entry(peer_id).or_default().attempts += 1;
+                Instant::now()
+            }
+        }
+    }
+}
+
+impl fmt::Display for HolePunchStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let failed: u32 = self.failures.values().sum();
+        write!(
+            f,
+            "holepunch: {} attempts, {} success, {} failed",
+            self.attempts, self.successes, failed
+        )?;
+        if !self.failures.is_empty() {
+            let kinds = self
+                .failures
+                .iter()
+                .map(|(kind, n)| format!("{kind}: {n}"))
+                .collect::<Vec<_>>();
+            write!(f, " ({})", kinds.join(", "))?;
+        }
+        if let Some(median) = self.median_time_to_direct() {
+            write!(f, ", median {:.1}s", median.as_secs_f64())?;
+        }
+        Ok(())
+    }
+}
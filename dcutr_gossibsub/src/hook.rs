@@ -0,0 +1,169 @@
+use crate::paths::TransportPath;
+use libp2p::PeerId;
+use std::io::Write;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// How many received messages wait for a free slot before further ones are dropped.
+const QUEUE_SIZE: usize = 100;
+/// How often a running hook is checked for having exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A received message as handed to the `--on-message` command.
+pub struct HookMessage {
+    pub topic: String,
+    pub message_id: String,
+    pub source: Option<PeerId>,
+    pub path: Option<TransportPath>,
+    pub body: String,
+    /// How `body` is encoded, as in [`crate::output::JsonMessage`].
+    pub body_encoding: &'static str,
+}
+
+/// Runs `--on-message` for each received message, run by `sh -c` so the command may carry
+/// arguments. The message's metadata is in `P2P_TOPIC`, `P2P_FROM`, `P2P_MSG_ID`, `P2P_PATH` and
+/// `P2P_BODY_ENCODING`, its body on stdin. The command's stdout is discarded, its stderr is ours.
+///
+/// The commands run on threads of their own, at most `concurrency` at once, and are killed after
+/// `timeout`. Messages arriving while the queue is full are dropped with a warning.
+pub struct Hook {
+    /// Topics that trigger the hook, all of them if empty.
+    topics: Vec<String>,
+    queue: SyncSender<HookMessage>,
+}
+
+impl Hook {
+    pub fn start(
+        command: String,
+        topics: Vec<String>,
+        concurrency: usize,
+        timeout: Duration,
+    ) -> Self {
+        let (queue, messages) = mpsc::sync_channel(QUEUE_SIZE);
+        let messages = Arc::new(Mutex::new(messages));
+        for _ in 0..concurrency.max(1) {
+            let messages = messages.clone();
+            let command = command.clone();
+            thread::spawn(move || work(&command, &messages, timeout));
+        }
+        Hook { topics, queue }
+    }
+
+    pub fn wants(&self, topic: &str) -> bool {
+        self.topics.is_empty() || self.topics.iter().any(|t| t == topic)
+    }
+
+    /// Queues the hook to run for `message`, if its topic is one that triggers it.
+    pub fn on_message(&self, message: HookMessage) {
+        if !self.wants(&message.topic) {
+            return;
+        }
+        match self.queue.try_send(message) {
+            Ok(()) => {}
+            Err(TrySendError::Full(message)) => warn!(
+                "Not running --on-message for {}, {QUEUE_SIZE} messages are already waiting",
+                message.message_id
+            ),
+            Err(TrySendError::Disconnected(_)) => warn!("--on-message is no longer running"),
+        }
+    }
+}
+
+fn work(command: &str, messages: &Mutex<Receiver<HookMessage>>, timeout: Duration) {
+    loop {
+        // The lock is only held while waiting, not while the command runs.
+        let message = match messages.lock() {
+            Ok(messages) => match messages.recv() {
+                Ok(message) => message,
+                Err(_) => return,
+            },
+            Err(_) => return,
+        };
+        run(command, &message, timeout);
+    }
+}
+
+fn run(command: &str, message: &HookMessage, timeout: Duration) {
+    let path = match message.path {
+        Some(TransportPath::Direct) => "direct",
+        Some(TransportPath::Relayed(_)) => "relayed",
+        None => "",
+    };
+    let child = shell(command)
+        .env("P2P_TOPIC", &message.topic)
+        .env(
+            "P2P_FROM",
+            message.source.map(|p| p.to_string()).unwrap_or_default(),
+        )
+        .env("P2P_MSG_ID", &message.message_id)
+        .env("P2P_PATH", path)
+        .env("P2P_BODY_ENCODING", message.body_encoding)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("Failed to run --on-message for {}: {e}", message.message_id);
+            return;
+        }
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // Commands that don't read their stdin close it, which isn't worth a warning.
+        let body = message.body.clone();
+        thread::spawn(move || {
+            let _ = stdin.write_all(body.as_bytes());
+        });
+    }
+    match wait(&mut child, timeout) {
+        Some(status) if status.success() => {
+            debug!("--on-message for {} finished", message.message_id)
+        }
+        Some(status) => warn!(
+            "--on-message for {} failed with {status}",
+            message.message_id
+        ),
+        None => {
+            warn!(
+                "--on-message for {} took longer than {timeout:?}, killing it",
+                message.message_id
+            );
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Waits for the command to exit, `None` if it didn't within `timeout`.
+fn wait(child: &mut Child, timeout: Duration) -> Option<std::process::ExitStatus> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return Some(status),
+            Ok(None) if Instant::now() < deadline => thread::sleep(POLL_INTERVAL),
+            Ok(None) => return None,
+            Err(e) => {
+                warn!("Failed to wait for --on-message: {e}");
+                return None;
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(not(unix))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
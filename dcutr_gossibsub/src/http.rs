@@ -0,0 +1,170 @@
+//! The small HTTP servers of `--metrics-addr`, `--health-addr` and `--http-api`. Handlers see a
+//! plain [`Request`] and answer a plain [`Response`], so they don't depend on the runtime: the
+//! server is hyper on tokio, and tide on async-std builds.
+
+use serde::Serialize;
+use std::error::Error;
+use std::future::Future;
+use std::net::SocketAddr;
+use tracing::{info, warn};
+
+/// What a handler gets of a request.
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub authorization: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// What a handler answers.
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn json(status: u16, body: &impl Serialize) -> Self {
+        Response {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_vec(body).expect("response serializes to JSON"),
+        }
+    }
+
+    pub fn text(status: u16, content_type: &'static str, body: String) -> Self {
+        Response {
+            status,
+            content_type,
+            body: body.into_bytes(),
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Response::text(404, "text/plain", "not found".to_string())
+    }
+}
+
+/// Serves `handler` on `addr` in the background, `what` names the server in logs and errors.
+/// Returns the address it's bound to, which tells the port picked for port `0`. Fails if `addr`
+/// can't be bound.
+pub fn serve<H, F>(
+    addr: SocketAddr,
+    what: &'static str,
+    handler: H,
+) -> Result<SocketAddr, Box<dyn Error>>
+where
+    H: Fn(Request) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = Response> + Send + 'static,
+{
+    let bound = spawn(addr, what, handler)
+        .map_err(|e| format!("failed to bind the {what} to {addr}: {e}"))?;
+    info!("Serving the {what} on http://{bound}");
+    Ok(bound)
+}
+
+#[cfg(feature = "tokio")]
+fn spawn<H, F>(
+    addr: SocketAddr,
+    what: &'static str,
+    handler: H,
+) -> Result<SocketAddr, Box<dyn Error>>
+where
+    H: Fn(Request) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = Response> + Send + 'static,
+{
+    use hyper::service::{make_service_fn, service_fn};
+    use std::convert::Infallible;
+
+    let builder = hyper::Server::try_bind(&addr)?;
+    let service = make_service_fn(move |_| {
+        let handler = handler.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: hyper::Request<hyper::Body>| {
+                let handler = handler.clone();
+                async move {
+                    let (parts, body) = request.into_parts();
+                    let body = hyper::body::to_bytes(body).await?.to_vec();
+                    let request = Request {
+                        method: parts.method.to_string(),
+                        path: parts.uri.path().to_string(),
+                        query: parts.uri.query().unwrap_or_default().to_string(),
+                        authorization: parts
+                            .headers
+                            .get(hyper::header::AUTHORIZATION)
+                            .and_then(|value| value.to_str().ok())
+                            .map(str::to_string),
+                        body,
+                    };
+                    let response = handler(request).await;
+                    Ok(hyper::Response::builder()
+                        .status(response.status)
+                        .header(hyper::header::CONTENT_TYPE, response.content_type)
+                        .body(hyper::Body::from(response.body))
+                        .expect("valid response"))
+                }
+            }))
+        }
+    });
+    let server = builder.serve(service);
+    let bound = server.local_addr();
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            warn!("The {what} stopped: {e}");
+        }
+    });
+    Ok(bound)
+}
+
+#[cfg(not(feature = "tokio"))]
+fn spawn<H, F>(
+    addr: SocketAddr,
+    what: &'static str,
+    handler: H,
+) -> Result<SocketAddr, Box<dyn Error>>
+where
+    H: Fn(Request) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = Response> + Send + 'static,
+{
+    use async_std::task;
+    use tide::listener::Listener;
+
+    let mut app = tide::new();
+    let endpoint = move |mut request: tide::Request<()>| {
+        let handler = handler.clone();
+        async move {
+            let body = request.body_bytes().await?;
+            let request = Request {
+                method: request.method().to_string(),
+                path: request.url().path().to_string(),
+                query: request.url().query().unwrap_or_default().to_string(),
+                authorization: request
+                    .header("Authorization")
+                    .map(|value| value.as_str().to_string()),
+                body,
+            };
+            let response = handler(request).await;
+            Ok(tide::Response::builder(response.status)
+                .content_type(response.content_type)
+                .body(response.body)
+                .build())
+        }
+    };
+    app.at("/").all(endpoint.clone());
+    app.at("*").all(endpoint);
+    let mut listener = task::block_on(app.bind(addr.to_string()))?;
+    let bound = listener
+        .info()
+        .iter()
+        .find_map(|info| info.connection().trim_start_matches("http://").parse().ok())
+        .ok_or("the listener has no address")?;
+    task::spawn(async move {
+        if let Err(e) = listener.accept().await {
+            warn!("The {what} stopped: {e}");
+        }
+    });
+    Ok(bound)
+}
@@ -0,0 +1,167 @@
+use crate::control::{ControlRequest, Response};
+use crate::health::Health;
+use crate::http;
+use futures::channel::{mpsc, oneshot};
+use futures::future::{self, Either};
+use futures_timer::Delay;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// The longest a `GET /messages?wait=` request is held open.
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+#[derive(Clone)]
+struct State {
+    requests: mpsc::UnboundedSender<ControlRequest>,
+    token: Option<String>,
+    health: Health,
+}
+
+#[derive(Deserialize)]
+struct Publish {
+    topic: String,
+    body: String,
+}
+
+#[derive(Deserialize)]
+struct Dial {
+    addr: String,
+}
+
+#[derive(Deserialize)]
+struct Messages {
+    #[serde(default)]
+    since: u64,
+    topic: Option<String>,
+    /// Seconds to wait for a message if there is none after `since` yet.
+    #[serde(default)]
+    wait: u64,
+}
+
+/// Serves the `--http-api` on the client's runtime. Requests are handed to the event loop the
+/// same way as those of the control socket, so the swarm is never shared. With a `token`,
+/// requests need an `Authorization: Bearer <token>` header, except for the health probes.
+pub fn start(
+    addr: SocketAddr,
+    token: Option<String>,
+    requests: mpsc::UnboundedSender<ControlRequest>,
+    health: &Health,
+) -> Result<SocketAddr, Box<dyn Error>> {
+    let state = State {
+        requests,
+        token,
+        health: health.clone(),
+    };
+    http::serve(addr, "HTTP API", move |request| {
+        let state = state.clone();
+        async move { route(&state, request).await }
+    })
+}
+
+async fn route(state: &State, request: http::Request) -> http::Response {
+    if let Some(response) = state.health.respond(&request) {
+        return response;
+    }
+    if !authorized(state, &request) {
+        return http::Response::json(401, &Response::error("missing or wrong bearer token"));
+    }
+    match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/publish") => publish(state, &request).await,
+        ("POST", "/dial") => dial(state, &request).await,
+        ("GET", "/peers") => ask(state, |reply| ControlRequest::Peers { reply }).await,
+        ("GET", "/status") => ask(state, |reply| ControlRequest::Status { reply }).await,
+        ("GET", "/messages") => messages(state, &request).await,
+        _ => http::Response::not_found(),
+    }
+}
+
+fn authorized(state: &State, request: &http::Request) -> bool {
+    let token = match &state.token {
+        Some(token) => token,
+        None => return true,
+    };
+    let authorization = request.authorization.as_deref();
+    authorization.and_then(|value| value.strip_prefix("Bearer ")) == Some(token.as_str())
+}
+
+/// Hands a request to the event loop and answers with its response.
+async fn ask(
+    state: &State,
+    request: impl FnOnce(oneshot::Sender<Response>) -> ControlRequest,
+) -> http::Response {
+    let (reply, response) = oneshot::channel();
+    if state.requests.unbounded_send(request(reply)).is_err() {
+        return respond(Response::error("the node is shutting down"));
+    }
+    respond(
+        response
+            .await
+            .unwrap_or_else(|_| Response::error("the request was not handled")),
+    )
+}
+
+fn respond(response: Response) -> http::Response {
+    let status = if response.is_ok() { 200 } else { 400 };
+    http::Response::json(status, &response)
+}
+
+fn body_json<T: DeserializeOwned>(request: &http::Request) -> Result<T, http::Response> {
+    serde_json::from_slice(&request.body)
+        .map_err(|e| respond(Response::error(format!("invalid request body: {e}"))))
+}
+
+async fn publish(state: &State, request: &http::Request) -> http::Response {
+    let Publish { topic, body } = match body_json(request) {
+        Ok(publish) => publish,
+        Err(response) => return response,
+    };
+    if topic.is_empty() || topic.contains(char::is_whitespace) {
+        return respond(Response::error("invalid topic name"));
+    }
+    if body.trim().is_empty() {
+        return respond(Response::error("empty body"));
+    }
+    let line = format!("@{topic} {body}");
+    ask(state, |reply| ControlRequest::Publish { line, reply }).await
+}
+
+async fn dial(state: &State, request: &http::Request) -> http::Response {
+    let Dial { addr } = match body_json(request) {
+        Ok(dial) => dial,
+        Err(response) => return response,
+    };
+    match addr.parse() {
+        Ok(addr) => ask(state, |reply| ControlRequest::Dial { addr, reply }).await,
+        Err(e) => respond(Response::error(format!("invalid address: {e}"))),
+    }
+}
+
+/// `GET /messages?since=<id>&topic=<name>&wait=<seconds>`, the messages received after `since`,
+/// waiting up to `wait` seconds for one if there are none yet.
+async fn messages(state: &State, request: &http::Request) -> http::Response {
+    let Messages { since, topic, wait } = match serde_urlencoded::from_str(&request.query) {
+        Ok(query) => query,
+        Err(e) => return respond(Response::error(format!("invalid query: {e}"))),
+    };
+    let wait = Duration::from_secs(wait).min(MAX_WAIT);
+    let (reply, response) = oneshot::channel();
+    let request = ControlRequest::Messages {
+        since,
+        topic,
+        wait: !wait.is_zero(),
+        reply,
+    };
+    if state.requests.unbounded_send(request).is_err() {
+        return respond(Response::error("the node is shutting down"));
+    }
+    let timeout = Delay::new(wait.max(Duration::from_secs(1)));
+    match future::select(response, timeout).await {
+        Either::Left((Ok(response), _)) => respond(response),
+        Either::Left((Err(_), _)) => respond(Response::error("the request was not handled")),
+        // Nothing arrived in time.
+        Either::Right(_) => respond(Response::ok(serde_json::Value::Array(Vec::new()))),
+    }
+}
@@ -0,0 +1,13 @@
+use libp2p::identity::Keypair;
+
+/// The ed25519 keypair derived from `--secret-key-seed`, so a seed always gives the same peer id.
+pub fn generate_ed25519(secret_key_seed: u8) -> Keypair {
+    Keypair::ed25519_from_bytes(ed25519_seed(secret_key_seed)).expect("only errors on wrong length")
+}
+
+/// The secret key bytes of [`generate_ed25519`], also used to derive the end-to-end key.
+pub fn ed25519_seed(secret_key_seed: u8) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    bytes[0] = secret_key_seed;
+    bytes
+}
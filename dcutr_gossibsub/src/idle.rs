@@ -0,0 +1,102 @@
+use crate::behaviour::BehaviourEvent;
+use libp2p::{gossipsub, request_response, swarm::SwarmEvent, PeerId};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Whether connections are held open while nothing uses them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeepAlive {
+    /// Each protocol decides, a connection closes once none of them needs it any more. Gossipsub
+    /// keeps mesh peers and closes other connections two minutes after their last message.
+    Default,
+    /// Every connection is held open. Combined with `--idle-timeout` only explicit peers and the
+    /// relay are held, others are closed once idle.
+    Always,
+}
+
+impl FromStr for KeepAlive {
+    type Err = String;
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "default" => Ok(KeepAlive::Default),
+            "always" => Ok(KeepAlive::Always),
+            _ => Err("Expected either 'default' or 'always'".to_string()),
+        }
+    }
+}
+
+/// Closes connections that carried no messages or requests for the idle timeout. Pings are no
+/// activity: they keep NAT mappings fresh, but a connection used for nothing else still counts
+/// as idle.
+pub struct IdleConnections {
+    timeout: Duration,
+    last_active: HashMap<PeerId, Instant>,
+}
+
+impl IdleConnections {
+    /// Closes connections once they were idle for `timeout`.
+    pub fn new(timeout: Duration) -> Self {
+        IdleConnections {
+            timeout,
+            last_active: HashMap::new(),
+        }
+    }
+
+    /// Notes connections coming and going, and the peers that sent us something.
+    pub fn record<E>(&mut self, event: &SwarmEvent<BehaviourEvent, E>, now: Instant) {
+        let peer_id =
+            match event {
+                SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+                    self.last_active.entry(*peer_id).or_insert(now);
+                    return;
+                }
+                SwarmEvent::ConnectionClosed {
+                    peer_id,
+                    num_established: 0,
+                    ..
+                } => {
+                    self.last_active.remove(peer_id);
+                    return;
+                }
+                SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                    propagation_source,
+                    ..
+                })) => propagation_source,
+                SwarmEvent::Behaviour(BehaviourEvent::Dm(request_response::Event::Message {
+                    peer,
+                    ..
+                }))
+                | SwarmEvent::Behaviour(BehaviourEvent::History(
+                    request_response::Event::Message { peer, .. },
+                ))
+                | SwarmEvent::Behaviour(BehaviourEvent::Transfer(
+                    request_response::Event::Message { peer, .. },
+                ))
+                | SwarmEvent::Behaviour(BehaviourEvent::Echo(request_response::Event::Message {
+                    peer,
+                    ..
+                })) => peer,
+                _ => return,
+            };
+        if let Some(last_active) = self.last_active.get_mut(peer_id) {
+            *last_active = now;
+        }
+    }
+
+    /// Peers idle for longer than the timeout that aren't `protected`, to disconnect. They aren't
+    /// returned again until another timeout has passed.
+    pub fn due(&mut self, now: Instant, protected: impl Fn(&PeerId) -> bool) -> Vec<PeerId> {
+        let timeout = self.timeout;
+        self.last_active
+            .iter_mut()
+            .filter(|(peer_id, last_active)| {
+                now.duration_since(**last_active) >= timeout && !protected(peer_id)
+            })
+            .map(|(peer_id, last_active)| {
+                *last_active = now;
+                *peer_id
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,267 @@
+use crate::console::say;
+use futures::{
+    channel::mpsc,
+    stream::{BoxStream, Fuse, FusedStream},
+    Stream, StreamExt,
+};
+use rustyline::{error::ReadlineError, DefaultEditor, ExternalPrinter};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+const PROMPT: &str = "> ";
+
+/// The lines typed on stdin, ending when it closes. On a terminal they are read with line editing
+/// and a history kept in `history_file` if `line_editing` is set, piped input is read as is.
+pub fn lines(history_file: Option<PathBuf>, line_editing: bool) -> BoxStream<'static, String> {
+    if !line_editing || !std::io::stdin().is_terminal() {
+        return piped();
+    }
+    let (tx, rx) = mpsc::unbounded();
+    // rustyline blocks while reading, so it gets a thread of its own.
+    thread::spawn(move || {
+        let mut editor = match DefaultEditor::new() {
+            Ok(editor) => editor,
+            Err(e) => {
+                warn!("Line editing is unavailable: {e}");
+                return;
+            }
+        };
+        if let Some(path) = history_file.as_ref().filter(|path| path.exists()) {
+            if let Err(e) = editor.load_history(path) {
+                warn!("Failed to load the input history {}: {e}", path.display());
+            }
+        }
+        match editor.create_external_printer() {
+            Ok(mut printer) => {
+                crate::console::set_sink(move |line| printer.print(line).map_err(|e| e.to_string()))
+            }
+            Err(e) => warn!("Output may overwrite the prompt: {e}"),
+        }
+        loop {
+            let line = match editor.readline(PROMPT) {
+                Ok(line) => line,
+                // Ctrl-C quits like in the TUI, Ctrl-D ends the input.
+                Err(ReadlineError::Interrupted) => {
+                    let _ = tx.unbounded_send("/quit".to_string());
+                    break;
+                }
+                Err(ReadlineError::Eof) => break,
+                Err(e) => {
+                    warn!("Failed to read input: {e}");
+                    break;
+                }
+            };
+            if !line.trim().is_empty() {
+                let _ = editor.add_history_entry(line.as_str());
+                if let Some(path) = &history_file {
+                    if let Err(e) = editor.save_history(path) {
+                        warn!("Failed to save the input history {}: {e}", path.display());
+                    }
+                }
+            }
+            if tx.unbounded_send(line).is_err() {
+                break;
+            }
+        }
+    });
+    rx.boxed()
+}
+
+/// Piped stdin, line by line. tokio reads it on its blocking pool, not a runtime worker. A read
+/// error is logged and ends the input like the end of the pipe does.
+#[cfg(feature = "tokio")]
+fn piped() -> BoxStream<'static, String> {
+    use tokio::io::{self, AsyncBufReadExt};
+    let lines = io::BufReader::new(io::stdin()).lines();
+    futures::stream::unfold(lines, |mut lines| async move {
+        match lines.next_line().await {
+            Ok(Some(line)) => Some((line, lines)),
+            Ok(None) => None,
+            Err(e) => {
+                warn!("Failed to read input: {e}");
+                None
+            }
+        }
+    })
+    .boxed()
+}
+
+#[cfg(not(feature = "tokio"))]
+fn piped() -> BoxStream<'static, String> {
+    use async_std::io;
+    use futures::AsyncBufReadExt;
+    io::BufReader::new(io::stdin())
+        .lines()
+        .take_while(|line| {
+            if let Err(e) = line {
+                warn!("Failed to read input: {e}");
+            }
+            futures::future::ready(line.is_ok())
+        })
+        .map(|line| line.expect("errors end the stream"))
+        .boxed()
+}
+
+/// The lines of `--input` instead of stdin. A regular file is read once, `delay` apart, and the
+/// stream ends after its last line unless `stay` is set. A named pipe is kept open for writers
+/// coming and going, it is opened again each time the last writer closed it. Fails if the path
+/// can't be read.
+pub fn from_path(
+    path: &Path,
+    delay: Duration,
+    stay: bool,
+) -> Result<BoxStream<'static, String>, String> {
+    let metadata = fs::metadata(path)
+        .map_err(|e| format!("failed to read --input {}: {e}", path.display()))?;
+    if is_fifo(&metadata) {
+        return Ok(fifo(path.to_path_buf()));
+    }
+    if !metadata.is_file() {
+        return Err(format!(
+            "--input {} is neither a regular file nor a named pipe",
+            path.display()
+        ));
+    }
+    let file =
+        File::open(path).map_err(|e| format!("failed to open --input {}: {e}", path.display()))?;
+    let (tx, rx) = mpsc::unbounded();
+    let path = path.to_path_buf();
+    // Reading and waiting between lines block, so the file gets a thread of its own.
+    thread::spawn(move || {
+        for (n, line) in BufReader::new(file).lines().enumerate() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    warn!("Failed to read --input {}: {e}", path.display());
+                    break;
+                }
+            };
+            if n > 0 && !delay.is_zero() {
+                thread::sleep(delay);
+            }
+            if tx.unbounded_send(line).is_err() {
+                return;
+            }
+        }
+    });
+    Ok(match stay {
+        true => rx.chain(futures::stream::pending()).boxed(),
+        false => rx.boxed(),
+    })
+}
+
+/// A named pipe, opened again whenever its writers are gone. Opening blocks until the next writer
+/// shows up.
+fn fifo(path: PathBuf) -> BoxStream<'static, String> {
+    let (tx, rx) = mpsc::unbounded();
+    thread::spawn(move || loop {
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("Failed to open --input {}: {e}", path.display());
+                return;
+            }
+        };
+        for line in BufReader::new(file).lines() {
+            match line {
+                Ok(line) => {
+                    if tx.unbounded_send(line).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to read --input {}: {e}", path.display());
+                    break;
+                }
+            }
+        }
+        // Every writer closed the pipe, wait for the next one.
+        if tx.is_closed() {
+            return;
+        }
+    });
+    rx.boxed()
+}
+
+#[cfg(unix)]
+fn is_fifo(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    metadata.file_type().is_fifo()
+}
+
+#[cfg(not(unix))]
+fn is_fifo(_: &fs::Metadata) -> bool {
+    false
+}
+
+/// Stdin for the event loop. Lines typed while bootstrapping are held back, the oldest ones being
+/// dropped when more than `capacity` pile up, and come out in order once [`Backlog::release`] was
+/// called. The input ending meanwhile is reported after them.
+pub struct Backlog {
+    stdin: Fuse<BoxStream<'static, String>>,
+    capacity: usize,
+    lines: VecDeque<String>,
+    released: bool,
+}
+
+impl Backlog {
+    pub fn new(stdin: Fuse<BoxStream<'static, String>>, capacity: usize) -> Self {
+        Backlog {
+            stdin,
+            capacity,
+            lines: VecDeque::new(),
+            released: false,
+        }
+    }
+
+    pub fn release(&mut self) {
+        self.released = true;
+    }
+
+    pub fn is_released(&self) -> bool {
+        self.released
+    }
+
+    fn hold(&mut self, line: String) {
+        if self.lines.len() >= self.capacity.max(1) {
+            if let Some(dropped) = self.lines.pop_front() {
+                say!("Dropped queued input '{dropped}', too much was typed while bootstrapping");
+            }
+        }
+        self.lines.push_back(line);
+        say!("queued (waiting for peers)");
+    }
+}
+
+impl Stream for Backlog {
+    type Item = String;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<String>> {
+        if self.released {
+            if let Some(line) = self.lines.pop_front() {
+                return Poll::Ready(Some(line));
+            }
+        }
+        loop {
+            match self.stdin.poll_next_unpin(cx) {
+                Poll::Ready(Some(line)) if !self.released => self.hold(line),
+                // Held until released, the event loop polls again by then.
+                Poll::Ready(None) if !self.released => return Poll::Pending,
+                poll => return poll,
+            }
+        }
+    }
+}
+
+impl FusedStream for Backlog {
+    fn is_terminated(&self) -> bool {
+        self.released && self.lines.is_empty() && self.stdin.is_terminated()
+    }
+}
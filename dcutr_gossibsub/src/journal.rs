@@ -0,0 +1,221 @@
+use crate::events::NodeEvent;
+use chrono::{Local, SecondsFormat};
+use serde_json::Value;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use tracing::warn;
+
+/// Records waiting for the writer thread. Further records are dropped until it catches up.
+const CAPACITY: usize = 1024;
+
+/// The `--event-log`, a durable record of what the node went through for debugging failed hole
+/// punches after the fact, one line per event:
+///
+/// ```text
+/// 2024-05-01T12:00:00.123+02:00 hole_punch_failed peer_id=12D3KooW... error="Handler(..)"
+/// ```
+///
+/// Lines are written by a thread of its own through a bounded channel, so a slow disk never holds
+/// up the event loop. The file is rotated to `<path>.1`, `<path>.2`, ... once it grows past
+/// `max_size`. Everything written is flushed when this is dropped, which includes returning
+/// from `main` with an error or unwinding from a panic.
+pub struct Journal {
+    sender: Option<SyncSender<String>>,
+    writer: Option<JoinHandle<()>>,
+    dropped: u64,
+}
+
+impl Journal {
+    pub fn open(path: &Path, max_size: u64, keep: usize) -> Result<Self, Box<dyn Error>> {
+        let file = Rotating::open(path.to_path_buf(), max_size, keep)?;
+        let (sender, receiver) = mpsc::sync_channel::<String>(CAPACITY);
+        let writer = thread::Builder::new()
+            .name("event-log".to_string())
+            .spawn(move || {
+                let mut file = file;
+                for line in receiver {
+                    file.write_line(&line);
+                }
+                file.flush();
+            })?;
+        Ok(Journal {
+            sender: Some(sender),
+            writer: Some(writer),
+            dropped: 0,
+        })
+    }
+
+    pub fn write(&mut self, event: &NodeEvent) {
+        let sender = match &self.sender {
+            Some(sender) => sender,
+            None => return,
+        };
+        let mut line = format_event(event);
+        if self.dropped > 0 {
+            line = format!(
+                "{} event_log_dropped count={}\n{line}",
+                timestamp(),
+                self.dropped
+            );
+        }
+        match sender.try_send(line) {
+            Ok(()) => self.dropped = 0,
+            Err(TrySendError::Full(_)) => self.dropped += 1,
+            Err(TrySendError::Disconnected(_)) => {
+                warn!("The event log writer stopped, no longer writing the event log");
+                self.sender = None;
+            }
+        }
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        if self.dropped > 0 {
+            warn!(
+                "Dropped {} event log records the writer couldn't keep up with",
+                self.dropped
+            );
+        }
+        // The writer flushes and exits once the channel is closed.
+        self.sender = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+fn timestamp() -> String {
+    Local::now().to_rfc3339_opts(SecondsFormat::Millis, false)
+}
+
+/// The event as `<timestamp> <event> key=value ...`, with nested fields as `outer.inner=value`.
+fn format_event(event: &NodeEvent) -> String {
+    let mut line = timestamp();
+    let fields = match serde_json::to_value(event) {
+        Ok(Value::Object(fields)) => fields,
+        _ => return line,
+    };
+    if let Some(Value::String(name)) = fields.get("event") {
+        line.push(' ');
+        line.push_str(name);
+    }
+    for (key, value) in fields.iter().filter(|(key, _)| *key != "event") {
+        push_field(&mut line, key, value);
+    }
+    line
+}
+
+fn push_field(line: &mut String, key: &str, value: &Value) {
+    match value {
+        Value::Null => {}
+        Value::Object(fields) => {
+            for (inner, value) in fields {
+                push_field(line, &format!("{key}.{inner}"), value);
+            }
+        }
+        Value::String(text) => {
+            if text.is_empty() || text.contains(|c: char| c.is_whitespace() || c == '"') {
+                let _ = write!(line, " {key}={text:?}");
+            } else {
+                let _ = write!(line, " {key}={text}");
+            }
+        }
+        Value::Array(values) => {
+            let joined = values
+                .iter()
+                .map(|value| match value {
+                    Value::String(text) => text.clone(),
+                    value => value.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(",");
+            let _ = write!(line, " {key}={joined}");
+        }
+        value => {
+            let _ = write!(line, " {key}={value}");
+        }
+    }
+}
+
+/// A buffered file that moves itself aside once it is too large.
+struct Rotating {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    out: BufWriter<File>,
+    size: u64,
+}
+
+impl Rotating {
+    fn open(path: PathBuf, max_size: u64, keep: usize) -> Result<Self, Box<dyn Error>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("failed to open event log {}: {e}", path.display()))?;
+        let size = file.metadata()?.len();
+        Ok(Rotating {
+            path,
+            max_size,
+            keep,
+            out: BufWriter::new(file),
+            size,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if let Err(e) = writeln!(self.out, "{line}") {
+            warn!("Failed to write to the event log: {e}");
+            return;
+        }
+        self.size += line.len() as u64 + 1;
+        if self.size > self.max_size {
+            if let Err(e) = self.rotate() {
+                warn!("Failed to rotate the event log: {e}");
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Err(e) = self.out.flush() {
+            warn!("Failed to flush the event log: {e}");
+        }
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        path.into()
+    }
+
+    /// Shifts `<path>.1` to `<path>.2` and so on, dropping the oldest, moves the current file to
+    /// `<path>.1` and starts a new one.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.out.flush()?;
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.rotated(self.keep));
+            for n in (1..self.keep).rev() {
+                let from = self.rotated(n);
+                if from.exists() {
+                    fs::rename(from, self.rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.out = BufWriter::new(file);
+        self.size = 0;
+        Ok(())
+    }
+}
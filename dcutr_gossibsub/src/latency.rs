@@ -0,0 +1,250 @@
+use crate::codec::{read_json, write_json};
+use crate::console::say;
+use crate::envelope;
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncWrite};
+use libp2p::{
+    core::upgrade::ProtocolName,
+    request_response::{self, RequestId},
+    PeerId,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// How long an echo may take before it counts as lost.
+const ECHO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the probe after the hole punch waits for the relayed connection to close, so the
+/// echoes only take the direct one.
+const RELAYED_WAIT: Duration = Duration::from_secs(10);
+
+/// Sent back unchanged by the peer. The time is only informational, round trips are measured
+/// with our own clock.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Echo {
+    pub seq: u32,
+    pub sent_at: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct EchoProtocol;
+
+impl ProtocolName for EchoProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        b"/chat-echo/1"
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct EchoCodec;
+
+#[async_trait]
+impl request_response::Codec for EchoCodec {
+    type Protocol = EchoProtocol;
+    type Request = Echo;
+    type Response = Echo;
+
+    async fn read_request<T>(&mut self, _: &EchoProtocol, io: &mut T) -> io::Result<Echo>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_json(io, 1024).await
+    }
+
+    async fn read_response<T>(&mut self, _: &EchoProtocol, io: &mut T) -> io::Result<Echo>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_json(io, 1024).await
+    }
+
+    async fn write_request<T>(&mut self, _: &EchoProtocol, io: &mut T, echo: Echo) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_json(io, &echo).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &EchoProtocol,
+        io: &mut T,
+        echo: Echo,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_json(io, &echo).await
+    }
+}
+
+pub fn behaviour() -> request_response::Behaviour<EchoCodec> {
+    let mut config = request_response::Config::default();
+    config.set_request_timeout(ECHO_TIMEOUT);
+    request_response::Behaviour::new(
+        EchoCodec,
+        [(EchoProtocol, request_response::ProtocolSupport::Full)],
+        config,
+    )
+}
+
+/// One series of `count` echoes, sent one after the other.
+struct Run {
+    label: &'static str,
+    sent: u32,
+    lost: u32,
+    in_flight: HashMap<RequestId, Instant>,
+    rtts: Vec<Duration>,
+}
+
+impl Run {
+    fn new(label: &'static str) -> Self {
+        say!("Latency probe over the {label} path started");
+        Run {
+            label,
+            sent: 0,
+            lost: 0,
+            in_flight: HashMap::new(),
+            rtts: Vec::new(),
+        }
+    }
+
+    /// `min/median/p95/p99/max` of the round trips and the loss.
+    fn summary(&mut self) -> String {
+        self.rtts.sort();
+        let loss = self.lost as f64 * 100.0 / self.sent.max(1) as f64;
+        if self.rtts.is_empty() {
+            return format!(
+                "latency [{}]: no replies, {}/{} lost ({loss:.1}%)",
+                self.label, self.lost, self.sent
+            );
+        }
+        let ms = |rtt: Duration| rtt.as_secs_f64() * 1000.0;
+        format!(
+            "latency [{}]: min {:.1} ms, median {:.1} ms, p95 {:.1} ms, p99 {:.1} ms, max {:.1} ms, {}/{} lost ({loss:.1}%)",
+            self.label,
+            ms(self.rtts[0]),
+            ms(percentile(&self.rtts, 50)),
+            ms(percentile(&self.rtts, 95)),
+            ms(percentile(&self.rtts, 99)),
+            ms(self.rtts[self.rtts.len() - 1]),
+            self.lost,
+            self.sent
+        )
+    }
+}
+
+/// The nearest-rank percentile of sorted, non-empty `rtts`.
+fn percentile(rtts: &[Duration], p: usize) -> Duration {
+    let rank = (p * rtts.len() + 99) / 100;
+    rtts[rank.clamp(1, rtts.len()) - 1]
+}
+
+/// `--latency-probe`: echoes `count` requests off the remote peer once the first connection to it
+/// is up and again after a hole punch succeeded, so the relayed and direct round trips of a
+/// session can be compared.
+pub struct LatencyProbe {
+    remote: PeerId,
+    count: u32,
+    run: Option<Run>,
+    before_done: bool,
+    upgraded_at: Option<Instant>,
+    after_done: bool,
+}
+
+impl LatencyProbe {
+    pub fn new(remote: PeerId, count: u32) -> Self {
+        LatencyProbe {
+            remote,
+            count,
+            run: None,
+            before_done: false,
+            upgraded_at: None,
+            after_done: false,
+        }
+    }
+
+    pub fn remote(&self) -> PeerId {
+        self.remote
+    }
+
+    /// Starts the first run once connected to the remote, over the path of that connection.
+    pub fn on_connected(&mut self, peer_id: PeerId, direct: bool) -> Option<Echo> {
+        if peer_id != self.remote || self.before_done || self.run.is_some() {
+            return None;
+        }
+        self.before_done = true;
+        self.start(if direct { "direct" } else { "relayed" })
+    }
+
+    pub fn on_upgraded(&mut self, peer_id: PeerId, now: Instant) {
+        if peer_id == self.remote && self.upgraded_at.is_none() {
+            self.upgraded_at = Some(now);
+        }
+    }
+
+    /// Starts the run after the hole punch once the previous one is done and the relayed
+    /// connection is gone, or it took too long to go away.
+    pub fn poll(&mut self, relayed_open: bool, now: Instant) -> Option<Echo> {
+        let upgraded_at = self.upgraded_at?;
+        if self.after_done || self.run.is_some() {
+            return None;
+        }
+        if relayed_open && now - upgraded_at < RELAYED_WAIT {
+            return None;
+        }
+        self.after_done = true;
+        self.before_done = true;
+        self.start(if relayed_open {
+            "relayed+direct"
+        } else {
+            "direct"
+        })
+    }
+
+    fn start(&mut self, label: &'static str) -> Option<Echo> {
+        self.run = Some(Run::new(label));
+        self.next()
+    }
+
+    /// The echo to send next, or `None` with the summary printed once the run is complete.
+    fn next(&mut self) -> Option<Echo> {
+        let run = self.run.as_mut()?;
+        if run.sent < self.count {
+            return Some(Echo {
+                seq: run.sent,
+                sent_at: envelope::unix_millis(),
+            });
+        }
+        if run.in_flight.is_empty() {
+            let mut run = self.run.take()?;
+            say!("{}", run.summary());
+        }
+        None
+    }
+
+    pub fn on_sent(&mut self, id: RequestId, now: Instant) {
+        if let Some(run) = self.run.as_mut() {
+            run.sent += 1;
+            run.in_flight.insert(id, now);
+        }
+    }
+
+    /// Records the round trip of an answered echo, returning the next one to send.
+    pub fn on_response(&mut self, id: &RequestId, now: Instant) -> Option<Echo> {
+        let run = self.run.as_mut()?;
+        let sent_at = run.in_flight.remove(id)?;
+        run.rtts.push(now - sent_at);
+        self.next()
+    }
+
+    /// Counts an echo that got no answer as lost, returning the next one to send.
+    pub fn on_failure(&mut self, id: &RequestId) -> Option<Echo> {
+        let run = self.run.as_mut()?;
+        run.in_flight.remove(id)?;
+        run.lost += 1;
+        self.next()
+    }
+}
@@ -0,0 +1,106 @@
+// Copyright 2021 Protocol Labs.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! A libp2p client that chats over gossipsub with peers behind NATs, through a relay circuit
+//! that DCUtR upgrades to a direct connection. The `dcutr` binary is a command line front end
+//! for it, [`client::run`] is all it does; [`node::Node`] is the entry point for using the
+//! client from other code.
+
+#[cfg(not(any(feature = "tokio", feature = "async-std-runtime")))]
+compile_error!("enable a runtime, either the `tokio` or the `async-std-runtime` feature");
+
+pub mod address_book;
+pub mod allow_list;
+pub mod bandwidth;
+pub mod bans;
+pub mod behaviour;
+pub mod bench;
+pub mod bootstrap;
+pub mod bootstrap_peers;
+pub mod bridge;
+pub mod chunking;
+pub mod client;
+pub mod codec;
+pub mod commands;
+pub mod config;
+pub mod console;
+pub mod control;
+pub mod dm;
+pub mod e2e;
+pub mod envelope;
+pub mod events;
+pub mod explicit;
+pub mod external;
+pub mod fallback;
+pub mod feed;
+pub mod forward;
+pub mod health;
+pub mod history;
+pub mod holepunch;
+pub mod hook;
+pub mod http;
+pub mod http_api;
+pub mod identity;
+pub mod idle;
+pub mod input;
+pub mod journal;
+pub mod latency;
+pub mod limits;
+pub mod logging;
+pub mod lookup;
+pub mod mesh;
+pub mod message_id;
+pub mod message_log;
+pub mod metrics;
+pub mod mqtt;
+pub mod node;
+pub mod once;
+pub mod outbox;
+pub mod output;
+pub mod paths;
+pub mod peer_info;
+pub mod pipeline;
+pub mod presence;
+pub mod psk;
+pub mod qr;
+pub mod relay_addr;
+pub mod relay_session;
+pub mod remotes;
+pub mod reputation;
+pub mod resend;
+pub mod resolver;
+pub mod rtt;
+pub mod scoring;
+pub mod seen;
+pub mod sequences;
+pub mod shutdown;
+pub mod signing;
+pub mod soak;
+pub mod subscribers;
+pub mod throttle;
+pub mod ticket;
+pub mod topic_keys;
+pub mod topics;
+pub mod traffic;
+pub mod transfer;
+pub mod transport;
+pub mod tui;
+pub mod upnp;
+pub mod validation;
@@ -0,0 +1,337 @@
+use crate::holepunch::is_relayed;
+use libp2p::{
+    core::{
+        multiaddr::{Multiaddr, Protocol},
+        Endpoint,
+    },
+    swarm::{
+        dummy, ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, NetworkBehaviourAction,
+        PollParameters, THandler, THandlerInEvent, THandlerOutEvent,
+    },
+    PeerId,
+};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::task::{Context, Poll};
+use void::Void;
+
+/// Caps on the number of connections, `None` is no cap.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+    pub max_established_incoming: Option<usize>,
+    pub max_established_outgoing: Option<usize>,
+    pub max_pending_incoming: Option<usize>,
+    pub max_established_per_peer: Option<usize>,
+    /// Incoming connections through a relay circuit, counting those still being upgraded but
+    /// not those of peers we have a direct connection with as well.
+    pub max_relayed_incoming: Option<usize>,
+}
+
+/// Why a connection was denied.
+#[derive(Debug)]
+pub struct Exceeded {
+    what: &'static str,
+    limit: usize,
+}
+
+impl fmt::Display for Exceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "limit of {} {} reached", self.limit, self.what)
+    }
+}
+
+impl std::error::Error for Exceeded {}
+
+/// Denies connections beyond the [`Limits`], counting those of every peer but the protected
+/// ones: the relay and explicit peers always get through, so strangers dialing us through a busy
+/// relay can't crowd out the session. Inbound circuits carry the peer id of the dialer, so
+/// they're turned away before the upgrade. Direct inbound connections only name their peer once
+/// the handshake is done, so they're capped then; until then the pending cap leaves a slot for
+/// each protected peer.
+pub struct ConnectionGate {
+    limits: Limits,
+    protected: HashSet<PeerId>,
+    pending_incoming: HashSet<ConnectionId>,
+    established_incoming: HashSet<ConnectionId>,
+    established_outgoing: HashSet<ConnectionId>,
+    established_per_peer: HashMap<PeerId, HashSet<ConnectionId>>,
+    /// Incoming circuits still being upgraded.
+    pending_relayed: HashSet<ConnectionId>,
+    /// Established circuits in either direction, to tell a peer's direct connections apart.
+    relayed: HashSet<ConnectionId>,
+    /// Established incoming circuits with the peer at the other end.
+    relayed_incoming: HashMap<ConnectionId, PeerId>,
+    /// Incoming circuits turned away for `max_relayed_incoming`.
+    denied_relayed: u64,
+}
+
+impl ConnectionGate {
+    /// Enforces `limits`, no peer is protected yet.
+    pub fn new(limits: Limits) -> Self {
+        ConnectionGate {
+            limits,
+            protected: HashSet::new(),
+            pending_incoming: HashSet::new(),
+            established_incoming: HashSet::new(),
+            established_outgoing: HashSet::new(),
+            established_per_peer: HashMap::new(),
+            pending_relayed: HashSet::new(),
+            relayed: HashSet::new(),
+            relayed_incoming: HashMap::new(),
+            denied_relayed: 0,
+        }
+    }
+
+    /// Exempts `peer_id` from the limits.
+    pub fn protect(&mut self, peer_id: PeerId) {
+        self.protected.insert(peer_id);
+    }
+
+    /// Puts `peer_id` under the limits again.
+    pub fn unprotect(&mut self, peer_id: &PeerId) {
+        self.protected.remove(peer_id);
+    }
+
+    fn check(
+        &self,
+        peer_id: Option<&PeerId>,
+        limit: Option<usize>,
+        current: usize,
+        what: &'static str,
+    ) -> Result<(), ConnectionDenied> {
+        if peer_id.map_or(false, |p| self.protected.contains(p)) {
+            return Ok(());
+        }
+        match limit {
+            Some(limit) if current >= limit => Err(ConnectionDenied::new(Exceeded { what, limit })),
+            _ => Ok(()),
+        }
+    }
+
+    /// The incoming circuits counting against `max_relayed_incoming`. One of a peer we also
+    /// have a direct connection with is on its way out, the hole punch having succeeded.
+    pub fn relayed_incoming(&self) -> usize {
+        let established = self
+            .relayed_incoming
+            .values()
+            .filter(|peer_id| !self.has_direct(peer_id))
+            .count();
+        self.pending_relayed.len() + established
+    }
+
+    fn has_direct(&self, peer_id: &PeerId) -> bool {
+        self.established_per_peer
+            .get(peer_id)
+            .map_or(false, |connections| {
+                connections.iter().any(|c| !self.relayed.contains(c))
+            })
+    }
+
+    fn check_relayed(
+        &mut self,
+        peer_id: Option<&PeerId>,
+        current: usize,
+    ) -> Result<(), ConnectionDenied> {
+        let checked = self.check(
+            peer_id,
+            self.limits.max_relayed_incoming,
+            current,
+            "relayed incoming connections",
+        );
+        if checked.is_err() {
+            self.denied_relayed += 1;
+        }
+        checked
+    }
+
+    fn check_per_peer(&self, peer_id: &PeerId) -> Result<(), ConnectionDenied> {
+        let current = self
+            .established_per_peer
+            .get(peer_id)
+            .map_or(0, |connections| connections.len());
+        self.check(
+            Some(peer_id),
+            self.limits.max_established_per_peer,
+            current,
+            "connections per peer",
+        )
+    }
+}
+
+/// The peer at the end of `addr`, the dialer of an inbound circuit.
+fn last_peer_id(addr: &Multiaddr) -> Option<PeerId> {
+    match addr.iter().last()? {
+        Protocol::P2p(hash) => PeerId::from_multihash(hash).ok(),
+        _ => None,
+    }
+}
+
+/// `relayed incoming: 3 open (at most 8), 12 denied`, for `/stats`.
+impl fmt::Display for ConnectionGate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "relayed incoming: {} open", self.relayed_incoming())?;
+        if let Some(max) = self.limits.max_relayed_incoming {
+            write!(f, " (at most {max})")?;
+        }
+        write!(f, ", {} denied", self.denied_relayed)
+    }
+}
+
+impl NetworkBehaviour for ConnectionGate {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type OutEvent = Void;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        let peer_id = last_peer_id(remote_addr);
+        // Inbound circuits are accepted on the relayed listen address, as `is_relayed` of the
+        // connection's endpoint has it.
+        let relayed = is_relayed(local_addr);
+        // A direct connection names its peer only after the handshake, it may be a protected one.
+        let headroom = match peer_id {
+            Some(_) => 0,
+            None => self.protected.len(),
+        };
+        self.check(
+            peer_id.as_ref(),
+            self.limits.max_pending_incoming.map(|max| max + headroom),
+            self.pending_incoming.len(),
+            "pending incoming connections",
+        )?;
+        // Without the peer id, this waits for `handle_established_inbound_connection`.
+        if peer_id.is_some() {
+            self.check(
+                peer_id.as_ref(),
+                self.limits.max_established_incoming,
+                self.established_incoming.len(),
+                "incoming connections",
+            )?;
+        }
+        if relayed {
+            self.check_relayed(peer_id.as_ref(), self.relayed_incoming())?;
+            self.pending_relayed.insert(connection_id);
+        }
+        self.pending_incoming.insert(connection_id);
+        Ok(())
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer_id: PeerId,
+        local_addr: &Multiaddr,
+        _: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.pending_incoming.remove(&connection_id);
+        self.check(
+            Some(&peer_id),
+            self.limits.max_established_incoming,
+            self.established_incoming.len(),
+            "incoming connections",
+        )?;
+        if self.pending_relayed.remove(&connection_id) || is_relayed(local_addr) {
+            // Checked again with the peer id known, if the address didn't carry it.
+            let current = self.relayed_incoming();
+            self.check_relayed(Some(&peer_id), current)?;
+        }
+        self.check_per_peer(&peer_id)?;
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        _: ConnectionId,
+        peer_id: Option<PeerId>,
+        _: &[Multiaddr],
+        _: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        self.check(
+            peer_id.as_ref(),
+            self.limits.max_established_outgoing,
+            self.established_outgoing.len(),
+            "outgoing connections",
+        )?;
+        if let Some(peer_id) = &peer_id {
+            self.check_per_peer(peer_id)?;
+        }
+        Ok(Vec::new())
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        _: ConnectionId,
+        peer_id: PeerId,
+        _: &Multiaddr,
+        _: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.check(
+            Some(&peer_id),
+            self.limits.max_established_outgoing,
+            self.established_outgoing.len(),
+            "outgoing connections",
+        )?;
+        self.check_per_peer(&peer_id)?;
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm<Self::ConnectionHandler>) {
+        match event {
+            FromSwarm::ConnectionEstablished(established) => {
+                let connection_id = established.connection_id;
+                match established.endpoint.is_dialer() {
+                    true => self.established_outgoing.insert(connection_id),
+                    false => self.established_incoming.insert(connection_id),
+                };
+                self.established_per_peer
+                    .entry(established.peer_id)
+                    .or_default()
+                    .insert(connection_id);
+                if established.endpoint.is_relayed() {
+                    self.relayed.insert(connection_id);
+                    if !established.endpoint.is_dialer() {
+                        self.relayed_incoming
+                            .insert(connection_id, established.peer_id);
+                    }
+                }
+            }
+            FromSwarm::ConnectionClosed(closed) => {
+                self.established_incoming.remove(&closed.connection_id);
+                self.established_outgoing.remove(&closed.connection_id);
+                self.relayed.remove(&closed.connection_id);
+                self.relayed_incoming.remove(&closed.connection_id);
+                if let Some(connections) = self.established_per_peer.get_mut(&closed.peer_id) {
+                    connections.remove(&closed.connection_id);
+                    if connections.is_empty() {
+                        self.established_per_peer.remove(&closed.peer_id);
+                    }
+                }
+            }
+            FromSwarm::ListenFailure(failure) => {
+                self.pending_incoming.remove(&failure.connection_id);
+                self.pending_relayed.remove(&failure.connection_id);
+            }
+            _ => {}
+        }
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        _: PeerId,
+        _: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        void::unreachable(event)
+    }
+
+    fn poll(
+        &mut self,
+        _: &mut Context<'_>,
+        _: &mut impl PollParameters,
+    ) -> Poll<NetworkBehaviourAction<Self::OutEvent, THandlerInEvent<Self>>> {
+        Poll::Pending
+    }
+}
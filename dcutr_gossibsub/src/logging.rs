@@ -0,0 +1,111 @@
+use libp2p::{dcutr, relay, PeerId};
+use std::error::Error;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Mutex;
+use tracing::{field, info_span, Span};
+use tracing_subscriber::EnvFilter;
+
+/// What diagnostics look like when no `RUST_LOG` is set: our own at info, libp2p's warnings.
+const DEFAULT_FILTER: &str = "warn,dcutr=info";
+
+/// How diagnostics are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per event, with the fields of the event and its spans.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("Expected either 'text' or 'json'".to_string()),
+        }
+    }
+}
+
+/// Installs the subscriber, filtered by `RUST_LOG`, or showing only errors with `quiet`.
+/// Diagnostics go to stderr, or to `pane` when the TUI is running. The `log` records of libp2p
+/// are turned into tracing events as well.
+pub fn init(
+    format: LogFormat,
+    quiet: bool,
+    pane: Option<impl Write + Send + 'static>,
+) -> Result<(), Box<dyn Error>> {
+    let filter = if quiet {
+        EnvFilter::new("error")
+    } else {
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER))
+    };
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    let result = match (format, pane) {
+        (LogFormat::Text, None) => builder.with_writer(std::io::stderr).try_init(),
+        (LogFormat::Text, Some(pane)) => builder
+            .with_ansi(false)
+            .with_writer(Mutex::new(pane))
+            .try_init(),
+        (LogFormat::Json, None) => builder.json().with_writer(std::io::stderr).try_init(),
+        (LogFormat::Json, Some(pane)) => builder.json().with_writer(Mutex::new(pane)).try_init(),
+    };
+    result.map_err(|e| e as Box<dyn Error>)
+}
+
+/// The stage of the session an event belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Listening, learning our addresses and reaching the relay and bootstrap peers.
+    Bootstrap,
+    Reservation,
+    HolePunch,
+    SteadyState,
+}
+
+impl Phase {
+    fn as_str(self) -> &'static str {
+        match self {
+            Phase::Bootstrap => "bootstrap",
+            Phase::Reservation => "reservation",
+            Phase::HolePunch => "holepunch",
+            Phase::SteadyState => "steady_state",
+        }
+    }
+}
+
+/// The span events of `phase` are handled in, with the remote peer they are about if any.
+pub fn span(phase: Phase, peer_id: Option<PeerId>) -> Span {
+    let span = info_span!("swarm", phase = phase.as_str(), peer_id = field::Empty);
+    if let Some(peer_id) = peer_id {
+        span.record("peer_id", field::display(peer_id));
+    }
+    span
+}
+
+/// The peer on the other end of a relay client event, the relay itself for our reservations.
+pub fn relay_peer(event: &relay::client::Event) -> PeerId {
+    use relay::client::Event;
+    match event {
+        Event::ReservationReqAccepted { relay_peer_id, .. }
+        | Event::ReservationReqFailed { relay_peer_id, .. }
+        | Event::OutboundCircuitEstablished { relay_peer_id, .. }
+        | Event::OutboundCircuitReqFailed { relay_peer_id, .. }
+        | Event::InboundCircuitReqFailed { relay_peer_id, .. } => *relay_peer_id,
+        Event::InboundCircuitEstablished { src_peer_id, .. }
+        | Event::InboundCircuitReqDenied { src_peer_id }
+        | Event::InboundCircuitReqDenyFailed { src_peer_id, .. } => *src_peer_id,
+    }
+}
+
+/// The peer a hole punch event is about.
+pub fn dcutr_peer(event: &dcutr::Event) -> PeerId {
+    use dcutr::Event;
+    match event {
+        Event::InitiatedDirectConnectionUpgrade { remote_peer_id, .. }
+        | Event::RemoteInitiatedDirectConnectionUpgrade { remote_peer_id, .. }
+        | Event::DirectConnectionUpgradeSucceeded { remote_peer_id }
+        | Event::DirectConnectionUpgradeFailed { remote_peer_id, .. } => *remote_peer_id,
+    }
+}
@@ -0,0 +1,124 @@
+use libp2p::{
+    kad::{self, store::MemoryStore, GetClosestPeersError, GetClosestPeersOk, Kademlia},
+    PeerId,
+};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Protocol name announced by peers that speak Kademlia.
+pub const KAD_PROTOCOL: &str = "/ipfs/kad/1.0.0";
+
+/// Finds the addresses of a remote peer through the DHT so it can be dialed without knowing
+/// which relay it is reserved on. Failed lookups and stale records are retried with a growing
+/// delay until `max_retries` is exhausted.
+pub struct RemoteLookup {
+    target: PeerId,
+    max_retries: u32,
+    attempts: u32,
+    query: Option<kad::QueryId>,
+    retry_at: Option<Instant>,
+    done: bool,
+}
+
+/// What the caller should do after feeding an event into the lookup.
+#[derive(Debug, PartialEq)]
+pub enum LookupStep {
+    /// Nothing to do yet.
+    Pending,
+    /// The DHT returned the target, dial it by peer id.
+    Dial,
+    /// All attempts failed, fall back to the circuit through our own relay.
+    GiveUp,
+}
+
+impl RemoteLookup {
+    pub fn new(target: PeerId, max_retries: u32) -> Self {
+        RemoteLookup {
+            target,
+            max_retries,
+            attempts: 0,
+            query: None,
+            retry_at: None,
+            done: false,
+        }
+    }
+
+    pub fn target(&self) -> PeerId {
+        self.target
+    }
+
+    pub fn start(&mut self, kademlia: &mut Kademlia<MemoryStore>) {
+        self.attempts += 1;
+        self.retry_at = None;
+        info!(
+            "Looking up {} in the DHT (attempt {}/{})",
+            self.target,
+            self.attempts,
+            self.max_retries + 1
+        );
+        self.query = Some(kademlia.get_closest_peers(self.target));
+    }
+
+    pub fn on_closest_peers(
+        &mut self,
+        id: kad::QueryId,
+        result: Result<GetClosestPeersOk, GetClosestPeersError>,
+    ) -> LookupStep {
+        if self.done || self.query != Some(id) {
+            return LookupStep::Pending;
+        }
+        self.query = None;
+
+        let peers = match result {
+            Ok(GetClosestPeersOk { peers, .. }) => peers,
+            Err(GetClosestPeersError::Timeout { peers, .. }) => {
+                warn!("DHT lookup for {} timed out", self.target);
+                peers
+            }
+        };
+        if peers.contains(&self.target) {
+            info!("DHT lookup found {}, dialing it", self.target);
+            return LookupStep::Dial;
+        }
+
+        warn!(
+            "DHT lookup did not find {} among {} closest peers",
+            self.target,
+            peers.len()
+        );
+        self.schedule_retry()
+    }
+
+    /// Dialing the addresses from the DHT failed, most likely because the record is stale.
+    pub fn on_dial_failure(&mut self) -> LookupStep {
+        if self.done || self.query.is_some() || self.retry_at.is_some() {
+            return LookupStep::Pending;
+        }
+        warn!("Addresses for {} from the DHT are unreachable", self.target);
+        self.schedule_retry()
+    }
+
+    pub fn on_connected(&mut self) {
+        self.done = true;
+        self.retry_at = None;
+    }
+
+    pub fn retry_due(&self, now: Instant) -> bool {
+        !self.done && self.retry_at.map_or(false, |at| at <= now)
+    }
+
+    fn schedule_retry(&mut self) -> LookupStep {
+        if self.attempts > self.max_retries {
+            self.done = true;
+            warn!(
+                "Giving up on DHT lookup for {} after {} attempts",
+                self.target, self.attempts
+            );
+            return LookupStep::GiveUp;
+        }
+        let delay = Duration::from_secs(1 << self.attempts.min(6));
+        info!("Retrying DHT lookup for {} in {:?}", self.target, delay);
+        self.retry_at = Some(Instant::now() + delay);
+        LookupStep::Pending
+    }
+}
@@ -18,326 +18,13 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use async_std::io;
-use clap::Parser;
-use futures::{
-    executor::{block_on, ThreadPool},
-    future::FutureExt,
-    stream::StreamExt,
-    AsyncBufReadExt,
-};
-use libp2p::{
-    core::{
-        multiaddr::{Multiaddr, Protocol},
-        transport::{OrTransport, Transport},
-        upgrade,
-    },
-    dcutr,
-    dns::DnsConfig,
-    gossipsub, identify, identity, noise, ping, relay,
-    swarm::{NetworkBehaviour, SwarmBuilder, SwarmEvent},
-    tcp, yamux, PeerId,
-};
-use log::info;
-use std::collections::hash_map::DefaultHasher;
+use dcutr::client::{self, Opts};
+use dcutr::config;
 use std::error::Error;
-use std::hash::{Hash, Hasher};
-use std::net::Ipv4Addr;
-use std::str::FromStr;
-use std::time::Duration;
-#[derive(Debug, Parser)]
-#[clap(name = "libp2p DCUtR client")]
-struct Opts {
-    /// The mode (client-listen, client-dial).
-    #[clap(long)]
-    mode: Mode,
+use std::process::ExitCode;
 
-    /// Fixed value to generate deterministic peer id.
-    #[clap(long)]
-    secret_key_seed: u8,
-
-    /// The listening address
-    #[clap(long)]
-    relay_address: Multiaddr,
-
-    /// Peer ID of the remote peer to hole punch to.
-    #[clap(long)]
-    remote_peer_id: Option<PeerId>,
-}
-
-#[derive(Clone, Debug, PartialEq, Parser)]
-enum Mode {
-    Dial,
-    Listen,
-}
-
-impl FromStr for Mode {
-    type Err = String;
-    fn from_str(mode: &str) -> Result<Self, Self::Err> {
-        match mode {
-            "dial" => Ok(Mode::Dial),
-            "listen" => Ok(Mode::Listen),
-            _ => Err("Expected either 'dial' or 'listen'".to_string()),
-        }
-    }
-}
-
-fn main() -> Result<(), Box<dyn Error>> {
-    env_logger::init();
-
-    let opts = Opts::parse();
-
-    let local_key = generate_ed25519(opts.secret_key_seed);
-    let local_peer_id = PeerId::from(local_key.public());
-    info!("Local peer id: {:?}", local_peer_id);
-
-    let (relay_transport, client) = relay::client::new(local_peer_id);
-
-    let transport = OrTransport::new(
-        relay_transport,
-        block_on(DnsConfig::system(tcp::async_io::Transport::new(
-            tcp::Config::default().port_reuse(true),
-        )))
-        .unwrap(),
-    )
-    .upgrade(upgrade::Version::V1Lazy)
-    .authenticate(
-        noise::Config::new(&local_key).expect("Signing libp2p-noise static DH keypair failed."),
-    )
-    .multiplex(yamux::Config::default())
-    .boxed();
-
-    // To content-address message, we can take the hash of message and use it as an ID.
-    let message_id_fn = |message: &gossipsub::Message| {
-        let mut s = DefaultHasher::new();
-        message.data.hash(&mut s);
-        gossipsub::MessageId::from(s.finish().to_string())
-    };
-
-    // Set a custom gossipsub configuration
-    let gossipsub_config = gossipsub::ConfigBuilder::default()
-        .heartbeat_interval(Duration::from_secs(10)) // This is set to aid debugging by not cluttering the log space
-        .validation_mode(gossipsub::ValidationMode::Strict) // This sets the kind of message validation. The default is Strict (enforce message signing)
-        .message_id_fn(message_id_fn) // content-address messages. No two messages of the same content will be propagated.
-        .build()
-        .expect("Valid config");
-
-    // build a gossipsub network behaviour
-    let mut gossipsub = gossipsub::Behaviour::new(
-        gossipsub::MessageAuthenticity::Signed(local_key.clone()),
-        gossipsub_config,
-    )
-    .expect("Correct configuration");
-    // Create a Gossipsub topic
-    let topic = gossipsub::IdentTopic::new("test-net");
-    // subscribes to our topic
-    gossipsub.subscribe(&topic)?;
-
-    #[derive(NetworkBehaviour)]
-    #[behaviour(to_swarm = "Event")]
-    struct Behaviour {
-        relay_client: relay::client::Behaviour,
-        ping: ping::Behaviour,
-        identify: identify::Behaviour,
-        dcutr: dcutr::Behaviour,
-        gossipsub: gossipsub::Behaviour,
-    }
-
-    #[derive(Debug)]
-    #[allow(clippy::large_enum_variant)]
-    enum Event {
-        Ping(ping::Event),
-        Identify(identify::Event),
-        Relay(relay::client::Event),
-        Dcutr(dcutr::Event),
-    }
-
-    impl From<ping::Event> for Event {
-        fn from(e: ping::Event) -> Self {
-            Event::Ping(e)
-        }
-    }
-
-    impl From<identify::Event> for Event {
-        fn from(e: identify::Event) -> Self {
-            Event::Identify(e)
-        }
-    }
-
-    impl From<relay::client::Event> for Event {
-        fn from(e: relay::client::Event) -> Self {
-            Event::Relay(e)
-        }
-    }
-
-    impl From<dcutr::Event> for Event {
-        fn from(e: dcutr::Event) -> Self {
-            Event::Dcutr(e)
-        }
-    }
-
-    let behaviour = Behaviour {
-        relay_client: client,
-        ping: ping::Behaviour::new(ping::Config::new()),
-        identify: identify::Behaviour::new(identify::Config::new(
-            "/TODO/0.0.1".to_string(),
-            local_key.public(),
-        )),
-        dcutr: dcutr::Behaviour::new(local_peer_id),
-        gossipsub,
-    };
-
-    let mut swarm = match ThreadPool::new() {
-        Ok(tp) => SwarmBuilder::with_executor(transport, behaviour, local_peer_id, tp),
-        Err(_) => SwarmBuilder::without_executor(transport, behaviour, local_peer_id),
-    }
-    .build();
-    let mut stdin = io::BufReader::new(io::stdin()).lines().fuse();
-
-    swarm
-        .listen_on(
-            Multiaddr::empty()
-                .with("0.0.0.0".parse::<Ipv4Addr>().unwrap().into())
-                .with(Protocol::Tcp(0)),
-        )
-        .unwrap();
-
-    // Wait to listen on all interfaces.
-    block_on(async {
-        let mut delay: futures::future::Fuse<futures_timer::Delay> =
-            futures_timer::Delay::new(std::time::Duration::from_secs(1)).fuse();
-        loop {
-            futures::select! {
-                event = swarm.next() => {
-                    match event.unwrap() {
-                        SwarmEvent::NewListenAddr { address, .. } => {
-                            info!("Listening on {:?}", address);
-                        }
-                        event => panic!("{event:?}"),
-                    }
-                }
-                _ = delay => {
-                    // Likely listening on all interfaces now, thus continuing by breaking the loop.
-                    break;
-                }
-            }
-        }
-    });
-
-    // Connect to the relay server. Not for the reservation or relayed connection, but to (a) learn
-    // our local public address and (b) enable a freshly started relay to learn its public address.
-    swarm.dial(opts.relay_address.clone()).unwrap();
-    block_on(async {
-        let mut learned_observed_addr = false;
-        let mut told_relay_observed_addr = false;
-
-        loop {
-            match swarm.next().await.unwrap() {
-                SwarmEvent::NewListenAddr { .. } => {}
-                SwarmEvent::Dialing { .. } => {}
-                SwarmEvent::ConnectionEstablished { .. } => {}
-                SwarmEvent::Behaviour(BehaviourEvent::Ping(_)) => {}
-                SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Sent {
-                    ..
-                })) => {
-                    info!("Told relay its public address.");
-                    told_relay_observed_addr = true;
-                }
-                SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Received {
-                    info: identify::Info { observed_addr, .. },
-                    ..
-                })) => {
-                    info!("Relay told us our public address: {:?}", observed_addr);
-                    learned_observed_addr = true;
-                }
-                event => panic!("{event:?}"),
-            }
-
-            if learned_observed_addr && told_relay_observed_addr {
-                break;
-            }
-        }
-    });
-
-    match opts.mode {
-        Mode::Dial => {
-            swarm
-                .dial(
-                    opts.relay_address
-                        .with(Protocol::P2pCircuit)
-                        .with(Protocol::P2p(opts.remote_peer_id.unwrap().into())),
-                )
-                .unwrap();
-        }
-        Mode::Listen => {
-            swarm
-                .listen_on(opts.relay_address.with(Protocol::P2pCircuit))
-                .unwrap();
-        }
-    }
-    println!("Enter messages via STDIN and they will be sent to connected peers using Gossipsub");
-
-    block_on(async {
-        loop {
-            futures::select!(
-                line = stdin.select_next_some() => {
-                    if let Err(e) = swarm
-                        .behaviour_mut().gossipsub
-                        .publish(topic.clone(), line.expect("Stdin not to close").as_bytes()) {
-                        println!("Publish error: {e:?}");
-                    }
-                },
-                event = swarm.select_next_some() => match event {
-                    SwarmEvent::NewListenAddr { address, .. } => {
-                        println!("Listening on {:?}", address);
-                    }
-                    SwarmEvent::Behaviour(BehaviourEvent::RelayClient(
-                        relay::client::Event::ReservationReqAccepted { .. },
-                    )) => {
-                        assert!(opts.mode == Mode::Listen);
-                        info!("Relay accepted our reservation request.");
-                    }
-                    SwarmEvent::Behaviour(BehaviourEvent::RelayClient(event)) => {
-                        info!("{:?}", event)
-                    }
-                    SwarmEvent::Behaviour(BehaviourEvent::Dcutr(event)) => {
-                        print!("+++++++++++++DCUTR++++++++++++++++++");
-                        println!("{:?}", event)
-                        //info!("{:?}", event)
-                    }
-                    SwarmEvent::Behaviour(BehaviourEvent::Identify(event)) => {
-                        info!("{:?}", event)
-                    }
-                    SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Message {
-                        propagation_source: peer_id,
-                        message_id: id,
-                        message,
-                    })) => println!(
-                            "Got message: '{}' with id: {id} from peer: {peer_id}",
-                            String::from_utf8_lossy(&message.data),
-                        ),
-                    SwarmEvent::Behaviour(BehaviourEvent::Ping(event)) => {
-                        info!("{:?}", event)
-                    }
-                    SwarmEvent::ConnectionEstablished {
-                        peer_id, endpoint, ..
-                    } => {
-                        println!("Established connection to {:?} via {:?}", peer_id, endpoint);
-                        swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
-                    }
-                    SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
-                        println!("Outgoing connection error to {:?}: {:?}", peer_id, error);
-                    }
-                    _ => {}
-                }
-            )
-        }
-    })
-}
-
-fn generate_ed25519(secret_key_seed: u8) -> identity::Keypair {
-    let mut bytes = [0u8; 32];
-    bytes[0] = secret_key_seed;
-
-    identity::Keypair::ed25519_from_bytes(bytes).expect("only errors on wrong length")
+#[cfg_attr(feature = "tokio", tokio::main)]
+#[cfg_attr(not(feature = "tokio"), async_std::main)]
+async fn main() -> Result<ExitCode, Box<dyn Error>> {
+    client::run(config::load::<Opts>()?).await
 }
@@ -0,0 +1,115 @@
+use libp2p::gossipsub;
+use std::time::Duration;
+use tracing::info;
+
+/// Gossipsub mesh parameters. Unset values keep gossipsub's defaults, which are tuned for large
+/// networks; for two or three peers something like `mesh_n = 2, mesh_n_low = 1, mesh_n_high = 4,
+/// mesh_outbound_min = 0` lets the mesh form right away.
+#[derive(Clone, Copy, Debug)]
+pub struct MeshParams {
+    pub mesh_n: usize,
+    pub mesh_n_low: usize,
+    pub mesh_n_high: usize,
+    pub mesh_outbound_min: usize,
+    pub gossip_lazy: usize,
+}
+
+impl MeshParams {
+    pub fn new(
+        mesh_n: Option<usize>,
+        mesh_n_low: Option<usize>,
+        mesh_n_high: Option<usize>,
+        mesh_outbound_min: Option<usize>,
+        gossip_lazy: Option<usize>,
+    ) -> Self {
+        let defaults = gossipsub::Config::default();
+        MeshParams {
+            mesh_n: mesh_n.unwrap_or(defaults.mesh_n()),
+            mesh_n_low: mesh_n_low.unwrap_or(defaults.mesh_n_low()),
+            mesh_n_high: mesh_n_high.unwrap_or(defaults.mesh_n_high()),
+            mesh_outbound_min: mesh_outbound_min.unwrap_or(defaults.mesh_outbound_min()),
+            gossip_lazy: gossip_lazy.unwrap_or(defaults.gossip_lazy()),
+        }
+    }
+
+    /// Checks the constraints `gossipsub::ConfigBuilder::build` enforces, with readable errors.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.mesh_n_low <= self.mesh_n && self.mesh_n <= self.mesh_n_high) {
+            return Err(format!(
+                "mesh parameters must satisfy mesh_n_low <= mesh_n <= mesh_n_high, got {} <= {} <= {}",
+                self.mesh_n_low, self.mesh_n, self.mesh_n_high
+            ));
+        }
+        if self.mesh_outbound_min > self.mesh_n_low || self.mesh_outbound_min * 2 > self.mesh_n {
+            return Err(format!(
+                "mesh_outbound_min ({}) must be at most mesh_n_low ({}) and at most half of mesh_n ({})",
+                self.mesh_outbound_min, self.mesh_n_low, self.mesh_n
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn apply<'a>(
+        &self,
+        builder: &'a mut gossipsub::ConfigBuilder,
+    ) -> &'a mut gossipsub::ConfigBuilder {
+        info!(
+            "Gossipsub mesh: mesh_n={} mesh_n_low={} mesh_n_high={} mesh_outbound_min={} gossip_lazy={}",
+            self.mesh_n, self.mesh_n_low, self.mesh_n_high, self.mesh_outbound_min, self.gossip_lazy
+        );
+        builder
+            .mesh_n(self.mesh_n)
+            .mesh_n_low(self.mesh_n_low)
+            .mesh_n_high(self.mesh_n_high)
+            .mesh_outbound_min(self.mesh_outbound_min)
+            .gossip_lazy(self.gossip_lazy)
+    }
+}
+
+/// Publish behaviour for tiny meshes, where waiting for the mesh to be grafted delays or drops
+/// the first messages.
+#[derive(Clone, Copy, Debug)]
+pub struct PublishParams {
+    /// Publish to every subscribed peer rather than just the mesh. `None` keeps gossipsub's
+    /// default.
+    pub flood_publish: Option<bool>,
+    pub heartbeat_initial_delay: Option<Duration>,
+    pub allow_self_origin: bool,
+}
+
+impl PublishParams {
+    pub fn validate(&self, heartbeat_interval: Duration) -> Result<(), String> {
+        if let Some(delay) = self.heartbeat_initial_delay {
+            if delay > heartbeat_interval {
+                return Err(format!(
+                    "heartbeat initial delay ({delay:?}) must not exceed the heartbeat interval ({heartbeat_interval:?})"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn apply<'a>(
+        &self,
+        builder: &'a mut gossipsub::ConfigBuilder,
+    ) -> &'a mut gossipsub::ConfigBuilder {
+        if let Some(flood_publish) = self.flood_publish {
+            builder.flood_publish(flood_publish);
+        }
+        if let Some(delay) = self.heartbeat_initial_delay {
+            builder.heartbeat_initial_delay(delay);
+        }
+        builder.allow_self_origin(self.allow_self_origin)
+    }
+}
+
+/// Logs the publish related settings gossipsub ended up with.
+pub fn log_publish_config(config: &gossipsub::Config) {
+    info!(
+        "Gossipsub publish: flood_publish={} heartbeat_initial_delay={:?} heartbeat_interval={:?} allow_self_origin={}",
+        config.flood_publish(),
+        config.heartbeat_initial_delay(),
+        config.heartbeat_interval(),
+        config.allow_self_origin()
+    );
+}
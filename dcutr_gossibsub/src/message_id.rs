@@ -0,0 +1,62 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use libp2p::gossipsub::{Message, MessageId};
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+/// How gossipsub message ids are derived. Peers deduplicate by id, so every peer of a session
+/// should use the same scheme.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageIdScheme {
+    /// SHA-256 over the origin, sequence number and data.
+    Sha256,
+    /// 64 bit `DefaultHasher` over the data only, what older versions of this client used.
+    Legacy,
+}
+
+impl FromStr for MessageIdScheme {
+    type Err = String;
+    fn from_str(scheme: &str) -> Result<Self, Self::Err> {
+        match scheme {
+            "sha256" => Ok(MessageIdScheme::Sha256),
+            "legacy" => Ok(MessageIdScheme::Legacy),
+            _ => Err("Expected either 'sha256' or 'legacy'".to_string()),
+        }
+    }
+}
+
+impl MessageIdScheme {
+    pub fn id_fn(self) -> fn(&Message) -> MessageId {
+        match self {
+            MessageIdScheme::Sha256 => sha256_id,
+            MessageIdScheme::Legacy => legacy_id,
+        }
+    }
+}
+
+/// Base64 (URL safe, unpadded) SHA-256 of the message's origin and sequence number, when
+/// present, followed by its data. Each field is length prefixed so they can't run into each other.
+pub fn sha256_id(message: &Message) -> MessageId {
+    let mut hasher = Sha256::new();
+    let source = message.source.map(|p| p.to_bytes()).unwrap_or_default();
+    hasher.update((source.len() as u64).to_be_bytes());
+    hasher.update(&source);
+    match message.sequence_number {
+        Some(seqno) => {
+            hasher.update([1]);
+            hasher.update(seqno.to_be_bytes());
+        }
+        None => hasher.update([0]),
+    }
+    hasher.update(&message.data);
+    MessageId::from(URL_SAFE_NO_PAD.encode(hasher.finalize()))
+}
+
+/// The original content address: `DefaultHasher` over the data. Only 64 bits and not stable
+/// across Rust releases, kept to interoperate with older peers.
+pub fn legacy_id(message: &Message) -> MessageId {
+    let mut s = DefaultHasher::new();
+    message.data.hash(&mut s);
+    MessageId::from(s.finish().to_string())
+}
@@ -0,0 +1,247 @@
+use crate::envelope;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use libp2p::{gossipsub::TopicHash, PeerId};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// How often appended records are flushed to disk.
+const SYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+/// One sent or received message, a line of the log.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LogRecord {
+    /// The topic hash.
+    pub topic: String,
+    pub topic_name: String,
+    /// The envelope's `origin/seq`, or the gossipsub message id for bare payloads.
+    pub message_id: String,
+    pub sender: String,
+    /// Unix time in milliseconds.
+    pub sent_at: u64,
+    pub body: String,
+    /// Unix time in milliseconds the envelope expires at, replay skips the record after that.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// Base64 of the payload as published, to feed the history buffer on replay.
+    pub data: String,
+}
+
+impl LogRecord {
+    /// The record of `data`, with an enveloped body decompressed up to `max_decompressed` bytes.
+    /// Payloads whose body can't be decompressed are kept like bare ones.
+    pub fn new(
+        topic: &TopicHash,
+        topic_name: String,
+        message_id: String,
+        source: Option<PeerId>,
+        data: &[u8],
+        max_decompressed: usize,
+    ) -> Self {
+        let envelope = envelope::open(data, max_decompressed).and_then(Result::ok);
+        let expires_at = envelope.as_ref().and_then(|e| e.expires_at);
+        let (message_id, sender, sent_at, body) = match envelope {
+            // Encrypted bodies stay encrypted at rest, replay decrypts them from `data`.
+            Some(envelope) if envelope.key_id.is_some() => (
+                envelope.key(),
+                envelope.sender().to_string(),
+                envelope.sent_at,
+                "<encrypted>".to_string(),
+            ),
+            Some(envelope) => (
+                envelope.key(),
+                envelope.sender().to_string(),
+                envelope.sent_at,
+                envelope.body,
+            ),
+            None => (
+                message_id,
+                source.map(|p| p.to_string()).unwrap_or_default(),
+                envelope::unix_millis(),
+                String::from_utf8_lossy(data).into_owned(),
+            ),
+        };
+        LogRecord {
+            topic: topic.to_string(),
+            topic_name,
+            message_id,
+            sender,
+            sent_at,
+            body,
+            expires_at,
+            data: STANDARD.encode(data),
+        }
+    }
+
+    pub fn is_expired(&self, now_millis: u64) -> bool {
+        self.expires_at
+            .map_or(false, |expires_at| now_millis > expires_at)
+    }
+
+    pub fn topic_hash(&self) -> TopicHash {
+        TopicHash::from_raw(self.topic.clone())
+    }
+
+    pub fn payload(&self) -> Vec<u8> {
+        STANDARD.decode(&self.data).unwrap_or_default()
+    }
+}
+
+/// Append-only JSONL log of sent and received messages, synced to disk every few seconds. Once
+/// it grows past its maximum size it is moved to `<path>.1`, replacing the one before, and a
+/// new file is started, so at most twice the maximum is kept.
+pub struct MessageLog {
+    path: PathBuf,
+    max_size: u64,
+    file: File,
+    size: u64,
+    dirty: bool,
+    synced: Instant,
+}
+
+impl MessageLog {
+    /// Opens the log of `peer_id` in `dir`, handing its records to `replay` oldest first, those
+    /// of the rotated file before the current one. The files are streamed, not read into memory.
+    /// A torn record at the end, as left by a crash in the middle of a write, is cut off;
+    /// unreadable records elsewhere are skipped.
+    pub fn open(
+        dir: &Path,
+        peer_id: &PeerId,
+        max_size: u64,
+        mut replay: impl FnMut(LogRecord),
+    ) -> Result<Self, Box<dyn Error>> {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("failed to create data dir {}: {e}", dir.display()))?;
+        let path = dir.join(format!("messages-{peer_id}.jsonl"));
+        let previous = rotated(&path);
+        match File::open(&previous) {
+            Ok(file) => {
+                read_records(BufReader::new(file), &mut replay)
+                    .map_err(|e| format!("failed to read {}: {e}", previous.display()))?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("failed to read {}: {e}", previous.display()).into()),
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .open(&path)
+            .map_err(|e| format!("failed to open {}: {e}", path.display()))?;
+        let scan = read_records(BufReader::new(&file), &mut replay)
+            .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+        if scan.valid_len < scan.len {
+            warn!(
+                "Truncating {} bytes of incomplete records ({} lines) at the end of {}",
+                scan.len - scan.valid_len,
+                scan.skipped,
+                path.display()
+            );
+            file.set_len(scan.valid_len)?;
+        }
+        file.seek(SeekFrom::End(0))?;
+        Ok(MessageLog {
+            path,
+            max_size,
+            file,
+            size: scan.valid_len,
+            dirty: false,
+            synced: Instant::now(),
+        })
+    }
+
+    pub fn append(&mut self, record: &LogRecord) {
+        let mut line = serde_json::to_vec(record).expect("log record serializes to JSON");
+        line.push(b'\n');
+        if let Err(e) = self.file.write_all(&line) {
+            warn!("Failed to append to the message log: {e}");
+            return;
+        }
+        self.dirty = true;
+        self.size += line.len() as u64;
+        if self.size > self.max_size {
+            if let Err(e) = self.rotate() {
+                warn!("Failed to rotate the message log: {e}");
+            }
+        }
+    }
+
+    /// Moves the log to `<path>.1` and starts a new one.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.sync();
+        fs::rename(&self.path, rotated(&self.path))?;
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Syncs appended records to disk if the last sync is long enough ago.
+    pub fn sync_due(&mut self, now: Instant) {
+        if now.duration_since(self.synced) >= SYNC_INTERVAL {
+            self.sync();
+            self.synced = now;
+        }
+    }
+
+    pub fn sync(&mut self) {
+        if self.dirty {
+            if let Err(e) = self.file.sync_data() {
+                warn!("Failed to sync the message log: {e}");
+            }
+            self.dirty = false;
+        }
+    }
+}
+
+fn rotated(path: &Path) -> PathBuf {
+    let mut path = path.to_path_buf().into_os_string();
+    path.push(".1");
+    path.into()
+}
+
+/// How far [`read_records`] got through a file.
+struct Scan {
+    len: u64,
+    /// The length up to the end of the last readable record.
+    valid_len: u64,
+    /// Unreadable lines after the last readable record.
+    skipped: usize,
+}
+
+/// Hands the records of `reader` to `each`, one line at a time.
+fn read_records(mut reader: impl BufRead, each: &mut impl FnMut(LogRecord)) -> io::Result<Scan> {
+    let mut scan = Scan {
+        len: 0,
+        valid_len: 0,
+        skipped: 0,
+    };
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)?;
+        if read == 0 {
+            return Ok(scan);
+        }
+        scan.len += read as u64;
+        if line.last() != Some(&b'\n') {
+            // No newline: the last write never completed.
+            return Ok(scan);
+        }
+        match serde_json::from_slice::<LogRecord>(&line[..read - 1]) {
+            Ok(record) => {
+                each(record);
+                scan.skipped = 0;
+                scan.valid_len = scan.len;
+            }
+            Err(_) => scan.skipped += 1,
+        }
+    }
+}
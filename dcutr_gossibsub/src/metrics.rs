@@ -0,0 +1,297 @@
+use crate::bandwidth::Bandwidth;
+use crate::behaviour::BehaviourEvent;
+use crate::http;
+use crate::node::Event;
+use crate::paths::{ConnectionPaths, TransportPath};
+use crate::rtt::RttStats;
+use libp2p::metrics::Recorder;
+use libp2p::{
+    relay,
+    swarm::{ConnectionId, SwarmEvent},
+    PeerId,
+};
+use prometheus_client::{
+    encoding::text::encode,
+    metrics::{
+        counter::Counter,
+        family::Family,
+        gauge::Gauge,
+        histogram::{exponential_buckets, Histogram},
+    },
+    registry::Registry,
+};
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+type Labels = Vec<(String, String)>;
+
+/// The libp2p metrics of the swarm and its behaviours, and our own counters on top.
+pub struct Metrics {
+    libp2p: libp2p::metrics::Metrics,
+    published: Family<Labels, Counter>,
+    received: Family<Labels, Counter>,
+    publish_errors: Family<Labels, Counter>,
+    hole_punches: Family<Labels, Counter>,
+    hole_punch_failures: Family<Labels, Counter>,
+    time_to_direct: Histogram,
+    relay_client: Family<Labels, Counter>,
+    bandwidth: Family<Labels, Counter>,
+    rtt: Family<Labels, Gauge>,
+}
+
+impl Metrics {
+    pub fn new(registry: &mut Registry) -> Self {
+        let libp2p = libp2p::metrics::Metrics::new(registry);
+        let registry = registry.sub_registry_with_prefix("chat");
+        let published = Family::default();
+        registry.register(
+            "messages_published",
+            "Chat messages published, by topic",
+            published.clone(),
+        );
+        let received = Family::default();
+        registry.register(
+            "messages_received",
+            "Topic messages received and accepted, by topic",
+            received.clone(),
+        );
+        let publish_errors = Family::default();
+        registry.register(
+            "publish_errors",
+            "Chat messages that could not be published, by topic",
+            publish_errors.clone(),
+        );
+        let hole_punches = Family::default();
+        registry.register(
+            "hole_punches",
+            "Finished hole punch attempts, by outcome",
+            hole_punches.clone(),
+        );
+        let hole_punch_failures = Family::default();
+        registry.register(
+            "hole_punch_failures",
+            "Failed hole punch attempts, by error kind",
+            hole_punch_failures.clone(),
+        );
+        let time_to_direct = Histogram::new(exponential_buckets(0.05, 2.0, 10));
+        registry.register(
+            "hole_punch_time_to_direct_seconds",
+            "Time from the start of a hole punch to the direct connection",
+            time_to_direct.clone(),
+        );
+        let relay_client = Family::default();
+        registry.register(
+            "relay_client_events",
+            "Relay reservation and circuit events, by kind",
+            relay_client.clone(),
+        );
+        let bandwidth = Family::default();
+        registry.register(
+            "bandwidth_bytes",
+            "Bytes moved since startup, by peer, path and direction",
+            bandwidth.clone(),
+        );
+        let rtt = Family::default();
+        registry.register(
+            "rtt_milliseconds",
+            "Ping round trip times over the recent pings, by peer, path and statistic",
+            rtt.clone(),
+        );
+        Metrics {
+            libp2p,
+            published,
+            received,
+            publish_errors,
+            hole_punches,
+            hole_punch_failures,
+            time_to_direct,
+            relay_client,
+            bandwidth,
+            rtt,
+        }
+    }
+
+    /// Records a swarm or behaviour event libp2p has metrics for, by reference.
+    pub fn record<E>(&self, event: &E)
+    where
+        libp2p::metrics::Metrics: Recorder<E>,
+    {
+        self.libp2p.record(event);
+    }
+
+    /// Records a swarm event and the behaviour event it carries, as the event loop sees them.
+    pub fn on_event(&self, event: &Event) {
+        self.record(event);
+        match event {
+            SwarmEvent::Behaviour(BehaviourEvent::Ping(ping)) => self.record(&ping.event),
+            SwarmEvent::Behaviour(BehaviourEvent::Identify(event)) => self.record(event),
+            SwarmEvent::Behaviour(BehaviourEvent::Dcutr(event)) => self.record(event),
+            SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(event)) => self.record(event),
+            SwarmEvent::Behaviour(BehaviourEvent::Kademlia(event)) => self.record(event),
+            SwarmEvent::Behaviour(BehaviourEvent::RelayClient(event)) => {
+                self.on_relay_client(event)
+            }
+            _ => {}
+        }
+    }
+
+    pub fn on_published(&self, topic: &str) {
+        self.published.get_or_create(&label("topic", topic)).inc();
+    }
+
+    pub fn on_received(&self, topic: &str) {
+        self.received.get_or_create(&label("topic", topic)).inc();
+    }
+
+    pub fn on_publish_error(&self, topic: &str) {
+        self.publish_errors
+            .get_or_create(&label("topic", topic))
+            .inc();
+    }
+
+    pub fn on_hole_punch_succeeded(&self, time_to_direct: Duration) {
+        self.hole_punches
+            .get_or_create(&label("outcome", "success"))
+            .inc();
+        self.time_to_direct.observe(time_to_direct.as_secs_f64());
+    }
+
+    /// `kind` is the DCUtR error variant, as [`HolePunchStats`](crate::holepunch::HolePunchStats)
+    /// tells it.
+    pub fn on_hole_punch_failed(&self, kind: &str) {
+        self.hole_punches
+            .get_or_create(&label("outcome", "failure"))
+            .inc();
+        self.hole_punch_failures
+            .get_or_create(&label("kind", kind))
+            .inc();
+    }
+
+    /// libp2p only has metrics for the relay server, so the client side is counted here.
+    pub fn on_relay_client(&self, event: &relay::client::Event) {
+        use relay::client::Event;
+        let kind = match event {
+            Event::ReservationReqAccepted { .. } => "reservation_accepted",
+            Event::ReservationReqFailed { .. } => "reservation_failed",
+            Event::OutboundCircuitEstablished { .. } => "outbound_circuit_established",
+            Event::OutboundCircuitReqFailed { .. } => "outbound_circuit_failed",
+            Event::InboundCircuitEstablished { .. } => "inbound_circuit_established",
+            Event::InboundCircuitReqFailed { .. } => "inbound_circuit_failed",
+            Event::InboundCircuitReqDenied { .. } => "inbound_circuit_denied",
+            Event::InboundCircuitReqDenyFailed { .. } => "inbound_circuit_deny_failed",
+        };
+        self.relay_client.get_or_create(&label("kind", kind)).inc();
+    }
+
+    /// Catches the registry up with the bandwidth counters, they are kept by the muxers
+    /// themselves and only ever grow.
+    pub fn on_bandwidth(&self, bandwidth: &Bandwidth) {
+        let mut totals = HashMap::<Labels, u64>::new();
+        for (peer_id, path, usage) in bandwidth.peers() {
+            let path = match path {
+                TransportPath::Direct => "direct",
+                TransportPath::Relayed(_) => "relayed",
+            };
+            for (direction, bytes) in [("in", usage.inbound), ("out", usage.outbound)] {
+                let labels = vec![
+                    ("peer".to_string(), peer_id.to_string()),
+                    ("path".to_string(), path.to_string()),
+                    ("direction".to_string(), direction.to_string()),
+                ];
+                let total = totals.entry(labels).or_default();
+                *total = total.saturating_add(bytes);
+            }
+        }
+        for (labels, bytes) in totals {
+            let counter = self.bandwidth.get_or_create(&labels);
+            counter.inc_by(bytes.saturating_sub(counter.get()));
+        }
+    }
+
+    /// Copies the round trip times of the open connections into the registry.
+    pub fn on_rtt(&self, rtt: &RttStats, connection_paths: &ConnectionPaths) {
+        for (peer_id, connection, summary) in rtt.all() {
+            let path = connection_paths
+                .connections(&peer_id)
+                .into_iter()
+                .find(|(id, _)| *id == connection)
+                .map(|(_, path)| path);
+            let path = match path {
+                Some(path) => path,
+                None => continue,
+            };
+            for (stat, value) in [
+                ("current", summary.current),
+                ("min", summary.min),
+                ("avg", summary.avg),
+                ("p95", summary.p95),
+            ] {
+                self.rtt
+                    .get_or_create(&rtt_labels(&peer_id, path, stat))
+                    .set(i64::try_from(value.as_millis()).unwrap_or(i64::MAX));
+            }
+        }
+    }
+
+    /// Drops the round trip times of a connection that closed, before `connection_paths`
+    /// forgets it. Another open connection over the same path puts them back on the next tick.
+    pub fn on_connection_closed(
+        &self,
+        peer_id: &PeerId,
+        connection: ConnectionId,
+        connection_paths: &ConnectionPaths,
+    ) {
+        let path = connection_paths
+            .connections(peer_id)
+            .into_iter()
+            .find(|(id, _)| *id == connection)
+            .map(|(_, path)| path);
+        if let Some(path) = path {
+            for stat in RTT_STATS {
+                self.rtt.remove(&rtt_labels(peer_id, path, stat));
+            }
+        }
+    }
+}
+
+const RTT_STATS: [&str; 4] = ["current", "min", "avg", "p95"];
+
+fn rtt_labels(peer_id: &PeerId, path: TransportPath, stat: &str) -> Labels {
+    let path = match path {
+        TransportPath::Direct => "direct",
+        TransportPath::Relayed(_) => "relayed",
+    };
+    vec![
+        ("peer".to_string(), peer_id.to_string()),
+        ("path".to_string(), path.to_string()),
+        ("stat".to_string(), stat.to_string()),
+    ]
+}
+
+fn label(name: &str, value: &str) -> Labels {
+    vec![(name.to_string(), value.to_string())]
+}
+
+/// Serves the registry in the Prometheus text format on `GET /metrics`, returning the address
+/// it's bound to.
+pub fn serve(addr: SocketAddr, registry: Registry) -> Result<SocketAddr, Box<dyn Error>> {
+    let registry = Arc::new(registry);
+    http::serve(addr, "metrics endpoint", move |request| {
+        let response = match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/metrics") => {
+                let mut body = String::new();
+                encode(&mut body, &registry).expect("encoding to a string doesn't fail");
+                http::Response::text(
+                    200,
+                    "application/openmetrics-text; version=1.0.0; charset=utf-8",
+                    body,
+                )
+            }
+            _ => http::Response::not_found(),
+        };
+        async move { response }
+    })
+}
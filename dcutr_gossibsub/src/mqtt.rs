@@ -0,0 +1,270 @@
+use futures::channel::mpsc::UnboundedSender;
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// How many of our own publishes are remembered to recognize them coming back from the broker.
+const RECENT_PUBLISHES: usize = 64;
+
+/// A `--mqtt-sub` or `--mqtt-pub` mapping, `<from>=<to>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mapping {
+    pub from: String,
+    pub to: String,
+}
+
+impl FromStr for Mapping {
+    type Err = String;
+    fn from_str(mapping: &str) -> Result<Self, Self::Err> {
+        match mapping.split_once('=') {
+            Some((from, to)) if !from.is_empty() && !to.is_empty() => Ok(Mapping {
+                from: from.to_string(),
+                to: to.to_string(),
+            }),
+            _ => Err(format!(
+                "invalid mapping '{mapping}', expected <from-topic>=<to-topic>"
+            )),
+        }
+    }
+}
+
+/// Whether an MQTT topic filter, with `+` and `#` wildcards, matches `topic`.
+pub fn filter_matches(filter: &str, topic: &str) -> bool {
+    let mut levels = topic.split('/');
+    for part in filter.split('/') {
+        match (part, levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (part, Some(level)) if part == level => {}
+            _ => return false,
+        }
+    }
+    levels.next().is_none()
+}
+
+/// `host:port` of `--mqtt-broker`, which may start with `mqtt://` and defaults to port 1883.
+pub fn broker_addr(broker: &str) -> Result<(String, u16), String> {
+    let broker = broker.strip_prefix("mqtt://").unwrap_or(broker);
+    match broker.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() => port
+            .parse()
+            .map(|port| (host.to_string(), port))
+            .map_err(|e| format!("invalid --mqtt-broker port '{port}': {e}")),
+        Some(_) => Err(format!("--mqtt-broker {broker} has no host")),
+        None if broker.is_empty() => Err("--mqtt-broker is empty".to_string()),
+        None => Ok((broker.to_string(), 1883)),
+    }
+}
+
+/// A message of the broker, to publish to the gossipsub topic it is mapped to.
+pub struct MqttMessage {
+    pub topic: String,
+    pub text: String,
+}
+
+#[derive(Default)]
+struct Counters {
+    /// Forwarded from the broker to gossipsub.
+    received: AtomicU64,
+    /// Handed to the MQTT client for the broker.
+    queued: AtomicU64,
+    /// Dropped because the MQTT client's queue was full, the broker being down for a while.
+    dropped: AtomicU64,
+    /// Our own publishes coming back, not forwarded again.
+    echoes: AtomicU64,
+    connected: AtomicBool,
+}
+
+#[cfg(feature = "mqtt")]
+type Client = rumqttc::AsyncClient;
+/// Without the `mqtt` feature there's no client, [`Mqtt::start`] fails.
+#[cfg(not(feature = "mqtt"))]
+type Client = std::convert::Infallible;
+
+/// Forwards messages between an MQTT broker and gossipsub topics: those of the `--mqtt-sub`
+/// filters to their gossipsub topics, those of the `--mqtt-pub` gossipsub topics to the broker.
+/// Reconnecting and QoS are left to the MQTT client, which runs on a task of its own, so an
+/// unreachable broker only shows in the counters.
+///
+/// Messages entering gossipsub from the broker carry a marker naming it, so no client bridging
+/// the same broker sends them back. Our own publishes the broker delivers to our subscriptions
+/// are recognized and skipped.
+pub struct Mqtt {
+    broker: String,
+    marker: String,
+    publish: Vec<Mapping>,
+    client: Client,
+    #[cfg_attr(not(feature = "mqtt"), allow(dead_code))]
+    qos: u8,
+    counters: Arc<Counters>,
+    recent: Arc<Mutex<VecDeque<(String, Vec<u8>)>>>,
+}
+
+impl Mqtt {
+    #[cfg(feature = "mqtt")]
+    pub fn start(
+        broker: &str,
+        client_id: String,
+        subscribe: Vec<Mapping>,
+        publish: Vec<Mapping>,
+        qos: u8,
+        messages: UnboundedSender<MqttMessage>,
+    ) -> Result<Self, String> {
+        use rumqttc::{AsyncClient, Event, MqttOptions, Packet};
+        use std::time::Duration;
+        use tracing::{debug, info, warn};
+
+        let (host, port) = broker_addr(broker)?;
+        let level = rumqttc::qos(qos).map_err(|_| format!("invalid --mqtt-qos {qos}"))?;
+        let mut options = MqttOptions::new(client_id, host, port);
+        options.set_keep_alive(Duration::from_secs(30));
+        let (client, mut event_loop) = AsyncClient::new(options, 100);
+        let counters = Arc::new(Counters::default());
+        let recent = Arc::new(Mutex::new(VecDeque::new()));
+        let (subscriber, task_counters, task_recent) =
+            (client.clone(), counters.clone(), recent.clone());
+        let broker = broker.to_string();
+        let task_broker = broker.clone();
+        tokio::spawn(async move {
+            loop {
+                match event_loop.poll().await {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        info!("Connected to the MQTT broker {task_broker}");
+                        task_counters.connected.store(true, Ordering::Relaxed);
+                        // Subscriptions don't outlive a clean session, they are renewed each time.
+                        for mapping in &subscribe {
+                            if let Err(e) = subscriber.try_subscribe(&mapping.from, level) {
+                                warn!("Failed to subscribe to MQTT topic {}: {e}", mapping.from);
+                            }
+                        }
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        let echo = {
+                            let mut recent = task_recent.lock().expect("recent publishes lock");
+                            let position = recent.iter().position(|(topic, payload)| {
+                                *topic == publish.topic && payload[..] == publish.payload[..]
+                            });
+                            position.and_then(|position| recent.remove(position))
+                        };
+                        if echo.is_some() {
+                            task_counters.echoes.fetch_add(1, Ordering::Relaxed);
+                            continue;
+                        }
+                        let text = String::from_utf8_lossy(&publish.payload).into_owned();
+                        for mapping in subscribe
+                            .iter()
+                            .filter(|mapping| filter_matches(&mapping.from, &publish.topic))
+                        {
+                            task_counters.received.fetch_add(1, Ordering::Relaxed);
+                            let message = MqttMessage {
+                                topic: mapping.to.clone(),
+                                text: text.clone(),
+                            };
+                            if messages.unbounded_send(message).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        if task_counters.connected.swap(false, Ordering::Relaxed) {
+                            warn!("Lost the MQTT broker {task_broker}: {e}");
+                        } else {
+                            debug!("MQTT broker {task_broker} unreachable: {e}");
+                        }
+                        // The next poll reconnects.
+                        futures_timer::Delay::new(Duration::from_secs(5)).await;
+                    }
+                }
+            }
+        });
+        Ok(Mqtt {
+            marker: format!("mqtt:{broker}"),
+            broker,
+            publish,
+            client,
+            qos,
+            counters,
+            recent,
+        })
+    }
+
+    #[cfg(not(feature = "mqtt"))]
+    pub fn start(
+        _broker: &str,
+        _client_id: String,
+        _subscribe: Vec<Mapping>,
+        _publish: Vec<Mapping>,
+        _qos: u8,
+        _messages: UnboundedSender<MqttMessage>,
+    ) -> Result<Self, String> {
+        Err("--mqtt-broker needs a build with the mqtt feature".to_string())
+    }
+
+    /// Put into the envelopes of the messages entering gossipsub from the broker.
+    pub fn marker(&self) -> &str {
+        &self.marker
+    }
+
+    /// Whether messages of the gossipsub `topic` go to the broker.
+    pub fn wants(&self, topic: &str) -> bool {
+        self.publish.iter().any(|mapping| mapping.from == topic)
+    }
+
+    /// Forwards a message of the gossipsub `topic` to the MQTT topics it is mapped to, unless
+    /// `marker` says it came from this broker.
+    pub fn on_message(&self, topic: &str, body: &str, marker: Option<&str>) {
+        if marker == Some(self.marker.as_str()) {
+            return;
+        }
+        for mapping in self.publish.iter().filter(|mapping| mapping.from == topic) {
+            {
+                let mut recent = self.recent.lock().expect("recent publishes lock");
+                if recent.len() >= RECENT_PUBLISHES {
+                    recent.pop_front();
+                }
+                recent.push_back((mapping.to.clone(), body.as_bytes().to_vec()));
+            }
+            let counter = match self.publish_to(&mapping.to, body) {
+                true => &self.counters.queued,
+                false => &self.counters.dropped,
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[cfg(feature = "mqtt")]
+    fn publish_to(&self, topic: &str, body: &str) -> bool {
+        let qos = rumqttc::qos(self.qos).expect("checked at start");
+        self.client
+            .try_publish(topic, qos, false, body.as_bytes().to_vec())
+            .is_ok()
+    }
+
+    #[cfg(not(feature = "mqtt"))]
+    fn publish_to(&self, _topic: &str, _body: &str) -> bool {
+        match self.client {}
+    }
+}
+
+/// `mqtt <broker> (connected): 10 in, 4 out, 0 dropped, 4 echoes`, for `/stats`.
+impl fmt::Display for Mqtt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counters = &self.counters;
+        let state = match counters.connected.load(Ordering::Relaxed) {
+            true => "connected",
+            false => "disconnected",
+        };
+        write!(
+            f,
+            "mqtt {} ({state}): {} in, {} out, {} dropped, {} echoes",
+            self.broker,
+            counters.received.load(Ordering::Relaxed),
+            counters.queued.load(Ordering::Relaxed),
+            counters.dropped.load(Ordering::Relaxed),
+            counters.echoes.load(Ordering::Relaxed),
+        )
+    }
+}
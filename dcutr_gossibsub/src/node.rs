@@ -0,0 +1,86 @@
+use crate::behaviour::{Behaviour, BehaviourEvent};
+use crate::bootstrap;
+use futures::stream::{FusedStream, Stream, StreamExt};
+use libp2p::{
+    core::{muxing::StreamMuxerBox, transport::Boxed, Multiaddr},
+    gossipsub::{MessageId, PublishError, TopicHash},
+    swarm::{DialError, Swarm, SwarmBuilder, SwarmEvent, THandlerErr},
+    PeerId,
+};
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// What a [`Node`] yields.
+pub type Event = SwarmEvent<BehaviourEvent, THandlerErr<Behaviour>>;
+
+/// A client on the runtime the cargo features pick. Its events come out of it as a [`Stream`],
+/// anything its methods don't cover is reached through the [`Swarm`] it dereferences to.
+pub struct Node {
+    swarm: Swarm<Behaviour>,
+}
+
+impl Node {
+    /// `transport` comes from [`crate::transport::build`].
+    pub fn new(
+        transport: Boxed<(PeerId, StreamMuxerBox)>,
+        behaviour: Behaviour,
+        local_peer_id: PeerId,
+    ) -> Self {
+        #[cfg(feature = "tokio")]
+        let swarm = SwarmBuilder::with_tokio_executor(transport, behaviour, local_peer_id);
+        #[cfg(not(feature = "tokio"))]
+        let swarm = SwarmBuilder::with_async_std_executor(transport, behaviour, local_peer_id);
+        Node {
+            swarm: swarm.build(),
+        }
+    }
+
+    /// Publishes `data` to the gossipsub `topic`.
+    pub fn publish(
+        &mut self,
+        topic: impl Into<TopicHash>,
+        data: impl Into<Vec<u8>>,
+    ) -> Result<MessageId, PublishError> {
+        self.swarm.behaviour_mut().gossipsub.publish(topic, data)
+    }
+
+    /// Dials `peer_id` through a circuit of the relay at `relay_address`, DCUtR upgrades it to a
+    /// direct connection if it can.
+    pub fn dial_peer(
+        &mut self,
+        peer_id: PeerId,
+        relay_address: &Multiaddr,
+    ) -> Result<(), DialError> {
+        self.swarm
+            .dial(bootstrap::relayed_addr(relay_address, peer_id))
+    }
+}
+
+impl Deref for Node {
+    type Target = Swarm<Behaviour>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.swarm
+    }
+}
+
+impl DerefMut for Node {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.swarm
+    }
+}
+
+impl Stream for Node {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.swarm.poll_next_unpin(cx)
+    }
+}
+
+impl FusedStream for Node {
+    fn is_terminated(&self) -> bool {
+        self.swarm.is_terminated()
+    }
+}
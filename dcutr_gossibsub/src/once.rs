@@ -0,0 +1,115 @@
+use crate::console::say;
+use libp2p::{core::multiaddr::Multiaddr, PeerId};
+use std::time::{Duration, Instant};
+
+pub const EXIT_SUCCESS: u8 = 0;
+pub const EXIT_HOLEPUNCH_FAILED: u8 = 2;
+pub const EXIT_RELAY_FAILED: u8 = 3;
+
+/// Final state of a `--once` run.
+pub enum Outcome {
+    Success(Multiaddr),
+    HolePunchFailed(String),
+    RelayFailed(String),
+}
+
+/// Drives a single hole punch attempt to `target` and prints a result line once it either
+/// succeeded or failed for good, leaving the exit code for the event loop to return.
+pub struct OneShot {
+    target: PeerId,
+    started: Instant,
+    deadline: Option<(Instant, Duration)>,
+    upgraded: bool,
+    direct_addr: Option<Multiaddr>,
+    dialed_circuit: bool,
+    exit_code: Option<u8>,
+}
+
+impl OneShot {
+    pub fn new(target: PeerId) -> Self {
+        OneShot {
+            target,
+            started: Instant::now(),
+            deadline: None,
+            upgraded: false,
+            direct_addr: None,
+            dialed_circuit: false,
+            exit_code: None,
+        }
+    }
+
+    /// Ends the run after `timeout`, counting it as a hole punch failure once the circuit has
+    /// been dialed and as a relay failure before that. Checked by [`OneShot::poll`].
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.deadline = Some((self.started + timeout, timeout));
+    }
+
+    /// Checks the timeout, from the event loop tick.
+    pub fn poll(&mut self, now: Instant) {
+        match self.deadline {
+            Some((at, timeout)) if now >= at => {
+                let reason = format!("timed out after {timeout:?}");
+                self.fail(if self.dialed_circuit {
+                    Outcome::HolePunchFailed(reason)
+                } else {
+                    Outcome::RelayFailed(reason)
+                });
+            }
+            _ => {}
+        }
+    }
+
+    pub fn on_circuit_dialed(&mut self) {
+        self.dialed_circuit = true;
+    }
+
+    pub fn on_upgrade_succeeded(&mut self, peer_id: PeerId) {
+        if peer_id == self.target {
+            self.upgraded = true;
+            self.finish_if_done();
+        }
+    }
+
+    pub fn on_direct_connection(&mut self, peer_id: PeerId, addr: &Multiaddr) {
+        if peer_id == self.target {
+            self.direct_addr = Some(addr.clone());
+            self.finish_if_done();
+        }
+    }
+
+    pub fn fail(&mut self, outcome: Outcome) {
+        self.finish(outcome)
+    }
+
+    /// The code to exit with once the run is over. Only the first outcome counts.
+    pub fn exit_code(&self) -> Option<u8> {
+        self.exit_code
+    }
+
+    fn finish_if_done(&mut self) {
+        if let (true, Some(addr)) = (self.upgraded, &self.direct_addr) {
+            self.finish(Outcome::Success(addr.clone()))
+        }
+    }
+
+    fn finish(&mut self, outcome: Outcome) {
+        if self.exit_code.is_some() {
+            return;
+        }
+        let elapsed_ms = self.started.elapsed().as_millis();
+        self.exit_code = Some(match &outcome {
+            Outcome::Success(addr) => {
+                say!("result=success direct_addr={addr} elapsed_ms={elapsed_ms}");
+                EXIT_SUCCESS
+            }
+            Outcome::HolePunchFailed(reason) => {
+                say!("result=holepunch_failed reason={reason:?} elapsed_ms={elapsed_ms}");
+                EXIT_HOLEPUNCH_FAILED
+            }
+            Outcome::RelayFailed(reason) => {
+                say!("result=relay_failed reason={reason:?} elapsed_ms={elapsed_ms}");
+                EXIT_RELAY_FAILED
+            }
+        });
+    }
+}
@@ -0,0 +1,206 @@
+use crate::throttle::TokenBucket;
+use crate::validation::Rate;
+use libp2p::gossipsub::{self, PublishError, TopicHash};
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Messages that could not be published yet because no peer was subscribed to their topic, or
+/// because we publish faster than the `--publish-rate` limit. They are retried in order once a
+/// peer subscribes, a later publish goes through or tokens refill.
+pub struct Outbox {
+    capacity: usize,
+    queue: VecDeque<Entry>,
+    limit: Option<TokenBucket>,
+    /// Whether messages are held back for the limit, to tell when that starts.
+    throttling: bool,
+    throttled: u64,
+    dropped: u64,
+}
+
+struct Entry {
+    topic: TopicHash,
+    data: Vec<u8>,
+    /// Queued for a lack of peers rather than the limit, which is worth telling once it goes out.
+    waited_for_peers: bool,
+}
+
+/// Result of handing a message to the outbox.
+pub enum Sent {
+    Published,
+    /// Queued until a peer subscribes, with the oldest message if one had to make room for it.
+    Queued(Option<(TopicHash, Vec<u8>)>),
+    /// Held back for the limit, `started` for the first message since nothing was. With the
+    /// oldest message if one had to make room for it.
+    Throttled {
+        started: bool,
+        dropped: Option<(TopicHash, Vec<u8>)>,
+    },
+}
+
+impl Outbox {
+    pub fn new(capacity: usize) -> Self {
+        Outbox {
+            capacity,
+            queue: VecDeque::new(),
+            limit: None,
+            throttling: false,
+            throttled: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Paces publishing by `limit`, if given.
+    pub fn with_limit(mut self, limit: Option<TokenBucket>) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    pub fn rate(&self) -> Option<Rate> {
+        self.limit.as_ref().map(TokenBucket::rate)
+    }
+
+    /// Publishes `data`, or queues it when nobody is subscribed to `topic` yet or the limit is
+    /// reached. A message is queued as well while older messages for the same topic are still
+    /// waiting, so that they keep their order.
+    pub fn publish(
+        &mut self,
+        gossipsub: &mut gossipsub::Behaviour,
+        topic: TopicHash,
+        data: Vec<u8>,
+        now: Instant,
+    ) -> Result<Sent, PublishError> {
+        let mut throttled = self.throttling;
+        if !self.queue.iter().any(|entry| entry.topic == topic) {
+            throttled = !self
+                .limit
+                .as_mut()
+                .map_or(true, |limit| limit.try_take(now));
+            if !throttled {
+                match gossipsub.publish(topic.clone(), data.clone()) {
+                    Ok(_) => return Ok(Sent::Published),
+                    Err(e) => {
+                        // Nothing went out, so it doesn't count against the limit.
+                        if let Some(limit) = self.limit.as_mut() {
+                            limit.give_back();
+                        }
+                        if !matches!(e, PublishError::InsufficientPeers) {
+                            return Err(e);
+                        }
+                    }
+                }
+            }
+        }
+        let dropped = self.enqueue(Entry {
+            topic,
+            data,
+            waited_for_peers: !throttled,
+        });
+        if !throttled {
+            return Ok(Sent::Queued(dropped));
+        }
+        self.throttled += 1;
+        let started = !self.throttling;
+        self.throttling = true;
+        Ok(Sent::Throttled { started, dropped })
+    }
+
+    /// Tries to publish the queued messages, oldest first, as far as the limit allows. Returns
+    /// the ones that went out after waiting for peers and the ones that failed for another
+    /// reason than a lack of peers and were given up on.
+    #[allow(clippy::type_complexity)]
+    pub fn flush(
+        &mut self,
+        gossipsub: &mut gossipsub::Behaviour,
+        now: Instant,
+    ) -> (Vec<(TopicHash, Vec<u8>)>, Vec<(TopicHash, PublishError)>) {
+        let mut sent = Vec::new();
+        let mut failed = Vec::new();
+        let mut waiting = HashSet::new();
+        let mut held_back = HashSet::new();
+        let mut kept = VecDeque::new();
+        for mut entry in self.queue.drain(..) {
+            if waiting.contains(&entry.topic) {
+                // Behind a message waiting for peers, it waits for them as well.
+                entry.waited_for_peers = true;
+                kept.push_back(entry);
+                continue;
+            }
+            if held_back.contains(&entry.topic)
+                || !self
+                    .limit
+                    .as_mut()
+                    .map_or(true, |limit| limit.try_take(now))
+            {
+                held_back.insert(entry.topic.clone());
+                kept.push_back(entry);
+                continue;
+            }
+            match gossipsub.publish(entry.topic.clone(), entry.data.clone()) {
+                Ok(_) if entry.waited_for_peers => sent.push((entry.topic, entry.data)),
+                Ok(_) => {}
+                Err(e) => {
+                    if let Some(limit) = self.limit.as_mut() {
+                        limit.give_back();
+                    }
+                    match e {
+                        PublishError::InsufficientPeers => {
+                            waiting.insert(entry.topic.clone());
+                            entry.waited_for_peers = true;
+                            kept.push_back(entry);
+                        }
+                        e => failed.push((entry.topic, e)),
+                    }
+                }
+            }
+        }
+        self.queue = kept;
+        if self.queue.iter().all(|entry| entry.waited_for_peers) {
+            self.throttling = false;
+        }
+        (sent, failed)
+    }
+
+    /// How long until the limit lets the next held back message go, if one is.
+    pub fn ready_in(&self, now: Instant) -> Option<Duration> {
+        match &self.limit {
+            Some(limit) if self.throttling => Some(limit.ready_in(now)),
+            _ => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Queues `entry`, returning the oldest message if it had to make room for it.
+    fn enqueue(&mut self, entry: Entry) -> Option<(TopicHash, Vec<u8>)> {
+        let dropped = if self.capacity == 0 {
+            Some(entry)
+        } else {
+            let dropped = match self.queue.len() >= self.capacity {
+                true => self.queue.pop_front(),
+                false => None,
+            };
+            self.queue.push_back(entry);
+            dropped
+        };
+        dropped.map(|entry| {
+            self.dropped += 1;
+            (entry.topic, entry.data)
+        })
+    }
+}
+
+/// `outbox: 3 queued, 120 throttled, 2 dropped`, for `/stats`.
+impl fmt::Display for Outbox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "outbox: {} queued, {} throttled, {} dropped",
+            self.queue.len(),
+            self.throttled,
+            self.dropped
+        )
+    }
+}
@@ -0,0 +1,112 @@
+use crate::envelope::{self, Envelope};
+use crate::paths::TransportPath;
+use crate::topic_keys::TopicKey;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use libp2p::{gossipsub::TopicHash, PeerId};
+use serde::Serialize;
+use std::str::FromStr;
+
+/// How received messages are printed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Output {
+    Text,
+    /// One [`JsonMessage`] per line on stdout, everything else on stderr.
+    Json,
+}
+
+impl FromStr for Output {
+    type Err = String;
+    fn from_str(output: &str) -> Result<Self, Self::Err> {
+        match output {
+            "text" => Ok(Output::Text),
+            "json" => Ok(Output::Json),
+            _ => Err("Expected either 'text' or 'json'".to_string()),
+        }
+    }
+}
+
+/// A received topic message as printed by `--output json`, on a single line:
+///
+/// ```text
+/// {"topic":"chat","message_id":"3132...","source":"12D3KooW...","nick":"alice",
+///  "timestamp":1700000000000,"path":"direct","body":"hello","body_encoding":"utf8"}
+/// ```
+///
+/// - `topic`: the topic name, or its hash for topics we don't know the name of.
+/// - `message_id`: the gossipsub message id.
+/// - `source`: peer id of the author, `null` for anonymous messages.
+/// - `nick`: the author's nickname, left out if the message has none.
+/// - `timestamp`: unix time in milliseconds the author sent the message at, or we received it at
+///   for messages without an envelope.
+/// - `path`: `"direct"` or `"relayed"`, how the peer that forwarded the message is connected,
+///   `null` if that's unknown.
+/// - `body`: the text if `body_encoding` is `"utf8"`, base64 of the bytes if it is `"base64"`,
+///   and base64 of the ciphertext if it is `"encrypted"`, for topics we have no key for.
+///
+/// Fields may be added, but existing ones keep their name and meaning.
+#[derive(Debug, Serialize)]
+pub struct JsonMessage {
+    pub topic: String,
+    pub message_id: String,
+    pub source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nick: Option<String>,
+    pub timestamp: u64,
+    pub path: Option<&'static str>,
+    pub body: String,
+    pub body_encoding: &'static str,
+}
+
+impl JsonMessage {
+    pub fn new(
+        topic_name: String,
+        message_id: String,
+        source: Option<PeerId>,
+        path: Option<TransportPath>,
+    ) -> Self {
+        JsonMessage {
+            topic: topic_name,
+            message_id,
+            source: source.map(|peer_id| peer_id.to_string()),
+            nick: None,
+            timestamp: envelope::unix_millis(),
+            path: path.map(|path| match path {
+                TransportPath::Direct => "direct",
+                TransportPath::Relayed(_) => "relayed",
+            }),
+            body: String::new(),
+            body_encoding: "utf8",
+        }
+    }
+
+    /// Fills in what the payload says, decrypting and decompressing its body.
+    pub fn with_payload(
+        mut self,
+        data: &[u8],
+        max_decompressed: usize,
+        topic: &TopicHash,
+        keys: &[TopicKey],
+    ) -> Result<Self, String> {
+        match Envelope::decode(data) {
+            Some(envelope) => {
+                self.nick = envelope.from_nick.clone();
+                self.timestamp = envelope.sent_at;
+                match envelope.clone().decrypt(topic, keys) {
+                    Ok(envelope) => self.body = envelope.decompress(max_decompressed)?.body,
+                    Err(_) => {
+                        self.body = envelope.body;
+                        self.body_encoding = "encrypted";
+                    }
+                }
+            }
+            None => match std::str::from_utf8(data) {
+                Ok(text) => self.body = text.to_string(),
+                Err(_) => {
+                    self.body = STANDARD.encode(data);
+                    self.body_encoding = "base64";
+                }
+            },
+        }
+        Ok(self)
+    }
+}
@@ -0,0 +1,96 @@
+use libp2p::{
+    core::{multiaddr::Protocol, ConnectedPoint},
+    swarm::ConnectionId,
+    PeerId,
+};
+use std::collections::HashMap;
+use std::fmt;
+
+/// How traffic to a peer travels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TransportPath {
+    Direct,
+    /// Through a relay circuit, with the relay's peer id if the address names it.
+    Relayed(Option<PeerId>),
+}
+
+impl TransportPath {
+    /// The path a connection takes. Inbound circuits only show the relay in the local address.
+    pub fn of(endpoint: &ConnectedPoint) -> Self {
+        let addr = match endpoint {
+            ConnectedPoint::Dialer { address, .. } => address,
+            ConnectedPoint::Listener { local_addr, .. } => local_addr,
+        };
+        let mut relay = None;
+        for protocol in addr.iter() {
+            match protocol {
+                Protocol::P2p(hash) => relay = PeerId::from_multihash(hash).ok(),
+                Protocol::P2pCircuit => return TransportPath::Relayed(relay),
+                _ => {}
+            }
+        }
+        TransportPath::Direct
+    }
+}
+
+impl fmt::Display for TransportPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportPath::Direct => write!(f, "[direct]"),
+            TransportPath::Relayed(Some(relay)) => write!(f, "[relayed via {relay}]"),
+            TransportPath::Relayed(None) => write!(f, "[relayed]"),
+        }
+    }
+}
+
+/// The open connections to each peer and the path each of them takes.
+#[derive(Default)]
+pub struct ConnectionPaths {
+    peers: HashMap<PeerId, HashMap<ConnectionId, TransportPath>>,
+}
+
+impl ConnectionPaths {
+    pub fn on_established(&mut self, peer_id: PeerId, id: ConnectionId, endpoint: &ConnectedPoint) {
+        self.peers
+            .entry(peer_id)
+            .or_default()
+            .insert(id, TransportPath::of(endpoint));
+    }
+
+    pub fn on_closed(&mut self, peer_id: PeerId, id: ConnectionId) {
+        if let Some(connections) = self.peers.get_mut(&peer_id) {
+            connections.remove(&id);
+            if connections.is_empty() {
+                self.peers.remove(&peer_id);
+            }
+        }
+    }
+
+    /// The open connections to `peer_id` with their paths.
+    pub fn connections(&self, peer_id: &PeerId) -> Vec<(ConnectionId, TransportPath)> {
+        self.peers
+            .get(peer_id)
+            .map(|connections| connections.iter().map(|(id, p)| (*id, *p)).collect())
+            .unwrap_or_default()
+    }
+
+    /// Whether a relayed connection to `peer_id` is open, next to a direct one or not.
+    pub fn has_relayed(&self, peer_id: &PeerId) -> bool {
+        self.peers.get(peer_id).map_or(false, |connections| {
+            connections
+                .values()
+                .any(|p| matches!(p, TransportPath::Relayed(_)))
+        })
+    }
+
+    /// The path traffic to `peer_id` currently takes, preferring a direct connection when there
+    /// are several.
+    pub fn path(&self, peer_id: &PeerId) -> Option<TransportPath> {
+        let connections = self.peers.get(peer_id)?;
+        connections
+            .values()
+            .find(|p| **p == TransportPath::Direct)
+            .or_else(|| connections.values().next())
+            .copied()
+    }
+}
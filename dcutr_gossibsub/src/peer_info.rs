@@ -0,0 +1,86 @@
+use libp2p::{identify, PeerId};
+use std::collections::HashMap;
+
+/// Protocol name announced by peers that can upgrade a relayed connection to a direct one.
+pub const DCUTR_PROTOCOL: &str = "/libp2p/dcutr";
+/// Prefix of the gossipsub protocol ids, followed by the version.
+pub const GOSSIPSUB_PROTOCOL_PREFIX: &str = "/meshsub/";
+
+/// What each connected peer told us about itself over identify. An entry is replaced by every
+/// identify response or push and dropped when the last connection to the peer closes.
+#[derive(Default)]
+pub struct PeerInfos {
+    peers: HashMap<PeerId, identify::Info>,
+}
+
+impl PeerInfos {
+    pub fn on_received(&mut self, peer_id: PeerId, info: identify::Info) {
+        self.peers.insert(peer_id, info);
+    }
+
+    pub fn on_disconnected(&mut self, peer_id: &PeerId) {
+        self.peers.remove(peer_id);
+    }
+
+    pub fn get(&self, peer_id: &PeerId) -> Option<&identify::Info> {
+        self.peers.get(peer_id)
+    }
+
+    /// The agent version and what's missing for the session, for `/peers`.
+    pub fn summary(&self, peer_id: &PeerId) -> Option<String> {
+        let info = self.peers.get(peer_id)?;
+        let mut summary = format!("{}, {} protocols", info.agent_version, info.protocols.len());
+        if !speaks_dcutr(info) {
+            summary.push_str(", no dcutr");
+        }
+        if !speaks_gossipsub(info) {
+            summary.push_str(", no gossipsub");
+        }
+        Some(summary)
+    }
+
+    /// Everything known about `peer_id`, for `/info`.
+    pub fn lines(&self, peer_id: &PeerId) -> Vec<String> {
+        let info = match self.peers.get(peer_id) {
+            Some(info) => info,
+            None => return Vec::new(),
+        };
+        let yes_no = |yes| match yes {
+            true => "yes",
+            false => "NO",
+        };
+        let mut lines = vec![
+            format!("agent version: {}", info.agent_version),
+            format!("protocol version: {}", info.protocol_version),
+            format!(
+                "dcutr: {}, gossipsub: {}",
+                yes_no(speaks_dcutr(info)),
+                yes_no(speaks_gossipsub(info))
+            ),
+            format!("observes us at: {}", info.observed_addr),
+            "listen addrs:".to_string(),
+        ];
+        lines.extend(info.listen_addrs.iter().map(|addr| format!("  {addr}")));
+        lines.push("protocols:".to_string());
+        lines.extend(info.protocols.iter().map(|protocol| {
+            if protocol == DCUTR_PROTOCOL {
+                format!("  {protocol} [dcutr]")
+            } else if protocol.starts_with(GOSSIPSUB_PROTOCOL_PREFIX) {
+                format!("  {protocol} [gossipsub]")
+            } else {
+                format!("  {protocol}")
+            }
+        }));
+        lines
+    }
+}
+
+fn speaks_dcutr(info: &identify::Info) -> bool {
+    info.protocols.iter().any(|p| p == DCUTR_PROTOCOL)
+}
+
+fn speaks_gossipsub(info: &identify::Info) -> bool {
+    info.protocols
+        .iter()
+        .any(|p| p.starts_with(GOSSIPSUB_PROTOCOL_PREFIX))
+}
@@ -0,0 +1,169 @@
+use crate::validation::Rejection;
+use futures::{
+    future::{self, BoxFuture, Either},
+    stream::{FusedStream, FuturesUnordered},
+    FutureExt, Stream, StreamExt,
+};
+use libp2p::{
+    gossipsub::{Message, MessageId},
+    PeerId,
+};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// A received message waiting for its verdict.
+pub struct Candidate {
+    pub id: MessageId,
+    /// The peer that forwarded the message to us, not necessarily its author.
+    pub propagation_source: PeerId,
+    pub message: Message,
+}
+
+/// A candidate with its verdict, to report to gossipsub.
+pub type Validated = (Candidate, Result<(), Rejection>);
+
+/// What a validator decided, right away or once its future completes.
+pub enum Check {
+    Done(Result<(), Rejection>),
+    Later(BoxFuture<'static, Result<(), Rejection>>),
+}
+
+impl From<Result<(), Rejection>> for Check {
+    fn from(verdict: Result<(), Rejection>) -> Self {
+        Check::Done(verdict)
+    }
+}
+
+/// One step of the [`Pipeline`]. A rejection's kind is what it is counted under in the stats.
+pub trait Validator: Send {
+    fn check(&mut self, candidate: &Candidate) -> Check;
+
+    /// Forgets what was kept about a peer that disconnected.
+    fn on_disconnected(&mut self, _peer_id: &PeerId) {}
+
+    /// What the validator has to tell the user since the last call, e.g. which peers it limits.
+    fn notices(&mut self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+struct FnValidator<F>(F);
+
+impl<F: FnMut(&Candidate) -> Check + Send> Validator for FnValidator<F> {
+    fn check(&mut self, candidate: &Candidate) -> Check {
+        (self.0)(candidate)
+    }
+}
+
+/// A validator out of a closure.
+pub fn from_fn(check: impl FnMut(&Candidate) -> Check + Send + 'static) -> impl Validator {
+    FnValidator(check)
+}
+
+/// The checks a received message has to pass before it is shown and propagated, run in the order
+/// they were pushed until one rejects it. Checks that can't decide right away return a future,
+/// which the pipeline polls as a stream next to the swarm, giving up on it after `timeout` and
+/// ignoring the message then. The checks after it run once it accepted.
+pub struct Pipeline {
+    validators: Vec<Box<dyn Validator>>,
+    timeout: Duration,
+    /// Candidates waiting for a future, with the index of the validator to go on with.
+    pending: FuturesUnordered<BoxFuture<'static, (Candidate, usize, Result<(), Rejection>)>>,
+}
+
+impl Pipeline {
+    pub fn new(timeout: Duration) -> Self {
+        Pipeline {
+            validators: Vec::new(),
+            timeout,
+            pending: FuturesUnordered::new(),
+        }
+    }
+
+    pub fn push(&mut self, validator: impl Validator + 'static) {
+        self.validators.push(Box::new(validator));
+    }
+
+    /// Runs the checks on `candidate`, returning the verdict unless a check has to wait. The
+    /// verdict then comes out of the stream.
+    pub fn validate(&mut self, candidate: Candidate) -> Option<Validated> {
+        self.run(candidate, 0)
+    }
+
+    pub fn on_disconnected(&mut self, peer_id: &PeerId) {
+        for validator in &mut self.validators {
+            validator.on_disconnected(peer_id);
+        }
+    }
+
+    /// The notices of all validators since the last call.
+    pub fn notices(&mut self) -> Vec<String> {
+        self.validators
+            .iter_mut()
+            .flat_map(|validator| validator.notices())
+            .collect()
+    }
+
+    /// How many candidates wait for a check.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn run(&mut self, candidate: Candidate, from: usize) -> Option<Validated> {
+        for index in from..self.validators.len() {
+            match self.validators[index].check(&candidate) {
+                Check::Done(Ok(())) => {}
+                Check::Done(Err(rejection)) => return Some((candidate, Err(rejection))),
+                Check::Later(check) => {
+                    let timeout = self.timeout;
+                    let timer = futures_timer::Delay::new(timeout);
+                    self.pending.push(
+                        future::select(check, timer)
+                            .map(move |outcome| {
+                                let verdict = match outcome {
+                                    Either::Left((verdict, _)) => verdict,
+                                    Either::Right(_) => Err(Rejection {
+                                        kind: "timeout",
+                                        reason: format!("not validated within {timeout:?}"),
+                                        penalize: false,
+                                    }),
+                                };
+                                (candidate, index + 1, verdict)
+                            })
+                            .boxed(),
+                    );
+                    return None;
+                }
+            }
+        }
+        Some((candidate, Ok(())))
+    }
+}
+
+impl Stream for Pipeline {
+    type Item = Validated;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Validated>> {
+        loop {
+            match self.pending.poll_next_unpin(cx) {
+                Poll::Ready(Some((candidate, _, Err(rejection)))) => {
+                    return Poll::Ready(Some((candidate, Err(rejection))))
+                }
+                Poll::Ready(Some((candidate, next, Ok(())))) => {
+                    if let Some(validated) = self.run(candidate, next) {
+                        return Poll::Ready(Some(validated));
+                    }
+                }
+                // More candidates may come, the event loop polls again after the next one.
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl FusedStream for Pipeline {
+    fn is_terminated(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,113 @@
+use crate::envelope::Envelope;
+use crate::paths::ConnectionPaths;
+use libp2p::PeerId;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Presence bodies.
+pub const JOINED: &str = "joined";
+pub const HERE: &str = "here";
+pub const LEAVING: &str = "leaving";
+
+/// Members are dropped after missing this many refreshes.
+const MISSED_REFRESHES: u32 = 3;
+
+struct Member {
+    nick: String,
+    last_seen: Instant,
+}
+
+/// Who announced being in our topics recently. Peers announce themselves when they join,
+/// every `interval` after that and when they leave.
+pub struct Roster {
+    interval: Duration,
+    members: HashMap<String, Member>,
+    next_announce: Instant,
+}
+
+impl Roster {
+    pub fn new(interval: Duration) -> Self {
+        Roster {
+            interval,
+            members: HashMap::new(),
+            next_announce: Instant::now(),
+        }
+    }
+
+    /// Whether our own periodic announcement is due.
+    pub fn announce_due(&mut self, now: Instant) -> bool {
+        if now < self.next_announce {
+            return false;
+        }
+        self.next_announce = now + self.interval;
+        true
+    }
+
+    /// Records a presence envelope and returns a notice for the user if someone joined or left.
+    /// Announcements of a member arriving faster than a quarter of the interval, e.g. one per
+    /// subscribed topic, are ignored.
+    pub fn on_presence(&mut self, envelope: &Envelope, now: Instant) -> Option<String> {
+        let sender = envelope.sender().to_string();
+        if envelope.body == LEAVING {
+            return self
+                .members
+                .remove(&envelope.origin)
+                .map(|member| format!("{} left", member.nick));
+        }
+        match self.members.get_mut(&envelope.origin) {
+            Some(member) => {
+                if now.duration_since(member.last_seen) >= self.interval / 4 {
+                    member.nick = sender;
+                    member.last_seen = now;
+                }
+                None
+            }
+            None => {
+                self.members.insert(
+                    envelope.origin.clone(),
+                    Member {
+                        nick: sender.clone(),
+                        last_seen: now,
+                    },
+                );
+                Some(format!("{sender} is here"))
+            }
+        }
+    }
+
+    /// Drops members that stopped announcing themselves and returns their nicks.
+    pub fn expire(&mut self, now: Instant) -> Vec<String> {
+        let timeout = self.interval * MISSED_REFRESHES;
+        let mut expired = Vec::new();
+        self.members.retain(|_, member| {
+            if now.duration_since(member.last_seen) < timeout {
+                return true;
+            }
+            expired.push(member.nick.clone());
+            false
+        });
+        expired
+    }
+
+    /// One line per member for `/who`.
+    pub fn lines(&self, paths: &ConnectionPaths, now: Instant) -> Vec<String> {
+        let mut members = self.members.iter().collect::<Vec<_>>();
+        members.sort_by(|a, b| a.1.nick.cmp(&b.1.nick));
+        members
+            .into_iter()
+            .map(|(origin, member)| {
+                let path = origin
+                    .parse::<PeerId>()
+                    .ok()
+                    .and_then(|peer_id| paths.path(&peer_id))
+                    .map(|path| path.to_string())
+                    .unwrap_or_else(|| "[via mesh]".to_string());
+                format!(
+                    "{} {origin} {path} seen {}s ago",
+                    member.nick,
+                    now.duration_since(member.last_seen).as_secs()
+                )
+            })
+            .collect()
+    }
+}
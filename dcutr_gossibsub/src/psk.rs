@@ -0,0 +1,80 @@
+use crate::transport::UpgradeFailure;
+use libp2p::{
+    core::transport::TransportError,
+    pnet::PreSharedKey,
+    swarm::{DialError, ListenError},
+};
+use rand::RngCore;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Loads a pre-shared key in the standard `swarm.key` format.
+pub fn load(path: &Path) -> Result<PreSharedKey, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("failed to read pre-shared key {}: {e}", path.display()))?;
+    let psk = contents
+        .parse::<PreSharedKey>()
+        .map_err(|e| format!("invalid pre-shared key in {}: {e}", path.display()))?;
+    Ok(psk)
+}
+
+/// Writes a new random key to `path` in the `swarm.key` format, refusing to replace an
+/// existing one. On unix only the owner may read it.
+pub fn generate(path: &Path) -> Result<PreSharedKey, Box<dyn Error>> {
+    if path.exists() {
+        return Err(format!(
+            "{} already exists, refusing to overwrite a pre-shared key",
+            path.display()
+        )
+        .into());
+    }
+    let mut key = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut key);
+    let psk = PreSharedKey::new(key);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(format!("{psk}\n").as_bytes()))
+        .map_err(|e| format!("failed to write pre-shared key {}: {e}", path.display()))?;
+    Ok(psk)
+}
+
+/// With a pre-shared key a peer using another or no key makes the pnet nonce exchange, the
+/// noise handshake or the protocol negotiation before it fail. Recognises those failures so
+/// they can be reported as such instead of as an obscure upgrade error.
+pub fn dial_refused(error: &DialError) -> bool {
+    match error {
+        DialError::Transport(errors) => {
+            !errors.is_empty() && errors.iter().all(|(_, error)| handshake_failed(error))
+        }
+        _ => false,
+    }
+}
+
+/// Like [`dial_refused`], for a connection a remote opened to us.
+pub fn listen_refused(error: &ListenError) -> bool {
+    match error {
+        ListenError::Transport(error) => handshake_failed(error),
+        _ => false,
+    }
+}
+
+fn handshake_failed(error: &TransportError<io::Error>) -> bool {
+    match error {
+        TransportError::Other(error) => {
+            matches!(
+                UpgradeFailure::of(error),
+                Some(UpgradeFailure::Handshake(_))
+            )
+        }
+        TransportError::MultiaddrNotSupported(_) => false,
+    }
+}
@@ -0,0 +1,37 @@
+use qrcode::{render::unicode::Dense1x2, EcLevel, QrCode};
+use std::env;
+
+/// The ticket as a QR code to scan off the terminal, in half blocks when the terminal takes
+/// unicode and in `#` otherwise. The code is light on dark, as most terminals are dark. A ticket
+/// with many relays gets a larger QR version and, if it doesn't fit at medium error correction,
+/// a lower one.
+pub fn render(ticket: &str, unicode: bool) -> Result<String, String> {
+    let code = QrCode::with_error_correction_level(ticket, EcLevel::M)
+        .or_else(|_| QrCode::with_error_correction_level(ticket, EcLevel::L))
+        .map_err(|e| format!("the ticket doesn't fit in a QR code: {e}"))?;
+    Ok(match unicode {
+        true => code
+            .render::<Dense1x2>()
+            .dark_color(Dense1x2::Light)
+            .light_color(Dense1x2::Dark)
+            .build(),
+        false => code
+            .render::<char>()
+            .module_dimensions(2, 1)
+            .dark_color(' ')
+            .light_color('#')
+            .build(),
+    })
+}
+
+/// Whether the locale says the terminal shows UTF-8.
+pub fn unicode_supported() -> bool {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .map_or(false, |value| {
+            let value = value.to_lowercase();
+            value.contains("utf-8") || value.contains("utf8")
+        })
+}
@@ -0,0 +1,136 @@
+use crate::console::say;
+use crate::node::Node;
+use crate::resolver;
+use futures::{FutureExt, StreamExt};
+use libp2p::{
+    core::{
+        multiaddr::{Multiaddr, Protocol},
+        ConnectedPoint,
+    },
+    swarm::SwarmEvent,
+    PeerId,
+};
+use std::time::Duration;
+use tracing::info;
+
+/// Checks `--relay-address` before anything is dialed, returning the relay's peer id if the
+/// address names it. Without one the address can still be completed with [`complete`].
+pub fn validate(addr: &Multiaddr) -> Result<Option<PeerId>, String> {
+    let mut protocols = addr.iter();
+    match protocols.next() {
+        None => return Err("--relay-address is empty".to_string()),
+        // A dnsaddr record resolves to complete addresses, transport included.
+        Some(Protocol::Dnsaddr(_)) => {}
+        Some(
+            Protocol::Ip4(_)
+            | Protocol::Ip6(_)
+            | Protocol::Dns(_)
+            | Protocol::Dns4(_)
+            | Protocol::Dns6(_),
+        ) => {
+            if !matches!(protocols.next(), Some(Protocol::Tcp(_))) {
+                return Err(format!(
+                    "--relay-address {addr} has no TCP port after the host, e.g. /ip4/198.51.100.1/tcp/4001"
+                ));
+            }
+        }
+        Some(_) => {
+            return Err(format!(
+                "--relay-address {addr} has to start with the relay's host, /ip4, /ip6, /dns, /dns4, /dns6 or /dnsaddr"
+            ))
+        }
+    }
+    let mut peer_id = None;
+    for protocol in addr.iter() {
+        match protocol {
+            Protocol::P2pCircuit => {
+                return Err(format!(
+                    "--relay-address {addr} contains /p2p-circuit, give the relay's own address, the circuit is added to it"
+                ))
+            }
+            Protocol::P2p(hash) => match PeerId::from_multihash(hash) {
+                Ok(id) if peer_id.is_none() => peer_id = Some(id),
+                Ok(_) => return Err(format!("--relay-address {addr} has more than one /p2p")),
+                Err(_) => return Err(format!("--relay-address {addr} has an invalid peer id")),
+            },
+            _ if peer_id.is_some() => {
+                return Err(format!(
+                    "--relay-address {addr} has to end with /p2p/<peer-id>, nothing may follow it"
+                ))
+            }
+            _ => {}
+        }
+    }
+    Ok(peer_id)
+}
+
+/// Catches peer ids that would make us dial ourselves or mistake the relay for a remote peer.
+/// Identical ids come from two sides picking the same `--secret-key-seed`, as everyone copying
+/// the same example command does.
+pub fn check_peer_ids(
+    local_peer_id: &PeerId,
+    relay: Option<PeerId>,
+    remotes: &[PeerId],
+) -> Result<(), String> {
+    if relay.as_ref() == Some(local_peer_id) {
+        return Err(format!(
+            "our peer id {local_peer_id} is the relay's, the relay was started with the same --secret-key-seed, pick another seed"
+        ));
+    }
+    for remote in remotes {
+        if remote == local_peer_id {
+            return Err(format!(
+                "--remote-peer-id {remote} is our own peer id, both sides use the same --secret-key-seed, pick another seed on one of them"
+            ));
+        }
+        if Some(*remote) == relay {
+            return Err(format!(
+                "--remote-peer-id {remote} is the relay's peer id, use the peer id the other client prints"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Dials `addr`, which lacks the relay's peer id, and appends the peer id the relay
+/// authenticated with. The connection stays open for the bootstrap to go on with.
+pub async fn complete(
+    swarm: &mut Node,
+    addr: &Multiaddr,
+    timeout: Duration,
+) -> Result<Multiaddr, String> {
+    info!("The relay address has no /p2p/<peer-id>, dialing {addr} to learn it");
+    swarm
+        .dial(addr.clone())
+        .map_err(|e| format!("failed to dial the relay {addr}: {e}"))?;
+    let mut deadline = futures_timer::Delay::new(timeout).fuse();
+    loop {
+        futures::select!(
+            event = swarm.select_next_some() => match event {
+                SwarmEvent::ConnectionEstablished {
+                    peer_id,
+                    endpoint: ConnectedPoint::Dialer { address, .. },
+                    ..
+                } if &address == addr => {
+                    let completed = addr.clone().with(Protocol::P2p(peer_id.into()));
+                    say!("Completed the relay address to {completed}");
+                    return Ok(completed);
+                }
+                SwarmEvent::OutgoingConnectionError { peer_id: None, error, .. } => {
+                    return Err(match resolver::host(addr) {
+                        Some(host) if resolver::is_resolve_failure(&error) => {
+                            format!("failed to resolve the relay's host {host}: {error}")
+                        }
+                        _ => format!("failed to reach the relay {addr}: {error}"),
+                    });
+                }
+                _ => {}
+            },
+            _ = deadline => {
+                return Err(format!(
+                    "the relay {addr} didn't answer within {timeout:?}, add /p2p/<peer-id> to the address to skip this"
+                ));
+            },
+        );
+    }
+}
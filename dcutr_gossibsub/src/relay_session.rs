@@ -0,0 +1,120 @@
+use crate::bootstrap;
+use crate::bootstrap_peers;
+use libp2p::{
+    core::multiaddr::{Multiaddr, Protocol},
+    PeerId,
+};
+use std::time::{Duration, Instant};
+
+/// How long to wait before dialing the relay again after losing the reservation.
+pub const REDIAL_DELAY: Duration = Duration::from_secs(5);
+
+/// What we have at the relay once bootstrapping is done: a reservation in listen mode, a circuit
+/// in dial mode, and when to dial the relay again after losing the connection that backed the
+/// reservation. It's only told what happened, the event loop does the dialing.
+pub struct RelaySession {
+    address: Multiaddr,
+    peer_id: Option<PeerId>,
+    status: &'static str,
+    redial_at: Option<Instant>,
+    circuit_established: bool,
+}
+
+impl RelaySession {
+    pub fn new(address: Multiaddr) -> Self {
+        RelaySession {
+            peer_id: bootstrap_peers::peer_id_of(&address),
+            address,
+            status: "not used",
+            redial_at: None,
+            circuit_established: false,
+        }
+    }
+
+    pub fn address(&self) -> &Multiaddr {
+        &self.address
+    }
+
+    /// Known if the address ends in `/p2p/<peer-id>`.
+    pub fn peer_id(&self) -> Option<PeerId> {
+        self.peer_id
+    }
+
+    pub fn is_relay(&self, peer_id: &PeerId) -> bool {
+        self.peer_id == Some(*peer_id)
+    }
+
+    /// The address of `peer_id` through a circuit of the relay.
+    pub fn relayed_addr(&self, peer_id: PeerId) -> Multiaddr {
+        bootstrap::relayed_addr(&self.address, peer_id)
+    }
+
+    /// The address to listen on for a reservation.
+    pub fn circuit_addr(&self) -> Multiaddr {
+        self.address.clone().with(Protocol::P2pCircuit)
+    }
+
+    /// "not used", "reservation pending", "reservation accepted" or "relay disconnected".
+    pub fn status(&self) -> &'static str {
+        self.status
+    }
+
+    pub fn on_reservation_requested(&mut self) {
+        self.status = "reservation pending";
+    }
+
+    pub fn on_reservation_accepted(&mut self) {
+        self.status = "reservation accepted";
+    }
+
+    pub fn on_circuit_established(&mut self) {
+        self.circuit_established = true;
+    }
+
+    /// Whether our circuit to a remote peer was established, in dial mode.
+    pub fn has_circuit(&self) -> bool {
+        self.circuit_established
+    }
+
+    /// Whether the reservation has to be renewed now that `peer_id` connected, which it has if
+    /// that's the relay and the reservation went with the previous connection.
+    pub fn on_connected(&mut self, peer_id: &PeerId) -> bool {
+        if !self.is_relay(peer_id) || self.status != "relay disconnected" {
+            return false;
+        }
+        self.status = "reservation pending";
+        true
+    }
+
+    /// Whether losing the last connection to `peer_id` lost the reservation. The relay is dialed
+    /// again [`REDIAL_DELAY`] later then.
+    pub fn on_disconnected(&mut self, peer_id: &PeerId, now: Instant) -> bool {
+        if !self.is_relay(peer_id) || self.status != "reservation accepted" {
+            return false;
+        }
+        self.status = "relay disconnected";
+        self.redial_at = Some(now + REDIAL_DELAY);
+        true
+    }
+
+    /// A dial of `peer_id` failed, the relay is tried again later while the reservation is lost.
+    pub fn on_dial_failure(&mut self, peer_id: &PeerId, now: Instant) {
+        if self.is_relay(peer_id) && self.status == "relay disconnected" {
+            self.redial_at = Some(now + REDIAL_DELAY);
+        }
+    }
+
+    /// Whether to dial the relay again now, true once per lost reservation or failed redial.
+    pub fn redial_due(&mut self, now: Instant) -> bool {
+        if self.redial_at.map_or(false, |at| at <= now) {
+            self.redial_at = None;
+            return true;
+        }
+        false
+    }
+
+    /// Dialing the relay failed right away, it's tried again later.
+    pub fn on_redial_failed(&mut self, now: Instant) {
+        self.redial_at = Some(now + REDIAL_DELAY);
+    }
+}
@@ -0,0 +1,243 @@
+use crate::allow_list;
+use crate::console::say;
+use crate::holepunch::is_relayed;
+use libp2p::{
+    core::multiaddr::{Multiaddr, Protocol},
+    PeerId,
+};
+use std::error::Error;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Reads the peers of a `--peers-file`, which has to name at least one.
+pub fn load_file(path: &Path) -> Result<Vec<PeerId>, Box<dyn Error>> {
+    let peers = allow_list::parse_peer_file(path, "peers file")?;
+    if peers.is_empty() {
+        return Err(format!("peers file {} contains no peer ids", path.display()).into());
+    }
+    Ok(peers)
+}
+
+/// Pairs each `--remote-address` with the remote peer it belongs to: the one its `/p2p` names,
+/// or the only remote peer if it names none. The `/p2p` is stripped, the dial carries the peer id
+/// and the handshake checks it.
+pub fn pair_addresses(
+    addrs: &[Multiaddr],
+    remotes: &[PeerId],
+) -> Result<Vec<(PeerId, Multiaddr)>, String> {
+    addrs
+        .iter()
+        .map(|addr| {
+            let mut addr = addr.clone();
+            if is_relayed(&addr) {
+                return Err(format!(
+                    "--remote-address {addr} is a relayed address, give the remote's direct address"
+                ));
+            }
+            let named = match addr.iter().last() {
+                Some(Protocol::P2p(hash)) => {
+                    addr.pop();
+                    Some(
+                        PeerId::from_multihash(hash)
+                            .map_err(|_| format!("--remote-address {addr} has an invalid peer id"))?,
+                    )
+                }
+                _ => None,
+            };
+            match (named, remotes) {
+                (Some(peer_id), _) if remotes.contains(&peer_id) => Ok((peer_id, addr)),
+                (Some(peer_id), _) => Err(format!(
+                    "--remote-address {addr} is for {peer_id}, which isn't one of the --remote-peer-id"
+                )),
+                (None, [peer_id]) => Ok((*peer_id, addr)),
+                (None, []) => Err(format!(
+                    "--remote-address {addr} requires --remote-peer-id"
+                )),
+                (None, _) => Err(format!(
+                    "--remote-address {addr} needs /p2p/<peer-id> to tell which remote peer it is for"
+                )),
+            }
+        })
+        .collect()
+}
+
+struct Remote {
+    peer_id: PeerId,
+    connected: bool,
+    failures: u32,
+    retry_at: Option<Instant>,
+    last_error: Option<String>,
+    /// When dialing the known direct addresses gives way to the relay.
+    direct_until: Option<Instant>,
+}
+
+/// The remote peers we dial through the relay in dial mode. Each is tracked on its own: one that
+/// can't be reached is redialed with backoff without holding up the others, one that drops is
+/// redialed right away.
+pub struct Remotes {
+    remotes: Vec<Remote>,
+}
+
+impl Remotes {
+    pub fn new(peers: &[PeerId]) -> Self {
+        let mut remotes = Vec::<Remote>::new();
+        for peer_id in peers {
+            if remotes.iter().all(|r| &r.peer_id != peer_id) {
+                remotes.push(Remote {
+                    peer_id: *peer_id,
+                    connected: false,
+                    failures: 0,
+                    retry_at: None,
+                    last_error: None,
+                    direct_until: None,
+                });
+            }
+        }
+        Remotes { remotes }
+    }
+
+    /// Adds `peer_id` at runtime, dialed from the next tick unless it's `connected` already.
+    pub fn add(&mut self, peer_id: PeerId, connected: bool) {
+        if self.contains(&peer_id) {
+            return;
+        }
+        self.remotes.push(Remote {
+            peer_id,
+            connected,
+            failures: 0,
+            retry_at: (!connected).then(Instant::now),
+            last_error: None,
+            direct_until: None,
+        });
+    }
+
+    pub fn remove(&mut self, peer_id: &PeerId) {
+        self.remotes.retain(|r| &r.peer_id != peer_id);
+    }
+
+    pub fn peer_ids(&self) -> impl Iterator<Item = PeerId> + '_ {
+        self.remotes.iter().map(|r| r.peer_id)
+    }
+
+    pub fn contains(&self, peer_id: &PeerId) -> bool {
+        self.remotes.iter().any(|r| &r.peer_id == peer_id)
+    }
+
+    /// Remotes whose redial is due. They won't be returned again until a failure is reported.
+    pub fn due(&mut self, now: Instant) -> Vec<PeerId> {
+        self.remotes
+            .iter_mut()
+            .filter(|r| !r.connected && r.retry_at.map_or(false, |at| at <= now))
+            .map(|r| {
+                r.retry_at = None;
+                r.peer_id
+            })
+            .collect()
+    }
+
+    pub fn on_connected(&mut self, peer_id: &PeerId) {
+        for remote in self.remotes.iter_mut().filter(|r| &r.peer_id == peer_id) {
+            if !remote.connected && remote.failures > 0 {
+                say!(
+                    "Reached {peer_id} after {} failed attempts",
+                    remote.failures
+                );
+            }
+            remote.connected = true;
+            remote.failures = 0;
+            remote.retry_at = None;
+            remote.last_error = None;
+            remote.direct_until = None;
+        }
+    }
+
+    /// The known direct addresses of `peer_id` are being dialed, the relay is used if none of
+    /// them answers before `until`.
+    pub fn on_direct_dial(&mut self, peer_id: &PeerId, until: Instant) {
+        for remote in self.remotes.iter_mut().filter(|r| &r.peer_id == peer_id) {
+            remote.direct_until = Some(until);
+        }
+    }
+
+    pub fn is_dialing_direct(&self, peer_id: &PeerId) -> bool {
+        self.remotes
+            .iter()
+            .any(|r| &r.peer_id == peer_id && r.direct_until.is_some())
+    }
+
+    /// The direct dial of `peer_id` failed, returning whether one was going on, in which case
+    /// it's the relay's turn.
+    pub fn on_direct_failed(&mut self, peer_id: &PeerId) -> bool {
+        self.remotes
+            .iter_mut()
+            .filter(|r| &r.peer_id == peer_id && !r.connected)
+            .any(|r| r.direct_until.take().is_some())
+    }
+
+    /// Remotes whose direct dial didn't get anywhere in time, to dial through the relay.
+    pub fn direct_timed_out(&mut self, now: Instant) -> Vec<PeerId> {
+        self.remotes
+            .iter_mut()
+            .filter(|r| !r.connected && r.direct_until.map_or(false, |until| until <= now))
+            .map(|r| {
+                r.direct_until = None;
+                r.peer_id
+            })
+            .collect()
+    }
+
+    pub fn on_disconnected(&mut self, peer_id: &PeerId) {
+        for remote in self.remotes.iter_mut().filter(|r| &r.peer_id == peer_id) {
+            info!("Lost connection to {peer_id}, redialing");
+            remote.connected = false;
+            remote.retry_at = Some(Instant::now());
+        }
+    }
+
+    /// A dial of `peer_id` failed. Ignored while connected, as failed hole punch dials leave the
+    /// relayed connection open.
+    pub fn on_dial_failure(&mut self, peer_id: &PeerId, error: &str) {
+        for remote in self
+            .remotes
+            .iter_mut()
+            .filter(|r| &r.peer_id == peer_id && !r.connected)
+        {
+            remote.failures += 1;
+            let delay = Duration::from_secs(1 << remote.failures.min(6)).min(MAX_RETRY_DELAY);
+            warn!(
+                "Failed to reach {peer_id} ({} failures), retrying in {:?}",
+                remote.failures, delay
+            );
+            remote.retry_at = Some(Instant::now() + delay);
+            remote.last_error = Some(error.to_string());
+        }
+    }
+
+    /// A line for each remote that isn't connected, for `/peers`.
+    pub fn lines(&self, now: Instant) -> Vec<String> {
+        self.remotes
+            .iter()
+            .filter(|r| !r.connected)
+            .map(|r| {
+                let retry = match (r.direct_until, r.retry_at) {
+                    (Some(_), _) => "dialing known direct addresses".to_string(),
+                    (None, Some(at)) => format!(
+                        "retrying in {}s",
+                        at.saturating_duration_since(now).as_secs()
+                    ),
+                    (None, None) => "dialing".to_string(),
+                };
+                match &r.last_error {
+                    Some(e) => format!(
+                        "{} not connected, {retry} ({} failures, last: {e})",
+                        r.peer_id, r.failures
+                    ),
+                    None => format!("{} not connected, {retry}", r.peer_id),
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,298 @@
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// How often the changed scores are written to disk with `--persist-reputation`.
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+/// How many of a peer's offences are kept for `/rep`.
+const HISTORY_SIZE: usize = 20;
+
+/// Points deducted per offence of a kind, the kinds being those of
+/// [`crate::validation::Rejection`] plus `signature` for bad signatures and `body` for bodies
+/// that don't decode or decompress beyond the limit.
+pub fn penalty(kind: &str) -> f64 {
+    match kind {
+        "signature" => 25.0,
+        "body" => 20.0,
+        "rate" => 10.0,
+        _ => 5.0,
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ReputationConfig {
+    /// Score at or below which a peer is banned, negative.
+    pub threshold: f64,
+    /// Points a peer earns back per minute, up to a score of zero.
+    pub recovery_per_min: f64,
+    /// How long the first ban lasts, each further one of the peer lasting twice as long.
+    pub ban: Duration,
+    pub max_ban: Duration,
+}
+
+impl ReputationConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.threshold >= 0.0 {
+            return Err(format!(
+                "reputation threshold must be negative, got {}",
+                self.threshold
+            ));
+        }
+        if self.recovery_per_min < 0.0 {
+            return Err(format!(
+                "reputation recovery must not be negative, got {}",
+                self.recovery_per_min
+            ));
+        }
+        Ok(())
+    }
+
+    fn ban_duration(&self, bans: u32) -> Duration {
+        let factor = 2u32.saturating_pow(bans.saturating_sub(1));
+        self.ban.saturating_mul(factor).min(self.max_ban)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Offence {
+    /// Unix time in seconds.
+    at: u64,
+    kind: String,
+    reason: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct Record {
+    score: f64,
+    /// Unix time in seconds the score was last brought up to date.
+    updated: u64,
+    /// How often the peer was banned, which doubles the next ban.
+    bans: u32,
+    banned_until: Option<u64>,
+    history: VecDeque<Offence>,
+}
+
+/// A peer's score falling to the threshold.
+#[derive(Debug)]
+pub struct Ban {
+    pub peer_id: PeerId,
+    pub duration: Duration,
+    /// The offences in the peer's history by kind, e.g. `12x rate, 1x signature`.
+    pub reasons: String,
+}
+
+/// Remembers how peers behaved, beyond the per-message verdicts gossipsub acts on. Each rejected
+/// message costs its propagation source points, which it earns back slowly. A peer whose score
+/// falls to the threshold is banned for a while, longer with each ban. The scores outlive
+/// reconnects and, with a path, restarts.
+pub struct Reputation {
+    config: ReputationConfig,
+    path: Option<PathBuf>,
+    records: HashMap<PeerId, Record>,
+    /// Scored like everybody, but never banned.
+    protected: HashSet<PeerId>,
+    dirty: bool,
+    saved: Instant,
+}
+
+impl Reputation {
+    /// Loads the scores kept in `dir` if given, starting from scratch otherwise.
+    pub fn load(
+        dir: Option<&Path>,
+        peer_id: &PeerId,
+        config: ReputationConfig,
+    ) -> Result<Self, Box<dyn Error>> {
+        let path = dir.map(|dir| dir.join(format!("reputation-{peer_id}.json")));
+        let records: HashMap<PeerId, Record> = match &path {
+            Some(path) if path.exists() => {
+                let contents = fs::read(path)
+                    .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+                serde_json::from_slice::<BTreeMap<String, Record>>(&contents)
+                    .unwrap_or_else(|e| {
+                        warn!("Ignoring unreadable reputation {}: {e}", path.display());
+                        BTreeMap::new()
+                    })
+                    .into_iter()
+                    .filter_map(|(peer_id, record)| Some((peer_id.parse().ok()?, record)))
+                    .collect()
+            }
+            _ => HashMap::new(),
+        };
+        if !records.is_empty() {
+            info!("Loaded the reputation of {} peers", records.len());
+        }
+        Ok(Reputation {
+            config,
+            path,
+            records,
+            protected: HashSet::new(),
+            dirty: false,
+            saved: Instant::now(),
+        })
+    }
+
+    /// Exempts `peer_id` from bans, its offences still count.
+    pub fn protect(&mut self, peer_id: PeerId) {
+        self.protected.insert(peer_id);
+    }
+
+    /// Lets `peer_id` be banned again.
+    pub fn unprotect(&mut self, peer_id: &PeerId) {
+        self.protected.remove(peer_id);
+    }
+
+    /// Deducts the penalty of `kind` from the score of `peer_id`, returning the ban if that
+    /// brought it to the threshold. `now` is the unix time in seconds.
+    pub fn on_offence(
+        &mut self,
+        peer_id: PeerId,
+        kind: &str,
+        reason: &str,
+        now: u64,
+    ) -> Option<Ban> {
+        let config = self.config;
+        let record = self.records.entry(peer_id).or_insert_with(|| Record {
+            updated: now,
+            ..Record::default()
+        });
+        recover(record, &config, now);
+        record.score -= penalty(kind);
+        if record.history.len() >= HISTORY_SIZE {
+            record.history.pop_front();
+        }
+        record.history.push_back(Offence {
+            at: now,
+            kind: kind.to_string(),
+            reason: reason.to_string(),
+        });
+        self.dirty = true;
+        let banned = record.banned_until.map_or(false, |until| until > now);
+        if record.score > config.threshold || banned || self.protected.contains(&peer_id) {
+            return None;
+        }
+        record.bans += 1;
+        let duration = config.ban_duration(record.bans);
+        record.banned_until = Some(now + duration.as_secs());
+        // A peer coming back gets a fresh start, only its bans are held against it.
+        record.score = 0.0;
+        let mut kinds = BTreeMap::<&str, usize>::new();
+        for offence in &record.history {
+            *kinds.entry(offence.kind.as_str()).or_default() += 1;
+        }
+        let reasons = kinds
+            .iter()
+            .map(|(kind, count)| format!("{count}x {kind}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        Some(Ban {
+            peer_id,
+            duration,
+            reasons,
+        })
+    }
+
+    /// The peers whose bans are in effect at `now`, to block again after a restart.
+    pub fn banned(&self, now: u64) -> Vec<PeerId> {
+        self.records
+            .iter()
+            .filter(|(_, record)| record.banned_until.map_or(false, |until| until > now))
+            .map(|(peer_id, _)| *peer_id)
+            .collect()
+    }
+
+    /// The peers whose bans ended by `now`, each returned once.
+    pub fn expired(&mut self, now: u64) -> Vec<PeerId> {
+        let mut expired = Vec::new();
+        for (peer_id, record) in &mut self.records {
+            if record.banned_until.map_or(false, |until| until <= now) {
+                record.banned_until = None;
+                expired.push(*peer_id);
+            }
+        }
+        if !expired.is_empty() {
+            self.dirty = true;
+        }
+        expired
+    }
+
+    /// The score of `peer_id` at `now`, zero for peers that never misbehaved.
+    pub fn score(&self, peer_id: &PeerId, now: u64) -> f64 {
+        self.records.get(peer_id).map_or(0.0, |record| {
+            let mut record = record.clone();
+            recover(&mut record, &self.config, now);
+            record.score
+        })
+    }
+
+    /// The score, bans and offences of `peer_id`, for `/rep`.
+    pub fn lines(&self, peer_id: &PeerId, now: u64) -> Vec<String> {
+        let record = match self.records.get(peer_id) {
+            Some(record) => record,
+            None => return Vec::new(),
+        };
+        let mut lines = vec![format!(
+            "score {:.1} (banned at {:.1}), banned {} times",
+            self.score(peer_id, now),
+            self.config.threshold,
+            record.bans
+        )];
+        if let Some(until) = record.banned_until.filter(|until| *until > now) {
+            lines.push(format!("banned for another {}s", until - now));
+        }
+        for offence in record.history.iter().rev() {
+            lines.push(format!(
+                "{}s ago: {} (-{}) {}",
+                now.saturating_sub(offence.at),
+                offence.kind,
+                penalty(&offence.kind),
+                offence.reason
+            ));
+        }
+        lines
+    }
+
+    /// Writes the scores to disk if they changed and the last save is long enough ago.
+    pub fn save_due(&mut self, now: Instant) {
+        if now.duration_since(self.saved) >= SAVE_INTERVAL {
+            self.save();
+            self.saved = now;
+        }
+    }
+
+    pub fn save(&mut self) {
+        let path = match &self.path {
+            Some(path) if self.dirty => path,
+            _ => return,
+        };
+        let records = self
+            .records
+            .iter()
+            .map(|(peer_id, record)| (peer_id.to_string(), record))
+            .collect::<BTreeMap<_, _>>();
+        let contents = serde_json::to_vec(&records).expect("reputation serializes to JSON");
+        if let Err(e) = fs::write(path, contents) {
+            warn!("Failed to save the reputation to {}: {e}", path.display());
+        }
+        self.dirty = false;
+    }
+}
+
+/// Brings the score up to date with the points earned back since it was last updated.
+fn recover(record: &mut Record, config: &ReputationConfig, now: u64) {
+    let minutes = now.saturating_sub(record.updated) as f64 / 60.0;
+    record.score = (record.score + minutes * config.recovery_per_min).min(0.0);
+    record.updated = record.updated.max(now);
+}
+
+/// The unix time in seconds, as the reputation is kept in.
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,173 @@
+use crate::envelope::Envelope;
+use libp2p::{gossipsub::TopicHash, PeerId};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+/// A message held back for a peer that is away.
+pub enum Queued {
+    /// A topic message, ready to be signed and published again. `text` is its plain body.
+    Topic {
+        topic: TopicHash,
+        envelope: Envelope,
+        text: String,
+    },
+    /// The body of a direct message that could not be delivered.
+    Dm { text: String },
+}
+
+impl Queued {
+    pub fn describe(&self, topic_name: impl Fn(&TopicHash) -> String) -> String {
+        match self {
+            Queued::Topic { topic, text, .. } => format!("[{}] {text}", topic_name(topic)),
+            Queued::Dm { text } => format!("DM '{text}'"),
+        }
+    }
+}
+
+struct Entry {
+    queued_at: Instant,
+    item: Queued,
+}
+
+/// Messages for peers whose connection dropped, sent again once they are back. Topic messages
+/// are buffered for the watched peers, i.e. the configured remote, while they are disconnected,
+/// and go out again when the peer subscribes to the topic anew. Direct messages that gave up
+/// while their peer was unreachable go out again as soon as it connects.
+pub struct ResendBuffer {
+    capacity: usize,
+    max_age: Duration,
+    watched: HashSet<PeerId>,
+    disconnected: HashSet<PeerId>,
+    queues: HashMap<PeerId, VecDeque<Entry>>,
+}
+
+impl ResendBuffer {
+    pub fn new(
+        capacity: usize,
+        max_age: Duration,
+        watched: impl IntoIterator<Item = PeerId>,
+    ) -> Self {
+        ResendBuffer {
+            capacity,
+            max_age,
+            watched: watched.into_iter().collect(),
+            disconnected: HashSet::new(),
+            queues: HashMap::new(),
+        }
+    }
+
+    /// Watched peers whose last connection closed.
+    pub fn disconnected(&self) -> impl Iterator<Item = &PeerId> {
+        self.disconnected.iter()
+    }
+
+    /// Called when the last connection to `peer_id` closed.
+    pub fn on_disconnected(&mut self, peer_id: PeerId) {
+        if self.watched.contains(&peer_id) {
+            self.disconnected.insert(peer_id);
+        }
+    }
+
+    /// Called when `peer_id` connected, returns the direct messages to send again.
+    pub fn on_connected(&mut self, peer_id: &PeerId) -> Vec<String> {
+        self.disconnected.remove(peer_id);
+        self.take(peer_id, |item| match item {
+            Queued::Dm { text } => Ok(text),
+            item => Err(item),
+        })
+    }
+
+    /// Called when `peer_id` subscribed to `topic`, returns the topic messages to publish again.
+    pub fn on_subscribed(
+        &mut self,
+        peer_id: &PeerId,
+        topic: &TopicHash,
+    ) -> Vec<(Envelope, String)> {
+        self.take(peer_id, |item| match item {
+            Queued::Topic {
+                topic: t,
+                envelope,
+                text,
+            } if &t == topic => Ok((envelope, text)),
+            item => Err(item),
+        })
+    }
+
+    /// Removes and returns the entries of `peer_id` that `select` takes, in order.
+    fn take<T>(
+        &mut self,
+        peer_id: &PeerId,
+        mut select: impl FnMut(Queued) -> Result<T, Queued>,
+    ) -> Vec<T> {
+        let queue = match self.queues.get_mut(peer_id) {
+            Some(queue) => queue,
+            None => return Vec::new(),
+        };
+        let mut taken = Vec::new();
+        let mut kept = VecDeque::new();
+        for entry in queue.drain(..) {
+            match select(entry.item) {
+                Ok(selected) => taken.push(selected),
+                Err(item) => kept.push_back(Entry {
+                    queued_at: entry.queued_at,
+                    item,
+                }),
+            }
+        }
+        if kept.is_empty() {
+            self.queues.remove(peer_id);
+        } else {
+            *queue = kept;
+        }
+        taken
+    }
+
+    /// Queues `item` for `peer_id`, returning the oldest queued one if it had to make room.
+    pub fn push(&mut self, peer_id: PeerId, item: Queued) -> Option<Queued> {
+        let queue = self.queues.entry(peer_id).or_default();
+        queue.push_back(Entry {
+            queued_at: Instant::now(),
+            item,
+        });
+        if queue.len() > self.capacity {
+            return queue.pop_front().map(|e| e.item);
+        }
+        None
+    }
+
+    /// Drops the messages queued for longer than the maximum age and returns them.
+    pub fn expire(&mut self, now: Instant) -> Vec<(PeerId, Queued)> {
+        let mut expired = Vec::new();
+        for (peer_id, queue) in self.queues.iter_mut() {
+            while queue
+                .front()
+                .map_or(false, |e| now.duration_since(e.queued_at) >= self.max_age)
+            {
+                let entry = queue.pop_front().expect("checked");
+                expired.push((*peer_id, entry.item));
+            }
+        }
+        self.queues.retain(|_, q| !q.is_empty());
+        expired
+    }
+
+    /// One line per queued message, for `/queue`.
+    pub fn lines(
+        &self,
+        now: Instant,
+        topic_name: impl Fn(&TopicHash) -> String + Copy,
+    ) -> Vec<String> {
+        self.queues
+            .iter()
+            .flat_map(|(peer_id, queue)| {
+                queue.iter().map(move |e| {
+                    format!(
+                        "to {peer_id}: {} (queued {}s ago)",
+                        e.item.describe(topic_name),
+                        now.duration_since(e.queued_at).as_secs()
+                    )
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,154 @@
+use libp2p::{
+    core::multiaddr::{Multiaddr, Protocol},
+    dns::{ResolverConfig, ResolverOpts},
+};
+use std::error::Error;
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::{info, warn};
+use trust_dns_resolver::config::NameServerConfigGroup;
+
+/// Public resolvers used when the system's DNS configuration can't be read, as happens in minimal
+/// containers without a resolv.conf.
+pub const FALLBACK_SERVERS: [IpAddr; 2] = [
+    IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)),
+    IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)),
+];
+
+/// How the configured DNS servers are queried.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DnsProtocol {
+    Udp,
+    /// DNS over TLS, port 853.
+    Tls,
+    /// DNS over HTTPS, port 443.
+    Https,
+}
+
+impl FromStr for DnsProtocol {
+    type Err = String;
+    fn from_str(protocol: &str) -> Result<Self, Self::Err> {
+        match protocol {
+            "udp" => Ok(DnsProtocol::Udp),
+            "tls" => Ok(DnsProtocol::Tls),
+            "https" => Ok(DnsProtocol::Https),
+            _ => Err("Expected one of 'udp', 'tls' or 'https'".to_string()),
+        }
+    }
+}
+
+/// Where host names in addresses are resolved. `/dnsaddr` addresses are followed through their
+/// TXT records by the dns transport, which gives up after a fixed number of lookups so records
+/// pointing at each other can't loop.
+pub struct DnsSettings {
+    /// The system's resolvers when empty.
+    pub servers: Vec<IpAddr>,
+    /// Queried over UDP when the system's configuration can't be read, [`FALLBACK_SERVERS`] when
+    /// empty.
+    pub fallback: Vec<IpAddr>,
+    pub protocol: DnsProtocol,
+    /// The name the certificates of the servers are issued for, with TLS and HTTPS.
+    pub tls_name: Option<String>,
+    pub timeout: Duration,
+}
+
+/// The system's resolvers.
+impl Default for DnsSettings {
+    fn default() -> Self {
+        DnsSettings {
+            servers: Vec::new(),
+            fallback: Vec::new(),
+            protocol: DnsProtocol::Udp,
+            tls_name: None,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl DnsSettings {
+    /// The resolver setup to use, which is logged. Only a missing TLS name is an error, a system
+    /// configuration that can't be read falls back to the [`DnsSettings::fallback`] servers.
+    pub fn resolver(&self) -> Result<(ResolverConfig, ResolverOpts), Box<dyn Error>> {
+        let (config, mut opts) = match (&self.protocol, &self.tls_name) {
+            _ if self.servers.is_empty() => {
+                match trust_dns_resolver::system_conf::read_system_conf() {
+                    Ok(system) => {
+                        info!("Resolving host names with the system's DNS configuration");
+                        system
+                    }
+                    Err(e) => {
+                        let fallback = match self.fallback.is_empty() {
+                            true => &FALLBACK_SERVERS[..],
+                            false => &self.fallback[..],
+                        };
+                        warn!(
+                        "Failed to read the system's DNS configuration ({e}), resolving host names with {fallback:?}"
+                    );
+                        (
+                            servers(NameServerConfigGroup::from_ips_clear(fallback, 53, true)),
+                            ResolverOpts::default(),
+                        )
+                    }
+                }
+            }
+            (DnsProtocol::Udp, _) => (
+                servers(NameServerConfigGroup::from_ips_clear(
+                    &self.servers,
+                    53,
+                    true,
+                )),
+                ResolverOpts::default(),
+            ),
+            (DnsProtocol::Tls, Some(name)) => (
+                servers(NameServerConfigGroup::from_ips_tls(
+                    &self.servers,
+                    853,
+                    name.clone(),
+                    true,
+                )),
+                ResolverOpts::default(),
+            ),
+            (DnsProtocol::Https, Some(name)) => (
+                servers(NameServerConfigGroup::from_ips_https(
+                    &self.servers,
+                    443,
+                    name.clone(),
+                    true,
+                )),
+                ResolverOpts::default(),
+            ),
+            (_, None) => return Err("--dns-protocol tls and https require --dns-tls-name".into()),
+        };
+        if !self.servers.is_empty() {
+            info!(
+                "Resolving host names with {:?} over {:?}",
+                self.servers, self.protocol
+            );
+        }
+        opts.timeout = self.timeout;
+        Ok((config, opts))
+    }
+}
+
+fn servers(group: NameServerConfigGroup) -> ResolverConfig {
+    ResolverConfig::from_parts(None, Vec::new(), group)
+}
+
+/// The host name `addr` starts with, if it has to be resolved.
+pub fn host(addr: &Multiaddr) -> Option<String> {
+    match addr.iter().next()? {
+        Protocol::Dns(host)
+        | Protocol::Dns4(host)
+        | Protocol::Dns6(host)
+        | Protocol::Dnsaddr(host) => Some(host.to_string()),
+        _ => None,
+    }
+}
+
+/// Whether a dial failed because a host name couldn't be resolved, rather than on the connection
+/// itself, so the name can be reported instead of an obscure transport error.
+pub fn is_resolve_failure(error: &impl std::fmt::Debug) -> bool {
+    let error = format!("{error:?}");
+    error.contains("ResolveError") || error.contains("TooManyLookups")
+}
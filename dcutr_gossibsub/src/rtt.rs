@@ -0,0 +1,312 @@
+use crate::paths::TransportPath;
+use libp2p::{
+    core::{multiaddr::Multiaddr, Endpoint},
+    ping,
+    swarm::{
+        ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, NetworkBehaviourAction,
+        PollParameters, THandler, THandlerInEvent, THandlerOutEvent,
+    },
+    PeerId,
+};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// Samples kept per connection, older ones are dropped.
+const WINDOW: usize = 20;
+/// Pings over a direct connection before it is compared with the relayed one.
+const COMPARE_AFTER: usize = 3;
+
+/// A ping result with the connection it was measured on.
+#[derive(Debug)]
+pub struct PingEvent {
+    pub connection: ConnectionId,
+    pub event: ping::Event,
+}
+
+/// [`ping::Behaviour`] with the connection of each result. The events of libp2p's ping only name
+/// the peer, which can't tell a relayed connection from a direct one, so the results are taken
+/// from the connection handlers here instead.
+pub struct Ping {
+    inner: ping::Behaviour,
+    events: VecDeque<PingEvent>,
+}
+
+impl Ping {
+    pub fn new(config: ping::Config) -> Self {
+        Ping {
+            inner: ping::Behaviour::new(config),
+            events: VecDeque::new(),
+        }
+    }
+}
+
+impl NetworkBehaviour for Ping {
+    type ConnectionHandler = <ping::Behaviour as NetworkBehaviour>::ConnectionHandler;
+    type OutEvent = PingEvent;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.inner
+            .handle_pending_inbound_connection(connection_id, local_addr, remote_addr)
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner.handle_established_inbound_connection(
+            connection_id,
+            peer,
+            local_addr,
+            remote_addr,
+        )
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        self.inner.handle_pending_outbound_connection(
+            connection_id,
+            maybe_peer,
+            addresses,
+            effective_role,
+        )
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner
+            .handle_established_outbound_connection(connection_id, peer, addr, role_override)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm<Self::ConnectionHandler>) {
+        self.inner.on_swarm_event(event)
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer: PeerId,
+        connection: ConnectionId,
+        result: THandlerOutEvent<Self>,
+    ) {
+        self.events.push_back(PingEvent {
+            connection,
+            event: ping::Event { peer, result },
+        });
+    }
+
+    fn poll(
+        &mut self,
+        _: &mut Context<'_>,
+        _: &mut impl PollParameters,
+    ) -> Poll<NetworkBehaviourAction<Self::OutEvent, THandlerInEvent<Self>>> {
+        // The inner behaviour only ever hands out the handler results, which never reach it.
+        match self.events.pop_front() {
+            Some(event) => Poll::Ready(NetworkBehaviourAction::GenerateEvent(event)),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Round trip times of a connection over the last [`WINDOW`] pings.
+#[derive(Clone, Copy, Debug)]
+pub struct Summary {
+    pub current: Duration,
+    pub min: Duration,
+    pub avg: Duration,
+    pub p95: Duration,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rtt {} ms (min {}, avg {}, p95 {})",
+            self.current.as_millis(),
+            self.min.as_millis(),
+            self.avg.as_millis(),
+            self.p95.as_millis()
+        )
+    }
+}
+
+struct Samples {
+    peer_id: PeerId,
+    rtts: VecDeque<Duration>,
+}
+
+impl Samples {
+    fn summary(&self) -> Option<Summary> {
+        let current = *self.rtts.back()?;
+        let mut sorted = self.rtts.iter().copied().collect::<Vec<_>>();
+        sorted.sort();
+        let p95 = sorted[((sorted.len() * 95 + 99) / 100).max(1) - 1];
+        Some(Summary {
+            current,
+            min: sorted[0],
+            avg: sorted.iter().sum::<Duration>() / sorted.len() as u32,
+            p95,
+        })
+    }
+}
+
+/// The recent round trip times of every open connection.
+#[derive(Default)]
+pub struct RttStats {
+    connections: HashMap<ConnectionId, Samples>,
+}
+
+impl RttStats {
+    pub fn record(&mut self, peer_id: PeerId, connection: ConnectionId, rtt: Duration) {
+        let samples = self
+            .connections
+            .entry(connection)
+            .or_insert_with(|| Samples {
+                peer_id,
+                rtts: VecDeque::with_capacity(WINDOW),
+            });
+        if samples.rtts.len() == WINDOW {
+            samples.rtts.pop_front();
+        }
+        samples.rtts.push_back(rtt);
+    }
+
+    pub fn on_closed(&mut self, connection: ConnectionId) {
+        self.connections.remove(&connection);
+    }
+
+    pub fn summary(&self, connection: ConnectionId) -> Option<Summary> {
+        self.connections.get(&connection)?.summary()
+    }
+
+    /// The number of samples of `connection` in the window.
+    pub fn count(&self, connection: ConnectionId) -> usize {
+        self.connections
+            .get(&connection)
+            .map_or(0, |samples| samples.rtts.len())
+    }
+
+    /// The summaries of each connection of `peer_id`.
+    pub fn of_peer(&self, peer_id: &PeerId) -> Vec<(ConnectionId, Summary)> {
+        self.connections
+            .iter()
+            .filter(|(_, samples)| &samples.peer_id == peer_id)
+            .filter_map(|(connection, samples)| Some((*connection, samples.summary()?)))
+            .collect()
+    }
+
+    /// The summary of every connection, for the metrics.
+    pub fn all(&self) -> impl Iterator<Item = (PeerId, ConnectionId, Summary)> + '_ {
+        self.connections.iter().filter_map(|(connection, samples)| {
+            Some((samples.peer_id, *connection, samples.summary()?))
+        })
+    }
+}
+
+/// The average of the current round trip times, for the status line.
+impl fmt::Display for RttStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let current = self
+            .connections
+            .values()
+            .filter_map(|samples| samples.rtts.back())
+            .collect::<Vec<_>>();
+        if current.is_empty() {
+            return write!(f, "rtt unknown");
+        }
+        let avg = current.iter().copied().sum::<Duration>() / current.len() as u32;
+        write!(
+            f,
+            "rtt {} ms avg over {} connections",
+            avg.as_millis(),
+            current.len()
+        )
+    }
+}
+
+/// The average round trip time of a direct connection next to the relayed one it replaced.
+#[derive(Clone, Copy, Debug)]
+pub struct Comparison {
+    pub peer_id: PeerId,
+    pub direct: Duration,
+    pub relayed: Duration,
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direct = self.direct.as_secs_f64().max(f64::EPSILON);
+        let relayed = self.relayed.as_secs_f64().max(f64::EPSILON);
+        write!(
+            f,
+            "direct path to {}: {}ms (was {}ms via relay, ",
+            self.peer_id,
+            self.direct.as_millis(),
+            self.relayed.as_millis()
+        )?;
+        if direct <= relayed {
+            write!(f, "{:.1}x faster)", relayed / direct)
+        } else {
+            write!(f, "{:.1}x slower)", direct / relayed)
+        }
+    }
+}
+
+/// Compares the round trip time of each peer's direct connection with its relayed one once the
+/// direct connection has a few pings. The relayed time is kept after the relayed connection
+/// closes, which it may well do before the direct connection has been pinged enough.
+#[derive(Default)]
+pub struct PathComparisons {
+    relayed: HashMap<PeerId, Duration>,
+    compared: HashMap<ConnectionId, Comparison>,
+}
+
+impl PathComparisons {
+    /// Call after `rtt_stats` recorded a ping of `connection`. Returns the comparison the first
+    /// time the direct connection has enough samples.
+    pub fn on_ping(
+        &mut self,
+        peer_id: PeerId,
+        connection: ConnectionId,
+        path: TransportPath,
+        rtt_stats: &RttStats,
+    ) -> Option<Comparison> {
+        let summary = rtt_stats.summary(connection)?;
+        if let TransportPath::Relayed(_) = path {
+            self.relayed.insert(peer_id, summary.avg);
+            return None;
+        }
+        if self.compared.contains_key(&connection) || rtt_stats.count(connection) < COMPARE_AFTER {
+            return None;
+        }
+        let comparison = Comparison {
+            peer_id,
+            direct: summary.avg,
+            relayed: *self.relayed.get(&peer_id)?,
+        };
+        self.compared.insert(connection, comparison);
+        Some(comparison)
+    }
+
+    /// Every comparison of this session, one line each.
+    pub fn lines(&self) -> Vec<String> {
+        self.compared.values().map(|c| c.to_string()).collect()
+    }
+}
@@ -0,0 +1,133 @@
+use crate::console::say;
+use libp2p::{
+    gossipsub::{self, PeerScoreParams, PeerScoreThresholds, TopicScoreParams},
+    PeerId,
+};
+use std::collections::HashMap;
+use std::fmt;
+
+/// The few peer scoring knobs exposed on the command line. Everything else keeps gossipsub's
+/// defaults.
+#[derive(Clone, Copy, Debug)]
+pub struct ScoreConfig {
+    /// Weight of each subscribed topic in a peer's score.
+    pub topic_weight: f64,
+    /// Penalty weight per message failing validation, must be negative.
+    pub invalid_message_penalty: f64,
+    /// Score below which a peer is graylisted and all its messages are ignored. The gossip and
+    /// publish thresholds are derived from it.
+    pub graylist_threshold: f64,
+}
+
+impl ScoreConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.topic_weight < 0.0 {
+            return Err(format!(
+                "score topic weight must not be negative, got {}",
+                self.topic_weight
+            ));
+        }
+        if self.invalid_message_penalty >= 0.0 {
+            return Err(format!(
+                "invalid message penalty must be negative, got {}",
+                self.invalid_message_penalty
+            ));
+        }
+        if self.graylist_threshold >= 0.0 {
+            return Err(format!(
+                "graylist threshold must be negative, got {}",
+                self.graylist_threshold
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn topic_params(&self) -> TopicScoreParams {
+        TopicScoreParams {
+            topic_weight: self.topic_weight,
+            invalid_message_deliveries_weight: self.invalid_message_penalty,
+            ..Default::default()
+        }
+    }
+
+    /// Peer score parameters without any topics, those are added as they are subscribed to.
+    pub fn params(&self) -> PeerScoreParams {
+        PeerScoreParams::default()
+    }
+
+    pub fn thresholds(&self) -> PeerScoreThresholds {
+        PeerScoreThresholds {
+            gossip_threshold: self.graylist_threshold / 8.0,
+            publish_threshold: self.graylist_threshold / 2.0,
+            graylist_threshold: self.graylist_threshold,
+            ..Default::default()
+        }
+    }
+}
+
+/// Where a peer's score stands relative to the thresholds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Standing {
+    Graylisted,
+    NoPublish,
+    NoGossip,
+    Good,
+}
+
+impl fmt::Display for Standing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Standing::Graylisted => write!(f, "graylisted, ignoring all its messages"),
+            Standing::NoPublish => write!(f, "below the publish threshold, not publishing to it"),
+            Standing::NoGossip => write!(f, "below the gossip threshold, not gossiping with it"),
+            Standing::Good => write!(f, "back above all thresholds"),
+        }
+    }
+}
+
+/// Gossipsub enforces the thresholds on its own but emits no events for it, so the peer scores
+/// are polled and every change in standing is printed.
+pub struct ScoreWatch {
+    thresholds: PeerScoreThresholds,
+    standings: HashMap<PeerId, Standing>,
+}
+
+impl ScoreWatch {
+    pub fn new(config: &ScoreConfig) -> Self {
+        ScoreWatch {
+            thresholds: config.thresholds(),
+            standings: HashMap::new(),
+        }
+    }
+
+    pub fn poll(&mut self, gossipsub: &gossipsub::Behaviour) {
+        let scores = gossipsub
+            .all_peers()
+            .filter_map(|(peer_id, _)| Some((*peer_id, gossipsub.peer_score(peer_id)?)))
+            .collect::<Vec<_>>();
+        self.standings
+            .retain(|peer_id, _| scores.iter().any(|(p, _)| p == peer_id));
+        for (peer_id, score) in scores {
+            let standing = self.standing(score);
+            let previous = self
+                .standings
+                .insert(peer_id, standing)
+                .unwrap_or(Standing::Good);
+            if standing != previous {
+                say!("Peer {peer_id} scored {score:.2}: {standing}");
+            }
+        }
+    }
+
+    fn standing(&self, score: f64) -> Standing {
+        if score < self.thresholds.graylist_threshold {
+            Standing::Graylisted
+        } else if score < self.thresholds.publish_threshold {
+            Standing::NoPublish
+        } else if score < self.thresholds.gossip_threshold {
+            Standing::NoGossip
+        } else {
+            Standing::Good
+        }
+    }
+}
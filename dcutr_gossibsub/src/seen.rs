@@ -0,0 +1,138 @@
+use crate::validation::Rejection;
+use libp2p::{gossipsub::MessageId, PeerId};
+use std::collections::{HashSet, VecDeque};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// How often the changed set is written to disk.
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The ids of the messages accepted lately, kept in the data dir so the messages the mesh still
+/// gossips right after a restart aren't shown and logged a second time. Gossipsub's own
+/// duplicate cache starts empty and can't be filled, so these are turned away in validation.
+/// At most `capacity` ids are kept, none older than `max_age`.
+pub struct SeenMessages {
+    path: Option<PathBuf>,
+    /// Ids with the unix time in seconds they were accepted at, oldest first.
+    order: VecDeque<(String, u64)>,
+    ids: HashSet<String>,
+    capacity: usize,
+    max_age: Duration,
+    dirty: bool,
+    saved: Instant,
+}
+
+impl SeenMessages {
+    pub fn load(
+        dir: Option<&Path>,
+        peer_id: &PeerId,
+        capacity: usize,
+        max_age: Duration,
+    ) -> Result<Self, Box<dyn Error>> {
+        let path = dir.map(|dir| dir.join(format!("seen-messages-{peer_id}.json")));
+        let order = match &path {
+            Some(path) if path.exists() => {
+                let contents = fs::read(path)
+                    .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+                serde_json::from_slice(&contents).unwrap_or_else(|e| {
+                    warn!("Ignoring unreadable seen messages {}: {e}", path.display());
+                    VecDeque::new()
+                })
+            }
+            _ => VecDeque::new(),
+        };
+        let mut seen = SeenMessages {
+            path,
+            ids: HashSet::new(),
+            order,
+            capacity,
+            max_age,
+            dirty: false,
+            saved: Instant::now(),
+        };
+        let loaded = seen.order.len();
+        seen.purge(now_secs());
+        seen.ids = seen.order.iter().map(|(id, _)| id.clone()).collect();
+        if loaded > 0 {
+            info!(
+                "Loaded {} seen message ids, {} expired",
+                seen.order.len(),
+                loaded - seen.order.len()
+            );
+        }
+        Ok(seen)
+    }
+
+    /// Turns away a message accepted before, most likely before a restart.
+    pub fn check(&self, id: &MessageId) -> Result<(), Rejection> {
+        match self.ids.contains(&id.to_string()) {
+            true => Err(Rejection {
+                kind: "seen",
+                reason: "already seen".to_string(),
+                penalize: false,
+            }),
+            false => Ok(()),
+        }
+    }
+
+    /// Remembers an accepted message.
+    pub fn insert(&mut self, id: &MessageId) {
+        let id = id.to_string();
+        if !self.ids.insert(id.clone()) {
+            return;
+        }
+        self.order.push_back((id, now_secs()));
+        self.purge(now_secs());
+        self.dirty = true;
+    }
+
+    pub fn contains(&self, id: &MessageId) -> bool {
+        self.ids.contains(&id.to_string())
+    }
+
+    /// Writes the set to disk if it changed and the last save is long enough ago.
+    pub fn save_due(&mut self, now: Instant) {
+        if now.duration_since(self.saved) >= SAVE_INTERVAL {
+            self.save();
+            self.saved = now;
+        }
+    }
+
+    pub fn save(&mut self) {
+        let path = match &self.path {
+            Some(path) if self.dirty => path,
+            _ => return,
+        };
+        let contents = serde_json::to_vec(&self.order).expect("seen messages serialize to JSON");
+        if let Err(e) = fs::write(path, contents) {
+            warn!(
+                "Failed to save the seen messages to {}: {e}",
+                path.display()
+            );
+        }
+        self.dirty = false;
+    }
+
+    /// Drops the ids older than the maximum age and the oldest beyond the capacity.
+    fn purge(&mut self, now: u64) {
+        let max_age = self.max_age.as_secs();
+        while let Some((id, at)) = self.order.front() {
+            if self.order.len() <= self.capacity && at + max_age > now {
+                break;
+            }
+            self.ids.remove(id);
+            self.order.pop_front();
+            self.dirty = true;
+        }
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
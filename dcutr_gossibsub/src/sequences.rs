@@ -0,0 +1,177 @@
+use libp2p::{gossipsub::TopicHash, PeerId};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// How often changed sequence state is written to disk.
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How many senders and topics the highest sequence numbers are kept of. The ones heard from
+/// least recently make room for new ones.
+const MAX_SEEN: usize = 4096;
+
+/// How long the highest sequence number of a sender that went silent is kept.
+const SEEN_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+struct Seen {
+    seq: u64,
+    /// Unix time in milliseconds the sequence number was seen at.
+    at: u64,
+    /// Unix time in milliseconds the message was sent at.
+    #[serde(default)]
+    sent_at: u64,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct State {
+    /// Our last sequence number per topic hash.
+    #[serde(default)]
+    sent: HashMap<String, u64>,
+    /// The highest sequence number seen per `<origin> <topic hash>`.
+    #[serde(default)]
+    seen: HashMap<String, Seen>,
+}
+
+/// Messages that went missing between two received ones.
+pub struct Gap {
+    pub missed: u64,
+    /// Unix time in milliseconds the message before the gap was sent at.
+    pub since: u64,
+}
+
+/// Per topic sequence numbers of our chat messages, and the highest ones seen of every other
+/// sender to detect messages gossipsub dropped. Kept in the data dir so a restart neither
+/// resets our numbering nor forgets what was seen.
+pub struct Sequences {
+    path: Option<PathBuf>,
+    state: State,
+    reset_after: Duration,
+    dirty: bool,
+    saved: Instant,
+}
+
+impl Sequences {
+    /// `reset_after` is how long a sender has to be silent before a lower sequence number is
+    /// taken as a restart with a fresh counter rather than a late message.
+    pub fn load(
+        dir: Option<&Path>,
+        peer_id: &PeerId,
+        reset_after: Duration,
+    ) -> Result<Self, Box<dyn Error>> {
+        let path = dir.map(|dir| dir.join(format!("sequences-{peer_id}.json")));
+        let state = match &path {
+            Some(path) if path.exists() => {
+                let contents = fs::read(path)
+                    .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+                serde_json::from_slice(&contents).unwrap_or_else(|e| {
+                    warn!("Ignoring unreadable sequence state {}: {e}", path.display());
+                    State::default()
+                })
+            }
+            _ => State::default(),
+        };
+        Ok(Sequences {
+            path,
+            state,
+            reset_after,
+            dirty: false,
+            saved: Instant::now(),
+        })
+    }
+
+    /// The sequence number of our next chat message on `topic`.
+    pub fn next(&mut self, topic: &TopicHash) -> u64 {
+        let seq = self.state.sent.entry(topic.to_string()).or_default();
+        *seq += 1;
+        self.dirty = true;
+        *seq
+    }
+
+    /// Records sequence number `seq` of `origin` on `topic`, sent at `sent_at`, returning the gap
+    /// before it if messages were skipped. Lower numbers are late arrivals, unless the sender was
+    /// silent for longer than the reset period and started counting anew. `origin` has to be
+    /// verified, anyone could claim numbers for an origin taken from the envelope.
+    pub fn observe(
+        &mut self,
+        origin: &PeerId,
+        topic: &TopicHash,
+        seq: u64,
+        sent_at: u64,
+        now_millis: u64,
+    ) -> Option<Gap> {
+        let key = format!("{origin} {topic}");
+        let reset_after = self.reset_after.as_millis() as u64;
+        let seen = Seen {
+            seq,
+            at: now_millis,
+            sent_at,
+        };
+        let previous = match self.state.seen.get(&key).copied() {
+            Some(previous) => previous,
+            None => {
+                self.make_room(now_millis);
+                self.state.seen.insert(key, seen);
+                self.dirty = true;
+                return None;
+            }
+        };
+        if seq <= previous.seq {
+            if previous.at + reset_after <= now_millis {
+                self.state.seen.insert(key, seen);
+                self.dirty = true;
+            }
+            return None;
+        }
+        self.state.seen.insert(key, seen);
+        self.dirty = true;
+        (seq > previous.seq + 1).then(|| Gap {
+            missed: seq - previous.seq - 1,
+            since: previous.sent_at,
+        })
+    }
+
+    /// Forgets senders silent for longer than [`SEEN_TTL`], and the one heard from least recently
+    /// if that still leaves no room for another.
+    fn make_room(&mut self, now_millis: u64) {
+        let ttl = SEEN_TTL.as_millis() as u64;
+        self.state
+            .seen
+            .retain(|_, seen| seen.at.saturating_add(ttl) > now_millis);
+        if self.state.seen.len() >= MAX_SEEN {
+            let oldest = self
+                .state
+                .seen
+                .iter()
+                .min_by_key(|(_, seen)| seen.at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.state.seen.remove(&oldest);
+            }
+        }
+    }
+
+    /// Writes the state to disk if it changed and the last save is long enough ago.
+    pub fn save_due(&mut self, now: Instant) {
+        if now.duration_since(self.saved) >= SAVE_INTERVAL {
+            self.save();
+            self.saved = now;
+        }
+    }
+
+    pub fn save(&mut self) {
+        let path = match &self.path {
+            Some(path) if self.dirty => path,
+            _ => return,
+        };
+        let state = serde_json::to_vec(&self.state).expect("sequence state serializes to JSON");
+        if let Err(e) = fs::write(path, state) {
+            warn!("Failed to save sequence state to {}: {e}", path.display());
+        }
+        self.dirty = false;
+    }
+}
@@ -0,0 +1,74 @@
+use crate::console::say;
+use futures::{channel::mpsc, FutureExt, StreamExt};
+use libp2p::swarm::{NetworkBehaviour, Swarm};
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// How long closing the connections may take before we leave without waiting for them.
+const GRACE: Duration = Duration::from_secs(5);
+
+/// How long the leaving announcement gets to go out before the connections are closed.
+const ANNOUNCE_WAIT: Duration = Duration::from_millis(500);
+
+/// The exit code after a second signal cut the shutdown short.
+pub const EXIT_INTERRUPTED: u8 = 130;
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// Installs the SIGINT and SIGTERM handler. Every signal comes out of the returned stream, the
+/// first one so the event loop can shut down in order, another one while shutting down to cut
+/// [`close_connections`] short.
+pub fn signals() -> Result<mpsc::UnboundedReceiver<()>, Box<dyn Error>> {
+    let (tx, rx) = mpsc::unbounded();
+    ctrlc::set_handler(move || {
+        if SHUTTING_DOWN.swap(true, Ordering::SeqCst) {
+            say!("Exiting without shutting down");
+        } else {
+            say!("Shutting down, press Ctrl-C again to exit right away");
+        }
+        let _ = tx.unbounded_send(());
+    })
+    .map_err(|e| format!("failed to install the signal handler: {e}"))?;
+    Ok(rx)
+}
+
+/// Marks the shutdown as started, however it was asked for, so a signal from now on exits right
+/// away.
+pub fn begin() {
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+}
+
+/// Lets what was just published go out, then closes every connection and drives the swarm until
+/// they are gone, for at most [`GRACE`]. Returns `false` if a signal from `signals` cut it short,
+/// in which case the process should exit right away with [`EXIT_INTERRUPTED`].
+pub async fn close_connections<B: NetworkBehaviour>(
+    swarm: &mut Swarm<B>,
+    signals: &mut mpsc::UnboundedReceiver<()>,
+) -> bool {
+    let mut announce_wait = futures_timer::Delay::new(ANNOUNCE_WAIT).fuse();
+    let mut grace = futures_timer::Delay::new(GRACE).fuse();
+    let mut closing = false;
+    while swarm.connected_peers().next().is_some() {
+        futures::select!(
+            _ = announce_wait => {
+                let peers = swarm.connected_peers().copied().collect::<Vec<_>>();
+                info!(peers = peers.len(), "Closing connections");
+                for peer_id in peers {
+                    let _ = swarm.disconnect_peer_id(peer_id);
+                }
+                closing = true;
+            },
+            _ = swarm.select_next_some() => {},
+            _ = signals.select_next_some() => return false,
+            _ = grace => {
+                if closing {
+                    warn!("Connections didn't close within {GRACE:?}, leaving anyway");
+                }
+                return true;
+            },
+        );
+    }
+    true
+}
@@ -0,0 +1,150 @@
+use crate::envelope::{Envelope, Kind};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use libp2p::{
+    identity::{Keypair, PublicKey},
+    PeerId,
+};
+use std::fmt;
+
+/// Prefix of the signed bytes, so an envelope signature can't be passed off as one over
+/// anything else signed with the same key.
+const DOMAIN: &[u8] = b"dcutr-gossipsub/envelope/1";
+
+/// Outcome of checking a received envelope's signature.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Verification {
+    /// Signed by the origin, which is also the gossipsub origin of the message.
+    Verified(PeerId),
+    /// Signed by `origin` and republished by `by`, a `--forward` of another client. The body is
+    /// the origin's, but nothing vouches for `forwarded_from` or that it came from the origin.
+    Forwarded { origin: PeerId, by: PeerId },
+    /// The envelope carries no signature, e.g. from a peer on an older version.
+    Unsigned,
+    /// The signature is missing its key, doesn't match or the origin doesn't match the sender.
+    Invalid(String),
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verification::Verified(peer_id) => {
+                let peer_id = peer_id.to_base58();
+                write!(f, "\u{2713}{}", &peer_id[peer_id.len().saturating_sub(6)..])
+            }
+            Verification::Forwarded { origin, by } => {
+                let origin = origin.to_base58();
+                let by = by.to_base58();
+                write!(
+                    f,
+                    "\u{2713}{} via {}",
+                    &origin[origin.len().saturating_sub(6)..],
+                    &by[by.len().saturating_sub(6)..]
+                )
+            }
+            Verification::Unsigned => write!(f, "unverified"),
+            Verification::Invalid(_) => write!(f, "FORGED?"),
+        }
+    }
+}
+
+/// The canonical encoding the signature is computed over: every field except the signature,
+/// length prefixed, independent of the wire format.
+pub fn signing_bytes(envelope: &Envelope) -> Vec<u8> {
+    fn field(out: &mut Vec<u8>, bytes: &[u8]) {
+        out.extend_from_slice(&(bytes.len() as u64).to_be_bytes());
+        out.extend_from_slice(bytes);
+    }
+    let mut out = DOMAIN.to_vec();
+    field(&mut out, envelope.origin.as_bytes());
+    field(&mut out, &envelope.seq.to_be_bytes());
+    field(
+        &mut out,
+        envelope.from_nick.as_deref().unwrap_or_default().as_bytes(),
+    );
+    field(&mut out, &envelope.sent_at.to_be_bytes());
+    let kind: &[u8] = match envelope.kind {
+        Kind::Chat => b"chat",
+        Kind::Presence => b"presence",
+        Kind::Control => b"control",
+    };
+    field(&mut out, kind);
+    field(&mut out, &[envelope.compressed as u8]);
+    // Fields added later are only signed when present, so signatures of older envelopes stay
+    // valid.
+    if let Some(key_id) = &envelope.key_id {
+        field(&mut out, b"key_id");
+        field(&mut out, key_id.as_bytes());
+    }
+    if let Some(topic_seq) = envelope.topic_seq {
+        field(&mut out, b"topic_seq");
+        field(&mut out, &topic_seq.to_be_bytes());
+    }
+    if envelope.replayed {
+        field(&mut out, b"replayed");
+    }
+    if let Some(bridge) = &envelope.bridge {
+        field(&mut out, b"bridge");
+        field(&mut out, bridge.as_bytes());
+    }
+    if let Some(expires_at) = envelope.expires_at {
+        field(&mut out, b"expires_at");
+        field(&mut out, &expires_at.to_be_bytes());
+    }
+    field(&mut out, envelope.body.as_bytes());
+    out
+}
+
+/// Signs `envelope` with `key`, which must be the key of its origin.
+pub fn sign(envelope: &mut Envelope, key: &Keypair) {
+    let signature = key
+        .sign(&signing_bytes(envelope))
+        .expect("ed25519 signing doesn't fail");
+    envelope.signature = Some(STANDARD.encode(signature));
+}
+
+/// Checks that `envelope` was signed by its origin and that the origin is `source`, the peer
+/// gossipsub says published the message. A forwarded envelope is published by whoever forwarded
+/// it, which `forwarded_from` can't prove as it isn't signed: it's only
+/// [`Verification::Forwarded`], never [`Verification::Verified`].
+pub fn verify(envelope: &Envelope, source: Option<PeerId>) -> Verification {
+    let signature = match &envelope.signature {
+        Some(signature) => signature,
+        None => return Verification::Unsigned,
+    };
+    let origin = match envelope.origin.parse::<PeerId>() {
+        Ok(origin) => origin,
+        Err(e) => return Verification::Invalid(format!("invalid origin: {e}")),
+    };
+    let forwarded_by = match source.filter(|s| *s != origin) {
+        Some(source) if envelope.forwarded_from.is_empty() => {
+            return Verification::Invalid(format!(
+                "claims to be from {origin} but was published by {source}"
+            ))
+        }
+        forwarded_by => forwarded_by,
+    };
+    let key = match public_key_of(&origin) {
+        Some(key) => key,
+        None => return Verification::Invalid(format!("no public key inlined in {origin}")),
+    };
+    let signature = match STANDARD.decode(signature) {
+        Ok(signature) => signature,
+        Err(e) => return Verification::Invalid(format!("invalid signature encoding: {e}")),
+    };
+    if !key.verify(&signing_bytes(envelope), &signature) {
+        Verification::Invalid(format!("signature does not match {origin}"))
+    } else if let Some(by) = forwarded_by {
+        Verification::Forwarded { origin, by }
+    } else {
+        Verification::Verified(origin)
+    }
+}
+
+/// Ed25519 peer ids inline the public key instead of hashing it.
+fn public_key_of(peer_id: &PeerId) -> Option<PublicKey> {
+    let multihash = peer_id.as_ref();
+    if multihash.code() != 0 {
+        return None;
+    }
+    PublicKey::from_protobuf_encoding(multihash.digest()).ok()
+}
@@ -0,0 +1,266 @@
+use crate::console::say;
+use crate::envelope;
+use crate::holepunch::HolePunchStats;
+use crate::paths::{ConnectionPaths, TransportPath};
+use libp2p::{gossipsub::IdentTopic, PeerId};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// The topic heartbeats go over, apart from the chat.
+pub const TOPIC: &str = "dcutr-soak";
+
+/// How often the soak report is printed and recorded while running.
+const REPORT_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Heartbeats start with this, followed by the sequence number.
+const MAGIC: &[u8] = b"SOAK1";
+
+/// The heartbeats of one peer.
+struct Heartbeats {
+    received: u64,
+    first_seq: u64,
+    highest_seq: u64,
+    last_at: Instant,
+    longest_gap: Duration,
+    connected: bool,
+    reconnects: u32,
+    /// Set once the peer missed enough heartbeats to try reconnecting, until the next arrives.
+    recovering: bool,
+}
+
+impl Heartbeats {
+    /// Heartbeats the peer sent since the first we got.
+    fn expected(&self) -> u64 {
+        self.highest_seq - self.first_seq + 1
+    }
+
+    fn loss_percent(&self) -> f64 {
+        self.expected().saturating_sub(self.received) as f64 * 100.0 / self.expected() as f64
+    }
+}
+
+/// A record of the soak report, appended to `soak-<peer id>.jsonl` in the data dir.
+#[derive(Serialize)]
+struct Record {
+    timestamp: u64,
+    #[serde(rename = "final")]
+    is_final: bool,
+    uptime_secs: u64,
+    interval_secs: u64,
+    sent: u64,
+    peer_id: String,
+    received: u64,
+    expected: u64,
+    loss_percent: f64,
+    longest_gap_ms: u64,
+    reconnects: u32,
+    hole_punch_attempts: u32,
+    path: Option<&'static str>,
+}
+
+/// `--soak`: publishes a numbered heartbeat every `interval` and tracks those of the other peers,
+/// to learn over a long session whether the connection holds up.
+pub struct Soak {
+    interval: Duration,
+    miss_threshold: u32,
+    started: Instant,
+    next_heartbeat: Instant,
+    next_report: Instant,
+    sent: u64,
+    peers: HashMap<PeerId, Heartbeats>,
+    records: Option<PathBuf>,
+}
+
+impl Soak {
+    /// Records are written to `data_dir` unless it's `None`.
+    pub fn new(
+        interval: Duration,
+        miss_threshold: u32,
+        data_dir: Option<&Path>,
+        local_peer_id: &PeerId,
+    ) -> Result<Self, Box<dyn Error>> {
+        let records = match data_dir {
+            Some(dir) => {
+                fs::create_dir_all(dir)
+                    .map_err(|e| format!("failed to create data dir {}: {e}", dir.display()))?;
+                Some(dir.join(format!("soak-{local_peer_id}.jsonl")))
+            }
+            None => None,
+        };
+        let now = Instant::now();
+        Ok(Soak {
+            interval,
+            miss_threshold,
+            started: now,
+            next_heartbeat: now + interval,
+            next_report: now + REPORT_INTERVAL,
+            sent: 0,
+            peers: HashMap::new(),
+            records,
+        })
+    }
+
+    pub fn topic() -> IdentTopic {
+        IdentTopic::new(TOPIC)
+    }
+
+    /// The next heartbeat to publish, once it's due.
+    pub fn heartbeat(&mut self, now: Instant) -> Option<Vec<u8>> {
+        if now < self.next_heartbeat {
+            return None;
+        }
+        self.next_heartbeat = now + self.interval;
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&self.sent.to_be_bytes());
+        self.sent += 1;
+        Some(data)
+    }
+
+    pub fn on_heartbeat(&mut self, peer_id: PeerId, data: &[u8], now: Instant) {
+        let seq = match data.strip_prefix(MAGIC).and_then(|seq| seq.try_into().ok()) {
+            Some(seq) => u64::from_be_bytes(seq),
+            None => return,
+        };
+        let heartbeats = self.peers.entry(peer_id).or_insert(Heartbeats {
+            received: 0,
+            first_seq: seq,
+            highest_seq: seq,
+            last_at: now,
+            longest_gap: Duration::ZERO,
+            connected: true,
+            reconnects: 0,
+            recovering: false,
+        });
+        if heartbeats.recovering {
+            say!("Heartbeats from {peer_id} are back");
+            heartbeats.recovering = false;
+        }
+        heartbeats.received += 1;
+        heartbeats.first_seq = heartbeats.first_seq.min(seq);
+        heartbeats.highest_seq = heartbeats.highest_seq.max(seq);
+        heartbeats.longest_gap = heartbeats.longest_gap.max(now - heartbeats.last_at);
+        heartbeats.last_at = now;
+    }
+
+    pub fn on_connected(&mut self, peer_id: &PeerId) {
+        if let Some(heartbeats) = self.peers.get_mut(peer_id) {
+            if !heartbeats.connected {
+                heartbeats.connected = true;
+                heartbeats.reconnects += 1;
+            }
+        }
+    }
+
+    /// All connections to the peer are gone.
+    pub fn on_disconnected(&mut self, peer_id: &PeerId) {
+        if let Some(heartbeats) = self.peers.get_mut(peer_id) {
+            heartbeats.connected = false;
+        }
+    }
+
+    /// Peers that just went `miss_threshold` intervals without a heartbeat, to reconnect to.
+    pub fn missed(&mut self, now: Instant) -> Vec<PeerId> {
+        let limit = self.interval * self.miss_threshold;
+        self.peers
+            .iter_mut()
+            .filter(|(_, heartbeats)| !heartbeats.recovering && now - heartbeats.last_at > limit)
+            .map(|(peer_id, heartbeats)| {
+                heartbeats.recovering = true;
+                warn!(%peer_id, missed = self.miss_threshold, "Heartbeats stopped, reconnecting");
+                *peer_id
+            })
+            .collect()
+    }
+
+    /// Prints and records the report every [`REPORT_INTERVAL`].
+    pub fn report_due(
+        &mut self,
+        now: Instant,
+        paths: &ConnectionPaths,
+        holepunch_stats: &HolePunchStats,
+    ) {
+        if now >= self.next_report {
+            self.next_report = now + REPORT_INTERVAL;
+            self.report(false, paths, holepunch_stats);
+        }
+    }
+
+    /// One line per peer, also appended as JSON records to the data dir.
+    pub fn report(
+        &self,
+        is_final: bool,
+        paths: &ConnectionPaths,
+        holepunch_stats: &HolePunchStats,
+    ) {
+        let uptime = self.started.elapsed();
+        say!(
+            "soak{}: up {}s, {} heartbeats sent every {:?}",
+            if is_final { " (final)" } else { "" },
+            uptime.as_secs(),
+            self.sent,
+            self.interval
+        );
+        if self.peers.is_empty() {
+            say!("  no heartbeats received");
+        }
+        for (peer_id, heartbeats) in &self.peers {
+            let path = paths.path(peer_id).map(|path| match path {
+                TransportPath::Direct => "direct",
+                TransportPath::Relayed(_) => "relayed",
+            });
+            let record = Record {
+                timestamp: envelope::unix_millis(),
+                is_final,
+                uptime_secs: uptime.as_secs(),
+                interval_secs: self.interval.as_secs(),
+                sent: self.sent,
+                peer_id: peer_id.to_string(),
+                received: heartbeats.received,
+                expected: heartbeats.expected(),
+                loss_percent: heartbeats.loss_percent(),
+                longest_gap_ms: heartbeats.longest_gap.as_millis() as u64,
+                reconnects: heartbeats.reconnects,
+                hole_punch_attempts: holepunch_stats.attempts(peer_id),
+                path,
+            };
+            say!(
+                "  {peer_id}: {}/{} received ({:.1}% lost), longest gap {:.1}s, {} reconnects, {} hole punch attempts, {}",
+                record.received,
+                record.expected,
+                record.loss_percent,
+                heartbeats.longest_gap.as_secs_f64(),
+                record.reconnects,
+                record.hole_punch_attempts,
+                path.map_or("disconnected".to_string(), |path| format!("{path} path"))
+            );
+            self.append(&record);
+        }
+    }
+
+    fn append(&self, record: &Record) {
+        let path = match &self.records {
+            Some(path) => path,
+            None => return,
+        };
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| {
+                let line = serde_json::to_string(record).expect("record serializes to JSON");
+                writeln!(file, "{line}")
+            });
+        if let Err(e) = written {
+            warn!(
+                "Failed to append the soak report to {}: {e}",
+                path.display()
+            );
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use libp2p::{gossipsub::TopicHash, PeerId};
+use std::collections::{HashMap, HashSet};
+
+/// The peers subscribed to each topic, as far as their subscription messages told us. Peers that
+/// disconnect without unsubscribing are dropped with their connection.
+#[derive(Default)]
+pub struct Subscribers {
+    topics: HashMap<TopicHash, HashSet<PeerId>>,
+}
+
+impl Subscribers {
+    /// Whether the peer wasn't known to be subscribed yet.
+    pub fn on_subscribed(&mut self, peer_id: PeerId, topic: TopicHash) -> bool {
+        self.topics.entry(topic).or_default().insert(peer_id)
+    }
+
+    /// Whether the peer was known to be subscribed.
+    pub fn on_unsubscribed(&mut self, peer_id: &PeerId, topic: &TopicHash) -> bool {
+        let removed = self
+            .topics
+            .get_mut(topic)
+            .map_or(false, |peers| peers.remove(peer_id));
+        self.topics.retain(|_, peers| !peers.is_empty());
+        removed
+    }
+
+    /// The topics the peer was subscribed to.
+    pub fn on_disconnected(&mut self, peer_id: &PeerId) -> Vec<TopicHash> {
+        let left = self
+            .topics
+            .iter_mut()
+            .filter_map(|(topic, peers)| peers.remove(peer_id).then(|| topic.clone()))
+            .collect();
+        self.topics.retain(|_, peers| !peers.is_empty());
+        left
+    }
+
+    pub fn count(&self, topic: &TopicHash) -> usize {
+        self.topics.get(topic).map_or(0, HashSet::len)
+    }
+}
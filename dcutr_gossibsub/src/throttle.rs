@@ -0,0 +1,77 @@
+use crate::validation::Rate;
+use std::time::{Duration, Instant};
+
+/// A token bucket, pacing our own publishes and those of each peer in
+/// [`PeerRateLimiter`](crate::validation::PeerRateLimiter). Each message takes a token, tokens
+/// refill at `rate` up to `burst`, which is also what the bucket starts with. The clock is passed
+/// in.
+#[derive(Clone, Debug)]
+pub struct TokenBucket {
+    rate: Rate,
+    burst: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: Rate, burst: u32, now: Instant) -> Self {
+        let burst = f64::from(burst.max(1));
+        TokenBucket {
+            rate,
+            burst,
+            tokens: burst,
+            refilled: now,
+        }
+    }
+
+    /// A bucket with a quarter of the rate and burst of this one, at least a token.
+    pub fn quarter(&self, now: Instant) -> Self {
+        let burst = (self.burst / 4.0).max(1.0);
+        TokenBucket {
+            rate: Rate {
+                per_second: self.rate.per_second / 4.0,
+            },
+            burst,
+            tokens: burst,
+            refilled: now,
+        }
+    }
+
+    pub fn rate(&self) -> Rate {
+        self.rate
+    }
+
+    /// Takes a token if there is one.
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return true;
+        }
+        false
+    }
+
+    /// Puts back a token taken for a message that didn't go out after all.
+    pub fn give_back(&mut self) {
+        self.tokens = (self.tokens + 1.0).min(self.burst);
+    }
+
+    /// How long until the next token is there, zero if one is.
+    pub fn ready_in(&self, now: Instant) -> Duration {
+        let tokens = self.tokens_at(now);
+        if tokens >= 1.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((1.0 - tokens) / self.rate.per_second)
+    }
+
+    fn tokens_at(&self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        (self.tokens + elapsed * self.rate.per_second).min(self.burst)
+    }
+
+    fn refill(&mut self, now: Instant) {
+        self.tokens = self.tokens_at(now);
+        self.refilled = self.refilled.max(now);
+    }
+}
@@ -0,0 +1,94 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use libp2p::{core::multiaddr::Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// What every ticket starts with, followed by the format version.
+const PREFIX: &str = "p2ptkt";
+/// The format version written, and the only one read.
+const VERSION: u32 = 1;
+/// Multibase code of base64url without padding, which the CBOR payload is encoded in.
+const MULTIBASE_BASE64URL: char = 'u';
+
+/// Everything a dialer needs to reach a listening client, in one string to pass around:
+/// `p2ptkt1u<base64url of the CBOR payload>`. The version in front lets the payload change
+/// later, a client rejects versions it doesn't know instead of misreading them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ticket {
+    pub peer_id: PeerId,
+    /// Relays the listener holds a reservation with, the dialer uses the first.
+    pub relays: Vec<Multiaddr>,
+    pub topic: Option<String>,
+}
+
+/// The payload of version 1, with the peer id and addresses in their binary forms.
+#[derive(Serialize, Deserialize)]
+struct Payload {
+    peer_id: Vec<u8>,
+    relays: Vec<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    topic: Option<String>,
+}
+
+impl fmt::Display for Ticket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let payload = Payload {
+            peer_id: self.peer_id.to_bytes(),
+            relays: self.relays.iter().map(|addr| addr.to_vec()).collect(),
+            topic: self.topic.clone(),
+        };
+        let mut data = Vec::new();
+        ciborium::ser::into_writer(&payload, &mut data).expect("ticket serializes to CBOR");
+        write!(
+            f,
+            "{PREFIX}{VERSION}{MULTIBASE_BASE64URL}{}",
+            URL_SAFE_NO_PAD.encode(data)
+        )
+    }
+}
+
+impl FromStr for Ticket {
+    type Err = String;
+    fn from_str(ticket: &str) -> Result<Self, Self::Err> {
+        let rest = ticket
+            .trim()
+            .strip_prefix(PREFIX)
+            .ok_or_else(|| format!("not a ticket, tickets start with '{PREFIX}'"))?;
+        let digits = rest.chars().take_while(char::is_ascii_digit).count();
+        let version = rest[..digits]
+            .parse::<u32>()
+            .map_err(|_| "the ticket has no version".to_string())?;
+        if version != VERSION {
+            return Err(format!(
+                "ticket version {version} isn't supported, this client reads version {VERSION}"
+            ));
+        }
+        let encoded = rest[digits..]
+            .strip_prefix(MULTIBASE_BASE64URL)
+            .ok_or("the ticket's payload has an unknown encoding")?;
+        let data = URL_SAFE_NO_PAD
+            .decode(encoded)
+            .map_err(|e| format!("the ticket is damaged: {e}"))?;
+        let payload: Payload = ciborium::de::from_reader(&data[..])
+            .map_err(|e| format!("the ticket is damaged: {e}"))?;
+        let peer_id = PeerId::from_bytes(&payload.peer_id)
+            .map_err(|e| format!("the ticket's peer id is invalid: {e}"))?;
+        let relays = payload
+            .relays
+            .into_iter()
+            .map(|addr| {
+                Multiaddr::try_from(addr)
+                    .map_err(|e| format!("the ticket's relay address is invalid: {e}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if relays.is_empty() {
+            return Err("the ticket names no relay".to_string());
+        }
+        Ok(Ticket {
+            peer_id,
+            relays,
+            topic: payload.topic,
+        })
+    }
+}
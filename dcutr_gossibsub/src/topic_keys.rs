@@ -0,0 +1,163 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// PBKDF2-HMAC-SHA256 rounds for turning a passphrase into a topic key.
+const KDF_ROUNDS: u32 = 100_000;
+
+/// Prefix of the KDF salt, followed by the topic name so each topic gets its own key.
+const KDF_SALT: &[u8] = b"dcutr-chat/topic-key/1/";
+
+/// A symmetric key for a topic's message bodies.
+#[derive(Clone)]
+pub struct TopicKey {
+    /// Short hex tag of the key, sent along so receivers know which key to try.
+    pub id: String,
+    key: [u8; 32],
+}
+
+impl TopicKey {
+    fn new(key: [u8; 32]) -> Self {
+        let id = Sha256::digest(key)[..4]
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        TopicKey { id, key }
+    }
+
+    pub fn from_passphrase(passphrase: &str, topic: &str) -> Self {
+        let mut salt = KDF_SALT.to_vec();
+        salt.extend_from_slice(topic.as_bytes());
+        let mut key = [0; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), &salt, KDF_ROUNDS, &mut key);
+        TopicKey::new(key)
+    }
+
+    /// ChaCha20-Poly1305 under a fresh random nonce, returned as base64 of the nonce followed by
+    /// the ciphertext.
+    pub fn seal(&self, aad: &[u8], plaintext: &[u8]) -> String {
+        let nonce = rand::thread_rng().gen::<[u8; 12]>();
+        let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&self.key))
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .expect("encrypting in memory");
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        STANDARD.encode(sealed)
+    }
+
+    pub fn open(&self, aad: &[u8], sealed: &str) -> Result<Vec<u8>, String> {
+        let sealed = STANDARD
+            .decode(sealed)
+            .map_err(|e| format!("invalid ciphertext encoding: {e}"))?;
+        if sealed.len() < 12 {
+            return Err("ciphertext too short".to_string());
+        }
+        let (nonce, ciphertext) = sealed.split_at(12);
+        ChaCha20Poly1305::new(Key::from_slice(&self.key))
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| format!("does not decrypt with key {}", self.id))
+    }
+}
+
+/// The keys of each topic, from `--topic-key-file` and `--topic-passphrase`. A topic can have
+/// several keys so that they can be rotated: all of them decrypt, the newest encrypts. Keys from
+/// the file count as older than those from passphrases, and within each source later ones are
+/// newer.
+///
+/// The KDF is slow on purpose, so passphrase keys are derived up front for the topics we are in
+/// and never for a topic name a peer sends.
+#[derive(Default)]
+pub struct TopicKeys {
+    files: HashMap<String, Vec<TopicKey>>,
+    passphrases: Vec<String>,
+    /// Keys per topic name, file keys first.
+    keys: HashMap<String, Vec<TopicKey>>,
+}
+
+impl TopicKeys {
+    /// Reads a key file of `<topic> <base64 of a 32 byte key>` lines and derives the passphrase
+    /// keys of `topics`.
+    pub fn load(
+        path: Option<&Path>,
+        passphrases: Vec<String>,
+        topics: &[String],
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut files = HashMap::<String, Vec<TopicKey>>::new();
+        if let Some(path) = path {
+            let contents = fs::read_to_string(path)
+                .map_err(|e| format!("failed to read topic key file {}: {e}", path.display()))?;
+            for (n, line) in contents.lines().map(str::trim).enumerate() {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let key = line
+                    .split_once(' ')
+                    .and_then(|(topic, key)| Some((topic, STANDARD.decode(key.trim()).ok()?)))
+                    .and_then(|(topic, key)| Some((topic, <[u8; 32]>::try_from(key).ok()?)));
+                let (topic, key) = key.ok_or_else(|| {
+                    format!(
+                        "{}:{}: expected '<topic> <base64 of 32 bytes>'",
+                        path.display(),
+                        n + 1
+                    )
+                })?;
+                files
+                    .entry(topic.to_string())
+                    .or_default()
+                    .push(TopicKey::new(key));
+            }
+        }
+        let mut keys = TopicKeys {
+            files,
+            passphrases,
+            keys: HashMap::new(),
+        };
+        for topic in topics {
+            keys.derive(topic);
+        }
+        Ok(keys)
+    }
+
+    /// Derives the passphrase keys of `topic`, a topic joined after startup.
+    pub fn derive(&mut self, topic: &str) {
+        if self.keys.contains_key(topic) {
+            return;
+        }
+        let mut keys = self.files.get(topic).cloned().unwrap_or_default();
+        keys.extend(
+            self.passphrases
+                .iter()
+                .map(|passphrase| TopicKey::from_passphrase(passphrase, topic)),
+        );
+        self.keys.insert(topic.to_string(), keys);
+    }
+
+    /// The keys of `topic`, oldest first. Empty if the topic isn't encrypted, only those of the
+    /// key file if it wasn't derived.
+    pub fn of(&self, topic: &str) -> &[TopicKey] {
+        self.keys
+            .get(topic)
+            .or_else(|| self.files.get(topic))
+            .map_or(&[], Vec::as_slice)
+    }
+}
@@ -0,0 +1,238 @@
+use crate::console::say;
+use libp2p::gossipsub::{
+    self, IdentTopic, PublishError, Sha256Topic, SubscriptionError, TopicHash, TopicScoreParams,
+};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// How topic names are turned into the topic hashes used on the wire. Peers only see each
+/// other's messages when they use the same hashing for a topic name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TopicHashing {
+    /// The name itself is the hash (`IdentTopic`), what this client has always used.
+    Identity,
+    /// The base64 SHA-256 of the name (`Sha256Topic`), used e.g. by some go-libp2p applications.
+    Sha256,
+}
+
+impl FromStr for TopicHashing {
+    type Err = String;
+    fn from_str(hashing: &str) -> Result<Self, Self::Err> {
+        match hashing {
+            "identity" => Ok(TopicHashing::Identity),
+            "sha256" => Ok(TopicHashing::Sha256),
+            _ => Err("Expected either 'identity' or 'sha256'".to_string()),
+        }
+    }
+}
+
+impl TopicHashing {
+    pub fn hash(&self, name: &str) -> TopicHash {
+        match self {
+            TopicHashing::Identity => IdentTopic::new(name).hash(),
+            TopicHashing::Sha256 => Sha256Topic::new(name).hash(),
+        }
+    }
+
+    fn subscribe(
+        &self,
+        gossipsub: &mut gossipsub::Behaviour,
+        name: &str,
+    ) -> Result<bool, SubscriptionError> {
+        match self {
+            TopicHashing::Identity => gossipsub.subscribe(&IdentTopic::new(name)),
+            TopicHashing::Sha256 => gossipsub.subscribe(&Sha256Topic::new(name)),
+        }
+    }
+
+    fn unsubscribe(
+        &self,
+        gossipsub: &mut gossipsub::Behaviour,
+        name: &str,
+    ) -> Result<bool, PublishError> {
+        match self {
+            TopicHashing::Identity => gossipsub.unsubscribe(&IdentTopic::new(name)),
+            TopicHashing::Sha256 => gossipsub.unsubscribe(&Sha256Topic::new(name)),
+        }
+    }
+
+    fn set_score_params(
+        &self,
+        gossipsub: &mut gossipsub::Behaviour,
+        name: &str,
+        params: TopicScoreParams,
+    ) -> Result<(), &'static str> {
+        match self {
+            TopicHashing::Identity => gossipsub.set_topic_params(IdentTopic::new(name), params),
+            TopicHashing::Sha256 => gossipsub.set_topic_params(Sha256Topic::new(name), params),
+        }
+    }
+}
+
+/// The topics we are subscribed to, by hash, and the one stdin input is published to.
+pub struct Topics {
+    hashing: TopicHashing,
+    topics: HashMap<TopicHash, String>,
+    selected: Option<TopicHash>,
+    score_params: Option<TopicScoreParams>,
+}
+
+impl Topics {
+    pub fn new(names: &[String], hashing: TopicHashing) -> Self {
+        let mut topics = Topics {
+            hashing,
+            topics: HashMap::new(),
+            selected: None,
+            score_params: None,
+        };
+        for name in names {
+            let hash = hashing.hash(name);
+            topics.selected.get_or_insert_with(|| hash.clone());
+            topics.topics.insert(hash, name.clone());
+        }
+        topics
+    }
+
+    pub fn subscribe_all(
+        &self,
+        gossipsub: &mut gossipsub::Behaviour,
+    ) -> Result<(), SubscriptionError> {
+        for name in self.topics.values() {
+            self.hashing.subscribe(gossipsub, name)?;
+        }
+        Ok(())
+    }
+
+    /// Leaves every topic on the way out, without forgetting them.
+    pub fn unsubscribe_all(&self, gossipsub: &mut gossipsub::Behaviour) {
+        for name in self.topics.values() {
+            if let Err(e) = self.hashing.unsubscribe(gossipsub, name) {
+                say!("Failed to unsubscribe from topic '{name}': {e:?}");
+            }
+        }
+    }
+
+    /// Scores peers on the current topics and every topic joined later with `params`. Peer
+    /// scoring must already be enabled on `gossipsub`.
+    pub fn set_score_params(
+        &mut self,
+        params: TopicScoreParams,
+        gossipsub: &mut gossipsub::Behaviour,
+    ) -> Result<(), String> {
+        for name in self.topics.values() {
+            self.hashing
+                .set_score_params(gossipsub, name, params.clone())
+                .map_err(|e| format!("failed to score topic '{name}': {e}"))?;
+        }
+        self.score_params = Some(params);
+        Ok(())
+    }
+
+    pub fn join(&mut self, name: &str, gossipsub: &mut gossipsub::Behaviour) {
+        let hash = self.hashing.hash(name);
+        if self.topics.contains_key(&hash) {
+            say!("Already subscribed to topic '{name}'. {}", self.summary());
+            return;
+        }
+        if let Err(e) = self.hashing.subscribe(gossipsub, name) {
+            say!("Failed to join topic '{name}': {e:?}");
+            return;
+        }
+        if let Some(params) = &self.score_params {
+            if let Err(e) = self
+                .hashing
+                .set_score_params(gossipsub, name, params.clone())
+            {
+                say!("Failed to score topic '{name}': {e}");
+            }
+        }
+        self.selected.get_or_insert_with(|| hash.clone());
+        self.topics.insert(hash, name.to_string());
+        say!("Joined topic '{name}'. {}", self.summary());
+    }
+
+    pub fn leave(&mut self, name: &str, gossipsub: &mut gossipsub::Behaviour) {
+        let hash = self.hashing.hash(name);
+        if self.topics.remove(&hash).is_none() {
+            say!("Not subscribed to topic '{name}'. {}", self.summary());
+            return;
+        }
+        if let Err(e) = self.hashing.unsubscribe(gossipsub, name) {
+            say!("Failed to leave topic '{name}': {e:?}");
+        }
+        if self.selected.as_ref() == Some(&hash) {
+            self.selected = self.topics.keys().min_by_key(|h| h.as_str()).cloned();
+            if self.selected.is_none() {
+                say!("Warning: no topics left, input will not be published until you /join one");
+            }
+        }
+        say!("Left topic '{name}'. {}", self.summary());
+    }
+
+    /// The current subscriptions and the topic input goes to.
+    pub fn summary(&self) -> String {
+        let mut names = self.topics.values().cloned().collect::<Vec<_>>();
+        names.sort();
+        match &self.selected {
+            Some(selected) => format!(
+                "Subscribed to: {} (publishing to '{}')",
+                names.join(", "),
+                self.name(selected)
+            ),
+            None => "Not subscribed to any topic".to_string(),
+        }
+    }
+
+    pub fn hash(&self, name: &str) -> TopicHash {
+        self.hashing.hash(name)
+    }
+
+    pub fn hashes(&self) -> impl Iterator<Item = &TopicHash> {
+        self.topics.keys()
+    }
+
+    /// The topic input lines without an `@topic` go to.
+    pub fn selected(&self) -> Option<&TopicHash> {
+        self.selected.as_ref()
+    }
+
+    pub fn contains(&self, hash: &TopicHash) -> bool {
+        self.topics.contains_key(hash)
+    }
+
+    /// Human readable name for a topic hash from the wire, in either hashing mode.
+    pub fn name(&self, hash: &TopicHash) -> String {
+        match self.topics.get(hash) {
+            Some(name) => name.clone(),
+            None => hash.to_string(),
+        }
+    }
+
+    /// Resolves where an input line goes. `@topic message` publishes to `topic` once, a bare
+    /// `@topic` selects it for the following lines and returns `Ok(None)`.
+    pub fn route<'a>(&mut self, line: &'a str) -> Result<Option<(TopicHash, &'a str)>, String> {
+        let rest = match line.strip_prefix('@') {
+            Some(rest) => rest,
+            None => {
+                return match &self.selected {
+                    Some(hash) => Ok(Some((hash.clone(), line))),
+                    None => Err("not subscribed to any topic, message not sent".to_string()),
+                }
+            }
+        };
+
+        let (name, message) = rest.split_once(' ').unwrap_or((rest, ""));
+        let hash = self.hashing.hash(name);
+        if !self.topics.contains_key(&hash) {
+            return Err(format!(
+                "not subscribed to topic '{name}', message not sent"
+            ));
+        }
+        if message.is_empty() {
+            say!("Publishing to topic '{name}'");
+            self.selected = Some(hash);
+            return Ok(None);
+        }
+        Ok(Some((hash, message)))
+    }
+}
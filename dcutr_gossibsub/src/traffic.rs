@@ -0,0 +1,36 @@
+use std::fmt;
+
+/// Chat traffic counters for `/stats`.
+#[derive(Default)]
+pub struct Traffic {
+    sent_messages: u64,
+    sent_bytes: u64,
+    received_messages: u64,
+    received_bytes: u64,
+}
+
+impl Traffic {
+    pub fn on_sent(&mut self, bytes: usize) {
+        self.sent_messages += 1;
+        self.sent_bytes += bytes as u64;
+    }
+
+    pub fn on_received(&mut self, bytes: usize) {
+        self.received_messages += 1;
+        self.received_bytes += bytes as u64;
+    }
+}
+
+/// Formats as `traffic: 3 sent (1.2 KiB), 5 received (2.0 KiB)`.
+impl fmt::Display for Traffic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "traffic: {} sent ({:.1} KiB), {} received ({:.1} KiB)",
+            self.sent_messages,
+            self.sent_bytes as f64 / 1024.0,
+            self.received_messages,
+            self.received_bytes as f64 / 1024.0
+        )
+    }
+}
@@ -0,0 +1,1027 @@
+use crate::codec::{read_json, write_json};
+use crate::console::say;
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::{
+    channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    stream::{FusedStream, Stream, StreamExt},
+    AsyncRead, AsyncWrite,
+};
+use libp2p::{
+    core::upgrade::ProtocolName,
+    request_response::{self, RequestId, ResponseChannel},
+    PeerId,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::mpsc as std_mpsc;
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::warn;
+
+/// Bytes of file data per chunk request.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Largest file transfer message we read, a chunk in base64 plus the JSON around it.
+const MAX_MESSAGE_SIZE: usize = CHUNK_SIZE * 2;
+
+/// How many times in a row a chunk failing its CRC is sent again before the transfer is given up.
+const MAX_RESENDS: u32 = 3;
+
+/// Minimum time between two progress lines of a transfer.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long the `.part` and `.checkpoint` files of a transfer nobody resumed are kept.
+const PART_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often the download directory is swept for `.part` files older than [`PART_MAX_AGE`].
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Requests of the sending side. A transfer is an offer, the chunks in order and a final done.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FileRequest {
+    Offer {
+        file_id: String,
+        name: String,
+        size: u64,
+        /// Hex SHA-256 of the whole file.
+        sha256: String,
+    },
+    Chunk {
+        file_id: String,
+        offset: u64,
+        /// Base64 of the chunk's bytes.
+        data: String,
+        /// CRC-32 of the chunk's bytes, so corruption shows at the chunk rather than only in the
+        /// whole-file hash at the end.
+        crc32: u32,
+    },
+    Done {
+        file_id: String,
+    },
+}
+
+impl FileRequest {
+    pub fn file_id(&self) -> &str {
+        match self {
+            FileRequest::Offer { file_id, .. }
+            | FileRequest::Chunk { file_id, .. }
+            | FileRequest::Done { file_id } => file_id,
+        }
+    }
+}
+
+/// Whether `file_id` has the shape of ours, the first 32 hex digits of the file's SHA-256. It
+/// goes into the names of the `.part` and `.checkpoint` files, so nothing else may get through.
+fn is_file_id(file_id: &str) -> bool {
+    file_id.len() == 32
+        && file_id
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FileResponse {
+    /// The offer is accepted, chunks are to be sent starting at `offset`. When resuming a
+    /// transfer, `checkpoint` is the hex SHA-256 of the `offset` bytes the receiver already has.
+    Accepted {
+        offset: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        checkpoint: Option<String>,
+    },
+    Rejected {
+        reason: String,
+    },
+    Ack,
+    /// The chunk at `offset` arrived corrupted and has to be sent again.
+    Resend {
+        offset: u64,
+    },
+    Complete,
+    Failed {
+        reason: String,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct FileProtocol;
+
+impl ProtocolName for FileProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        b"/dcutr-chat/file/1.0.0"
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct FileCodec;
+
+#[async_trait]
+impl request_response::Codec for FileCodec {
+    type Protocol = FileProtocol;
+    type Request = FileRequest;
+    type Response = FileResponse;
+
+    async fn read_request<T>(&mut self, _: &FileProtocol, io: &mut T) -> io::Result<FileRequest>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_json(io, MAX_MESSAGE_SIZE).await
+    }
+
+    async fn read_response<T>(&mut self, _: &FileProtocol, io: &mut T) -> io::Result<FileResponse>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_json(io, MAX_MESSAGE_SIZE).await
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &FileProtocol,
+        io: &mut T,
+        request: FileRequest,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_json(io, &request).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &FileProtocol,
+        io: &mut T,
+        response: FileResponse,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_json(io, &response).await
+    }
+}
+
+pub fn behaviour() -> request_response::Behaviour<FileCodec> {
+    request_response::Behaviour::new(
+        FileCodec,
+        [(FileProtocol, request_response::ProtocolSupport::Full)],
+        request_response::Config::default(),
+    )
+}
+
+fn hex(hasher: Sha256) -> String {
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Hashes the first `len` bytes of `file`, read in chunks rather than loaded into memory. The
+/// file is left positioned at `len`.
+fn hash_prefix(file: &mut File, len: u64) -> io::Result<Sha256> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut left = len;
+    while left > 0 {
+        let n = file.read(&mut buf[..CHUNK_SIZE.min(left as usize)])?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        hasher.update(&buf[..n]);
+        left -= n as u64;
+    }
+    Ok(hasher)
+}
+
+/// Progress of a partially received file, kept next to it so the transfer can resume after the
+/// stream or either process died.
+#[derive(Serialize, Deserialize)]
+struct Checkpoint {
+    size: u64,
+    sha256: String,
+    written: u64,
+    /// Hex SHA-256 of the `written` bytes.
+    prefix_sha256: String,
+}
+
+impl Checkpoint {
+    fn load(path: &Path) -> Option<Self> {
+        serde_json::from_slice(&fs::read(path).ok()?).ok()
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_vec(self)?)
+    }
+}
+
+/// Tracks bytes over time for progress lines like `42% (1.3 MiB/s)`.
+struct Progress {
+    size: u64,
+    started: Instant,
+    reported: Instant,
+}
+
+impl Progress {
+    fn new(size: u64) -> Self {
+        let now = Instant::now();
+        Progress {
+            size,
+            started: now,
+            reported: now,
+        }
+    }
+
+    /// A progress line if the last one is long enough ago.
+    fn due(&mut self, done: u64) -> Option<String> {
+        let now = Instant::now();
+        if now.duration_since(self.reported) < PROGRESS_INTERVAL {
+            return None;
+        }
+        self.reported = now;
+        Some(self.line(done))
+    }
+
+    fn line(&self, done: u64) -> String {
+        let percent = if self.size == 0 {
+            100
+        } else {
+            done * 100 / self.size
+        };
+        let rate = done as f64 / self.started.elapsed().as_secs_f64().max(0.001) / 1024.0 / 1024.0;
+        format!("{percent}% ({rate:.2} MiB/s)")
+    }
+}
+
+struct Outgoing {
+    peer_id: PeerId,
+    name: String,
+    file: File,
+    size: u64,
+    sent: u64,
+    /// Resends of the current chunk.
+    resends: u32,
+    progress: Progress,
+    /// When the peer last answered.
+    active: Instant,
+}
+
+struct Incoming {
+    peer_id: PeerId,
+    name: String,
+    size: u64,
+    sha256: String,
+    part_path: PathBuf,
+    checkpoint_path: PathBuf,
+    final_path: PathBuf,
+    file: File,
+    written: u64,
+    /// Hash of the `written` bytes so far.
+    hasher: Sha256,
+    progress: Progress,
+    /// When the peer last sent a request.
+    active: Instant,
+}
+
+impl Incoming {
+    fn save_checkpoint(&self) -> io::Result<()> {
+        Checkpoint {
+            size: self.size,
+            sha256: self.sha256.clone(),
+            written: self.written,
+            prefix_sha256: hex(self.hasher.clone()),
+        }
+        .save(&self.checkpoint_path)
+    }
+
+    /// Drops everything received so far.
+    fn restart(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.written = 0;
+        self.hasher = Sha256::new();
+        self.save_checkpoint()
+    }
+}
+
+/// File transfers in both directions. The sender drives a transfer one request at a time and
+/// sends the next one when the previous is acknowledged. At most `max_incoming` files are
+/// received at once, and transfers the other side stopped driving for `timeout` are dropped.
+/// Their partial downloads stay around for [`PART_MAX_AGE`] to be resumed.
+pub struct Transfers {
+    download_dir: PathBuf,
+    overwrite: bool,
+    max_size: u64,
+    max_incoming: usize,
+    timeout: Duration,
+    outgoing: HashMap<String, Outgoing>,
+    incoming: HashMap<String, Incoming>,
+    requests: HashMap<RequestId, String>,
+    swept: Option<Instant>,
+}
+
+impl Transfers {
+    pub fn new(
+        download_dir: PathBuf,
+        overwrite: bool,
+        max_size: u64,
+        max_incoming: usize,
+        timeout: Duration,
+    ) -> Self {
+        Transfers {
+            download_dir,
+            overwrite,
+            max_size,
+            max_incoming,
+            timeout,
+            outgoing: HashMap::new(),
+            incoming: HashMap::new(),
+            requests: HashMap::new(),
+            swept: None,
+        }
+    }
+
+    /// Starts sending the file at `path` to `peer_id`, returning the offer to send. The file id
+    /// is derived from the content, so offering the same file again resumes where an interrupted
+    /// transfer stopped.
+    pub fn offer(&mut self, peer_id: PeerId, path: &Path) -> Result<FileRequest, String> {
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| format!("{} is not a file", path.display()))?
+            .to_string();
+        let mut file =
+            File::open(path).map_err(|e| format!("failed to open {}: {e}", path.display()))?;
+        let size = file
+            .metadata()
+            .map_err(|e| format!("failed to stat {}: {e}", path.display()))?
+            .len();
+        let sha256 = hash_prefix(&mut file, size)
+            .map(hex)
+            .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+        let file_id = sha256[..32].to_string();
+        self.outgoing.insert(
+            file_id.clone(),
+            Outgoing {
+                peer_id,
+                name: name.clone(),
+                file,
+                size,
+                sent: 0,
+                resends: 0,
+                progress: Progress::new(size),
+                active: Instant::now(),
+            },
+        );
+        Ok(FileRequest::Offer {
+            file_id,
+            name,
+            size,
+            sha256,
+        })
+    }
+
+    pub fn on_sent(&mut self, request_id: RequestId, file_id: String) {
+        self.requests.insert(request_id, file_id);
+    }
+
+    /// Handles the response to one of our requests and returns the next request of that
+    /// transfer, if any.
+    pub fn on_response(
+        &mut self,
+        request_id: &RequestId,
+        response: FileResponse,
+    ) -> Option<(PeerId, FileRequest)> {
+        let file_id = self.requests.remove(request_id)?;
+        let transfer = self.outgoing.get_mut(&file_id)?;
+        transfer.active = Instant::now();
+        match response {
+            FileResponse::Accepted { offset: 0, .. } => {
+                transfer.sent = 0;
+                say!("{} accepted '{}', sending", transfer.peer_id, transfer.name);
+            }
+            FileResponse::Accepted { offset, checkpoint } => {
+                let ours = if offset <= transfer.size {
+                    hash_prefix(&mut transfer.file, offset).map(hex).ok()
+                } else {
+                    None
+                };
+                if ours.is_some() && ours == checkpoint {
+                    transfer.sent = offset;
+                    say!(
+                        "{} has {}% of '{}', resuming",
+                        transfer.peer_id,
+                        offset * 100 / transfer.size.max(1),
+                        transfer.name
+                    );
+                } else {
+                    transfer.sent = 0;
+                    say!(
+                        "Warning: the {offset} bytes of '{}' {} has don't match ours, restarting \
+                         from zero",
+                        transfer.name,
+                        transfer.peer_id
+                    );
+                }
+            }
+            FileResponse::Ack => {
+                transfer.resends = 0;
+                if let Some(line) = transfer.progress.due(transfer.sent) {
+                    say!("Sending '{}': {line}", transfer.name);
+                }
+            }
+            FileResponse::Resend { offset } => {
+                transfer.resends += 1;
+                if transfer.resends > MAX_RESENDS || offset > transfer.size {
+                    say!(
+                        "Sending '{}' to {} failed: the chunk at offset {offset} keeps arriving \
+                         corrupted",
+                        transfer.name,
+                        transfer.peer_id
+                    );
+                    self.outgoing.remove(&file_id);
+                    return None;
+                }
+                warn!(
+                    "Chunk at offset {offset} of '{}' arrived corrupted at {}, sending it again",
+                    transfer.name, transfer.peer_id
+                );
+                transfer.sent = offset;
+            }
+            FileResponse::Complete => {
+                say!(
+                    "Sent '{}' to {}: {}",
+                    transfer.name,
+                    transfer.peer_id,
+                    transfer.progress.line(transfer.size)
+                );
+                self.outgoing.remove(&file_id);
+                return None;
+            }
+            FileResponse::Rejected { reason } | FileResponse::Failed { reason } => {
+                say!(
+                    "Sending '{}' to {} failed: {reason}",
+                    transfer.name,
+                    transfer.peer_id
+                );
+                self.outgoing.remove(&file_id);
+                return None;
+            }
+        }
+        let peer_id = transfer.peer_id;
+        match next_chunk(&file_id, transfer) {
+            Ok(request) => Some((peer_id, request)),
+            Err(e) => {
+                say!("Sending '{}' to {peer_id} failed: {e}", transfer.name);
+                self.outgoing.remove(&file_id);
+                None
+            }
+        }
+    }
+
+    pub fn on_failure(&mut self, request_id: &RequestId, error: &dyn std::fmt::Display) {
+        if let Some(transfer) = self
+            .requests
+            .remove(request_id)
+            .and_then(|file_id| self.outgoing.remove(&file_id))
+        {
+            say!(
+                "Sending '{}' to {} failed: {error}. /send it again to resume",
+                transfer.name,
+                transfer.peer_id
+            );
+        }
+    }
+
+    /// Handles a request of a sending peer.
+    pub fn on_request(&mut self, peer_id: PeerId, request: FileRequest) -> FileResponse {
+        match request {
+            FileRequest::Offer {
+                file_id,
+                name,
+                size,
+                sha256,
+            } => match self.accept(peer_id, &file_id, &name, size, sha256) {
+                Ok(incoming) => FileResponse::Accepted {
+                    offset: incoming.written,
+                    checkpoint: (incoming.written > 0).then(|| hex(incoming.hasher.clone())),
+                },
+                Err(reason) => {
+                    say!("Refused file '{name}' from {peer_id}: {reason}");
+                    FileResponse::Rejected { reason }
+                }
+            },
+            FileRequest::Chunk {
+                file_id,
+                offset,
+                data,
+                crc32,
+            } => match self.write_chunk(peer_id, &file_id, offset, &data, crc32) {
+                Ok(true) => FileResponse::Ack,
+                Ok(false) => FileResponse::Resend { offset },
+                Err(reason) => self.fail(&file_id, reason),
+            },
+            FileRequest::Done { file_id } => match self.finish(peer_id, &file_id) {
+                Ok(()) => FileResponse::Complete,
+                Err(reason) => self.fail(&file_id, reason),
+            },
+        }
+    }
+
+    fn accept(
+        &mut self,
+        peer_id: PeerId,
+        file_id: &str,
+        name: &str,
+        size: u64,
+        sha256: String,
+    ) -> Result<&Incoming, String> {
+        if !is_file_id(file_id) {
+            return Err("invalid file id".to_string());
+        }
+        // Only the file name counts, a peer must not pick where it ends up.
+        let name = Path::new(name)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .filter(|n| !n.starts_with('.'))
+            .ok_or_else(|| "invalid file name".to_string())?
+            .to_string();
+        if size > self.max_size {
+            return Err(format!(
+                "file of {size} bytes exceeds the limit of {} bytes",
+                self.max_size
+            ));
+        }
+        match self.incoming.get(file_id) {
+            Some(transfer) if transfer.peer_id != peer_id => {
+                return Err(format!("{} is sending this file already", transfer.peer_id));
+            }
+            Some(_) => {}
+            None if self.incoming.len() >= self.max_incoming => {
+                return Err(format!(
+                    "{} files are being received already",
+                    self.incoming.len()
+                ));
+            }
+            None => {}
+        }
+        let final_path = self.download_dir.join(&name);
+        if final_path.exists() && !self.overwrite {
+            return Err(format!("{} already exists", final_path.display()));
+        }
+        fs::create_dir_all(&self.download_dir)
+            .map_err(|e| format!("failed to create the download directory: {e}"))?;
+        let part_path = self.download_dir.join(format!(".{name}.{file_id}.part"));
+        let checkpoint_path = self
+            .download_dir
+            .join(format!(".{name}.{file_id}.checkpoint"));
+        // A transfer interrupted earlier, in this process or a previous one, is picked up
+        // from its checkpoint.
+        self.incoming.remove(file_id);
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .open(&part_path)
+            .map_err(|e| format!("failed to create {}: {e}", part_path.display()))?;
+        let mut incoming = Incoming {
+            peer_id,
+            name,
+            size,
+            sha256,
+            part_path,
+            checkpoint_path,
+            final_path,
+            written: 0,
+            hasher: Sha256::new(),
+            progress: Progress::new(size),
+            active: Instant::now(),
+            file,
+        };
+        let resumable = Checkpoint::load(&incoming.checkpoint_path)
+            .filter(|c| c.size == size && c.sha256 == incoming.sha256 && c.written <= size);
+        if let Some(checkpoint) = resumable {
+            match hash_prefix(&mut incoming.file, checkpoint.written) {
+                Ok(hasher) if hex(hasher.clone()) == checkpoint.prefix_sha256 => {
+                    incoming.written = checkpoint.written;
+                    incoming.hasher = hasher;
+                }
+                _ => say!(
+                    "Warning: the partial download of '{}' is damaged, starting over",
+                    incoming.name
+                ),
+            }
+        }
+        let resumed = incoming.written;
+        if resumed == 0 {
+            incoming
+                .restart()
+                .map_err(|e| format!("failed to write: {e}"))?;
+        } else {
+            incoming
+                .file
+                .set_len(resumed)
+                .and_then(|_| incoming.file.seek(SeekFrom::Start(resumed)))
+                .map_err(|e| format!("failed to write: {e}"))?;
+            say!(
+                "Resuming '{}' from {}: {resumed} of {size} bytes already here",
+                incoming.name,
+                incoming.peer_id
+            );
+        }
+        say!(
+            "Receiving '{}' ({size} bytes) from {}",
+            incoming.name,
+            incoming.peer_id
+        );
+        Ok(self.incoming.entry(file_id.to_string()).or_insert(incoming))
+    }
+
+    /// Writes a chunk, or returns false without writing it when it fails its CRC.
+    fn write_chunk(
+        &mut self,
+        peer_id: PeerId,
+        file_id: &str,
+        offset: u64,
+        data: &str,
+        crc32: u32,
+    ) -> Result<bool, String> {
+        let transfer = self
+            .incoming
+            .get_mut(file_id)
+            .filter(|t| t.peer_id == peer_id)
+            .ok_or_else(|| "unknown transfer".to_string())?;
+        transfer.active = Instant::now();
+        if offset == 0 && transfer.written > 0 {
+            say!(
+                "Warning: {peer_id} restarted '{}' from zero, dropping the {} bytes received",
+                transfer.name,
+                transfer.written
+            );
+            transfer
+                .restart()
+                .map_err(|e| format!("failed to write: {e}"))?;
+        }
+        if offset != transfer.written {
+            return Err(format!(
+                "chunk at offset {offset}, expected {}",
+                transfer.written
+            ));
+        }
+        let data = STANDARD
+            .decode(data)
+            .map_err(|e| format!("invalid chunk: {e}"))?;
+        if crc32fast::hash(&data) != crc32 {
+            warn!(
+                "Chunk at offset {offset} of '{}' from {peer_id} failed its CRC",
+                transfer.name
+            );
+            return Ok(false);
+        }
+        if transfer.written + data.len() as u64 > transfer.size {
+            return Err("more data than announced".to_string());
+        }
+        transfer
+            .file
+            .write_all(&data)
+            .map_err(|e| format!("failed to write: {e}"))?;
+        transfer.written += data.len() as u64;
+        transfer.hasher.update(&data);
+        transfer
+            .save_checkpoint()
+            .map_err(|e| format!("failed to save the checkpoint: {e}"))?;
+        if let Some(line) = transfer.progress.due(transfer.written) {
+            say!("Receiving '{}': {line}", transfer.name);
+        }
+        Ok(true)
+    }
+
+    fn finish(&mut self, peer_id: PeerId, file_id: &str) -> Result<(), String> {
+        let transfer = self
+            .incoming
+            .get_mut(file_id)
+            .filter(|t| t.peer_id == peer_id)
+            .ok_or_else(|| "unknown transfer".to_string())?;
+        if transfer.written != transfer.size {
+            return Err(format!(
+                "got {} of {} bytes",
+                transfer.written, transfer.size
+            ));
+        }
+        transfer
+            .file
+            .sync_all()
+            .map_err(|e| format!("failed to write: {e}"))?;
+        // The file is hashed again as it is on disk rather than trusting the running hash of
+        // what was received.
+        say!("Receiving '{}': verifying…", transfer.name);
+        let sha256 = hash_prefix(&mut transfer.file, transfer.size)
+            .map(hex)
+            .map_err(|e| format!("failed to read the received file: {e}"))?;
+        if sha256 != transfer.sha256 {
+            return Err(format!(
+                "SHA-256 mismatch, expected {} but the received file has {sha256}",
+                transfer.sha256
+            ));
+        }
+        let transfer = self.incoming.remove(file_id).expect("present");
+        fs::rename(&transfer.part_path, &transfer.final_path)
+            .map_err(|e| format!("failed to move the file into place: {e}"))?;
+        let _ = fs::remove_file(&transfer.checkpoint_path);
+        say!(
+            "Received '{}' from {peer_id} into {}, SHA-256 verified: {}",
+            transfer.name,
+            transfer.final_path.display(),
+            transfer.progress.line(transfer.size)
+        );
+        Ok(())
+    }
+
+    /// Drops transfers the other side stopped driving, and every [`SWEEP_INTERVAL`] the partial
+    /// downloads nobody resumed within [`PART_MAX_AGE`].
+    pub fn expire(&mut self, now: Instant) {
+        let timeout = self.timeout;
+        self.outgoing.retain(|_, transfer| {
+            let alive = now.duration_since(transfer.active) < timeout;
+            if !alive {
+                say!(
+                    "Sending '{}' to {} failed: no answer for {timeout:?}. /send it again to \
+                     resume",
+                    transfer.name,
+                    transfer.peer_id
+                );
+            }
+            alive
+        });
+        self.incoming.retain(|_, transfer| {
+            let alive = now.duration_since(transfer.active) < timeout;
+            if !alive {
+                say!(
+                    "Receiving '{}' from {} stalled for {timeout:?}, giving up until it is sent \
+                     again",
+                    transfer.name,
+                    transfer.peer_id
+                );
+            }
+            alive
+        });
+        let outgoing = &self.outgoing;
+        self.requests
+            .retain(|_, file_id| outgoing.contains_key(file_id));
+        if self
+            .swept
+            .map_or(true, |swept| now.duration_since(swept) >= SWEEP_INTERVAL)
+        {
+            self.swept = Some(now);
+            self.sweep();
+        }
+    }
+
+    /// Deletes the `.part` and `.checkpoint` files in the download directory that haven't been
+    /// written to for [`PART_MAX_AGE`].
+    fn sweep(&self) {
+        let entries = match fs::read_dir(&self.download_dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        let in_use = |path: &Path| {
+            self.incoming
+                .values()
+                .any(|t| t.part_path == path || t.checkpoint_path == path)
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if !name.starts_with('.')
+                || !(name.ends_with(".part") || name.ends_with(".checkpoint"))
+                || in_use(&path)
+            {
+                continue;
+            }
+            let stale = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                .map_or(false, |age| age >= PART_MAX_AGE);
+            if stale {
+                match fs::remove_file(&path) {
+                    Ok(()) => warn!("Deleted the abandoned partial download {}", path.display()),
+                    Err(e) => warn!("Failed to delete {}: {e}", path.display()),
+                }
+            }
+        }
+    }
+
+    fn fail(&mut self, file_id: &str, reason: String) -> FileResponse {
+        if let Some(transfer) = self.incoming.remove(file_id) {
+            let _ = fs::remove_file(&transfer.part_path);
+            let _ = fs::remove_file(&transfer.checkpoint_path);
+            say!(
+                "Receiving '{}' from {} failed: {reason}",
+                transfer.name,
+                transfer.peer_id
+            );
+        }
+        FileResponse::Failed { reason }
+    }
+}
+
+/// The next chunk of `transfer`, or the final done once everything was sent.
+fn next_chunk(file_id: &str, transfer: &mut Outgoing) -> io::Result<FileRequest> {
+    if transfer.sent >= transfer.size {
+        return Ok(FileRequest::Done {
+            file_id: file_id.to_string(),
+        });
+    }
+    let mut buf = vec![0; CHUNK_SIZE.min((transfer.size - transfer.sent) as usize)];
+    transfer.file.seek(SeekFrom::Start(transfer.sent))?;
+    transfer.file.read_exact(&mut buf)?;
+    let offset = transfer.sent;
+    transfer.sent += buf.len() as u64;
+    Ok(FileRequest::Chunk {
+        file_id: file_id.to_string(),
+        offset,
+        crc32: crc32fast::hash(&buf),
+        data: STANDARD.encode(buf),
+    })
+}
+
+enum Job {
+    Offer {
+        peer_id: PeerId,
+        path: PathBuf,
+    },
+    Sent {
+        request_id: RequestId,
+        file_id: String,
+    },
+    Request {
+        peer_id: PeerId,
+        request: FileRequest,
+        channel: ResponseChannel<FileResponse>,
+    },
+    Response {
+        request_id: RequestId,
+        response: FileResponse,
+    },
+    Failure {
+        request_id: RequestId,
+        error: String,
+    },
+    Expire(Instant),
+}
+
+/// What [`TransferWorker`] needs sent to a peer.
+pub enum TransferOutput {
+    /// A request of one of our transfers.
+    Request(PeerId, FileRequest),
+    /// The answer to a request of the peer.
+    Response(PeerId, ResponseChannel<FileResponse>, FileResponse),
+}
+
+/// Runs [`Transfers`] on a thread of its own, so hashing files and writing chunks never holds up
+/// the event loop. The methods queue the work, the requests and responses to send come out of
+/// the stream in the order the work was queued.
+pub struct TransferWorker {
+    jobs: std_mpsc::Sender<Job>,
+    outputs: UnboundedReceiver<TransferOutput>,
+}
+
+impl TransferWorker {
+    pub fn start(transfers: Transfers) -> io::Result<Self> {
+        let (jobs, queued) = std_mpsc::channel();
+        let (outputs, receiver) = mpsc::unbounded();
+        thread::Builder::new()
+            .name("file-transfers".to_string())
+            .spawn(move || work(transfers, queued, outputs))?;
+        Ok(TransferWorker {
+            jobs,
+            outputs: receiver,
+        })
+    }
+
+    /// Starts sending the file at `path` to `peer_id`, see [`Transfers::offer`].
+    pub fn offer(&self, peer_id: PeerId, path: PathBuf) {
+        self.queue(Job::Offer { peer_id, path });
+    }
+
+    pub fn on_sent(&self, request_id: RequestId, request: &FileRequest) {
+        self.queue(Job::Sent {
+            request_id,
+            file_id: request.file_id().to_string(),
+        });
+    }
+
+    pub fn on_request(
+        &self,
+        peer_id: PeerId,
+        request: FileRequest,
+        channel: ResponseChannel<FileResponse>,
+    ) {
+        self.queue(Job::Request {
+            peer_id,
+            request,
+            channel,
+        });
+    }
+
+    pub fn on_response(&self, request_id: RequestId, response: FileResponse) {
+        self.queue(Job::Response {
+            request_id,
+            response,
+        });
+    }
+
+    pub fn on_failure(&self, request_id: RequestId, error: &dyn std::fmt::Display) {
+        self.queue(Job::Failure {
+            request_id,
+            error: error.to_string(),
+        });
+    }
+
+    pub fn expire(&self, now: Instant) {
+        self.queue(Job::Expire(now));
+    }
+
+    fn queue(&self, job: Job) {
+        if self.jobs.send(job).is_err() {
+            warn!("The file transfer thread stopped, no longer transferring files");
+        }
+    }
+}
+
+impl Stream for TransferWorker {
+    type Item = TransferOutput;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.outputs.poll_next_unpin(cx)
+    }
+}
+
+impl FusedStream for TransferWorker {
+    fn is_terminated(&self) -> bool {
+        self.outputs.is_terminated()
+    }
+}
+
+fn work(
+    mut transfers: Transfers,
+    jobs: std_mpsc::Receiver<Job>,
+    outputs: UnboundedSender<TransferOutput>,
+) {
+    for job in jobs {
+        let output = match job {
+            Job::Offer { peer_id, path } => match transfers.offer(peer_id, &path) {
+                Ok(request) => Some(TransferOutput::Request(peer_id, request)),
+                Err(e) => {
+                    say!("Can't send {}: {e}", path.display());
+                    None
+                }
+            },
+            Job::Sent {
+                request_id,
+                file_id,
+            } => {
+                transfers.on_sent(request_id, file_id);
+                None
+            }
+            Job::Request {
+                peer_id,
+                request,
+                channel,
+            } => {
+                let response = transfers.on_request(peer_id, request);
+                Some(TransferOutput::Response(peer_id, channel, response))
+            }
+            Job::Response {
+                request_id,
+                response,
+            } => transfers
+                .on_response(&request_id, response)
+                .map(|(peer_id, request)| TransferOutput::Request(peer_id, request)),
+            Job::Failure { request_id, error } => {
+                transfers.on_failure(&request_id, &error);
+                None
+            }
+            Job::Expire(now) => {
+                transfers.expire(now);
+                None
+            }
+        };
+        if let Some(output) = output {
+            if outputs.unbounded_send(output).is_err() {
+                return;
+            }
+        }
+    }
+}
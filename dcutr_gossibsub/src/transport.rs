@@ -0,0 +1,165 @@
+use crate::bandwidth::Bandwidth;
+use crate::paths::TransportPath;
+use crate::resolver::DnsSettings;
+use either::Either::{Left, Right};
+use futures::future::{self, Either, FutureExt, TryFutureExt};
+use futures::{AsyncRead, AsyncWrite};
+use libp2p::{
+    core::{
+        muxing::StreamMuxerBox,
+        transport::{Boxed, MemoryTransport, OrTransport, Transport},
+        upgrade,
+    },
+    dns, identity, noise,
+    pnet::{PnetConfig, PnetError, PnetOutput, PreSharedKey},
+    relay, tcp, yamux, PeerId,
+};
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Why a connection failed to come up, telling a failed security handshake apart from failing
+/// to reach the peer at all. It's what the transports' `io::Error`s carry.
+#[derive(Debug)]
+pub enum UpgradeFailure {
+    /// The pre-shared key exchange, the negotiation of noise or noise itself failed. Between
+    /// peers of a private network that means the other side uses another key or none.
+    Handshake(Box<dyn Error + Send + Sync>),
+    /// Anything else, from the socket up to the multiplexer.
+    Other(Box<dyn Error + Send + Sync>),
+}
+
+impl UpgradeFailure {
+    /// The failure an `io::Error` of the transports carries, if it does.
+    pub fn of(error: &io::Error) -> Option<&UpgradeFailure> {
+        error.get_ref()?.downcast_ref()
+    }
+
+    /// Sorts the errors of the upgrades stacked onto a transport of `T` errors by [`build`] and
+    /// [`memory`]: the pnet handshake wraps the transport's errors, noise those and yamux noise's.
+    fn classify<T, S, M>(
+        error: either::Either<either::Either<either::Either<T, PnetError>, S>, M>,
+    ) -> Self
+    where
+        T: Error + Send + Sync + 'static,
+        S: Error + Send + Sync + 'static,
+        M: Error + Send + Sync + 'static,
+    {
+        match error {
+            Left(Left(Left(e))) => UpgradeFailure::Other(e.into()),
+            Left(Left(Right(e))) | Left(Right(e)) => UpgradeFailure::Handshake(e.into()),
+            Right(e) => UpgradeFailure::Other(e.into()),
+        }
+    }
+}
+
+impl fmt::Display for UpgradeFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpgradeFailure::Handshake(e) => write!(f, "handshake failed: {e}"),
+            UpgradeFailure::Other(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for UpgradeFailure {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            UpgradeFailure::Handshake(e) | UpgradeFailure::Other(e) => Some(e.as_ref()),
+        }
+    }
+}
+
+/// Runs the pnet handshake on `socket` with a pre-shared key, passes it on as it is without.
+fn private<S>(
+    socket: S,
+    psk: Option<PreSharedKey>,
+) -> impl future::Future<Output = Result<Either<PnetOutput<S>, S>, PnetError>>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    match psk {
+        Some(psk) => PnetConfig::new(psk)
+            .handshake(socket)
+            .map_ok(Either::Left)
+            .left_future(),
+        None => future::ok::<_, PnetError>(Either::Right(socket)).right_future(),
+    }
+}
+
+/// TCP with DNS resolution as `dns` says next to the relay client transport, authenticated with
+/// noise and multiplexed with yamux. With a pre-shared key only peers of the same private network
+/// can connect. The traffic of each connection is counted in `bandwidth`.
+pub async fn build(
+    local_key: &identity::Keypair,
+    relay_transport: relay::client::Transport,
+    psk: Option<PreSharedKey>,
+    dns: &DnsSettings,
+    bandwidth: &Bandwidth,
+) -> Result<Boxed<(PeerId, StreamMuxerBox)>, Box<dyn Error>> {
+    let tcp_config = tcp::Config::default().port_reuse(true);
+    let (resolver_config, resolver_opts) = dns.resolver()?;
+    #[cfg(feature = "tokio")]
+    let tcp_transport = dns::TokioDnsConfig::custom(
+        tcp::tokio::Transport::new(tcp_config),
+        resolver_config,
+        resolver_opts,
+    )?;
+    #[cfg(not(feature = "tokio"))]
+    let tcp_transport = dns::DnsConfig::custom(
+        tcp::async_io::Transport::new(tcp_config),
+        resolver_config,
+        resolver_opts,
+    )
+    .await?;
+    let transport = OrTransport::new(relay_transport, tcp_transport)
+        // The pnet layer sits below noise, on both the direct and the relayed connections.
+        .and_then(move |socket, _| private(socket, psk))
+        .upgrade(upgrade::Version::V1Lazy)
+        .authenticate(
+            noise::Config::new(local_key).expect("Signing libp2p-noise static DH keypair failed."),
+        )
+        .multiplex(yamux::Config::default())
+        .map({
+            let bandwidth = bandwidth.clone();
+            move |(peer_id, muxer), endpoint| {
+                let path = TransportPath::of(&endpoint);
+                (
+                    peer_id,
+                    StreamMuxerBox::new(bandwidth.count(muxer, peer_id, path)),
+                )
+            }
+        })
+        .map_err(UpgradeFailure::classify)
+        .boxed();
+    Ok(transport)
+}
+
+/// [`MemoryTransport`] in place of TCP and the relay, with the same pnet, noise and yamux
+/// upgrades on top, so the behaviours can be run in-process without sockets, e.g. in tests. Nodes
+/// listen on `/memory/<port>`, `0` picks a free one.
+pub fn memory(
+    local_key: &identity::Keypair,
+    psk: Option<PreSharedKey>,
+    bandwidth: &Bandwidth,
+) -> Boxed<(PeerId, StreamMuxerBox)> {
+    MemoryTransport::default()
+        .and_then(move |socket, _| private(socket, psk))
+        .upgrade(upgrade::Version::V1Lazy)
+        .authenticate(
+            noise::Config::new(local_key).expect("Signing libp2p-noise static DH keypair failed."),
+        )
+        .multiplex(yamux::Config::default())
+        .map({
+            let bandwidth = bandwidth.clone();
+            move |(peer_id, muxer), endpoint| {
+                let path = TransportPath::of(&endpoint);
+                (
+                    peer_id,
+                    StreamMuxerBox::new(bandwidth.count(muxer, peer_id, path)),
+                )
+            }
+        })
+        .map_err(UpgradeFailure::classify)
+        .boxed()
+}
@@ -0,0 +1,274 @@
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use futures::{channel::mpsc, stream::BoxStream, StreamExt};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Direction, Layout},
+    widgets::{Block, Borders, List, ListItem, Paragraph},
+    Frame, Terminal,
+};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::{self, Stdout, Write};
+use std::panic;
+use std::sync::mpsc::{self as std_mpsc, Receiver, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How long the UI waits for a key before looking for updates from the swarm.
+const FRAME: Duration = Duration::from_millis(100);
+
+/// Lines kept in the message and log panes.
+const SCROLLBACK: usize = 1000;
+
+/// What the swarm side sends the UI.
+enum UiEvent {
+    Message(String),
+    Log(String),
+    Peers(Vec<String>),
+    Status(String),
+    Quit,
+}
+
+/// The terminal UI of `--tui`, running on a thread of its own. It only talks to the swarm through
+/// channels: output and state updates go in, typed lines come out. The terminal is restored when
+/// this is dropped, and on panics.
+pub struct Tui {
+    events: Sender<UiEvent>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Tui {
+    /// Takes over the terminal, returning the UI and the lines typed into it.
+    pub fn start() -> Result<(Self, BoxStream<'static, String>), Box<dyn Error>> {
+        enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        if let Err(e) = execute!(stdout, EnterAlternateScreen) {
+            restore();
+            return Err(e.into());
+        }
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            restore();
+            previous(info);
+        }));
+        let terminal = Terminal::new(CrosstermBackend::new(stdout))?;
+
+        let (events, rx) = std_mpsc::channel();
+        let (lines, typed) = mpsc::unbounded();
+        let thread = thread::spawn(move || {
+            let result = run(terminal, rx, lines);
+            restore();
+            if let Err(e) = result {
+                eprintln!("Terminal UI failed: {e}");
+            }
+        });
+        let output = events.clone();
+        crate::console::set_sink(move |line| {
+            output
+                .send(UiEvent::Message(line))
+                .map_err(|e| e.to_string())
+        });
+        let tui = Tui {
+            events,
+            thread: Some(thread),
+        };
+        Ok((tui, typed.boxed()))
+    }
+
+    /// Where log records go, into the log pane.
+    pub fn log_writer(&self) -> LogWriter {
+        LogWriter {
+            events: self.events.clone(),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Refreshes the peer sidebar and the status bar.
+    pub fn update(&self, peers: Vec<String>, status: String) {
+        let _ = self.events.send(UiEvent::Peers(peers));
+        let _ = self.events.send(UiEvent::Status(status));
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        let _ = self.events.send(UiEvent::Quit);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Sends complete lines written to it to the log pane.
+pub struct LogWriter {
+    events: Sender<UiEvent>,
+    buffer: Vec<u8>,
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line = self.buffer.drain(..=end).collect::<Vec<_>>();
+            let line = String::from_utf8_lossy(&line).trim_end().to_string();
+            let _ = self.events.send(UiEvent::Log(line));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn restore() {
+    let _ = disable_raw_mode();
+    let _ = execute!(io::stdout(), LeaveAlternateScreen, cursor::Show);
+}
+
+#[derive(Default)]
+struct State {
+    messages: VecDeque<String>,
+    logs: VecDeque<String>,
+    peers: Vec<String>,
+    status: String,
+    input: String,
+    /// How many lines the message pane is scrolled up from the bottom.
+    scroll: usize,
+}
+
+fn run(
+    mut terminal: Terminal<CrosstermBackend<Stdout>>,
+    events: Receiver<UiEvent>,
+    lines: mpsc::UnboundedSender<String>,
+) -> io::Result<()> {
+    let mut state = State::default();
+    loop {
+        loop {
+            match events.try_recv() {
+                Ok(UiEvent::Message(line)) => push(&mut state.messages, line),
+                Ok(UiEvent::Log(line)) => push(&mut state.logs, line),
+                Ok(UiEvent::Peers(peers)) => state.peers = peers,
+                Ok(UiEvent::Status(status)) => state.status = status,
+                Ok(UiEvent::Quit) | Err(TryRecvError::Disconnected) => return Ok(()),
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+        // Drawing resizes to the current terminal size, so resize events need nothing else.
+        terminal.draw(|frame| draw(frame, &state))?;
+        if !event::poll(FRAME)? {
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if let Some(line) = on_key(&mut state, key) {
+                push(&mut state.messages, format!("> {line}"));
+                if lines.unbounded_send(line).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+fn push(lines: &mut VecDeque<String>, line: String) {
+    lines.push_back(line);
+    if lines.len() > SCROLLBACK {
+        lines.pop_front();
+    }
+}
+
+/// Edits the input line, returning it once it is entered.
+fn on_key(state: &mut State, key: KeyEvent) -> Option<String> {
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    match key.code {
+        KeyCode::Char('c' | 'd') if ctrl => return Some("/quit".to_string()),
+        KeyCode::Char('u') if ctrl => state.input.clear(),
+        KeyCode::Char('w') if ctrl => {
+            let kept = state.input.trim_end().rfind(' ').map_or(0, |i| i + 1);
+            state.input.truncate(kept);
+        }
+        KeyCode::Char(c) => state.input.push(c),
+        KeyCode::Backspace => {
+            state.input.pop();
+        }
+        KeyCode::Esc => state.input.clear(),
+        KeyCode::PageUp => state.scroll = (state.scroll + 10).min(state.messages.len()),
+        KeyCode::PageDown => state.scroll = state.scroll.saturating_sub(10),
+        KeyCode::Enter if !state.input.trim().is_empty() => {
+            state.scroll = 0;
+            return Some(std::mem::take(&mut state.input));
+        }
+        _ => {}
+    }
+    None
+}
+
+fn draw(frame: &mut Frame, state: &State) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Min(3),
+            Constraint::Length(1),
+            Constraint::Length(3),
+        ])
+        .split(frame.size());
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(75), Constraint::Percentage(25)])
+        .split(rows[0]);
+    let panes = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Percentage(75), Constraint::Percentage(25)])
+        .split(columns[0]);
+
+    let height = panes[0].height.saturating_sub(2) as usize;
+    let end = state.messages.len() - state.scroll.min(state.messages.len());
+    frame.render_widget(
+        List::new(tail(&state.messages, end, height)).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Messages (PgUp/PgDn to scroll)"),
+        ),
+        panes[0],
+    );
+    let height = panes[1].height.saturating_sub(2) as usize;
+    frame.render_widget(
+        List::new(tail(&state.logs, state.logs.len(), height))
+            .block(Block::default().borders(Borders::ALL).title("Logs")),
+        panes[1],
+    );
+    let peers = state
+        .peers
+        .iter()
+        .map(|peer| ListItem::new(peer.clone()))
+        .collect::<Vec<_>>();
+    frame.render_widget(
+        List::new(peers).block(Block::default().borders(Borders::ALL).title("Peers")),
+        columns[1],
+    );
+    frame.render_widget(Paragraph::new(state.status.clone()), rows[1]);
+    frame.render_widget(
+        Paragraph::new(format!("> {}", state.input)).block(Block::default().borders(Borders::ALL)),
+        rows[2],
+    );
+    frame.set_cursor(
+        rows[2].x + 3 + state.input.chars().count() as u16,
+        rows[2].y + 1,
+    );
+}
+
+/// The last `height` of the lines before `end`.
+fn tail(lines: &VecDeque<String>, end: usize, height: usize) -> Vec<ListItem<'static>> {
+    lines
+        .range(end.saturating_sub(height)..end)
+        .map(|line| ListItem::new(line.clone()))
+        .collect()
+}
@@ -0,0 +1,153 @@
+use futures::channel::mpsc;
+use igd::{Gateway, PortMappingProtocol, SearchOptions};
+use libp2p::core::multiaddr::{Multiaddr, Protocol};
+use std::net::{SocketAddrV4, UdpSocket};
+use std::sync::mpsc as std_mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Lease requested from the router. The mapping is renewed at half this interval, so a mapping
+/// left behind by a crashed process expires on its own.
+const LEASE: Duration = Duration::from_secs(3600);
+const DESCRIPTION: &str = "libp2p dcutr client";
+
+#[derive(Debug)]
+pub enum UpnpEvent {
+    /// The router forwards this public address to our listener.
+    Mapped(Multiaddr),
+    /// No mapping could be set up, the relay/DCUtR flow is used as before.
+    Failed(String),
+}
+
+/// Keeps a UPnP port mapping for the local TCP listener alive from a background thread. Dropping
+/// the handle removes the mapping from the router.
+pub struct UpnpHandle {
+    stop: Option<std_mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl UpnpHandle {
+    pub fn spawn(local_port: u16, events: mpsc::UnboundedSender<UpnpEvent>) -> Self {
+        let (stop, stopped) = std_mpsc::channel();
+        let thread = thread::spawn(move || {
+            let mapping = match Mapping::create(local_port) {
+                Ok(mapping) => mapping,
+                Err(e) => {
+                    let _ = events.unbounded_send(UpnpEvent::Failed(e));
+                    return;
+                }
+            };
+            let _ = events.unbounded_send(UpnpEvent::Mapped(mapping.external_multiaddr()));
+
+            while let Err(std_mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(LEASE / 2) {
+                if let Err(e) = mapping.renew() {
+                    warn!("Failed to renew UPnP port mapping: {e}");
+                }
+            }
+            mapping.remove();
+        });
+        UpnpHandle {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for UpnpHandle {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct Mapping {
+    gateway: Gateway,
+    local: SocketAddrV4,
+    external: SocketAddrV4,
+}
+
+impl Mapping {
+    fn create(local_port: u16) -> Result<Self, String> {
+        let gateway = igd::search_gateway(SearchOptions::default())
+            .map_err(|e| format!("no UPnP gateway found: {e}"))?;
+        let local_ip = local_ip_towards(gateway.addr)
+            .map_err(|e| format!("failed to determine local address: {e}"))?;
+        let local = SocketAddrV4::new(local_ip, local_port);
+        let external_ip = gateway
+            .get_external_ip()
+            .map_err(|e| format!("failed to query external address: {e}"))?;
+
+        // Prefer the same port as the listener so the mapping matches what identify observes.
+        let external_port = match gateway.add_port(
+            PortMappingProtocol::TCP,
+            local_port,
+            local,
+            LEASE.as_secs() as u32,
+            DESCRIPTION,
+        ) {
+            Ok(()) => local_port,
+            Err(e) => {
+                info!("UPnP mapping of port {local_port} failed ({e}), asking for any port");
+                gateway
+                    .add_any_port(
+                        PortMappingProtocol::TCP,
+                        local,
+                        LEASE.as_secs() as u32,
+                        DESCRIPTION,
+                    )
+                    .map_err(|e| format!("failed to add port mapping: {e}"))?
+            }
+        };
+
+        Ok(Mapping {
+            gateway,
+            local,
+            external: SocketAddrV4::new(external_ip, external_port),
+        })
+    }
+
+    fn external_multiaddr(&self) -> Multiaddr {
+        Multiaddr::empty()
+            .with(Protocol::Ip4(*self.external.ip()))
+            .with(Protocol::Tcp(self.external.port()))
+    }
+
+    fn renew(&self) -> Result<(), String> {
+        self.gateway
+            .add_port(
+                PortMappingProtocol::TCP,
+                self.external.port(),
+                self.local,
+                LEASE.as_secs() as u32,
+                DESCRIPTION,
+            )
+            .map_err(|e| e.to_string())
+    }
+
+    fn remove(&self) {
+        match self
+            .gateway
+            .remove_port(PortMappingProtocol::TCP, self.external.port())
+        {
+            Ok(()) => info!("Removed UPnP port mapping for {}", self.external),
+            Err(e) => warn!("Failed to remove UPnP port mapping: {e}"),
+        }
+    }
+}
+
+/// The address of the interface that routes to `gateway`, found by connecting a UDP socket
+/// (which sends nothing).
+fn local_ip_towards(gateway: SocketAddrV4) -> std::io::Result<std::net::Ipv4Addr> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect(gateway)?;
+    match socket.local_addr()? {
+        std::net::SocketAddr::V4(addr) => Ok(*addr.ip()),
+        std::net::SocketAddr::V6(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "gateway is reachable over IPv6 only",
+        )),
+    }
+}
@@ -0,0 +1,311 @@
+use crate::envelope::{self, Envelope};
+use crate::pipeline::{Candidate, Check, Validator};
+use crate::throttle::TokenBucket;
+use libp2p::{gossipsub::MessageAcceptance, PeerId};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Minimum time between two notices about the same rate limited peer.
+const RATE_NOTICE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Why a message was rejected. Rejected messages are reported to gossipsub so they are not
+/// propagated any further and count against the peer's score.
+#[derive(Clone, Debug)]
+pub struct Rejection {
+    /// Short category the rejection is counted under in the stats.
+    pub kind: &'static str,
+    pub reason: String,
+    /// Whether the propagating peer is to blame, otherwise the message is merely ignored.
+    pub penalize: bool,
+}
+
+impl Rejection {
+    pub fn acceptance(&self) -> MessageAcceptance {
+        if self.penalize {
+            MessageAcceptance::Reject
+        } else {
+            MessageAcceptance::Ignore
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+/// Rejects messages whose envelope timestamp is older than `max_age` or lies in the future, and
+/// those whose envelope expired, each allowing for `skew` between the clocks of sender and
+/// receiver. Payloads without a timestamp, like bare lines of old peers and chunks, can't be
+/// judged and pass; chunks are judged by [`Reassembled`] once they make up the message.
+#[derive(Clone, Copy, Debug)]
+pub struct Freshness {
+    pub max_age: Duration,
+    pub skew: Duration,
+}
+
+impl Freshness {
+    pub fn check(&self, data: &[u8], now_millis: u64) -> Result<(), Rejection> {
+        let envelope = match Envelope::decode(data) {
+            Some(envelope) if envelope.sent_at > 0 => envelope,
+            _ => return Ok(()),
+        };
+        let sent_at = envelope.sent_at;
+        let max_age = (self.max_age + self.skew).as_millis() as u64;
+        let skew = self.skew.as_millis() as u64;
+        if now_millis > sent_at.saturating_add(max_age) {
+            return Err(Rejection {
+                kind: "stale",
+                reason: format!("sent {}s ago", (now_millis - sent_at) / 1000),
+                penalize: true,
+            });
+        }
+        if sent_at > now_millis.saturating_add(skew) {
+            return Err(Rejection {
+                kind: "future",
+                reason: format!("sent {}s in the future", (sent_at - now_millis) / 1000),
+                penalize: true,
+            });
+        }
+        // Expiring on the way is nobody's fault.
+        if envelope.is_expired(now_millis, self.skew) {
+            return Err(Rejection {
+                kind: "expired",
+                reason: "expired".to_string(),
+                penalize: false,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Validator for Freshness {
+    fn check(&mut self, candidate: &Candidate) -> Check {
+        Freshness::check(self, &candidate.message.data, envelope::unix_millis()).into()
+    }
+}
+
+/// Rejects envelopes whose compressed body inflates beyond `max_decompressed` bytes, so they
+/// aren't propagated to peers that would have to inflate them as well. Encrypted bodies can
+/// only be checked once they are decrypted, when they are shown, and chunks once they are
+/// reassembled.
+#[derive(Clone, Copy, Debug)]
+pub struct BodySize {
+    pub max_decompressed: usize,
+}
+
+impl BodySize {
+    pub fn check(&self, data: &[u8]) -> Result<(), Rejection> {
+        match Envelope::decode(data) {
+            Some(envelope) if envelope.compressed && envelope.key_id.is_none() => envelope
+                .decompress(self.max_decompressed)
+                .map(|_| ())
+                .map_err(|reason| Rejection {
+                    kind: "body",
+                    reason,
+                    penalize: true,
+                }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Validator for BodySize {
+    fn check(&mut self, candidate: &Candidate) -> Check {
+        BodySize::check(self, &candidate.message.data).into()
+    }
+}
+
+/// The [`Freshness`] and [`BodySize`] checks for a payload put back together from chunks, which
+/// both let through one chunk at a time.
+#[derive(Clone, Copy, Debug)]
+pub struct Reassembled {
+    pub freshness: Freshness,
+    pub body_size: BodySize,
+}
+
+impl Reassembled {
+    pub fn check(&self, data: &[u8], now_millis: u64) -> Result<(), Rejection> {
+        self.freshness.check(data, now_millis)?;
+        self.body_size.check(data)
+    }
+}
+
+/// A message rate like `10/s` or `600/m`. A bare number is per second.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rate {
+    pub per_second: f64,
+}
+
+impl FromStr for Rate {
+    type Err = String;
+    fn from_str(rate: &str) -> Result<Self, Self::Err> {
+        let (count, unit) = rate.split_once('/').unwrap_or((rate, "s"));
+        let count = count
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| format!("Expected a rate like '10/s', got '{rate}'"))?;
+        let seconds = match unit.trim() {
+            "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            _ => return Err(format!("Expected a rate per 's', 'm' or 'h', got '{rate}'")),
+        };
+        if count <= 0.0 || !count.is_finite() {
+            return Err(format!("Rate must be positive, got '{rate}'"));
+        }
+        Ok(Rate {
+            per_second: count / seconds,
+        })
+    }
+}
+
+/// A peer whose messages are dropped for exceeding the rate, reported at most every
+/// [`RATE_NOTICE_INTERVAL`] per peer rather than once per message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimited {
+    pub peer_id: PeerId,
+    pub per_second: f64,
+    /// Messages dropped since the previous notice about the peer.
+    pub dropped: u64,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Peer {} sends more than {:.1} messages/s, dropped {} of its messages",
+            self.peer_id, self.per_second, self.dropped
+        )
+    }
+}
+
+struct Bucket {
+    tokens: TokenBucket,
+    dropped: u64,
+    noticed: Option<Instant>,
+}
+
+/// A [`TokenBucket`] per propagating peer, messages finding it empty are rejected.
+pub struct PeerRateLimiter {
+    rate: Rate,
+    burst: u32,
+    buckets: HashMap<PeerId, Bucket>,
+    limited: Vec<RateLimited>,
+}
+
+impl PeerRateLimiter {
+    pub fn new(rate: Rate, burst: u32) -> Self {
+        PeerRateLimiter {
+            rate,
+            burst,
+            buckets: HashMap::new(),
+            limited: Vec::new(),
+        }
+    }
+
+    pub fn check(&mut self, peer_id: PeerId, now: Instant) -> Result<(), Rejection> {
+        let (rate, burst) = (self.rate, self.burst);
+        let bucket = self.buckets.entry(peer_id).or_insert_with(|| Bucket {
+            tokens: TokenBucket::new(rate, burst, now),
+            dropped: 0,
+            noticed: None,
+        });
+        if bucket.tokens.try_take(now) {
+            return Ok(());
+        }
+
+        bucket.dropped += 1;
+        if bucket.noticed.map_or(true, |at| {
+            now.saturating_duration_since(at) >= RATE_NOTICE_INTERVAL
+        }) {
+            self.limited.push(RateLimited {
+                peer_id,
+                per_second: self.rate.per_second,
+                dropped: bucket.dropped,
+            });
+            bucket.noticed = Some(now);
+            bucket.dropped = 0;
+        }
+        Err(Rejection {
+            kind: "rate",
+            reason: "rate limit exceeded".to_string(),
+            penalize: true,
+        })
+    }
+
+    pub fn on_disconnected(&mut self, peer_id: &PeerId) {
+        self.buckets.remove(peer_id);
+    }
+
+    /// The peers that became due for a notice since the last call.
+    pub fn take_limited(&mut self) -> Vec<RateLimited> {
+        std::mem::take(&mut self.limited)
+    }
+}
+
+impl Validator for PeerRateLimiter {
+    fn check(&mut self, candidate: &Candidate) -> Check {
+        PeerRateLimiter::check(self, candidate.propagation_source, Instant::now()).into()
+    }
+
+    fn on_disconnected(&mut self, peer_id: &PeerId) {
+        PeerRateLimiter::on_disconnected(self, peer_id)
+    }
+
+    fn notices(&mut self) -> Vec<String> {
+        self.take_limited()
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+}
+
+/// Counts of accepted and rejected messages, the latter by kind.
+#[derive(Default)]
+pub struct ValidationStats {
+    accepted: u64,
+    rejected: BTreeMap<&'static str, u64>,
+    changed: bool,
+}
+
+impl ValidationStats {
+    pub fn on_accepted(&mut self) {
+        self.accepted += 1;
+        self.changed = true;
+    }
+
+    pub fn on_rejected(&mut self, rejection: &Rejection) {
+        *self.rejected.entry(rejection.kind).or_default() += 1;
+        self.changed = true;
+    }
+
+    /// Whether anything was counted since the last call.
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+}
+
+/// Formats as `validation: 10 accepted, 2 rejected (stale: 1, future: 1)`.
+impl fmt::Display for ValidationStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rejected: u64 = self.rejected.values().sum();
+        write!(
+            f,
+            "validation: {} accepted, {rejected} rejected",
+            self.accepted
+        )?;
+        if !self.rejected.is_empty() {
+            let kinds = self
+                .rejected
+                .iter()
+                .map(|(kind, n)| format!("{kind}: {n}"))
+                .collect::<Vec<_>>();
+            write!(f, " ({})", kinds.join(", "))?;
+        }
+        Ok(())
+    }
+}
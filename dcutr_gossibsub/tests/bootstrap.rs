@@ -0,0 +1,302 @@
+//! The bootstrap state machine, fed the progress the event loop would see, and driven by a
+//! client against a relay in the test process.
+
+mod common;
+
+use common::peer;
+use dcutr::bootstrap::{
+    Bootstrap, Phase, Progress, Step, EXIT_CIRCUIT_TIMEOUT, EXIT_LISTEN_TIMEOUT,
+};
+use libp2p::{autonat::NatStatus, core::transport::ListenerId, Multiaddr, PeerId};
+use std::time::{Duration, Instant};
+
+const AUTONAT_WAIT: Duration = Duration::from_secs(10);
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+fn public_addr() -> Multiaddr {
+    "/ip4/198.51.100.7/tcp/4001".parse().expect("valid address")
+}
+
+/// A bootstrap in listen mode, or dialing `remotes`, that got as far as the relay connection.
+fn connecting_relay(relay: PeerId, remotes: Vec<PeerId>) -> Bootstrap {
+    let listener = ListenerId::next();
+    let mut bootstrap = Bootstrap::new(
+        [listener].into(),
+        Some(relay),
+        remotes,
+        Some(AUTONAT_WAIT),
+        TIMEOUT,
+    );
+    let step = bootstrap.on_progress(Progress::NewListenAddr(listener));
+    assert!(matches!(step, Ok(Some(Step::DialRelay))), "{step:?}");
+    let step = bootstrap.on_progress(Progress::ConnectionEstablished(relay));
+    assert!(matches!(step, Ok(None)), "{step:?}");
+    bootstrap
+}
+
+/// Exchanges identify with the relay, which completes its phase.
+fn identify(bootstrap: &mut Bootstrap, relay: PeerId) -> Option<Step> {
+    let step = bootstrap.on_progress(Progress::IdentifySent(relay));
+    assert!(matches!(step, Ok(None)), "{step:?}");
+    bootstrap
+        .on_progress(Progress::IdentifyReceived(relay))
+        .expect("no error")
+}
+
+#[test]
+fn bootstrap_goes_on_through_the_relay_without_waiting_for_autonat() {
+    let relay = peer(1);
+    let mut bootstrap = connecting_relay(relay, vec![peer(2)]);
+
+    let step = identify(&mut bootstrap, relay);
+    assert!(
+        matches!(step, Some(Step::Connect(NatStatus::Unknown))),
+        "{step:?}"
+    );
+
+    let step = bootstrap.on_progress(Progress::NatStatus(NatStatus::Public(public_addr())));
+    assert!(
+        matches!(&step, Ok(Some(Step::Reachable(addr))) if *addr == public_addr()),
+        "a verdict coming in late is still acted on: {step:?}"
+    );
+    let step = bootstrap.on_progress(Progress::NatStatus(NatStatus::Public(public_addr())));
+    assert!(matches!(step, Ok(None)), "only once: {step:?}");
+}
+
+#[test]
+fn a_verdict_after_the_wait_is_ignored() {
+    let relay = peer(1);
+    let mut bootstrap = connecting_relay(relay, vec![]);
+    assert!(matches!(
+        identify(&mut bootstrap, relay),
+        Some(Step::Connect(NatStatus::Unknown))
+    ));
+
+    assert!(bootstrap
+        .poll(Instant::now() + AUTONAT_WAIT + Duration::from_secs(1))
+        .is_ok());
+    let step = bootstrap.on_progress(Progress::NatStatus(NatStatus::Public(public_addr())));
+    assert!(matches!(step, Ok(None)), "{step:?}");
+    assert!(!bootstrap.is_running(), "still waiting for the reservation");
+}
+
+#[test]
+fn a_public_verdict_known_in_time_skips_the_reservation() {
+    let relay = peer(1);
+    let mut bootstrap = connecting_relay(relay, vec![]);
+    let step = bootstrap.on_progress(Progress::NatStatus(NatStatus::Public(public_addr())));
+    assert!(matches!(step, Ok(None)), "{step:?}");
+
+    let step = identify(&mut bootstrap, relay);
+    assert!(
+        matches!(&step, Some(Step::Connect(NatStatus::Public(addr))) if *addr == public_addr()),
+        "{step:?}"
+    );
+    assert!(bootstrap.is_running());
+}
+
+#[test]
+fn force_relay_ignores_autonat() {
+    let relay = peer(1);
+    let listener = ListenerId::next();
+    let mut bootstrap = Bootstrap::new([listener].into(), Some(relay), vec![], None, TIMEOUT);
+    bootstrap
+        .on_progress(Progress::NewListenAddr(listener))
+        .expect("no error");
+    bootstrap
+        .on_progress(Progress::NatStatus(NatStatus::Public(public_addr())))
+        .expect("no error");
+    bootstrap
+        .on_progress(Progress::ConnectionEstablished(relay))
+        .expect("no error");
+
+    let step = identify(&mut bootstrap, relay);
+    assert!(
+        matches!(step, Some(Step::Connect(NatStatus::Unknown))),
+        "{step:?}"
+    );
+    assert!(!bootstrap.is_running());
+}
+
+#[test]
+fn an_unrelated_peer_connecting_during_bootstrap_changes_nothing() {
+    let relay = peer(1);
+    let stranger = peer(9);
+    let listener = ListenerId::next();
+    let mut bootstrap = Bootstrap::new([listener].into(), Some(relay), vec![], None, TIMEOUT);
+    let ignored = |bootstrap: &mut Bootstrap| {
+        for progress in [
+            Progress::ConnectionEstablished(stranger),
+            Progress::IdentifySent(stranger),
+            Progress::IdentifyReceived(stranger),
+            Progress::DialFailed(Some(stranger), "connection refused".to_string()),
+        ] {
+            let step = bootstrap.on_progress(progress);
+            assert!(matches!(step, Ok(None)), "{step:?}");
+        }
+    };
+
+    ignored(&mut bootstrap);
+    let step = bootstrap.on_progress(Progress::NewListenAddr(listener));
+    assert!(matches!(step, Ok(Some(Step::DialRelay))), "{step:?}");
+
+    ignored(&mut bootstrap);
+    bootstrap
+        .on_progress(Progress::ConnectionEstablished(relay))
+        .expect("no error");
+    ignored(&mut bootstrap);
+    let step = identify(&mut bootstrap, relay);
+    assert!(
+        matches!(step, Some(Step::Connect(NatStatus::Unknown))),
+        "{step:?}"
+    );
+
+    ignored(&mut bootstrap);
+    assert!(!bootstrap.is_running(), "still waiting for the reservation");
+    let step = bootstrap.on_progress(Progress::ReservationAccepted);
+    assert!(matches!(step, Ok(Some(Step::Running))), "{step:?}");
+}
+
+#[test]
+fn relay_dialing_waits_for_every_listener() {
+    let (first, second) = (ListenerId::next(), ListenerId::next());
+    let mut bootstrap = Bootstrap::new([first, second].into(), None, vec![], None, TIMEOUT);
+    let step = bootstrap.on_progress(Progress::NewListenAddr(first));
+    assert!(matches!(step, Ok(None)), "{step:?}");
+    let step = bootstrap.on_progress(Progress::NewListenAddr(first));
+    assert!(
+        matches!(step, Ok(None)),
+        "a second address of the same listener: {step:?}"
+    );
+    let step = bootstrap.on_progress(Progress::NewListenAddr(second));
+    assert!(matches!(step, Ok(Some(Step::DialRelay))), "{step:?}");
+}
+
+#[test]
+fn failing_listeners_and_relay_dials_end_the_bootstrap() {
+    let listener = ListenerId::next();
+    let mut bootstrap = Bootstrap::new([listener].into(), None, vec![], None, TIMEOUT);
+    let other = ListenerId::next();
+    let step = bootstrap.on_progress(Progress::ListenerFailed(other, "gone".to_string()));
+    assert!(matches!(step, Ok(None)), "not one we wait for: {step:?}");
+    let error = bootstrap
+        .on_progress(Progress::ListenerFailed(
+            listener,
+            "address in use".to_string(),
+        ))
+        .unwrap_err();
+    assert_eq!(error, "failed to listen: address in use");
+
+    // Without a `/p2p` suffix any failed dial is taken for the relay's.
+    let listener = ListenerId::next();
+    let mut bootstrap = Bootstrap::new([listener].into(), None, vec![], None, TIMEOUT);
+    bootstrap
+        .on_progress(Progress::NewListenAddr(listener))
+        .expect("no error");
+    let error = bootstrap
+        .on_progress(Progress::DialFailed(None, "connection refused".to_string()))
+        .unwrap_err();
+    assert_eq!(error, "failed to connect to the relay: connection refused");
+}
+
+#[test]
+fn dial_mode_runs_once_a_circuit_or_a_direct_connection_is_up() {
+    let relay = peer(1);
+    let remote = peer(2);
+    let mut bootstrap = connecting_relay(relay, vec![remote]);
+    identify(&mut bootstrap, relay);
+    let step = bootstrap.on_progress(Progress::ReservationAccepted);
+    assert!(
+        matches!(step, Ok(None)),
+        "no reservation in dial mode: {step:?}"
+    );
+    let step = bootstrap.on_progress(Progress::CircuitEstablished);
+    assert!(matches!(step, Ok(Some(Step::Running))), "{step:?}");
+    assert!(bootstrap.is_running());
+
+    let mut bootstrap = connecting_relay(relay, vec![remote]);
+    identify(&mut bootstrap, relay);
+    let step = bootstrap.on_progress(Progress::ConnectionEstablished(peer(3)));
+    assert!(matches!(step, Ok(None)), "{step:?}");
+    let step = bootstrap.on_progress(Progress::ConnectionEstablished(remote));
+    assert!(matches!(step, Ok(Some(Step::Running))), "{step:?}");
+}
+
+#[test]
+fn phases_time_out_with_their_exit_code() {
+    let listener = ListenerId::next();
+    let mut bootstrap = Bootstrap::new([listener].into(), None, vec![], None, TIMEOUT);
+    let late = Instant::now() + TIMEOUT + Duration::from_secs(1);
+    assert!(
+        bootstrap.poll(late).is_ok(),
+        "a completion already queued gets one more poll"
+    );
+    let timed_out = bootstrap.poll(late).unwrap_err();
+    assert_eq!(timed_out.phase(), Phase::Listen);
+    assert_eq!(timed_out.exit_code(), EXIT_LISTEN_TIMEOUT);
+    assert_eq!(
+        timed_out.to_string(),
+        "Bootstrap timed out after 30s in the listen phase: no listen address came up"
+    );
+
+    let relay = peer(1);
+    let mut bootstrap = connecting_relay(relay, vec![peer(2)]);
+    identify(&mut bootstrap, relay);
+    assert!(bootstrap.poll(Instant::now()).is_ok(), "just started");
+    let late = Instant::now() + TIMEOUT + Duration::from_secs(1);
+    let _ = bootstrap.poll(late);
+    let timed_out = bootstrap.poll(late).unwrap_err();
+    assert_eq!(timed_out.phase(), Phase::CircuitDial);
+    assert_eq!(timed_out.exit_code(), EXIT_CIRCUIT_TIMEOUT);
+
+    let mut bootstrap = connecting_relay(relay, vec![]);
+    identify(&mut bootstrap, relay);
+    bootstrap
+        .on_progress(Progress::ReservationAccepted)
+        .expect("no error");
+    let late = Instant::now() + TIMEOUT + Duration::from_secs(1);
+    assert!(bootstrap.poll(late).is_ok() && bootstrap.poll(late).is_ok());
+}
+
+/// A client listening on localhost goes through every phase against a relay in the test process,
+/// acting on the steps like the event loop does.
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn a_client_reserves_a_slot_at_an_in_process_relay() {
+    use futures::StreamExt;
+    use libp2p::core::multiaddr::Protocol;
+
+    let relay_addr = common::spawn_relay(0).await;
+    let mut node = common::spawn_node(1).await;
+    let listener = node
+        .listen_on("/ip4/127.0.0.1/tcp/0".parse().expect("valid address"))
+        .expect("listens on localhost");
+    let mut bootstrap = Bootstrap::new([listener].into(), Some(peer(0)), vec![], None, TIMEOUT);
+
+    let mut steps = Vec::new();
+    common::within("the bootstrap", async {
+        while !bootstrap.is_running() {
+            let progress = match Progress::of(&node.select_next_some().await) {
+                Some(progress) => progress,
+                None => continue,
+            };
+            match bootstrap.on_progress(progress).expect("no error") {
+                Some(Step::DialRelay) => {
+                    steps.push("dial relay");
+                    node.dial(relay_addr.clone()).expect("dials the relay");
+                }
+                Some(Step::Connect(nat_status)) => {
+                    assert!(matches!(nat_status, NatStatus::Unknown), "{nat_status:?}");
+                    steps.push("connect");
+                    node.listen_on(relay_addr.clone().with(Protocol::P2pCircuit))
+                        .expect("listens through the relay");
+                }
+                Some(Step::Running) => steps.push("running"),
+                Some(step) => panic!("unexpected {step:?}"),
+                None => {}
+            }
+        }
+    })
+    .await;
+    assert_eq!(steps, ["dial relay", "connect", "running"]);
+}
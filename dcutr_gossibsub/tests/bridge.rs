@@ -0,0 +1,127 @@
+//! `--bridge` specs, and a TCP bridge carrying messages both ways.
+
+use dcutr::bridge::{BridgeLine, BridgeSpec, Bridges, Framing, Transport};
+use dcutr::identity;
+use futures::{channel::mpsc, executor::block_on, StreamExt};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A local address nothing listens on right now.
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("binds an ephemeral port")
+}
+
+fn start(spec: &str) -> (Bridges, mpsc::UnboundedReceiver<BridgeLine>) {
+    let (sender, lines) = mpsc::unbounded();
+    let local_peer_id = identity::generate_ed25519(1).public().to_peer_id();
+    let spec = spec.parse::<BridgeSpec>().expect("valid spec");
+    let bridges = Bridges::start(&[spec], &local_peer_id, sender).expect("binds");
+    (bridges, lines)
+}
+
+/// Connects and waits until the bridge counts the client.
+fn connect(bridges: &Bridges, addr: SocketAddr) -> TcpStream {
+    let client = TcpStream::connect(addr).expect("connects");
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !bridges.lines()[0].contains(": 1 clients") {
+        assert!(Instant::now() < deadline, "the client was never counted");
+        thread::sleep(Duration::from_millis(10));
+    }
+    client
+}
+
+#[test]
+fn specs_parse() {
+    let spec = "tcp-listen:127.0.0.1:9000:topic=test-net"
+        .parse::<BridgeSpec>()
+        .expect("parses");
+    assert_eq!(
+        spec,
+        BridgeSpec {
+            transport: Transport::TcpListen,
+            addr: "127.0.0.1:9000".parse().expect("valid address"),
+            topic: "test-net".to_string(),
+            framing: Framing::Lines,
+        }
+    );
+    let spec = "udp-listen:[::1]:9000:topic=chat:framing=length"
+        .parse::<BridgeSpec>()
+        .expect("parses");
+    assert_eq!(spec.transport, Transport::UdpListen);
+    assert_eq!(spec.addr, "[::1]:9000".parse().expect("valid address"));
+    assert_eq!(spec.framing, Framing::LengthPrefixed);
+
+    for broken in [
+        "tcp-listen:127.0.0.1:9000",
+        "tcp-connect:127.0.0.1:9000:topic=x",
+        "tcp-listen:localhost:topic=x",
+        "tcp-listen:127.0.0.1:9000:topic=x:framing=xml",
+        "tcp-listen:127.0.0.1:9000:topic=x:color=red",
+    ] {
+        assert!(broken.parse::<BridgeSpec>().is_err(), "{broken} parsed");
+    }
+}
+
+#[test]
+fn lines_travel_both_ways() {
+    let addr = free_addr();
+    let (bridges, mut lines) = start(&format!("tcp-listen:{addr}:topic=test-net"));
+    let mut client = connect(&bridges, addr);
+
+    client.write_all(b"hello from legacy\r\n").expect("writes");
+    let line = block_on(lines.next()).expect("a line");
+    assert_eq!((line.bridge, line.text.as_str()), (0, "hello from legacy"));
+
+    // What entered through this bridge isn't echoed back to it.
+    bridges.on_message("test-net", "echo", Some(bridges.marker(0)));
+    bridges.on_message("other", "elsewhere", None);
+    bridges.on_message("test-net", "hello from the topic", None);
+    let mut reader = BufReader::new(client);
+    let mut received = String::new();
+    reader.read_line(&mut received).expect("reads");
+    assert_eq!(received, "hello from the topic\n");
+    assert!(bridges.lines()[0].contains("1 in, 1 out, 0 dropped"));
+}
+
+#[test]
+fn length_prefixed_messages_may_span_lines() {
+    let addr = free_addr();
+    let (bridges, mut lines) = start(&format!("tcp-listen:{addr}:topic=test-net:framing=length"));
+    let mut client = connect(&bridges, addr);
+
+    let body = b"two\nlines";
+    client
+        .write_all(&(body.len() as u32).to_be_bytes())
+        .expect("writes");
+    client.write_all(body).expect("writes");
+    assert_eq!(
+        block_on(lines.next()).expect("a message").text,
+        "two\nlines"
+    );
+
+    bridges.on_message("test-net", "back", None);
+    let mut length = [0; 4];
+    client.read_exact(&mut length).expect("reads");
+    let mut body = vec![0; u32::from_be_bytes(length) as usize];
+    client.read_exact(&mut body).expect("reads");
+    assert_eq!(body, b"back");
+}
+
+#[test]
+fn binding_a_taken_address_fails() {
+    let taken = TcpListener::bind("127.0.0.1:0").expect("binds");
+    let addr = taken.local_addr().expect("has an address");
+    let (sender, _lines) = mpsc::unbounded();
+    let local_peer_id = identity::generate_ed25519(1).public().to_peer_id();
+    let spec = format!("tcp-listen:{addr}:topic=test-net")
+        .parse::<BridgeSpec>()
+        .expect("valid spec");
+    let error = Bridges::start(&[spec], &local_peer_id, sender)
+        .err()
+        .expect("fails");
+    assert!(error.contains("failed to bind --bridge"), "{error}");
+}
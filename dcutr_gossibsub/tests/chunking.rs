@@ -0,0 +1,132 @@
+//! Long payloads are split into chunks that fit and put back together in any order, with what
+//! waits for missing chunks bounded in count, bytes and time.
+
+mod common;
+
+use common::peer;
+use dcutr::chunking::{self, Chunk, Reassembly};
+use std::time::{Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+fn reassembly() -> Reassembly {
+    Reassembly::new(TIMEOUT, 2, 4096)
+}
+
+fn chunks(payload: &[u8], max_payload: usize) -> Vec<Chunk> {
+    chunking::split(payload.to_vec(), max_payload)
+        .expect("splits")
+        .iter()
+        .map(|data| Chunk::decode(data).expect("a chunk"))
+        .collect()
+}
+
+#[test]
+fn payloads_of_exactly_the_maximum_are_not_chunked() {
+    let payload = vec![7; 1000];
+    assert_eq!(chunking::split(payload.clone(), 1000), Ok(vec![payload]));
+    let chunked = chunking::split(vec![7; 1001], 1000).expect("splits");
+    assert!(chunked.len() > 1);
+    assert!(chunked.iter().all(|chunk| chunk.len() <= 1000));
+}
+
+#[test]
+fn chunks_are_reassembled_in_any_order() {
+    let payload = (0..5000u32).map(|i| i as u8).collect::<Vec<_>>();
+    let mut chunks = chunks(&payload, 1000);
+    assert!(chunks.len() > 2);
+    chunks.reverse();
+    let last = chunks.pop().expect("chunks");
+
+    let mut reassembly = Reassembly::new(TIMEOUT, 1, 1 << 20);
+    for chunk in chunks.iter().chain(chunks.iter()) {
+        assert_eq!(reassembly.add(Some(peer(1)), chunk.clone()), Ok(None));
+    }
+    assert_eq!(reassembly.add(Some(peer(1)), last), Ok(Some(payload)));
+    assert_eq!(reassembly.buffered(), 0);
+}
+
+#[test]
+fn a_message_missing_a_chunk_expires() {
+    let mut chunks = chunks(&[1; 3000], 1000);
+    chunks.pop();
+    let mut reassembly = reassembly();
+    for chunk in chunks {
+        assert_eq!(reassembly.add(Some(peer(1)), chunk), Ok(None));
+    }
+    assert!(reassembly.buffered() > 0);
+
+    assert!(reassembly.expire(Instant::now()).is_empty());
+    let expired = reassembly.expire(Instant::now() + TIMEOUT);
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].1, 1, "one chunk is missing");
+    assert_eq!(reassembly.buffered(), 0);
+}
+
+#[test]
+fn each_source_has_a_few_messages_in_progress_at_most() {
+    let mut reassembly = reassembly();
+    for i in 0..2u8 {
+        let chunk = chunks(&[i; 2000], 1000).remove(0);
+        assert_eq!(reassembly.add(Some(peer(1)), chunk), Ok(None));
+    }
+    let third = chunks(&[2; 2000], 1000).remove(0);
+    assert!(reassembly.add(Some(peer(1)), third.clone()).is_err());
+    assert_eq!(
+        reassembly.add(Some(peer(2)), third),
+        Ok(None),
+        "other peers aren't held back"
+    );
+}
+
+#[test]
+fn buffered_bytes_are_capped() {
+    let mut reassembly = Reassembly::new(TIMEOUT, 16, 4096);
+    let mut chunks = chunks(&[3; 10_000], 1000);
+    chunks.pop();
+    let refused = chunks
+        .into_iter()
+        .filter(|chunk| reassembly.add(Some(peer(1)), chunk.clone()).is_err())
+        .count();
+    assert!(refused > 0);
+    assert!(reassembly.buffered() <= 4096);
+}
+
+#[test]
+fn broken_chunks_are_refused() {
+    let mut reassembly = reassembly();
+    let chunk = chunks(&[4; 3000], 1000).remove(0);
+    for broken in [
+        Chunk {
+            chunk_count: 0,
+            ..chunk.clone()
+        },
+        Chunk {
+            chunk_index: chunk.chunk_count,
+            ..chunk.clone()
+        },
+        Chunk {
+            data: "not base64!".to_string(),
+            ..chunk.clone()
+        },
+    ] {
+        assert!(reassembly.add(Some(peer(1)), broken).is_err());
+    }
+    assert_eq!(reassembly.buffered(), 0);
+}
+
+#[test]
+fn the_max_message_size_must_leave_room_for_a_chunk() {
+    assert!(chunking::validate_max_message_size(99, ["test-net"]).is_err());
+    assert!(chunking::validate_max_message_size(300, ["test-net"]).is_err());
+    let min = 256 + "test-net".len() + 128 + 4;
+    assert_eq!(
+        chunking::validate_max_message_size(min, ["test-net"]),
+        Ok(())
+    );
+    assert!(chunking::validate_max_message_size(min, ["test-net", "a-longer-topic"]).is_err());
+
+    // Whatever passes can still carry a long line.
+    let max_payload = chunking::max_payload(min, "test-net");
+    assert!(chunks(&[1; 100], max_payload).len() > 1);
+}
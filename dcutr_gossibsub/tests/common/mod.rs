@@ -0,0 +1,28 @@
+//! Helpers shared by the tests. Those running a relay and clients need the tokio runtime.
+
+#![allow(dead_code)]
+
+#[cfg(feature = "tokio")]
+mod swarm;
+
+#[cfg(feature = "tokio")]
+#[allow(unused_imports)]
+pub use swarm::*;
+
+use dcutr::identity;
+use libp2p::PeerId;
+use std::fs;
+use std::path::PathBuf;
+
+/// The peer id of the ed25519 key derived from `seed`, as `--secret-key-seed` does.
+pub fn peer(seed: u8) -> PeerId {
+    identity::generate_ed25519(seed).public().to_peer_id()
+}
+
+/// A fresh, empty directory named after the test, to keep data in.
+pub fn data_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dcutr-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("creates the data dir");
+    dir
+}
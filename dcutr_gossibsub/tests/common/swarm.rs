@@ -0,0 +1,335 @@
+//! Relays and clients run in the test process, on localhost or in memory. Every wait is bounded
+//! by [`TIMEOUT`] so a broken flow fails the test instead of hanging it.
+
+use dcutr::bandwidth::Bandwidth;
+use dcutr::behaviour::{Behaviour, BehaviourConfig, BehaviourEvent};
+use dcutr::identity;
+use dcutr::idle::KeepAlive;
+use dcutr::message_id::MessageIdScheme;
+use dcutr::node::{Event, Node};
+use dcutr::resolver::DnsSettings;
+use dcutr::transport;
+use futures::future::{self, Either};
+use futures::{Future, StreamExt};
+use libp2p::{
+    core::{
+        multiaddr::{Multiaddr, Protocol},
+        muxing::StreamMuxerBox,
+        transport::{Boxed, Transport},
+        upgrade,
+    },
+    gossipsub::{self, IdentTopic, PublishError, TopicHash},
+    identify,
+    identity::Keypair,
+    noise, ping,
+    pnet::PreSharedKey,
+    relay,
+    swarm::{AddressScore, NetworkBehaviour, SwarmBuilder, SwarmEvent},
+    tcp, yamux, PeerId,
+};
+use std::pin::pin;
+use std::task::Poll;
+use std::time::Duration;
+
+/// How long any single step of a test may take.
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The topic the test clients subscribe to.
+pub const TOPIC: &str = "test-net";
+
+pub fn topic() -> IdentTopic {
+    IdentTopic::new(TOPIC)
+}
+
+#[derive(NetworkBehaviour)]
+struct RelayBehaviour {
+    relay: relay::Behaviour,
+    ping: ping::Behaviour,
+    identify: identify::Behaviour,
+}
+
+/// Starts a relay server on a task of its own, returning its address with the `/p2p` suffix.
+pub async fn spawn_relay(secret_key_seed: u8) -> Multiaddr {
+    let key = identity::generate_ed25519(secret_key_seed);
+    let peer_id = key.public().to_peer_id();
+    let transport = tcp::tokio::Transport::new(tcp::Config::default())
+        .upgrade(upgrade::Version::V1)
+        .authenticate(noise::Config::new(&key).expect("noise keypair"))
+        .multiplex(yamux::Config::default())
+        .boxed();
+    let behaviour = RelayBehaviour {
+        relay: relay::Behaviour::new(peer_id, relay::Config::default()),
+        ping: ping::Behaviour::new(ping::Config::new()),
+        identify: identify::Behaviour::new(identify::Config::new(
+            "/dcutr-test/0.0.1".to_string(),
+            key.public(),
+        )),
+    };
+    let mut swarm = SwarmBuilder::with_tokio_executor(transport, behaviour, peer_id).build();
+    swarm
+        .listen_on(localhost())
+        .expect("relay listens on localhost");
+    let addr = within("the relay to listen", async {
+        loop {
+            if let SwarmEvent::NewListenAddr { address, .. } = swarm.select_next_some().await {
+                return address;
+            }
+        }
+    })
+    .await;
+    // Reservations carry the relay's external addresses, there has to be one.
+    swarm.add_external_address(addr.clone(), AddressScore::Infinite);
+    tokio::spawn(async move {
+        loop {
+            swarm.select_next_some().await;
+        }
+    });
+    addr.with(Protocol::P2p(peer_id.into()))
+}
+
+/// A client subscribed to [`TOPIC`], listening on localhost.
+pub async fn spawn_node(secret_key_seed: u8) -> Node {
+    spawn_tcp_node(secret_key_seed, None, true).await
+}
+
+/// A client on the TCP transport like [`spawn_node`], in the private network of `psk` if there
+/// is one and not subscribed to anything. It's listening once this returns, [`connect`] reaches
+/// it.
+pub async fn spawn_private_tcp_node(secret_key_seed: u8, psk: Option<PreSharedKey>) -> Node {
+    let mut node = spawn_tcp_node(secret_key_seed, psk, false).await;
+    wait_for_event(
+        &mut node,
+        &mut [],
+        "a listen address",
+        |event| match event {
+            SwarmEvent::NewListenAddr { .. } => Some(()),
+            _ => None,
+        },
+    )
+    .await;
+    node
+}
+
+async fn spawn_tcp_node(secret_key_seed: u8, psk: Option<PreSharedKey>, subscribe: bool) -> Node {
+    let key = identity::generate_ed25519(secret_key_seed);
+    let (relay_transport, relay_client) = relay::client::new(key.public().to_peer_id());
+    let transport = transport::build(
+        &key,
+        relay_transport,
+        psk,
+        &DnsSettings::default(),
+        &Bandwidth::default(),
+    )
+    .await
+    .expect("transport builds");
+    let config = gossipsub_config(MessageIdScheme::Sha256)
+        .build()
+        .expect("valid gossipsub config");
+    let mut gossipsub = gossipsub(&key, config);
+    if subscribe {
+        gossipsub.subscribe(&topic()).expect("subscribes");
+    }
+    let mut node = node(&key, transport, relay_client, gossipsub);
+    node.listen_on(localhost())
+        .expect("node listens on localhost");
+    node
+}
+
+/// A client subscribed to [`TOPIC`] on the in-memory transport, without a relay. It's listening
+/// once this returns, [`connect`] reaches it.
+pub async fn spawn_memory_node(secret_key_seed: u8, message_id: MessageIdScheme) -> Node {
+    spawn_memory_node_with(secret_key_seed, |key| {
+        let config = gossipsub_config(message_id)
+            .build()
+            .expect("valid gossipsub config");
+        let mut gossipsub = gossipsub(key, config);
+        gossipsub.subscribe(&topic()).expect("subscribes");
+        gossipsub
+    })
+    .await
+}
+
+/// Like [`spawn_memory_node`], with the gossipsub behaviour the test builds for the key.
+pub async fn spawn_memory_node_with(
+    secret_key_seed: u8,
+    gossipsub: impl FnOnce(&Keypair) -> gossipsub::Behaviour,
+) -> Node {
+    spawn_private_node_with(secret_key_seed, None, gossipsub).await
+}
+
+/// Like [`spawn_memory_node`], in the private network of `psk` if there is one.
+pub async fn spawn_private_node(secret_key_seed: u8, psk: Option<PreSharedKey>) -> Node {
+    spawn_private_node_with(secret_key_seed, psk, |key| {
+        let config = gossipsub_config(MessageIdScheme::Sha256)
+            .build()
+            .expect("valid gossipsub config");
+        gossipsub(key, config)
+    })
+    .await
+}
+
+async fn spawn_private_node_with(
+    secret_key_seed: u8,
+    psk: Option<PreSharedKey>,
+    gossipsub: impl FnOnce(&Keypair) -> gossipsub::Behaviour,
+) -> Node {
+    let key = identity::generate_ed25519(secret_key_seed);
+    // Only there for the behaviour, there are no circuits in memory.
+    let (_, relay_client) = relay::client::new(key.public().to_peer_id());
+    let transport = transport::memory(&key, psk, &Bandwidth::default());
+    let gossipsub = gossipsub(&key);
+    let mut node = node(&key, transport, relay_client, gossipsub);
+    node.listen_on(Multiaddr::empty().with(Protocol::Memory(0)))
+        .expect("node listens in memory");
+    wait_for_event(
+        &mut node,
+        &mut [],
+        "a memory address",
+        |event| match event {
+            SwarmEvent::NewListenAddr { .. } => Some(()),
+            _ => None,
+        },
+    )
+    .await;
+    node
+}
+
+/// Dials the listen address of `remote` from `node` and waits for the connection.
+pub async fn connect(node: &mut Node, remote: &mut Node) {
+    let remote_id = *remote.local_peer_id();
+    let addr = remote.listeners().next().expect("remote listens").clone();
+    node.dial(addr).expect("dials the remote");
+    wait_for_event(node, &mut [remote], "a connection", |event| match event {
+        SwarmEvent::ConnectionEstablished { peer_id, .. } if peer_id == remote_id => Some(()),
+        _ => None,
+    })
+    .await;
+}
+
+/// The gossipsub config of the test clients, with a heartbeat short enough for the mesh to form
+/// quickly.
+pub fn gossipsub_config(message_id: MessageIdScheme) -> gossipsub::ConfigBuilder {
+    let mut config = gossipsub::ConfigBuilder::default();
+    config
+        .heartbeat_interval(Duration::from_millis(100))
+        .message_id_fn(message_id.id_fn());
+    config
+}
+
+/// Gossipsub signing with `key`, not subscribed to anything yet.
+pub fn gossipsub(key: &Keypair, config: gossipsub::Config) -> gossipsub::Behaviour {
+    gossipsub::Behaviour::new(gossipsub::MessageAuthenticity::Signed(key.clone()), config)
+        .expect("valid gossipsub behaviour")
+}
+
+fn node(
+    key: &Keypair,
+    transport: Boxed<(PeerId, StreamMuxerBox)>,
+    relay_client: relay::client::Behaviour,
+    gossipsub: gossipsub::Behaviour,
+) -> Node {
+    let config = BehaviourConfig {
+        fallback_channel: false,
+        // Connections only close when a test closes them.
+        keep_alive: KeepAlive::Always,
+        ..BehaviourConfig::new(key)
+    };
+    let behaviour = Behaviour::new(key, relay_client, gossipsub, config);
+    Node::new(transport, behaviour, key.public().to_peer_id())
+}
+
+/// The gossipsub message of an event, if it is one.
+pub fn message(event: Event) -> Option<gossipsub::Message> {
+    match event {
+        SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Message {
+            message,
+            ..
+        })) => Some(message),
+        _ => None,
+    }
+}
+
+/// Waits for an event of `node` that `matches` picks, while `others` keep running.
+pub async fn wait_for_event<T>(
+    node: &mut Node,
+    others: &mut [&mut Node],
+    what: &str,
+    mut matches: impl FnMut(Event) -> Option<T>,
+) -> T {
+    let wait = async {
+        loop {
+            let event = node.select_next_some().await;
+            if let Some(found) = matches(event) {
+                return found;
+            }
+        }
+    };
+    within(what, alongside(wait, others)).await
+}
+
+/// Publishes `data` to [`TOPIC`] as soon as `node` knows of a peer subscribed to it.
+pub async fn publish(node: &mut Node, others: &mut [&mut Node], data: &[u8]) {
+    publish_to(node, others, topic().hash(), data).await
+}
+
+/// Publishes `data` to `topic` as soon as `node` knows of a peer subscribed to it.
+pub async fn publish_to(node: &mut Node, others: &mut [&mut Node], topic: TopicHash, data: &[u8]) {
+    let publish = async {
+        loop {
+            match node.publish(topic.clone(), data) {
+                Ok(_) => return,
+                Err(PublishError::InsufficientPeers) => {
+                    let tick = futures_timer::Delay::new(Duration::from_millis(100));
+                    future::select(node.select_next_some(), tick).await;
+                }
+                Err(e) => panic!("failed to publish: {e:?}"),
+            }
+        }
+    };
+    within("a peer on the topic", alongside(publish, others)).await
+}
+
+/// Runs `wait` with the swarms of `others` polled meanwhile, their events are dropped.
+pub async fn alongside<T>(wait: impl Future<Output = T>, others: &mut [&mut Node]) -> T {
+    let mut wait = pin!(wait);
+    future::poll_fn(|cx| {
+        for other in others.iter_mut() {
+            while let Poll::Ready(Some(_)) = other.poll_next_unpin(cx) {}
+        }
+        wait.as_mut().poll(cx)
+    })
+    .await
+}
+
+/// Runs `nodes` for `duration`, failing the test if `unexpected` picks any of their events.
+pub async fn quiet(
+    nodes: &mut [&mut Node],
+    duration: Duration,
+    what: &str,
+    mut unexpected: impl FnMut(&Event) -> bool,
+) {
+    let run = future::poll_fn(|cx| {
+        for node in nodes.iter_mut() {
+            while let Poll::Ready(Some(event)) = node.poll_next_unpin(cx) {
+                assert!(!unexpected(&event), "unexpected {what}: {event:?}");
+            }
+        }
+        Poll::<()>::Pending
+    });
+    future::select(pin!(run), futures_timer::Delay::new(duration)).await;
+}
+
+/// Fails the test if `wait` takes longer than [`TIMEOUT`].
+pub async fn within<T>(what: &str, wait: impl Future<Output = T>) -> T {
+    let timeout = futures_timer::Delay::new(TIMEOUT);
+    match future::select(pin!(wait), timeout).await {
+        Either::Left((found, _)) => found,
+        Either::Right(_) => panic!("timed out after {TIMEOUT:?} waiting for {what}"),
+    }
+}
+
+fn localhost() -> Multiaddr {
+    Multiaddr::empty()
+        .with(Protocol::Ip4([127, 0, 0, 1].into()))
+        .with(Protocol::Tcp(0))
+}
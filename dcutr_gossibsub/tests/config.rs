@@ -0,0 +1,150 @@
+//! Settings come from the command line over the environment over the `--config` file over the
+//! defaults.
+
+mod common;
+
+use clap::Parser;
+use dcutr::config::{self, Config};
+use std::env::VarError;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+struct Opts {
+    #[clap(long)]
+    config: Option<PathBuf>,
+
+    #[clap(long)]
+    print_config: bool,
+
+    #[clap(long, default_value = "4001")]
+    port: u16,
+
+    #[clap(long)]
+    topics: Vec<String>,
+
+    #[clap(long)]
+    quiet: bool,
+
+    #[clap(long)]
+    http_token: Option<String>,
+
+    #[clap(long, default_value = "0.5")]
+    ratio: f64,
+}
+
+/// Loads `args` with the variables `vars` set and, if given, a config file holding `file`.
+fn load(name: &str, file: Option<&str>, vars: &[(&str, &str)], args: &[&str]) -> Config<Opts> {
+    let mut argv = vec!["dcutr".to_string()];
+    if let Some(file) = file {
+        let path = common::data_dir(name).join("config.toml");
+        fs::write(&path, file).expect("writes the config file");
+        argv.push(format!("--config={}", path.display()));
+    }
+    argv.extend(args.iter().map(|arg| arg.to_string()));
+    let vars = vars
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect::<Vec<_>>();
+    config::load_from(argv, |name| {
+        vars.iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.clone())
+            .ok_or(VarError::NotPresent)
+    })
+    .expect("loads")
+}
+
+#[test]
+fn defaults_apply_without_any_source() {
+    let config = load("defaults", None, &[], &[]);
+    assert_eq!(config.opts.port, 4001);
+    assert!(config.opts.topics.is_empty());
+    assert!(!config.opts.quiet);
+    assert!(config.from_env.is_empty());
+}
+
+#[test]
+fn the_file_overrides_the_defaults() {
+    let file = "port = 5000\ntopics = [\"a\", \"b\"]\nquiet = true\n";
+    let config = load("file", Some(file), &[], &[]);
+    assert_eq!(config.opts.port, 5000);
+    assert_eq!(config.opts.topics, ["a", "b"]);
+    assert!(config.opts.quiet);
+}
+
+#[test]
+fn the_environment_overrides_the_file() {
+    let file = "port = 5000\ntopics = [\"a\", \"b\"]\nquiet = true\n";
+    let vars = [
+        ("P2P_PORT", "6000"),
+        ("P2P_TOPICS", "c, d"),
+        ("P2P_QUIET", "false"),
+    ];
+    let config = load("env", Some(file), &vars, &[]);
+    assert_eq!(config.opts.port, 6000);
+    assert_eq!(config.opts.topics, ["c", "d"]);
+    assert!(!config.opts.quiet);
+    assert_eq!(
+        config.from_env,
+        ["P2P_PORT=6000", "P2P_TOPICS=c, d", "P2P_QUIET=false"]
+    );
+}
+
+#[test]
+fn the_command_line_overrides_the_environment_and_the_file() {
+    let file = "port = 5000\ntopics = [\"a\", \"b\"]\n";
+    let vars = [("P2P_PORT", "6000"), ("P2P_TOPICS", "c")];
+    let args = ["--port", "7000", "--topics", "e"];
+    let config = load("cli", Some(file), &vars, &args);
+    assert_eq!(config.opts.port, 7000);
+    assert_eq!(config.opts.topics, ["e"]);
+    assert!(config.from_env.is_empty());
+}
+
+#[test]
+fn each_setting_takes_its_own_highest_source() {
+    let file = "port = 5000\ntopics = [\"a\"]\nquiet = true\n";
+    let config = load(
+        "mixed",
+        Some(file),
+        &[("P2P_TOPICS", "b")],
+        &["--port=7000"],
+    );
+    assert_eq!(config.opts.port, 7000);
+    assert_eq!(config.opts.topics, ["b"]);
+    assert!(config.opts.quiet);
+}
+
+#[test]
+fn secrets_are_redacted() {
+    let config = load("secrets", None, &[("P2P_HTTP_TOKEN", "hunter2")], &[]);
+    assert_eq!(config.opts.http_token.as_deref(), Some("hunter2"));
+    assert_eq!(config.from_env, ["P2P_HTTP_TOKEN=<redacted>"]);
+    let effective = config.effective();
+    assert!(!effective.contains("hunter2"), "{effective}");
+    assert!(
+        effective.contains("http_token = \"<redacted>\""),
+        "{effective}"
+    );
+    assert!(effective.contains("port = 4001"), "{effective}");
+}
+
+#[test]
+fn printed_values_keep_the_type_of_their_flag() {
+    let args = ["--port=0080", "--topics=007", "--topics=1.5", "--ratio=nan"];
+    let config = load("typed", None, &[], &args);
+    let effective = config.effective();
+    assert!(effective.contains("port = 80\n"), "{effective}");
+    assert!(
+        effective.contains(r#"topics = ["007", "1.5"]"#),
+        "{effective}"
+    );
+    assert!(effective.contains(r#"ratio = "nan""#), "{effective}");
+
+    // What's printed loads back to the same settings.
+    let reloaded = load("typed-reloaded", Some(&effective), &[], &[]);
+    assert_eq!(reloaded.opts.port, 80);
+    assert_eq!(reloaded.opts.topics, ["007", "1.5"]);
+    assert!(reloaded.opts.ratio.is_nan());
+}
@@ -0,0 +1,169 @@
+//! End-to-end encryption of DMs, against vectors computed independently of this crate: X25519
+//! of the identity keys, HKDF-SHA256 and ChaCha20-Poly1305 as specified in `e2e.rs`.
+
+mod common;
+
+use common::peer;
+use dcutr::dm::DirectMessage;
+use dcutr::e2e::{E2eKey, ReplayGuard, Sealed, REPLAY_WINDOW};
+use dcutr::identity;
+use libp2p::PeerId;
+
+fn key(seed: u8) -> E2eKey {
+    E2eKey::from_ed25519_seed(&identity::ed25519_seed(seed), peer(seed))
+}
+
+fn sealed(nonce: &str, ciphertext: &str) -> Sealed {
+    Sealed {
+        nonce: nonce.to_string(),
+        ciphertext: ciphertext.to_string(),
+    }
+}
+
+#[test]
+fn a_known_ciphertext_opens_on_both_sides() {
+    // Sealed by seed 1 for seed 2 under nonce 00 01 .. 0b with aad "aad", the shared key being
+    // bca576933f3782fdd7e100ae1e7f186bcb37d7ab0c61d1b643da0adf0355b182.
+    let vector = sealed("AAECAwQFBgcICQoL", "ua78XqttfzWYuojvarXeH3wVnekc");
+    assert_eq!(
+        key(2).open(&peer(1), b"aad", &vector).expect("opens"),
+        b"hello"
+    );
+    // The key is shared, so the sender can open what it sealed.
+    assert_eq!(
+        key(1).open(&peer(2), b"aad", &vector).expect("opens"),
+        b"hello"
+    );
+    assert!(key(2).open(&peer(1), b"other", &vector).is_err());
+    assert!(key(3).open(&peer(1), b"aad", &vector).is_err());
+}
+
+#[test]
+fn a_known_dm_opens_with_its_fields_authenticated() {
+    let message = DirectMessage {
+        id: "00000000000000ff".to_string(),
+        from_nick: Some("one".to_string()),
+        sent_at: 1_700_000_000_000,
+        body: String::new(),
+        replayed: false,
+        sealed: Some(sealed(
+            "BwcHBwcHBwcHBwcH",
+            "/eM9KppgjcNnSXFffioKg/jHH7ysqlatwTDV",
+        )),
+    };
+    assert_eq!(
+        message.open(&key(2), peer(1), peer(2)).expect("opens"),
+        "hi from one"
+    );
+
+    for tampered in [
+        DirectMessage {
+            from_nick: Some("two".to_string()),
+            ..message.clone()
+        },
+        DirectMessage {
+            sent_at: message.sent_at + 1,
+            ..message.clone()
+        },
+        DirectMessage {
+            replayed: true,
+            ..message.clone()
+        },
+        DirectMessage {
+            id: "00000000000000fe".to_string(),
+            ..message.clone()
+        },
+    ] {
+        assert!(tampered.open(&key(2), peer(1), peer(2)).is_err());
+    }
+    assert!(
+        message.open(&key(2), peer(3), peer(2)).is_err(),
+        "wrong sender"
+    );
+}
+
+#[test]
+fn a_plaintext_dm_does_not_open() {
+    let message = DirectMessage {
+        id: "00000000000000ff".to_string(),
+        from_nick: Some("one".to_string()),
+        sent_at: 1_700_000_000_000,
+        body: "hi from one".to_string(),
+        replayed: false,
+        sealed: None,
+    };
+    assert_eq!(
+        message.open(&key(2), peer(1), peer(2)),
+        Err("not encrypted".to_string())
+    );
+}
+
+#[test]
+fn sealing_round_trips_under_fresh_nonces() {
+    let first = key(1).seal(&peer(2), b"aad", b"hello").expect("seals");
+    let second = key(1).seal(&peer(2), b"aad", b"hello").expect("seals");
+    assert_ne!(first.nonce, second.nonce);
+    assert_ne!(first.ciphertext, second.ciphertext);
+    for sealed in [first, second] {
+        assert_eq!(
+            key(2).open(&peer(1), b"aad", &sealed).expect("opens"),
+            b"hello"
+        );
+    }
+}
+
+#[test]
+fn peers_without_an_inlined_ed25519_key_are_refused() {
+    let error = key(1)
+        .seal(&PeerId::random(), b"", b"hello")
+        .expect_err("no key to encrypt to");
+    assert!(error.contains("no inlined ed25519 key"), "{error}");
+}
+
+#[test]
+fn replayed_nonces_and_stale_messages_are_rejected() {
+    let window = REPLAY_WINDOW.as_millis() as u64;
+    let now = 10 * window;
+    let sealed = key(1).seal(&peer(2), b"", b"hello").expect("seals");
+    let mut guard = ReplayGuard::default();
+    assert_eq!(guard.check(peer(1), &sealed, now, now), Ok(()));
+    assert!(guard.check(peer(1), &sealed, now, now + 1).is_err());
+    assert_eq!(
+        guard.check(peer(3), &sealed, now, now + 1),
+        Ok(()),
+        "nonces are per peer"
+    );
+
+    let fresh = key(1).seal(&peer(2), b"", b"hello").expect("seals");
+    assert!(guard.check(peer(1), &fresh, now - window - 1, now).is_err());
+    assert!(guard.check(peer(1), &fresh, now + window + 1, now).is_err());
+    assert_eq!(guard.check(peer(1), &fresh, now - window, now), Ok(()));
+}
+
+#[test]
+fn a_timestamp_near_the_end_of_time_is_rejected() {
+    let window = REPLAY_WINDOW.as_millis() as u64;
+    let now = 10 * window;
+    let mut guard = ReplayGuard::default();
+    for sent_at in [u64::MAX, u64::MAX - window + 1] {
+        let sealed = key(1).seal(&peer(2), b"", b"hello").expect("seals");
+        assert!(guard.check(peer(1), &sealed, sent_at, now).is_err());
+    }
+}
+
+#[test]
+fn messages_dated_ahead_are_forgotten_like_the_others() {
+    let window = REPLAY_WINDOW.as_millis() as u64;
+    let now = 10 * window;
+    let mut guard = ReplayGuard::default();
+    let ahead = key(1).seal(&peer(2), b"", b"hello").expect("seals");
+    assert_eq!(guard.check(peer(1), &ahead, now + window, now), Ok(()));
+    let later = key(1).seal(&peer(2), b"", b"hello").expect("seals");
+    assert_eq!(guard.check(peer(1), &later, now + 1, now + 1), Ok(()));
+
+    // Both are past the window by now and were dropped in the order they came.
+    let after = now + 2 * window + 2;
+    let next = key(1).seal(&peer(2), b"", b"hello").expect("seals");
+    assert_eq!(guard.check(peer(1), &next, after, after), Ok(()));
+    assert_eq!(guard.len(), 1);
+}
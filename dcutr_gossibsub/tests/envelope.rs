@@ -0,0 +1,150 @@
+//! Envelopes survive the trip through the wire, and payloads that aren't envelopes are still
+//! shown.
+
+mod common;
+
+use common::peer;
+use dcutr::envelope::{render, Envelope, Kind, WireFormat};
+use libp2p::gossipsub::{IdentTopic, TopicHash};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+fn topic() -> TopicHash {
+    IdentTopic::new("chat").hash()
+}
+
+fn envelope(body: &str) -> Envelope {
+    Envelope {
+        origin: peer(1).to_base58(),
+        seq: 42,
+        topic_seq: None,
+        from_nick: Some("alice".to_string()),
+        sent_at: 0,
+        kind: Kind::Chat,
+        compressed: false,
+        key_id: None,
+        replayed: false,
+        bridge: None,
+        forwarded_from: Vec::new(),
+        expires_at: None,
+        body: body.to_string(),
+        signature: None,
+    }
+}
+
+fn shown(data: &[u8]) -> String {
+    render(data, None, 1 << 20, &topic(), &[]).expect("renders")
+}
+
+#[test]
+fn json_envelopes_round_trip() {
+    let envelope = Envelope {
+        topic_seq: Some(3),
+        sent_at: 1_700_000_000_000,
+        kind: Kind::Presence,
+        ..envelope("hello \u{1F44B}")
+    };
+    let data = envelope.encode(WireFormat::Json);
+    assert_eq!(data[0], b'{');
+    assert_eq!(Envelope::decode(&data), Some(envelope));
+}
+
+#[test]
+fn envelopes_are_shown_with_time_and_nick() {
+    let data = envelope("hello").encode(WireFormat::Json);
+    assert_eq!(shown(&data), "--:-- <alice unverified> hello");
+
+    let anonymous = Envelope {
+        from_nick: None,
+        ..envelope("hi")
+    };
+    let origin = peer(1).to_base58();
+    assert_eq!(
+        shown(&anonymous.encode(WireFormat::Json)),
+        format!("--:-- <{} unverified> hi", &origin[origin.len() - 8..])
+    );
+}
+
+#[test]
+fn origins_that_are_not_peer_ids_are_shown_whole() {
+    // The last 8 bytes start inside the é.
+    let anonymous = Envelope {
+        origin: "caf\u{e9}1234567".to_string(),
+        from_nick: None,
+        ..envelope("hi")
+    };
+    assert_eq!(anonymous.sender(), "caf\u{e9}1234567");
+    assert_eq!(
+        shown(&anonymous.encode(WireFormat::Json)),
+        "--:-- <caf\u{e9}1234567 unverified> hi"
+    );
+}
+
+#[test]
+fn envelopes_of_older_peers_parse_with_defaults() {
+    let data = br#"{"origin":"someone","seq":1,"body":"from the past"}"#;
+    let envelope = Envelope::decode(data).expect("decodes");
+    assert_eq!(envelope.kind, Kind::Chat);
+    assert_eq!(envelope.sent_at, 0);
+    assert_eq!(envelope.from_nick, None);
+    assert_eq!(envelope.body, "from the past");
+}
+
+#[test]
+fn malformed_payloads_are_shown_raw() {
+    for raw in [
+        &b"just a line"[..],
+        b"{not json",
+        br#"{"origin":"someone"}"#,
+        b"",
+    ] {
+        assert_eq!(Envelope::decode(raw), None);
+        assert_eq!(
+            shown(raw),
+            format!("[raw] {}", String::from_utf8_lossy(raw))
+        );
+    }
+    assert_eq!(shown(&[0xff, 0x00, 0x7f]), "[hex] ff007f");
+}
+
+#[test]
+fn envelopes_round_trip_in_both_wire_formats() {
+    let envelope = Envelope {
+        topic_seq: Some(7),
+        sent_at: 1_700_000_000_000,
+        forwarded_from: vec!["other".to_string()],
+        expires_at: Some(1_700_000_060_000),
+        ..envelope("telemetry 42")
+    };
+    let json = envelope.encode(WireFormat::Json);
+    let cbor = envelope.encode(WireFormat::Cbor);
+    assert_eq!(cbor[0], 0x01, "CBOR is tagged");
+    assert!(cbor.len() < json.len());
+    assert_eq!(Envelope::decode(&json), Some(envelope.clone()));
+    assert_eq!(Envelope::decode(&cbor), Some(envelope));
+}
+
+#[test]
+fn random_bytes_never_break_the_decoder() {
+    let mut rng = StdRng::seed_from_u64(546);
+    let valid = envelope("hello").encode(WireFormat::Cbor);
+    for round in 0..2_000 {
+        let mut data = match round % 3 {
+            // Random garbage behind each tag, and damaged envelopes.
+            0 => vec![0x01],
+            1 => vec![b'{'],
+            _ => valid.clone(),
+        };
+        let len = rng.gen_range(0..64);
+        match round % 3 {
+            2 => {
+                for _ in 0..=len % 4 {
+                    let at = rng.gen_range(0..data.len());
+                    data[at] = rng.gen();
+                }
+            }
+            _ => data.extend((0..len).map(|_| rng.gen::<u8>())),
+        }
+        let _ = Envelope::decode(&data);
+        assert!(render(&data, None, 1 << 20, &topic(), &[]).is_ok());
+    }
+}
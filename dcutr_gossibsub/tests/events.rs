@@ -0,0 +1,189 @@
+//! Lifecycle events come out of `--event-file` with stable names and fields.
+
+mod common;
+
+use common::{data_dir, peer};
+use dcutr::events::{EventLog, NodeEvent};
+use dcutr::identity;
+use libp2p::{
+    core::{multiaddr::Multiaddr, ConnectedPoint, Endpoint},
+    dcutr::Event as HolePunchEvent,
+    identify, relay,
+};
+use serde_json::{json, Value};
+use std::fs;
+
+fn addr(s: &str) -> Multiaddr {
+    s.parse().expect("valid multiaddr")
+}
+
+fn value(event: &NodeEvent) -> Value {
+    serde_json::to_value(event).expect("serializes")
+}
+
+#[test]
+fn connections_carry_their_endpoint() {
+    let dialed = ConnectedPoint::Dialer {
+        address: addr("/ip4/1.2.3.4/tcp/4001"),
+        role_override: Endpoint::Dialer,
+    };
+    assert_eq!(
+        value(&NodeEvent::connection_established(&peer(1), &dialed)),
+        json!({
+            "event": "connection_established",
+            "peer_id": peer(1).to_string(),
+            "endpoint": {"role": "dialer", "address": "/ip4/1.2.3.4/tcp/4001", "relayed": false},
+        })
+    );
+
+    // An inbound circuit, accepted on the relayed listen address.
+    let accepted = ConnectedPoint::Listener {
+        local_addr: addr(&format!(
+            "/ip4/1.2.3.4/tcp/4001/p2p/{}/p2p-circuit",
+            peer(3)
+        )),
+        send_back_addr: addr(&format!("/p2p/{}", peer(2))),
+    };
+    assert_eq!(
+        value(&NodeEvent::connection_closed(
+            &peer(2),
+            &accepted,
+            Some("timeout".to_string())
+        )),
+        json!({
+            "event": "connection_closed",
+            "peer_id": peer(2).to_string(),
+            "endpoint": {"role": "listener", "address": format!("/p2p/{}", peer(2)), "relayed": true},
+            "cause": "timeout",
+        })
+    );
+}
+
+#[test]
+fn listen_addresses() {
+    assert_eq!(
+        value(&NodeEvent::listen_addr(&addr("/ip4/127.0.0.1/tcp/4001"))),
+        json!({"event": "listen_addr", "address": "/ip4/127.0.0.1/tcp/4001"})
+    );
+}
+
+#[test]
+fn relay_events() {
+    let accepted = relay::client::Event::ReservationReqAccepted {
+        relay_peer_id: peer(3),
+        renewal: true,
+        limit: None,
+    };
+    assert_eq!(
+        NodeEvent::from_relay(&accepted).map(|e| value(&e)),
+        Some(json!({
+            "event": "reservation_accepted",
+            "relay_peer_id": peer(3).to_string(),
+            "renewal": true,
+        }))
+    );
+
+    let outbound = relay::client::Event::OutboundCircuitEstablished {
+        relay_peer_id: peer(3),
+        limit: None,
+    };
+    assert_eq!(
+        NodeEvent::from_relay(&outbound).map(|e| value(&e)),
+        Some(json!({
+            "event": "circuit_established",
+            "direction": "outbound",
+            "peer_id": peer(3).to_string(),
+        }))
+    );
+
+    let denied = relay::client::Event::InboundCircuitReqDenied {
+        src_peer_id: peer(2),
+    };
+    assert_eq!(
+        NodeEvent::from_relay(&denied).map(|e| value(&e)),
+        Some(json!({
+            "event": "circuit_failed",
+            "direction": "inbound",
+            "peer_id": peer(2).to_string(),
+            "error": "denied",
+        }))
+    );
+}
+
+#[test]
+fn hole_punch_events() {
+    let initiated = HolePunchEvent::RemoteInitiatedDirectConnectionUpgrade {
+        remote_peer_id: peer(2),
+        remote_relayed_addr: addr("/ip4/1.2.3.4/tcp/4001/p2p-circuit"),
+    };
+    assert_eq!(
+        NodeEvent::from_dcutr(&initiated).map(|e| value(&e)),
+        Some(json!({
+            "event": "hole_punch_started",
+            "peer_id": peer(2).to_string(),
+            "initiator": "remote",
+        }))
+    );
+
+    let succeeded = HolePunchEvent::DirectConnectionUpgradeSucceeded {
+        remote_peer_id: peer(2),
+    };
+    assert_eq!(
+        NodeEvent::from_dcutr(&succeeded).map(|e| value(&e)),
+        Some(json!({"event": "hole_punch_succeeded", "peer_id": peer(2).to_string()}))
+    );
+}
+
+#[test]
+fn only_received_identify_info_is_an_event() {
+    let info = identify::Info {
+        public_key: identity::generate_ed25519(2).public(),
+        protocol_version: "/dcutr/1.0.0".to_string(),
+        agent_version: "dcutr/0.1.0".to_string(),
+        listen_addrs: vec![addr("/ip4/10.0.0.2/tcp/4001")],
+        protocols: vec!["/meshsub/1.1.0".to_string()],
+        observed_addr: addr("/ip4/1.2.3.4/tcp/5555"),
+    };
+    let received = identify::Event::Received {
+        peer_id: peer(2),
+        info,
+    };
+    assert_eq!(
+        NodeEvent::from_identify(&received).map(|e| value(&e)),
+        Some(json!({
+            "event": "identify_received",
+            "peer_id": peer(2).to_string(),
+            "agent_version": "dcutr/0.1.0",
+            "protocol_version": "/dcutr/1.0.0",
+            "listen_addrs": ["/ip4/10.0.0.2/tcp/4001"],
+            "observed_addr": "/ip4/1.2.3.4/tcp/5555",
+            "protocols": ["/meshsub/1.1.0"],
+        }))
+    );
+
+    let sent = identify::Event::Sent { peer_id: peer(2) };
+    assert!(NodeEvent::from_identify(&sent).is_none());
+}
+
+#[test]
+fn the_event_file_gets_one_stamped_line_per_event() {
+    let path = data_dir("events").join("events.ndjson");
+    let mut log = EventLog::open(&path).expect("opens");
+    log.write(&NodeEvent::listen_addr(&addr("/ip4/127.0.0.1/tcp/4001")));
+    log.write(&NodeEvent::PublishError {
+        topic: "chat".to_string(),
+        error: "InsufficientPeers".to_string(),
+    });
+    drop(log);
+
+    let lines = fs::read_to_string(&path).expect("reads");
+    let lines: Vec<Value> = lines
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("one JSON object per line"))
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["event"], "listen_addr");
+    assert!(lines[0]["timestamp"].as_u64().unwrap() > 1_700_000_000_000);
+    assert_eq!(lines[1]["event"], "publish_error");
+    assert_eq!(lines[1]["topic"], "chat");
+}
@@ -0,0 +1,59 @@
+//! What the client reports when it exits, here after failing to reach a relay.
+//!
+//! Binds sockets and takes a few seconds, run with `cargo test -- --ignored`.
+
+#![cfg(feature = "tokio")]
+
+mod common;
+
+use dcutr::client::{self, Opts};
+use dcutr::config;
+use dcutr::console;
+use std::env::VarError;
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+#[tokio::test]
+#[ignore = "binds sockets and takes a few seconds"]
+async fn the_hole_punch_summary_is_reported_on_exit() {
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let sink = lines.clone();
+    console::set_sink(move |line| {
+        sink.lock().unwrap().push(line);
+        Ok(())
+    });
+    let dir = common::data_dir("exit");
+    let input = dir.join("input.txt");
+    fs::write(&input, "").expect("writes the input");
+    // Nothing listens there, so the bootstrap times out.
+    let relay = format!("/ip4/127.0.0.1/tcp/1/p2p/{}", common::peer(9));
+    let config = config::load_from::<Opts>(
+        [
+            "dcutr",
+            "--mode",
+            "listen",
+            "--secret-key-seed",
+            "1",
+            "--relay-address",
+            relay.as_str(),
+            "--bootstrap-timeout",
+            "1",
+            "--no-persist",
+            "--data-dir",
+            dir.to_str().expect("utf-8 path"),
+            "--input",
+            input.to_str().expect("utf-8 path"),
+        ],
+        |_| Err(VarError::NotPresent),
+    )
+    .expect("parses");
+    client::run(config).await.expect("exits");
+
+    let lines = lines.lock().unwrap();
+    assert!(
+        lines
+            .iter()
+            .any(|line| line.starts_with("holepunch: 0 attempts, 0 success, 0 failed")),
+        "{lines:?}"
+    );
+}
@@ -0,0 +1,102 @@
+//! Envelopes with an `expires_at` are dropped once it passed: in validation, in history and on
+//! replay of the message log.
+
+use dcutr::envelope::{unix_millis, Envelope, Kind, KindTtl, Sequencer, WireFormat};
+use dcutr::history::{History, HistoryRequest};
+use dcutr::identity;
+use dcutr::message_log::LogRecord;
+use dcutr::signing::{self, Verification};
+use dcutr::validation::Freshness;
+use libp2p::gossipsub::IdentTopic;
+use std::time::Duration;
+
+const SKEW: Duration = Duration::from_secs(30);
+
+/// A signed presence, expiring `expires_in` milliseconds from now, negative for the past.
+fn presence(expires_in: i64) -> (Envelope, Vec<u8>) {
+    let key = identity::generate_ed25519(1);
+    let mut envelope = Sequencer::new(key.public().to_peer_id(), None).wrap(Kind::Presence, "here");
+    envelope.expires_at = Some((unix_millis() as i64 + expires_in) as u64);
+    let envelope = envelope.signed(&key);
+    let data = envelope.encode(WireFormat::Json);
+    (envelope, data)
+}
+
+#[test]
+fn ttls_are_set_per_kind() {
+    let peer_id = identity::generate_ed25519(1).public().to_peer_id();
+    let ttl = "presence=10".parse::<KindTtl>().expect("parses");
+    let mut sequencer = Sequencer::new(peer_id, None).with_ttls(&[ttl]);
+    let presence = sequencer.wrap(Kind::Presence, "here");
+    assert_eq!(presence.expires_at, Some(presence.sent_at + 10_000));
+    assert_eq!(sequencer.wrap(Kind::Chat, "hello").expires_at, None);
+    for broken in ["presence", "typing=10", "presence=soon"] {
+        assert!(broken.parse::<KindTtl>().is_err(), "{broken} parsed");
+    }
+}
+
+#[test]
+fn expired_messages_are_ignored_in_validation() {
+    let freshness = Freshness {
+        max_age: Duration::from_secs(300),
+        skew: SKEW,
+    };
+    let (_, live) = presence(5_000);
+    assert!(freshness.check(&live, unix_millis()).is_ok());
+    // Within the clock skew it still counts as live.
+    let (_, barely) = presence(-10_000);
+    assert!(freshness.check(&barely, unix_millis()).is_ok());
+    let (_, expired) = presence(-60_000);
+    let rejection = freshness.check(&expired, unix_millis()).unwrap_err();
+    assert_eq!(rejection.kind, "expired");
+    assert!(
+        !rejection.penalize,
+        "expiring on the way isn't the sender's fault"
+    );
+}
+
+#[test]
+fn the_expiry_is_signed() {
+    let (mut envelope, _) = presence(5_000);
+    envelope.expires_at = envelope.expires_at.map(|expires_at| expires_at + 3_600_000);
+    assert!(matches!(
+        signing::verify(&envelope, None),
+        Verification::Invalid(_)
+    ));
+}
+
+#[test]
+fn history_leaves_out_expired_messages() {
+    let topic = IdentTopic::new("test-net").hash();
+    let mut history = History::new(10).with_clock_skew(SKEW);
+    let (_, gone) = presence(-60_000);
+    assert!(!history.record(&topic, &gone));
+
+    // Recorded while the skew still allowed it, but no longer served.
+    let (_, stale) = presence(-1_000);
+    let (_, live) = presence(60_000);
+    assert!(history.record(&topic, &stale));
+    assert!(history.record(&topic, &live));
+    let response = history.respond(&HistoryRequest {
+        topic: topic.to_string(),
+        limit: 10,
+        since: None,
+    });
+    assert_eq!(response.entries.len(), 1);
+}
+
+#[test]
+fn log_records_remember_the_expiry() {
+    let topic = IdentTopic::new("test-net").hash();
+    let (envelope, data) = presence(-1_000);
+    let record = LogRecord::new(
+        &topic,
+        "test-net".to_string(),
+        String::new(),
+        None,
+        &data,
+        1 << 20,
+    );
+    assert_eq!(record.expires_at, envelope.expires_at);
+    assert!(record.is_expired(unix_millis()));
+}
@@ -0,0 +1,167 @@
+//! The direct channel opens for peers connected without a gossipsub mesh and closes once the
+//! mesh forms.
+
+mod common;
+
+use common::peer;
+use dcutr::envelope::{Envelope, Kind, Sequencer, WireFormat};
+use dcutr::fallback::{Change, FallbackChannel, FallbackMessage};
+use libp2p::gossipsub::{IdentTopic, TopicHash};
+use std::time::{Duration, Instant};
+
+fn topic(name: &str) -> TopicHash {
+    IdentTopic::new(name).hash()
+}
+
+#[test]
+fn the_channel_opens_without_a_mesh_and_closes_once_it_forms() {
+    let start = Instant::now();
+    let mut channel = FallbackChannel::new(Duration::from_secs(30), 1);
+    let remote = peer(2);
+    let topics = [topic("chat")];
+    channel.on_connected(remote, start);
+
+    let changes = channel.poll(start + Duration::from_secs(29), &topics, |_| 0, |_, _| true);
+    assert!(changes.is_empty(), "not before the threshold");
+    // Seen again later, the first time counts.
+    channel.on_connected(remote, start + Duration::from_secs(20));
+
+    let changes = channel.poll(start + Duration::from_secs(30), &topics, |_| 0, |_, _| true);
+    assert_eq!(
+        changes,
+        [Change::Opened {
+            peer_id: remote,
+            topic: topics[0].clone()
+        }]
+    );
+    assert_eq!(channel.peers(&topics[0]), [remote]);
+    assert!(channel
+        .poll(start + Duration::from_secs(31), &topics, |_| 0, |_, _| true)
+        .is_empty());
+
+    let changes = channel.poll(start + Duration::from_secs(40), &topics, |_| 1, |_, _| true);
+    assert_eq!(
+        changes,
+        [Change::Closed {
+            peer_id: remote,
+            topic: topics[0].clone()
+        }]
+    );
+    assert!(channel.peers(&topics[0]).is_empty());
+    assert!(channel
+        .poll(start + Duration::from_secs(41), &topics, |_| 1, |_, _| true)
+        .is_empty());
+}
+
+#[test]
+fn only_topics_short_of_the_minimum_mesh_get_a_channel() {
+    let start = Instant::now();
+    let mut channel = FallbackChannel::new(Duration::ZERO, 2);
+    let remote = peer(2);
+    let (meshed, thin) = (topic("meshed"), topic("thin"));
+    channel.on_connected(remote, start);
+    let changes = channel.poll(
+        start,
+        [&meshed, &thin],
+        |t| match *t == meshed {
+            true => 3,
+            false => 1,
+        },
+        |_, _| true,
+    );
+    assert_eq!(
+        changes,
+        [Change::Opened {
+            peer_id: remote,
+            topic: thin.clone()
+        }]
+    );
+    assert!(channel.is_open(&remote, &thin));
+    assert!(!channel.is_open(&remote, &meshed));
+
+    channel.on_left(&thin);
+    assert!(!channel.is_open(&remote, &thin));
+}
+
+#[test]
+fn disconnected_and_unsupported_peers_lose_their_channel() {
+    let start = Instant::now();
+    let mut channel = FallbackChannel::new(Duration::ZERO, 1);
+    let (a, b) = (peer(2), peer(3));
+    let topics = [topic("chat")];
+    channel.on_connected(a, start);
+    channel.on_connected(b, start);
+    assert_eq!(channel.poll(start, &topics, |_| 0, |_, _| true).len(), 2);
+
+    assert_eq!(channel.on_disconnected(&a), topics);
+    assert_eq!(channel.on_unsupported(b), topics);
+    assert!(channel.peers(&topics[0]).is_empty());
+    assert!(
+        channel.poll(start, &topics, |_| 0, |_, _| true).is_empty(),
+        "neither is tried again"
+    );
+}
+
+#[test]
+fn only_peers_on_the_topic_get_a_channel() {
+    let start = Instant::now();
+    let mut channel = FallbackChannel::new(Duration::ZERO, 1);
+    let (member, stranger) = (peer(2), peer(3));
+    let topics = [topic("chat")];
+    channel.on_connected(member, start);
+    channel.on_connected(stranger, start);
+
+    let changes = channel.poll(start, &topics, |_| 0, |p, _| *p == member);
+    assert_eq!(
+        changes,
+        [Change::Opened {
+            peer_id: member,
+            topic: topics[0].clone()
+        }]
+    );
+    assert_eq!(channel.peers(&topics[0]), [member]);
+
+    // The member left the topic, the mesh still hasn't formed.
+    let changes = channel.poll(start, &topics, |_| 0, |_, _| false);
+    assert_eq!(
+        changes,
+        [Change::Closed {
+            peer_id: member,
+            topic: topics[0].clone()
+        }]
+    );
+    assert!(channel.peers(&topics[0]).is_empty());
+}
+
+#[test]
+fn messages_carry_the_published_bytes() {
+    let message = FallbackMessage::new("chat".to_string(), &[0, 159, 255]);
+    let json = serde_json::to_string(&message).expect("serializes");
+    let decoded: FallbackMessage = serde_json::from_str(&json).expect("deserializes");
+    assert_eq!(decoded.topic, "chat");
+    assert_eq!(decoded.data().expect("valid base64"), [0, 159, 255]);
+}
+
+#[test]
+fn a_message_is_delivered_once_over_either_channel() {
+    let mut channel = FallbackChannel::new(Duration::from_secs(30), 1);
+    let mut sequencer = Sequencer::new(peer(2), None);
+    let first = sequencer.wrap(Kind::Chat, "hi").encode(WireFormat::Json);
+    let second = sequencer.wrap(Kind::Chat, "hi").encode(WireFormat::Json);
+
+    assert!(channel.on_delivered(&first), "directly");
+    assert!(
+        !channel.on_delivered(&first),
+        "the same message over gossipsub later"
+    );
+    // Same envelope, other encoding.
+    let envelope = Envelope::decode(&second).expect("an envelope");
+    assert!(channel.on_delivered(&envelope.encode(WireFormat::Cbor)));
+    assert!(!channel.on_delivered(&second));
+
+    assert!(channel.on_delivered(b"bare"));
+    assert!(
+        channel.on_delivered(b"bare"),
+        "bare payloads can't be told apart"
+    );
+}
@@ -0,0 +1,11 @@
+#!/bin/sh
+# Records what an --on-message hook is handed, in the file named by its first argument.
+{
+    echo "topic=$P2P_TOPIC"
+    echo "from=$P2P_FROM"
+    echo "id=$P2P_MSG_ID"
+    echo "path=$P2P_PATH"
+    echo "body=$(cat)"
+} > "$1.tmp"
+mv "$1.tmp" "$1"
+exit "${2:-0}"
@@ -0,0 +1,93 @@
+//! With flood publishing a message reaches a peer as soon as it subscribed, not only once a
+//! heartbeat grafted it into the mesh.
+
+#![cfg(feature = "tokio")]
+
+mod common;
+
+use dcutr::behaviour::BehaviourEvent;
+use dcutr::mesh::PublishParams;
+use dcutr::message_id::MessageIdScheme;
+use dcutr::node::Node;
+use libp2p::{gossipsub, swarm::SwarmEvent};
+use std::time::Duration;
+
+/// Far beyond how long the test waits, no heartbeat runs in it.
+const HEARTBEAT: Duration = Duration::from_secs(60);
+
+async fn spawn(secret_key_seed: u8, flood_publish: bool) -> Node {
+    common::spawn_memory_node_with(secret_key_seed, |key| {
+        let params = PublishParams {
+            flood_publish: Some(flood_publish),
+            heartbeat_initial_delay: Some(HEARTBEAT),
+            allow_self_origin: false,
+        };
+        params.validate(HEARTBEAT).expect("valid publish params");
+        let mut builder = common::gossipsub_config(MessageIdScheme::Sha256);
+        builder.heartbeat_interval(HEARTBEAT);
+        let config = params.apply(&mut builder).build().expect("valid config");
+        let mut gossipsub = common::gossipsub(key, config);
+        gossipsub.subscribe(&common::topic()).expect("subscribes");
+        gossipsub
+    })
+    .await
+}
+
+#[tokio::test]
+async fn flood_publish_delivers_before_the_first_heartbeat() {
+    let mut a = spawn(1, true).await;
+    let mut b = spawn(2, true).await;
+    common::connect(&mut a, &mut b).await;
+    let b_id = *b.local_peer_id();
+    common::wait_for_event(
+        &mut a,
+        &mut [&mut b],
+        "b's subscription",
+        |event| match event {
+            SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Subscribed {
+                peer_id,
+                ..
+            })) if peer_id == b_id => Some(()),
+            _ => None,
+        },
+    )
+    .await;
+    assert!(
+        a.behaviour()
+            .gossipsub
+            .mesh_peers(&common::topic().hash())
+            .next()
+            .is_none(),
+        "no heartbeat grafted b yet"
+    );
+
+    a.publish(common::topic(), b"right away".to_vec())
+        .expect("floods to the subscribed peer");
+    let received =
+        common::wait_for_event(&mut b, &mut [&mut a], "the message", common::message).await;
+    assert_eq!(received.data, b"right away");
+}
+
+#[tokio::test]
+async fn without_flood_publish_nothing_goes_out_before_the_mesh_forms() {
+    let mut a = spawn(1, false).await;
+    let mut b = spawn(2, false).await;
+    common::connect(&mut a, &mut b).await;
+    common::wait_for_event(
+        &mut a,
+        &mut [&mut b],
+        "b's subscription",
+        |event| match event {
+            SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Subscribed {
+                ..
+            })) => Some(()),
+            _ => None,
+        },
+    )
+    .await;
+
+    assert!(matches!(
+        a.publish(common::topic(), b"too early".to_vec()),
+        Err(gossipsub::PublishError::InsufficientPeers)
+    ));
+}
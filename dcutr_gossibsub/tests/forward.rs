@@ -0,0 +1,103 @@
+//! `--forward` between topics, and loops between forwarding clients ending.
+
+use dcutr::envelope::{Envelope, Kind, Sequencer, WireFormat};
+use dcutr::forward::{ForwardSpec, Forwarder};
+use dcutr::identity;
+use dcutr::signing::{self, Verification};
+
+fn forwarder(spec: &str) -> Forwarder {
+    Forwarder::new(&[spec.parse::<ForwardSpec>().expect("valid spec")])
+}
+
+/// A signed chat message of the peer with seed 1.
+fn message(body: &str) -> Vec<u8> {
+    let key = identity::generate_ed25519(1);
+    Sequencer::new(key.public().to_peer_id(), None)
+        .wrap(Kind::Chat, body)
+        .signed(&key)
+        .encode(WireFormat::Json)
+}
+
+#[test]
+fn specs_parse() {
+    let one_way = "rooms/old=rooms/new"
+        .parse::<ForwardSpec>()
+        .expect("parses");
+    assert_eq!(
+        one_way.routes,
+        [("rooms/old".to_string(), "rooms/new".to_string())]
+    );
+    let both_ways = "a~b".parse::<ForwardSpec>().expect("parses");
+    assert_eq!(
+        both_ways.routes,
+        [
+            ("a".to_string(), "b".to_string()),
+            ("b".to_string(), "a".to_string())
+        ]
+    );
+    for broken in ["a", "a=", "=b", "a=a", "a~b=c"] {
+        assert!(broken.parse::<ForwardSpec>().is_err(), "{broken} parsed");
+    }
+}
+
+#[test]
+fn forwarded_messages_keep_their_author() {
+    let mut forwarder = forwarder("rooms/old=rooms/new");
+    let forwards = forwarder.on_message("rooms/old", &message("hello"));
+    assert_eq!(forwards.len(), 1);
+    let (to, envelope) = &forwards[0];
+    assert_eq!(to, "rooms/new");
+    assert_eq!(envelope.forwarded_from, ["rooms/old"]);
+    assert_eq!(envelope.body, "hello");
+
+    // Published by the forwarder, still signed by the author.
+    let forwarder_id = identity::generate_ed25519(2).public().to_peer_id();
+    let author = identity::generate_ed25519(1).public().to_peer_id();
+    assert_eq!(
+        signing::verify(envelope, Some(forwarder_id)),
+        Verification::Forwarded {
+            origin: author,
+            by: forwarder_id
+        }
+    );
+    assert!(forwarder
+        .on_message("rooms/new", &message("other way"))
+        .is_empty());
+}
+
+#[test]
+fn two_forwarding_clients_dont_loop() {
+    let mut first = forwarder("a~b");
+    let mut second = forwarder("a~b");
+    let (to, envelope) = first.on_message("a", &message("hello")).remove(0);
+    assert_eq!(to, "b");
+    let data = envelope.encode(WireFormat::Cbor);
+    // The second client sees it on b and would send it back to a.
+    assert!(second.on_message("b", &data).is_empty());
+    assert!(second.lines()[1].contains("0 forwarded, 1 looped"));
+}
+
+#[test]
+fn longer_loops_end_too() {
+    let mut forwarder = Forwarder::new(&[
+        "a=b".parse().expect("valid spec"),
+        "b=c".parse().expect("valid spec"),
+        "c=a".parse().expect("valid spec"),
+    ]);
+    let (_, envelope) = forwarder.on_message("a", &message("round")).remove(0);
+    let (_, envelope) = forwarder
+        .on_message("b", &envelope.encode(WireFormat::Json))
+        .remove(0);
+    assert_eq!(envelope.forwarded_from, ["a", "b"]);
+    assert!(forwarder
+        .on_message("c", &envelope.encode(WireFormat::Json))
+        .is_empty());
+}
+
+#[test]
+fn raw_payloads_are_skipped() {
+    let mut forwarder = forwarder("a=b");
+    assert!(forwarder.on_message("a", b"bare line").is_empty());
+    assert!(Envelope::decode(b"bare line").is_none());
+    assert!(forwarder.lines()[0].contains("1 skipped"));
+}
@@ -0,0 +1,232 @@
+//! Relayed connections are closed once a hole punch brought up a direct one, and only then.
+
+mod common;
+
+use common::peer;
+use dcutr::holepunch::{HolePunchStats, RelayedConnections};
+use libp2p::{
+    core::{multiaddr::Multiaddr, ConnectedPoint, Endpoint},
+    swarm::ConnectionId,
+};
+use std::time::{Duration, Instant};
+
+const GRACE: Duration = Duration::from_secs(5);
+
+fn relayed_addr() -> Multiaddr {
+    format!("/ip4/198.51.100.1/tcp/4001/p2p/{}/p2p-circuit", peer(100))
+        .parse()
+        .expect("valid address")
+}
+
+fn direct_addr() -> Multiaddr {
+    "/ip4/203.0.113.9/tcp/5000".parse().expect("valid address")
+}
+
+fn dialed(address: Multiaddr) -> ConnectedPoint {
+    ConnectedPoint::Dialer {
+        address,
+        role_override: Endpoint::Dialer,
+    }
+}
+
+/// A circuit the remote opened to us, the relay only shows in our address.
+fn inbound_circuit() -> ConnectedPoint {
+    ConnectedPoint::Listener {
+        local_addr: relayed_addr(),
+        send_back_addr: format!("/p2p/{}", peer(2)).parse().expect("valid address"),
+    }
+}
+
+#[test]
+fn only_the_relayed_connection_is_closed_after_the_grace_period() {
+    let mut connections = RelayedConnections::new(GRACE);
+    let remote = peer(2);
+    let relayed = ConnectionId::new_unchecked(1);
+    connections.on_established(remote, relayed, &dialed(relayed_addr()));
+    connections.on_established(
+        remote,
+        ConnectionId::new_unchecked(2),
+        &dialed(direct_addr()),
+    );
+    connections.on_upgraded(remote);
+
+    assert!(
+        connections.due(Instant::now()).is_empty(),
+        "streams in flight get the grace period"
+    );
+    let due = connections.due(Instant::now() + GRACE);
+    assert_eq!(due, [(remote, vec![relayed], direct_addr())]);
+    assert!(connections.due(Instant::now() + GRACE * 2).is_empty());
+}
+
+#[test]
+fn relayed_connections_stay_without_a_hole_punch() {
+    let mut connections = RelayedConnections::new(GRACE);
+    let remote = peer(2);
+    connections.on_established(
+        remote,
+        ConnectionId::new_unchecked(1),
+        &dialed(relayed_addr()),
+    );
+    connections.on_established(
+        remote,
+        ConnectionId::new_unchecked(2),
+        &dialed(direct_addr()),
+    );
+
+    assert!(connections.due(Instant::now() + GRACE).is_empty());
+}
+
+#[test]
+fn relayed_connections_stay_once_the_direct_one_is_gone() {
+    let mut connections = RelayedConnections::new(GRACE);
+    let remote = peer(2);
+    let direct = ConnectionId::new_unchecked(2);
+    connections.on_established(
+        remote,
+        ConnectionId::new_unchecked(1),
+        &dialed(relayed_addr()),
+    );
+    connections.on_upgraded(remote);
+    connections.on_established(remote, direct, &dialed(direct_addr()));
+    connections.on_closed(remote, direct);
+
+    assert!(
+        connections.due(Instant::now() + GRACE).is_empty(),
+        "the relay is all that is left"
+    );
+}
+
+#[test]
+fn inbound_circuits_are_relayed_connections_too() {
+    let mut connections = RelayedConnections::new(GRACE);
+    let remote = peer(2);
+    let relayed = ConnectionId::new_unchecked(1);
+    connections.on_established(remote, relayed, &inbound_circuit());
+    connections.on_established(
+        remote,
+        ConnectionId::new_unchecked(2),
+        &dialed(direct_addr()),
+    );
+    connections.on_upgraded(remote);
+
+    let due = connections.due(Instant::now() + GRACE);
+    assert_eq!(due, [(remote, vec![relayed], direct_addr())]);
+}
+
+/// Two clients meet through an in-process relay and hole punch on localhost. The one that took
+/// the circuit closes it like the event loop does, its direct connection stays.
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn only_the_direct_connection_remains_after_a_hole_punch() {
+    use dcutr::behaviour::BehaviourEvent;
+    use futures::StreamExt;
+    use libp2p::{
+        core::multiaddr::Protocol,
+        dcutr::Event as HolePunchEvent,
+        relay,
+        swarm::{AddressScore, SwarmEvent},
+    };
+    use std::collections::HashSet;
+
+    let relay_addr = common::spawn_relay(0).await;
+    let mut listener = common::spawn_node(1).await;
+    let mut dialer = common::spawn_node(2).await;
+    for node in [&mut listener, &mut dialer] {
+        // What the other side dials to punch the hole.
+        let addr = common::wait_for_event(node, &mut [], "a listen address", |event| match event {
+            SwarmEvent::NewListenAddr { address, .. } => Some(address),
+            _ => None,
+        })
+        .await;
+        node.add_external_address(addr, AddressScore::Infinite);
+    }
+    let listener_id = *listener.local_peer_id();
+    let dialer_id = *dialer.local_peer_id();
+
+    listener
+        .listen_on(relay_addr.clone().with(Protocol::P2pCircuit))
+        .expect("listens through the relay");
+    common::wait_for_event(
+        &mut listener,
+        &mut [],
+        "the relay reservation",
+        |event| match event {
+            SwarmEvent::Behaviour(BehaviourEvent::RelayClient(
+                relay::client::Event::ReservationReqAccepted { .. },
+            )) => Some(()),
+            _ => None,
+        },
+    )
+    .await;
+    dialer
+        .dial_peer(listener_id, &relay_addr)
+        .expect("dials the listener through the relay");
+
+    let mut connections = RelayedConnections::new(Duration::ZERO);
+    let mut relayed = HashSet::new();
+    let track = async {
+        loop {
+            match listener.select_next_some().await {
+                SwarmEvent::ConnectionEstablished {
+                    peer_id,
+                    connection_id,
+                    endpoint,
+                    ..
+                } if peer_id == dialer_id => {
+                    if endpoint.is_relayed() {
+                        relayed.insert(connection_id);
+                    }
+                    connections.on_established(peer_id, connection_id, &endpoint);
+                }
+                SwarmEvent::Behaviour(BehaviourEvent::Dcutr(
+                    HolePunchEvent::DirectConnectionUpgradeSucceeded { remote_peer_id },
+                )) => connections.on_upgraded(remote_peer_id),
+                SwarmEvent::ConnectionClosed {
+                    peer_id,
+                    connection_id,
+                    ..
+                } if peer_id == dialer_id => {
+                    assert!(
+                        relayed.contains(&connection_id),
+                        "the direct connection closed"
+                    );
+                    return;
+                }
+                _ => {}
+            }
+            for (_, due, _) in connections.due(Instant::now()) {
+                for connection_id in due {
+                    listener.close_connection(connection_id);
+                }
+            }
+        }
+    };
+    common::within(
+        "the relayed connection to close",
+        common::alongside(track, &mut [&mut dialer]),
+    )
+    .await;
+    assert_eq!(
+        relayed.len(),
+        1,
+        "the circuit was an inbound relayed connection"
+    );
+    assert!(
+        listener.is_connected(&dialer_id),
+        "the direct connection stays"
+    );
+}
+
+#[test]
+fn stats_name_the_failure_kind_and_time_the_success() {
+    let mut stats = HolePunchStats::default();
+    stats.on_attempt(peer(1));
+    let kind = stats.on_failure(peer(1), "Connect(InboundError(Io(Kind(TimedOut))))");
+    assert_eq!(kind, "Connect");
+
+    stats.on_attempt(peer(2));
+    let time_to_direct = stats.on_success(peer(2));
+    assert!(time_to_direct < Duration::from_secs(1));
+    assert_eq!(stats.median_time_to_direct(), Some(time_to_direct));
+}
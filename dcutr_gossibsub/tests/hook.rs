@@ -0,0 +1,108 @@
+//! `--on-message` runs a command per received message, here the fixture script recording what
+//! it was handed.
+
+mod common;
+
+use dcutr::hook::{Hook, HookMessage};
+use dcutr::identity;
+use dcutr::paths::TransportPath;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn out(name: &str) -> PathBuf {
+    common::data_dir(&format!("hook-{name}")).join("record")
+}
+
+fn fixture(out: &Path, exit: u8) -> String {
+    format!(
+        "sh {}/tests/fixtures/hook.sh \"{}\" {exit}",
+        env!("CARGO_MANIFEST_DIR"),
+        out.display()
+    )
+}
+
+fn message(topic: &str, body: &str) -> HookMessage {
+    HookMessage {
+        topic: topic.to_string(),
+        message_id: "3132".to_string(),
+        source: Some(identity::generate_ed25519(1).public().to_peer_id()),
+        path: Some(TransportPath::Direct),
+        body: body.to_string(),
+        body_encoding: "utf8",
+    }
+}
+
+/// The fixture's record, once it appeared.
+fn record(path: &Path, within: Duration) -> Option<String> {
+    let deadline = Instant::now() + within;
+    while Instant::now() < deadline {
+        if let Ok(record) = fs::read_to_string(path) {
+            return Some(record);
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    None
+}
+
+#[test]
+fn the_hook_gets_the_metadata_and_the_body() {
+    let out = out("metadata");
+    let hook = Hook::start(fixture(&out, 0), Vec::new(), 1, Duration::from_secs(10));
+    hook.on_message(message("chat", "hello there"));
+    let record = record(&out, Duration::from_secs(10)).expect("the hook ran");
+    let from = identity::generate_ed25519(1).public().to_peer_id();
+    assert_eq!(
+        record,
+        format!("topic=chat\nfrom={from}\nid=3132\npath=direct\nbody=hello there\n")
+    );
+}
+
+#[test]
+fn other_topics_are_filtered_out() {
+    let out = out("filtered");
+    let hook = Hook::start(
+        fixture(&out, 0),
+        vec!["alerts".to_string()],
+        1,
+        Duration::from_secs(10),
+    );
+    assert!(hook.wants("alerts"));
+    assert!(!hook.wants("chat"));
+    hook.on_message(message("chat", "not for the hook"));
+    assert_eq!(record(&out, Duration::from_millis(500)), None);
+}
+
+#[test]
+fn failing_and_hanging_hooks_dont_stop_later_ones() {
+    // Each run records into a file of its own topic and exits with 3.
+    let failing = out("failing");
+    let second = PathBuf::from(format!("{}-second", failing.display()));
+    let hook = Hook::start(
+        fixture(&failing, 3).replace("\" 3", "-$P2P_TOPIC\" 3"),
+        Vec::new(),
+        1,
+        Duration::from_secs(10),
+    );
+    hook.on_message(message("first", "first"));
+    hook.on_message(message("second", "second"));
+    let record = record(&second, Duration::from_secs(10)).expect("the second hook ran");
+    assert!(record.contains("body=second"), "{record}");
+
+    // A single slot, taken by a hook that only frees it by being killed.
+    let after = out("after-hang");
+    let hook = Hook::start(
+        format!(
+            "[ \"$P2P_TOPIC\" = slow ] && sleep 60; {}",
+            fixture(&after, 0)
+        ),
+        Vec::new(),
+        1,
+        Duration::from_millis(200),
+    );
+    hook.on_message(message("slow", "hangs"));
+    hook.on_message(message("chat", "still running"));
+    let record = record(&after, Duration::from_secs(10)).expect("the later hook ran");
+    assert!(record.contains("body=still running"), "{record}");
+}
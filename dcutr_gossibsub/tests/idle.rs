@@ -0,0 +1,53 @@
+//! Idle connections being closed, on the in-memory transport.
+
+#![cfg(feature = "tokio")]
+
+mod common;
+
+use dcutr::idle::IdleConnections;
+use dcutr::message_id::MessageIdScheme;
+use futures::future::{self, Either};
+use futures::StreamExt;
+use libp2p::swarm::SwarmEvent;
+use std::time::{Duration, Instant};
+
+const IDLE_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[tokio::test]
+async fn idle_peers_are_disconnected_unless_explicit() {
+    let mut node = common::spawn_memory_node(1, MessageIdScheme::Sha256).await;
+    let mut explicit = common::spawn_memory_node(2, MessageIdScheme::Sha256).await;
+    let mut stranger = common::spawn_memory_node(3, MessageIdScheme::Sha256).await;
+    let explicit_id = *explicit.local_peer_id();
+    let stranger_id = *stranger.local_peer_id();
+    for remote in [&explicit, &stranger] {
+        let addr = remote.listeners().next().expect("remote listens").clone();
+        node.dial(addr).expect("dials the remote");
+    }
+
+    let mut idle = IdleConnections::new(IDLE_TIMEOUT);
+    let started = Instant::now();
+    let wait = async {
+        loop {
+            let tick = futures_timer::Delay::new(Duration::from_millis(50));
+            if let Either::Left((event, _)) = future::select(node.select_next_some(), tick).await {
+                idle.record(&event, Instant::now());
+                if let SwarmEvent::ConnectionClosed { peer_id, .. } = event {
+                    return peer_id;
+                }
+            }
+            for peer_id in idle.due(Instant::now(), |peer_id| peer_id == &explicit_id) {
+                let _ = node.disconnect_peer_id(peer_id);
+            }
+        }
+    };
+    let closed = common::within(
+        "the idle connection to close",
+        common::alongside(wait, &mut [&mut explicit, &mut stranger]),
+    )
+    .await;
+
+    assert_eq!(closed, stranger_id);
+    assert!(started.elapsed() >= IDLE_TIMEOUT);
+    assert!(node.is_connected(&explicit_id));
+}
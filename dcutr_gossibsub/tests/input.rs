@@ -0,0 +1,53 @@
+//! `--input` read from a regular file and from a named pipe.
+
+mod common;
+
+use dcutr::input;
+use futures::{executor::block_on, StreamExt};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn path(name: &str) -> PathBuf {
+    common::data_dir(&format!("input-{name}")).join("input")
+}
+
+#[test]
+fn a_file_is_read_line_by_line_and_ends() {
+    let path = path("file");
+    fs::write(&path, "hello\n/peers\nbye\n").expect("writes the input");
+    let lines = input::from_path(&path, Duration::from_millis(1), false).expect("opens");
+    assert_eq!(
+        block_on(lines.collect::<Vec<_>>()),
+        ["hello", "/peers", "bye"]
+    );
+}
+
+#[test]
+fn missing_paths_fail_up_front() {
+    let error = input::from_path(&path("missing"), Duration::ZERO, false)
+        .err()
+        .expect("fails");
+    assert!(error.contains("failed to read --input"), "{error}");
+    let error = input::from_path(&std::env::temp_dir(), Duration::ZERO, false)
+        .err()
+        .expect("fails");
+    assert!(error.contains("neither a regular file"), "{error}");
+}
+
+#[cfg(unix)]
+#[test]
+fn a_named_pipe_outlives_its_writers() {
+    let path = path("fifo");
+    let status = std::process::Command::new("mkfifo")
+        .arg(&path)
+        .status()
+        .expect("runs mkfifo");
+    assert!(status.success());
+    let mut lines = input::from_path(&path, Duration::ZERO, false).expect("opens");
+    // Each write opens and closes the pipe, the second one finds it opened again.
+    fs::write(&path, "first\n").expect("writes to the pipe");
+    assert_eq!(block_on(lines.next()).as_deref(), Some("first"));
+    fs::write(&path, "second\n").expect("writes to the pipe again");
+    assert_eq!(block_on(lines.next()).as_deref(), Some("second"));
+}
@@ -0,0 +1,170 @@
+//! The connection gate caps incoming relay circuits without touching direct connections.
+
+mod common;
+
+use common::peer;
+use dcutr::limits::{ConnectionGate, Limits};
+use libp2p::{
+    core::{
+        multiaddr::{Multiaddr, Protocol},
+        ConnectedPoint,
+    },
+    swarm::{
+        behaviour::{ConnectionClosed, ConnectionEstablished},
+        dummy, ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour,
+    },
+    PeerId,
+};
+
+fn gate(max: usize) -> ConnectionGate {
+    ConnectionGate::new(Limits {
+        max_relayed_incoming: Some(max),
+        ..Limits::default()
+    })
+}
+
+/// The local and remote address of a connection from `peer_id`, through the relay or not.
+fn addrs(peer_id: PeerId, relayed: bool) -> (Multiaddr, Multiaddr) {
+    match relayed {
+        true => (
+            "/ip4/198.51.100.1/tcp/4001"
+                .parse::<Multiaddr>()
+                .expect("valid address")
+                .with(Protocol::P2p(peer(100).into()))
+                .with(Protocol::P2pCircuit),
+            Multiaddr::empty().with(Protocol::P2p(peer_id.into())),
+        ),
+        false => (
+            "/ip4/0.0.0.0/tcp/5000".parse().expect("valid address"),
+            "/ip4/192.168.1.20/tcp/6000".parse().expect("valid address"),
+        ),
+    }
+}
+
+/// Lets a connection from `peer_id` in as far as the gate allows.
+fn connect(
+    gate: &mut ConnectionGate,
+    id: usize,
+    peer_id: PeerId,
+    relayed: bool,
+) -> Result<(), ConnectionDenied> {
+    let connection_id = ConnectionId::new_unchecked(id);
+    let (local_addr, send_back_addr) = addrs(peer_id, relayed);
+    gate.handle_pending_inbound_connection(connection_id, &local_addr, &send_back_addr)?;
+    gate.handle_established_inbound_connection(
+        connection_id,
+        peer_id,
+        &local_addr,
+        &send_back_addr,
+    )?;
+    let endpoint = ConnectedPoint::Listener {
+        local_addr,
+        send_back_addr,
+    };
+    gate.on_swarm_event(FromSwarm::ConnectionEstablished(ConnectionEstablished {
+        peer_id,
+        connection_id,
+        endpoint: &endpoint,
+        failed_addresses: &[],
+        other_established: 0,
+    }));
+    Ok(())
+}
+
+fn close(gate: &mut ConnectionGate, id: usize, peer_id: PeerId, relayed: bool) {
+    let (local_addr, send_back_addr) = addrs(peer_id, relayed);
+    let endpoint = ConnectedPoint::Listener {
+        local_addr,
+        send_back_addr,
+    };
+    gate.on_swarm_event(FromSwarm::ConnectionClosed(ConnectionClosed {
+        peer_id,
+        connection_id: ConnectionId::new_unchecked(id),
+        endpoint: &endpoint,
+        handler: dummy::ConnectionHandler,
+        remaining_established: 0,
+    }));
+}
+
+#[test]
+fn circuits_beyond_the_cap_are_denied_but_direct_connections_are_not() {
+    let mut gate = gate(2);
+    connect(&mut gate, 1, peer(1), true).expect("first circuit");
+    connect(&mut gate, 2, peer(2), true).expect("second circuit");
+    let denied = connect(&mut gate, 3, peer(3), true).unwrap_err();
+    assert!(denied.to_string().contains("relayed incoming"), "{denied}");
+    connect(&mut gate, 4, peer(3), false).expect("a direct connection isn't capped");
+    assert_eq!(gate.relayed_incoming(), 2);
+    assert_eq!(
+        gate.to_string(),
+        "relayed incoming: 2 open (at most 2), 1 denied"
+    );
+}
+
+#[test]
+fn protected_peers_bypass_the_cap() {
+    let mut gate = gate(1);
+    gate.protect(peer(9));
+    connect(&mut gate, 1, peer(1), true).expect("first circuit");
+    connect(&mut gate, 2, peer(9), true).expect("a known peer gets in");
+    assert!(connect(&mut gate, 3, peer(3), true).is_err());
+}
+
+#[test]
+fn closed_and_upgraded_circuits_free_their_slot() {
+    let mut gate = gate(2);
+    connect(&mut gate, 1, peer(1), true).expect("first circuit");
+    connect(&mut gate, 2, peer(2), true).expect("second circuit");
+    assert!(connect(&mut gate, 3, peer(3), true).is_err());
+
+    close(&mut gate, 1, peer(1), true);
+    assert_eq!(gate.relayed_incoming(), 1);
+    connect(&mut gate, 4, peer(3), true).expect("a closed circuit's slot is free");
+
+    // The hole punch succeeded, the circuit of peer 2 is on its way out.
+    connect(&mut gate, 5, peer(2), false).expect("direct connection");
+    assert_eq!(gate.relayed_incoming(), 1);
+    connect(&mut gate, 6, peer(4), true).expect("an upgraded circuit's slot is free");
+    close(&mut gate, 2, peer(2), true);
+    assert_eq!(gate.relayed_incoming(), 2);
+}
+
+#[test]
+fn protected_peers_get_in_directly_beyond_the_incoming_caps() {
+    let mut gate = ConnectionGate::new(Limits {
+        max_established_incoming: Some(1),
+        max_pending_incoming: Some(1),
+        ..Limits::default()
+    });
+    gate.protect(peer(9));
+    connect(&mut gate, 1, peer(1), false).expect("first connection");
+    assert!(connect(&mut gate, 2, peer(2), false).is_err());
+    // A direct dial of the remote after a hole punch, its peer id only known after the
+    // handshake.
+    connect(&mut gate, 3, peer(9), false).expect("a protected peer gets in");
+}
+
+#[test]
+fn the_pending_cap_leaves_a_slot_for_each_protected_peer() {
+    let mut gate = ConnectionGate::new(Limits {
+        max_pending_incoming: Some(1),
+        ..Limits::default()
+    });
+    gate.protect(peer(9));
+    let (local_addr, send_back_addr) = addrs(peer(1), false);
+    for id in 1..=2 {
+        gate.handle_pending_inbound_connection(
+            ConnectionId::new_unchecked(id),
+            &local_addr,
+            &send_back_addr,
+        )
+        .expect("within the cap and the headroom");
+    }
+    assert!(gate
+        .handle_pending_inbound_connection(
+            ConnectionId::new_unchecked(3),
+            &local_addr,
+            &send_back_addr,
+        )
+        .is_err());
+}
@@ -0,0 +1,149 @@
+//! Message handling between clients on the in-memory transport, fast enough to run every time.
+
+#![cfg(feature = "tokio")]
+
+mod common;
+
+use dcutr::behaviour::BehaviourEvent;
+use dcutr::envelope::{Envelope, Kind, Sequencer, WireFormat};
+use dcutr::history::History;
+use dcutr::identity;
+use dcutr::message_id::MessageIdScheme;
+use dcutr::node::Node;
+use dcutr::signing::{self, Verification};
+use futures::StreamExt;
+use libp2p::{
+    gossipsub::PublishError,
+    request_response::{self, Message},
+    swarm::SwarmEvent,
+};
+use std::time::Duration;
+
+/// Two connected clients, with secret key seeds 1 and 2.
+async fn pair(message_id: MessageIdScheme) -> (Node, Node) {
+    let mut a = common::spawn_memory_node(1, message_id).await;
+    let mut b = common::spawn_memory_node(2, message_id).await;
+    common::connect(&mut a, &mut b).await;
+    (a, b)
+}
+
+#[tokio::test]
+async fn envelopes_arrive_intact_in_both_wire_formats() {
+    let (mut a, mut b) = pair(MessageIdScheme::Sha256).await;
+    let key = identity::generate_ed25519(1);
+    let mut sequencer = Sequencer::new(*a.local_peer_id(), Some("a".to_string()));
+    for format in [WireFormat::Json, WireFormat::Cbor] {
+        let envelope = sequencer.wrap(Kind::Chat, "hello").signed(&key);
+        common::publish(&mut a, &mut [&mut b], &envelope.encode(format)).await;
+        let received =
+            common::wait_for_event(&mut b, &mut [&mut a], "the envelope", common::message).await;
+        let decoded = Envelope::decode(&received.data).expect("decodes");
+        assert_eq!(decoded, envelope);
+        assert!(matches!(
+            signing::verify(&decoded, received.source),
+            Verification::Verified(_)
+        ));
+    }
+}
+
+#[tokio::test]
+async fn history_is_served_to_peers_that_ask() {
+    let (mut a, mut b) = pair(MessageIdScheme::Sha256).await;
+    let topic = common::topic().hash();
+    let key = identity::generate_ed25519(1);
+    let mut sequencer = Sequencer::new(*a.local_peer_id(), None);
+    let mut served = History::new(10);
+    let sent = (0..3)
+        .map(|i| {
+            let data = sequencer
+                .wrap(Kind::Chat, &format!("message {i}"))
+                .signed(&key)
+                .encode(WireFormat::Json);
+            served.record(&topic, &data);
+            data
+        })
+        .collect::<Vec<_>>();
+
+    let mut asking = History::new(10);
+    let a_id = *a.local_peer_id();
+    let request_id = b
+        .behaviour_mut()
+        .history
+        .send_request(&a_id, asking.request(&topic));
+    asking.on_request_sent(request_id, topic.clone());
+    let (request, channel) =
+        common::wait_for_event(&mut a, &mut [&mut b], "the request", |event| match event {
+            SwarmEvent::Behaviour(BehaviourEvent::History(request_response::Event::Message {
+                message:
+                    Message::Request {
+                        request, channel, ..
+                    },
+                ..
+            })) => Some((request, channel)),
+            _ => None,
+        })
+        .await;
+    a.behaviour_mut()
+        .history
+        .send_response(channel, served.respond(&request))
+        .expect("the requester is still connected");
+    let response =
+        common::wait_for_event(&mut b, &mut [&mut a], "the response", |event| match event {
+            SwarmEvent::Behaviour(BehaviourEvent::History(request_response::Event::Message {
+                message: Message::Response { response, .. },
+                ..
+            })) => Some(response),
+            _ => None,
+        })
+        .await;
+    let (merged_topic, merged) = asking
+        .merge(&request_id, response)
+        .expect("the request is known");
+    assert_eq!(merged_topic, topic);
+    assert_eq!(merged, sent);
+}
+
+#[tokio::test]
+async fn identical_payloads_are_deduplicated_by_the_legacy_id() {
+    let (mut a, mut b) = pair(MessageIdScheme::Legacy).await;
+    common::publish(&mut a, &mut [&mut b], b"same").await;
+    assert!(matches!(
+        a.publish(common::topic(), b"same".to_vec()),
+        Err(PublishError::Duplicate)
+    ));
+    common::wait_for_event(&mut b, &mut [&mut a], "the message", common::message).await;
+    // Nothing else may arrive.
+    let rest = common::alongside(
+        futures_timer::Delay::new(Duration::from_millis(300)),
+        &mut [&mut a],
+    );
+    let mut received = 0;
+    common::within(
+        "the quiet period",
+        futures::future::select(
+            Box::pin(rest),
+            Box::pin(async {
+                while let Some(event) = b.next().await {
+                    if common::message(event).is_some() {
+                        received += 1;
+                    }
+                }
+            }),
+        ),
+    )
+    .await;
+    assert_eq!(received, 0);
+}
+
+#[tokio::test]
+async fn identical_payloads_are_separate_messages_by_the_sha256_id() {
+    let (mut a, mut b) = pair(MessageIdScheme::Sha256).await;
+    common::publish(&mut a, &mut [&mut b], b"same").await;
+    a.publish(common::topic(), b"same".to_vec())
+        .expect("not a duplicate");
+    for _ in 0..2 {
+        let received =
+            common::wait_for_event(&mut b, &mut [&mut a], "the message", common::message).await;
+        assert_eq!(received.data, b"same");
+    }
+}
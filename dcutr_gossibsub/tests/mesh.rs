@@ -0,0 +1,102 @@
+//! Mesh and publish parameters are checked before gossipsub sees them, and small values let
+//! messages flow between a handful of peers.
+
+#![cfg(feature = "tokio")]
+
+mod common;
+
+use dcutr::behaviour::BehaviourEvent;
+use dcutr::mesh::{MeshParams, PublishParams};
+use dcutr::message_id::MessageIdScheme;
+use dcutr::node::Node;
+use libp2p::{gossipsub, swarm::SwarmEvent};
+use std::time::Duration;
+
+/// The parameters the docs suggest for two or three peers.
+fn small() -> MeshParams {
+    MeshParams::new(Some(2), Some(1), Some(4), Some(0), None)
+}
+
+/// A client subscribed to the test topic with `mesh` applied.
+async fn spawn(secret_key_seed: u8, mesh: MeshParams) -> Node {
+    common::spawn_memory_node_with(secret_key_seed, |key| {
+        let mut builder = common::gossipsub_config(MessageIdScheme::Sha256);
+        let config = mesh.apply(&mut builder).build().expect("valid mesh");
+        let mut gossipsub = common::gossipsub(key, config);
+        gossipsub.subscribe(&common::topic()).expect("subscribes");
+        gossipsub
+    })
+    .await
+}
+
+#[test]
+fn mesh_bounds_are_checked_with_readable_errors() {
+    assert_eq!(small().validate(), Ok(()));
+    assert_eq!(
+        MeshParams::new(None, None, None, None, None).validate(),
+        Ok(())
+    );
+
+    let error = MeshParams::new(Some(2), Some(3), Some(4), Some(0), None)
+        .validate()
+        .unwrap_err();
+    assert!(
+        error.contains("mesh_n_low <= mesh_n <= mesh_n_high"),
+        "{error}"
+    );
+    let error = MeshParams::new(Some(5), Some(1), Some(4), Some(0), None)
+        .validate()
+        .unwrap_err();
+    assert!(error.contains("got 1 <= 5 <= 4"), "{error}");
+    let error = MeshParams::new(Some(2), Some(1), Some(4), Some(2), None)
+        .validate()
+        .unwrap_err();
+    assert!(error.contains("mesh_outbound_min (2)"), "{error}");
+}
+
+#[test]
+fn the_initial_heartbeat_delay_is_at_most_the_interval() {
+    let params = |delay| PublishParams {
+        flood_publish: None,
+        heartbeat_initial_delay: Some(Duration::from_millis(delay)),
+        allow_self_origin: false,
+    };
+    assert!(params(500).validate(Duration::from_secs(1)).is_ok());
+    assert!(params(1_000).validate(Duration::from_secs(1)).is_ok());
+    assert!(params(1_001).validate(Duration::from_secs(1)).is_err());
+}
+
+#[tokio::test]
+async fn messages_flow_along_a_chain_of_three_with_mesh_n_low_of_one() {
+    let mut a = spawn(1, small()).await;
+    let mut b = spawn(2, small()).await;
+    let mut c = spawn(3, small()).await;
+    common::connect(&mut a, &mut b).await;
+    common::connect(&mut b, &mut c).await;
+    let a_id = *a.local_peer_id();
+    // a and c know nothing of each other, b has to forward what a publishes.
+    common::wait_for_event(
+        &mut b,
+        &mut [&mut a, &mut c],
+        "c's subscription",
+        |event| match event {
+            SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Subscribed {
+                peer_id,
+                ..
+            })) if peer_id != a_id => Some(()),
+            _ => None,
+        },
+    )
+    .await;
+
+    common::publish(&mut a, &mut [&mut b, &mut c], b"along the chain").await;
+    let received = common::wait_for_event(
+        &mut c,
+        &mut [&mut a, &mut b],
+        "the message",
+        common::message,
+    )
+    .await;
+    assert_eq!(received.data, b"along the chain");
+    assert_eq!(received.source, Some(a_id));
+}
@@ -0,0 +1,60 @@
+//! Message ids tell apart messages differing in a single byte and don't change between runs.
+
+mod common;
+
+use common::peer;
+use dcutr::message_id::{sha256_id, MessageIdScheme};
+use libp2p::gossipsub::{IdentTopic, Message, MessageId};
+
+fn message(data: &[u8], sequence_number: Option<u64>) -> Message {
+    Message {
+        source: None,
+        data: data.to_vec(),
+        sequence_number,
+        topic: IdentTopic::new("chat").hash(),
+    }
+}
+
+#[test]
+fn ids_differ_for_messages_one_byte_apart() {
+    let id = sha256_id(&message(b"hello", None));
+    assert_ne!(id, sha256_id(&message(b"hellp", None)));
+    assert_ne!(id, sha256_id(&message(b"hello\0", None)));
+    assert_ne!(id, sha256_id(&message(b"hello", Some(0))));
+    assert_ne!(
+        id,
+        sha256_id(&Message {
+            source: Some(peer(1)),
+            ..message(b"hello", None)
+        })
+    );
+}
+
+#[test]
+fn ids_are_the_same_in_every_run() {
+    assert_eq!(
+        sha256_id(&message(b"hello", None)),
+        MessageId::from("EqIC6QT-nFe6JMd_tII9UhSz3txI9OLzx2_mq2oB-iE")
+    );
+    assert_eq!(
+        sha256_id(&message(b"hello", Some(7))),
+        MessageId::from("2KbdM09AhGnCTbPYSp7h9pJt7kw7Z159o2jxYjsZWAA")
+    );
+    assert_eq!(
+        MessageIdScheme::Sha256.id_fn()(&message(b"hello", None)),
+        sha256_id(&message(b"hello", None))
+    );
+}
+
+#[test]
+fn the_scheme_is_picked_by_name() {
+    assert_eq!(
+        "sha256".parse::<MessageIdScheme>(),
+        Ok(MessageIdScheme::Sha256)
+    );
+    assert_eq!(
+        "legacy".parse::<MessageIdScheme>(),
+        Ok(MessageIdScheme::Legacy)
+    );
+    assert!("md5".parse::<MessageIdScheme>().is_err());
+}
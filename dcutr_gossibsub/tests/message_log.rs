@@ -0,0 +1,120 @@
+//! The message log survives a crash in the middle of a write and stays within its size cap.
+
+mod common;
+
+use common::{data_dir, peer};
+use dcutr::message_log::{LogRecord, MessageLog};
+use libp2p::gossipsub::TopicHash;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+const MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+fn record(body: &str) -> LogRecord {
+    LogRecord::new(
+        &TopicHash::from_raw("chat"),
+        "chat".to_string(),
+        body.to_string(),
+        Some(peer(2)),
+        body.as_bytes(),
+        1 << 20,
+    )
+}
+
+/// Opens the log of `peer(1)` in `dir`, returning it with the bodies it replayed.
+fn open(dir: &Path, max_size: u64) -> (MessageLog, Vec<String>) {
+    let mut bodies = Vec::new();
+    let log = MessageLog::open(dir, &peer(1), max_size, |record| bodies.push(record.body))
+        .expect("opens the log");
+    (log, bodies)
+}
+
+fn path(dir: &Path) -> std::path::PathBuf {
+    dir.join(format!("messages-{}.jsonl", peer(1)))
+}
+
+#[test]
+fn a_torn_record_left_by_a_crash_is_cut_off() {
+    let dir = data_dir("message-log-torn");
+    let (mut log, bodies) = open(&dir, MAX_SIZE);
+    assert!(bodies.is_empty());
+    log.append(&record("one"));
+    log.append(&record("two"));
+    log.sync();
+    drop(log);
+    let complete = fs::metadata(path(&dir)).expect("log exists").len();
+
+    // A crash in the middle of the third write.
+    let mut line = serde_json::to_vec(&record("three")).expect("serializes");
+    line.truncate(line.len() / 2);
+    OpenOptions::new()
+        .append(true)
+        .open(path(&dir))
+        .expect("opens the log file")
+        .write_all(&line)
+        .expect("writes");
+
+    let (mut log, bodies) = open(&dir, MAX_SIZE);
+    assert_eq!(bodies, ["one", "two"]);
+    assert_eq!(
+        fs::metadata(path(&dir)).expect("log exists").len(),
+        complete
+    );
+
+    log.append(&record("four"));
+    log.sync();
+    drop(log);
+    let (_, bodies) = open(&dir, MAX_SIZE);
+    assert_eq!(
+        bodies,
+        ["one", "two", "four"],
+        "appends after the cut are readable"
+    );
+}
+
+#[test]
+fn unreadable_records_in_the_middle_are_skipped() {
+    let dir = data_dir("message-log-garbage");
+    let (mut log, _) = open(&dir, MAX_SIZE);
+    log.append(&record("one"));
+    log.sync();
+    drop(log);
+    let mut lines = b"not json\n".to_vec();
+    lines.extend(serde_json::to_vec(&record("two")).expect("serializes"));
+    lines.push(b'\n');
+    OpenOptions::new()
+        .append(true)
+        .open(path(&dir))
+        .expect("opens the log file")
+        .write_all(&lines)
+        .expect("writes");
+    let len = fs::metadata(path(&dir)).expect("log exists").len();
+
+    let (_, bodies) = open(&dir, MAX_SIZE);
+    assert_eq!(bodies, ["one", "two"]);
+    assert_eq!(fs::metadata(path(&dir)).expect("log exists").len(), len);
+}
+
+#[test]
+fn the_log_is_rotated_once_it_outgrows_its_cap() {
+    let dir = data_dir("message-log-rotation");
+    let size = serde_json::to_vec(&record("0")).expect("serializes").len() as u64 + 1;
+    // Room for three records, rotated on the fourth.
+    let (mut log, _) = open(&dir, size * 3);
+    for body in 0..10 {
+        log.append(&record(&body.to_string()));
+    }
+    log.sync();
+    drop(log);
+
+    let rotated = dir.join(format!("messages-{}.jsonl.1", peer(1)));
+    assert!(fs::metadata(path(&dir)).expect("log exists").len() <= size * 3);
+    assert!(fs::metadata(rotated).expect("rotated log exists").len() <= size * 4);
+    let (_, bodies) = open(&dir, size * 3);
+    assert_eq!(
+        bodies,
+        ["4", "5", "6", "7", "8", "9"],
+        "only the last two files are kept"
+    );
+}
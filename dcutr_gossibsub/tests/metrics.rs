@@ -0,0 +1,157 @@
+//! `/metrics` serves the swarm's and the chat's series, fed from the events of a local exchange.
+
+#![cfg(feature = "tokio")]
+
+mod common;
+
+use dcutr::message_id::MessageIdScheme;
+use dcutr::metrics::{self, Metrics};
+use dcutr::node::Node;
+use dcutr::paths::ConnectionPaths;
+use dcutr::rtt::RttStats;
+use libp2p::{
+    core::{ConnectedPoint, Endpoint},
+    swarm::{ConnectionId, SwarmEvent},
+};
+use prometheus_client::{encoding::text::encode, registry::Registry};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+/// The body of `GET /metrics` at `addr`.
+fn scrape(addr: SocketAddr) -> String {
+    let mut stream = TcpStream::connect(addr).expect("connects to the metrics endpoint");
+    write!(stream, "GET /metrics HTTP/1.0\r\nHost: {addr}\r\n\r\n").expect("sends the request");
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .expect("reads the response");
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .expect("a complete response");
+    assert!(
+        head.starts_with("HTTP/1.1 200") || head.starts_with("HTTP/1.0 200"),
+        "{head}"
+    );
+    body.to_string()
+}
+
+/// The sum of the samples of the series starting with `name`, `None` if there are none.
+fn sample(body: &str, name: &str) -> Option<f64> {
+    let values = body
+        .lines()
+        .filter(|line| line.starts_with(name))
+        .filter_map(|line| line.rsplit(' ').next()?.parse::<f64>().ok())
+        .collect::<Vec<_>>();
+    (!values.is_empty()).then(|| values.iter().sum())
+}
+
+/// Runs `b` until `data` from `a` arrives, recording each of its events like the event loop does.
+async fn receive(recorder: &Metrics, a: &mut Node, b: &mut Node, data: &[u8]) {
+    common::wait_for_event(b, &mut [a], "the message", |event| {
+        recorder.on_event(&event);
+        common::message(event).filter(|message| message.data == data)
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn scraped_series_grow_with_the_exchange() {
+    let mut registry = Registry::default();
+    let recorder = Metrics::new(&mut registry);
+    let any_port = SocketAddr::from(([127, 0, 0, 1], 0));
+    let addr = metrics::serve(any_port, registry).expect("serves the metrics");
+
+    let mut a = common::spawn_memory_node(1, MessageIdScheme::Sha256).await;
+    let mut b = common::spawn_memory_node(2, MessageIdScheme::Sha256).await;
+    let b_addr = b.listeners().next().expect("b listens").clone();
+    a.dial(b_addr).expect("dials b");
+    common::wait_for_event(&mut b, &mut [&mut a], "a connection", |event| {
+        recorder.on_event(&event);
+        matches!(event, SwarmEvent::ConnectionEstablished { .. }).then_some(())
+    })
+    .await;
+
+    common::publish(&mut a, &mut [&mut b], b"first").await;
+    receive(&recorder, &mut a, &mut b, b"first").await;
+    let first = scrape(addr);
+    let connections = "libp2p_swarm_connections_established_total";
+    assert_eq!(sample(&first, connections), Some(1.0), "{first}");
+    let gossipsub = "libp2p_gossipsub_messages_total";
+    assert_eq!(sample(&first, gossipsub), Some(1.0), "{first}");
+    assert!(
+        first.contains("# TYPE chat_messages_received counter"),
+        "chat series are registered: {first}"
+    );
+
+    common::publish(&mut a, &mut [&mut b], b"second").await;
+    receive(&recorder, &mut a, &mut b, b"second").await;
+    let second = scrape(addr);
+    assert_eq!(sample(&second, connections), Some(1.0), "{second}");
+    assert_eq!(sample(&second, gossipsub), Some(2.0), "{second}");
+}
+
+#[test]
+fn the_round_trip_times_of_a_closed_connection_are_dropped() {
+    let mut registry = Registry::default();
+    let recorder = Metrics::new(&mut registry);
+    let remote = common::peer(2);
+    let connection = ConnectionId::new_unchecked(1);
+    let mut paths = ConnectionPaths::default();
+    let endpoint = ConnectedPoint::Dialer {
+        address: "/ip4/203.0.113.9/tcp/5000".parse().expect("valid address"),
+        role_override: Endpoint::Dialer,
+    };
+    paths.on_established(remote, connection, &endpoint);
+    let mut rtt = RttStats::default();
+    rtt.record(remote, connection, Duration::from_millis(20));
+
+    recorder.on_rtt(&rtt, &paths);
+    let rtt_series = format!("chat_rtt_milliseconds{{peer=\"{remote}\"");
+    let mut body = String::new();
+    encode(&mut body, &registry).expect("encodes");
+    assert_eq!(sample(&body, &rtt_series), Some(80.0), "{body}");
+
+    recorder.on_connection_closed(&remote, connection, &paths);
+    paths.on_closed(remote, connection);
+    rtt.on_closed(connection);
+    recorder.on_rtt(&rtt, &paths);
+    let mut body = String::new();
+    encode(&mut body, &registry).expect("encodes");
+    assert_eq!(sample(&body, &rtt_series), None, "{body}");
+}
+
+#[test]
+fn hole_punches_count_failures_by_kind_and_time_the_successes() {
+    let mut registry = Registry::default();
+    let recorder = Metrics::new(&mut registry);
+    recorder.on_hole_punch_succeeded(Duration::from_millis(300));
+    recorder.on_hole_punch_failed("Connect");
+    recorder.on_hole_punch_failed("Connect");
+    recorder.on_hole_punch_failed("Dial");
+
+    let mut body = String::new();
+    encode(&mut body, &registry).expect("encodes");
+    let failures = "chat_hole_punch_failures_total";
+    assert_eq!(
+        sample(&body, &format!("{failures}{{kind=\"Connect\"}}")),
+        Some(2.0),
+        "{body}"
+    );
+    assert_eq!(
+        sample(&body, &format!("{failures}{{kind=\"Dial\"}}")),
+        Some(1.0),
+        "{body}"
+    );
+    let time_to_direct = "chat_hole_punch_time_to_direct_seconds";
+    assert_eq!(
+        sample(&body, &format!("{time_to_direct}_count")),
+        Some(1.0),
+        "{body}"
+    );
+    assert_eq!(
+        sample(&body, &format!("{time_to_direct}_sum")),
+        Some(0.3),
+        "{body}"
+    );
+}
@@ -0,0 +1,59 @@
+//! The parts of the MQTT bridge that don't need a broker: mappings, topic filters and broker
+//! addresses.
+
+use dcutr::mqtt::{broker_addr, filter_matches, Mapping};
+
+#[test]
+fn mappings_parse() {
+    assert_eq!(
+        "sensors/+/temp=test-net".parse::<Mapping>(),
+        Ok(Mapping {
+            from: "sensors/+/temp".to_string(),
+            to: "test-net".to_string(),
+        })
+    );
+    for broken in ["", "sensors", "=test-net", "sensors="] {
+        assert!(broken.parse::<Mapping>().is_err(), "{broken:?} parsed");
+    }
+}
+
+#[test]
+fn filters_match_like_a_broker() {
+    for (filter, topic) in [
+        ("sensors/kitchen/temp", "sensors/kitchen/temp"),
+        ("sensors/+/temp", "sensors/kitchen/temp"),
+        ("sensors/#", "sensors/kitchen/temp"),
+        ("sensors/#", "sensors"),
+        ("#", "anything/at/all"),
+    ] {
+        assert!(
+            filter_matches(filter, topic),
+            "{filter} should match {topic}"
+        );
+    }
+    for (filter, topic) in [
+        ("sensors/+/temp", "sensors/kitchen/humidity"),
+        ("sensors/+", "sensors/kitchen/temp"),
+        ("sensors/kitchen/temp", "sensors/kitchen"),
+    ] {
+        assert!(
+            !filter_matches(filter, topic),
+            "{filter} shouldn't match {topic}"
+        );
+    }
+}
+
+#[test]
+fn broker_addresses_default_the_port() {
+    assert_eq!(
+        broker_addr("localhost"),
+        Ok(("localhost".to_string(), 1883))
+    );
+    assert_eq!(
+        broker_addr("mqtt://broker.lan:8883"),
+        Ok(("broker.lan".to_string(), 8883))
+    );
+    assert!(broker_addr("broker.lan:port").is_err());
+    assert!(broker_addr(":1883").is_err());
+    assert!(broker_addr("").is_err());
+}
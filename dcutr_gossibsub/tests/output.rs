@@ -0,0 +1,128 @@
+//! `--output json` lines keep their shape, other tools parse them.
+
+mod common;
+
+use common::peer;
+use dcutr::envelope::{text_of, Envelope, Kind, WireFormat};
+use dcutr::output::JsonMessage;
+use dcutr::paths::TransportPath;
+use dcutr::topic_keys::TopicKey;
+use libp2p::gossipsub::{IdentTopic, TopicHash};
+use serde_json::json;
+
+fn topic() -> TopicHash {
+    IdentTopic::new("chat").hash()
+}
+
+fn envelope(body: &str) -> Envelope {
+    Envelope {
+        origin: peer(1).to_base58(),
+        seq: 1,
+        topic_seq: None,
+        from_nick: Some("alice".to_string()),
+        sent_at: 1_700_000_000_000,
+        kind: Kind::Chat,
+        compressed: false,
+        key_id: None,
+        replayed: false,
+        bridge: None,
+        forwarded_from: Vec::new(),
+        expires_at: None,
+        body: body.to_string(),
+        signature: None,
+    }
+}
+
+fn line(data: &[u8], source: Option<u8>, path: Option<TransportPath>) -> String {
+    let mut message =
+        JsonMessage::new("chat".to_string(), "id".to_string(), source.map(peer), path)
+            .with_payload(data, 1 << 20, &topic(), &[])
+            .expect("renders");
+    // Bare payloads are stamped with the time they came in.
+    if Envelope::decode(data).is_none() {
+        message.timestamp = 0;
+    }
+    serde_json::to_string(&message).expect("serializes")
+}
+
+#[test]
+fn envelopes_keep_their_shape() {
+    let data = envelope("hello").encode(WireFormat::Json);
+    assert_eq!(
+        line(&data, Some(1), Some(TransportPath::Direct)),
+        format!(
+            r#"{{"topic":"chat","message_id":"id","source":"{}","nick":"alice","timestamp":1700000000000,"path":"direct","body":"hello","body_encoding":"utf8"}}"#,
+            peer(1)
+        )
+    );
+}
+
+#[test]
+fn missing_fields_are_null_and_no_nick_is_left_out() {
+    let anonymous = Envelope {
+        from_nick: None,
+        ..envelope("hi")
+    };
+    let value: serde_json::Value =
+        serde_json::from_str(&line(&anonymous.encode(WireFormat::Cbor), None, None)).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "topic": "chat",
+            "message_id": "id",
+            "source": null,
+            "timestamp": 1_700_000_000_000u64,
+            "path": null,
+            "body": "hi",
+            "body_encoding": "utf8",
+        })
+    );
+}
+
+#[test]
+fn relayed_paths_and_bare_payloads() {
+    let value: serde_json::Value = serde_json::from_str(&line(
+        b"plain text",
+        Some(2),
+        Some(TransportPath::Relayed(Some(peer(3)))),
+    ))
+    .unwrap();
+    assert_eq!(value["path"], "relayed");
+    assert_eq!(value["body"], "plain text");
+    assert_eq!(value["body_encoding"], "utf8");
+    assert_eq!(value["timestamp"], 0);
+    assert!(value.get("nick").is_none());
+}
+
+#[test]
+fn binary_payloads_are_base64() {
+    let value: serde_json::Value =
+        serde_json::from_str(&line(&[0xff, 0xfe, 0x00], None, None)).unwrap();
+    assert_eq!(value["body"], "//4A");
+    assert_eq!(value["body_encoding"], "base64");
+}
+
+#[test]
+fn bodies_without_a_key_stay_encrypted() {
+    let key = TopicKey::from_passphrase("secret", "chat");
+    let sealed = envelope("hello").encrypt(&topic(), &key);
+    let value: serde_json::Value =
+        serde_json::from_str(&line(&sealed.encode(WireFormat::Json), Some(1), None)).unwrap();
+    assert_eq!(value["body"], sealed.body.as_str());
+    assert_eq!(value["body_encoding"], "encrypted");
+    assert_eq!(value["nick"], "alice");
+}
+
+#[test]
+fn compressed_bodies_are_limited() {
+    let body = "a".repeat(4096);
+    let data = envelope(&body).compress(64).encode(WireFormat::Json);
+    let message = JsonMessage::new("chat".to_string(), "id".to_string(), None, None);
+    assert!(message.with_payload(&data, 1024, &topic(), &[]).is_err());
+
+    assert_eq!(text_of(&data, 4096), body);
+    assert_eq!(
+        text_of(&data, 1024),
+        "<body decompresses to more than 1024 bytes>"
+    );
+}
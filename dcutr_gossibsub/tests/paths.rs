@@ -0,0 +1,84 @@
+//! Connections are told apart as direct or relayed by their whole endpoint, whichever side
+//! opened them.
+
+mod common;
+
+use common::peer;
+use dcutr::paths::{ConnectionPaths, TransportPath};
+use libp2p::{
+    core::{multiaddr::Multiaddr, ConnectedPoint, Endpoint},
+    swarm::ConnectionId,
+};
+
+fn relayed_addr() -> Multiaddr {
+    format!("/ip4/198.51.100.1/tcp/4001/p2p/{}/p2p-circuit", peer(100))
+        .parse()
+        .expect("valid address")
+}
+
+fn direct_addr() -> Multiaddr {
+    "/ip4/203.0.113.9/tcp/5000".parse().expect("valid address")
+}
+
+fn dialed(address: Multiaddr) -> ConnectedPoint {
+    ConnectedPoint::Dialer {
+        address,
+        role_override: Endpoint::Dialer,
+    }
+}
+
+fn accepted(local_addr: Multiaddr) -> ConnectedPoint {
+    ConnectedPoint::Listener {
+        local_addr,
+        send_back_addr: format!("/p2p/{}", peer(2)).parse().expect("valid address"),
+    }
+}
+
+#[test]
+fn dialed_circuits_name_the_relay() {
+    assert_eq!(
+        TransportPath::of(&dialed(relayed_addr())),
+        TransportPath::Relayed(Some(peer(100)))
+    );
+    assert_eq!(
+        TransportPath::of(&dialed(direct_addr())),
+        TransportPath::Direct
+    );
+}
+
+#[test]
+fn inbound_circuits_are_told_by_the_local_address() {
+    assert_eq!(
+        TransportPath::of(&accepted(relayed_addr())),
+        TransportPath::Relayed(Some(peer(100)))
+    );
+    assert_eq!(
+        TransportPath::of(&accepted(direct_addr())),
+        TransportPath::Direct
+    );
+}
+
+#[test]
+fn a_direct_connection_is_preferred_over_an_inbound_circuit() {
+    let mut paths = ConnectionPaths::default();
+    let remote = peer(2);
+    paths.on_established(
+        remote,
+        ConnectionId::new_unchecked(1),
+        &accepted(relayed_addr()),
+    );
+    assert_eq!(
+        paths.path(&remote),
+        Some(TransportPath::Relayed(Some(peer(100))))
+    );
+    paths.on_established(
+        remote,
+        ConnectionId::new_unchecked(2),
+        &accepted(direct_addr()),
+    );
+    assert_eq!(paths.path(&remote), Some(TransportPath::Direct));
+    assert!(paths.has_relayed(&remote));
+
+    paths.on_closed(remote, ConnectionId::new_unchecked(1));
+    assert!(!paths.has_relayed(&remote));
+}
@@ -0,0 +1,127 @@
+//! The validation pipeline with mock validators: order, rejections, checks that complete later
+//! and one that never does.
+
+use dcutr::identity;
+use dcutr::pipeline::{self, Candidate, Check, Pipeline};
+use dcutr::validation::Rejection;
+use futures::{executor::block_on, future, FutureExt, StreamExt};
+use libp2p::gossipsub::{IdentTopic, Message, MessageId};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+fn candidate(data: &str) -> Candidate {
+    Candidate {
+        id: MessageId::new(data.as_bytes()),
+        propagation_source: identity::generate_ed25519(1).public().to_peer_id(),
+        message: Message {
+            source: None,
+            data: data.as_bytes().to_vec(),
+            sequence_number: None,
+            topic: IdentTopic::new("test-net").hash(),
+        },
+    }
+}
+
+fn rejection(kind: &'static str) -> Rejection {
+    Rejection {
+        kind,
+        reason: kind.to_string(),
+        penalize: true,
+    }
+}
+
+/// A validator noting its name in `ran` and then deciding with `check`.
+fn logging(
+    name: &'static str,
+    ran: &Arc<Mutex<Vec<&'static str>>>,
+    mut check: impl FnMut(&Candidate) -> Check + Send + 'static,
+) -> impl pipeline::Validator {
+    let ran = ran.clone();
+    pipeline::from_fn(move |candidate| {
+        ran.lock().expect("log lock").push(name);
+        check(candidate)
+    })
+}
+
+#[test]
+fn validators_run_in_order_until_one_rejects() {
+    let ran = Arc::new(Mutex::new(Vec::new()));
+    let mut pipeline = Pipeline::new(Duration::from_secs(5));
+    pipeline.push(logging("first", &ran, |_| Check::Done(Ok(()))));
+    pipeline.push(logging("spam", &ran, |candidate| {
+        match candidate.message.data.starts_with(b"spam") {
+            true => Check::Done(Err(rejection("spam"))),
+            false => Check::Done(Ok(())),
+        }
+    }));
+    pipeline.push(logging("last", &ran, |_| Check::Done(Ok(()))));
+
+    let (_, verdict) = pipeline
+        .validate(candidate("hello"))
+        .expect("decided right away");
+    assert!(verdict.is_ok());
+    let (candidate, verdict) = pipeline
+        .validate(candidate("spam!"))
+        .expect("decided right away");
+    assert_eq!(verdict.unwrap_err().kind, "spam");
+    assert_eq!(candidate.message.data, b"spam!");
+    assert_eq!(
+        *ran.lock().expect("log lock"),
+        ["first", "spam", "last", "first", "spam"]
+    );
+}
+
+#[test]
+fn later_checks_resume_the_pipeline() {
+    let ran = Arc::new(Mutex::new(Vec::new()));
+    let mut pipeline = Pipeline::new(Duration::from_secs(5));
+    pipeline.push(logging("lookup", &ran, |candidate| {
+        let known = candidate.message.data != b"unknown";
+        Check::Later(
+            async move {
+                match known {
+                    true => Ok(()),
+                    false => Err(rejection("unknown")),
+                }
+            }
+            .boxed(),
+        )
+    }));
+    pipeline.push(logging("last", &ran, |_| Check::Done(Ok(()))));
+
+    assert!(pipeline.validate(candidate("known")).is_none());
+    assert_eq!(pipeline.pending(), 1);
+    let (candidate, verdict) = block_on(pipeline.next()).expect("a verdict");
+    assert_eq!(candidate.message.data, b"known");
+    assert!(verdict.is_ok());
+    assert_eq!(*ran.lock().expect("log lock"), ["lookup", "last"]);
+
+    assert!(pipeline.validate(self::candidate("unknown")).is_none());
+    let (_, verdict) = block_on(pipeline.next()).expect("a verdict");
+    assert_eq!(verdict.unwrap_err().kind, "unknown");
+    // The check after the rejecting one didn't run.
+    assert_eq!(*ran.lock().expect("log lock"), ["lookup", "last", "lookup"]);
+}
+
+#[test]
+fn checks_that_hang_time_out() {
+    let mut pipeline = Pipeline::new(Duration::from_millis(50));
+    pipeline.push(pipeline::from_fn(|_| {
+        Check::Later(future::pending().boxed())
+    }));
+    assert!(pipeline.validate(candidate("hello")).is_none());
+    let (_, verdict) = block_on(pipeline.next()).expect("a verdict");
+    let rejection = verdict.unwrap_err();
+    assert_eq!(rejection.kind, "timeout");
+    assert!(!rejection.penalize, "a slow check isn't the sender's fault");
+    assert_eq!(pipeline.pending(), 0);
+}
+
+#[test]
+fn an_empty_pipeline_accepts() {
+    let mut pipeline = Pipeline::new(Duration::from_secs(5));
+    let (_, verdict) = pipeline
+        .validate(candidate("hello"))
+        .expect("decided right away");
+    assert!(verdict.is_ok());
+}
@@ -0,0 +1,111 @@
+//! Only peers holding the same pre-shared key get a connection, in memory and over TCP, and the
+//! others are told apart from ordinary dial failures.
+
+#![cfg(feature = "tokio")]
+
+mod common;
+
+use dcutr::node::Node;
+use dcutr::psk;
+use libp2p::{pnet::PreSharedKey, swarm::SwarmEvent};
+use std::fs;
+
+fn key(byte: u8) -> PreSharedKey {
+    PreSharedKey::new([byte; 32])
+}
+
+/// Dials `remote` from `node` and waits for both ends to give up, checking each side's error is
+/// recognised as a refused handshake.
+async fn refused(node: &mut Node, remote: &mut Node) {
+    let addr = remote.listeners().next().expect("remote listens").clone();
+    node.dial(addr).expect("dials the remote");
+    let dialer = common::wait_for_event(node, &mut [], "a dial error", |event| match event {
+        SwarmEvent::OutgoingConnectionError { error, .. } => Some(psk::dial_refused(&error)),
+        SwarmEvent::ConnectionEstablished { .. } => panic!("connected without the key"),
+        _ => None,
+    });
+    let listener = common::wait_for_event(remote, &mut [], "a listen error", |event| match event {
+        SwarmEvent::IncomingConnectionError { error, .. } => Some(psk::listen_refused(&error)),
+        SwarmEvent::ConnectionEstablished { .. } => panic!("connected without the key"),
+        _ => None,
+    });
+    let (dialer, listener) = tokio::join!(dialer, listener);
+    assert!(dialer, "the dialer saw a refused handshake");
+    assert!(listener, "the listener saw a refused handshake");
+}
+
+#[tokio::test]
+async fn peers_sharing_a_key_connect() {
+    let mut a = common::spawn_private_node(1, Some(key(7))).await;
+    let mut b = common::spawn_private_node(2, Some(key(7))).await;
+    common::connect(&mut a, &mut b).await;
+}
+
+#[tokio::test]
+async fn a_keyed_peer_refuses_an_unkeyed_one() {
+    let mut keyed = common::spawn_private_node(1, Some(key(7))).await;
+    let mut unkeyed = common::spawn_private_node(2, None).await;
+    refused(&mut keyed, &mut unkeyed).await;
+    refused(&mut unkeyed, &mut keyed).await;
+}
+
+#[tokio::test]
+async fn peers_with_different_keys_refuse_each_other() {
+    let mut a = common::spawn_private_node(1, Some(key(7))).await;
+    let mut b = common::spawn_private_node(2, Some(key(8))).await;
+    refused(&mut a, &mut b).await;
+}
+
+#[tokio::test]
+async fn tcp_peers_connect_with_the_same_key_or_none() {
+    let mut a = common::spawn_private_tcp_node(1, Some(key(7))).await;
+    let mut b = common::spawn_private_tcp_node(2, Some(key(7))).await;
+    common::connect(&mut a, &mut b).await;
+
+    let mut c = common::spawn_private_tcp_node(3, None).await;
+    let mut d = common::spawn_private_tcp_node(4, None).await;
+    common::connect(&mut c, &mut d).await;
+}
+
+#[tokio::test]
+async fn tcp_peers_with_different_keys_or_none_refuse_each_other() {
+    let mut a = common::spawn_private_tcp_node(1, Some(key(7))).await;
+    let mut b = common::spawn_private_tcp_node(2, Some(key(8))).await;
+    refused(&mut a, &mut b).await;
+
+    let mut unkeyed = common::spawn_private_tcp_node(3, None).await;
+    refused(&mut unkeyed, &mut a).await;
+}
+
+#[test]
+fn a_generated_key_loads_back_and_is_never_overwritten() {
+    let path = common::data_dir("psk").join("swarm.key");
+
+    let generated = psk::generate(&path).expect("writes the key");
+    let loaded = psk::load(&path).expect("reads the key");
+    assert_eq!(
+        loaded.fingerprint().to_string(),
+        generated.fingerprint().to_string()
+    );
+    assert!(psk::generate(&path).is_err());
+    assert_eq!(
+        psk::load(&path)
+            .expect("reads the key")
+            .fingerprint()
+            .to_string(),
+        generated.fingerprint().to_string()
+    );
+
+    fs::write(&path, "not a key\n").expect("writes the file");
+    assert!(psk::load(&path).is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn a_generated_key_is_only_readable_by_its_owner() {
+    use std::os::unix::fs::PermissionsExt;
+    let path = common::data_dir("psk-mode").join("swarm.key");
+    psk::generate(&path).expect("writes the key");
+    let mode = fs::metadata(&path).expect("stats the key").permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
@@ -0,0 +1,78 @@
+//! Checks of the relay address and of the peer ids the client is started with.
+
+mod common;
+
+use common::peer;
+use dcutr::relay_addr::{check_peer_ids, validate};
+use libp2p::core::multiaddr::Multiaddr;
+
+fn addr(addr: &str) -> Multiaddr {
+    addr.parse().expect("valid multiaddr")
+}
+
+#[test]
+fn relay_addresses_with_a_peer_id_name_it() {
+    let relay = peer(1);
+    assert_eq!(
+        validate(&addr(&format!("/ip4/198.51.100.1/tcp/4001/p2p/{relay}"))),
+        Ok(Some(relay))
+    );
+    assert_eq!(
+        validate(&addr(&format!(
+            "/dns4/relay.example.com/tcp/4001/p2p/{relay}"
+        ))),
+        Ok(Some(relay))
+    );
+    assert_eq!(validate(&addr("/ip4/198.51.100.1/tcp/4001")), Ok(None));
+    assert_eq!(validate(&addr("/dnsaddr/relay.example.com")), Ok(None));
+}
+
+#[test]
+fn broken_relay_addresses_are_rejected() {
+    let relay = peer(1);
+    let remote = peer(2);
+    for (broken, complaint) in [
+        ("/ip4/198.51.100.1".to_string(), "no TCP port"),
+        (
+            format!("/p2p/{relay}"),
+            "has to start with the relay's host",
+        ),
+        (
+            format!("/ip4/198.51.100.1/tcp/4001/p2p/{relay}/p2p-circuit"),
+            "contains /p2p-circuit",
+        ),
+        (
+            format!("/ip4/198.51.100.1/tcp/4001/p2p/{relay}/p2p-circuit/p2p/{remote}"),
+            "contains /p2p-circuit",
+        ),
+        (
+            format!("/ip4/198.51.100.1/tcp/4001/p2p/{relay}/tcp/1"),
+            "nothing may follow",
+        ),
+    ] {
+        let error = validate(&addr(&broken)).unwrap_err();
+        assert!(error.contains(complaint), "{broken}: {error}");
+    }
+    assert!(validate(&Multiaddr::empty()).is_err());
+}
+
+#[test]
+fn distinct_peer_ids_pass() {
+    assert_eq!(
+        check_peer_ids(&peer(1), Some(peer(2)), &[peer(3), peer(4)]),
+        Ok(())
+    );
+    assert_eq!(check_peer_ids(&peer(1), None, &[]), Ok(()));
+}
+
+#[test]
+fn seed_collisions_are_explained() {
+    let error = check_peer_ids(&peer(1), Some(peer(2)), &[peer(1)]).unwrap_err();
+    assert!(error.contains("same --secret-key-seed"), "{error}");
+
+    let error = check_peer_ids(&peer(1), Some(peer(1)), &[peer(3)]).unwrap_err();
+    assert!(error.contains("same --secret-key-seed"), "{error}");
+
+    let error = check_peer_ids(&peer(1), Some(peer(2)), &[peer(2)]).unwrap_err();
+    assert!(error.contains("the relay's peer id"), "{error}");
+}
@@ -0,0 +1,76 @@
+//! The whole flow: a listening client reserves a slot at the relay, a dialing client reaches it
+//! through a circuit and both chat over gossipsub.
+//!
+//! Binds sockets on localhost and takes a few seconds, run with `cargo test -- --ignored`.
+
+#![cfg(feature = "tokio")]
+
+mod common;
+
+use dcutr::behaviour::BehaviourEvent;
+use dcutr::node::Node;
+use libp2p::{core::multiaddr::Protocol, gossipsub, relay, swarm::SwarmEvent};
+
+#[tokio::test]
+#[ignore = "binds localhost sockets and takes a few seconds"]
+async fn relayed_clients_exchange_messages() {
+    let relay_addr = common::spawn_relay(0).await;
+    let mut listener = common::spawn_node(1).await;
+    let mut dialer = common::spawn_node(2).await;
+    let listener_id = *listener.local_peer_id();
+
+    listener
+        .listen_on(relay_addr.clone().with(Protocol::P2pCircuit))
+        .expect("listens through the relay");
+    common::wait_for_event(
+        &mut listener,
+        &mut [],
+        "the relay reservation",
+        |event| match event {
+            SwarmEvent::Behaviour(BehaviourEvent::RelayClient(
+                relay::client::Event::ReservationReqAccepted { .. },
+            )) => Some(()),
+            _ => None,
+        },
+    )
+    .await;
+
+    dialer
+        .dial_peer(listener_id, &relay_addr)
+        .expect("dials the listener through the relay");
+    common::wait_for_event(
+        &mut dialer,
+        &mut [&mut listener],
+        "a connection to the listener",
+        |event| match event {
+            SwarmEvent::ConnectionEstablished { peer_id, .. } if peer_id == listener_id => Some(()),
+            _ => None,
+        },
+    )
+    .await;
+
+    exchange(&mut dialer, &mut listener).await;
+    exchange(&mut listener, &mut dialer).await;
+}
+
+/// Publishes a message from `sender` and checks `receiver` gets it as it was sent.
+async fn exchange(sender: &mut Node, receiver: &mut Node) {
+    let sender_id = *sender.local_peer_id();
+    let data = format!("hello from {sender_id}").into_bytes();
+    common::publish(sender, &mut [&mut *receiver], &data).await;
+    let message = common::wait_for_event(
+        receiver,
+        &mut [sender],
+        "the message",
+        |event| match event {
+            SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                message,
+                ..
+            })) => Some(message),
+            _ => None,
+        },
+    )
+    .await;
+    assert_eq!(message.data, data);
+    assert_eq!(message.source, Some(sender_id));
+}
@@ -0,0 +1,67 @@
+//! The relay session renewing the reservation after losing the relay, without a swarm.
+
+mod common;
+
+use dcutr::relay_session::{RelaySession, REDIAL_DELAY};
+use libp2p::core::multiaddr::{Multiaddr, Protocol};
+use std::time::{Duration, Instant};
+
+/// A session at a relay with the peer id of `seed`, holding a reservation.
+fn reserved(seed: u8) -> RelaySession {
+    let address = "/ip4/10.0.0.1/tcp/4001"
+        .parse::<Multiaddr>()
+        .expect("parses")
+        .with(Protocol::P2p(common::peer(seed).into()));
+    let mut relay = RelaySession::new(address);
+    relay.on_reservation_requested();
+    relay.on_reservation_accepted();
+    relay
+}
+
+#[test]
+fn losing_the_relay_schedules_one_redial() {
+    let mut relay = reserved(1);
+    let now = Instant::now();
+    assert!(relay.on_disconnected(&common::peer(1), now));
+    assert_eq!(relay.status(), "relay disconnected");
+    assert!(!relay.redial_due(now + REDIAL_DELAY - Duration::from_millis(1)));
+    assert!(relay.redial_due(now + REDIAL_DELAY));
+    assert!(!relay.redial_due(now + REDIAL_DELAY * 2));
+}
+
+#[test]
+fn reconnecting_renews_the_reservation_once() {
+    let mut relay = reserved(1);
+    assert!(
+        !relay.on_connected(&common::peer(1)),
+        "the reservation is still there"
+    );
+    relay.on_disconnected(&common::peer(1), Instant::now());
+    assert!(relay.on_connected(&common::peer(1)));
+    assert_eq!(relay.status(), "reservation pending");
+    assert!(!relay.on_connected(&common::peer(1)));
+}
+
+#[test]
+fn other_peers_leave_the_reservation_alone() {
+    let mut relay = reserved(1);
+    let now = Instant::now();
+    assert!(!relay.on_disconnected(&common::peer(2), now));
+    relay.on_dial_failure(&common::peer(2), now);
+    assert_eq!(relay.status(), "reservation accepted");
+    assert!(!relay.redial_due(now + REDIAL_DELAY));
+}
+
+#[test]
+fn failed_redials_are_tried_again() {
+    let mut relay = reserved(1);
+    let now = Instant::now();
+    relay.on_disconnected(&common::peer(1), now);
+    assert!(relay.redial_due(now + REDIAL_DELAY));
+    let later = now + REDIAL_DELAY * 2;
+    relay.on_dial_failure(&common::peer(1), later);
+    assert!(!relay.redial_due(later));
+    assert!(relay.redial_due(later + REDIAL_DELAY));
+    relay.on_redial_failed(later);
+    assert!(relay.redial_due(later + REDIAL_DELAY));
+}
@@ -0,0 +1,119 @@
+//! Peers lose reputation for rejected messages, earn it back over time and are banned for
+//! longer each time it runs out.
+
+mod common;
+
+use common::{data_dir, peer};
+use dcutr::reputation::{Reputation, ReputationConfig};
+use std::fs;
+use std::time::Duration;
+
+fn config() -> ReputationConfig {
+    ReputationConfig {
+        threshold: -50.0,
+        recovery_per_min: 10.0,
+        ban: Duration::from_secs(60),
+        max_ban: Duration::from_secs(200),
+    }
+}
+
+#[test]
+fn flooding_peers_are_banned_for_longer_each_time() {
+    let mut reputation = Reputation::load(None, &peer(1), config()).expect("loads");
+    let flooder = peer(2);
+    let mut now = 1_000;
+    for _ in 0..4 {
+        let ban = reputation.on_offence(flooder, "rate", "rate limit exceeded", now);
+        assert!(ban.is_none());
+    }
+    assert_eq!(reputation.score(&flooder, now), -40.0);
+    let ban = reputation
+        .on_offence(flooder, "signature", "signature does not match", now)
+        .expect("banned at the threshold");
+    assert_eq!(ban.peer_id, flooder);
+    assert_eq!(ban.duration, Duration::from_secs(60));
+    assert_eq!(ban.reasons, "4x rate, 1x signature");
+    assert_eq!(reputation.banned(now), [flooder]);
+
+    assert!(reputation.expired(now + 59).is_empty());
+    now += 60;
+    assert_eq!(reputation.expired(now), [flooder]);
+    assert!(
+        reputation.expired(now).is_empty(),
+        "each end is reported once"
+    );
+    assert!(reputation.banned(now).is_empty());
+
+    let mut durations = Vec::new();
+    for _ in 0..3 {
+        let ban = (0..2)
+            .find_map(|_| reputation.on_offence(flooder, "signature", "bad", now))
+            .expect("banned again");
+        durations.push(ban.duration.as_secs());
+        now += ban.duration.as_secs();
+        reputation.expired(now);
+    }
+    assert_eq!(durations, [120, 200, 200], "doubled up to the longest ban");
+}
+
+#[test]
+fn reputation_recovers_over_time() {
+    let mut reputation = Reputation::load(None, &peer(1), config()).expect("loads");
+    let peer_id = peer(2);
+    assert_eq!(reputation.score(&peer_id, 0), 0.0);
+    assert!(reputation.lines(&peer_id, 0).is_empty());
+    reputation.on_offence(peer_id, "stale", "sent 100s ago", 0);
+    reputation.on_offence(peer_id, "rate", "rate limit exceeded", 0);
+    assert_eq!(reputation.score(&peer_id, 0), -15.0);
+    assert_eq!(reputation.score(&peer_id, 60), -5.0);
+    assert_eq!(reputation.score(&peer_id, 600), 0.0, "never above zero");
+    let lines = reputation.lines(&peer_id, 60);
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].contains("rate"), "newest first: {lines:?}");
+}
+
+#[test]
+fn protected_peers_are_never_banned() {
+    let mut reputation = Reputation::load(None, &peer(1), config()).expect("loads");
+    let relay = peer(2);
+    reputation.protect(relay);
+    for _ in 0..10 {
+        assert!(reputation
+            .on_offence(relay, "signature", "bad", 0)
+            .is_none());
+    }
+    assert_eq!(reputation.score(&relay, 0), -250.0);
+    reputation.unprotect(&relay);
+    assert!(reputation
+        .on_offence(relay, "signature", "bad", 0)
+        .is_some());
+}
+
+#[test]
+fn reputation_persists_only_with_a_data_dir() {
+    let dir = data_dir("reputation-persist");
+    let local = peer(1);
+    let flooder = peer(2);
+    let now = dcutr::reputation::now_secs();
+
+    let mut reputation = Reputation::load(Some(&dir), &local, config()).expect("loads");
+    for _ in 0..5 {
+        reputation.on_offence(flooder, "rate", "rate limit exceeded", now);
+    }
+    reputation.on_offence(peer(3), "stale", "sent 100s ago", now);
+    reputation.save();
+    drop(reputation);
+
+    let restarted = Reputation::load(Some(&dir), &local, config()).expect("reloads");
+    assert_eq!(
+        restarted.banned(now),
+        [flooder],
+        "the ban outlives the restart"
+    );
+    assert_eq!(restarted.score(&peer(3), now), -5.0);
+
+    let fresh = Reputation::load(None, &local, config()).expect("loads");
+    assert!(fresh.banned(now).is_empty());
+    assert_eq!(fresh.score(&peer(3), now), 0.0);
+    let _ = fs::remove_dir_all(&dir);
+}
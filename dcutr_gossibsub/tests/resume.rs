@@ -0,0 +1,184 @@
+//! A file transfer whose stream dies half way resumes from the receiver's checkpoint, even after
+//! the receiver restarted, and the file arrives intact.
+
+#![cfg(feature = "tokio")]
+
+mod common;
+
+use common::data_dir;
+use dcutr::behaviour::BehaviourEvent;
+use dcutr::message_id::MessageIdScheme;
+use dcutr::node::{Event, Node};
+use dcutr::transfer::{FileRequest, FileResponse, Transfers};
+use futures::StreamExt;
+use libp2p::{
+    request_response::{self, Message, OutboundFailure, RequestId, ResponseChannel},
+    swarm::SwarmEvent,
+    PeerId,
+};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The chunk size of the sending side.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+fn sha256(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn transfers(download_dir: PathBuf) -> Transfers {
+    Transfers::new(download_dir, false, 1 << 30, 4, Duration::from_secs(60))
+}
+
+fn send(node: &mut Node, sender: &mut Transfers, peer_id: PeerId, request: FileRequest) {
+    let id = node
+        .behaviour_mut()
+        .transfer
+        .send_request(&peer_id, request.clone());
+    sender.on_sent(id, request.file_id().to_string());
+}
+
+fn offer(node: &mut Node, sender: &mut Transfers, peer_id: PeerId, path: &Path) {
+    let request = sender.offer(peer_id, path).expect("offers the file");
+    send(node, sender, peer_id, request);
+}
+
+/// A file transfer request the peer sent.
+fn request(event: Event) -> Option<(PeerId, FileRequest, ResponseChannel<FileResponse>)> {
+    match event {
+        SwarmEvent::Behaviour(BehaviourEvent::Transfer(request_response::Event::Message {
+            peer,
+            message: Message::Request {
+                request, channel, ..
+            },
+        })) => Some((peer, request, channel)),
+        _ => None,
+    }
+}
+
+/// The answer to one of our file transfer requests, or why there is none.
+fn response(event: Event) -> Option<(RequestId, Result<FileResponse, OutboundFailure>)> {
+    match event {
+        SwarmEvent::Behaviour(BehaviourEvent::Transfer(request_response::Event::Message {
+            message:
+                Message::Response {
+                    request_id,
+                    response,
+                },
+            ..
+        })) => Some((request_id, Ok(response))),
+        SwarmEvent::Behaviour(BehaviourEvent::Transfer(
+            request_response::Event::OutboundFailure {
+                request_id, error, ..
+            },
+        )) => Some((request_id, Err(error))),
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn an_interrupted_transfer_resumes_where_it_stopped() {
+    let dir = data_dir("resume");
+    let source = dir.join("source.bin");
+    let data = (0..CHUNK_SIZE * 3 + 100)
+        .map(|i| (i * 7 % 251) as u8)
+        .collect::<Vec<_>>();
+    fs::write(&source, &data).expect("writes the source");
+    let downloads = dir.join("downloads");
+
+    let mut a = common::spawn_memory_node(1, MessageIdScheme::Sha256).await;
+    let mut b = common::spawn_memory_node(2, MessageIdScheme::Sha256).await;
+    common::connect(&mut a, &mut b).await;
+    let b_id = *b.local_peer_id();
+    let mut sender = transfers(dir.join("sent"));
+    let mut receiver = transfers(downloads.clone());
+
+    // The stream dies while the second chunk is on its way.
+    offer(&mut a, &mut sender, b_id, &source);
+    common::within("the interruption", async {
+        loop {
+            futures::select! {
+                event = a.select_next_some() => match response(event) {
+                    Some((request_id, Ok(response))) => {
+                        if let Some((peer_id, request)) = sender.on_response(&request_id, response) {
+                            send(&mut a, &mut sender, peer_id, request);
+                        }
+                    }
+                    Some((request_id, Err(error))) => {
+                        sender.on_failure(&request_id, &error);
+                        return;
+                    }
+                    None => {}
+                },
+                event = b.select_next_some() => if let Some((peer, request, channel)) = request(event) {
+                    if matches!(request, FileRequest::Chunk { offset, .. } if offset > 0) {
+                        b.disconnect_peer_id(peer).expect("connected to the sender");
+                    } else {
+                        let response = receiver.on_request(peer, request);
+                        b.behaviour_mut()
+                            .transfer
+                            .send_response(channel, response)
+                            .expect("answers the sender");
+                    }
+                },
+            }
+        }
+    })
+    .await;
+    assert!(!downloads.join("source.bin").exists());
+
+    // The receiver restarts, the partial download and its checkpoint are all that is left.
+    drop(receiver);
+    let mut receiver = transfers(downloads.clone());
+    common::connect(&mut a, &mut b).await;
+    offer(&mut a, &mut sender, b_id, &source);
+    let mut accepted = None;
+    let mut offsets = Vec::new();
+    common::within("the rest of the file", async {
+        loop {
+            futures::select! {
+                event = a.select_next_some() => match response(event) {
+                    Some((request_id, Ok(response))) => {
+                        let complete = matches!(response, FileResponse::Complete);
+                        if let Some((peer_id, request)) = sender.on_response(&request_id, response) {
+                            send(&mut a, &mut sender, peer_id, request);
+                        }
+                        if complete {
+                            return;
+                        }
+                    }
+                    Some((_, Err(error))) => panic!("the resumed transfer failed: {error}"),
+                    None => {}
+                },
+                event = b.select_next_some() => if let Some((peer, request, channel)) = request(event) {
+                    if let FileRequest::Chunk { offset, .. } = &request {
+                        offsets.push(*offset);
+                    }
+                    let response = receiver.on_request(peer, request);
+                    if let FileResponse::Accepted { offset, checkpoint } = &response {
+                        accepted = Some((*offset, checkpoint.clone()));
+                    }
+                    b.behaviour_mut()
+                        .transfer
+                        .send_response(channel, response)
+                        .expect("answers the sender");
+                },
+            }
+        }
+    })
+    .await;
+
+    assert_eq!(
+        accepted,
+        Some((CHUNK_SIZE as u64, Some(sha256(&data[..CHUNK_SIZE])))),
+        "the receiver offered to resume after the first chunk"
+    );
+    assert_eq!(offsets.first(), Some(&(CHUNK_SIZE as u64)), "{offsets:?}");
+    let received = fs::read(downloads.join("source.bin")).expect("the file arrived");
+    assert_eq!(sha256(&received), sha256(&data));
+}
@@ -0,0 +1,105 @@
+//! Peers whose messages fail validation lose score, and the scoring knobs are checked up front.
+
+#![cfg(feature = "tokio")]
+
+mod common;
+
+use dcutr::behaviour::BehaviourEvent;
+use dcutr::message_id::MessageIdScheme;
+use dcutr::node::Node;
+use dcutr::scoring::ScoreConfig;
+use libp2p::{
+    gossipsub::{self, MessageAcceptance},
+    swarm::SwarmEvent,
+};
+
+fn config() -> ScoreConfig {
+    ScoreConfig {
+        topic_weight: 1.0,
+        invalid_message_penalty: -10.0,
+        graylist_threshold: -80.0,
+    }
+}
+
+/// A client scoring its peers on the test topic, which validates messages itself.
+async fn scoring(secret_key_seed: u8) -> Node {
+    common::spawn_memory_node_with(secret_key_seed, |key| {
+        let score = config();
+        let config = common::gossipsub_config(MessageIdScheme::Sha256)
+            .validate_messages()
+            .build()
+            .expect("valid gossipsub config");
+        let mut gossipsub = common::gossipsub(key, config);
+        gossipsub
+            .with_peer_score(score.params(), score.thresholds())
+            .expect("valid score parameters");
+        gossipsub
+            .set_topic_params(common::topic(), score.topic_params())
+            .expect("scoring is enabled");
+        gossipsub.subscribe(&common::topic()).expect("subscribes");
+        gossipsub
+    })
+    .await
+}
+
+#[test]
+fn score_knobs_are_checked() {
+    assert!(config().validate().is_ok());
+    for broken in [
+        ScoreConfig {
+            topic_weight: -1.0,
+            ..config()
+        },
+        ScoreConfig {
+            invalid_message_penalty: 0.0,
+            ..config()
+        },
+        ScoreConfig {
+            graylist_threshold: 1.0,
+            ..config()
+        },
+    ] {
+        assert!(broken.validate().is_err(), "{broken:?}");
+    }
+    let thresholds = config().thresholds();
+    assert_eq!(thresholds.graylist_threshold, -80.0);
+    assert_eq!(thresholds.publish_threshold, -40.0);
+    assert_eq!(thresholds.gossip_threshold, -10.0);
+}
+
+#[tokio::test]
+async fn rejected_messages_cost_the_sender_score() {
+    let mut a = common::spawn_memory_node(1, MessageIdScheme::Sha256).await;
+    let mut b = scoring(2).await;
+    common::connect(&mut a, &mut b).await;
+    let a_id = *a.local_peer_id();
+
+    common::publish(&mut a, &mut [&mut b], b"not what b accepts").await;
+    let (id, source) =
+        common::wait_for_event(&mut b, &mut [&mut a], "the message", |event| match event {
+            SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                propagation_source,
+                message_id,
+                ..
+            })) => Some((message_id, propagation_source)),
+            _ => None,
+        })
+        .await;
+    assert_eq!(source, a_id);
+    let before = b
+        .behaviour()
+        .gossipsub
+        .peer_score(&a_id)
+        .expect("scoring is enabled");
+
+    b.behaviour_mut()
+        .gossipsub
+        .report_message_validation_result(&id, &source, MessageAcceptance::Reject)
+        .expect("reports the result");
+    let after = b
+        .behaviour()
+        .gossipsub
+        .peer_score(&a_id)
+        .expect("scoring is enabled");
+    assert!(after < before, "{after} is not below {before}");
+}
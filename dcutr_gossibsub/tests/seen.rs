@@ -0,0 +1,62 @@
+//! The ids of received messages outlive a restart, so the messages still gossiped afterwards are
+//! turned away.
+
+mod common;
+
+use common::{data_dir, peer};
+use dcutr::seen::SeenMessages;
+use libp2p::gossipsub::MessageId;
+use std::time::Duration;
+
+fn id(n: u32) -> MessageId {
+    MessageId::new(&n.to_be_bytes())
+}
+
+#[test]
+fn seen_ids_are_suppressed_after_a_restart() {
+    let dir = data_dir("seen-restart");
+    let peer_id = peer(1);
+    let max_age = Duration::from_secs(600);
+
+    let mut seen = SeenMessages::load(Some(&dir), &peer_id, 100, max_age).expect("loads");
+    assert!(seen.check(&id(1)).is_ok());
+    seen.insert(&id(1));
+    seen.insert(&id(2));
+    seen.save();
+    drop(seen);
+
+    let mut restarted = SeenMessages::load(Some(&dir), &peer_id, 100, max_age).expect("reloads");
+    let rejection = restarted.check(&id(1)).unwrap_err();
+    assert!(!rejection.penalize, "a duplicate isn't the sender's fault");
+    assert!(restarted.check(&id(2)).is_err());
+    assert!(restarted.check(&id(3)).is_ok());
+    restarted.insert(&id(3));
+    assert!(restarted.contains(&id(3)));
+}
+
+#[test]
+fn expired_ids_are_purged_on_load() {
+    let dir = data_dir("seen-expired");
+    let peer_id = peer(1);
+
+    let mut seen =
+        SeenMessages::load(Some(&dir), &peer_id, 100, Duration::from_secs(600)).expect("loads");
+    seen.insert(&id(1));
+    seen.save();
+    drop(seen);
+
+    let restarted = SeenMessages::load(Some(&dir), &peer_id, 100, Duration::ZERO).expect("reloads");
+    assert!(restarted.check(&id(1)).is_ok());
+}
+
+#[test]
+fn the_oldest_ids_go_beyond_the_capacity() {
+    let peer_id = peer(1);
+    let mut seen = SeenMessages::load(None, &peer_id, 2, Duration::from_secs(600)).expect("loads");
+    for n in 1..=3 {
+        seen.insert(&id(n));
+    }
+    assert!(!seen.contains(&id(1)));
+    assert!(seen.contains(&id(2)));
+    assert!(seen.contains(&id(3)));
+}
@@ -0,0 +1,93 @@
+//! Gaps in the sequence numbers of a sender show messages gossipsub dropped, and what is kept to
+//! notice them stays bounded.
+
+mod common;
+
+use common::peer;
+use dcutr::sequences::Sequences;
+use libp2p::gossipsub::TopicHash;
+use std::time::Duration;
+
+const DAY: u64 = 24 * 60 * 60 * 1000;
+
+fn sequences() -> Sequences {
+    Sequences::load(None, &peer(1), Duration::from_secs(60)).expect("loads")
+}
+
+fn topic() -> TopicHash {
+    TopicHash::from_raw("chat")
+}
+
+#[test]
+fn skipped_numbers_are_reported_once() {
+    let mut sequences = sequences();
+    let sender = peer(2);
+    assert!(sequences
+        .observe(&sender, &topic(), 1, 100, 1_000)
+        .is_none());
+    assert!(sequences
+        .observe(&sender, &topic(), 2, 200, 1_001)
+        .is_none());
+    let gap = sequences
+        .observe(&sender, &topic(), 5, 500, 1_002)
+        .expect("two went missing");
+    assert_eq!((gap.missed, gap.since), (2, 200));
+    assert!(
+        sequences
+            .observe(&sender, &topic(), 3, 300, 1_003)
+            .is_none(),
+        "a late arrival"
+    );
+    assert!(
+        sequences
+            .observe(&peer(3), &topic(), 9, 900, 1_004)
+            .is_none(),
+        "senders are counted apart"
+    );
+}
+
+#[test]
+fn a_restarted_counter_is_taken_after_the_reset_period() {
+    let mut sequences = sequences();
+    let sender = peer(2);
+    sequences.observe(&sender, &topic(), 50, 100, 1_000);
+    sequences.observe(&sender, &topic(), 1, 200, 61_000);
+    let gap = sequences
+        .observe(&sender, &topic(), 3, 300, 61_001)
+        .expect("counts from the restart");
+    assert_eq!(gap.missed, 1);
+}
+
+#[test]
+fn silent_senders_are_forgotten() {
+    let mut sequences = sequences();
+    let sender = peer(2);
+    sequences.observe(&sender, &topic(), 1, 100, 1_000);
+    // Another sender coming along makes room, a week later.
+    sequences.observe(&peer(3), &topic(), 1, 100, 1_000 + 8 * DAY);
+    assert!(
+        sequences
+            .observe(&sender, &topic(), 5, 500, 1_000 + 8 * DAY)
+            .is_none(),
+        "starts over without a gap"
+    );
+}
+
+#[test]
+fn the_least_recently_heard_sender_makes_room() {
+    let mut sequences = sequences();
+    let sender = peer(2);
+    sequences.observe(&sender, &topic(), 1, 100, 1_000);
+    for n in 0..4096 {
+        let topic = TopicHash::from_raw(format!("topic-{n}"));
+        sequences.observe(&sender, &topic, 1, 100, 2_000 + n);
+    }
+    assert!(
+        sequences
+            .observe(&sender, &topic(), 5, 500, 10_000)
+            .is_none(),
+        "the oldest entry was dropped"
+    );
+    let gap = sequences.observe(&sender, &TopicHash::from_raw("topic-4095"), 3, 300, 10_001);
+    assert!(gap.is_some(), "recent entries are kept");
+}
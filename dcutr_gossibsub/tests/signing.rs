@@ -0,0 +1,156 @@
+//! Envelope signatures, against known answers and tampering.
+
+mod common;
+
+use common::peer;
+use dcutr::envelope::{Envelope, Kind};
+use dcutr::identity;
+use dcutr::signing::{self, Verification};
+
+/// The peer id of secret key seed 1.
+const ORIGIN: &str = "12D3KooWPjceQrSwdWXPyLLeABRXmuqt69Rg3sBYbU1Nft9HyQ6X";
+
+/// Ed25519 of seed 1 over the signing bytes of [`envelope`].
+const SIGNATURE: &str =
+    "RPIM0+phtSzEZwXmmHhLvD806opyUJuzAYXISixpWkn76DlipgHKJRI2n6wEwDqufeMQj08hzPA9bpFrb0udCw==";
+
+fn envelope() -> Envelope {
+    Envelope {
+        origin: ORIGIN.to_string(),
+        seq: 42,
+        topic_seq: None,
+        from_nick: Some("alice".to_string()),
+        sent_at: 1_700_000_000_000,
+        kind: Kind::Chat,
+        compressed: false,
+        key_id: None,
+        replayed: false,
+        bridge: None,
+        forwarded_from: Vec::new(),
+        expires_at: None,
+        body: "hello".to_string(),
+        signature: None,
+    }
+}
+
+#[test]
+fn signatures_match_the_known_answer() {
+    assert_eq!(peer(1).to_base58(), ORIGIN);
+    let signed = envelope().signed(&identity::generate_ed25519(1));
+    assert_eq!(signed.signature.as_deref(), Some(SIGNATURE));
+    assert_eq!(
+        signing::verify(&signed, Some(peer(1))),
+        Verification::Verified(peer(1))
+    );
+    assert_eq!(
+        signing::verify(&signed, None),
+        Verification::Verified(peer(1))
+    );
+}
+
+#[test]
+fn tampered_envelopes_fail_verification() {
+    let signed = Envelope {
+        signature: Some(SIGNATURE.to_string()),
+        ..envelope()
+    };
+    for tampered in [
+        Envelope {
+            body: "hellO".to_string(),
+            ..signed.clone()
+        },
+        Envelope {
+            from_nick: Some("mallory".to_string()),
+            ..signed.clone()
+        },
+        Envelope {
+            seq: 43,
+            ..signed.clone()
+        },
+        Envelope {
+            signature: Some("not base64!".to_string()),
+            ..signed.clone()
+        },
+    ] {
+        assert!(matches!(
+            signing::verify(&tampered, Some(peer(1))),
+            Verification::Invalid(_)
+        ));
+    }
+}
+
+#[test]
+fn envelopes_published_by_someone_else_fail_unless_forwarded() {
+    let signed = envelope().signed(&identity::generate_ed25519(1));
+    assert!(matches!(
+        signing::verify(&signed, Some(peer(2))),
+        Verification::Invalid(_)
+    ));
+    // Signed by someone else than the origin it claims.
+    let forged = envelope().signed(&identity::generate_ed25519(2));
+    assert!(matches!(
+        signing::verify(&forged, Some(peer(1))),
+        Verification::Invalid(_)
+    ));
+
+    // The forwarding client adds the field after signing, it isn't signed. So anyone can claim
+    // to have forwarded a message, which doesn't make it the origin's.
+    let forwarded = Envelope {
+        forwarded_from: vec!["other".to_string()],
+        ..signed
+    };
+    assert_eq!(
+        signing::verify(&forwarded, Some(peer(2))),
+        Verification::Forwarded {
+            origin: peer(1),
+            by: peer(2)
+        }
+    );
+    assert_eq!(
+        signing::verify(&forwarded, Some(peer(1))),
+        Verification::Verified(peer(1))
+    );
+    let by = peer(2).to_base58();
+    assert_eq!(
+        Verification::Forwarded {
+            origin: peer(1),
+            by: peer(2)
+        }
+        .to_string(),
+        format!(
+            "\u{2713}{} via {}",
+            &ORIGIN[ORIGIN.len() - 6..],
+            &by[by.len() - 6..]
+        )
+    );
+}
+
+#[test]
+fn the_key_id_is_signed_with_its_label() {
+    let encrypted = Envelope {
+        key_id: Some("k1".to_string()),
+        ..envelope()
+    };
+    let bytes = signing::signing_bytes(&encrypted);
+    let label = b"key_id";
+    let at = bytes
+        .windows(label.len())
+        .position(|w| w == label)
+        .expect("labelled");
+    assert_eq!(&bytes[at - 8..at], &(label.len() as u64).to_be_bytes());
+    assert_eq!(&bytes[at + label.len()..][..8], &2u64.to_be_bytes());
+    assert_eq!(&bytes[at + label.len() + 8..][..2], b"k1");
+}
+
+#[test]
+fn envelopes_of_legacy_peers_are_unsigned() {
+    assert_eq!(
+        signing::verify(&envelope(), Some(peer(1))),
+        Verification::Unsigned
+    );
+    assert_eq!(Verification::Unsigned.to_string(), "unverified");
+    assert_eq!(
+        Verification::Verified(peer(1)).to_string(),
+        format!("\u{2713}{}", &ORIGIN[ORIGIN.len() - 6..])
+    );
+}
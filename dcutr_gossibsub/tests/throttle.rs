@@ -0,0 +1,201 @@
+//! Our own publishes are paced by a token bucket, on a clock the tests move by hand.
+
+mod common;
+
+use dcutr::identity;
+#[cfg(feature = "tokio")]
+use dcutr::message_id::MessageIdScheme;
+use dcutr::outbox::{Outbox, Sent};
+use dcutr::throttle::TokenBucket;
+use dcutr::validation::Rate;
+use libp2p::gossipsub::{self, IdentTopic, MessageAuthenticity};
+use std::time::{Duration, Instant};
+
+fn rate(rate: &str) -> Rate {
+    rate.parse().expect("valid rate")
+}
+
+/// How many of the messages offered every `every` for `over` get a token.
+fn passed(bucket: &mut TokenBucket, start: Instant, every: Duration, over: Duration) -> usize {
+    let mut now = start;
+    let mut passed = 0;
+    while now < start + over {
+        if bucket.try_take(now) {
+            passed += 1;
+        }
+        now += every;
+    }
+    passed
+}
+
+#[test]
+fn a_burst_goes_out_then_the_rate_applies() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(rate("5/s"), 10, start);
+    let burst = (0..20).filter(|_| bucket.try_take(start)).count();
+    assert_eq!(burst, 10);
+    assert_eq!(bucket.ready_in(start), Duration::from_millis(200));
+    assert!(bucket.ready_in(start + Duration::from_millis(199)) > Duration::ZERO);
+    assert!(bucket.try_take(start + Duration::from_millis(200)));
+
+    // Offering 100 messages/s for ten seconds, 5/s get through.
+    let later = start + Duration::from_millis(200);
+    let passed = passed(
+        &mut bucket,
+        later,
+        Duration::from_millis(10),
+        Duration::from_secs(10),
+    );
+    assert!((49..=51).contains(&passed), "{passed} messages passed");
+}
+
+#[test]
+fn tokens_refill_up_to_the_burst() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(rate("1/s"), 3, start);
+    let drained = (0..3).filter(|_| bucket.try_take(start)).count();
+    assert_eq!(drained, 3);
+    let idle = start + Duration::from_secs(60);
+    assert_eq!(bucket.ready_in(idle), Duration::ZERO);
+    let burst = (0..10).filter(|_| bucket.try_take(idle)).count();
+    assert_eq!(
+        burst, 3,
+        "an idle minute doesn't save up more than the burst"
+    );
+
+    assert!(!bucket.try_take(idle));
+    bucket.give_back();
+    assert!(
+        bucket.try_take(idle),
+        "a token given back can be taken again"
+    );
+}
+
+#[test]
+fn the_presence_budget_is_a_quarter() {
+    let start = Instant::now();
+    let chat = TokenBucket::new(rate("8/s"), 20, start);
+    let mut presence = chat.quarter(start);
+    assert_eq!(presence.rate(), rate("2/s"));
+    let burst = (0..10).filter(|_| presence.try_take(start)).count();
+    assert_eq!(burst, 5);
+    assert_eq!(presence.ready_in(start), Duration::from_millis(500));
+
+    let mut tiny = TokenBucket::new(rate("1/m"), 1, start).quarter(start);
+    assert!(tiny.try_take(start), "at least a token");
+}
+
+#[test]
+fn the_outbox_counts_what_it_drops() {
+    let key = identity::generate_ed25519(1);
+    let mut gossipsub = gossipsub::Behaviour::new(
+        MessageAuthenticity::Signed(key),
+        gossipsub::Config::default(),
+    )
+    .expect("valid config");
+    let limit = TokenBucket::new(rate("1/s"), 1, Instant::now());
+    let mut outbox = Outbox::new(2).with_limit(Some(limit));
+    let topic = IdentTopic::new("test-net").hash();
+    for n in 0..3u8 {
+        // Nobody is subscribed, which doesn't use up the limit: they all wait for peers.
+        let sent = outbox
+            .publish(&mut gossipsub, topic.clone(), vec![n], Instant::now())
+            .expect("queued");
+        match sent {
+            Sent::Queued(dropped) => assert_eq!(dropped.is_some(), n == 2),
+            _ => panic!("message {n} should wait for peers"),
+        }
+    }
+    assert_eq!(outbox.ready_in(Instant::now()), None);
+    assert_eq!(
+        outbox.to_string(),
+        "outbox: 2 queued, 0 throttled, 1 dropped"
+    );
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn the_outbox_holds_back_what_the_limit_doesnt_let_through() {
+    let mut a = common::spawn_memory_node(1, MessageIdScheme::Sha256).await;
+    let mut b = common::spawn_memory_node(2, MessageIdScheme::Sha256).await;
+    common::connect(&mut a, &mut b).await;
+    // Once this went out, `a` knows `b` is subscribed.
+    common::publish(&mut a, &mut [&mut b], b"probe").await;
+
+    let start = Instant::now();
+    let limit = TokenBucket::new(rate("1/s"), 2, start);
+    let mut outbox = Outbox::new(10).with_limit(Some(limit));
+    let topic = common::topic().hash();
+    {
+        let gossipsub = &mut a.behaviour_mut().gossipsub;
+        let sent = (0..5u8)
+            .map(|n| {
+                outbox
+                    .publish(gossipsub, topic.clone(), vec![n], start)
+                    .expect("published or held back")
+            })
+            .collect::<Vec<_>>();
+        assert!(matches!(sent[0], Sent::Published));
+        assert!(matches!(sent[1], Sent::Published));
+        assert!(matches!(
+            sent[2],
+            Sent::Throttled {
+                started: true,
+                dropped: None
+            }
+        ));
+        for later in &sent[3..] {
+            assert!(matches!(
+                later,
+                Sent::Throttled {
+                    started: false,
+                    dropped: None
+                }
+            ));
+        }
+        assert_eq!(
+            outbox.to_string(),
+            "outbox: 3 queued, 3 throttled, 0 dropped"
+        );
+        assert_eq!(outbox.ready_in(start), Some(Duration::from_secs(1)));
+
+        // No token yet, nothing goes.
+        let (sent, failed) = outbox.flush(gossipsub, start + Duration::from_millis(500));
+        assert!(sent.is_empty() && failed.is_empty());
+        assert_eq!(
+            outbox.to_string(),
+            "outbox: 3 queued, 3 throttled, 0 dropped"
+        );
+
+        // One token, one message.
+        let second = start + Duration::from_secs(1);
+        let (sent, failed) = outbox.flush(gossipsub, second);
+        assert!(sent.is_empty(), "held back for the limit, not for peers");
+        assert!(failed.is_empty());
+        assert_eq!(
+            outbox.to_string(),
+            "outbox: 2 queued, 3 throttled, 0 dropped"
+        );
+        assert_eq!(outbox.ready_in(second), Some(Duration::from_secs(1)));
+
+        // Two tokens drain it.
+        outbox.flush(gossipsub, start + Duration::from_secs(3));
+        assert!(outbox.is_empty());
+        assert_eq!(outbox.ready_in(start + Duration::from_secs(3)), None);
+    }
+
+    let mut received = Vec::new();
+    common::wait_for_event(&mut b, &mut [&mut a], "the paced messages", |event| {
+        let message = common::message(event)?;
+        if message.data != b"probe" {
+            received.push(message.data);
+        }
+        (received.len() == 5).then_some(())
+    })
+    .await;
+    assert_eq!(
+        received,
+        (0..5u8).map(|n| vec![n]).collect::<Vec<_>>(),
+        "in the order they were published"
+    );
+}
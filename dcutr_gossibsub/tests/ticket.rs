@@ -0,0 +1,82 @@
+//! Encoding and decoding of connection tickets, including what damaged tickets turn into.
+
+use dcutr::identity;
+use dcutr::ticket::Ticket;
+use libp2p::core::multiaddr::Multiaddr;
+
+fn ticket(relays: usize, topic: Option<&str>) -> Ticket {
+    let relay_id = identity::generate_ed25519(1).public().to_peer_id();
+    Ticket {
+        peer_id: identity::generate_ed25519(2).public().to_peer_id(),
+        relays: (0..relays)
+            .map(|i| {
+                format!("/ip4/198.51.100.{i}/tcp/4001/p2p/{relay_id}")
+                    .parse::<Multiaddr>()
+                    .expect("valid address")
+            })
+            .collect(),
+        topic: topic.map(str::to_string),
+    }
+}
+
+#[test]
+fn tickets_round_trip() {
+    for ticket in [
+        ticket(1, None),
+        ticket(1, Some("test-net")),
+        ticket(3, Some("chat")),
+    ] {
+        let encoded = ticket.to_string();
+        assert!(encoded.starts_with("p2ptkt1"), "{encoded}");
+        assert_eq!(encoded.parse::<Ticket>(), Ok(ticket));
+    }
+}
+
+#[test]
+fn surrounding_whitespace_is_ignored() {
+    let ticket = ticket(1, None);
+    assert_eq!(format!("  {ticket}\n").parse::<Ticket>(), Ok(ticket));
+}
+
+#[test]
+fn unsupported_versions_are_rejected() {
+    let encoded = ticket(1, None)
+        .to_string()
+        .replacen("p2ptkt1", "p2ptkt2", 1);
+    let error = encoded.parse::<Ticket>().unwrap_err();
+    assert!(error.contains("version 2 isn't supported"), "{error}");
+}
+
+#[test]
+fn damaged_tickets_are_rejected() {
+    let encoded = ticket(2, Some("test-net")).to_string();
+    let truncated = &encoded[..encoded.len() - 10];
+    let mut flipped = encoded.clone().into_bytes();
+    let middle = flipped.len() / 2;
+    flipped[middle] = if flipped[middle] == b'A' { b'B' } else { b'A' };
+    let flipped = String::from_utf8(flipped).expect("still ascii");
+    for damaged in [
+        "",
+        "p2ptkt",
+        "p2ptkt1",
+        "p2ptkt1u",
+        "p2ptkt1x0000",
+        "p2ptkt1u!!!!",
+        "hello",
+        truncated,
+    ] {
+        assert!(damaged.parse::<Ticket>().is_err(), "{damaged:?} parsed");
+    }
+    // A flipped character may still decode, but never into the original ticket.
+    assert_ne!(
+        flipped.parse::<Ticket>().ok(),
+        encoded.parse::<Ticket>().ok()
+    );
+}
+
+#[test]
+fn tickets_without_relays_are_rejected() {
+    let encoded = ticket(0, None).to_string();
+    let error = encoded.parse::<Ticket>().unwrap_err();
+    assert!(error.contains("no relay"), "{error}");
+}
@@ -0,0 +1,116 @@
+//! Topic keys from passphrases and key files encrypt message bodies that only holders of the
+//! same key on the same topic can read.
+
+mod common;
+
+use common::data_dir;
+use dcutr::envelope::{Kind, Sequencer};
+use dcutr::identity;
+use dcutr::topic_keys::{TopicKey, TopicKeys};
+use libp2p::gossipsub::TopicHash;
+use std::fs;
+
+/// Base64 of the bytes 0 to 31.
+const FILE_KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+
+fn topics() -> Vec<String> {
+    vec!["chat".to_string()]
+}
+
+#[test]
+fn passphrase_keys_match_pbkdf2_and_differ_per_topic() {
+    // PBKDF2-HMAC-SHA256("secret", "dcutr-chat/topic-key/1/chat", 100000) is be74e6e0..., whose
+    // SHA-256 starts with f01eef74.
+    assert_eq!(TopicKey::from_passphrase("secret", "chat").id, "f01eef74");
+    assert_ne!(TopicKey::from_passphrase("secret", "news").id, "f01eef74");
+}
+
+#[test]
+fn bodies_round_trip_and_fail_with_another_key() {
+    let key = TopicKey::from_passphrase("secret", "chat");
+    let sealed = key.seal(b"aad", b"hello");
+    assert_eq!(key.open(b"aad", &sealed).expect("opens"), b"hello");
+    assert_ne!(key.seal(b"aad", b"hello"), sealed, "fresh nonce");
+
+    assert!(key.open(b"other aad", &sealed).is_err());
+    let wrong = TopicKey::from_passphrase("guess", "chat");
+    let error = wrong.open(b"aad", &sealed).expect_err("wrong key");
+    assert!(error.contains(&wrong.id), "{error}");
+    assert!(key.open(b"aad", "AAEC").is_err(), "too short");
+}
+
+#[test]
+fn envelopes_decrypt_with_any_key_of_the_rotation() {
+    let topic = TopicHash::from_raw("chat");
+    let peer_id = identity::generate_ed25519(1).public().to_peer_id();
+    let old = TopicKey::from_passphrase("old", "chat");
+    let new = TopicKey::from_passphrase("new", "chat");
+    let envelope = Sequencer::new(peer_id, None)
+        .wrap(Kind::Chat, "hello")
+        .encrypt(&topic, &new);
+    assert_ne!(envelope.body, "hello");
+
+    let decrypted = envelope
+        .clone()
+        .decrypt(&topic, &[old.clone(), new.clone()])
+        .expect("decrypts");
+    assert_eq!(decrypted.body, "hello");
+    assert!(envelope.clone().decrypt(&topic, &[old]).is_err());
+    assert!(
+        envelope
+            .decrypt(&TopicHash::from_raw("news"), &[new])
+            .is_err(),
+        "bound to its topic"
+    );
+}
+
+#[test]
+fn keys_are_derived_up_front_for_our_topics_only() {
+    let path = data_dir("topic-keys").join("keys");
+    fs::write(
+        &path,
+        format!("# comment\nchat {FILE_KEY}\nnews {FILE_KEY}\n"),
+    )
+    .expect("writes");
+    let mut keys =
+        TopicKeys::load(Some(&path), vec!["secret".to_string()], &topics()).expect("loads");
+
+    let ids = |keys: &TopicKeys, topic| {
+        keys.of(topic)
+            .iter()
+            .map(|k| k.id.clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        ids(&keys, "chat"),
+        ["630dcd29", "f01eef74"],
+        "file keys are older"
+    );
+    assert_eq!(
+        ids(&keys, "news"),
+        ["630dcd29"],
+        "not derived for a topic we aren't in"
+    );
+    assert!(keys.of("elsewhere").is_empty());
+
+    keys.derive("news");
+    assert_eq!(
+        ids(&keys, "news"),
+        [
+            "630dcd29",
+            TopicKey::from_passphrase("secret", "news").id.as_str()
+        ]
+    );
+}
+
+#[test]
+fn malformed_key_files_are_refused() {
+    let path = data_dir("topic-keys-malformed").join("keys");
+    for contents in ["chat", "chat not-base64", "chat AAEC"] {
+        fs::write(&path, contents).expect("writes");
+        let error = TopicKeys::load(Some(&path), vec![], &topics())
+            .err()
+            .expect("refused");
+        assert!(error.to_string().contains(":1:"), "{error}");
+    }
+}
@@ -0,0 +1,66 @@
+//! Peers only exchange messages on a topic name when they hash it the same way.
+
+#![cfg(feature = "tokio")]
+
+mod common;
+
+use dcutr::behaviour::BehaviourEvent;
+use dcutr::message_id::MessageIdScheme;
+use dcutr::node::{Event, Node};
+use dcutr::topics::{TopicHashing, Topics};
+use libp2p::{gossipsub, swarm::SwarmEvent};
+use std::time::Duration;
+
+const NAME: &str = "chat";
+
+fn is_message(event: &Event) -> bool {
+    matches!(
+        event,
+        SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Message { .. }))
+    )
+}
+
+/// A client subscribed to [`NAME`] hashed with `hashing`.
+async fn subscribed(secret_key_seed: u8, hashing: TopicHashing) -> Node {
+    common::spawn_memory_node_with(secret_key_seed, |key| {
+        let config = common::gossipsub_config(MessageIdScheme::Sha256)
+            .build()
+            .expect("valid gossipsub config");
+        let mut gossipsub = common::gossipsub(key, config);
+        Topics::new(&[NAME.to_string()], hashing)
+            .subscribe_all(&mut gossipsub)
+            .expect("subscribes");
+        gossipsub
+    })
+    .await
+}
+
+#[tokio::test]
+async fn only_peers_hashing_alike_exchange_messages() {
+    let mut a = subscribed(1, TopicHashing::Sha256).await;
+    let mut b = subscribed(2, TopicHashing::Sha256).await;
+    let mut c = subscribed(3, TopicHashing::Identity).await;
+    common::connect(&mut a, &mut b).await;
+    common::connect(&mut a, &mut c).await;
+
+    let hash = TopicHashing::Sha256.hash(NAME);
+    assert_ne!(hash, TopicHashing::Identity.hash(NAME));
+    common::publish_to(&mut a, &mut [&mut b, &mut c], hash.clone(), b"hashed").await;
+    let received = common::wait_for_event(
+        &mut b,
+        &mut [&mut a, &mut c],
+        "the message",
+        common::message,
+    )
+    .await;
+    assert_eq!(received.topic, hash);
+    assert_eq!(received.data, b"hashed");
+
+    common::quiet(
+        &mut [&mut c, &mut a, &mut b],
+        Duration::from_secs(1),
+        "message",
+        is_message,
+    )
+    .await;
+}
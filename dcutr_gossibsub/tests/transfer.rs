@@ -0,0 +1,140 @@
+//! Offers of peers are checked before anything touches the download directory, and receiving
+//! is bounded in number and time.
+
+mod common;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use common::{data_dir, peer};
+use dcutr::transfer::{FileRequest, FileResponse, Transfers};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const MAX_SIZE: u64 = 1024 * 1024;
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+fn sha256(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// The offer our sending side would make for `data`.
+fn offer(name: &str, data: &[u8]) -> FileRequest {
+    let sha256 = sha256(data);
+    FileRequest::Offer {
+        file_id: sha256[..32].to_string(),
+        name: name.to_string(),
+        size: data.len() as u64,
+        sha256,
+    }
+}
+
+fn chunk(file_id: &str, offset: u64, data: &[u8]) -> FileRequest {
+    FileRequest::Chunk {
+        file_id: file_id.to_string(),
+        offset,
+        data: STANDARD.encode(data),
+        crc32: crc32fast::hash(data),
+    }
+}
+
+fn transfers(dir: &Path, max_incoming: usize) -> Transfers {
+    Transfers::new(dir.to_path_buf(), false, MAX_SIZE, max_incoming, TIMEOUT)
+}
+
+fn is_accepted(response: &FileResponse) -> bool {
+    matches!(response, FileResponse::Accepted { .. })
+}
+
+#[test]
+fn file_ids_that_are_not_ours_are_refused() {
+    let dir = data_dir("transfer-ids");
+    let mut transfers = transfers(&dir, 4);
+    let data = b"contents";
+    for file_id in [
+        "../../../../tmp/evil".to_string(),
+        "0123456789abcdef0123456789abcde".to_string(),
+        "0123456789ABCDEF0123456789ABCDEF".to_string(),
+        format!("{}/x", &sha256(data)[..30]),
+    ] {
+        let response = transfers.on_request(
+            peer(2),
+            FileRequest::Offer {
+                file_id: file_id.clone(),
+                name: "notes.txt".to_string(),
+                size: data.len() as u64,
+                sha256: sha256(data),
+            },
+        );
+        assert!(
+            matches!(&response, FileResponse::Rejected { reason } if reason == "invalid file id"),
+            "{file_id}: {response:?}"
+        );
+    }
+    assert_eq!(
+        fs::read_dir(&dir).expect("lists").count(),
+        0,
+        "nothing was written"
+    );
+}
+
+#[test]
+fn another_peer_cannot_take_over_a_transfer() {
+    let dir = data_dir("transfer-takeover");
+    let mut transfers = transfers(&dir, 4);
+    let data = b"the real contents";
+    let request = offer("notes.txt", data);
+    let file_id = request.file_id().to_string();
+    assert!(is_accepted(&transfers.on_request(peer(2), request.clone())));
+
+    let response = transfers.on_request(peer(3), request);
+    assert!(
+        matches!(response, FileResponse::Rejected { .. }),
+        "{response:?}"
+    );
+    let response = transfers.on_request(peer(2), chunk(&file_id, 0, data));
+    assert!(matches!(response, FileResponse::Ack), "{response:?}");
+    let response = transfers.on_request(peer(2), FileRequest::Done { file_id });
+    assert!(matches!(response, FileResponse::Complete), "{response:?}");
+    assert_eq!(fs::read(dir.join("notes.txt")).expect("received"), data);
+}
+
+#[test]
+fn incoming_transfers_are_capped() {
+    let dir = data_dir("transfer-cap");
+    let mut transfers = transfers(&dir, 1);
+    assert!(is_accepted(
+        &transfers.on_request(peer(2), offer("one.txt", b"one"))
+    ));
+    let response = transfers.on_request(peer(3), offer("two.txt", b"two"));
+    assert!(
+        matches!(response, FileResponse::Rejected { .. }),
+        "{response:?}"
+    );
+    assert!(
+        is_accepted(&transfers.on_request(peer(2), offer("one.txt", b"one"))),
+        "the transfer in progress may be offered again"
+    );
+}
+
+#[test]
+fn stalled_transfers_are_dropped_and_free_their_slot() {
+    let dir = data_dir("transfer-stalled");
+    let mut transfers = transfers(&dir, 1);
+    let request = offer("one.txt", b"one");
+    let file_id = request.file_id().to_string();
+    assert!(is_accepted(&transfers.on_request(peer(2), request)));
+
+    transfers.expire(Instant::now() + TIMEOUT);
+    let response = transfers.on_request(peer(2), chunk(&file_id, 0, b"one"));
+    assert!(
+        matches!(&response, FileResponse::Failed { reason } if reason == "unknown transfer"),
+        "{response:?}"
+    );
+    assert!(is_accepted(
+        &transfers.on_request(peer(3), offer("two.txt", b"two"))
+    ));
+}
@@ -0,0 +1,226 @@
+//! The validators messages pass before they are shown and propagated.
+
+mod common;
+
+use common::peer;
+use dcutr::chunking::{self, Chunk, Reassembly};
+use dcutr::envelope::{Envelope, Kind, Sequencer, WireFormat};
+use dcutr::validation::{
+    BodySize, Freshness, PeerRateLimiter, Rate, RateLimited, Reassembled, ValidationStats,
+};
+use std::time::{Duration, Instant};
+
+const NOW: u64 = 1_700_000_000_000;
+
+fn sent_at(sent_at: u64, expires_at: Option<u64>) -> Vec<u8> {
+    Envelope {
+        sent_at,
+        expires_at,
+        ..Sequencer::new(peer(1), None).wrap(Kind::Chat, "hi")
+    }
+    .encode(WireFormat::Json)
+}
+
+#[test]
+fn bodies_inflating_beyond_the_limit_are_rejected_before_they_spread() {
+    let mut sequencer = Sequencer::new(peer(1), None);
+    let bomb = sequencer
+        .wrap(Kind::Chat, &"a".repeat(100_000))
+        .compress(1024);
+    assert!(bomb.compressed);
+    let data = bomb.encode(WireFormat::Json);
+    assert!(data.len() < 10_000);
+
+    let rejection = BodySize {
+        max_decompressed: 10_000,
+    }
+    .check(&data)
+    .unwrap_err();
+    assert_eq!(rejection.kind, "body");
+    assert!(rejection.penalize);
+    assert!(BodySize {
+        max_decompressed: 100_000
+    }
+    .check(&data)
+    .is_ok());
+}
+
+#[test]
+fn uncompressed_and_foreign_payloads_pass_the_body_check() {
+    let check = BodySize {
+        max_decompressed: 10,
+    };
+    let mut sequencer = Sequencer::new(peer(1), None);
+    let plain = sequencer.wrap(Kind::Chat, &"a".repeat(100));
+    assert!(check.check(&plain.encode(WireFormat::Cbor)).is_ok());
+    assert!(check.check(b"a bare line of an old peer").is_ok());
+}
+
+#[test]
+fn freshness_allows_for_the_skew_up_to_the_millisecond() {
+    let freshness = Freshness {
+        max_age: Duration::from_secs(300),
+        skew: Duration::from_secs(5),
+    };
+    let oldest = NOW - 305_000;
+    assert!(freshness.check(&sent_at(oldest, None), NOW).is_ok());
+    let stale = freshness
+        .check(&sent_at(oldest - 1, None), NOW)
+        .unwrap_err();
+    assert_eq!(stale.kind, "stale");
+    assert!(stale.penalize);
+
+    assert!(freshness.check(&sent_at(NOW + 5_000, None), NOW).is_ok());
+    let future = freshness
+        .check(&sent_at(NOW + 5_001, None), NOW)
+        .unwrap_err();
+    assert_eq!(future.kind, "future");
+
+    assert!(freshness
+        .check(&sent_at(NOW - 1_000, Some(NOW - 5_000)), NOW)
+        .is_ok());
+    let expired = freshness
+        .check(&sent_at(NOW - 1_000, Some(NOW - 5_001)), NOW)
+        .unwrap_err();
+    assert_eq!(expired.kind, "expired");
+    assert!(!expired.penalize, "expiring on the way is nobody's fault");
+}
+
+#[test]
+fn payloads_without_a_timestamp_are_fresh() {
+    let freshness = Freshness {
+        max_age: Duration::from_secs(1),
+        skew: Duration::ZERO,
+    };
+    assert!(freshness.check(b"a bare line", NOW).is_ok());
+    assert!(freshness.check(&sent_at(0, None), NOW).is_ok());
+}
+
+#[test]
+fn stats_count_rejections_by_kind() {
+    let freshness = Freshness {
+        max_age: Duration::from_secs(1),
+        skew: Duration::ZERO,
+    };
+    let mut stats = ValidationStats::default();
+    assert!(!stats.take_changed());
+    stats.on_accepted();
+    for data in [sent_at(NOW - 10_000, None), sent_at(NOW + 10_000, None)] {
+        stats.on_rejected(&freshness.check(&data, NOW).unwrap_err());
+    }
+    assert!(stats.take_changed());
+    assert_eq!(
+        stats.to_string(),
+        "validation: 1 accepted, 2 rejected (future: 1, stale: 1)"
+    );
+}
+
+#[test]
+fn bursts_pass_up_to_the_limit_and_floods_are_clamped() {
+    let rate = "10/s".parse::<Rate>().expect("valid rate");
+    let mut limiter = PeerRateLimiter::new(rate, 20);
+    let flooder = peer(2);
+    let start = Instant::now();
+    for _ in 0..20 {
+        assert!(limiter.check(flooder, start).is_ok());
+    }
+    let rejection = limiter.check(flooder, start).unwrap_err();
+    assert_eq!(rejection.kind, "rate");
+    assert!(
+        limiter.check(peer(3), start).is_ok(),
+        "buckets are per peer"
+    );
+
+    // A minute of 100 messages a second gets through at the rate only.
+    let mut passed = 0;
+    for i in 1..=6_000 {
+        let now = start + Duration::from_millis(i * 10);
+        passed += limiter.check(flooder, now).is_ok() as u32;
+    }
+    assert!((599..=601).contains(&passed), "{passed} passed");
+}
+
+#[test]
+fn limited_peers_are_reported_once_per_interval() {
+    let rate = "1/s".parse::<Rate>().expect("valid rate");
+    let mut limiter = PeerRateLimiter::new(rate, 1);
+    let flooder = peer(2);
+    let start = Instant::now();
+    for i in 0..50 {
+        let _ = limiter.check(flooder, start + Duration::from_millis(i));
+    }
+    assert_eq!(
+        limiter.take_limited(),
+        [RateLimited {
+            peer_id: flooder,
+            per_second: 1.0,
+            dropped: 1,
+        }]
+    );
+    assert!(limiter.take_limited().is_empty());
+
+    let _ = limiter.check(flooder, start + Duration::from_secs(11));
+    let _ = limiter.check(flooder, start + Duration::from_secs(11));
+    let limited = limiter.take_limited();
+    assert_eq!(limited.len(), 1);
+    assert_eq!(
+        limited[0].dropped, 49,
+        "everything dropped since the last notice"
+    );
+    assert_eq!(
+        limited[0].to_string(),
+        format!("Peer {flooder} sends more than 1.0 messages/s, dropped 49 of its messages")
+    );
+}
+
+#[test]
+fn buckets_of_disconnected_peers_start_over() {
+    let rate = "1/m".parse::<Rate>().expect("valid rate");
+    let mut limiter = PeerRateLimiter::new(rate, 1);
+    let now = Instant::now();
+    assert!(limiter.check(peer(2), now).is_ok());
+    assert!(limiter.check(peer(2), now).is_err());
+    limiter.on_disconnected(&peer(2));
+    assert!(limiter.check(peer(2), now).is_ok());
+}
+
+#[test]
+fn chunked_messages_are_checked_once_reassembled() {
+    let freshness = Freshness {
+        max_age: Duration::from_secs(60),
+        skew: Duration::ZERO,
+    };
+    let reassembled = Reassembled {
+        freshness,
+        body_size: BodySize {
+            max_decompressed: 10_000,
+        },
+    };
+    let stale = Envelope {
+        sent_at: NOW - 61_000,
+        ..Sequencer::new(peer(1), None).wrap(Kind::Chat, &"a".repeat(3000))
+    }
+    .encode(WireFormat::Json);
+    let chunks = chunking::split(stale.clone(), 1000).expect("splits");
+    assert!(chunks.len() > 1);
+
+    let mut reassembly = Reassembly::new(Duration::from_secs(30), 1, 1 << 20);
+    let mut data = None;
+    for chunk in &chunks {
+        // The pipeline can't tell a chunk's age.
+        assert!(freshness.check(chunk, NOW).is_ok());
+        data = reassembly
+            .add(Some(peer(1)), Chunk::decode(chunk).expect("a chunk"))
+            .expect("reassembles");
+    }
+    assert_eq!(data.as_deref(), Some(&stale[..]));
+    assert_eq!(reassembled.check(&stale, NOW).unwrap_err().kind, "stale");
+
+    let bomb = Envelope {
+        sent_at: NOW,
+        ..Sequencer::new(peer(1), None).wrap(Kind::Chat, &"a".repeat(100_000))
+    }
+    .compress(1024)
+    .encode(WireFormat::Json);
+    assert_eq!(reassembled.check(&bomb, NOW).unwrap_err().kind, "body");
+}
@@ -21,19 +21,26 @@
 
 use clap::Parser;
 use futures::executor::block_on;
+use futures::future::{self, Either, FutureExt, TryFutureExt};
 use futures::stream::StreamExt;
 use libp2p::{
+    autonat,
     core::multiaddr::Protocol,
     core::upgrade,
     core::{Multiaddr, Transport},
     identify, identity,
     identity::PeerId,
-    noise, ping, relay,
+    kad::{store::MemoryStore, Kademlia},
+    noise, ping,
+    pnet::{PnetConfig, PnetError, PreSharedKey},
+    relay,
     swarm::{NetworkBehaviour, SwarmBuilder, SwarmEvent},
     tcp,
 };
 use std::error::Error;
+use std::fs;
 use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
 
 fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init();
@@ -46,9 +53,25 @@ fn main() -> Result<(), Box<dyn Error>> {
     let local_peer_id = PeerId::from(local_key.public());
     println!("Local peer id: {local_peer_id:?}");
 
+    let psk = match &opt.psk_file {
+        Some(path) => Some(
+            fs::read_to_string(path)?
+                .parse::<PreSharedKey>()
+                .map_err(|e| format!("invalid pre-shared key in {}: {e}", path.display()))?,
+        ),
+        None => None,
+    };
+
     let tcp_transport = tcp::async_io::Transport::default();
 
     let transport = tcp_transport
+        .and_then(move |socket, _| match psk {
+            Some(psk) => PnetConfig::new(psk)
+                .handshake(socket)
+                .map_ok(Either::Left)
+                .left_future(),
+            None => future::ok::<_, PnetError>(Either::Right(socket)).right_future(),
+        })
         .upgrade(upgrade::Version::V1Lazy)
         .authenticate(
             noise::Config::new(&local_key).expect("Signing libp2p-noise static DH keypair failed."),
@@ -63,6 +86,10 @@ fn main() -> Result<(), Box<dyn Error>> {
             "/TODO/0.0.1".to_string(),
             local_key.public(),
         )),
+        // A DHT node for the clients' `--kademlia` lookups to start from.
+        kademlia: Kademlia::new(local_peer_id, MemoryStore::new(local_peer_id)),
+        // Dials back the clients' observed addresses so they learn whether they need the relay.
+        autonat: autonat::Behaviour::new(local_peer_id, autonat::Config::default()),
     };
 
     let mut swarm = SwarmBuilder::without_executor(transport, behaviour, local_peer_id).build();
@@ -80,6 +107,19 @@ fn main() -> Result<(), Box<dyn Error>> {
         loop {
             match swarm.next().await.expect("Infinite Stream.") {
                 SwarmEvent::Behaviour(event) => {
+                    if let BehaviourEvent::Identify(identify::Event::Received { peer_id, info }) =
+                        &event
+                    {
+                        // Clients that speak Kademlia become routable through the relay's DHT.
+                        if info.protocols.iter().any(|p| p == KAD_PROTOCOL) {
+                            for addr in &info.listen_addrs {
+                                swarm
+                                    .behaviour_mut()
+                                    .kademlia
+                                    .add_address(peer_id, addr.clone());
+                            }
+                        }
+                    }
                     println!("{event:?}")
                 }
                 SwarmEvent::NewListenAddr { address, .. } => {
@@ -96,8 +136,13 @@ struct Behaviour {
     relay: relay::Behaviour,
     ping: ping::Behaviour,
     identify: identify::Behaviour,
+    kademlia: Kademlia<MemoryStore>,
+    autonat: autonat::Behaviour,
 }
 
+/// Protocol name announced by peers that speak Kademlia.
+const KAD_PROTOCOL: &str = "/ipfs/kad/1.0.0";
+
 fn generate_ed25519(secret_key_seed: u8) -> identity::Keypair {
     let mut bytes = [0u8; 32];
     bytes[0] = secret_key_seed;
@@ -119,4 +164,8 @@ struct Opt {
     /// The port used to listen on all interfaces
     #[clap(long)]
     port: u16,
+
+    /// Pre-shared key (swarm.key) of the private network the relay serves
+    #[clap(long)]
+    psk_file: Option<PathBuf>,
 }